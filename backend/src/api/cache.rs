@@ -0,0 +1,318 @@
+//! In-memory cache for resolvers that are expensive, but whose results rarely
+//! change.
+//!
+//! A resolver using the cache declares a tag (the DB table its result depends
+//! on), a key and a TTL. The cache is shared by all requests of one Tobira
+//! process. Triggers on the tagged tables send a notification on the
+//! `cache_invalidation` channel whenever they are modified, which removes all
+//! entries with that tag. As long as the listener for these notifications is
+//! not connected, nothing is cached.
+//!
+//! If several requests need the same missing entry at the same time (e.g. the
+//! home page after an invalidation), only one of them loads it while the
+//! others wait for the result.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}},
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use tokio_postgres::AsyncMessage;
+
+use crate::{
+    api::err::ApiResult,
+    db::{self, DbConfig},
+    prelude::*,
+};
+
+
+/// The data a cache entry depends on. Each variant corresponds to a DB table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum CacheTag {
+    Blocks,
+    Events,
+    Realms,
+    Series,
+    Settings,
+}
+
+impl CacheTag {
+    const ALL: [Self; 5] = [Self::Blocks, Self::Events, Self::Realms, Self::Series, Self::Settings];
+
+    fn table(self) -> &'static str {
+        match self {
+            Self::Blocks => "blocks",
+            Self::Events => "events",
+            Self::Realms => "realms",
+            Self::Series => "series",
+            Self::Settings => "settings",
+        }
+    }
+}
+
+/// Entries are identified by their tag and a key.
+type EntryKey = (CacheTag, String);
+
+/// The cache shared by all requests.
+pub(crate) struct ResolverCache {
+    entries: Mutex<HashMap<EntryKey, Entry>>,
+
+    /// Incremented whenever entries of a tag are invalidated. Used to not
+    /// insert values that were loaded before the invalidation.
+    generations: Mutex<HashMap<CacheTag, u64>>,
+
+    /// Locks of entries that are currently being loaded. Requests for the same
+    /// entry wait for the lock instead of loading it again.
+    loading: Mutex<HashMap<EntryKey, Arc<tokio::sync::Mutex<()>>>>,
+
+    /// Whether we currently listen for invalidations.
+    listening: AtomicBool,
+
+    /// Number of lookups that found a value or had to load it, see
+    /// `RequestCache::counts`.
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    expires: Instant,
+}
+
+impl ResolverCache {
+    /// If there are more entries than this, expired ones are removed on insert.
+    const PRUNE_THRESHOLD: usize = 1000;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            generations: Mutex::new(HashMap::new()),
+            loading: Mutex::new(HashMap::new()),
+            listening: AtomicBool::new(false),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether values are currently cached, see `invalidation_listener`.
+    pub(crate) fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
+    }
+
+    fn get<T: Clone + 'static>(&self, key: &EntryKey) -> Option<T> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.expires <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+
+        entry.value.downcast_ref::<T>().cloned()
+    }
+
+    fn insert<T>(&self, key: EntryKey, value: T, ttl: Duration, generation: u64)
+    where
+        T: Send + Sync + 'static,
+    {
+        // Both locks are held to make sure no invalidation happens in between.
+        let generations = self.generations.lock().unwrap();
+        if generations.get(&key.0).copied().unwrap_or(0) != generation {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if entries.len() >= Self::PRUNE_THRESHOLD {
+            entries.retain(|_, entry| entry.expires > now);
+        }
+        entries.insert(key, Entry { value: Arc::new(value), expires: now + ttl });
+    }
+
+    fn generation(&self, tag: CacheTag) -> u64 {
+        self.generations.lock().unwrap().get(&tag).copied().unwrap_or(0)
+    }
+
+    fn invalidate(&self, tag: CacheTag) {
+        let mut generations = self.generations.lock().unwrap();
+        *generations.entry(tag).or_insert(0) += 1;
+        self.entries.lock().unwrap().retain(|(entry_tag, _), _| *entry_tag != tag);
+    }
+
+    fn invalidate_all(&self) {
+        for tag in CacheTag::ALL {
+            self.invalidate(tag);
+        }
+    }
+}
+
+/// Per-request view of the cache.
+pub(crate) struct RequestCache {
+    shared: Arc<ResolverCache>,
+
+    /// Tags of tables that were modified in this request's transaction. The
+    /// shared cache only learns about that once the transaction is committed,
+    /// so this request must not use it for these tags anymore.
+    modified: Mutex<Vec<CacheTag>>,
+}
+
+impl RequestCache {
+    pub(crate) fn new(shared: Arc<ResolverCache>) -> Self {
+        Self {
+            shared,
+            modified: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the cached value for `key`, or loads it with `load` and caches
+    /// it for `ttl`.
+    pub(crate) async fn get_or_load<T, F>(
+        &self,
+        tag: CacheTag,
+        key: String,
+        ttl: Duration,
+        load: F,
+    ) -> ApiResult<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Future<Output = ApiResult<T>>,
+    {
+        if !self.shared.listening.load(Ordering::SeqCst)
+            || self.modified.lock().unwrap().contains(&tag)
+        {
+            return load.await;
+        }
+
+        let key = (tag, key);
+        if let Some(value) = self.shared.get(&key) {
+            self.shared.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        // Only one request loads the entry, the others wait here and then find
+        // it in the cache. If loading fails or the result is outdated, the
+        // next one in line tries again.
+        let loading = LoadingGuard::new(&self.shared, &key);
+        let _lock = loading.lock.clone().lock_owned().await;
+        if let Some(value) = self.shared.get(&key) {
+            self.shared.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        self.shared.misses.fetch_add(1, Ordering::Relaxed);
+        let generation = self.shared.generation(tag);
+        let value = load.await?;
+        self.shared.insert(key.clone(), value.clone(), ttl, generation);
+
+        Ok(value)
+    }
+
+    /// Whether values are currently cached, see `invalidation_listener`.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.shared.is_listening()
+    }
+
+    /// Number of lookups in the shared cache that found a value (hits) and
+    /// that had to load it (misses), since it was created. Requests that
+    /// bypass the cache are not counted.
+    pub(crate) fn counts(&self) -> (u64, u64) {
+        (self.shared.hits.load(Ordering::Relaxed), self.shared.misses.load(Ordering::Relaxed))
+    }
+
+    /// Has to be called whenever the request modifies the table corresponding
+    /// to `tag`.
+    pub(crate) fn modified(&self, tag: CacheTag) {
+        let mut modified = self.modified.lock().unwrap();
+        if !modified.contains(&tag) {
+            modified.push(tag);
+        }
+    }
+}
+
+/// Registers a request as interested in loading an entry and unregisters it
+/// when dropped, also if the request is cancelled.
+struct LoadingGuard<'a> {
+    cache: &'a ResolverCache,
+    key: &'a EntryKey,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> LoadingGuard<'a> {
+    fn new(cache: &'a ResolverCache, key: &'a EntryKey) -> Self {
+        let lock = cache.loading.lock().unwrap().entry(key.clone()).or_default().clone();
+        Self { cache, key, lock }
+    }
+}
+
+impl Drop for LoadingGuard<'_> {
+    fn drop(&mut self) {
+        let mut loading = self.cache.loading.lock().unwrap();
+        // Only the last interested request removes the lock. The map holds
+        // one reference as well.
+        if Arc::strong_count(&self.lock) == 2
+            && loading.get(self.key).is_some_and(|lock| Arc::ptr_eq(lock, &self.lock))
+        {
+            loading.remove(self.key);
+        }
+    }
+}
+
+/// Long running task that listens for invalidation notifications from the DB
+/// and reconnects if the connection is lost. Never returns.
+pub(crate) async fn invalidation_listener(cache: Arc<ResolverCache>, config: &DbConfig) {
+    const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+    loop {
+        if let Err(e) = listen(&cache, config).await {
+            error!("Resolver cache invalidation listener failed: {e:#}");
+        }
+
+        // Without the listener, we would miss invalidations.
+        cache.listening.store(false, Ordering::SeqCst);
+        cache.invalidate_all();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(cache: &ResolverCache, config: &DbConfig) -> Result<()> {
+    let (client, mut connection) = db::connect_single(config).await?;
+    let mut messages = futures::stream::poll_fn(|cx| connection.poll_message(cx));
+
+    // The connection has to be polled for the `listen` command to be sent.
+    let listen = client.batch_execute("listen cache_invalidation");
+    tokio::pin!(listen);
+    loop {
+        tokio::select! {
+            result = &mut listen => {
+                result.context("failed to listen for cache invalidations")?;
+                break;
+            }
+            message = messages.next() => match message {
+                Some(message) => { message?; }
+                None => bail!("DB connection closed"),
+            },
+        }
+    }
+
+    // Anything might have changed while we were not listening.
+    cache.invalidate_all();
+    cache.listening.store(true, Ordering::SeqCst);
+    debug!("Listening for resolver cache invalidations");
+
+    while let Some(message) = messages.next().await {
+        if let AsyncMessage::Notification(notification) = message? {
+            match CacheTag::ALL.into_iter().find(|tag| tag.table() == notification.payload()) {
+                Some(tag) => {
+                    trace!("Invalidating resolver cache for '{}'", tag.table());
+                    cache.invalidate(tag);
+                }
+                None => warn!("Unknown cache invalidation '{}'", notification.payload()),
+            }
+        }
+    }
+
+    bail!("DB connection closed")
+}
@@ -5,9 +5,18 @@ use crate::{
     api::{
         Id, Context,
         err::{self, ApiResult},
-        model::{event::Event, series::Series, realm::Realm},
+        model::{
+            block::{TitleBlock, TextBlock, SeriesBlock, VideoBlock, CreatorBlock, ExtensionBlock},
+            event::{Event, EventMarker},
+            moderator_delegation::ModeratorDelegation,
+            notification::Notification,
+            pending_operation::PendingOperation,
+            realm::Realm,
+            saved_search::SavedSearch,
+            search::SearchEvent,
+            series::Series,
+        },
     },
-    search::Event as SearchEvent,
     search::Realm as SearchRealm,
 };
 
@@ -15,7 +24,24 @@ use crate::{
 /// A node with a globally unique ID. Mostly useful for relay.
 #[juniper::graphql_interface(
     Context = Context,
-    for = [Event, Realm, Series, SearchEvent, SearchRealm]
+    for = [
+        Event,
+        Realm,
+        Series,
+        SearchEvent,
+        SearchRealm,
+        TitleBlock,
+        TextBlock,
+        SeriesBlock,
+        VideoBlock,
+        CreatorBlock,
+        ExtensionBlock,
+        Notification,
+        PendingOperation,
+        SavedSearch,
+        EventMarker,
+        ModeratorDelegation,
+    ]
 )]
 pub(crate) trait Node {
     fn id(&self) -> Id;
@@ -1,22 +1,40 @@
-use std::sync::Arc;
+use std::{future::Future, mem, net::IpAddr, sync::Arc, time::Duration};
+
+use deadpool_postgres::Pool;
 
 use crate::{
-    api::err::{ApiError, ApiErrorKind, ApiResult},
-    auth::{AuthToken, JwtContext, User},
+    api::{cache::RequestCache, err::{ApiError, ApiErrorKind, ApiResult}, tracing::Tracer},
+    auth::{self, AuthToken, JwtContext, Permissions, User},
     config::Config,
-    db::Transaction,
-    search,
+    db::{Transaction, types::Key},
+    extension::Extensions,
+    geoblock::Country,
     prelude::*,
+    search,
 };
 
 
 /// The context that is accessible to every resolver in our API.
 pub(crate) struct Context {
     pub(crate) db: Transaction,
+    /// Only used for status information, resolvers use `db`.
+    pub(crate) db_pool: Pool,
     pub(crate) user: Option<User>,
+
+    /// Derived from `user`, so that roles don't have to be checked again in
+    /// each resolver.
+    pub(crate) permissions: Permissions,
     pub(crate) config: Arc<Config>,
     pub(crate) jwt: Arc<JwtContext>,
     pub(crate) search: Arc<search::Client>,
+    pub(crate) cache: RequestCache,
+    pub(crate) extensions: Arc<Extensions>,
+    /// Set if timings should be reported, see `tracing`.
+    pub(crate) tracer: Option<Tracer>,
+    /// Address of the client (see `http.client_ip_header`), if known.
+    pub(crate) client_ip: Option<IpAddr>,
+    /// Country of the client, only known if geoblocking is enabled.
+    pub(crate) client_country: Option<Country>,
 }
 
 impl juniper::Context for Context {}
@@ -29,7 +47,7 @@ impl Context {
     }
 
     pub(crate) fn require_upload_permission(&self) -> ApiResult<AuthToken> {
-        self.user.required_upload_permission(&self.config.auth).ok_or_else(|| {
+        self.permissions.require_upload().ok_or_else(|| {
             if let Some(user) = &self.user {
                 ApiError {
                     msg: format!("User '{}' is not allowed to upload videos", user.username),
@@ -47,7 +65,7 @@ impl Context {
     }
 
     pub(crate) fn require_moderator(&self) -> ApiResult<AuthToken> {
-        self.user.require_moderator(&self.config.auth).ok_or_else(|| {
+        self.permissions.require_moderator().ok_or_else(|| {
             if let Some(user) = &self.user {
                 ApiError {
                     msg: format!("moderator required, but '{}' is not a moderator", user.username),
@@ -63,4 +81,152 @@ impl Context {
             }
         })
     }
+
+    /// Like `require_moderator`, but also accepts users to whom moderation
+    /// of `realm` or one of its ancestors has been delegated. Unless the user
+    /// is an admin, `realm` and its ancestors must not be locked. All
+    /// mutations of realms and blocks go through this.
+    pub(crate) async fn require_realm_moderator(&self, realm: Key) -> ApiResult<AuthToken> {
+        let token = match self.require_moderator() {
+            Ok(token) => token,
+            Err(err) => match &self.user {
+                Some(user) => auth::require_delegated_moderator(&self.db, user, realm).await?
+                    .ok_or(err)?,
+                None => return Err(err),
+            },
+        };
+
+        self.require_unlocked_realm(realm).await?;
+        Ok(token)
+    }
+
+    /// Fails if `realm` or one of its ancestors is locked, unless the user is
+    /// an admin.
+    pub(crate) async fn require_unlocked_realm(&self, realm: Key) -> ApiResult<()> {
+        if self.permissions.admin {
+            return Ok(());
+        }
+
+        let locked = self.db
+            .query_one(
+                "select exists(select from ancestors_of_realm($1) where locked)",
+                &[&realm],
+            )
+            .await?
+            .get::<_, bool>(0);
+        if locked {
+            return Err(Self::realm_locked_error(realm));
+        }
+
+        Ok(())
+    }
+
+    /// Like `require_unlocked_realm`, but also fails if a descendant of
+    /// `realm` is locked. Used before removing realms.
+    pub(crate) async fn require_unlocked_subtree(&self, realm: Key) -> ApiResult<()> {
+        self.require_unlocked_realm(realm).await?;
+        if self.permissions.admin {
+            return Ok(());
+        }
+
+        let locked = self.db
+            .query_one(
+                "select exists(select from realms \
+                    where locked \
+                    and starts_with(full_path, (select full_path from realms where id = $1) || '/'))",
+                &[&realm],
+            )
+            .await?
+            .get::<_, bool>(0);
+        if locked {
+            return Err(Self::realm_locked_error(realm));
+        }
+
+        Ok(())
+    }
+
+    fn realm_locked_error(realm: Key) -> ApiError {
+        ApiError {
+            msg: format!("realm {:?} or one of its ancestors or descendants is locked", realm),
+            kind: ApiErrorKind::NotAuthorized,
+            key: Some("mutation.realm-locked"),
+        }
+    }
+
+    pub(crate) fn require_admin(&self) -> ApiResult<AuthToken> {
+        self.permissions.require_admin().ok_or_else(|| {
+            if let Some(user) = &self.user {
+                ApiError {
+                    msg: format!("admin required, but '{}' is not an admin", user.username),
+                    kind: ApiErrorKind::NotAuthorized,
+                    key: Some("mutation.not-an-admin"),
+                }
+            } else {
+                ApiError {
+                    msg: "admin required, but user is not logged in".into(),
+                    kind: ApiErrorKind::NotAuthorized,
+                    key: Some("mutation.not-logged-in"),
+                }
+            }
+        })
+    }
+}
+
+
+/// Runs `f` with the API context created by `make_context` from a wrapper
+/// of `tx`. Returns the output of `f` and `tx` again, so that the caller can
+/// commit or roll it back. If `explain_threshold` is set, plans of slow
+/// queries are recorded (see `Transaction::take_query_plans`).
+pub(crate) async fn with_transaction<'tx, T, Fut>(
+    tx: deadpool_postgres::Transaction<'tx>,
+    explain_threshold: Option<Duration>,
+    make_context: impl FnOnce(Transaction) -> Context,
+    f: impl FnOnce(Arc<Context>) -> Fut,
+) -> (T, deadpool_postgres::Transaction<'tx>)
+where
+    Fut: Future<Output = T>,
+{
+    // Okay, lets take a deep breath.
+    //
+    // Unfortunately, `juniper` does not support contexts with a lifetime
+    // parameter. However, we'd like to have one SQL transaction per API
+    // request. The transaction type (`deadpool_postgres::Transaction`) borrows
+    // from the DB connection (`tokio_postgres::Client`) and thus has a
+    // lifetime parameter. This makes sense for the API of that library since
+    // it statically prevents a number of logic bugs. But it is inconvenient
+    // for us.
+    //
+    // Unfortunately, we think the best solution for us is to use `unsafe` here
+    // to just get rid of the lifetime parameter. We can pretend that the
+    // lifetime is `'static`. Of course, we then have to make sure that the
+    // transaction does not outlive the borrowed connection. We do that by
+    // putting the transaction into an `Arc`. That way we can check whether
+    // there still exists a reference after `f` is done. The transaction is not
+    // `Clone` and `Arc` only gives an immutable reference to the underlying
+    // value. So even a buggy resolver could not move the transaction out of
+    // the `Arc`. If this future is dropped early, the `Arc` is dropped with
+    // it, i.e. also while the connection is still borrowed.
+    //
+    // Afterwards, the transaction gets its real lifetime back, so that the
+    // connection stays borrowed until the caller is done with it.
+    type PgTx<'a> = deadpool_postgres::Transaction<'a>;
+    let tx = unsafe { Arc::new(mem::transmute::<PgTx<'tx>, PgTx<'static>>(tx)) };
+
+    let context = make_context(Transaction::new(tx.clone(), explain_threshold));
+    let out = f(Arc::new(context)).await;
+
+    // Check whether we own the last remaining handle of this Arc.
+    match Arc::try_unwrap(tx) {
+        Ok(tx) => (out, unsafe { mem::transmute::<PgTx<'static>, PgTx<'tx>>(tx) }),
+        Err(_) => {
+            // There are still other handles, meaning that a resolver
+            // incorrectly stored the transaction in some static variable. This
+            // is our fault and should NEVER happen. If it does happen, we
+            // would have UB after this function exits. We can't have that. And
+            // since panicking only brings down the current thread, we have to
+            // reach for more drastic measures.
+            error!("FATAL BUG: API handler kept reference to transaction. Ending process.");
+            std::process::abort();
+        }
+    }
 }
@@ -24,7 +24,9 @@ pub(crate) struct Id {
     /// This uniquely refers to one object from the class of objects defined by
     /// `kind`. This is typically exactly the primary bigint database key. This
     /// data is encoded with base85 (well, our own flavor of it) to compactly
-    /// represent it in a JSON string.
+    /// represent it in a JSON string. The database keys are already
+    /// pseudo-random (see `02-id-generation.sql`), so this does not allow
+    /// enumerating objects.
     ///
     /// Note that this is a private field. To get to it, you need to prove
     /// that you know what kind of ID it is using [`Self::key_for`].
@@ -85,6 +87,11 @@ define_kinds![
     event = b"ev",
     search_realm = b"rs",
     search_event = b"es",
+    notification = b"no",
+    pending_operation = b"po",
+    saved_search = b"ss",
+    event_marker = b"em",
+    moderator_delegation = b"md",
 ];
 
 
@@ -4,29 +4,37 @@ use self::{
     mutation::Mutation,
     query::Query,
     subscription::Subscription,
+    tracing::Traced,
 };
 
+pub(crate) mod cache;
+pub(crate) mod err;
 pub(crate) mod mutation;
 pub(crate) mod query;
 pub(crate) mod subscription;
+pub(crate) mod tracing;
 
 mod context;
-mod err;
 mod id;
 mod model;
 mod common;
 
 pub(crate) use self::{
     id::Id,
-    context::Context,
+    context::{Context, with_transaction},
     common::{Cursor, Node, NodeValue},
+    model::{
+        capture_agent::CaptureAgentsConfig,
+        event::{ExternalEventsConfig, member_access_sql},
+        upload::UploadConfig,
+    },
 };
 
 
 /// Creates and returns the API root node.
 pub(crate) fn root_node() -> RootNode {
-    RootNode::new(Query, Mutation, Subscription::new())
+    RootNode::new(Traced(Query), Traced(Mutation), Subscription::new())
 }
 
 /// Type of our API root node.
-pub(crate) type RootNode = juniper::RootNode<'static, Query, Mutation, Subscription>;
+pub(crate) type RootNode = juniper::RootNode<'static, Traced<Query>, Traced<Mutation>, Subscription>;
@@ -1,13 +1,20 @@
 //! Blocks that make up the content of realm pages.
 
-use std::{fmt, error::Error};
+use std::{fmt, error::Error, time::Duration};
 use juniper::{graphql_interface, graphql_object, GraphQLEnum};
 use postgres_types::{FromSql, ToSql};
 use tokio_postgres::Row;
 
 use crate::{
-    api::{Context, err::{ApiError, ApiResult, internal_server_err}, Id, model::{series::Series, event::Event}},
-    db::types::Key,
+    api::{
+        Context, Id, Node, NodeValue,
+        cache::CacheTag,
+        err::{ApiResult, internal_server_err},
+        model::{series::Series, event::{Event, EventSortOrder}},
+    },
+    auth::{self, HasRoles},
+    db::{types::Key, util::define_columns},
+    extension::ExtensionBlockData,
     prelude::*,
 };
 
@@ -19,16 +26,27 @@ pub(crate) use mutations::{
     NewTextBlock,
     NewSeriesBlock,
     NewVideoBlock,
+    NewCreatorBlock,
+    NewExtensionBlock,
     UpdateTitleBlock,
     UpdateTextBlock,
     UpdateSeriesBlock,
     UpdateVideoBlock,
+    UpdateCreatorBlock,
+    UpdateExtensionBlock,
     RemovedBlock,
 };
 
 
 /// A `Block`: a UI element that belongs to a realm.
-#[graphql_interface(Context = Context, for = [TitleBlock, TextBlock, SeriesBlock, VideoBlock])]
+#[graphql_interface(Context = Context, for = [
+    TitleBlock,
+    TextBlock,
+    SeriesBlock,
+    VideoBlock,
+    CreatorBlock,
+    ExtensionBlock,
+])]
 pub(crate) trait Block {
     // To avoid code duplication, all the shared data is stored in `SharedData`
     // and only a `shared` method is mandatory. All other method (in particular,
@@ -42,6 +60,11 @@ pub(crate) trait Block {
     fn index(&self) -> i32 {
         self.shared().index
     }
+    /// The block is only shown to users with one of these roles, and to
+    /// moderators of its realm. `null` if it is visible to everyone.
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
 }
 
 #[derive(Debug, Clone, Copy, FromSql)]
@@ -55,6 +78,10 @@ pub(crate) enum BlockType {
     Series,
     #[postgres(name = "video")]
     Video,
+    #[postgres(name = "creator")]
+    Creator,
+    #[postgres(name = "extension")]
+    Extension,
 }
 
 #[derive(Debug, Clone, Copy, FromSql, ToSql, GraphQLEnum)]
@@ -67,11 +94,14 @@ pub(crate) enum VideoListOrder {
 }
 
 /// Data shared by all blocks.
+#[derive(Clone)]
 pub(crate) struct SharedData {
     pub(crate) id: Id,
     pub(crate) index: i32,
+    pub(crate) read_roles: Option<Vec<String>>,
 }
 
+#[derive(Clone)]
 pub(crate) struct TitleBlock {
     pub(crate) shared: SharedData,
     pub(crate) content: String,
@@ -83,8 +113,15 @@ impl Block for TitleBlock {
     }
 }
 
+#[graphql_interface]
+impl Node for TitleBlock {
+    fn id(&self) -> Id {
+        self.shared.id
+    }
+}
+
 /// A block just showing some title.
-#[graphql_object(Context = Context, impl = BlockValue)]
+#[graphql_object(Context = Context, impl = [BlockValue, NodeValue])]
 impl TitleBlock {
     fn content(&self) -> &str {
         &self.content
@@ -97,8 +134,13 @@ impl TitleBlock {
     fn index(&self) -> i32 {
         self.shared().index
     }
+
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
 }
 
+#[derive(Clone)]
 pub(crate) struct TextBlock {
     pub(crate) shared: SharedData,
     pub(crate) content: String,
@@ -110,8 +152,15 @@ impl Block for TextBlock {
     }
 }
 
+#[graphql_interface]
+impl Node for TextBlock {
+    fn id(&self) -> Id {
+        self.shared.id
+    }
+}
+
 /// A block just showing some text.
-#[graphql_object(Context = Context, impl = BlockValue)]
+#[graphql_object(Context = Context, impl = [BlockValue, NodeValue])]
 impl TextBlock {
     fn content(&self) -> &str {
         &self.content
@@ -124,13 +173,22 @@ impl TextBlock {
     fn index(&self) -> i32 {
         self.shared().index
     }
+
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
 }
 
+#[derive(Clone)]
 pub(crate) struct SeriesBlock {
     pub(crate) shared: SharedData,
     pub(crate) series: Option<Id>,
     pub(crate) show_title: bool,
     pub(crate) order: VideoListOrder,
+    pub(crate) canonical: bool,
+    /// Whether the current user has any member role of the realm (see
+    /// `Realm::member_roles`) and can thus see all events of the series.
+    pub(crate) member_access: bool,
 }
 
 impl Block for SeriesBlock {
@@ -139,14 +197,24 @@ impl Block for SeriesBlock {
     }
 }
 
+#[graphql_interface]
+impl Node for SeriesBlock {
+    fn id(&self) -> Id {
+        self.shared.id
+    }
+}
+
 /// A block just showing the list of videos in an Opencast series
-#[graphql_object(Context = Context, impl = BlockValue)]
+#[graphql_object(Context = Context, impl = [BlockValue, NodeValue])]
 impl SeriesBlock {
     async fn series(&self, context: &Context) -> ApiResult<Option<Series>> {
         match self.series {
             None => Ok(None),
             // `unwrap` is okay here because of our foreign key constraint
-            Some(series_id) => Ok(Some(Series::load_by_id(series_id, context).await?.unwrap())),
+            Some(series_id) => {
+                let series = Series::load_by_id(series_id, context).await?.unwrap();
+                Ok(Some(series.with_member_access(self.member_access)))
+            }
         }
     }
 
@@ -154,6 +222,12 @@ impl SeriesBlock {
         self.show_title
     }
 
+    /// Whether this realm should be preferred as the canonical page of the
+    /// shown videos (see `Event.hostRealms`).
+    fn canonical(&self) -> bool {
+        self.canonical
+    }
+
     fn order(&self) -> VideoListOrder {
         self.order
     }
@@ -165,12 +239,20 @@ impl SeriesBlock {
     fn index(&self) -> i32 {
         self.shared().index
     }
+
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
 }
 
+#[derive(Clone)]
 pub(crate) struct VideoBlock {
     pub(crate) shared: SharedData,
     pub(crate) event: Option<Id>,
     pub(crate) show_title: bool,
+    pub(crate) canonical: bool,
+    /// See `SeriesBlock::member_access`.
+    pub(crate) member_access: bool,
 }
 
 impl Block for VideoBlock {
@@ -179,14 +261,24 @@ impl Block for VideoBlock {
     }
 }
 
+#[graphql_interface]
+impl Node for VideoBlock {
+    fn id(&self) -> Id {
+        self.shared.id
+    }
+}
+
 /// A block for presenting a single Opencast event
-#[graphql_object(Context = Context, impl = BlockValue)]
+#[graphql_object(Context = Context, impl = [BlockValue, NodeValue])]
 impl VideoBlock {
     async fn event(&self, context: &Context) -> ApiResult<Option<Event>> {
         match self.event {
             None => Ok(None),
             // `unwrap` is okay here because of our foreign key constraint
-            Some(event_id) => Ok(Some(Event::load_by_id(event_id, context).await?.unwrap())),
+            Some(event_id) => {
+                let event = Event::load_for_block(event_id, self.member_access, context).await?;
+                Ok(Some(event.unwrap()))
+            }
         }
     }
 
@@ -194,6 +286,131 @@ impl VideoBlock {
         self.show_title
     }
 
+    /// Whether this realm should be preferred as the canonical page of the
+    /// shown videos (see `Event.hostRealms`).
+    fn canonical(&self) -> bool {
+        self.canonical
+    }
+
+    fn id(&self) -> Id {
+        self.shared().id
+    }
+
+    fn index(&self) -> i32 {
+        self.shared().index
+    }
+
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CreatorBlock {
+    pub(crate) shared: SharedData,
+    pub(crate) creator: String,
+    pub(crate) show_title: bool,
+    pub(crate) order: VideoListOrder,
+    pub(crate) canonical: bool,
+}
+
+impl Block for CreatorBlock {
+    fn shared(&self) -> &SharedData {
+        &self.shared
+    }
+}
+
+#[graphql_interface]
+impl Node for CreatorBlock {
+    fn id(&self) -> Id {
+        self.shared.id
+    }
+}
+
+/// A block listing all events that have a specific person as creator. The
+/// list is not stored, so newly synced events show up automatically.
+#[graphql_object(Context = Context, impl = [BlockValue, NodeValue])]
+impl CreatorBlock {
+    /// The creator name events are matched against (exactly, as it appears
+    /// in `Event.creators`).
+    fn creator(&self) -> &str {
+        &self.creator
+    }
+
+    /// All events by the creator that the current user can read. `filter`
+    /// and `captioned` work like in `Series.events`.
+    async fn events(
+        &self,
+        filter: Option<String>,
+        captioned: Option<bool>,
+        context: &Context,
+    ) -> ApiResult<Vec<Event>> {
+        let order = EventSortOrder::default();
+        Event::load_for_creator(&self.creator, order, filter, captioned, context).await
+    }
+
+    /// All values of the field configured as `general.event_filter` of
+    /// `events`, sorted.
+    async fn event_filter_values(&self, context: &Context) -> ApiResult<Vec<String>> {
+        let condition = "creators @> array[$3] and read_roles && $4";
+        let args = dbargs![&self.creator, &context.user.roles()];
+        Event::load_filter_values(condition, &args, context).await
+    }
+
+    fn show_title(&self) -> bool {
+        self.show_title
+    }
+
+    /// Whether this realm should be preferred as the canonical page of the
+    /// shown videos (see `Event.hostRealms`).
+    fn canonical(&self) -> bool {
+        self.canonical
+    }
+
+    fn order(&self) -> VideoListOrder {
+        self.order
+    }
+
+    fn id(&self) -> Id {
+        self.shared().id
+    }
+
+    fn index(&self) -> i32 {
+        self.shared().index
+    }
+
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ExtensionBlock {
+    pub(crate) shared: SharedData,
+    pub(crate) data: ExtensionBlockData,
+}
+
+impl Block for ExtensionBlock {
+    fn shared(&self) -> &SharedData {
+        &self.shared
+    }
+}
+
+#[graphql_interface]
+impl Node for ExtensionBlock {
+    fn id(&self) -> Id {
+        self.shared.id
+    }
+}
+
+/// A block of a compiled-in extension (see `docs/extensions.md`). Only shown
+/// by frontends that know the extension.
+#[graphql_object(Context = Context, impl = [BlockValue, NodeValue])]
+impl ExtensionBlock {
+    fn data(&self) -> &ExtensionBlockData {
+        &self.data
+    }
+
     fn id(&self) -> Id {
         self.shared().id
     }
@@ -201,62 +418,197 @@ impl VideoBlock {
     fn index(&self) -> i32 {
         self.shared().index
     }
+
+    fn read_roles(&self) -> Option<&Vec<String>> {
+        self.shared().read_roles.as_ref()
+    }
+}
+
+// The enum is generated by `graphql_interface`, so `Clone` can't be derived.
+impl Clone for BlockValue {
+    fn clone(&self) -> Self {
+        match self {
+            Self::TitleBlock(block) => block.clone().into(),
+            Self::TextBlock(block) => block.clone().into(),
+            Self::SeriesBlock(block) => block.clone().into(),
+            Self::VideoBlock(block) => block.clone().into(),
+            Self::CreatorBlock(block) => block.clone().into(),
+            Self::ExtensionBlock(block) => block.clone().into(),
+        }
+    }
 }
 
 impl BlockValue {
     /// Fetches all blocks for the given realm from the database.
     pub(crate) async fn load_for_realm(realm_key: Key, context: &Context) -> ApiResult<Vec<Self>> {
+        let member_access = Self::member_access(realm_key, context).await?;
+
+        let load = async {
+            context.db
+                .query_mapped(
+                    &format!(
+                        "select {} \
+                            from blocks \
+                            where realm_id = $1 \
+                            order by index asc",
+                        cols::COL_NAMES,
+                    ),
+                    &[realm_key],
+                    |row| Self::from_row(row, member_access),
+                )
+                .await?
+                .into_iter()
+                .collect()
+        };
+
+        // The home page is requested far more often than any other page.
+        // The cached blocks are the same for all users, so restricted blocks
+        // are only filtered out afterwards.
+        let blocks = if realm_key == Key(0) {
+            let key = format!("home-blocks:{member_access}");
+            context.cache
+                .get_or_load(CacheTag::Blocks, key, Duration::from_secs(300), load)
+                .await?
+        } else {
+            load.await?
+        };
+
+        Self::retain_visible(blocks, realm_key, context).await
+    }
+
+    /// Removes the blocks the current user must not see (see
+    /// `Block.readRoles`). Moderators of `realm_key` see all blocks, so that
+    /// they can edit them.
+    async fn retain_visible(
+        mut blocks: Vec<Self>,
+        realm_key: Key,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let roles = context.user.roles();
+        let is_visible = |block: &Self| match &block.shared().read_roles {
+            None => true,
+            Some(read_roles) => read_roles.iter().any(|role| roles.contains(role)),
+        };
+        if blocks.iter().all(is_visible) {
+            return Ok(blocks);
+        }
+
+        // Only checked if necessary, as it might require a query.
+        let can_edit = match context.require_moderator() {
+            Ok(_) => true,
+            Err(_) => match &context.user {
+                Some(user) => auth::require_delegated_moderator(&context.db, user, realm_key)
+                    .await?
+                    .is_some(),
+                None => false,
+            },
+        };
+        if !can_edit {
+            blocks.retain(is_visible);
+        }
+
+        Ok(blocks)
+    }
+
+    /// Fetches the block with the given ID.
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        let key = match id.key_for(Id::BLOCK_KIND) {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+
+        let query = format!("select {}, realm_id from blocks where id = $1", cols::COL_NAMES);
+        let row = match context.db.query_opt(&query, &[&key]).await? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        let realm_key = row.get("realm_id");
+        let member_access = Self::member_access(realm_key, context).await?;
+
+        let block = Self::from_row(row, member_access)?;
+        Self::retain_visible(vec![block], realm_key, context).await?
+            .pop()
+            .pipe(Ok)
+    }
+
+    /// Returns whether the current user has any member role of the given realm
+    /// or its ancestors (see `Realm::member_roles`).
+    pub(crate) async fn member_access(realm_key: Key, context: &Context) -> ApiResult<bool> {
         context.db
-            .query_raw(
-                &format!(
-                    "select {} \
-                        from blocks \
-                        where realm_id = $1 \
-                        order by index asc",
-                    Self::COL_NAMES,
-                ),
-                &[realm_key],
+            .query_one(
+                "select exists(\
+                    select 1 from ancestors_of_realm($1) where member_roles && $2\
+                )",
+                &[&realm_key, &context.user.roles()],
             )
             .await?
-            .err_into::<ApiError>()
-            .and_then(|row| async move { Self::from_row(row) })
-            .try_collect()
-            .await
-            .map_err(Into::into)
+            .get::<_, bool>(0)
+            .pipe(Ok)
     }
 
-    const COL_NAMES: &'static str
-        = "id, type, index, text_content, series_id, videolist_order, video_id, show_title";
-
-    fn from_row(row: Row) -> ApiResult<Self> {
-        let ty: BlockType = row.get(1);
+    /// `member_access` is whether the current user has a member role of the
+    /// realm of this block.
+    fn from_row(row: Row, member_access: bool) -> ApiResult<Self> {
         let shared = SharedData {
-            id: Id::block(row.get(0)),
-            index: row.get::<_, i16>(2).into(),
+            id: Id::block(cols::key(&row)),
+            index: cols::index(&row).into(),
+            read_roles: cols::read_roles(&row),
         };
 
-        let block = match ty {
+        let block = match cols::ty(&row) {
             BlockType::Title => TitleBlock {
                 shared,
-                content: get_type_dependent(&row, 3, "title", "text_content")?,
+                content: get_type_dependent(cols::text_content(&row), "title", "text_content")?,
             }.into(),
 
             BlockType::Text => TextBlock {
                 shared,
-                content: get_type_dependent(&row, 3, "text", "text_content")?,
+                content: get_type_dependent(cols::text_content(&row), "text", "text_content")?,
             }.into(),
 
             BlockType::Series => SeriesBlock {
                 shared,
-                series: row.get::<_, Option<Key>>(4).map(Id::series),
-                order: get_type_dependent(&row, 5, "videolist", "videolist_order")?,
-                show_title: get_type_dependent(&row, 7, "titled", "show_title")?,
+                series: cols::series(&row).map(Id::series),
+                order: get_type_dependent(
+                    cols::videolist_order(&row),
+                    "videolist",
+                    "videolist_order",
+                )?,
+                show_title: get_type_dependent(cols::show_title(&row), "titled", "show_title")?,
+                canonical: cols::canonical(&row),
+                member_access,
             }.into(),
 
             BlockType::Video => VideoBlock {
                 shared,
-                event: row.get::<_, Option<Key>>(6).map(Id::event),
-                show_title: get_type_dependent(&row, 7, "titled", "show_title")?,
+                event: cols::event(&row).map(Id::event),
+                show_title: get_type_dependent(cols::show_title(&row), "titled", "show_title")?,
+                canonical: cols::canonical(&row),
+                member_access,
+            }.into(),
+
+            BlockType::Creator => CreatorBlock {
+                shared,
+                creator: get_type_dependent(cols::creator(&row), "creator", "creator")?,
+                order: get_type_dependent(
+                    cols::videolist_order(&row),
+                    "videolist",
+                    "videolist_order",
+                )?,
+                show_title: get_type_dependent(cols::show_title(&row), "titled", "show_title")?,
+                canonical: cols::canonical(&row),
+            }.into(),
+
+            BlockType::Extension => ExtensionBlock {
+                shared,
+                data: ExtensionBlockData {
+                    extension: get_type_dependent(cols::extension(&row), "extension", "extension")?,
+                    data: get_type_dependent(
+                        cols::extension_data(&row),
+                        "extension",
+                        "extension_data",
+                    )?,
+                },
             }.into(),
         };
 
@@ -264,16 +616,29 @@ impl BlockValue {
     }
 }
 
-/// Helper functions to fetch fields from the table that are bound to a type of
-/// block. For example, "text" blocks need to have the "text_content" column
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        ty: BlockType = "type",
+        index: i16 = "index",
+        text_content: Option<String> = "text_content",
+        series: Option<Key> = "series_id",
+        videolist_order: Option<VideoListOrder> = "videolist_order",
+        event: Option<Key> = "video_id",
+        show_title: Option<bool> = "show_title",
+        creator: Option<String> = "creator",
+        canonical: bool = "canonical",
+        extension: Option<String> = "extension",
+        extension_data: Option<serde_json::Value> = "extension_data",
+        read_roles: Option<Vec<String>> = "read_roles",
+    }
+}
+
+/// Helper functions to check fields from the table that are bound to a type
+/// of block. For example, "text" blocks need to have the "text_content" column
 /// set (non-null).
-fn get_type_dependent<'a, T: FromSql<'a>>(
-    row: &'a Row,
-    idx: usize,
-    type_name: &str,
-    field_name: &str,
-) -> ApiResult<T> {
-    row.get::<_, Option<_>>(idx).ok_or_else(|| internal_server_err!(
+fn get_type_dependent<T>(value: Option<T>, type_name: &str, field_name: &str) -> ApiResult<T> {
+    value.ok_or_else(|| internal_server_err!(
         "DB broken: block with type='{}' has null `{}`",
         type_name,
         field_name,
@@ -321,3 +686,16 @@ impl TryFrom<BlockValue> for SeriesBlock {
         }
     }
 }
+
+impl From<BlockValue> for NodeValue {
+    fn from(block: BlockValue) -> Self {
+        match block {
+            BlockValue::TitleBlock(b) => b.into(),
+            BlockValue::TextBlock(b) => b.into(),
+            BlockValue::SeriesBlock(b) => b.into(),
+            BlockValue::VideoBlock(b) => b.into(),
+            BlockValue::CreatorBlock(b) => b.into(),
+            BlockValue::ExtensionBlock(b) => b.into(),
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use futures::StreamExt;
 use juniper::{GraphQLInputObject, GraphQLObject};
 
-use crate::{api::{Context, Id, err::{ApiResult, invalid_input}}, dbargs};
+use crate::{
+    api::{Context, Id, cache::CacheTag, err::{ApiResult, invalid_input, not_authorized}},
+    auth::AuthToken,
+    dbargs,
+    prelude::*,
+};
 use crate::db::types::Key;
 use super::{BlockValue, VideoListOrder, super::realm::Realm};
 
@@ -13,7 +17,7 @@ impl BlockValue {
         block: NewTitleBlock,
         context: &Context,
     ) -> ApiResult<Realm> {
-        context.require_moderator()?;
+        Self::require_moderator_of_realm(&realm, context).await?;
 
         let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
 
@@ -36,7 +40,7 @@ impl BlockValue {
         block: NewTextBlock,
         context: &Context,
     ) -> ApiResult<Realm> {
-        context.require_moderator()?;
+        Self::require_moderator_of_realm(&realm, context).await?;
 
         let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
 
@@ -59,19 +63,22 @@ impl BlockValue {
         block: NewSeriesBlock,
         context: &Context,
     ) -> ApiResult<Realm> {
-        context.require_moderator()?;
-
-        let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
+        Self::require_moderator_of_realm(&realm, context).await?;
 
         let series = block.series.key_for(Id::SERIES_KIND)
             .ok_or_else(|| invalid_input!("`block.series` does not refer to a series"))?;
+        if let Some(realm) = realm.key_for(Id::REALM_KIND) {
+            Self::require_mountable(realm, Mounted::Series(series), context).await?;
+        }
+
+        let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
 
         context.db
             .execute(
                 "insert into blocks \
-                    (realm_id, index, type, series_id, videolist_order, show_title) \
-                    values ($1, $2, 'series', $3, $4, $5)",
-                &[&realm, &index, &series, &block.order, &block.show_title],
+                    (realm_id, index, type, series_id, videolist_order, show_title, canonical) \
+                    values ($1, $2, 'series', $3, $4, $5, $6)",
+                &[&realm, &index, &series, &block.order, &block.show_title, &block.canonical],
             )
             .await?;
 
@@ -86,18 +93,70 @@ impl BlockValue {
         block: NewVideoBlock,
         context: &Context,
     ) -> ApiResult<Realm> {
-        context.require_moderator()?;
-
-        let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
+        Self::require_moderator_of_realm(&realm, context).await?;
 
         let event = block.event.key_for(Id::EVENT_KIND)
             .ok_or_else(|| invalid_input!("`block.event` does not refer to an event"))?;
+        if let Some(realm) = realm.key_for(Id::REALM_KIND) {
+            Self::require_mountable(realm, Mounted::Event(event), context).await?;
+        }
+
+        let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
+
+        context.db
+            .execute(
+                "insert into blocks (realm_id, index, type, video_id, show_title, canonical) \
+                    values ($1, $2, 'video', $3, $4, $5)",
+                &[&realm, &index, &event, &block.show_title, &block.canonical],
+            )
+            .await?;
+
+        Realm::load_by_key(realm, context)
+            .await?
+            .ok_or_else(|| invalid_input!("`realm` does not refer to a valid realm"))
+    }
+
+    pub(crate) async fn add_creator(
+        realm: Id,
+        index: i32,
+        block: NewCreatorBlock,
+        context: &Context,
+    ) -> ApiResult<Realm> {
+        Self::require_moderator_of_realm(&realm, context).await?;
+
+        let creator = Self::validate_creator(&block.creator)?;
+        let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
+
+        context.db
+            .execute(
+                "insert into blocks \
+                    (realm_id, index, type, creator, videolist_order, show_title, canonical) \
+                    values ($1, $2, 'creator', $3, $4, $5, $6)",
+                &[&realm, &index, &creator, &block.order, &block.show_title, &block.canonical],
+            )
+            .await?;
+
+        Realm::load_by_key(realm, context)
+            .await?
+            .ok_or_else(|| invalid_input!("`realm` does not refer to a valid realm"))
+    }
+
+    pub(crate) async fn add_extension(
+        realm: Id,
+        index: i32,
+        block: NewExtensionBlock,
+        context: &Context,
+    ) -> ApiResult<Realm> {
+        Self::require_moderator_of_realm(&realm, context).await?;
+
+        let data = context.extensions.block_data(&block.extension, &block.data)?;
+        let (realm, index) = Self::prepare_realm_for_block(realm, index, context).await?;
 
         context.db
             .execute(
-                "insert into blocks (realm_id, index, type, video_id, show_title) \
-                    values ($1, $2, 'video', $3, $4)",
-                &[&realm, &index, &event, &block.show_title],
+                "insert into blocks (realm_id, index, type, extension, extension_data) \
+                    values ($1, $2, 'extension', $3, $4)",
+                &[&realm, &index, &block.extension, &data],
             )
             .await?;
 
@@ -106,6 +165,84 @@ impl BlockValue {
             .ok_or_else(|| invalid_input!("`realm` does not refer to a valid realm"))
     }
 
+    /// Makes sure the current user is allowed to moderate `realm`, either as
+    /// moderator or by delegation. If `realm` is not a realm ID, only
+    /// moderators pass, who then get an error about the invalid ID.
+    async fn require_moderator_of_realm(realm: &Id, context: &Context) -> ApiResult<AuthToken> {
+        context.cache.modified(CacheTag::Blocks);
+        match realm.key_for(Id::REALM_KIND) {
+            Some(key) => context.require_realm_moderator(key).await,
+            None => context.require_moderator(),
+        }
+    }
+
+    /// Like `require_moderator_of_realm`, but for the realm containing the
+    /// block `id`.
+    async fn require_moderator_of_block(id: &Id, context: &Context) -> ApiResult<AuthToken> {
+        context.cache.modified(CacheTag::Blocks);
+        match Self::realm_of_block(id, context).await? {
+            Some(realm) => context.require_realm_moderator(realm).await,
+            None => context.require_moderator(),
+        }
+    }
+
+    /// Returns the realm containing the block `id`, if it exists.
+    async fn realm_of_block(id: &Id, context: &Context) -> ApiResult<Option<Key>> {
+        let Some(key) = id.key_for(Id::BLOCK_KIND) else {
+            return Ok(None);
+        };
+
+        context.db
+            .query_opt("select realm_id from blocks where id = $1", &[&key])
+            .await?
+            .map(|row| row.get::<_, Key>(0))
+            .pipe(Ok)
+    }
+
+    /// Makes sure the current user may show `mounted` in `realm`. Moderators
+    /// always may. Moderators by delegation can only mount what they can
+    /// read themselves, i.e. an event they can read or a series without
+    /// events hidden from them. They can't mount anything in realms where
+    /// any ancestor has member roles, as members then get access to it.
+    async fn require_mountable(realm: Key, mounted: Mounted, context: &Context) -> ApiResult<()> {
+        if context.require_moderator().is_ok() {
+            return Ok(());
+        }
+
+        let query = match mounted {
+            Mounted::Series(_) => "select \
+                not exists(select from ancestors_of_realm($1) where member_roles <> '{}') \
+                and not exists(select from events where series = $2 and not read_roles && $3)",
+            Mounted::Event(_) => "select \
+                not exists(select from ancestors_of_realm($1) where member_roles <> '{}') \
+                and exists(select from events where id = $2 and read_roles && $3)",
+        };
+        let (Mounted::Series(key) | Mounted::Event(key)) = mounted;
+        let allowed = context.db
+            .query_one(query, &[&realm, &key, &context.user.roles()])
+            .await?
+            .get::<_, bool>(0);
+        if !allowed {
+            return Err(not_authorized!(
+                key = "mutation.content-not-mountable",
+                "moderators by delegation can only show content they can read, \
+                    and only in realms without member roles",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Trims the creator name and makes sure it's not empty. Creators of
+    /// events are sanitized during sync, so the name is matched exactly.
+    fn validate_creator(creator: &str) -> ApiResult<&str> {
+        let creator = creator.trim();
+        if creator.is_empty() {
+            return Err(invalid_input!("`creator` must not be empty"));
+        }
+        Ok(creator)
+    }
+
     /// For all blocks in `realm` with an index `>= index`,
     /// increase their index by `1`.
     /// This basically moves all the blocks after the `index`-th one aside,
@@ -161,7 +298,7 @@ impl BlockValue {
                 .ok_or_else(|| invalid_input!("`realm` is not a valid realm"));
         }
 
-        let db = context.db(context.require_moderator()?);
+        let db = context.db(Self::require_moderator_of_realm(&realm, context).await?);
 
         // The next query will swap two blocks' indices;
         // during the execution of that statement a moment will exist
@@ -169,8 +306,8 @@ impl BlockValue {
         // Since this violates one of our constraints, we defer it.
         db.execute("set constraints index_unique_in_realm deferred", &[]).await?;
 
-        let realm_stream = db
-            .query_raw(
+        let realms = db
+            .query_mapped(
                 &format!(
                     // This query is a bit involved, but this allows us to do the full swap in one
                     // go, including "bound checking".
@@ -210,28 +347,20 @@ impl BlockValue {
                     &realm.key_for(Id::REALM_KIND)
                         .ok_or_else(|| invalid_input!("`realm` is not a valid realm id"))?,
                 ],
+                Realm::from_row,
             )
-            .await?
-            // We will get the realm twice for two updated rows
-            // if everything goes according to plan.
-            // If we skip the first and can successfully grab the second,
-            // we know it did.
-            .skip(1);
+            .await?;
 
         // TODO Actually reset to whatever it was before, but that needs nested transactions
         db.execute("set constraints index_unique_in_realm immediate", &[]).await?;
 
-        futures::pin_mut!(realm_stream);
-
-        let realm = realm_stream
-            .next()
-            .await
-            .ok_or_else(|| invalid_input!(
-                "`indexA`, `indexB` or `realm` wasn't a valid block index or realm"
-            ))?
-            .map(Realm::from_row)?;
-
-        Ok(realm)
+        // We will get the realm twice for two updated rows
+        // if everything goes according to plan.
+        // If we skip the first and can successfully grab the second,
+        // we know it did.
+        realms.into_iter().nth(1).ok_or_else(|| invalid_input!(
+            "`indexA`, `indexB` or `realm` wasn't a valid block index or realm"
+        ))
     }
 
     pub(crate) async fn update_title(
@@ -239,7 +368,7 @@ impl BlockValue {
         set: UpdateTitleBlock,
         context: &Context,
     ) -> ApiResult<BlockValue> {
-        let updated_block = context.db(context.require_moderator()?)
+        let updated_block = context.db(Self::require_moderator_of_block(&id, context).await?)
             .query_one(
                 &format!(
                     "update blocks set \
@@ -247,7 +376,7 @@ impl BlockValue {
                         where id = $1 \
                         and type = 'title' \
                         returning {}",
-                    Self::COL_NAMES,
+                    super::cols::COL_NAMES,
                 ),
                 &[
                     &id.key_for(Id::BLOCK_KIND)
@@ -257,7 +386,7 @@ impl BlockValue {
             )
             .await?;
 
-        Ok(Self::from_row(updated_block)?)
+        Self::from_row(updated_block, false)
     }
 
     pub(crate) async fn update_text(
@@ -265,7 +394,7 @@ impl BlockValue {
         set: UpdateTextBlock,
         context: &Context,
     ) -> ApiResult<BlockValue> {
-        let updated_block = context.db(context.require_moderator()?)
+        let updated_block = context.db(Self::require_moderator_of_block(&id, context).await?)
             .query_one(
                 &format!(
                     "update blocks set \
@@ -273,7 +402,7 @@ impl BlockValue {
                         where id = $1 \
                         and type = 'text' \
                         returning {}",
-                    Self::COL_NAMES,
+                    super::cols::COL_NAMES,
                 ),
                 &[
                     &id.key_for(Id::BLOCK_KIND)
@@ -283,7 +412,7 @@ impl BlockValue {
             )
             .await?;
 
-        Ok(Self::from_row(updated_block)?)
+        Self::from_row(updated_block, false)
     }
 
     pub(crate) async fn update_series(
@@ -291,32 +420,40 @@ impl BlockValue {
         set: UpdateSeriesBlock,
         context: &Context,
     ) -> ApiResult<BlockValue> {
-        let updated_block = context.db(context.require_moderator()?)
+        let token = Self::require_moderator_of_block(&id, context).await?;
+        let series = set.series.map(
+            |series| series.key_for(Id::SERIES_KIND)
+                .ok_or_else(|| invalid_input!("`set.series` does not refer to a series"))
+        ).transpose()?;
+        if let (Some(series), Some(realm)) = (series, Self::realm_of_block(&id, context).await?) {
+            Self::require_mountable(realm, Mounted::Series(series), context).await?;
+        }
+
+        let updated_block = context.db(token)
             .query_one(
                 &format!(
                     "update blocks set \
                         series_id = coalesce($2, series_id), \
                         videolist_order = coalesce($3, videolist_order), \
-                        show_title = coalesce($4, show_title) \
+                        show_title = coalesce($4, show_title), \
+                        canonical = coalesce($5, canonical) \
                         where id = $1 \
                         and type = 'series' \
                         returning {}",
-                    Self::COL_NAMES,
+                    super::cols::COL_NAMES,
                 ),
                 &[
                     &id.key_for(Id::BLOCK_KIND)
                         .ok_or_else(|| invalid_input!("`id` does not refer to a block"))?,
-                    &set.series.map(
-                        |series| series.key_for(Id::SERIES_KIND)
-                            .ok_or_else(|| invalid_input!("`set.series` does not refer to a series"))
-                    ).transpose()?,
+                    &series,
                     &set.order,
                     &set.show_title,
+                    &set.canonical,
                 ],
             )
             .await?;
 
-        Ok(Self::from_row(updated_block)?)
+        Self::from_row(updated_block, false)
     }
 
     pub(crate) async fn update_video(
@@ -324,34 +461,141 @@ impl BlockValue {
         set: UpdateVideoBlock,
         context: &Context,
     ) -> ApiResult<BlockValue> {
-        let updated_block = context.db(context.require_moderator()?)
+        let token = Self::require_moderator_of_block(&id, context).await?;
+        let event = set.event.map(
+            |event| event.key_for(Id::EVENT_KIND)
+                .ok_or_else(|| invalid_input!("`set.event` does not refer to a event"))
+        ).transpose()?;
+        if let (Some(event), Some(realm)) = (event, Self::realm_of_block(&id, context).await?) {
+            Self::require_mountable(realm, Mounted::Event(event), context).await?;
+        }
+
+        let updated_block = context.db(token)
             .query_one(
                 &format!(
                     "update blocks set \
                         video_id = coalesce($2, video_id), \
-                        show_title = coalesce($3, show_title) \
+                        show_title = coalesce($3, show_title), \
+                        canonical = coalesce($4, canonical) \
                         where id = $1 \
                         and type = 'video' \
                         returning {}",
-                    Self::COL_NAMES,
+                    super::cols::COL_NAMES,
+                ),
+                &[
+                    &id.key_for(Id::BLOCK_KIND)
+                        .ok_or_else(|| invalid_input!("`id` does not refer to a block"))?,
+                    &event,
+                    &set.show_title,
+                    &set.canonical,
+                ],
+            )
+            .await?;
+
+        Self::from_row(updated_block, false)
+    }
+
+    pub(crate) async fn update_creator(
+        id: Id,
+        set: UpdateCreatorBlock,
+        context: &Context,
+    ) -> ApiResult<BlockValue> {
+        let creator = set.creator.as_deref().map(Self::validate_creator).transpose()?;
+        let updated_block = context.db(Self::require_moderator_of_block(&id, context).await?)
+            .query_one(
+                &format!(
+                    "update blocks set \
+                        creator = coalesce($2, creator), \
+                        videolist_order = coalesce($3, videolist_order), \
+                        show_title = coalesce($4, show_title), \
+                        canonical = coalesce($5, canonical) \
+                        where id = $1 \
+                        and type = 'creator' \
+                        returning {}",
+                    super::cols::COL_NAMES,
                 ),
                 &[
                     &id.key_for(Id::BLOCK_KIND)
                         .ok_or_else(|| invalid_input!("`id` does not refer to a block"))?,
-                    &set.event.map(
-                        |series| series.key_for(Id::EVENT_KIND)
-                            .ok_or_else(|| invalid_input!("`set.event` does not refer to a event"))
-                    ).transpose()?,
+                    &creator,
+                    &set.order,
                     &set.show_title,
+                    &set.canonical,
                 ],
             )
             .await?;
 
-        Ok(Self::from_row(updated_block)?)
+        Self::from_row(updated_block, false)
+    }
+
+    pub(crate) async fn update_extension(
+        id: Id,
+        set: UpdateExtensionBlock,
+        context: &Context,
+    ) -> ApiResult<BlockValue> {
+        let db = context.db(Self::require_moderator_of_block(&id, context).await?);
+        let key = id.key_for(Id::BLOCK_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a block"))?;
+        let extension: String = db
+            .query_opt("select extension from blocks where id = $1 and type = 'extension'", &[&key])
+            .await?
+            .ok_or_else(|| invalid_input!("`id` does not refer to an extension block"))?
+            .get(0);
+        let data = context.extensions.block_data(&extension, &set.data)?;
+
+        let updated_block = db
+            .query_one(
+                &format!(
+                    "update blocks set extension_data = $2 where id = $1 returning {}",
+                    super::cols::COL_NAMES,
+                ),
+                &[&key, &data],
+            )
+            .await?;
+
+        Self::from_row(updated_block, false)
+    }
+
+    /// Sets `read_roles` of the block. An empty list makes it visible to
+    /// everyone.
+    pub(crate) async fn set_read_roles(
+        id: Id,
+        roles: Vec<String>,
+        context: &Context,
+    ) -> ApiResult<BlockValue> {
+        let db = context.db(Self::require_moderator_of_block(&id, context).await?);
+        let mut read_roles = Vec::<String>::new();
+        for role in roles {
+            let role = role.trim();
+            if role.is_empty() {
+                return Err(invalid_input!("`roles` must not contain empty roles"));
+            }
+            if !read_roles.iter().any(|r| r == role) {
+                read_roles.push(role.to_owned());
+            }
+        }
+        let read_roles = (!read_roles.is_empty()).then_some(read_roles);
+
+        let updated_block = db
+            .query_opt(
+                &format!(
+                    "update blocks set read_roles = $2 where id = $1 returning {}",
+                    super::cols::COL_NAMES,
+                ),
+                &[
+                    &id.key_for(Id::BLOCK_KIND)
+                        .ok_or_else(|| invalid_input!("`id` does not refer to a block"))?,
+                    &read_roles,
+                ],
+            )
+            .await?
+            .ok_or_else(|| invalid_input!("`id` does not refer to an existing block"))?;
+
+        Self::from_row(updated_block, false)
     }
 
     pub(crate) async fn remove(id: Id, context: &Context) -> ApiResult<RemovedBlock> {
-        let db = context.db(context.require_moderator()?);
+        let db = context.db(Self::require_moderator_of_block(&id, context).await?);
 
         let result = db
             .query_one(
@@ -389,6 +633,13 @@ impl BlockValue {
 }
 
 
+/// Content shown by a series or video block, see `require_mountable`.
+#[derive(Clone, Copy)]
+enum Mounted {
+    Series(Key),
+    Event(Key),
+}
+
 #[derive(GraphQLInputObject)]
 pub(crate) struct NewTitleBlock {
     content: String,
@@ -404,12 +655,34 @@ pub(crate) struct NewSeriesBlock {
     series: Id,
     show_title: bool,
     order: VideoListOrder,
+    #[graphql(default = "false")]
+    canonical: bool,
 }
 
 #[derive(GraphQLInputObject)]
 pub(crate) struct NewVideoBlock {
     event: Id,
     show_title: bool,
+    #[graphql(default = "false")]
+    canonical: bool,
+}
+
+#[derive(GraphQLInputObject)]
+pub(crate) struct NewCreatorBlock {
+    /// Name as it appears in the creators of events.
+    creator: String,
+    show_title: bool,
+    order: VideoListOrder,
+    #[graphql(default = "false")]
+    canonical: bool,
+}
+
+#[derive(GraphQLInputObject)]
+pub(crate) struct NewExtensionBlock {
+    /// Name of the extension the block belongs to.
+    extension: String,
+    /// The block data as JSON, in the format defined by the extension.
+    data: String,
 }
 
 
@@ -428,12 +701,28 @@ pub(crate) struct UpdateSeriesBlock {
     series: Option<Id>,
     show_title: Option<bool>,
     order: Option<VideoListOrder>,
+    canonical: Option<bool>,
 }
 
 #[derive(GraphQLInputObject)]
 pub(crate) struct UpdateVideoBlock {
     event: Option<Id>,
     show_title: Option<bool>,
+    canonical: Option<bool>,
+}
+
+#[derive(GraphQLInputObject)]
+pub(crate) struct UpdateCreatorBlock {
+    creator: Option<String>,
+    show_title: Option<bool>,
+    order: Option<VideoListOrder>,
+    canonical: Option<bool>,
+}
+
+#[derive(GraphQLInputObject)]
+pub(crate) struct UpdateExtensionBlock {
+    /// The new block data as JSON, see `NewExtensionBlock.data`.
+    data: String,
 }
 
 
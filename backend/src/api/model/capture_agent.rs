@@ -0,0 +1,230 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{Body, Request, Uri};
+use hyper_rustls::HttpsConnectorBuilder;
+use juniper::GraphQLObject;
+use once_cell::sync::Lazy;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{
+    api::{Context, err::{ApiResult, internal_server_err, not_authorized}},
+    auth::HasRoles,
+    config::Config,
+    http::HttpClient,
+    prelude::*,
+};
+
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct CaptureAgentsConfig {
+    /// Whether `captureAgents` in the API returns the status of the capture
+    /// agents and their scheduled captures, fetched from the external API of
+    /// `opencast.sync_node`. The sync user needs the roles
+    /// `ROLE_API_CAPTURE_AGENTS_VIEW` and `ROLE_API_EVENTS_VIEW` for that.
+    #[config(default = false)]
+    pub(crate) enabled: bool,
+
+    /// Users with this role can see the status, in addition to moderators.
+    /// Useful to let staff check whether a lecture hall is recording
+    /// without giving them access to the Opencast admin UI.
+    pub(crate) role: Option<String>,
+
+    /// How long the data fetched from Opencast is reused.
+    #[config(default = "1min", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) cache_duration: Duration,
+
+    /// How far into the future scheduled captures are returned. At most
+    /// "366d".
+    #[config(default = "2d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) upcoming: Duration,
+}
+
+impl CaptureAgentsConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.upcoming > Duration::from_secs(366 * 24 * 60 * 60) {
+            bail!("`capture_agents.upcoming` must not be longer than 366 days");
+        }
+
+        Ok(())
+    }
+}
+
+/// Status of all capture agents registered in Opencast.
+#[derive(Clone, GraphQLObject)]
+pub(crate) struct CaptureAgentStatus {
+    /// When the data was fetched from Opencast.
+    fetched: DateTime<Utc>,
+    /// All capture agents, ordered by name.
+    agents: Vec<CaptureAgent>,
+}
+
+#[derive(Clone, GraphQLObject)]
+pub(crate) struct CaptureAgent {
+    name: String,
+    /// State as reported by the agent, e.g. `idle`, `capturing`, `uploading`
+    /// or `offline`.
+    state: String,
+    /// When the agent last reported its state.
+    last_update: Option<DateTime<Utc>>,
+    /// Captures scheduled on this agent that did not end yet, ordered by
+    /// start.
+    captures: Vec<ScheduledCapture>,
+}
+
+#[derive(Clone, GraphQLObject)]
+pub(crate) struct ScheduledCapture {
+    /// Opencast ID of the scheduled event.
+    opencast_id: String,
+    title: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+/// The last status fetched from Opencast and when it was fetched.
+type Cached = (Instant, Arc<CaptureAgentStatus>);
+
+/// Shared by all requests. The lock is held while fetching, so that Opencast
+/// only receives one request at a time.
+static CACHE: Lazy<tokio::sync::Mutex<Option<Cached>>> = Lazy::new(Default::default);
+
+static HTTP_CLIENT: Lazy<HttpClient> = Lazy::new(|| {
+    hyper::Client::builder().build(
+        HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build(),
+    )
+});
+
+/// How many scheduled events are fetched at most.
+const MAX_EVENTS: u32 = 1000;
+
+/// Captures that started more than this many hours ago are not considered,
+/// even if they did not end yet.
+const MAX_CAPTURE_HOURS: i64 = 24;
+
+impl CaptureAgentStatus {
+    pub(crate) async fn load(context: &Context) -> ApiResult<Arc<Self>> {
+        let config = &context.config.capture_agents;
+        if !config.enabled {
+            return Err(not_authorized!("`capture_agents.enabled` is not set"));
+        }
+        let has_role = config.role.as_ref().is_some_and(|role| context.user.roles().contains(role));
+        if !has_role {
+            context.require_moderator()?;
+        }
+
+        let mut cache = CACHE.lock().await;
+        if let Some((fetched, status)) = &*cache {
+            if fetched.elapsed() < config.cache_duration {
+                return Ok(status.clone());
+            }
+        }
+
+        let status = Self::fetch(&context.config).await.map_err(|e| {
+            error!("Failed to fetch capture agent status from Opencast: {:#}", e);
+            internal_server_err!("failed to fetch capture agent status from Opencast")
+        })?;
+        let status = Arc::new(status);
+        *cache = Some((Instant::now(), status.clone()));
+        Ok(status)
+    }
+
+    async fn fetch(config: &Config) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct OcAgent {
+            agent_id: String,
+            status: String,
+            update: Option<DateTime<Utc>>,
+        }
+
+        #[derive(Deserialize)]
+        struct OcEvent {
+            identifier: String,
+            title: String,
+            scheduling: Option<OcScheduling>,
+        }
+
+        #[derive(Deserialize)]
+        struct OcScheduling {
+            agent_id: String,
+            start: DateTime<Utc>,
+            end: DateTime<Utc>,
+        }
+
+        let now = Utc::now();
+        let upcoming = chrono::Duration::from_std(config.capture_agents.upcoming)
+            .expect("`capture_agents.upcoming` is validated");
+        let range = format!(
+            "{}/{}",
+            (now - chrono::Duration::hours(MAX_CAPTURE_HOURS))
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            (now + upcoming).to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+
+        let agents: Vec<OcAgent> = get(config, "/api/agents").await?;
+        let events: Vec<OcEvent> = get(config, &format!(
+            "/api/events?filter=start:{range}&withscheduling=true\
+                &sort=start_date:ASC&limit={MAX_EVENTS}",
+        )).await?;
+
+        let mut agents = agents.into_iter()
+            .map(|agent| {
+                let captures = events.iter()
+                    .filter_map(|event| {
+                        let scheduling = event.scheduling.as_ref()?;
+                        (scheduling.agent_id == agent.agent_id && scheduling.end > now)
+                            .then(|| ScheduledCapture {
+                                opencast_id: event.identifier.clone(),
+                                title: event.title.clone(),
+                                start: scheduling.start,
+                                end: scheduling.end,
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                CaptureAgent {
+                    name: agent.agent_id,
+                    state: agent.status,
+                    last_update: agent.update,
+                    captures,
+                }
+            })
+            .collect::<Vec<_>>();
+        agents.sort_by(|a, b| a.name.cmp(&b.name));
+        for agent in &mut agents {
+            agent.captures.sort_by_key(|capture| capture.start);
+        }
+
+        Ok(Self { fetched: now, agents })
+    }
+}
+
+/// Sends a GET request to the sync node, authenticated as the sync user, and
+/// deserializes the JSON response.
+async fn get<T: DeserializeOwned>(config: &Config, path_and_query: &str) -> Result<T> {
+    let node = config.opencast.sync_node();
+    let uri = Uri::builder()
+        .scheme(node.scheme.clone())
+        .authority(node.authority.clone())
+        .path_and_query(path_and_query)
+        .build()
+        .context("failed to build URI")?;
+    let req = Request::get(&uri)
+        .header("Authorization", config.sync.basic_auth_header().expose_secret())
+        .header("Accept", "application/json")
+        .body(Body::empty())
+        .expect("bug: failed to build request");
+
+    let response = tokio::time::timeout(Duration::from_secs(30), HTTP_CLIENT.request(req)).await
+        .with_context(|| format!("request to {uri} timed out"))?
+        .with_context(|| format!("failed to GET {uri}"))?;
+    if !response.status().is_success() {
+        bail!("GET {uri} returned {}", response.status());
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await
+        .with_context(|| format!("failed to download body from {uri}"))?;
+    serde_json::from_slice(&body).with_context(|| format!("invalid response from {uri}"))
+}
@@ -0,0 +1,56 @@
+use juniper::GraphQLObject;
+use postgres_types::ToSql;
+
+use crate::{
+    api::{Context, err::ApiResult},
+    db::types::EventCaption,
+};
+
+
+/// A caption (subtitle) file of an event.
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct Caption {
+    uri: String,
+    /// Language tag, e.g. `en`, if known.
+    pub(super) lang: Option<String>,
+}
+
+impl From<EventCaption> for Caption {
+    fn from(src: EventCaption) -> Self {
+        Self {
+            uri: src.uri,
+            lang: src.lang,
+        }
+    }
+}
+
+/// How many events of a series or realm have captions, e.g. for accessibility
+/// reports.
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct CaptionCoverage {
+    /// Number of events that the current user can read.
+    events: i32,
+    /// Number of these events that have captions.
+    captioned: i32,
+}
+
+impl CaptionCoverage {
+    /// Counts the events matching `condition`.
+    pub(crate) async fn load(
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+        context: &Context,
+    ) -> ApiResult<Self> {
+        let query = format!(
+            "select count(*), count(*) filter (where cardinality(captions) > 0) \
+                from events \
+                where {condition}",
+        );
+        let row = context.db.query_one(&query, args).await?;
+        let cast_i32 = |x: i64| x.try_into().expect("more then 2^31 events");
+        Ok(Self {
+            events: cast_i32(row.get(0)),
+            captioned: cast_i32(row.get(1)),
+        })
+    }
+}
@@ -0,0 +1,261 @@
+use chrono::{DateTime, Datelike, Utc};
+use juniper::GraphQLObject;
+
+use crate::{
+    api::{Context, Id, err::ApiResult},
+    http::canonical,
+};
+use super::Event;
+
+
+/// Metadata to cite an event with, e.g. in academic work.
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct Citation {
+    /// The creators of the event or, if there are none, its contributors.
+    authors: Vec<String>,
+    title: String,
+    /// When the event was created, which is used as publication date.
+    date: DateTime<Utc>,
+    /// The title of this Tobira instance.
+    publisher: String,
+    /// Absolute URL of the canonical page of the event. `null` if
+    /// `general.site_url` is not set.
+    url: Option<String>,
+    /// DOI of the event, from the metadata field configured as `sync.doi`.
+    doi: Option<String>,
+    /// Citation in APA style, as plain text.
+    apa: String,
+    /// Citation as BibTeX entry.
+    bibtex: String,
+}
+
+impl Citation {
+    pub(crate) async fn load(event: &Event, context: &Context) -> ApiResult<Self> {
+        let (doi_namespace, doi_field) = match &context.config.sync.doi {
+            Some(field) => (Some(&field.namespace), Some(&field.field)),
+            None => (None, None),
+        };
+        let query = format!(
+            "select \
+                array(select jsonb_array_elements_text(\
+                    metadata -> 'http://purl.org/dc/terms/' -> 'contributor')), \
+                metadata -> $2::text -> $3::text ->> 0, \
+                {} \
+                from events where id = $1",
+            canonical::realm_path_sql("$1", context.config.http.canonical_realm),
+        );
+        let row = context.db.query_one(&query, &[&event.key, &doi_namespace, &doi_field]).await?;
+
+        let authors = if event.creators.is_empty() {
+            row.get::<_, Vec<String>>(0)
+        } else {
+            event.creators.clone()
+        };
+        let doi = row.get::<_, Option<String>>(1)
+            .map(|doi| doi.trim().trim_start_matches("https://doi.org/").to_owned())
+            .filter(|doi| !doi.is_empty());
+        let url = context.config.general.site_url.as_ref().map(|site_url| format!(
+            "{site_url}{}",
+            canonical::video_path_in(row.get(2), event.key),
+        ));
+
+        let mut out = Self {
+            authors,
+            title: event.title.clone(),
+            date: event.created,
+            publisher: context.config.general.site_title.en().to_owned(),
+            url,
+            doi,
+            apa: String::new(),
+            bibtex: String::new(),
+        };
+        out.apa = out.format_apa();
+        out.bibtex = out.format_bibtex(&Id::event(event.key).to_string());
+        Ok(out)
+    }
+
+    fn format_apa(&self) -> String {
+        let date = format!(
+            "{}, {} {}",
+            self.date.year(),
+            self.date.format("%B"),
+            self.date.day(),
+        );
+        let mut out = match apa_authors(&self.authors) {
+            Some(authors) => format!("{authors} ({date}). {} [Video]. ", self.title),
+            None => format!("{} [Video]. ({date}). ", self.title),
+        };
+        out += &self.publisher;
+        if !out.ends_with('.') {
+            out += ".";
+        }
+        match (&self.doi, &self.url) {
+            (Some(doi), _) => out += &format!(" https://doi.org/{doi}"),
+            (None, Some(url)) => out += &format!(" {url}"),
+            (None, None) => {}
+        }
+        out
+    }
+
+    fn format_bibtex(&self, key: &str) -> String {
+        let mut fields = vec![];
+        if !self.authors.is_empty() {
+            let authors = self.authors.iter()
+                .map(|author| bibtex_escape(author.trim()))
+                .collect::<Vec<_>>();
+            fields.push(("author", format!("{{{}}}", authors.join(" and "))));
+        }
+        fields.push(("title", format!("{{{{{}}}}}", bibtex_escape(&self.title))));
+        fields.push(("year", format!("{{{}}}", self.date.year())));
+        fields.push(("month", self.date.format("%b").to_string().to_lowercase()));
+        fields.push(("howpublished", "{Video}".into()));
+        fields.push(("publisher", format!("{{{}}}", bibtex_escape(&self.publisher))));
+        if let Some(url) = &self.url {
+            fields.push(("url", format!("{{{url}}}")));
+        }
+        if let Some(doi) = &self.doi {
+            fields.push(("doi", format!("{{{}}}", bibtex_escape(doi))));
+        }
+
+        let mut out = format!("@misc{{tobira-{key},\n");
+        for (name, value) in fields {
+            out += &format!("  {name} = {value},\n");
+        }
+        out += "}";
+        out
+    }
+}
+
+/// Formats authors as "Family, G. N., Family, G., & Family, G." or returns
+/// `None` if there are none. Names are expected as "Given Names Family" or
+/// "Family, Given Names". Names consisting of a single word are kept as is.
+fn apa_authors(authors: &[String]) -> Option<String> {
+    let names = authors.iter()
+        .map(|author| author.trim())
+        .filter(|author| !author.is_empty())
+        .map(apa_name)
+        .collect::<Vec<_>>();
+
+    // APA lists at most 20 authors: the first 19, an ellipsis and the last.
+    let out = match names.as_slice() {
+        [] => return None,
+        [name] => name.clone(),
+        [rest @ .., last] if names.len() <= 20 => format!("{}, & {last}", rest.join(", ")),
+        [..] => format!("{}, . . . {}", names[..19].join(", "), names[names.len() - 1]),
+    };
+    Some(out)
+}
+
+fn apa_name(name: &str) -> String {
+    let (family, given) = match name.split_once(',') {
+        Some((family, given)) => (family.trim(), given.trim()),
+        None => match name.rsplit_once(char::is_whitespace) {
+            Some((given, family)) => (family, given.trim()),
+            None => return name.to_owned(),
+        },
+    };
+
+    // "Jean-Luc Marie" -> "J.-L. M."
+    let initials = given.split_whitespace()
+        .map(|part| part.split('-')
+            .filter_map(|s| s.chars().next())
+            .map(|c| format!("{c}."))
+            .collect::<Vec<_>>()
+            .join("-"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if initials.is_empty() {
+        family.to_owned()
+    } else {
+        format!("{family}, {initials}")
+    }
+}
+
+/// Escapes characters with special meaning in BibTeX values.
+fn bibtex_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out += r"\textbackslash{}",
+            '~' => out += r"\textasciitilde{}",
+            '^' => out += r"\textasciicircum{}",
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{Citation, apa_authors};
+
+    fn citation(authors: &[&str], doi: Option<&str>) -> Citation {
+        Citation {
+            authors: authors.iter().map(|s| s.to_string()).collect(),
+            title: "Rust & 100% safety_tips".into(),
+            date: Utc.ymd(2023, 3, 7).and_hms(14, 0, 0),
+            publisher: "Tobira Videoportal".into(),
+            url: Some("https://tobira.example.org/lectures/v/abc".into()),
+            doi: doi.map(Into::into),
+            apa: String::new(),
+            bibtex: String::new(),
+        }
+    }
+
+    #[test]
+    fn authors() {
+        let authors = |names: &[&str]| {
+            apa_authors(&names.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(authors(&[]), None);
+        assert_eq!(authors(&["Peter Lustig"]).unwrap(), "Lustig, P.");
+        assert_eq!(authors(&["Lustig, Peter Paul"]).unwrap(), "Lustig, P. P.");
+        assert_eq!(authors(&["Jean-Luc Picard", "Admin"]).unwrap(), "Picard, J.-L., & Admin");
+        assert_eq!(
+            authors(&["Ada Lovelace", "Alan Turing", "Grace Hopper"]).unwrap(),
+            "Lovelace, A., Turing, A., & Hopper, G.",
+        );
+
+        let many = (1..=22).map(|i| format!("A{i}")).collect::<Vec<_>>();
+        let many = apa_authors(&many).unwrap();
+        assert!(many.starts_with("A1, A2, "));
+        assert!(many.ends_with("A18, A19, . . . A22"));
+    }
+
+    #[test]
+    fn apa() {
+        assert_eq!(
+            citation(&["Peter Lustig"], None).format_apa(),
+            "Lustig, P. (2023, March 7). Rust & 100% safety_tips [Video]. Tobira Videoportal. \
+                https://tobira.example.org/lectures/v/abc",
+        );
+        assert_eq!(
+            citation(&[], Some("10.1234/abc")).format_apa(),
+            "Rust & 100% safety_tips [Video]. (2023, March 7). Tobira Videoportal. \
+                https://doi.org/10.1234/abc",
+        );
+    }
+
+    #[test]
+    fn bibtex() {
+        assert_eq!(
+            citation(&["Peter Lustig", "Lovelace, Ada"], Some("10.1234/abc")).format_bibtex("evX"),
+            "@misc{tobira-evX,\n  \
+                author = {Peter Lustig and Lovelace, Ada},\n  \
+                title = {{Rust \\& 100\\% safety\\_tips}},\n  \
+                year = {2023},\n  \
+                month = mar,\n  \
+                howpublished = {Video},\n  \
+                publisher = {Tobira Videoportal},\n  \
+                url = {https://tobira.example.org/lectures/v/abc},\n  \
+                doi = {10.1234/abc},\n\
+            }",
+        );
+    }
+}
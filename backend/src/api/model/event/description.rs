@@ -0,0 +1,288 @@
+//! Safe HTML version of event descriptions, see `Event.descriptionHtml`.
+//!
+//! Descriptions come from Opencast and are plain text that sometimes contains
+//! URLs and a bit of HTML markup. Everything is escaped except for a few
+//! simple tags without attributes, and URLs are turned into links.
+
+use juniper::{GraphQLEnum, GraphQLObject};
+
+use crate::config::KnownLink;
+
+
+/// Tags that are kept, but only without attributes.
+const ALLOWED_TAGS: &[&str] = &["b", "i", "em", "strong", "u", "code", "br", "p", "ul", "ol", "li"];
+
+/// Tags in which newlines are not turned into `<br>`.
+const BLOCK_TAGS: &[&str] = &["p", "ul", "ol", "li"];
+
+/// A link found in a description.
+#[derive(Debug, PartialEq, Eq, GraphQLObject)]
+pub(crate) struct DescriptionLink {
+    /// The absolute URL, for DOIs the one at `https://doi.org`.
+    url: String,
+    kind: DescriptionLinkKind,
+    /// The DOI, e.g. `10.1000/xyz123`, if `kind` is `DOI`.
+    doi: Option<String>,
+    /// Name of the site, if it is configured in `general.known_links`.
+    site: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+pub(crate) enum DescriptionLinkKind {
+    /// A digital object identifier, written as `doi:10...` or as URL.
+    Doi,
+    /// A URL starting with one of the prefixes in `general.known_links`.
+    Known,
+    Other,
+}
+
+pub(crate) struct Description {
+    pub(crate) html: String,
+    /// Distinct links in the order of their first occurrence.
+    pub(crate) links: Vec<DescriptionLink>,
+}
+
+pub(crate) fn render(text: &str, known_links: &[KnownLink]) -> Description {
+    let mut out = Description { html: String::new(), links: vec![] };
+    let mut open_tags = Vec::new();
+    let has_blocks = BLOCK_TAGS.iter().any(|tag| text.contains(&format!("<{tag}>")));
+
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let text_len = rest.find('<').unwrap_or(rest.len());
+        let (text, after) = rest.split_at(text_len);
+        out.text(text, known_links, !has_blocks);
+        rest = after;
+        if rest.is_empty() {
+            break;
+        }
+
+        match parse_tag(rest) {
+            Some((tag, len)) => {
+                out.tag(tag, &mut open_tags);
+                rest = &rest[len..];
+            }
+            None => {
+                out.html.push_str("&lt;");
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    for tag in open_tags.into_iter().rev() {
+        out.html.push_str(&format!("</{tag}>"));
+    }
+
+    out
+}
+
+enum Tag {
+    Open(&'static str),
+    Close(&'static str),
+}
+
+/// Parses an allowed tag without attributes at the start of `s` and returns it
+/// with its length.
+fn parse_tag(s: &str) -> Option<(Tag, usize)> {
+    let end = s.find('>')?;
+    let inner = s[1..end].trim_end_matches('/').trim_end();
+    let (name, closing) = match inner.strip_prefix('/') {
+        Some(name) => (name, true),
+        None => (inner, false),
+    };
+    let name = ALLOWED_TAGS.iter().find(|tag| tag.eq_ignore_ascii_case(name))?;
+
+    let tag = if closing { Tag::Close(name) } else { Tag::Open(name) };
+    Some((tag, end + 1))
+}
+
+impl Description {
+    fn tag(&mut self, tag: Tag, open_tags: &mut Vec<&'static str>) {
+        match tag {
+            Tag::Open("br") => self.html.push_str("<br>"),
+            Tag::Close("br") => {}
+            Tag::Open(name) => {
+                self.html.push_str(&format!("<{name}>"));
+                open_tags.push(name);
+            }
+            // Closes all tags opened after `name` as well. Closing tags that
+            // were never opened are dropped.
+            Tag::Close(name) => {
+                if let Some(pos) = open_tags.iter().rposition(|tag| *tag == name) {
+                    for tag in open_tags.drain(pos..).rev() {
+                        self.html.push_str(&format!("</{tag}>"));
+                    }
+                }
+            }
+        }
+    }
+
+    fn text(&mut self, mut text: &str, known_links: &[KnownLink], breaks: bool) {
+        while let Some((start, end, link)) = find_link(text, known_links) {
+            self.escaped(&text[..start], breaks);
+            self.html.push_str(&format!(
+                "<a href=\"{}\" rel=\"nofollow noopener\">",
+                escape(&link.url),
+            ));
+            self.escaped(&text[start..end], false);
+            self.html.push_str("</a>");
+            if !self.links.contains(&link) {
+                self.links.push(link);
+            }
+            text = &text[end..];
+        }
+        self.escaped(text, breaks);
+    }
+
+    fn escaped(&mut self, text: &str, breaks: bool) {
+        let escaped = escape(text);
+        if breaks {
+            self.html.push_str(&escaped.replace('\n', "<br>\n"));
+        } else {
+            self.html.push_str(&escaped);
+        }
+    }
+}
+
+/// Finds the first link in `text` and returns its start and end offset.
+fn find_link(text: &str, known_links: &[KnownLink]) -> Option<(usize, usize, DescriptionLink)> {
+    let lower = text.to_ascii_lowercase();
+    let start = ["https://", "http://", "doi:"].iter()
+        .filter_map(|prefix| lower.find(prefix))
+        .min()?;
+
+    // URLs end at whitespace or characters that cannot be part of them, and
+    // trailing punctuation is most likely part of the sentence.
+    let len = text[start..]
+        .find(|c: char| c.is_whitespace() || "<>\"'`".contains(c))
+        .unwrap_or(text.len() - start);
+    let mut raw = &text[start..start + len];
+    loop {
+        let trimmed = raw.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(s) if s.matches('(').count() < s.matches(')').count() + 1 => s,
+            _ => trimmed,
+        };
+        if trimmed.len() == raw.len() {
+            break;
+        }
+        raw = trimmed;
+    }
+    let end = start + raw.len();
+
+    let lower = raw.to_ascii_lowercase();
+    let doi = lower.strip_prefix("doi:")
+        .or_else(|| ["https://doi.org/", "https://dx.doi.org/", "http://doi.org/", "http://dx.doi.org/"]
+            .iter()
+            .find_map(|prefix| lower.strip_prefix(prefix)))
+        .map(|doi| &raw[raw.len() - doi.len()..])
+        .filter(|doi| doi.starts_with("10.") && doi.contains('/'));
+
+    let link = if let Some(doi) = doi {
+        DescriptionLink {
+            url: format!("https://doi.org/{doi}"),
+            kind: DescriptionLinkKind::Doi,
+            doi: Some(doi.to_owned()),
+            site: None,
+        }
+    } else if lower.starts_with("doi:") || !raw.contains("://") || raw.ends_with("://") {
+        // Not a valid DOI or URL: skip it and continue after it.
+        let (next_start, next_end, link) = find_link(&text[end..], known_links)?;
+        return Some((end + next_start, end + next_end, link));
+    } else {
+        let known = known_links.iter().find(|known| raw.starts_with(&known.prefix));
+        DescriptionLink {
+            url: raw.to_owned(),
+            kind: if known.is_some() { DescriptionLinkKind::Known } else { DescriptionLinkKind::Other },
+            doi: None,
+            site: known.map(|known| known.name.clone()),
+        }
+    };
+
+    Some((start, end, link))
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(text: &str) -> String {
+        render(text, &[]).html
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(html("a < b && <b>c</b>"), "a &lt; b &amp;&amp; <b>c</b>");
+        assert_eq!(
+            html("<script>alert(1)</script><B onclick=\"x\">y</B>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;&lt;B onclick=&quot;x&quot;&gt;y",
+        );
+        assert_eq!(html("<i>x<b>y</i>z</b><br/>"), "<i>x<b>y</b></i>z<br>");
+        assert_eq!(html("<em>open"), "<em>open</em>");
+    }
+
+    #[test]
+    fn line_breaks() {
+        assert_eq!(html("  a\nb\n"), "a<br>\nb");
+        assert_eq!(html("<ul>\n<li>a</li>\n</ul>"), "<ul>\n<li>a</li>\n</ul>");
+    }
+
+    #[test]
+    fn links() {
+        let known = [KnownLink {
+            prefix: "https://library.example.org/".into(),
+            name: "Library".into(),
+        }];
+        let out = render(
+            "Slides (https://example.com/a_(b)): see https://library.example.org/x?a=1&b=2. \
+                Paper: doi:10.1000/xyz123, https://doi.org/10.1000/xyz123 or http:// nothing",
+            &known,
+        );
+        assert_eq!(out.html, "Slides (<a href=\"https://example.com/a_(b)\" rel=\"nofollow noopener\">\
+            https://example.com/a_(b)</a>): see <a href=\"https://library.example.org/x?a=1&amp;b=2\" \
+            rel=\"nofollow noopener\">https://library.example.org/x?a=1&amp;b=2</a>. Paper: \
+            <a href=\"https://doi.org/10.1000/xyz123\" rel=\"nofollow noopener\">doi:10.1000/xyz123</a>, \
+            <a href=\"https://doi.org/10.1000/xyz123\" rel=\"nofollow noopener\">https://doi.org/10.1000/xyz123</a> \
+            or http:// nothing");
+        assert_eq!(out.links, vec![
+            DescriptionLink {
+                url: "https://example.com/a_(b)".into(),
+                kind: DescriptionLinkKind::Other,
+                doi: None,
+                site: None,
+            },
+            DescriptionLink {
+                url: "https://library.example.org/x?a=1&b=2".into(),
+                kind: DescriptionLinkKind::Known,
+                doi: None,
+                site: Some("Library".into()),
+            },
+            DescriptionLink {
+                url: "https://doi.org/10.1000/xyz123".into(),
+                kind: DescriptionLinkKind::Doi,
+                doi: Some("10.1000/xyz123".into()),
+                site: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn no_javascript_links() {
+        assert_eq!(html("javascript://%0aalert(1)"), "javascript://%0aalert(1)");
+    }
+}
@@ -0,0 +1,39 @@
+use juniper::GraphQLObject;
+
+use crate::{
+    api::{Context, err::ApiResult},
+    prelude::*,
+};
+
+
+/// A location at which events were recorded, see `Event.location`.
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct Location {
+    location: String,
+    /// Name of the room as configured in `general.rooms`, if any.
+    room: Option<String>,
+    /// Number of events recorded at this location that the current user can
+    /// read.
+    num_events: i32,
+}
+
+impl Location {
+    /// Loads all locations of events the current user can read, sorted.
+    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
+        let query = "select location, count(*) from events \
+            where location is not null and read_roles && $1 \
+            group by location \
+            order by location";
+        context.db
+            .query_mapped(query, dbargs![&context.user.roles()], |row| {
+                let location = row.get::<_, String>(0);
+                Self {
+                    room: context.config.general.room(&location).map(Into::into),
+                    location,
+                    num_events: row.get::<_, i64>(1).try_into().expect("more then 2^31 events"),
+                }
+            })
+            .await?
+            .pipe(Ok)
+    }
+}
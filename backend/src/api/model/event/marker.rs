@@ -0,0 +1,237 @@
+use chrono::{DateTime, Utc};
+use juniper::{GraphQLInputObject, GraphQLObject, graphql_object};
+use tokio_postgres::Row;
+
+use crate::{
+    api::{
+        Context, Id, Node, NodeValue,
+        err::{ApiResult, invalid_input, not_authorized},
+    },
+    auth::HasRoles,
+    db::{types::Key, util::define_columns},
+    prelude::*,
+};
+
+
+/// A marker at a point in time of an event, e.g. a poll or question that an
+/// interactive overlay shows at that point. Tobira only stores markers, it
+/// does not interpret them.
+pub(crate) struct EventMarker {
+    key: Key,
+    event: Key,
+    kind: String,
+    time: i32,
+    payload: String,
+    created: DateTime<Utc>,
+}
+
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        event: Key = "event",
+        kind: String = "kind",
+        time: i32 = "time",
+        payload: serde_json::Value = "payload",
+        created: DateTime<Utc> = "created",
+    }
+}
+
+#[juniper::graphql_interface]
+impl Node for EventMarker {
+    fn id(&self) -> Id {
+        Id::event_marker(self.key)
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl EventMarker {
+    fn id(&self) -> Id {
+        Node::id(self)
+    }
+
+    /// ID of the event this marker belongs to.
+    fn event(&self) -> Id {
+        Id::event(self.event)
+    }
+
+    /// What kind of marker this is, e.g. `poll`. Defined by the tool that
+    /// created the marker.
+    fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Position in the event in ms.
+    fn time(&self) -> i32 {
+        self.time
+    }
+
+    /// Arbitrary JSON data, defined by the tool that created the marker.
+    fn payload(&self) -> &str {
+        &self.payload
+    }
+
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+}
+
+impl EventMarker {
+    /// Maximum number of markers per event.
+    const MAX_PER_EVENT: i64 = 500;
+
+    /// Maximum length of `kind` in bytes.
+    const MAX_KIND_LEN: usize = 50;
+
+    /// Maximum length of `payload` in bytes.
+    const MAX_PAYLOAD_LEN: usize = 16 * 1024;
+
+    fn from_row(row: Row) -> Self {
+        Self {
+            key: cols::key(&row),
+            event: cols::event(&row),
+            kind: cols::kind(&row),
+            time: cols::time(&row),
+            payload: cols::payload(&row).to_string(),
+            created: cols::created(&row),
+        }
+    }
+
+    /// Returns the marker with the given ID if the current user can read its
+    /// event.
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        let key = match id.key_for(Id::EVENT_MARKER_KIND) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+
+        let query = format!(
+            "select {} from event_markers \
+                where id = $1 \
+                and (select read_roles from events where id = event) && $2",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_opt(&query, &[&key, &context.user.roles()])
+            .await?
+            .map(Self::from_row)
+            .pipe(Ok)
+    }
+
+    /// Returns all markers of the given event, ordered by time. Does not check
+    /// whether the current user can read the event!
+    pub(crate) async fn load_for_event(event: Key, context: &Context) -> ApiResult<Vec<Self>> {
+        let query = format!(
+            "select {} from event_markers where event = $1 order by time, created",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_mapped(&query, dbargs![&event], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Adds a marker to an event the current user has write access to.
+    pub(crate) async fn add(
+        event: Id,
+        marker: NewEventMarker,
+        context: &Context,
+    ) -> ApiResult<Self> {
+        let event_key = event.key_for(Id::EVENT_KIND)
+            .ok_or_else(|| invalid_input!("`event` does not refer to an event"))?;
+
+        if marker.kind.is_empty()
+            || marker.kind.len() > Self::MAX_KIND_LEN
+            || !marker.kind.bytes().all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-'))
+        {
+            return Err(invalid_input!(
+                "`kind` has to consist of 1 to {} lowercase ASCII letters, digits or dashes",
+                Self::MAX_KIND_LEN,
+            ));
+        }
+        if marker.payload.len() > Self::MAX_PAYLOAD_LEN {
+            return Err(invalid_input!(
+                "`payload` must not be longer than {} bytes",
+                Self::MAX_PAYLOAD_LEN,
+            ));
+        }
+        let payload: serde_json::Value = serde_json::from_str(&marker.payload)
+            .map_err(|e| invalid_input!("`payload` is not valid JSON: {}", e))?;
+
+        let row = context.db
+            .query_opt(
+                "select duration, write_roles && $2, \
+                    (select count(*) from event_markers where event = $1) \
+                    from events where id = $1 and read_roles && $2",
+                &[&event_key, &context.user.roles()],
+            )
+            .await?
+            .ok_or_else(|| invalid_input!("`event` does not refer to an existing event"))?;
+        let duration: i32 = row.get(0);
+        let can_write: bool = row.get(1);
+        let count: i64 = row.get(2);
+        if !can_write {
+            return Err(not_authorized!("you cannot add markers to this event"));
+        }
+        if count >= Self::MAX_PER_EVENT {
+            return Err(invalid_input!(
+                "an event cannot have more than {} markers",
+                Self::MAX_PER_EVENT,
+            ));
+        }
+        if marker.time < 0 || marker.time > duration {
+            return Err(invalid_input!("`time` has to be between 0 and the duration of the event"));
+        }
+
+        let query = format!(
+            "insert into event_markers (event, kind, time, payload) \
+                values ($1, $2, $3, $4) \
+                returning {}",
+            cols::COL_NAMES,
+        );
+        let row = context.db
+            .query_one(&query, &[&event_key, &marker.kind, &marker.time, &payload])
+            .await?;
+        debug!("Added '{}' marker at {}ms to event {:?}", marker.kind, marker.time, event_key);
+
+        Ok(Self::from_row(row))
+    }
+
+    /// Removes a marker of an event the current user has write access to.
+    pub(crate) async fn remove(id: Id, context: &Context) -> ApiResult<RemovedEventMarker> {
+        let key = id.key_for(Id::EVENT_MARKER_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to an event marker"))?;
+
+        let affected_rows = context.db
+            .execute(
+                "delete from event_markers \
+                    where id = $1 \
+                    and (select write_roles from events where id = event) && $2",
+                &[&key, &context.user.roles()],
+            )
+            .await?;
+        if affected_rows != 1 {
+            return Err(invalid_input!(
+                "`id` does not refer to a marker of an event you have write access to",
+            ));
+        }
+        debug!("Removed event marker {:?}", key);
+
+        Ok(RemovedEventMarker { id })
+    }
+}
+
+#[derive(GraphQLInputObject)]
+pub(crate) struct NewEventMarker {
+    /// Lowercase ASCII letters, digits and dashes, e.g. `poll`.
+    kind: String,
+    /// Position in the event in ms.
+    time: i32,
+    /// Arbitrary JSON data, at most 16 KiB.
+    payload: String,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RemovedEventMarker {
+    id: Id,
+}
@@ -0,0 +1,1076 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio_postgres::Row;
+use juniper::{GraphQLEnum, GraphQLObject, graphql_object};
+use postgres_types::{FromSql, ToSql};
+
+use crate::{
+    api::{
+        Context, Cursor, Id, Node, NodeValue,
+        err::{self, ApiResult, invalid_input},
+        model::{series::Series, realm::Realm},
+    },
+    db::{types::{EventCaption, EventTrack, Key}, util::define_columns},
+    http::{placeholder, playback, thumbnail},
+    prelude::*,
+    util::lazy_format,
+};
+
+
+mod captions;
+mod citation;
+mod description;
+mod location;
+mod marker;
+mod mutations;
+mod passphrase;
+mod stats;
+
+pub(crate) use captions::{Caption, CaptionCoverage};
+pub(crate) use citation::Citation;
+pub(crate) use description::DescriptionLink;
+pub(crate) use location::Location;
+pub(crate) use marker::{EventMarker, NewEventMarker, RemovedEventMarker};
+pub(crate) use mutations::{ExternalEventsConfig, NewExternalEvent, RemovedEvent};
+pub(crate) use stats::EventStats;
+
+
+/// SQL condition whether the event `event` is shown by a video or series
+/// block of the realm `realm` and any of `roles` is a member role of that
+/// realm or its ancestors (all SQL expressions). Creator blocks do not grant
+/// member access and retracted events are never accessible this way.
+pub(crate) fn member_access_sql(event: &str, realm: &str, roles: &str) -> String {
+    format!(
+        "exists(select 1 from events where id = {event} and not retracted) \
+            and exists(select 1 from event_host_realms({event}) \
+                where realm = {realm} and directness < 2) \
+            and exists(select 1 from ancestors_of_realm({realm}) where member_roles && {roles})",
+    )
+}
+
+#[derive(Debug)]
+pub(crate) struct Event {
+    key: Key,
+    series: Option<Key>,
+    opencast_id: Option<String>,
+    source: EventSource,
+
+    title: String,
+    description: Option<String>,
+    duration: i32,
+    /// Start of the played part in ms, 0 for untrimmed events.
+    trim_start: i32,
+    duration_trimmed: i32,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+    creators: Vec<String>,
+
+    thumbnail: Option<String>,
+    thumbnail_color: Option<String>,
+    tracks: Vec<Track>,
+    captions: Vec<Caption>,
+    location: Option<String>,
+    has_passphrase: bool,
+    can_write: bool,
+    read_roles: Vec<String>,
+}
+
+/// Where an event originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "event_source")]
+pub(crate) enum EventSource {
+    /// Synced from the connected Opencast instance.
+    #[postgres(name = "opencast")]
+    Opencast,
+    /// Registered manually in Tobira with a URL to a video on another server.
+    #[postgres(name = "external")]
+    External,
+}
+
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct Track {
+    uri: String,
+    flavor: String,
+    mimetype: Option<String>,
+    // TODO: this should be `[i32; 2]` but the relevant patch is not released
+    // yet: https://github.com/graphql-rust/juniper/pull/966
+    resolution: Option<Vec<i32>>,
+    /// Whether this track only contains audio, as indicated by its mimetype.
+    /// `null` if that's unknown, e.g. for HLS or DASH manifests.
+    is_audio: Option<bool>,
+}
+
+/// A part of an event with how often it was played.
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct WatchSegment {
+    /// Start of the segment in ms of media time.
+    start: f64,
+    /// End of the segment in ms of media time.
+    end: f64,
+    /// Number of times this segment was played, relative to the most played
+    /// segment of the event. Between 0 and 1.
+    relative_views: f64,
+}
+
+impl WatchSegment {
+    /// Turns the view counts per bucket into segments covering the played
+    /// part of the event, which starts at `start` and is `duration` long.
+    /// Returns an empty list if there are no views.
+    fn from_views(views: &[(i32, i64)], start: i32, duration: i32) -> Vec<Self> {
+        let max = views.iter().map(|&(_, views)| views).max().unwrap_or(0);
+        if max == 0 {
+            return vec![];
+        }
+
+        let bucket_len = f64::from(duration) / f64::from(playback::BUCKETS);
+        (0..playback::BUCKETS)
+            .map(|bucket| {
+                let views = views.iter()
+                    .find(|&&(b, _)| b == bucket)
+                    .map_or(0, |&(_, views)| views);
+                Self {
+                    start: f64::from(start) + f64::from(bucket) * bucket_len,
+                    end: f64::from(start) + f64::from(bucket + 1) * bucket_len,
+                    relative_views: views as f64 / max as f64,
+                }
+            })
+            .collect()
+    }
+}
+
+#[juniper::graphql_interface]
+impl Node for Event {
+    fn id(&self) -> Id {
+        Id::event(self.key)
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl Event {
+    fn id(&self) -> Id {
+        Node::id(self)
+    }
+    /// The Opencast ID of this event. `null` for external events.
+    fn opencast_id(&self) -> Option<&str> {
+        self.opencast_id.as_deref()
+    }
+    /// Whether this event was synced from Opencast or was registered as an
+    /// external video.
+    fn source(&self) -> EventSource {
+        self.source
+    }
+    fn title(&self) -> &str {
+        &self.title
+    }
+    /// `null` if the event is locked (see `hasPassphrase`).
+    fn description(&self, access_token: Option<String>, context: &Context) -> Option<&str> {
+        if self.is_locked(access_token.as_deref(), context) {
+            return None;
+        }
+        self.description.as_deref()
+    }
+    /// `description` as HTML that can be inserted into a page as is: all
+    /// markup is escaped except for a few simple tags without attributes
+    /// (like `<b>` or `<ul>`), URLs and DOIs are turned into links and, if
+    /// there are no paragraphs or lists, line breaks into `<br>`. `null` if
+    /// there is no description or the event is locked.
+    fn description_html(&self, access_token: Option<String>, context: &Context) -> Option<String> {
+        if self.is_locked(access_token.as_deref(), context) {
+            return None;
+        }
+        let description = self.description.as_deref()?;
+        Some(description::render(description, context.config.general.known_links()).html)
+    }
+    /// The links in `descriptionHtml`, each only once. Empty if the event is
+    /// locked.
+    fn description_links(
+        &self,
+        access_token: Option<String>,
+        context: &Context,
+    ) -> Vec<DescriptionLink> {
+        match &self.description {
+            Some(description) if !self.is_locked(access_token.as_deref(), context) => {
+                description::render(description, context.config.general.known_links()).links
+            }
+            _ => vec![],
+        }
+    }
+    /// Duration in ms.
+    fn duration(&self) -> i32 {
+        self.duration
+    }
+    /// Duration in ms of the part that is actually played, i.e. with the
+    /// trimming of non-destructive editing in Opencast applied. Equal to
+    /// `duration` for untrimmed events.
+    fn duration_trimmed(&self) -> i32 {
+        self.duration_trimmed
+    }
+    /// URL of the thumbnail. If the event has none, a generated placeholder
+    /// is returned. If `http.thumbnails` is configured, this points to Tobira,
+    /// which serves resized versions when a `width` parameter is appended.
+    /// Tobira does not serve thumbnails of events with a passphrase, and
+    /// locked events (see `hasPassphrase`) always get the placeholder.
+    fn thumbnail(&self, access_token: Option<String>, context: &Context) -> String {
+        if self.is_locked(access_token.as_deref(), context) {
+            return placeholder::thumbnail_url(self.key);
+        }
+        match &self.thumbnail {
+            Some(_) if context.config.http.thumbnails.enabled() && !self.has_passphrase => {
+                thumbnail::url(self.key)
+            }
+            Some(url) => url.clone(),
+            None => placeholder::thumbnail_url(self.key),
+        }
+    }
+    /// Average color of the thumbnail as `#rrggbb`, to show until the
+    /// thumbnail is loaded. Only known if `http.thumbnails` is configured and
+    /// the thumbnail was requested before.
+    fn thumbnail_color(&self) -> Option<&str> {
+        self.thumbnail_color.as_deref()
+    }
+    /// Empty if the event is locked (see `hasPassphrase`) or geoblocked.
+    fn tracks(&self, access_token: Option<String>, context: &Context) -> &[Track] {
+        let tracks: &[Track] = if self.is_locked(access_token.as_deref(), context)
+            || self.geoblocked(context)
+        {
+            &[]
+        } else {
+            &self.tracks
+        };
+        tracks
+    }
+    /// Empty if the event is locked (see `hasPassphrase`) or geoblocked.
+    fn captions(&self, access_token: Option<String>, context: &Context) -> &[Caption] {
+        let captions: &[Caption] = if self.is_locked(access_token.as_deref(), context)
+            || self.geoblocked(context)
+        {
+            &[]
+        } else {
+            &self.captions
+        };
+        captions
+    }
+    fn has_captions(&self) -> bool {
+        !self.captions.is_empty()
+    }
+    /// Distinct languages of `captions`, leaving out captions without a
+    /// known language.
+    fn caption_languages(&self) -> Vec<&str> {
+        let mut languages = Vec::new();
+        for lang in self.captions.iter().filter_map(|caption| caption.lang.as_deref()) {
+            if !languages.contains(&lang) {
+                languages.push(lang);
+            }
+        }
+        languages
+    }
+    /// URL of an audio-only rendition of this event, e.g. for podcasts.
+    /// `null` if there is none or the event is locked (see `hasPassphrase`)
+    /// or geoblocked.
+    fn audio_download(&self, access_token: Option<String>, context: &Context) -> Option<&str> {
+        if self.is_locked(access_token.as_deref(), context) || self.geoblocked(context) {
+            return None;
+        }
+        self.tracks.iter().find(|track| track.is_audio == Some(true)).map(|track| track.uri.as_str())
+    }
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+    /// Where the event was recorded, usually the ID of the capture agent.
+    fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+    /// Name of the room the event was recorded in, as configured for its
+    /// `location` in `general.rooms`.
+    fn room(&self, context: &Context) -> Option<String> {
+        context.config.general.room(self.location.as_deref()?).map(Into::into)
+    }
+    fn updated(&self) -> DateTime<Utc> {
+        self.updated
+    }
+    fn creators(&self) -> &Vec<String> {
+        &self.creators
+    }
+
+    /// Whether the event is protected by a passphrase. Unless the current
+    /// user has write access, it is then locked: its description, thumbnail,
+    /// tracks and captions are hidden. To unlock it, pass the token returned
+    /// by the mutation `unlockEvent` as `accessToken` to these fields.
+    fn has_passphrase(&self) -> bool {
+        self.has_passphrase
+    }
+
+    /// Whether the current user has write access to this event.
+    fn can_write(&self) -> bool {
+        self.can_write
+    }
+
+    /// Whether the event cannot be played from the country of the current
+    /// client (see `geoblocking.rules`). Its tracks and captions are hidden
+    /// then.
+    fn is_geoblocked(&self, context: &Context) -> bool {
+        self.geoblocked(context)
+    }
+
+    /// Metadata and formatted citations to cite this event with.
+    async fn citation(&self, context: &Context) -> ApiResult<Citation> {
+        Citation::load(self, context).await
+    }
+
+    async fn series(&self, context: &Context) -> ApiResult<Option<Series>> {
+        if let Some(series) = self.series {
+            Series::load_by_id(Id::series(series), context).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns a list of realms where this event is referenced (via some kind of block).
+    /// The first one is the canonical location of the event, as configured by
+    /// `http.canonical_realm`.
+    async fn host_realms(&self, context: &Context) -> ApiResult<Vec<Realm>> {
+        Self::load_host_realms(self.key, context).await
+    }
+
+    /// Markers at points in time of this event, ordered by time.
+    async fn markers(&self, context: &Context) -> ApiResult<Vec<EventMarker>> {
+        EventMarker::load_for_event(self.key, context).await
+    }
+
+    /// How often each part of this event was played (see
+    /// `http.playback_stats`). Empty if it was never played. `null` if
+    /// playback statistics are disabled or the current user has no write
+    /// access to this event.
+    async fn watch_segments(&self, context: &Context) -> ApiResult<Option<Vec<WatchSegment>>> {
+        if !self.can_write || !context.config.http.playback_stats {
+            return Ok(None);
+        }
+
+        let views = context.db
+            .query_mapped(
+                "select bucket, views from playback_stats where event = $1",
+                dbargs![&self.key],
+                |row| (row.get::<_, i32>(0), row.get::<_, i64>(1)),
+            )
+            .await?;
+
+        Ok(Some(WatchSegment::from_views(&views, self.trim_start, self.duration_trimmed)))
+    }
+}
+
+impl Event {
+    /// Returns all realms where the event with the given key is referenced
+    /// (via some kind of block), each only once and in canonical order (see
+    /// `Realm::load_hosts`).
+    pub(crate) async fn load_host_realms(key: Key, context: &Context) -> ApiResult<Vec<Realm>> {
+        Realm::load_hosts("event_host_realms($1)", key, context).await
+    }
+
+    /// Whether the event cannot be played from the client's country. Users
+    /// with write access are never blocked.
+    fn geoblocked(&self, context: &Context) -> bool {
+        !self.can_write && !context.config.geoblocking.allows(
+            self.opencast_id.as_deref(),
+            &self.read_roles,
+            context.client_country,
+        )
+    }
+
+    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
+        context.db(context.require_moderator()?)
+            .query_mapped(
+                &format!(
+                    "select {} from events \
+                        where read_roles && $1 \
+                        order by title collate {}",
+                    cols::COL_NAMES,
+                    context.config.general.collation(),
+                ),
+                dbargs![&context.user.roles()],
+                Self::from_row,
+            )
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Returns all events recorded at `location` that the current user can
+    /// read.
+    pub(crate) async fn load_at_location(
+        location: &str,
+        order: EventSortOrder,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let query = format!(
+            "select {} from events where location = $2 and read_roles && $1 {}",
+            cols::COL_NAMES,
+            order.to_sql(context),
+        );
+        context.db
+            .query_mapped(&query, dbargs![&context.user.roles(), &location], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        Self::load_for_block(id, false, context).await
+    }
+
+    /// Like `load_by_id`, but members of the realm at `realm_path` (see
+    /// `Realm::member_roles`) can also see the event if a video or series
+    /// block of that realm shows it. That's the same access members get via
+    /// the blocks themselves.
+    pub(crate) async fn load_in_realm(
+        id: Id,
+        realm_path: String,
+        context: &Context,
+    ) -> ApiResult<Option<Self>> {
+        let (key, realm) = match id.key_for(Id::EVENT_KIND) {
+            None => return Ok(None),
+            Some(key) => (key, Realm::load_by_path(realm_path, context).await?),
+        };
+
+        let member_access = match realm {
+            None => false,
+            Some(realm) => context.db
+                .query_one(
+                    &format!("select {}", member_access_sql("$1", "$2", "$3")),
+                    &[&key, &realm.key, &context.user.roles()],
+                )
+                .await?
+                .get::<_, bool>(0),
+        };
+
+        Self::load_for_block(id, member_access, context).await
+    }
+
+    /// Like `load_by_id`, but if `member_access` is `true`, the event is
+    /// returned regardless of its read roles. See `Realm::member_roles`.
+    pub(crate) async fn load_for_block(
+        id: Id,
+        member_access: bool,
+        context: &Context,
+    ) -> ApiResult<Option<Self>> {
+        let key = match id.key_for(Id::EVENT_KIND) {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+
+        let query = format!(
+            "select {}, ($3 and not retracted) or $1 && read_roles as can_read \
+                from events where id = $2",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_opt(&query, &[&context.user.roles(), &key, &member_access])
+            .await?
+            .map(|row| {
+                if row.get::<_, bool>("can_read") {
+                    Ok(Self::from_row(row))
+                } else {
+                    Err(err::not_authorized!(
+                        key = "view.event",
+                        "you cannot view this event",
+                    ))
+                }
+            })
+            .transpose()
+    }
+
+    /// Returns all events of the series that the current user can read or,
+    /// if `member_access` is `true`, all events of the series.
+    pub(crate) async fn load_for_series(
+        series_key: Key,
+        order: EventSortOrder,
+        member_access: bool,
+        filter: Option<String>,
+        captioned: Option<bool>,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let filter = Self::metadata_filter(filter, context)?;
+        let query = format!(
+            "select {} from events \
+                where series = $2 and (($3 and not retracted) or read_roles && $1) \
+                and ($4::jsonb is null or metadata @> $4) \
+                and ($5::boolean is null or (cardinality(captions) > 0) = $5) {}",
+            cols::COL_NAMES,
+            order.to_sql(context),
+        );
+        let args = dbargs![
+            &context.user.roles(),
+            &series_key,
+            &member_access,
+            &filter,
+            &captioned,
+        ];
+        context.db
+            .query_mapped(&query, args, Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Returns the first `limit` events of each series, video and creator
+    /// block of the realm, ordered like in the block, in one query. Access is
+    /// checked like in `load_for_series`, `load_for_block` and
+    /// `load_for_creator`, but unreadable events of video blocks are left out
+    /// instead of resulting in an error. Each event comes with the key of its
+    /// block and the number of events of the block without the limit.
+    pub(crate) async fn load_for_blocks(
+        realm_key: Key,
+        member_access: bool,
+        limit: i64,
+        context: &Context,
+    ) -> ApiResult<Vec<(Key, i64, Self)>> {
+        let lateral = |filter: &str, block_type: &str| format!(
+            "select events.*, blocks.id from blocks cross join lateral ( \
+                select {}, count(*) over () from events \
+                where {filter} \
+                order by case when blocks.videolist_order = 'old_to_new' then created end, \
+                    created desc \
+                limit $3 \
+            ) events \
+            where blocks.realm_id = $2 and blocks.type = '{block_type}'",
+            cols::COL_NAMES,
+        );
+        let query = [
+            lateral("series = blocks.series_id and (($4 and not retracted) or read_roles && $1)", "series"),
+            lateral("id = blocks.video_id and (($4 and not retracted) or read_roles && $1)", "video"),
+            lateral("creators @> array[blocks.creator] and read_roles && $1", "creator"),
+        ].join(" union all ");
+
+        let num_cols = cols::COLUMNS.len();
+        let args = dbargs![&context.user.roles(), &realm_key, &limit, &member_access];
+        context.db
+            .query_mapped(&query, args, |row| {
+                (row.get::<_, Key>(num_cols + 1), row.get::<_, i64>(num_cols), Self::from_row(row))
+            })
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Returns all events that have `creator` as one of their creators and
+    /// that the current user can read.
+    pub(crate) async fn load_for_creator(
+        creator: &str,
+        order: EventSortOrder,
+        filter: Option<String>,
+        captioned: Option<bool>,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let filter = Self::metadata_filter(filter, context)?;
+        let query = format!(
+            "select {} from events \
+                where creators @> array[$2] and read_roles && $1 \
+                and ($3::jsonb is null or metadata @> $3) \
+                and ($4::boolean is null or (cardinality(captions) > 0) = $4) {}",
+            cols::COL_NAMES,
+            order.to_sql(context),
+        );
+        let args = dbargs![&context.user.roles(), &creator, &filter, &captioned];
+        context.db
+            .query_mapped(&query, args, Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Returns the value to compare `metadata` with (using `@>`) to only get
+    /// events with `value` in the field configured as `general.event_filter`.
+    fn metadata_filter(
+        value: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Option<serde_json::Value>> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let Some(config) = &context.config.general.event_filter else {
+            return Err(invalid_input!("cannot filter events: `general.event_filter` is not set"));
+        };
+        Ok(Some(serde_json::json!({ config.namespace.as_str(): { config.field.as_str(): [value] } })))
+    }
+
+    /// Returns all values of the field configured as `general.event_filter`
+    /// of the events matching `condition`, sorted. Parameters `$1` and `$2`
+    /// are taken by the namespace and field, so `condition` has to start with
+    /// `$3`. Returns an empty list if no field is configured.
+    pub(crate) async fn load_filter_values(
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+        context: &Context,
+    ) -> ApiResult<Vec<String>> {
+        let Some(config) = &context.config.general.event_filter else {
+            return Ok(vec![]);
+        };
+        let query = format!(
+            "select distinct value \
+                from events, jsonb_array_elements_text(metadata -> $1 -> $2) value \
+                where {condition} \
+                order by value",
+        );
+        let mut all_args = dbargs![&config.namespace, &config.field].to_vec();
+        all_args.extend_from_slice(args);
+        context.db
+            .query_mapped(&query, all_args, |row| row.get::<_, String>(0))
+            .await?
+            .pipe(Ok)
+    }
+
+    pub(crate) async fn load_writable_for_user(
+        context: &Context,
+        order: EventSortOrder,
+        first: Option<i32>,
+        after: Option<Cursor>,
+        last: Option<i32>,
+        before: Option<Cursor>,
+    ) -> ApiResult<EventConnection> {
+        const MAX_COUNT: i32 = 100;
+
+        // Argument validation
+        let after = after.map(|c| c.deserialize::<EventCursor>()).transpose()?;
+        let before = before.map(|c| c.deserialize::<EventCursor>()).transpose()?;
+        if first.map_or(false, |first| first <= 0) {
+            return Err(invalid_input!("argument 'first' has to be > 0, but is {:?}", first));
+        }
+        if last.map_or(false, |last| last <= 0) {
+            return Err(invalid_input!("argument 'last' has to be > 0, but is {:?}", last));
+        }
+
+        // Make sure only one of `first` and `last` is set and figure out the
+        // limit and SQL sort order. If `last` is set, we reverse the order in
+        // the SQL query in order to use `limit` effectively. We reverse it
+        // again in Rust further below.
+        let (limit, sql_sort_order) = match (first, last) {
+            (Some(first), None) => (first, order.direction),
+            (None, Some(last)) => (last, order.direction.reversed()),
+            _ => return Err(invalid_input!("exactly one of 'first' and 'last' must be given")),
+        };
+        let limit = std::cmp::min(limit, MAX_COUNT);
+
+
+        // Assemble argument list and the "where" part of the query. This
+        // depends on `after` and `before`.
+        let arg_user_roles = &context.user.roles() as &(dyn ToSql + Sync);
+        let mut args = vec![arg_user_roles];
+
+        let col = order.column.to_sql(context);
+        let op_after = if order.direction.is_ascending() { '>' } else { '<' };
+        let op_before = if order.direction.is_ascending() { '<' } else { '>' };
+        let filter = match (&after, &before) {
+            (None, None) => String::new(),
+            (Some(after), None) => {
+                args.extend_from_slice(&[after.to_sql_arg(&order)?, &after.key]);
+                format!("where ({}, id) {} ($2, $3)", col, op_after)
+            }
+            (None, Some(before)) => {
+                args.extend_from_slice(&[before.to_sql_arg(&order)?, &before.key]);
+                format!("where ({}, id) {} ($2, $3)", col, op_before)
+            }
+            (Some(after), Some(before)) => {
+                args.extend_from_slice(&[
+                    after.to_sql_arg(&order)?,
+                    &after.key,
+                    before.to_sql_arg(&order)?,
+                    &before.key,
+                ]);
+                format!(
+                    "where ({}, id) {} ($2, $3) and ({}, id) {} ($4, $5)",
+                    col, op_after, col, op_before,
+                )
+            },
+        };
+
+        // Assemble full query. This query is a bit involved but allows us to
+        // retrieve the total count, the absolute offsets of our window and all
+        // the event data in one go. The "over(...)" things are window
+        // functions.
+        let query = format!(
+            "select {cols}, row_num, total_count \
+                from (\
+                    select {cols}, \
+                        write_roles, \
+                        row_number() over(order by ({sort_col}, id) {sort_order}) as row_num, \
+                        count(*) over() as total_count \
+                    from events \
+                    where write_roles && $1 and read_roles && $1 \
+                    order by ({sort_col}, id) {sort_order} \
+                ) as tmp \
+                {filter} \
+                limit {limit}",
+            cols = cols::COL_NAMES,
+            sort_col = col,
+            sort_order = sql_sort_order.to_sql(),
+            limit = limit,
+            filter = filter,
+        );
+
+        // `first_num` and `last_num` are 1-based!
+        let mut total_count = None;
+        let mut first_num = None;
+        let mut last_num = None;
+
+        // Execute query
+        let mut events = context.db.query_mapped(&query, args, |row: Row| {
+            // Retrieve total count once
+            if total_count.is_none() {
+                total_count = Some(row.get::<_, i64>("total_count"));
+            }
+
+            // Handle row numbers
+            let row_num = row.get::<_, i64>("row_num");
+            last_num = Some(row_num);
+            if first_num.is_none() {
+                first_num = Some(row_num);
+            }
+
+            // Retrieve actual event data
+            Self::from_row(row)
+        }).await?;
+
+        // If total count is `None`, there are no events. We really do want to
+        // know the total count, so we do another query.
+        let total_count = match total_count {
+            Some(c) => c,
+            None => {
+                let query = "select count(*) \
+                    from events \
+                    where write_roles && $1 and read_roles && $1";
+                context.db
+                    .query_one(query, &[&context.user.roles()])
+                    .await?
+                    .get::<_, i64>(0)
+            }
+        };
+
+        // If `last` was given, we had to query in reverse order to make `limit`
+        // work. So now we need to reverse the result here. We also need to
+        // adjust the last and first "num".
+        if sql_sort_order != order.direction {
+            events.reverse();
+            let tmp = first_num;
+            first_num = last_num.map(|n| total_count - n + 1);
+            last_num = tmp.map(|n| total_count - n + 1);
+        }
+
+        // Figure out whether there is a next and/or previous page.
+        let (has_next_page, has_previous_page) = match Option::zip(first_num, last_num) {
+            Some((first, last)) => (last < total_count, first > 1),
+            None => {
+                // The DB returned 0 events. That means there are either actually 0 writable
+                // events for that user, or all of them were filtered by `after` or `before`.
+                if total_count == 0 {
+                    (false, false)
+                } else if after.is_some() {
+                    (false, true)
+                } else {
+                    (true, false)
+                }
+            }
+        };
+
+        let cast_i32 = |x: i64| x.try_into().expect("more then 2^31 events");
+        Ok(EventConnection {
+            total_count: cast_i32(total_count),
+            page_info: EventPageInfo {
+                has_next_page,
+                has_previous_page,
+                start_cursor: events.first().map(|e| Cursor::new(EventCursor::new(e, &order))),
+                end_cursor: events.last().map(|e| Cursor::new(EventCursor::new(e, &order))),
+                start_index: first_num.map(cast_i32),
+                end_index: last_num.map(cast_i32),
+            },
+            items: events,
+        })
+    }
+
+    pub(crate) fn from_row(row: Row) -> Self {
+        Self {
+            key: cols::key(&row),
+            series: cols::series(&row),
+            opencast_id: cols::opencast_id(&row),
+            source: cols::source(&row),
+            title: cols::title(&row),
+            description: cols::description(&row),
+            duration: cols::duration(&row),
+            trim_start: cols::trim_start(&row).unwrap_or(0),
+            duration_trimmed: match (cols::trim_start(&row), cols::trim_end(&row)) {
+                (Some(start), Some(end)) => end - start,
+                _ => cols::duration(&row),
+            },
+            created: cols::created(&row),
+            updated: cols::updated(&row),
+            creators: cols::creators(&row),
+            thumbnail: cols::thumbnail(&row),
+            thumbnail_color: cols::thumbnail_color(&row),
+            tracks: cols::tracks(&row).into_iter().map(Track::from).collect(),
+            captions: cols::captions(&row).into_iter().map(Caption::from).collect(),
+            location: cols::location(&row),
+            has_passphrase: cols::has_passphrase(&row),
+            can_write: cols::can_write(&row),
+            read_roles: cols::read_roles(&row),
+        }
+    }
+}
+
+// `$1` has to be bound to the roles of the current user.
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        series: Option<Key> = "series",
+        opencast_id: Option<String> = "opencast_id",
+        source: EventSource = "source",
+        title: String = "title",
+        description: Option<String> = "description",
+        duration: i32 = "duration",
+        trim_start: Option<i32> = "trim_start",
+        trim_end: Option<i32> = "trim_end",
+        created: DateTime<Utc> = "created",
+        updated: DateTime<Utc> = "updated",
+        creators: Vec<String> = "creators",
+        thumbnail: Option<String> = "thumbnail",
+        thumbnail_color: Option<String> = "thumbnail_color",
+        tracks: Vec<EventTrack> = "tracks",
+        captions: Vec<EventCaption> = "captions",
+        location: Option<String> = "location",
+        has_passphrase: bool = "passphrase_hash is not null as has_passphrase",
+        can_write: bool = "write_roles && $1 as can_write",
+        read_roles: Vec<String> = "read_roles",
+    }
+}
+
+impl From<EventTrack> for Track {
+    fn from(src: EventTrack) -> Self {
+        Self {
+            uri: src.uri,
+            flavor: src.flavor,
+            mimetype: src.mimetype,
+            resolution: src.resolution.map(Into::into),
+            is_audio: src.is_audio,
+        }
+    }
+}
+
+/// Defines the sort order for events.
+#[derive(Debug, Clone, Copy, juniper::GraphQLInputObject)]
+pub(crate) struct EventSortOrder {
+    column: EventSortColumn,
+    direction: SortDirection,
+}
+
+#[derive(Debug, Clone, Copy, juniper::GraphQLEnum)]
+enum EventSortColumn {
+    Title,
+    Duration,
+    Created,
+    Updated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, juniper::GraphQLEnum)]
+enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+impl Default for EventSortOrder {
+    fn default() -> Self {
+        Self {
+            column: EventSortColumn::Created,
+            direction: SortDirection::Descending,
+        }
+    }
+}
+
+impl EventSortOrder {
+    /// Returns an SQL query fragment like `order by foo asc`.
+    fn to_sql(self, context: &Context) -> impl fmt::Display {
+        let Self { column, direction } = self;
+        let column = column.to_sql(context);
+        lazy_format!("order by {} {}", column, direction.to_sql())
+    }
+}
+
+impl EventSortColumn {
+    /// Titles are compared according to `general.sort_locale`.
+    fn to_sql(self, context: &Context) -> String {
+        match self {
+            EventSortColumn::Title => {
+                format!("title collate {}", context.config.general.collation())
+            }
+            EventSortColumn::Duration => "duration".into(),
+            EventSortColumn::Created => "created".into(),
+            EventSortColumn::Updated => "updated".into(),
+        }
+    }
+}
+
+impl SortDirection {
+    fn to_sql(self) -> &'static str {
+        match self {
+            SortDirection::Ascending => "asc",
+            SortDirection::Descending => "desc",
+        }
+    }
+
+    fn is_ascending(&self) -> bool {
+        matches!(self, Self::Ascending)
+    }
+
+    fn reversed(self) -> Self {
+        match self {
+            SortDirection::Ascending => SortDirection::Descending,
+            SortDirection::Descending => SortDirection::Ascending,
+        }
+    }
+}
+
+
+#[derive(Debug, juniper::GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct EventConnection {
+    page_info: EventPageInfo,
+    items: Vec<Event>,
+    total_count: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EventCursor {
+    key: Key,
+    sort_filter: CursorSortFilter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum CursorSortFilter {
+    Title(String),
+    Duration(i32),
+    Created(DateTime<Utc>),
+    Updated(DateTime<Utc>),
+}
+
+impl EventCursor {
+    fn new(event: &Event, order: &EventSortOrder) -> Self {
+        let sort_filter = match order.column {
+            EventSortColumn::Title => CursorSortFilter::Title(event.title.clone()),
+            EventSortColumn::Duration => CursorSortFilter::Duration(event.duration),
+            EventSortColumn::Created => CursorSortFilter::Created(event.created),
+            EventSortColumn::Updated => CursorSortFilter::Updated(event.updated),
+        };
+
+        Self {
+            sort_filter,
+            key: event.key,
+        }
+    }
+
+    /// Returns the actual filter value as trait object if `self.sort_filter`
+    /// matches `order.column` (both about the same column). Returns an error
+    /// otherwise.
+    fn to_sql_arg(&self, order: &EventSortOrder) -> ApiResult<&(dyn ToSql + Sync + '_)> {
+        match (&self.sort_filter, order.column) {
+            (CursorSortFilter::Title(title), EventSortColumn::Title) => Ok(title),
+            (CursorSortFilter::Duration(duration), EventSortColumn::Duration) => Ok(duration),
+            (CursorSortFilter::Created(created), EventSortColumn::Created) => Ok(created),
+            (CursorSortFilter::Updated(updated), EventSortColumn::Updated) => Ok(updated),
+            _ => Err(invalid_input!("sort order does not match 'before'/'after' argument")),
+        }
+    }
+}
+
+// TODO: when we add more `PageInfo` structs it might make sense to extract the
+// common fields somehow.
+#[derive(Debug, Clone, juniper::GraphQLObject)]
+pub(crate) struct EventPageInfo {
+    pub(crate) has_next_page: bool,
+    pub(crate) has_previous_page: bool,
+
+    // TODO: the spec says these shouldn't be optional, but that makes no sense.
+    // See: https://stackoverflow.com/q/70448483/2408867
+    pub(crate) start_cursor: Option<Cursor>,
+    pub(crate) end_cursor: Option<Cursor>,
+
+    /// The index of the first returned event.
+    pub(crate) start_index: Option<i32>,
+    /// The index of the last returned event.
+    pub(crate) end_index: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_postgres::NoTls;
+
+    use super::member_access_sql;
+
+    /// Requires the dev DB (see `scripts/dev-db`) with all migrations
+    /// applied: run with `cargo test -- --ignored`. Nothing is committed.
+    #[tokio::test]
+    #[ignore]
+    async fn member_access_via_realm() {
+        let config = "host=127.0.0.1 user=tobira password=tobira dbname=tobira";
+        let (mut client, conn) = tokio_postgres::connect(config, NoTls).await.unwrap();
+        tokio::spawn(conn);
+        let tx = client.transaction().await.unwrap();
+
+        let insert = |sql: &'static str| {
+            let tx = &tx;
+            async move { tx.query_one(sql, &[]).await.unwrap().get::<_, i64>(0) }
+        };
+        let series = insert("insert into series (opencast_id, title, updated) \
+            values ('member-access-test', 'Test', now()) returning id").await;
+        let event = |opencast_id: &'static str, series: Option<i64>| {
+            let tx = &tx;
+            async move {
+                tx.query_one(
+                    "insert into events (opencast_id, series, title, duration, \
+                        created, updated, tracks, read_roles, write_roles) \
+                        values ($1, $2, 'Test', 0, now(), now(), '{}', '{ROLE_ADMIN}', '{ROLE_ADMIN}') \
+                        returning id",
+                    &[&opencast_id, &series],
+                ).await.unwrap().get::<_, i64>(0)
+            }
+        };
+        let in_video_block = event("member-access-test-1", None).await;
+        let in_series_block = event("member-access-test-2", Some(series)).await;
+        let not_shown = event("member-access-test-3", None).await;
+
+        let parent = insert("insert into realms (parent, name, path_segment, member_roles) \
+            values (0, 'Test', 'member-access-test', '{ROLE_MEMBER}') returning id").await;
+        let realm = tx.query_one(
+            "insert into realms (parent, name, path_segment) \
+                values ($1, 'Child', 'child') returning id",
+            &[&parent],
+        ).await.unwrap().get::<_, i64>(0);
+        tx.execute(
+            "insert into blocks (realm_id, index, type, video_id, show_title) \
+                values ($1, 0, 'video', $2, true)",
+            &[&realm, &in_video_block],
+        ).await.unwrap();
+        tx.execute(
+            "insert into blocks (realm_id, index, type, series_id, videolist_order, show_title) \
+                values ($1, 1, 'series', $2, 'new_to_old', true)",
+            &[&realm, &series],
+        ).await.unwrap();
+
+        let check = |event: i64, realm: i64, roles: &'static [&'static str]| {
+            let tx = &tx;
+            async move {
+                let query = format!("select {}", member_access_sql("$1", "$2", "$3"));
+                tx.query_one(&query, &[&event, &realm, &roles])
+                    .await
+                    .unwrap()
+                    .get::<_, bool>(0)
+            }
+        };
+        let member: &[&str] = &["ROLE_ANONYMOUS", "ROLE_MEMBER"];
+        let other: &[&str] = &["ROLE_ANONYMOUS", "ROLE_USER"];
+        assert!(check(in_video_block, realm, member).await);
+        assert!(check(in_series_block, realm, member).await);
+        assert!(!check(not_shown, realm, member).await);
+        assert!(!check(in_video_block, realm, other).await);
+        assert!(!check(in_video_block, parent, member).await);
+
+        tx.execute("update events set retracted = true where id = $1", &[&in_video_block])
+            .await
+            .unwrap();
+        assert!(!check(in_video_block, realm, member).await);
+
+        tx.rollback().await.unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use chrono::{DateTime, Utc};
+use hyper::http::uri::{Scheme, Uri};
+use juniper::{GraphQLInputObject, GraphQLObject};
+
+use crate::{
+    api::{Context, Id, cache::CacheTag, err::{ApiResult, invalid_input}},
+    auth::ROLE_ANONYMOUS,
+    db::types::{EventTrack, Key, is_audio_mimetype},
+    prelude::*,
+    search,
+};
+use super::Event;
+
+
+impl Event {
+    /// Registers a new external event, i.e. a video that is not managed by
+    /// Opencast but lives on some other server.
+    pub(crate) async fn add_external(
+        event: NewExternalEvent,
+        context: &Context,
+    ) -> ApiResult<Event> {
+        let db = context.db(context.require_moderator()?);
+        context.cache.modified(CacheTag::Events);
+
+        let external_events = &context.config.external_events;
+        external_events.check_url(&event.url, "`url`")?;
+        if let Some(thumbnail) = &event.thumbnail {
+            external_events.check_url(thumbnail, "`thumbnail`")?;
+        }
+        let sanitize = &context.config.sanitize;
+        let title = sanitize.line(&event.title);
+        if title.is_empty() {
+            return Err(invalid_input!("`title` must not be empty"));
+        }
+        let description = event.description.as_deref().and_then(|d| sanitize.text(d));
+        let creators = event.creators.unwrap_or_default()
+            .iter()
+            .map(|c| sanitize.line(c))
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        if event.duration < 0 {
+            return Err(invalid_input!("`duration` must not be negative"));
+        }
+
+        let series = event.series
+            .map(|id| id.key_for(Id::SERIES_KIND)
+                .ok_or_else(|| invalid_input!("`series` does not refer to a series")))
+            .transpose()?;
+
+        // Unless specified otherwise, external events are public. Only
+        // moderators are allowed to change them.
+        let read_roles = event.read_roles.unwrap_or_else(|| vec![ROLE_ANONYMOUS.into()]);
+        let write_roles = vec![context.config.auth.moderator_role.clone()];
+        let tracks = vec![EventTrack {
+            uri: event.url,
+            flavor: EXTERNAL_TRACK_FLAVOR.into(),
+            is_audio: event.mimetype.as_deref().and_then(is_audio_mimetype),
+            mimetype: event.mimetype,
+            resolution: None,
+        }];
+
+        let key: Key = db
+            .query_one(
+                "insert into events (source, series, title, description, duration, \
+                    created, updated, creators, thumbnail, tracks, read_roles, write_roles) \
+                    values ('external', $1, $2, $3, $4, coalesce($5, now()), now(), \
+                        $6, $7, $8, $9, $10) \
+                    returning id",
+                &[
+                    &series,
+                    &title,
+                    &description,
+                    &event.duration,
+                    &event.created,
+                    &creators,
+                    &event.thumbnail,
+                    &tracks,
+                    &read_roles,
+                    &write_roles,
+                ],
+            )
+            .await?
+            .get(0);
+        db.queue_for_reindex(search::IndexItemKind::Event, key).await?;
+        debug!("Added external event {:?} ('{}')", key, title);
+
+        Event::load_by_id(Id::event(key), context).await?
+            .ok_or_else(|| invalid_input!("external event was added with roles hiding it from you"))
+    }
+
+    /// Removes an external event. Events synced from Opencast cannot be
+    /// removed this way.
+    pub(crate) async fn remove_external(id: Id, context: &Context) -> ApiResult<RemovedEvent> {
+        let db = context.db(context.require_moderator()?);
+        context.cache.modified(CacheTag::Events);
+
+        let key = id.key_for(Id::EVENT_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to an event"))?;
+        let affected_rows = db
+            .execute("delete from events where id = $1 and source = 'external'", &[&key])
+            .await?;
+
+        if affected_rows != 1 {
+            return Err(invalid_input!("`id` does not refer to an existing external event"));
+        }
+
+        db.queue_for_reindex(search::IndexItemKind::Event, key).await?;
+        debug!("Removed external event {:?}", key);
+
+        Ok(RemovedEvent { id })
+    }
+}
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct ExternalEventsConfig {
+    /// Hosts that videos and thumbnails of external events (events not
+    /// managed by Opencast, see `addExternalEvent`) may be served from, e.g.
+    /// `["media.example.org"]`. Tobira fetches some of these URLs itself
+    /// (e.g. to resize thumbnails), so only list hosts you trust. Ports are
+    /// ignored. If not set, external events cannot be added.
+    pub(crate) allowed_hosts: Option<Vec<String>>,
+}
+
+impl ExternalEventsConfig {
+    /// Makes sure `url` is an absolute HTTP(S) URL pointing to one of the
+    /// allowed hosts. `field` is used in error messages.
+    fn check_url(&self, url: &str, field: &str) -> ApiResult<()> {
+        let uri = url.parse::<Uri>()
+            .map_err(|e| invalid_input!("{} is not a valid URL: {}", field, e))?;
+        if uri.scheme() != Some(&Scheme::HTTP) && uri.scheme() != Some(&Scheme::HTTPS) {
+            return Err(invalid_input!("{} has to be an absolute HTTP(S) URL", field));
+        }
+
+        let allowed = uri.host().is_some_and(|host| {
+            self.allowed_hosts.iter().flatten().any(|allowed| allowed.eq_ignore_ascii_case(host))
+        });
+        if !allowed {
+            return Err(invalid_input!(
+                "host of {} is not listed in `external_events.allowed_hosts`",
+                field,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Flavor of the single track that is created for each external event.
+const EXTERNAL_TRACK_FLAVOR: &str = "presenter/external";
+
+#[derive(GraphQLInputObject)]
+pub(crate) struct NewExternalEvent {
+    /// URL of the video file. Has to be an absolute HTTP(S) URL on one of the
+    /// hosts listed in `external_events.allowed_hosts`.
+    url: String,
+    /// Mimetype of the video file, e.g. `video/mp4`.
+    mimetype: Option<String>,
+    title: String,
+    description: Option<String>,
+    /// Duration in ms.
+    duration: i32,
+    /// Creation date of the video. If not given, the current time is used.
+    created: Option<DateTime<Utc>>,
+    creators: Option<Vec<String>>,
+    /// URL of a thumbnail image. The same restrictions as for `url` apply.
+    thumbnail: Option<String>,
+    /// Series the event should be part of.
+    series: Option<Id>,
+    /// Roles that are allowed to view this event. If not given, the event is
+    /// public.
+    read_roles: Option<Vec<String>>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RemovedEvent {
+    id: Id,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExternalEventsConfig;
+
+    #[test]
+    fn external_urls() {
+        let config = ExternalEventsConfig {
+            allowed_hosts: Some(vec!["media.example.org".into()]),
+        };
+        let check = |url| config.check_url(url, "`url`").is_ok();
+
+        assert!(check("https://media.example.org/video.mp4"));
+        assert!(check("http://MEDIA.example.org:8080/video.mp4"));
+        assert!(!check("https://example.org/video.mp4"));
+        assert!(!check("https://media.example.org.evil.com/video.mp4"));
+        assert!(!check("http://127.0.0.1/latest/meta-data"));
+        assert!(!check("ftp://media.example.org/video.mp4"));
+        assert!(!check("/video.mp4"));
+
+        let unset = ExternalEventsConfig { allowed_hosts: None };
+        assert!(unset.check_url("https://media.example.org/video.mp4", "`url`").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{net::IpAddr, num::NonZeroU32, time::Duration};
+
+use once_cell::sync::Lazy;
+use ring::{pbkdf2, rand::{SecureRandom, SystemRandom}};
+
+use crate::{
+    api::{
+        Context, Id,
+        err::{ApiResult, invalid_input, not_authorized},
+    },
+    db::types::Key,
+    http::rate_limit::RateLimiter,
+    prelude::*,
+    search,
+};
+use super::Event;
+
+
+const ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+const MAX_LEN: usize = 256;
+
+/// Unlock attempts per client IP, successful or not.
+static ATTEMPTS_PER_IP: Lazy<RateLimiter<IpAddr>> =
+    Lazy::new(|| RateLimiter::new(30, Duration::from_secs(5 * 60)));
+
+/// Unlock attempts per event, from any client. Reset on success, so
+/// effectively consecutive failures. Counted before verifying the
+/// passphrase, so that concurrent attempts can't exceed the limit.
+static FAILURES_PER_EVENT: Lazy<RateLimiter<Key>> =
+    Lazy::new(|| RateLimiter::new(20, Duration::from_secs(15 * 60)));
+
+/// Hashes `passphrase` with a random salt, in the format stored in
+/// `events.passphrase_hash`. Slow on purpose, so it runs on a blocking thread.
+async fn hash(passphrase: String) -> String {
+    tokio::task::spawn_blocking(move || hash_sync(&passphrase))
+        .await
+        .expect("failed to hash passphrase")
+}
+
+fn hash_sync(passphrase: &str) -> String {
+    let mut salt = [0; SALT_LEN];
+    SystemRandom::new().fill(&mut salt).expect("failed to generate salt");
+    let mut hash = [0; HASH_LEN];
+    let iterations = NonZeroU32::new(ITERATIONS).unwrap();
+    pbkdf2::derive(ALGORITHM, iterations, &salt, passphrase.as_bytes(), &mut hash);
+
+    format!("pbkdf2-sha256${}${}${}", ITERATIONS, hex::encode(salt), hex::encode(hash))
+}
+
+/// Checks `passphrase` against a hash returned by `hash`. Runs on a blocking
+/// thread, like `hash`.
+async fn verify(passphrase: String, stored: String) -> bool {
+    tokio::task::spawn_blocking(move || verify_sync(&passphrase, &stored))
+        .await
+        .expect("failed to verify passphrase")
+}
+
+fn verify_sync(passphrase: &str, stored: &str) -> bool {
+    let parts = stored.split('$').collect::<Vec<_>>();
+    let [algorithm, iterations, salt, hash] = parts[..] else {
+        return false;
+    };
+    let iterations = iterations.parse().ok().and_then(NonZeroU32::new);
+    match (algorithm, iterations, hex::decode(salt), hex::decode(hash)) {
+        ("pbkdf2-sha256", Some(iterations), Ok(salt), Ok(hash)) => {
+            pbkdf2::verify(ALGORITHM, iterations, &salt, passphrase.as_bytes(), &hash).is_ok()
+        }
+        _ => {
+            warn!("Invalid passphrase hash in database");
+            false
+        }
+    }
+}
+
+impl Event {
+    /// Sets or, if `passphrase` is `None`, removes the passphrase of an event
+    /// the current user has write access to.
+    pub(crate) async fn set_passphrase(
+        id: Id,
+        passphrase: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Self> {
+        let key = id.key_for(Id::EVENT_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to an event"))?;
+        if let Some(passphrase) = &passphrase {
+            if passphrase.trim().is_empty() {
+                return Err(invalid_input!("`passphrase` must not be empty"));
+            }
+            if passphrase.len() > MAX_LEN {
+                return Err(invalid_input!("`passphrase` must not be longer than {} bytes", MAX_LEN));
+            }
+        }
+
+        let passphrase_hash = match &passphrase {
+            Some(passphrase) => Some(hash(passphrase.clone()).await),
+            None => None,
+        };
+        let affected_rows = context.db
+            .execute(
+                "update events set passphrase_hash = $3 where id = $1 and write_roles && $2",
+                &[&key, &context.user.roles(), &passphrase_hash],
+            )
+            .await?;
+        if affected_rows != 1 {
+            return Err(invalid_input!(
+                "`id` does not refer to an event you have write access to",
+            ));
+        }
+        // The search index must not contain the details of protected events.
+        context.db.queue_for_reindex(search::IndexItemKind::Event, key).await?;
+        debug!(
+            "{} passphrase of event {:?}",
+            if passphrase.is_some() { "Set" } else { "Removed" },
+            key,
+        );
+
+        Self::load_by_id(id, context)
+            .await?
+            .ok_or_else(|| invalid_input!("`id` does not refer to an event you can read"))
+    }
+
+    /// Checks `passphrase` and returns a token that can be passed to the
+    /// fields of the event hidden by it. Attempts are rate limited per client
+    /// IP and per event. Once a limit is hit, every attempt fails as if the
+    /// passphrase was wrong.
+    pub(crate) async fn unlock(id: Id, passphrase: String, context: &Context) -> ApiResult<String> {
+        let key = id.key_for(Id::EVENT_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to an event"))?;
+        let stored = context.db
+            .query_opt(
+                "select passphrase_hash from events where id = $1 and read_roles && $2",
+                &[&key, &context.user.roles()],
+            )
+            .await?
+            .ok_or_else(|| invalid_input!("`id` does not refer to an event you can read"))?
+            .get::<_, Option<String>>(0)
+            .ok_or_else(|| invalid_input!("the event is not protected by a passphrase"))?;
+
+        let limited = context.client_ip.is_some_and(|ip| !ATTEMPTS_PER_IP.hit(ip))
+            || !FAILURES_PER_EVENT.hit(key);
+        if limited {
+            debug!("Rate limited unlock attempt for event {:?}", key);
+            return Err(not_authorized!("wrong passphrase"));
+        }
+        if !verify(passphrase, stored).await {
+            return Err(not_authorized!("wrong passphrase"));
+        }
+        FAILURES_PER_EVENT.reset(&key);
+
+        Ok(context.jwt.new_event_access_token(key))
+    }
+
+    /// Whether the fields hidden by the passphrase cannot be shown: the event
+    /// has one, the user cannot write it and `access_token` is not valid.
+    pub(super) fn is_locked(&self, access_token: Option<&str>, context: &Context) -> bool {
+        self.has_passphrase
+            && !self.can_write
+            && !access_token.is_some_and(|token| {
+                context.jwt.check_event_access_token(token, self.key)
+            })
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use postgres_types::ToSql;
+
+use crate::{api::{Context, cache::CacheTag, err::ApiResult}, auth::HasRoles};
+
+
+/// Aggregates over the events of a series or realm that the current user can
+/// read, so that overview pages can show e.g. "142 videos · 96 hours" without
+/// loading all events.
+#[derive(Debug, Clone)]
+pub(crate) struct EventStats {
+    pub(crate) count: i32,
+    /// Sum of the played (i.e. trimmed) durations in seconds.
+    pub(crate) total_duration: f64,
+    pub(crate) latest: Option<DateTime<Utc>>,
+}
+
+impl EventStats {
+    /// Aggregates the events matching `condition`. The result is cached under
+    /// `key` and the roles of the current user, so all fields of one series or
+    /// realm only need one query. `key` has to identify `condition` and
+    /// `args` apart from the roles.
+    pub(crate) async fn load(
+        key: String,
+        condition: &str,
+        args: &[&(dyn ToSql + Sync)],
+        context: &Context,
+    ) -> ApiResult<Self> {
+        let mut roles = context.user.roles().to_vec();
+        roles.sort();
+        let key = format!("event-stats:{key}:{}", roles.join(","));
+
+        let load = async {
+            let query = format!(
+                "select count(*), \
+                    coalesce(sum(coalesce(trim_end - trim_start, duration)), 0)::float8 / 1000, \
+                    max(created) \
+                    from events \
+                    where {condition}",
+            );
+            let row = context.db.query_one(&query, args).await?;
+            Ok(Self {
+                count: row.get::<_, i64>(0).try_into().expect("more then 2^31 events"),
+                total_duration: row.get(1),
+                latest: row.get(2),
+            })
+        };
+
+        // Changes to blocks are not noticed here, so realms are outdated for
+        // at most this long after blocks were changed.
+        let ttl = Duration::from_secs(60);
+        context.cache.get_or_load(CacheTag::Events, key, ttl, load).await
+    }
+}
@@ -2,8 +2,18 @@
 //! API.
 
 pub(crate) mod block;
+pub(crate) mod capture_agent;
 pub(crate) mod event;
+pub(crate) mod moderator_delegation;
+pub(crate) mod notification;
+pub(crate) mod pending_operation;
+pub(crate) mod preference;
 pub(crate) mod realm;
+pub(crate) mod retention;
+pub(crate) mod saved_search;
 pub(crate) mod search;
 pub(crate) mod series;
+pub(crate) mod setting;
+pub(crate) mod system_status;
+pub(crate) mod upload;
 pub(crate) mod user;
@@ -0,0 +1,228 @@
+use chrono::{DateTime, Duration, Utc};
+use juniper::graphql_object;
+use tokio_postgres::Row;
+
+use crate::{
+    api::{
+        Context, Id, Node, NodeValue,
+        err::{ApiResult, invalid_input},
+    },
+    db::{types::Key, util::define_columns},
+    prelude::*,
+};
+use super::realm::Realm;
+
+
+/// Moderation rights on a realm and all its descendants, temporarily
+/// delegated to a user by a moderator. This is meant for cases like vacation
+/// replacements, where changing the user's roles in the identity provider
+/// is overkill. Only realms and their blocks can be moderated this way.
+pub(crate) struct ModeratorDelegation {
+    key: Key,
+    realm: Key,
+    username: String,
+    delegated_by: String,
+    expires: DateTime<Utc>,
+    created: DateTime<Utc>,
+}
+
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        realm: Key = "realm",
+        username: String = "username",
+        delegated_by: String = "delegated_by",
+        expires: DateTime<Utc> = "expires",
+        created: DateTime<Utc> = "created",
+    }
+}
+
+#[juniper::graphql_interface]
+impl Node for ModeratorDelegation {
+    fn id(&self) -> Id {
+        Id::moderator_delegation(self.key)
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl ModeratorDelegation {
+    fn id(&self) -> Id {
+        Node::id(self)
+    }
+
+    /// The realm whose subtree can be moderated.
+    async fn realm(&self, context: &Context) -> ApiResult<Realm> {
+        Realm::load_by_key(self.realm, context)
+            .await?
+            .ok_or_else(|| invalid_input!("delegation refers to a removed realm"))
+    }
+
+    /// The user who received the moderation rights.
+    fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// The moderator who created this delegation.
+    fn delegated_by(&self) -> &str {
+        &self.delegated_by
+    }
+
+    /// After this point in time, the delegation has no effect anymore.
+    fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+}
+
+impl ModeratorDelegation {
+    /// Maximum duration of a delegation in days. Longer replacements should
+    /// be handled via roles.
+    const MAX_DAYS: i64 = 90;
+
+    fn from_row(row: Row) -> Self {
+        Self {
+            key: cols::key(&row),
+            realm: cols::realm(&row),
+            username: cols::username(&row),
+            delegated_by: cols::delegated_by(&row),
+            expires: cols::expires(&row),
+            created: cols::created(&row),
+        }
+    }
+
+    /// Returns the active delegation with the given ID. Only moderators can
+    /// see delegations.
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        let key = match id.key_for(Id::MODERATOR_DELEGATION_KIND) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+
+        let query = format!(
+            "select {} from moderator_delegations where id = $1 and expires > now()",
+            cols::COL_NAMES,
+        );
+        context.db(context.require_moderator()?)
+            .query_opt(&query, &[&key])
+            .await?
+            .map(Self::from_row)
+            .pipe(Ok)
+    }
+
+    /// Returns all active delegations for the given realm. Only moderators
+    /// can see delegations.
+    pub(crate) async fn load_for_realm(realm: Key, context: &Context) -> ApiResult<Vec<Self>> {
+        let query = format!(
+            "select {} from moderator_delegations \
+                where realm = $1 and expires > now() \
+                order by expires",
+            cols::COL_NAMES,
+        );
+        context.db(context.require_moderator()?)
+            .query_mapped(&query, dbargs![&realm], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Returns all active delegations to the current user.
+    pub(crate) async fn load_for_user(context: &Context) -> ApiResult<Vec<Self>> {
+        let user = match &context.user {
+            Some(user) => user,
+            None => return Ok(vec![]),
+        };
+
+        let query = format!(
+            "select {} from moderator_delegations \
+                where username = $1 and expires > now() \
+                order by expires",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_mapped(&query, dbargs![&user.username], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Delegates moderation of `realm` and its descendants to the given user
+    /// until `expires`. Only moderators can do this; delegated rights cannot
+    /// be passed on.
+    pub(crate) async fn create(
+        realm: Id,
+        username: String,
+        expires: DateTime<Utc>,
+        context: &Context,
+    ) -> ApiResult<Self> {
+        let db = context.db(context.require_moderator()?);
+        let moderator = &context.user.as_ref().expect("moderator is not logged in").username;
+
+        let realm = realm.key_for(Id::REALM_KIND)
+            .ok_or_else(|| invalid_input!("`realm` does not refer to a realm"))?;
+        let username = username.trim();
+        if username.is_empty() {
+            return Err(invalid_input!("`username` must not be empty"));
+        }
+
+        let now = Utc::now();
+        if expires <= now {
+            return Err(invalid_input!("`expires` has to be in the future"));
+        }
+        if expires > now + Duration::days(Self::MAX_DAYS) {
+            return Err(invalid_input!(
+                "delegations cannot last longer than {} days",
+                Self::MAX_DAYS,
+            ));
+        }
+
+        let sql = format!(
+            "insert into moderator_delegations (realm, username, delegated_by, expires) \
+                select id, $2, $3, $4 from realms where id = $1 \
+                returning {}",
+            cols::COL_NAMES,
+        );
+        let row = db.query_opt(&sql, &[&realm, &username, moderator, &expires])
+            .await?
+            .ok_or_else(|| invalid_input!("`realm` does not refer to an existing realm"))?;
+        info!(
+            "User '{}' delegated moderation of realm {:?} to '{}' until {}",
+            moderator,
+            realm,
+            username,
+            expires,
+        );
+
+        Ok(Self::from_row(row))
+    }
+
+    /// Revokes a delegation before it expires. Any moderator can do that.
+    pub(crate) async fn revoke(
+        id: Id,
+        context: &Context,
+    ) -> ApiResult<RemovedModeratorDelegation> {
+        let db = context.db(context.require_moderator()?);
+        let key = id.key_for(Id::MODERATOR_DELEGATION_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a moderator delegation"))?;
+
+        let affected_rows = db
+            .execute("delete from moderator_delegations where id = $1", &[&key])
+            .await?;
+        if affected_rows != 1 {
+            return Err(invalid_input!("`id` does not refer to an existing moderator delegation"));
+        }
+        info!(
+            "User '{}' revoked moderator delegation {:?}",
+            context.user.as_ref().expect("moderator is not logged in").username,
+            key,
+        );
+
+        Ok(RemovedModeratorDelegation { id })
+    }
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RemovedModeratorDelegation {
+    id: Id,
+}
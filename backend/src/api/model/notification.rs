@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use juniper::{GraphQLEnum, graphql_object};
+use postgres_types::{FromSql, ToSql};
+use tokio_postgres::Row;
+
+use crate::{
+    api::{
+        Context, Id, Node, NodeValue,
+        err::{ApiResult, invalid_input, not_authorized},
+        model::event::Event,
+    },
+    db::{types::Key, util::define_columns},
+    prelude::*,
+};
+
+
+/// A message for the user, shown in the app.
+pub(crate) struct Notification {
+    key: Key,
+    kind: NotificationKind,
+    event: Option<Key>,
+    created: DateTime<Utc>,
+    read: bool,
+}
+
+/// What a notification is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "notification_kind")]
+pub(crate) enum NotificationKind {
+    /// A video of the user was processed by Opencast and is now available.
+    /// `event` is that video.
+    #[postgres(name = "upload_finished")]
+    UploadFinished,
+    /// A new event was published in a series the user follows (see
+    /// `followSeries`). `event` is that event.
+    #[postgres(name = "new_series_event")]
+    NewSeriesEvent,
+}
+
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        kind: NotificationKind = "kind",
+        event: Option<Key> = "event",
+        created: DateTime<Utc> = "created",
+        read: bool = "read",
+    }
+}
+
+#[juniper::graphql_interface]
+impl Node for Notification {
+    fn id(&self) -> Id {
+        Id::notification(self.key)
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl Notification {
+    fn id(&self) -> Id {
+        Node::id(self)
+    }
+
+    fn kind(&self) -> NotificationKind {
+        self.kind
+    }
+
+    /// The event this notification is about. `null` if the notification is not
+    /// about an event or the event was deleted.
+    async fn event(&self, context: &Context) -> ApiResult<Option<Event>> {
+        match self.event {
+            Some(key) => Event::load_by_id(Id::event(key), context).await,
+            None => Ok(None),
+        }
+    }
+
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    /// Whether the user has already seen this notification.
+    fn read(&self) -> bool {
+        self.read
+    }
+}
+
+impl Notification {
+    /// Maximum number of notifications returned by `load_for_user`.
+    const MAX_COUNT: i64 = 100;
+
+    fn from_row(row: Row) -> Self {
+        Self {
+            key: cols::key(&row),
+            kind: cols::kind(&row),
+            event: cols::event(&row),
+            created: cols::created(&row),
+            read: cols::read(&row),
+        }
+    }
+
+    /// Returns the notification with the given ID if it is addressed to any
+    /// role of the current user.
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        let key = match id.key_for(Id::NOTIFICATION_KIND) {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+
+        let query = format!(
+            "select {} from notifications where id = $1 and recipient = any($2)",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_opt(&query, &[&key, &context.user.roles()])
+            .await?
+            .map(Self::from_row)
+            .pipe(Ok)
+    }
+
+    /// Returns the newest notifications addressed to any role of the current
+    /// user, newest first.
+    pub(crate) async fn load_for_user(
+        unread_only: bool,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let query = format!(
+            "select {} from notifications \
+                where recipient = any($1) and (not $2 or not read) \
+                order by created desc \
+                limit $3",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_mapped(
+                &query,
+                dbargs![&context.user.roles(), &unread_only, &Self::MAX_COUNT],
+                Self::from_row,
+            )
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Marks the given notifications as read. Notifications that are not
+    /// addressed to the current user result in an error.
+    pub(crate) async fn mark_read(ids: Vec<Id>, context: &Context) -> ApiResult<Vec<Self>> {
+        if context.user.is_none() {
+            return Err(not_authorized!(key = "mutation.not-logged-in", "you are not logged in"));
+        }
+
+        let mut keys = ids.iter()
+            .map(|id| id.key_for(Id::NOTIFICATION_KIND)
+                .ok_or_else(|| invalid_input!("`ids` contains an ID that is not a notification")))
+            .collect::<ApiResult<Vec<_>>>()?;
+        keys.sort_unstable_by_key(|key| key.0);
+        keys.dedup();
+
+        let query = format!(
+            "update notifications set read = true \
+                where id = any($1) and recipient = any($2) \
+                returning {}",
+            cols::COL_NAMES,
+        );
+        let notifications = context.db
+            .query_mapped(&query, dbargs![&keys, &context.user.roles()], Self::from_row)
+            .await?;
+
+        if notifications.len() != keys.len() {
+            return Err(invalid_input!("`ids` contains unknown notifications"));
+        }
+
+        Ok(notifications)
+    }
+}
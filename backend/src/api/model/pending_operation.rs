@@ -0,0 +1,223 @@
+use chrono::{DateTime, Utc};
+use juniper::{GraphQLEnum, graphql_object};
+use postgres_types::{FromSql, ToSql};
+use tokio_postgres::Row;
+
+use crate::{
+    api::{
+        Context,
+        Id,
+        Node,
+        NodeValue,
+        err::{ApiResult, invalid_input, not_authorized},
+        model::realm::Realm,
+    },
+    db::{types::Key, util::define_columns},
+    prelude::*,
+};
+
+
+/// A destructive operation that was requested by one moderator and has to
+/// be approved by another one before it is executed. Only used if
+/// `auth.deletion_approval` is enabled.
+pub(crate) struct PendingOperation {
+    key: Key,
+    kind: PendingOperationKind,
+    realm: Option<Key>,
+    requested_by: String,
+    requested: DateTime<Utc>,
+    expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "pending_operation_kind")]
+pub(crate) enum PendingOperationKind {
+    /// Removing `realm` and all its descendants.
+    #[postgres(name = "remove_realm")]
+    RemoveRealm,
+}
+
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        kind: PendingOperationKind = "kind",
+        realm: Option<Key> = "realm",
+        requested_by: String = "requested_by",
+        requested: DateTime<Utc> = "requested",
+        expires: DateTime<Utc> = "expires",
+    }
+}
+
+#[juniper::graphql_interface]
+impl Node for PendingOperation {
+    fn id(&self) -> Id {
+        Id::pending_operation(self.key)
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl PendingOperation {
+    fn id(&self) -> Id {
+        Node::id(self)
+    }
+
+    fn kind(&self) -> PendingOperationKind {
+        self.kind
+    }
+
+    /// The realm the operation affects, if any.
+    async fn realm(&self, context: &Context) -> ApiResult<Option<Realm>> {
+        match self.realm {
+            Some(key) => Realm::load_by_key(key, context).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Username of the moderator that requested the operation.
+    fn requested_by(&self) -> &str {
+        &self.requested_by
+    }
+
+    fn requested(&self) -> DateTime<Utc> {
+        self.requested
+    }
+
+    /// After this point in time, the operation cannot be approved anymore.
+    fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+}
+
+impl PendingOperation {
+    fn from_row(row: Row) -> Self {
+        Self {
+            key: cols::key(&row),
+            kind: cols::kind(&row),
+            realm: cols::realm(&row),
+            requested_by: cols::requested_by(&row),
+            requested: cols::requested(&row),
+            expires: cols::expires(&row),
+        }
+    }
+
+    /// Returns all operations that have not expired yet, oldest first. Only
+    /// moderators can see them.
+    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
+        let db = context.db(context.require_moderator()?);
+
+        let query = format!(
+            "select {} from pending_operations where expires > now() order by requested",
+            cols::COL_NAMES,
+        );
+        db.query_mapped(&query, dbargs![], Self::from_row).await?.pipe(Ok)
+    }
+
+    /// Returns the operation with the given ID, even if it has expired. Only
+    /// moderators can see it.
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        let db = context.db(context.require_moderator()?);
+        let key = match id.key_for(Id::PENDING_OPERATION_KIND) {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+
+        let query = format!("select {} from pending_operations where id = $1", cols::COL_NAMES);
+        db.query_opt(&query, &[&key]).await?.map(Self::from_row).pipe(Ok)
+    }
+
+    /// Requests removing the given realm and all its descendants.
+    pub(crate) async fn request_realm_removal(id: Id, context: &Context) -> ApiResult<Self> {
+        let db = context.db(context.require_moderator()?);
+        let username = Self::username(context)?;
+
+        let key = id.key_for(Id::REALM_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a realm"))?;
+        if key.0 == 0 {
+            return Err(invalid_input!("Cannot remove the root realm"));
+        }
+        if Realm::load_by_key(key, context).await?.is_none() {
+            return Err(invalid_input!("`id` does not refer to an existing realm"));
+        }
+        context.require_unlocked_subtree(key).await?;
+
+        db.execute("delete from pending_operations where expires <= now()", &[]).await?;
+        let query = format!(
+            "insert into pending_operations (kind, realm, requested_by, expires) \
+                values ('remove_realm', $1, $2, now() + make_interval(secs => $3)) \
+                on conflict (realm, kind) do nothing \
+                returning {}",
+            cols::COL_NAMES,
+        );
+        let expiry = context.config.auth.deletion_approval_expiry.as_secs_f64();
+        let operation = db.query_opt(&query, &[&key, &username, &expiry]).await?
+            .map(Self::from_row)
+            .ok_or_else(|| invalid_input!("the removal of this realm was already requested"))?;
+        info!("User '{}' requested the removal of realm {:?}", username, key);
+
+        Ok(operation)
+    }
+
+    /// Executes the given operation. It has to be approved by a different
+    /// moderator than the one who requested it.
+    pub(crate) async fn approve(id: Id, context: &Context) -> ApiResult<Self> {
+        let operation = Self::load_for_update(id, context).await?;
+        let username = Self::username(context)?;
+        if operation.requested_by == username {
+            return Err(not_authorized!(
+                key = "mutation.own-operation",
+                "operations have to be approved by a different moderator",
+            ));
+        }
+
+        match operation.kind {
+            PendingOperationKind::RemoveRealm => {
+                let realm = operation.realm.expect("bug: 'remove_realm' without realm");
+
+                // This also deletes the operation via `on delete cascade`.
+                let token = context.require_moderator()?;
+                Realm::remove_unchecked(realm, token, context).await?;
+                info!(
+                    "User '{}' approved the removal of realm {:?} requested by '{}'",
+                    username,
+                    realm,
+                    operation.requested_by,
+                );
+            }
+        }
+
+        Ok(operation)
+    }
+
+    /// Cancels the given operation. Any moderator can do that.
+    pub(crate) async fn cancel(id: Id, context: &Context) -> ApiResult<Self> {
+        let operation = Self::load_for_update(id, context).await?;
+        context.db(context.require_moderator()?)
+            .execute("delete from pending_operations where id = $1", &[&operation.key])
+            .await?;
+        info!("User '{}' canceled operation {:?}", Self::username(context)?, operation.key);
+
+        Ok(operation)
+    }
+
+    /// Loads and locks the operation with the given ID, which must not be
+    /// expired. Only moderators can do that.
+    async fn load_for_update(id: Id, context: &Context) -> ApiResult<Self> {
+        let db = context.db(context.require_moderator()?);
+
+        let key = id.key_for(Id::PENDING_OPERATION_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a pending operation"))?;
+        let query = format!(
+            "select {} from pending_operations where id = $1 and expires > now() for update",
+            cols::COL_NAMES,
+        );
+        db.query_opt(&query, &[&key]).await?
+            .map(Self::from_row)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a pending operation or it expired"))
+    }
+
+    fn username(context: &Context) -> ApiResult<&str> {
+        context.user.as_ref()
+            .map(|user| user.username.as_str())
+            .ok_or_else(|| not_authorized!(key = "mutation.not-logged-in", "you are not logged in"))
+    }
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use juniper::{GraphQLEnum, GraphQLObject};
+use postgres_types::{FromSql, ToSql};
+
+use crate::{
+    api::{Context, err::{ApiResult, invalid_input, not_authorized}},
+    auth::User,
+    prelude::*,
+};
+
+
+/// Interface settings that users can store, so that they follow them across
+/// devices. The frontend decides how to apply them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "user_preference")]
+pub(crate) enum PreferenceKey {
+    /// Default playback speed, a number between 0.25 and 4, e.g. `1.5`.
+    #[postgres(name = "playback_speed")]
+    PlaybackSpeed,
+    /// Preferred video quality: `auto` or a height like `720p`.
+    #[postgres(name = "preferred_quality")]
+    PreferredQuality,
+    /// Language of the interface as language code, e.g. `de` or `pt-BR`.
+    #[postgres(name = "language")]
+    Language,
+    /// Color scheme of the interface: `light`, `dark` or `auto`.
+    #[postgres(name = "theme")]
+    Theme,
+}
+
+impl PreferenceKey {
+    /// Returns whether `value` is valid for this preference.
+    fn is_valid(self, value: &str) -> bool {
+        match self {
+            Self::PlaybackSpeed => value.parse::<f64>()
+                .is_ok_and(|speed| (0.25..=4.0).contains(&speed)),
+            Self::PreferredQuality => value == "auto" || value.strip_suffix('p')
+                .and_then(|height| height.parse::<u16>().ok())
+                .is_some_and(|height| (1..=4320).contains(&height)),
+            Self::Language => {
+                let (language, region) = match value.split_once('-') {
+                    Some((language, region)) => (language, Some(region)),
+                    None => (value, None),
+                };
+                (2..=3).contains(&language.len())
+                    && language.bytes().all(|b| b.is_ascii_lowercase())
+                    && region.is_none_or(|region| {
+                        region.len() == 2 && region.bytes().all(|b| b.is_ascii_uppercase())
+                    })
+            }
+            Self::Theme => matches!(value, "light" | "dark" | "auto"),
+        }
+    }
+}
+
+/// A stored interface preference of the current user.
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct Preference {
+    key: PreferenceKey,
+    value: String,
+    updated: DateTime<Utc>,
+}
+
+impl Preference {
+    fn require_user(context: &Context) -> ApiResult<&User> {
+        context.user.as_ref()
+            .ok_or_else(|| not_authorized!(key = "mutation.not-logged-in", "you are not logged in"))
+    }
+
+    /// Returns all preferences the current user has set.
+    pub(crate) async fn load_for_user(context: &Context) -> ApiResult<Vec<Self>> {
+        let user = Self::require_user(context)?;
+        context.db
+            .query_mapped(
+                "select key, value, updated from user_preferences \
+                    where username = $1 order by key",
+                dbargs![&user.username],
+                |row| Self { key: row.get(0), value: row.get(1), updated: row.get(2) },
+            )
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Stores a preference of the current user. `None` removes it, so that
+    /// the default applies again. Returns all preferences of the user.
+    pub(crate) async fn set(
+        key: PreferenceKey,
+        value: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let user = Self::require_user(context)?;
+
+        match value {
+            Some(value) => {
+                if !key.is_valid(&value) {
+                    return Err(invalid_input!("invalid value for preference {:?}", key));
+                }
+                context.db.execute(
+                    "insert into user_preferences (username, key, value) values ($1, $2, $3) \
+                        on conflict (username, key) \
+                        do update set value = excluded.value, updated = now()",
+                    &[&user.username, &key, &value],
+                ).await?;
+            }
+            None => {
+                context.db.execute(
+                    "delete from user_preferences where username = $1 and key = $2",
+                    &[&user.username, &key],
+                ).await?;
+            }
+        }
+
+        Self::load_for_user(context).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreferenceKey;
+
+    #[test]
+    fn validation() {
+        assert!(PreferenceKey::PlaybackSpeed.is_valid("1.5"));
+        assert!(PreferenceKey::PlaybackSpeed.is_valid("4"));
+        assert!(!PreferenceKey::PlaybackSpeed.is_valid("0.1"));
+        assert!(!PreferenceKey::PlaybackSpeed.is_valid("NaN"));
+        assert!(PreferenceKey::PreferredQuality.is_valid("auto"));
+        assert!(PreferenceKey::PreferredQuality.is_valid("720p"));
+        assert!(!PreferenceKey::PreferredQuality.is_valid("0p"));
+        assert!(!PreferenceKey::PreferredQuality.is_valid("hd"));
+        assert!(PreferenceKey::Language.is_valid("de"));
+        assert!(PreferenceKey::Language.is_valid("pt-BR"));
+        assert!(!PreferenceKey::Language.is_valid("DE"));
+        assert!(!PreferenceKey::Language.is_valid("de-"));
+        assert!(PreferenceKey::Theme.is_valid("dark"));
+        assert!(!PreferenceKey::Theme.is_valid("blue"));
+    }
+}
@@ -1,17 +1,33 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use juniper::{graphql_object, GraphQLEnum};
 use postgres_types::{FromSql, ToSql};
 
+use std::{collections::HashMap, time::Duration};
+
 use crate::{
-    api::{Context, Id, err::ApiResult, Node, NodeValue},
-    db::types::Key,
+    api::{Context, Id, cache::CacheTag, err::{ApiErrorKind, ApiResult}, Node, NodeValue},
+    db::{types::Key, util::define_columns},
+    http::realm_icon,
     prelude::*,
 };
-use super::block::BlockValue;
+use super::{
+    block::BlockValue,
+    event::{CaptionCoverage, Event, EventStats},
+    moderator_delegation::ModeratorDelegation,
+};
 
 
 mod mutations;
+mod page_data;
+mod permissions;
+mod tree;
+mod views;
 
 pub(crate) use mutations::{ChildIndex, NewRealm, RemovedRealm, UpdateRealm};
+pub(crate) use page_data::PageData;
+pub(crate) use permissions::{PermissionChanges, PermissionChangeSummary};
+pub(crate) use tree::RealmTree;
+pub(crate) use views::{RealmViews, ViewsInterval};
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
@@ -25,6 +41,70 @@ pub(crate) enum RealmOrder {
     AlphabeticDesc,
 }
 
+/// How densely the content of a realm page is listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "realm_list_density")]
+pub(crate) enum RealmListDensity {
+    #[postgres(name = "comfortable")]
+    Comfortable,
+    #[postgres(name = "compact")]
+    Compact,
+}
+
+/// Style of the banner showing the realm's name at the top of its page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "realm_banner_style")]
+pub(crate) enum RealmBannerStyle {
+    #[postgres(name = "default")]
+    Default,
+    #[postgres(name = "dark")]
+    Dark,
+}
+
+/// Icons that can be shown for a realm, e.g. in the navigation. Rendering
+/// them is the responsibility of the frontend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSql, ToSql, GraphQLEnum)]
+#[postgres(name = "realm_icon")]
+pub(crate) enum RealmIcon {
+    #[postgres(name = "award")]
+    Award,
+    #[postgres(name = "book")]
+    Book,
+    #[postgres(name = "briefcase")]
+    Briefcase,
+    #[postgres(name = "calendar")]
+    Calendar,
+    #[postgres(name = "camera")]
+    Camera,
+    #[postgres(name = "code")]
+    Code,
+    #[postgres(name = "compass")]
+    Compass,
+    #[postgres(name = "cpu")]
+    Cpu,
+    #[postgres(name = "feather")]
+    Feather,
+    #[postgres(name = "film")]
+    Film,
+    #[postgres(name = "globe")]
+    Globe,
+    #[postgres(name = "heart")]
+    Heart,
+    #[postgres(name = "map")]
+    Map,
+    #[postgres(name = "mic")]
+    Mic,
+    #[postgres(name = "monitor")]
+    Monitor,
+    #[postgres(name = "music")]
+    Music,
+    #[postgres(name = "star")]
+    Star,
+    #[postgres(name = "users")]
+    Users,
+}
+
+#[derive(Clone)]
 pub(crate) struct Realm {
     pub(crate) key: Key,
     parent_key: Option<Key>,
@@ -32,22 +112,57 @@ pub(crate) struct Realm {
     full_path: String,
     index: i32,
     child_order: RealmOrder,
+    locale: Option<String>,
+    noindex: bool,
+    member_roles: Vec<String>,
+    list_density: RealmListDensity,
+    show_children: bool,
+    banner_style: RealmBannerStyle,
+    locked: bool,
+    icon: Option<RealmIcon>,
+    icon_uploaded: Option<DateTime<Utc>>,
+    accent_color: Option<String>,
+    hidden_from_navigation: bool,
+    hidden_from_search: bool,
 }
 
 impl Realm {
     pub(crate) async fn root(context: &Context) -> ApiResult<Self> {
-        let row = context.db
-            .query_one("select child_order from realms where id = 0", &[])
-            .await?;
-
-        Ok(Self {
-            key: Key(0),
-            parent_key: None,
-            name: String::new(),
-            full_path: String::new(),
-            index: 0,
-            child_order: row.get(0),
-        })
+        // Loaded for every request of the home page.
+        let load = async {
+            let row = context.db
+                .query_one(
+                    "select child_order, locale, noindex, member_roles, \
+                        list_density, show_children, banner_style, locked, \
+                        icon, icon_uploaded, accent_color \
+                        from realms where id = 0",
+                    &[],
+                )
+                .await?;
+
+            Ok(Self {
+                key: Key(0),
+                parent_key: None,
+                name: String::new(),
+                full_path: String::new(),
+                index: 0,
+                child_order: row.get(0),
+                locale: row.get(1),
+                noindex: row.get(2),
+                member_roles: row.get(3),
+                list_density: row.get(4),
+                show_children: row.get(5),
+                banner_style: row.get(6),
+                locked: row.get(7),
+                icon: row.get(8),
+                icon_uploaded: row.get(9),
+                accent_color: row.get(10),
+                hidden_from_navigation: false,
+                hidden_from_search: false,
+            })
+        };
+        let ttl = Duration::from_secs(300);
+        context.cache.get_or_load(CacheTag::Realms, "root-realm".into(), ttl, load).await
     }
 
     pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
@@ -63,43 +178,148 @@ impl Realm {
             return Ok(Some(Self::root(context).await?));
         }
 
-        let result = context.db
-            .query_opt(
-                "select parent, name, full_path, index, child_order \
-                    from realms \
-                    where id = $1",
-                &[&key],
-            )
+        let query = format!("select {} from realms where id = $1", cols::COL_NAMES);
+        context.db
+            .query_opt(&query, &[&key])
             .await?
-            .map(|row| Self {
-                key,
-                parent_key: Some(row.get(0)),
-                name: row.get(1),
-                full_path: row.get(2),
-                index: row.get(3),
-                child_order: row.get(4),
-            });
+            .map(Self::from_row)
+            .pipe(Ok)
+    }
 
-        Ok(result)
+    /// Loads the realms returned by `hosts`, an SQL table expression with the
+    /// columns of `event_host_realms`, which can refer to `$1`. The first
+    /// realm is the canonical place to show the item (see
+    /// `http.canonical_realm`).
+    pub(crate) async fn load_hosts(
+        hosts: &str,
+        key: Key,
+        context: &Context,
+    ) -> ApiResult<Vec<Self>> {
+        let query = format!(
+            "select {} from {hosts} as hosts \
+                inner join realms on realms.id = hosts.realm \
+                order by {}",
+            Self::col_names("realms"),
+            context.config.http.canonical_realm.order_by(),
+        );
+        context.db.query_mapped(&query, dbargs![&key], Self::from_row)
+            .await?
+            .pipe(Ok)
     }
 
+    /// Like `load_hosts` with `event_host_realms`, but for many events at
+    /// once. Events without host realms are missing from the returned map.
+    pub(crate) async fn load_event_hosts(
+        keys: &[Key],
+        context: &Context,
+    ) -> ApiResult<HashMap<Key, Vec<Self>>> {
+        let query = format!(
+            "select {}, events.id from unnest($1::bigint[]) as events(id) \
+                cross join lateral event_host_realms(events.id) as hosts \
+                inner join realms on realms.id = hosts.realm \
+                order by {}",
+            Self::col_names("realms"),
+            context.config.http.canonical_realm.order_by(),
+        );
+        let rows = context.db
+            .query_mapped(&query, dbargs![&keys], |row| {
+                (row.get::<_, Key>(cols::COLUMNS.len()), Self::from_row(row))
+            })
+            .await?;
+
+        let mut out = HashMap::<_, Vec<_>>::new();
+        for (event, realm) in rows {
+            out.entry(event).or_default().push(realm);
+        }
+        Ok(out)
+    }
+
+    /// Like `cols::COL_NAMES`, but each column is qualified with `from`.
     pub(crate) fn col_names(from: &str) -> String {
-        ["id", "parent", "name", "full_path", "index", "child_order"]
+        cols::COLUMNS.iter()
             .map(|column| format!("{}.{}", from, column))
+            .collect::<Vec<_>>()
             .join(",")
     }
 
     pub(crate) fn from_row(row: tokio_postgres::Row) -> Self {
         Self {
-            key: row.get(0),
-            parent_key: row.get(1),
-            name: row.get(2),
-            full_path: row.get(3),
-            index: row.get(4),
-            child_order: row.get(5),
+            key: cols::key(&row),
+            parent_key: cols::parent_key(&row),
+            name: cols::name(&row),
+            full_path: cols::full_path(&row),
+            index: cols::index(&row),
+            child_order: cols::child_order(&row),
+            locale: cols::locale(&row),
+            noindex: cols::noindex(&row),
+            member_roles: cols::member_roles(&row),
+            list_density: cols::list_density(&row),
+            show_children: cols::show_children(&row),
+            banner_style: cols::banner_style(&row),
+            locked: cols::locked(&row),
+            icon: cols::icon(&row),
+            icon_uploaded: cols::icon_uploaded(&row),
+            accent_color: cols::accent_color(&row),
+            hidden_from_navigation: cols::hidden_from_navigation(&row),
+            hidden_from_search: cols::hidden_from_search(&row),
         }
     }
 
+    /// See `Realm.canCurrentUserEdit`.
+    async fn is_editable(&self, context: &Context) -> ApiResult<bool> {
+        match context.require_realm_moderator(self.key).await {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(e.kind, ApiErrorKind::NotAuthorized) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// See `Realm.ancestors`.
+    pub(crate) async fn load_ancestors(&self, context: &Context) -> ApiResult<Vec<Self>> {
+        let query = format!(
+            "select {} from ancestors_of_realm($1) where height <> 0 and id <> 0",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_mapped(&query, dbargs![&self.key], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// See `Realm.children`.
+    pub(crate) async fn load_children(&self, context: &Context) -> ApiResult<Vec<Self>> {
+        // This is requested for the navigation on every realm page.
+        let load = async {
+            let collation = context.config.general.collation();
+            let query = format!(
+                "select {} from realms where parent = $1 order by {}",
+                cols::COL_NAMES,
+                match self.child_order {
+                    RealmOrder::ByIndex => "index".into(),
+                    RealmOrder::AlphabeticAsc => format!("name collate {collation} asc, id"),
+                    RealmOrder::AlphabeticDesc => format!("name collate {collation} desc, id"),
+                },
+            );
+            context.db
+                .query_mapped(&query, dbargs![&self.key], Self::from_row)
+                .await?
+                .pipe(Ok)
+        };
+        let key = format!("realm-children:{}", self.key.0);
+        let children: Vec<Self> = context.cache
+            .get_or_load(CacheTag::Realms, key, Duration::from_secs(300), load)
+            .await?;
+
+        // Hidden realms are only listed for users who can edit this realm,
+        // so that they can still manage them.
+        if children.iter().any(|child| child.hidden_from_navigation)
+            && !self.is_editable(context).await?
+        {
+            return Ok(children.into_iter().filter(|child| !child.hidden_from_navigation).collect());
+        }
+        Ok(children)
+    }
+
     pub(crate) async fn load_by_path(mut path: String, context: &Context) -> ApiResult<Option<Self>> {
         // Normalize path: strip optional trailing slash.
         if path.ends_with('/') {
@@ -116,24 +336,49 @@ impl Realm {
             return Ok(None);
         }
 
-        let result = context.db
-            .query_opt(
-                "select id, parent, name, index, child_order \
-                    from realms \
-                    where full_path = $1",
-                &[&path],
-            )
+        let query = format!("select {} from realms where full_path = $1", cols::COL_NAMES);
+        context.db
+            .query_opt(&query, &[&path])
             .await?
-            .map(|row| Self {
-                key: row.get(0),
-                parent_key: Some(row.get(1)),
-                name: row.get(2),
-                full_path: path,
-                index: row.get(3),
-                child_order: row.get(4),
-            });
+            .map(Self::from_row)
+            .pipe(Ok)
+    }
+
+    /// Aggregates the events counted by `captionCoverage`.
+    async fn event_stats(&self, context: &Context) -> ApiResult<EventStats> {
+        let member_access = BlockValue::member_access(self.key, context).await?;
+        let key = format!("realm:{}:{member_access}", self.key.0);
+        let condition = "((series in (select series_id from blocks where realm_id = $1) \
+                or id in (select video_id from blocks where realm_id = $1)) \
+                and (($2 and not retracted) or read_roles && $3)) \
+            or (creators && array(select creator from blocks \
+                    where realm_id = $1 and creator is not null) \
+                and read_roles && $3)";
+        let args = dbargs![&self.key, &member_access, &context.user.roles()];
+        EventStats::load(key, condition, &args, context).await
+    }
+}
 
-        Ok(result)
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        parent_key: Option<Key> = "parent",
+        name: String = "name",
+        full_path: String = "full_path",
+        index: i32 = "index",
+        child_order: RealmOrder = "child_order",
+        locale: Option<String> = "locale",
+        noindex: bool = "noindex",
+        member_roles: Vec<String> = "member_roles",
+        list_density: RealmListDensity = "list_density",
+        show_children: bool = "show_children",
+        banner_style: RealmBannerStyle = "banner_style",
+        locked: bool = "locked",
+        icon: Option<RealmIcon> = "icon",
+        icon_uploaded: Option<DateTime<Utc>> = "icon_uploaded",
+        accent_color: Option<String> = "accent_color",
+        hidden_from_navigation: bool = "hidden_from_navigation",
+        hidden_from_search: bool = "hidden_from_search",
     }
 }
 
@@ -168,6 +413,106 @@ impl Realm {
         self.child_order
     }
 
+    /// The locale (e.g. `fr` or `de-CH`) set for this realm. Not inherited,
+    /// see `effectiveLocale`.
+    fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// The locale that is used for pages of this realm: its own or that of
+    /// the nearest ancestor that has one. `null` if no such realm exists, in
+    /// which case the user's language is used.
+    async fn effective_locale(&self, context: &Context) -> ApiResult<Option<String>> {
+        if self.locale.is_some() {
+            return Ok(self.locale.clone());
+        }
+
+        context.db
+            .query_opt(
+                "select locale from ancestors_of_realm($1) \
+                    where locale is not null \
+                    order by height \
+                    limit 1",
+                &[&self.key],
+            )
+            .await?
+            .map(|row| row.get(0))
+            .pipe(Ok)
+    }
+
+    /// Whether this realm and all its descendants should be excluded from
+    /// search engines.
+    fn noindex(&self) -> bool {
+        self.noindex
+    }
+
+    /// Users with any of these roles can see all videos shown on pages of
+    /// this realm and its descendants via video or series blocks, regardless
+    /// of the videos' read roles. This only applies to the realm pages and
+    /// the video pages opened from them: search, thumbnails served by Tobira
+    /// (`http.thumbnails`), series downloads and the video's own URL
+    /// (`/!v/...`) still only use the read roles. Only moderators can see
+    /// this.
+    fn member_roles(&self, context: &Context) -> ApiResult<&Vec<String>> {
+        context.require_moderator()?;
+        Ok(&self.member_roles)
+    }
+
+    /// How densely the blocks of this realm should list their content. That's
+    /// the responsibility of the frontend.
+    fn list_density(&self) -> RealmListDensity {
+        self.list_density
+    }
+
+    /// Whether the children of this realm should be shown on its page (e.g.
+    /// in the navigation list).
+    fn show_children(&self) -> bool {
+        self.show_children
+    }
+
+    /// Style of the banner at the top of this realm's page.
+    fn banner_style(&self) -> RealmBannerStyle {
+        self.banner_style
+    }
+
+    /// Whether this realm is locked. The structure and blocks of a locked
+    /// realm and all its descendants can only be changed by admins. This
+    /// does not say whether an ancestor is locked, see `canCurrentUserEdit`.
+    fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// One of the curated icons to show for this realm, e.g. in the
+    /// navigation and breadcrumbs. `null` if there is none or an image was
+    /// uploaded instead (see `iconUrl`).
+    fn icon(&self) -> Option<RealmIcon> {
+        self.icon
+    }
+
+    /// URL of the image uploaded as icon of this realm (via
+    /// `POST /~realm-icon/<id>`), if any.
+    fn icon_url(&self) -> Option<String> {
+        self.icon_uploaded.map(|uploaded| realm_icon::icon_url(self.key, uploaded))
+    }
+
+    /// Color (`#rrggbb`) to highlight this realm with, e.g. in the
+    /// navigation.
+    fn accent_color(&self) -> Option<&str> {
+        self.accent_color.as_deref()
+    }
+
+    /// Whether this realm is left out of the navigation of its parent (see
+    /// `children`), e.g. for unlisted pages. It can still be accessed by its
+    /// URL. Clients of `realmTree` have to check this themselves.
+    fn hidden_from_navigation(&self) -> bool {
+        self.hidden_from_navigation
+    }
+
+    /// Whether this realm is left out of the search results.
+    fn hidden_from_search(&self) -> bool {
+        self.hidden_from_search
+    }
+
     /// Returns the full path of this realm. `"/"` for the root realm. For
     /// non-root realms, the path always starts with `/` and never has a
     /// trailing `/`.
@@ -187,58 +532,15 @@ impl Realm {
     /// (excluding both, the root realm and this realm). It starts with a
     /// direct child of the root and ends with the parent of `self`.
     async fn ancestors(&self, context: &Context) -> ApiResult<Vec<Realm>> {
-        let result = context.db
-            .query_raw(
-                "select id, parent, name, full_path, index, child_order \
-                    from ancestors_of_realm($1) \
-                    where height <> 0 and id <> 0",
-                &[&self.key],
-            )
-            .await?
-            .map_ok(|row| {
-                Self {
-                    key: row.get(0),
-                    parent_key: Some(row.get(1)),
-                    name: row.get(2),
-                    full_path: row.get(3),
-                    index: row.get(4),
-                    child_order: row.get(5),
-                }
-            })
-            .try_collect()
-            .await?;
-
-        Ok(result)
+        self.load_ancestors(context).await
     }
 
-    /// Returns all immediate children of this realm. The children are always
-    /// ordered by the internal index. If `childOrder` returns an ordering
-    /// different from `BY_INDEX`, the frontend is supposed to sort the
-    /// children.
+    /// Returns all immediate children of this realm, ordered as specified by
+    /// `childOrder`. Names are compared according to `general.sort_locale`.
+    /// Children hidden from the navigation are only included if the current
+    /// user can edit this realm.
     async fn children(&self, context: &Context) -> ApiResult<Vec<Self>> {
-        let result = context.db
-            .query_raw(
-                "select id, name, full_path, index, child_order \
-                    from realms \
-                    where parent = $1 \
-                    order by index",
-                &[&self.key],
-            )
-            .await?
-            .map_ok(|row| {
-                Self {
-                    key: row.get(0),
-                    parent_key: Some(self.key),
-                    name: row.get(1),
-                    full_path: row.get(2),
-                    index: row.get(3),
-                    child_order: row.get(4),
-                }
-            })
-            .try_collect()
-            .await?;
-
-        Ok(result)
+        self.load_children(context).await
     }
 
     /// Returns the (content) blocks of this realm.
@@ -250,6 +552,67 @@ impl Realm {
         BlockValue::load_for_realm(self.key, context).await
     }
 
+    /// All values of the field configured as `general.event_filter` of
+    /// events shown in series and creator blocks of this realm that the
+    /// current user can read, sorted. Useful to show a switcher that passes
+    /// the selected value as `filter` to the `events` of all blocks.
+    async fn event_filter_values(&self, context: &Context) -> ApiResult<Vec<String>> {
+        let member_access = BlockValue::member_access(self.key, context).await?;
+        let condition = "(series in (select series_id from blocks where realm_id = $3) \
+                and (($4 and not retracted) or read_roles && $5)) \
+            or (creators && array(select creator from blocks \
+                    where realm_id = $3 and creator is not null) \
+                and read_roles && $5)";
+        let args = dbargs![&self.key, &member_access, &context.user.roles()];
+        Event::load_filter_values(condition, &args, context).await
+    }
+
+    /// How many of the events shown in blocks of this realm that the current
+    /// user can read have captions. Each event is only counted once.
+    async fn caption_coverage(&self, context: &Context) -> ApiResult<CaptionCoverage> {
+        let member_access = BlockValue::member_access(self.key, context).await?;
+        let condition = "((series in (select series_id from blocks where realm_id = $1) \
+                or id in (select video_id from blocks where realm_id = $1)) \
+                and (($2 and not retracted) or read_roles && $3)) \
+            or (creators && array(select creator from blocks \
+                    where realm_id = $1 and creator is not null) \
+                and read_roles && $3)";
+        let args = dbargs![&self.key, &member_access, &context.user.roles()];
+        CaptionCoverage::load(condition, &args, context).await
+    }
+
+    /// Number of events shown in blocks of this realm that the current user
+    /// can read. Each event is only counted once. Might be outdated by up to
+    /// a minute after blocks of this realm were changed.
+    async fn event_count(&self, context: &Context) -> ApiResult<i32> {
+        Ok(self.event_stats(context).await?.count)
+    }
+
+    /// Sum of the (trimmed) durations of these events in seconds.
+    async fn total_duration(&self, context: &Context) -> ApiResult<f64> {
+        Ok(self.event_stats(context).await?.total_duration)
+    }
+
+    /// When the newest of these events was created, `null` if there are none.
+    async fn latest_event_date(&self, context: &Context) -> ApiResult<Option<DateTime<Utc>>> {
+        Ok(self.event_stats(context).await?.latest)
+    }
+
+    /// Page views of this realm and its subtree per interval between `from`
+    /// and `to` (both inclusive, at most three years), including intervals
+    /// without views. Only moderators of this realm can see this. `null` if
+    /// statistics are disabled (see `http.playback_stats`).
+    #[graphql(arguments(interval(default = ViewsInterval::Day)))]
+    async fn views(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        interval: ViewsInterval,
+        context: &Context,
+    ) -> ApiResult<Option<Vec<RealmViews>>> {
+        RealmViews::load(self.key, from, to, interval, context).await
+    }
+
     /// Returns the number of realms that are descendants of this one
     /// (excluding this one). Returns a number ≥ 0.
     async fn number_of_descendants(&self, context: &Context) -> ApiResult<i32> {
@@ -264,9 +627,20 @@ impl Realm {
         Ok(count.try_into().expect("number of descendants overflows i32"))
     }
 
-    fn can_current_user_edit(&self, context: &Context) -> bool {
-        // TODO: at some point, we want ACLs per realm
-        context.user.is_moderator(&context.config.auth)
+    /// Whether the current user is a moderator or moderates this realm by
+    /// delegation (see `moderatorDelegations`), and the realm is not locked
+    /// for them (see `locked`).
+    async fn can_current_user_edit(&self, context: &Context) -> ApiResult<bool> {
+        self.is_editable(context).await
+    }
+
+    /// Active moderator delegations for this realm, not including those of
+    /// ancestors. Only moderators can see this.
+    async fn moderator_delegations(
+        &self,
+        context: &Context,
+    ) -> ApiResult<Vec<ModeratorDelegation>> {
+        ModeratorDelegation::load_for_realm(self.key, context).await
     }
 
     /// Returns `true` if this realm somehow references the given node via
@@ -281,12 +655,7 @@ impl Realm {
     async fn references(&self, id: Id, context: &Context) -> ApiResult<bool> {
         if let Some(event_key) = id.key_for(Id::EVENT_KIND) {
             let query = "select exists(\
-                select 1 \
-                from blocks \
-                where realm_id = $1 and ( \
-                    video_id = $2 or \
-                    series_id = (select series from events where id = $2) \
-                )\
+                select 1 from event_host_realms($2) where realm = $1\
             )";
             context.db.query_one(&query, &[&self.key, &event_key])
                 .await?
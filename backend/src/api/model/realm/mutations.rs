@@ -1,21 +1,24 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::{
-    api::{Context, Id, err::{ApiResult, invalid_input}},
+    api::{Context, Id, cache::CacheTag, err::{ApiResult, invalid_input}},
+    auth::{AuthToken, ROLE_ANONYMOUS, ROLE_USER},
     db::types::Key,
+    outbox,
     prelude::*,
     search,
 };
-use super::{Realm, RealmOrder};
+use super::{Realm, RealmBannerStyle, RealmIcon, RealmListDensity, RealmOrder};
 
 
 impl Realm {
     pub(crate) async fn add(realm: NewRealm, context: &Context) -> ApiResult<Realm> {
-        let db = context.db(context.require_moderator()?);
+        let parent_key = id_to_key(realm.parent, "`parent`")?;
+        let db = context.db(context.require_realm_moderator(parent_key).await?);
+        context.cache.modified(CacheTag::Realms);
 
         // TODO: validate input
 
-        let parent_key = id_to_key(realm.parent, "`parent`")?;
         let key: Key = db
             .query_one(
                 "insert into realms (parent, name, path_segment) \
@@ -27,7 +30,9 @@ impl Realm {
             .get(0);
         db.queue_for_reindex(search::IndexItemKind::Realm, key).await?;
 
-        Self::load_by_key(key, context).await.map(Option::unwrap)
+        let realm = Self::load_by_key(key, context).await?.unwrap();
+        realm.push_to_outbox("realm-added", context).await?;
+        Ok(realm)
     }
 
     pub(crate) async fn set_child_order(
@@ -42,11 +47,12 @@ impl Realm {
         // frontend error or the DB has changed since the user opened the
         // page. TODO: The latter case we should communicate to the user somehow.
 
-        let db = context.db(context.require_moderator()?);
-
         // Verify and convert arguments.
         let parent_key = id_to_key(parent, "`parent`")?;
 
+        let db = context.db(context.require_realm_moderator(parent_key).await?);
+        context.cache.modified(CacheTag::Realms);
+
         if let Some(child_indices) = child_indices {
             if child_order != RealmOrder::ByIndex {
                 return Err(invalid_input!(
@@ -78,10 +84,11 @@ impl Realm {
 
             // Retrieve the current children of the given realm
             let current_children: Vec<(_, i32)> = db
-                .query_raw("select id, index from realms where parent = $1", [parent_key])
-                .await?
-                .map_ok(|row| (row.get(0), row.get(1)))
-                .try_collect()
+                .query_mapped(
+                    "select id, index from realms where parent = $1",
+                    [parent_key],
+                    |row| (row.get(0), row.get(1)),
+                )
                 .await?;
 
             // Make sure the list of given children matches the current ones.
@@ -141,43 +148,143 @@ impl Realm {
     pub(crate) async fn update(id: Id, set: UpdateRealm, context: &Context) -> ApiResult<Realm> {
         // TODO: validate input
 
-        let db = context.db(context.require_moderator()?);
-
         let key = id_to_key(id, "`id`")?;
         let parent_key = set.parent.map(|parent| id_to_key(parent, "`parent`")).transpose()?;
 
+        let db = context.db(context.require_realm_moderator(key).await?);
+        context.cache.modified(CacheTag::Realms);
+
+        // Member roles grant access to videos, so only moderators decide
+        // about them, not users moderating this realm by delegation. Moving
+        // or renaming the realm changes the children of its current (and new)
+        // parent, so the user has to be allowed to moderate those, too.
+        if set.member_roles.is_some() {
+            context.require_moderator()?;
+        }
+        if set.locked.is_some() {
+            context.require_admin()?;
+        }
+        if parent_key.is_some() || set.path_segment.is_some() {
+            let old_parent = Self::parent_of(key, context).await?;
+            for realm in old_parent.into_iter().chain(parent_key) {
+                context.require_realm_moderator(realm).await?;
+            }
+        }
+
+        let hides = set.hidden_from_navigation == Some(true) || set.hidden_from_search == Some(true);
+        if key.0 == 0 && hides {
+            return Err(invalid_input!("the root realm cannot be hidden"));
+        }
+
+        let locale = set.locale.explicit();
+        if let Some(Some(locale)) = &locale {
+            if !is_valid_locale(locale) {
+                return Err(invalid_input!("`locale` is not a valid language tag (e.g. 'fr-CH')"));
+            }
+        }
+        let member_roles = set.member_roles
+            .map(|roles| normalize_member_roles(roles, "`memberRoles`"))
+            .transpose()?;
+        let icon = set.icon.explicit();
+        let accent_color = set.accent_color.explicit()
+            .map(|color| color.map(|color| normalize_accent_color(&color)).transpose())
+            .transpose()?;
+
         let affected_rows = db
             .execute(
                 "update realms set \
                     parent = coalesce($2, parent), \
                     name = coalesce($3, name), \
-                    path_segment = coalesce($4, path_segment) \
+                    path_segment = coalesce($4, path_segment), \
+                    locale = case when $5 then $6 else locale end, \
+                    noindex = coalesce($7, noindex), \
+                    member_roles = coalesce($8, member_roles), \
+                    list_density = coalesce($9, list_density), \
+                    show_children = coalesce($10, show_children), \
+                    banner_style = coalesce($11, banner_style), \
+                    locked = coalesce($12, locked), \
+                    icon = case when $13 then $14 else icon end, \
+                    icon_uploaded = case when $13 then null else icon_uploaded end, \
+                    accent_color = case when $15 then $16 else accent_color end, \
+                    hidden_from_navigation = coalesce($17, hidden_from_navigation), \
+                    hidden_from_search = coalesce($18, hidden_from_search) \
                     where id = $1",
-                &[&key, &parent_key, &set.name, &set.path_segment],
+                &[
+                    &key,
+                    &parent_key,
+                    &set.name,
+                    &set.path_segment,
+                    &locale.is_some(),
+                    &locale.flatten(),
+                    &set.noindex,
+                    &member_roles,
+                    &set.list_density,
+                    &set.show_children,
+                    &set.banner_style,
+                    &set.locked,
+                    &icon.is_some(),
+                    &icon.flatten(),
+                    &accent_color.is_some(),
+                    &accent_color.flatten(),
+                    &set.hidden_from_navigation,
+                    &set.hidden_from_search,
+                ],
             )
             .await?;
 
         if affected_rows != 1 {
             return Err(invalid_input!("`id` does not refer to an existing realm"));
         }
+        if icon.is_some() {
+            db.execute("delete from realm_icons where realm = $1", &[&key]).await?;
+        }
 
         db.queue_for_reindex(search::IndexItemKind::Realm, key).await?;
-        Self::load_by_key(key, context).await.map(Option::unwrap)
+        let realm = Self::load_by_key(key, context).await?.unwrap();
+        realm.push_to_outbox("realm-changed", context).await?;
+        Ok(realm)
     }
 
     pub(crate) async fn remove(id: Id, context: &Context) -> ApiResult<RemovedRealm> {
-        let db = context.db(context.require_moderator()?);
-
+        // Removing a realm modifies its parent, so moderating the realm
+        // itself by delegation is not sufficient.
         let key = id_to_key(id, "`id`")?;
+        let token = match Self::parent_of(key, context).await? {
+            Some(parent) => context.require_realm_moderator(parent).await?,
+            None => context.require_moderator()?,
+        };
+        if context.config.auth.deletion_approval {
+            return Err(invalid_input!(
+                key = "mutation.approval-required",
+                "removing realms requires approval by a second moderator: \
+                    use `requestRealmRemoval` instead",
+            ));
+        }
+
+        Self::remove_unchecked(key, token, context).await
+    }
+
+    /// Removes the realm without checking whether that has to be approved.
+    /// The caller has to make sure the user is allowed to remove it.
+    pub(crate) async fn remove_unchecked(
+        key: Key,
+        token: AuthToken,
+        context: &Context,
+    ) -> ApiResult<RemovedRealm> {
+        let db = context.db(token);
+        context.cache.modified(CacheTag::Realms);
+
         if key.0 == 0 {
             return Err(invalid_input!("Cannot remove the root realm"));
         }
+        context.require_unlocked_subtree(key).await?;
 
         let realm = Self::load_by_key(key, context).await?
             .ok_or_else(|| invalid_input!("`id` does not refer to an existing realm"))?;
 
         db.execute("delete from realms where id = $1", &[&key]).await?;
         db.queue_for_reindex(search::IndexItemKind::Realm, key).await?;
+        realm.push_to_outbox("realm-removed", context).await?;
 
         // We checked above that `realm` is not the root realm, so we can unwrap.
         let parent = Self::load_by_key(realm.parent_key.expect("missing parent"), context)
@@ -186,14 +293,80 @@ impl Realm {
 
         Ok(RemovedRealm { parent })
     }
+
+    /// Queues a webhook message about this realm (see `outbox.rs`). Only
+    /// called after the user was authorized to make the change.
+    pub(super) async fn push_to_outbox(&self, kind: &str, context: &Context) -> ApiResult<()> {
+        let path = if self.full_path.is_empty() { "/" } else { &self.full_path };
+        let payload = serde_json::json!({
+            "id": Id::realm(self.key).to_string(),
+            "path": path,
+            "name": self.name,
+        });
+        outbox::push(&context.db, &context.config, kind, payload).await?;
+        Ok(())
+    }
+
+    /// Returns the key of the parent of the given realm, `None` for the root
+    /// realm or if the realm does not exist.
+    async fn parent_of(key: Key, context: &Context) -> ApiResult<Option<Key>> {
+        context.db
+            .query_opt("select parent from realms where id = $1", &[&key])
+            .await?
+            .and_then(|row| row.get::<_, Option<Key>>(0))
+            .pipe(Ok)
+    }
 }
 
 /// Makes sure the ID refers to a realm and returns its key.
-fn id_to_key(id: Id, name: &str) -> ApiResult<Key> {
+pub(super) fn id_to_key(id: Id, name: &str) -> ApiResult<Key> {
     id.key_for(Id::REALM_KIND)
         .ok_or_else(|| invalid_input!("{} does not refer to a realm", name))
 }
 
+/// Trims the given member roles and makes sure they are valid. `name` is used
+/// in error messages.
+pub(super) fn normalize_member_roles(roles: Vec<String>, name: &str) -> ApiResult<Vec<String>> {
+    let roles = roles.into_iter().map(|role| role.trim().to_owned()).collect::<Vec<_>>();
+    if roles.iter().any(|role| role.is_empty()) {
+        return Err(invalid_input!("{} must not contain empty roles", name));
+    }
+    // These would make all videos shown in the realm public (or visible to
+    // all logged in users), regardless of their ACL.
+    if roles.iter().any(|role| role == ROLE_ANONYMOUS || role == ROLE_USER) {
+        return Err(invalid_input!(
+            "{} must not contain '{}' or '{}'",
+            name,
+            ROLE_ANONYMOUS,
+            ROLE_USER,
+        ));
+    }
+
+    Ok(roles)
+}
+
+/// Checks the rough format of BCP 47 language tags, like the DB constraint.
+fn is_valid_locale(locale: &str) -> bool {
+    let mut parts = locale.split('-');
+    let language = parts.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| {
+            (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// Checks that `color` has the form `#rrggbb` and returns it in lowercase.
+fn normalize_accent_color(color: &str) -> ApiResult<String> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(invalid_input!("`accentColor` is not a valid color (e.g. '#1a7f37')"));
+    }
+    Ok(color.to_ascii_lowercase())
+}
+
 #[derive(juniper::GraphQLInputObject)]
 pub(crate) struct ChildIndex {
     id: Id,
@@ -205,6 +378,35 @@ pub(crate) struct UpdateRealm {
     parent: Option<Id>,
     name: Option<String>,
     path_segment: Option<String>,
+    /// Locale for this realm and its descendants, e.g. `fr`. Set to `null`
+    /// to remove it. Not changed if omitted.
+    locale: juniper::Nullable<String>,
+    /// Whether to exclude this realm and its descendants from search engines.
+    noindex: Option<bool>,
+    /// Users with any of these roles can see all videos shown on pages of
+    /// this realm and its descendants (see `Realm.memberRoles`).
+    /// `ROLE_ANONYMOUS` and `ROLE_USER` are not allowed.
+    member_roles: Option<Vec<String>>,
+    /// Display options for this realm's page (see the `Realm` fields of the
+    /// same names). Not changed if omitted.
+    list_density: Option<RealmListDensity>,
+    show_children: Option<bool>,
+    banner_style: Option<RealmBannerStyle>,
+    /// Whether to lock this realm (see `Realm.locked`). Only admins can
+    /// change this.
+    locked: Option<bool>,
+    /// One of the curated icons for this realm. Set to `null` to remove it.
+    /// Both also remove an uploaded icon (see `Realm.iconUrl`). Not changed
+    /// if omitted.
+    icon: juniper::Nullable<RealmIcon>,
+    /// Accent color of this realm in the form `#rrggbb`. Set to `null` to
+    /// remove it. Not changed if omitted.
+    accent_color: juniper::Nullable<String>,
+    /// Whether to hide this realm from the navigation of its parent and from
+    /// the search (see the `Realm` fields of the same names). Not possible
+    /// for the root realm.
+    hidden_from_navigation: Option<bool>,
+    hidden_from_search: Option<bool>,
 }
 
 #[derive(juniper::GraphQLInputObject)]
@@ -0,0 +1,88 @@
+use juniper::{GraphQLObject, graphql_object};
+
+use crate::api::{Context, Id, err::ApiResult, model::{block::BlockValue, event::Event}};
+use super::Realm;
+
+
+/// Number of events per block included in `PageData.blockEvents`.
+const EVENTS_PER_BLOCK: i64 = 20;
+
+/// Everything needed to render a realm page, loaded with a few queries up
+/// front instead of one query per nested field.
+pub(crate) struct PageData {
+    realm: Realm,
+    ancestors: Vec<Realm>,
+    children: Vec<Realm>,
+    blocks: Vec<BlockValue>,
+    block_events: Vec<BlockEvents>,
+}
+
+/// The first events of a series, video or creator block.
+#[derive(GraphQLObject)]
+#[graphql(context = Context)]
+pub(crate) struct BlockEvents {
+    block: Id,
+    /// Ordered like in the block. For video blocks, this is empty if the
+    /// user cannot see the event.
+    events: Vec<Event>,
+    /// Whether the block has more events than included in `events`.
+    has_more: bool,
+}
+
+impl PageData {
+    pub(crate) async fn load(path: String, context: &Context) -> ApiResult<Option<Self>> {
+        let Some(realm) = Realm::load_by_path(path, context).await? else {
+            return Ok(None);
+        };
+
+        let ancestors = realm.load_ancestors(context).await?;
+        let children = realm.load_children(context).await?;
+        let blocks = BlockValue::load_for_realm(realm.key, context).await?;
+
+        let member_access = BlockValue::member_access(realm.key, context).await?;
+        let events = Event::load_for_blocks(realm.key, member_access, EVENTS_PER_BLOCK, context)
+            .await?;
+        let mut block_events = Vec::<BlockEvents>::new();
+        for (block, total, event) in events {
+            let block = Id::block(block);
+            match block_events.iter_mut().find(|b| b.block == block) {
+                Some(entry) => entry.events.push(event),
+                None => block_events.push(BlockEvents {
+                    block,
+                    events: vec![event],
+                    has_more: total > EVENTS_PER_BLOCK,
+                }),
+            }
+        }
+
+        Ok(Some(Self { realm, ancestors, children, blocks, block_events }))
+    }
+}
+
+#[graphql_object(Context = Context)]
+impl PageData {
+    fn realm(&self) -> &Realm {
+        &self.realm
+    }
+
+    /// See `Realm.ancestors`.
+    fn ancestors(&self) -> &[Realm] {
+        &self.ancestors
+    }
+
+    /// See `Realm.children`.
+    fn children(&self) -> &[Realm] {
+        &self.children
+    }
+
+    /// See `Realm.blocks`.
+    fn blocks(&self) -> &[BlockValue] {
+        &self.blocks
+    }
+
+    /// The first events of all series, video and creator blocks that have
+    /// any events the user can see, in no particular order.
+    fn block_events(&self) -> &[BlockEvents] {
+        &self.block_events
+    }
+}
@@ -0,0 +1,164 @@
+use juniper::{GraphQLInputObject, GraphQLObject};
+
+use crate::{
+    api::{Context, Id, cache::CacheTag, err::{ApiResult, invalid_input}},
+    db::types::Key,
+    prelude::*,
+};
+use super::{
+    Realm,
+    cols,
+    mutations::{id_to_key, normalize_member_roles},
+};
+
+
+/// A change of the member roles (see `Realm.memberRoles`) of a realm and its
+/// descendants, see `applyPermissionChanges`.
+#[derive(GraphQLInputObject)]
+pub(crate) struct PermissionChanges {
+    /// Roles added to the member roles of the realm. Its descendants inherit
+    /// them, so they are not changed.
+    #[graphql(default)]
+    add_member_roles: Vec<String>,
+    /// Roles removed from the member roles of the realm and all its
+    /// descendants.
+    #[graphql(default)]
+    remove_member_roles: Vec<String>,
+    /// Whether to remove all member roles of the descendants, so that they
+    /// only have the ones inherited from the realm.
+    #[graphql(default)]
+    clear_overrides: bool,
+}
+
+/// How a permission change affects a realm and its descendants.
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct PermissionChangeSummary {
+    /// Number of realms in the subtree, including the realm itself.
+    realm_count: i32,
+    /// Number of realms whose member roles are changed.
+    affected_count: i32,
+    /// Descendants with member roles of their own that are changed, ordered
+    /// by path.
+    affected_overrides: Vec<Realm>,
+}
+
+struct Change {
+    realm: Realm,
+    new_roles: Vec<String>,
+}
+
+impl Realm {
+    /// Reports what `apply_permission_changes` would do, without
+    /// changing anything.
+    pub(crate) async fn preview_permission_changes(
+        id: Id,
+        changes: PermissionChanges,
+        context: &Context,
+    ) -> ApiResult<PermissionChangeSummary> {
+        let key = id_to_key(id, "`realm`")?;
+        let (realm_count, changes) = Self::plan_permission_changes(key, changes, context).await?;
+        Ok(PermissionChangeSummary::new(realm_count, key, changes))
+    }
+
+    /// Changes the member roles of a realm and its descendants in one
+    /// transaction.
+    pub(crate) async fn apply_permission_changes(
+        id: Id,
+        changes: PermissionChanges,
+        context: &Context,
+    ) -> ApiResult<PermissionChangeSummary> {
+        let key = id_to_key(id, "`realm`")?;
+        let (realm_count, mut changes) = Self::plan_permission_changes(key, changes, context).await?;
+        context.cache.modified(CacheTag::Realms);
+
+        for Change { realm, new_roles } in &mut changes {
+            context.db
+                .execute(
+                    "update realms set member_roles = $2 where id = $1",
+                    &[&realm.key, &*new_roles],
+                )
+                .await?;
+            realm.member_roles = std::mem::take(new_roles);
+            realm.push_to_outbox("realm-changed", context).await?;
+        }
+        info!(
+            "Changed member roles of {} of {} realms below {:?} (user: {})",
+            changes.len(),
+            realm_count,
+            key,
+            crate::auth::debug_log_username(&context.user),
+        );
+
+        Ok(PermissionChangeSummary::new(realm_count, key, changes))
+    }
+
+    /// Loads the subtree of the given realm and returns its size and the
+    /// realms whose member roles change, in order of their path.
+    async fn plan_permission_changes(
+        key: Key,
+        changes: PermissionChanges,
+        context: &Context,
+    ) -> ApiResult<(usize, Vec<Change>)> {
+        // Member roles grant access to videos, see `Realm::update`.
+        context.require_moderator()?;
+        context.require_unlocked_subtree(key).await?;
+        let add = normalize_member_roles(changes.add_member_roles, "`addMemberRoles`")?;
+        let remove = normalize_member_roles(changes.remove_member_roles, "`removeMemberRoles`")?;
+        if add.iter().any(|role| remove.contains(role)) {
+            return Err(invalid_input!(
+                "`addMemberRoles` and `removeMemberRoles` must not have roles in common",
+            ));
+        }
+
+        let query = format!(
+            "select {} from realms \
+                where id = $1 \
+                    or starts_with(full_path, (select full_path from realms where id = $1) || '/') \
+                order by full_path",
+            cols::COL_NAMES,
+        );
+        let realms = context.db.query_mapped(&query, dbargs![&key], Self::from_row).await?;
+        if realms.first().map(|realm| realm.key) != Some(key) {
+            return Err(invalid_input!("`realm` does not refer to an existing realm"));
+        }
+
+        let realm_count = realms.len();
+        let changes = realms.into_iter()
+            .filter_map(|realm| {
+                let mut new_roles = if realm.key != key && changes.clear_overrides {
+                    vec![]
+                } else {
+                    realm.member_roles.clone()
+                };
+                if realm.key == key {
+                    for role in &add {
+                        if !new_roles.contains(role) {
+                            new_roles.push(role.clone());
+                        }
+                    }
+                }
+                new_roles.retain(|role| !remove.contains(role));
+
+                (new_roles != realm.member_roles).then_some(Change { realm, new_roles })
+            })
+            .collect();
+
+        Ok((realm_count, changes))
+    }
+}
+
+impl PermissionChangeSummary {
+    fn new(realm_count: usize, root: Key, changes: Vec<Change>) -> Self {
+        let cast = |n: usize| n.try_into().unwrap_or(i32::MAX);
+        Self {
+            realm_count: cast(realm_count),
+            affected_count: cast(changes.len()),
+            // Descendants are only changed if they have member roles.
+            affected_overrides: changes.into_iter()
+                .map(|change| change.realm)
+                .filter(|realm| realm.key != root)
+                .collect(),
+        }
+    }
+}
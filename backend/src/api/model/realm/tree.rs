@@ -0,0 +1,81 @@
+use juniper::graphql_object;
+
+use crate::{
+    api::{Context, Id, err::{ApiResult, invalid_input}},
+    db::types::Key,
+    prelude::*,
+};
+use super::{Realm, cols};
+
+
+/// Changes of the realm tree since a version, see `Query.realmTree`.
+pub(crate) struct RealmTree {
+    version: i64,
+    complete: bool,
+    realms: Vec<Realm>,
+    removed: Vec<Key>,
+}
+
+impl RealmTree {
+    pub(crate) async fn load(since: Option<String>, context: &Context) -> ApiResult<Self> {
+        let since = since
+            .map(|since| since.parse::<i64>())
+            .transpose()
+            .map_err(|_| invalid_input!("`since` is not a version returned by `realmTree`"))?;
+
+        // Loading the version first is fine: changes made in between are
+        // just included in this and the next response.
+        let version = context.db
+            .query_one("select coalesce(max(version), 0) from realm_tree_changes", &[])
+            .await?
+            .get::<_, i64>(0);
+
+        let Some(since) = since else {
+            let query = format!("select {} from realms order by full_path", cols::COL_NAMES);
+            let realms = context.db.query_mapped(&query, dbargs![], Realm::from_row).await?;
+            return Ok(Self { version, complete: true, realms, removed: vec![] });
+        };
+
+        let query = format!(
+            "select {} from realms \
+                where id in (select realm from realm_tree_changes where version > $1) \
+                order by full_path",
+            cols::COL_NAMES,
+        );
+        let realms = context.db.query_mapped(&query, dbargs![&since], Realm::from_row).await?;
+        let removed = context.db
+            .query_mapped(
+                "select realm from realm_tree_changes where version > $1 and removed",
+                dbargs![&since],
+                |row| row.get::<_, Key>(0),
+            )
+            .await?;
+
+        Ok(Self { version, complete: false, realms, removed })
+    }
+}
+
+#[graphql_object(Context = Context)]
+impl RealmTree {
+    /// Version of the tree in this response. Pass it as `since` to get the
+    /// changes after this response.
+    fn version(&self) -> String {
+        self.version.to_string()
+    }
+
+    /// Whether `realms` contains all realms, i.e. `since` was not given.
+    fn complete(&self) -> bool {
+        self.complete
+    }
+
+    /// All realms that were added or changed (including moves and renames of
+    /// ancestors), ordered by path.
+    fn realms(&self) -> &[Realm] {
+        &self.realms
+    }
+
+    /// IDs of all realms that were removed.
+    fn removed(&self) -> Vec<Id> {
+        self.removed.iter().map(|key| Id::realm(*key)).collect()
+    }
+}
@@ -0,0 +1,108 @@
+use chrono::NaiveDate;
+use juniper::{GraphQLEnum, GraphQLObject};
+
+use crate::{
+    api::{Context, err::{ApiResult, invalid_input}},
+    auth,
+    db::types::Key,
+    prelude::*,
+};
+
+
+/// Maximum length of the date range of `Realm.views` in days.
+const MAX_DAYS: i64 = 3 * 366;
+
+/// How the page views in `Realm.views` are grouped.
+#[derive(Debug, Clone, Copy, GraphQLEnum)]
+pub(crate) enum ViewsInterval {
+    Day,
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+impl ViewsInterval {
+    fn to_sql(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+/// Page views of a realm in one interval (see `Realm.views`).
+#[derive(Debug, GraphQLObject)]
+pub(crate) struct RealmViews {
+    /// First day of the interval. For the first interval, this can be before
+    /// the start of the requested date range, but only views in that range
+    /// are counted.
+    start: NaiveDate,
+    /// Views of the page of this realm.
+    views: i32,
+    /// Views of the pages of this realm and all its descendants.
+    subtree_views: i32,
+}
+
+impl RealmViews {
+    pub(crate) async fn load(
+        realm: Key,
+        from: NaiveDate,
+        to: NaiveDate,
+        interval: ViewsInterval,
+        context: &Context,
+    ) -> ApiResult<Option<Vec<Self>>> {
+        // Moderators of the realm (including delegated ones) can see the
+        // statistics, even if the realm is locked for them.
+        if let Err(err) = context.require_moderator() {
+            let delegated = match &context.user {
+                Some(user) => auth::require_delegated_moderator(&context.db, user, realm)
+                    .await?
+                    .is_some(),
+                None => false,
+            };
+            if !delegated {
+                return Err(err);
+            }
+        }
+
+        if !context.config.http.playback_stats {
+            return Ok(None);
+        }
+        if from > to {
+            return Err(invalid_input!("`from` is after `to`"));
+        }
+        if (to - from).num_days() >= MAX_DAYS {
+            return Err(invalid_input!("date range is longer than {MAX_DAYS} days"));
+        }
+
+        // Intervals without views are included with zero views.
+        let query = "\
+            with subtree as (\
+                select id from realms \
+                where id = $1 \
+                or starts_with(full_path, (select full_path from realms where id = $1) || '/')\
+            ) \
+            select start::date, \
+                coalesce(sum(views) filter (where realm = $1), 0)::bigint, \
+                coalesce(sum(views), 0)::bigint \
+            from generate_series(\
+                date_trunc($4, $2::date), $3::date, ('1 ' || $4)::interval\
+            ) as start \
+            left join realm_views_daily \
+                on day >= start and day < start + ('1 ' || $4)::interval \
+                and day between $2 and $3 \
+                and realm in (select id from subtree) \
+            group by start \
+            order by start";
+        context.db
+            .query_mapped(query, dbargs![&realm, &from, &to, &interval.to_sql()], |row| Self {
+                start: row.get(0),
+                views: row.get::<_, i64>(1).try_into().unwrap_or(i32::MAX),
+                subtree_views: row.get::<_, i64>(2).try_into().unwrap_or(i32::MAX),
+            })
+            .await?
+            .pipe(Some)
+            .pipe(Ok)
+    }
+}
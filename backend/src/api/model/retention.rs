@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use juniper::GraphQLObject;
+
+use crate::{
+    api::{Context, Id, err::{ApiResult, invalid_input}},
+    prelude::*,
+    retention::RetentionAction,
+    search,
+};
+
+
+/// An event that matched a retention rule.
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RetentionFlag {
+    /// ID of the event.
+    event_id: Id,
+    event_title: String,
+    /// Path of the realm of the retention rule that matched.
+    realm: String,
+    action: RetentionAction,
+    flagged: DateTime<Utc>,
+    /// When the action of the rule is (or was) applied.
+    due: DateTime<Utc>,
+    /// Whether the event was retracted already.
+    retracted: bool,
+}
+
+impl RetentionFlag {
+    /// Returns all flagged events, ordered by due date. Only moderators can
+    /// see them.
+    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
+        let db = context.db(context.require_moderator()?);
+
+        db.query_mapped(
+            "select events.id, events.title, f.realm, f.action, f.flagged, f.due, events.retracted \
+                from retention_flags f \
+                join events on events.id = f.event \
+                order by f.due, events.title",
+            dbargs![],
+            |row| Self {
+                event_id: Id::event(row.get(0)),
+                event_title: row.get(1),
+                realm: row.get(2),
+                action: row.get(3),
+                flagged: row.get(4),
+                due: row.get(5),
+                retracted: row.get(6),
+            },
+        ).await?.pipe(Ok)
+    }
+    /// Exempts an event from all retention rules: its flag is removed and it
+    /// is never flagged again. If it was retracted already, it is restored.
+    /// Only moderators can do that.
+    pub(crate) async fn exempt(event: Id, context: &Context) -> ApiResult<RetentionExemption> {
+        let db = context.db(context.require_moderator()?);
+        let moderator = &context.user.as_ref().expect("moderator is not logged in").username;
+        let key = event.key_for(Id::EVENT_KIND)
+            .ok_or_else(|| invalid_input!("`event` does not refer to an event"))?;
+
+        let inserted = db.execute(
+            "insert into retention_exemptions (event, exempted_by) \
+                select id, $2 from events where id = $1 \
+                on conflict (event) do update set exempted_by = retention_exemptions.exempted_by",
+            &[&key, moderator],
+        ).await?;
+        if inserted == 0 {
+            return Err(invalid_input!("`event` does not refer to an existing event"));
+        }
+        db.execute("delete from retention_flags where event = $1", &[&key]).await?;
+
+        // The original read roles are unknown for events retracted before
+        // they were stored. These stay restricted until the next sync of
+        // their ACL.
+        let restored = db.execute(
+            "update events set \
+                retracted = false, \
+                read_roles = coalesce(retracted_read_roles, read_roles), \
+                retracted_read_roles = null \
+                where id = $1 and retracted",
+            &[&key],
+        ).await?;
+        if restored > 0 {
+            db.queue_for_reindex(search::IndexItemKind::Event, key).await?;
+        }
+        info!(
+            "User '{}' exempted event {:?} from retention{}",
+            moderator,
+            key,
+            if restored > 0 { " and restored it" } else { "" },
+        );
+
+        Ok(RetentionExemption { event_id: event, restored: restored > 0 })
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RetentionExemption {
+    event_id: Id,
+    /// Whether the event was retracted and is now restored.
+    restored: bool,
+}
@@ -0,0 +1,211 @@
+use chrono::{DateTime, Utc};
+use juniper::graphql_object;
+use tokio_postgres::Row;
+
+use crate::{
+    api::{
+        Context, Id, Node, NodeValue,
+        err::{ApiResult, internal_server_err, invalid_input, not_authorized},
+    },
+    auth::{HasRoles, User},
+    db::{crypt::{self, Column}, types::Key, util::define_columns},
+    prelude::*,
+};
+
+
+/// A search query saved by a user. If alerts are enabled, the user is
+/// notified via email about new videos containing all words of the query.
+pub(crate) struct SavedSearch {
+    key: Key,
+    query: String,
+    alert_email: Option<String>,
+    created: DateTime<Utc>,
+}
+
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        query: String = "query",
+        alert_email: Option<String> = "alert_email",
+        created: DateTime<Utc> = "created",
+    }
+}
+
+#[juniper::graphql_interface]
+impl Node for SavedSearch {
+    fn id(&self) -> Id {
+        Id::saved_search(self.key)
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl SavedSearch {
+    fn id(&self) -> Id {
+        Node::id(self)
+    }
+
+    fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Whether email alerts about new matching videos are sent.
+    fn email_alerts(&self) -> bool {
+        self.alert_email.is_some()
+    }
+
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+}
+
+impl SavedSearch {
+    /// Maximum number of saved searches per user.
+    const MAX_PER_USER: i64 = 50;
+
+    /// Maximum length of a saved query in characters.
+    const MAX_QUERY_LEN: usize = 200;
+
+    fn from_row(row: Row) -> Self {
+        Self {
+            key: cols::key(&row),
+            query: cols::query(&row),
+            alert_email: cols::alert_email(&row),
+            created: cols::created(&row),
+        }
+    }
+
+    fn require_user(context: &Context) -> ApiResult<&User> {
+        context.user.as_ref()
+            .ok_or_else(|| not_authorized!(key = "mutation.not-logged-in", "you are not logged in"))
+    }
+
+    /// Returns the saved search with the given ID if it belongs to the
+    /// current user.
+    pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
+        let (key, user) = match (id.key_for(Id::SAVED_SEARCH_KIND), &context.user) {
+            (Some(key), Some(user)) => (key, user),
+            _ => return Ok(None),
+        };
+
+        let query = format!(
+            "select {} from saved_searches where id = $1 and username = $2",
+            cols::COL_NAMES,
+        );
+        context.db
+            .query_opt(&query, &[&key, &user.username])
+            .await?
+            .map(Self::from_row)
+            .pipe(Ok)
+    }
+
+    /// Returns all saved searches of the current user, newest first. The
+    /// roles used for alerts are refreshed on the way (see `saved_search.rs`).
+    pub(crate) async fn load_for_user(context: &Context) -> ApiResult<Vec<Self>> {
+        let user = match &context.user {
+            Some(user) => user,
+            None => return Ok(vec![]),
+        };
+
+        let query = format!(
+            "update saved_searches set roles = $2, roles_updated = now() \
+                where username = $1 \
+                returning {}",
+            cols::COL_NAMES,
+        );
+        let mut searches = context.db
+            .query_mapped(&query, dbargs![&user.username, &user.roles], Self::from_row)
+            .await?;
+        searches.sort_by_key(|search| std::cmp::Reverse(search.created));
+
+        Ok(searches)
+    }
+
+    /// Saves a search query for the current user. Email alerts can only be
+    /// enabled if they are configured and the email address of the user is
+    /// known.
+    pub(crate) async fn save(
+        query: String,
+        email_alerts: bool,
+        context: &Context,
+    ) -> ApiResult<Self> {
+        let user = Self::require_user(context)?;
+
+        let query = query.trim();
+        if query.is_empty() {
+            return Err(invalid_input!("search query must not be empty"));
+        }
+        if query.chars().count() > Self::MAX_QUERY_LEN {
+            return Err(invalid_input!(
+                "search query must not be longer than {} characters",
+                Self::MAX_QUERY_LEN,
+            ));
+        }
+        if query.contains(char::is_control) {
+            return Err(invalid_input!("search query must not contain control characters"));
+        }
+
+        let alert_email = if email_alerts {
+            if !context.config.saved_searches.alerts_enabled() {
+                return Err(invalid_input!("email alerts are disabled"));
+            }
+            let email = match &user.email {
+                Some(email) if !email.contains(char::is_control) => email,
+                _ => return Err(invalid_input!("the email address of the user is unknown")),
+            };
+            crypt::encrypt(&context.config.db, Column::ALERT_EMAIL, email)
+                .map(Some)
+                .map_err(|e| internal_server_err!("failed to encrypt email address: {:#}", e))?
+        } else {
+            None
+        };
+
+        let count = context.db
+            .query_one("select count(*) from saved_searches where username = $1", &[&user.username])
+            .await?
+            .get::<_, i64>(0);
+        if count >= Self::MAX_PER_USER {
+            return Err(invalid_input!(
+                "a user cannot have more than {} saved searches",
+                Self::MAX_PER_USER,
+            ));
+        }
+
+        let sql = format!(
+            "insert into saved_searches (username, query, roles, alert_email) \
+                values ($1, $2, $3, $4) \
+                returning {}",
+            cols::COL_NAMES,
+        );
+        let row = context.db
+            .query_one(&sql, &[&user.username, &query, &context.user.roles(), &alert_email])
+            .await?;
+        debug!("User '{}' saved search '{}' (alerts: {})", user.username, query, email_alerts);
+
+        Ok(Self::from_row(row))
+    }
+
+    /// Removes a saved search of the current user.
+    pub(crate) async fn remove(id: Id, context: &Context) -> ApiResult<RemovedSavedSearch> {
+        let user = Self::require_user(context)?;
+        let key = id.key_for(Id::SAVED_SEARCH_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a saved search"))?;
+
+        let affected_rows = context.db
+            .execute(
+                "delete from saved_searches where id = $1 and username = $2",
+                &[&key, &user.username],
+            )
+            .await?;
+        if affected_rows != 1 {
+            return Err(invalid_input!("`id` does not refer to a saved search of the user"));
+        }
+
+        Ok(RemovedSavedSearch { id })
+    }
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RemovedSavedSearch {
+    id: Id,
+}
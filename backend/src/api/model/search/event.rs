@@ -1,43 +1,72 @@
 use crate::{
-    api::{Context, Node, Id, NodeValue},
+    api::{
+        Context, Node, Id, NodeValue,
+        model::realm::Realm,
+    },
+    http::{placeholder, thumbnail},
     search,
 };
 
 
+/// An event found by the search. The host realms are loaded for all results at
+/// once, see `perform`.
+pub(crate) struct SearchEvent {
+    pub(crate) event: search::Event,
+    pub(crate) host_realms: Vec<Realm>,
+}
+
 #[juniper::graphql_interface]
-impl Node for search::Event {
+impl Node for SearchEvent {
     fn id(&self) -> Id {
-        Id::search_event(self.id.0)
+        Id::search_event(self.event.id.0)
     }
 }
 
-#[juniper::graphql_object(Context = Context, impl = NodeValue, name = "SearchEvent")]
-impl search::Event {
+#[juniper::graphql_object(Context = Context, impl = NodeValue)]
+impl SearchEvent {
     fn id(&self) -> Id {
         Node::id(self)
     }
 
     fn title(&self) -> &str {
-        &self.title
+        &self.event.title
     }
 
     fn series_title(&self) -> Option<&str> {
-        self.series_title.as_deref()
+        self.event.series_title.as_deref()
     }
 
     fn description(&self) -> Option<&str> {
-        self.description.as_deref()
+        self.event.description.as_deref()
     }
 
     fn creators(&self) -> &[String] {
-        &self.creators
+        &self.event.creators
     }
 
-    fn thumbnail(&self) -> Option<&str> {
-        self.thumbnail.as_deref()
+    /// URL of the thumbnail. If the event has none, a generated placeholder
+    /// is returned. If `http.thumbnails` is configured, this points to Tobira,
+    /// which serves resized versions when a `width` parameter is appended.
+    fn thumbnail(&self, context: &Context) -> String {
+        match &self.event.thumbnail {
+            Some(_) if context.config.http.thumbnails.enabled() => thumbnail::url(self.event.id.0),
+            Some(url) => url.clone(),
+            None => placeholder::thumbnail_url(self.event.id.0),
+        }
     }
 
     fn duration(&self) -> i32 {
-        self.duration
+        self.event.duration
+    }
+
+    fn has_captions(&self) -> bool {
+        self.event.has_captions
+    }
+
+    /// All realms in which this event appears (via some kind of block). An
+    /// event is only returned once by the search, even if it appears in many
+    /// realms.
+    fn host_realms(&self) -> &[Realm] {
+        &self.host_realms
     }
 }
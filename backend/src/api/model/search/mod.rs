@@ -1,10 +1,16 @@
+use std::cmp::Ordering;
+
+use chrono::Utc;
+
 use crate::{
     api::{
         Context,
         err::{ApiResult, ApiErrorKind, ApiError},
         NodeValue,
+        model::realm::Realm,
     },
     auth::HasRoles,
+    prelude::*,
     search,
 };
 
@@ -12,51 +18,94 @@ use crate::{
 mod event;
 mod realm;
 
+pub(crate) use event::SearchEvent;
+
 
 #[derive(juniper::GraphQLObject)]
 #[graphql(Context = Context)]
 pub(crate) struct SearchResults {
     items: Vec<NodeValue>,
+
+    /// A corrected version of the query if it likely contains spelling
+    /// mistakes and there are only few results. `null` otherwise.
+    suggestion: Option<String>,
+}
+
+/// Restricts the search to the content of one realm.
+#[derive(juniper::GraphQLInputObject)]
+pub(crate) struct InRealm {
+    /// Path of the realm, e.g. `/lectures/physics`.
+    path: String,
+    /// Whether the content of all descendants of the realm is included.
+    #[graphql(default = "true")]
+    include_descendants: bool,
+}
+
+impl InRealm {
+    /// Returns the Meili filter for this restriction, given the names of the
+    /// attributes containing the exact paths and all subtrees (e.g.
+    /// `search::Event::host_realms` and `host_subtrees`).
+    fn filter(&self, path_attr: &str, subtrees_attr: &str) -> String {
+        let attr = if self.include_descendants { subtrees_attr } else { path_attr };
+        let path = search::encode_path(self.path.trim_end_matches('/'));
+        format!("{attr} = '{path}'")
+    }
 }
 
+/// Number of events and realms that are requested from Meili.
+const EVENT_LIMIT: usize = 15;
+const REALM_LIMIT: usize = 10;
+
 pub(crate) async fn perform(
     user_query: &str,
+    realm: Option<&str>,
+    in_realm: Option<InRealm>,
+    captioned: Option<bool>,
     context: &Context,
 ) -> ApiResult<Option<SearchResults>> {
     if user_query.is_empty() {
         return Ok(None);
     }
 
-    // Prepare the event search
-    let mut acl_filter = None;
+    // Prepare the event search. If the user is not admin, there has to be one
+    // user role inside the event's ACL.
+    let mut event_filters = vec![];
+    if !context.permissions.admin {
+        let filter = context.user.roles()
+            .iter()
+            .map(|role| format!("read_roles = '{}'", hex::encode(role)))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        event_filters.push(format!("({filter})"));
+    }
+    if let Some(in_realm) = &in_realm {
+        event_filters.push(in_realm.filter("host_realms", "host_subtrees"));
+    }
+    if let Some(captioned) = captioned {
+        event_filters.push(format!("has_captions = {captioned}"));
+    }
+    let event_filter = event_filters.join(" AND ");
     let event_query = {
-        // If the user is not admin, build ACL filter: there has to be one user role
-        // inside the event's ACL.
-        if !context.user.is_admin() {
-            let filter = context.user.roles()
-                .iter()
-                .map(|role| format!("read_roles = '{}'", hex::encode(role)))
-                .collect::<Vec<_>>()
-                .join(" OR ");
-            acl_filter = Some(filter);
-        };
-
-        // Build search query
         let mut query = context.search.event_index.search();
         query.with_query(user_query);
-        query.with_limit(15);
+        query.with_limit(EVENT_LIMIT);
         query.with_matches(true);
-        query.filter = acl_filter.as_deref();
+        if !event_filter.is_empty() {
+            query.filter = Some(&event_filter);
+        }
         query
     };
 
 
     // Prepare the realm search
+    let realm_filter = in_realm.as_ref()
+        .map(|in_realm| in_realm.filter("encoded_path", "subtrees"));
     let realm_query = {
         let mut query = context.search.realm_index.search();
         query.with_query(user_query);
-        query.with_limit(10);
+        query.with_limit(REALM_LIMIT);
         query.with_matches(true);
+        query.filter = realm_filter.as_deref();
         query
     };
 
@@ -77,13 +126,12 @@ pub(crate) async fn perform(
     fn calc_relevancy<T, I, F, G>(
         results: I,
         field_weight: F,
-        should_boost: G,
-    ) -> impl Iterator<Item = (NodeValue, f64)>
+        boost: G,
+    ) -> impl Iterator<Item = (T, f64)>
     where
-        NodeValue: From<T>,
         I: IntoIterator<Item = meilisearch_sdk::search::SearchResult<T>>,
         F: Fn(&str) -> f64,
-        G: Fn(&T) -> bool,
+        G: Fn(&T) -> f64,
     {
         results.into_iter()
             .map(move |hit| {
@@ -104,39 +152,74 @@ pub(crate) async fn perform(
                     })
                     .sum::<f64>();
 
-                if should_boost(&hit.result) {
-                    relevancy += 200.0;
-                }
+                relevancy += boost(&hit.result);
 
-                (NodeValue::from(hit.result), relevancy)
+                (hit.result, relevancy)
             })
     }
 
+    // Events that appear in the realm the search was started from (or its
+    // descendants) get the realm boost.
+    let config = &context.config.search;
+    let local_subtree = realm.map(|realm| search::encode_path(realm.trim_end_matches('/')));
+
+    // The host realms of all events are loaded at once instead of in one query
+    // per result.
+    let event_keys = event_results.hits.iter().map(|hit| hit.result.id.0).collect::<Vec<_>>();
+    let mut host_realms = Realm::load_event_hosts(&event_keys, context).await?;
+
     // Attach a relevancy score to each result, to be able to sort afterwards.
-    let events = calc_relevancy(event_results.hits, |field| {
+    let exact_match_boost = |title: &str| if title.to_lowercase() == user_query.to_lowercase() {
+        config.exact_match_boost
+    } else {
+        0.0
+    };
+    let now = Utc::now();
+    let mut events = calc_relevancy(event_results.hits, |field| {
         match field {
-            "title" => 10.0,
-            "creators" => 3.0,
-            "description" => 2.0,
-            "series_title" => 1.0,
+            "title" => config.title_weight,
+            "creators" => config.creators_weight,
+            "description" => config.description_weight,
+            "series_title" => config.series_title_weight,
             _ => 0.0,
         }
-    }, |event| event.title.to_lowercase() == user_query.to_lowercase());
+    }, |event| {
+        let mut boost = exact_match_boost(&event.title);
+        if let Some(created) = event.created {
+            let age = now.signed_duration_since(created).num_seconds().max(0) as f64;
+            let half_lives = age / config.recency_half_life.as_secs_f64();
+            boost += config.recency_boost * 0.5f64.powf(half_lives);
+        }
+        if local_subtree.as_ref().is_some_and(|subtree| event.host_subtrees.contains(subtree)) {
+            boost += config.realm_boost;
+        }
+        boost
+    }).map(|(event, relevancy)| {
+        let host_realms = host_realms.remove(&event.id.0).unwrap_or_default();
+        (NodeValue::from(SearchEvent { event, host_realms }), relevancy)
+    }).collect::<Vec<_>>();
     let realms = calc_relevancy(realm_results.hits, |field| {
         match field {
-            "name" => 10.0,
+            "name" => config.realm_name_weight,
             _ => 0.0,
         }
-    }, |realm| realm.name.to_lowercase() == user_query.to_lowercase());
+    }, |realm| exact_match_boost(&realm.name))
+        .map(|(realm, relevancy)| (NodeValue::from(realm), relevancy));
+
+    // With boosts that Meili does not know about, its order of the events is
+    // not what we want.
+    if config.reorders_events() {
+        events.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    }
 
 
     // Merge and sort the results. We could simply sort by our own relevancy,
     // but that is definitely worse than the relevance sorting by Meili. So we
     // only want to use it to merge both lists together. The order of events
-    // and realms is not changed. The multiplier help in breaking up larger
-    // blocks of one kind.
+    // (unless reordered above) and realms is not changed. The multiplier help
+    // in breaking up larger blocks of one kind.
     let mut items = Vec::new();
-    let mut events = events.peekable();
+    let mut events = events.into_iter().peekable();
     let mut realms = realms.peekable();
     let mut event_multiplier = 1.0;
     let mut realm_multiplier = 1.0;
@@ -157,7 +240,47 @@ pub(crate) async fn perform(
         }
     }
 
-    Ok(Some(SearchResults { items }))
+    let suggestion = if items.len() < config.suggestion_threshold as usize {
+        suggest(user_query, context).await?
+    } else {
+        None
+    };
+
+    Ok(Some(SearchResults { items, suggestion }))
+}
+
+/// Replaces each word of the query with the most similar word (by trigram
+/// similarity) that appears in the `search_words` view. Returns `None` if
+/// that does not change anything.
+async fn suggest(user_query: &str, context: &Context) -> ApiResult<Option<String>> {
+    let words = user_query.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>();
+    if words.is_empty() {
+        return Ok(None);
+    }
+
+    // Short words have too few trigrams for meaningful similarities, so they
+    // are kept as they are.
+    let corrected = context.db
+        .query_mapped(
+            "select coalesce(best.word, query.word) \
+                from unnest($1::text[]) with ordinality as query(word, i) \
+                left join lateral ( \
+                    select word from search_words \
+                    where char_length(query.word) >= 3 and word % query.word \
+                    order by similarity(word, query.word) desc, frequency desc \
+                    limit 1 \
+                ) as best on true \
+                order by query.i",
+            dbargs![&words],
+            |row| row.get::<_, String>(0),
+        )
+        .await?;
+
+    if corrected == words {
+        Ok(None)
+    } else {
+        Ok(Some(corrected.join(" ")))
+    }
 }
 
 impl From<meilisearch_sdk::errors::Error> for ApiError {
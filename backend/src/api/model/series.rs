@@ -1,17 +1,30 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use juniper::graphql_object;
 use tokio_postgres::Row;
 
 use crate::{
-    api::{Context, err::ApiResult, Id, model::event::{Event, EventSortOrder}, Node, NodeValue},
-    db::{types::Key},
+    api::{
+        Context, cache::CacheTag, Id, Node, NodeValue,
+        err::{ApiResult, internal_server_err, invalid_input, not_authorized},
+        model::{event::{CaptionCoverage, Event, EventSortOrder, EventStats}, realm::Realm},
+    },
+    auth::USER_ROLE_PREFIX,
+    db::{crypt::{self, Column}, types::Key, util::define_columns},
     prelude::*,
 };
 
 
+#[derive(Clone)]
 pub(crate) struct Series {
     key: Key,
     title: String,
     description: Option<String>,
+    /// If `true`, all events of this series are returned, regardless of their
+    /// read roles. Set for series shown to realm members (see
+    /// `Realm::member_roles`).
+    member_access: bool,
 }
 
 #[juniper::graphql_interface]
@@ -35,28 +48,107 @@ impl Series {
         self.description.as_deref()
     }
 
+    /// If `filter` is given, only events with that value in the field
+    /// configured as `general.event_filter` are returned. If `captioned` is
+    /// given, only events with (`true`) or without (`false`) captions are
+    /// returned.
     #[graphql(arguments(order(default = Default::default())))]
-    async fn events(&self, order: EventSortOrder, context: &Context) -> ApiResult<Vec<Event>> {
-        Event::load_for_series(self.key, order, context).await
+    async fn events(
+        &self,
+        order: EventSortOrder,
+        filter: Option<String>,
+        captioned: Option<bool>,
+        context: &Context,
+    ) -> ApiResult<Vec<Event>> {
+        Event::load_for_series(self.key, order, self.member_access, filter, captioned, context)
+            .await
     }
-}
 
-impl Series {
-    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
-        context.db(context.require_moderator()?)
-            .query_mapped(
-                &format!(
-                    "select {} from series \
-                        order by title",
-                    Self::COL_NAMES,
-                ),
-                dbargs![],
-                Self::from_row,
+    /// How many of the events in this series that the current user can read
+    /// have captions.
+    async fn caption_coverage(&self, context: &Context) -> ApiResult<CaptionCoverage> {
+        let condition = "series = $1 and (($2 and not retracted) or read_roles && $3)";
+        let args = dbargs![&self.key, &self.member_access, &context.user.roles()];
+        CaptionCoverage::load(condition, &args, context).await
+    }
+
+    /// Number of events in this series that the current user can read.
+    async fn event_count(&self, context: &Context) -> ApiResult<i32> {
+        Ok(self.event_stats(context).await?.count)
+    }
+
+    /// Sum of the (trimmed) durations of these events in seconds.
+    async fn total_duration(&self, context: &Context) -> ApiResult<f64> {
+        Ok(self.event_stats(context).await?.total_duration)
+    }
+
+    /// When the newest of these events was created, `null` if there are none.
+    async fn latest_event_date(&self, context: &Context) -> ApiResult<Option<DateTime<Utc>>> {
+        Ok(self.event_stats(context).await?.latest)
+    }
+
+    /// All values of the field configured as `general.event_filter` of
+    /// events in this series that the current user can read, sorted. These
+    /// are the useful values for `filter` in `events`.
+    async fn event_filter_values(&self, context: &Context) -> ApiResult<Vec<String>> {
+        let condition = "series = $3 and (($4 and not retracted) or read_roles && $5)";
+        let args = dbargs![&self.key, &self.member_access, &context.user.roles()];
+        Event::load_filter_values(condition, &args, context).await
+    }
+
+    /// Whether the current user follows this series (see `followSeries`).
+    async fn followed(&self, context: &Context) -> ApiResult<bool> {
+        let Some(user) = &context.user else {
+            return Ok(false);
+        };
+
+        context.db
+            .query_one(
+                "select exists(select from series_follows where series = $1 and username = $2)",
+                &[&self.key, &user.username],
             )
             .await?
+            .get::<_, bool>(0)
             .pipe(Ok)
     }
 
+    /// Returns a list of realms with a series block for this series. The
+    /// first one is the canonical location of the series (see
+    /// `Event.hostRealms`).
+    async fn host_realms(&self, context: &Context) -> ApiResult<Vec<Realm>> {
+        let hosts = "(select realm_id as realm, 0 as directness, \
+                bool_or(canonical) as canonical, \
+                min(coalesce(created, '-infinity')) as mounted \
+            from blocks where series_id = $1 group by realm_id)";
+        Realm::load_hosts(hosts, self.key, context).await
+    }
+}
+
+impl Series {
+    /// Maximum number of series a user can follow.
+    const MAX_FOLLOWS_PER_USER: i64 = 200;
+
+    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
+        let db = context.db(context.require_moderator()?);
+        let load = async {
+            db
+                .query_mapped(
+                    &format!(
+                        "select {} from series \
+                            order by title collate {}",
+                        cols::COL_NAMES,
+                        context.config.general.collation(),
+                    ),
+                    dbargs![],
+                    Self::from_row,
+                )
+                .await?
+                .pipe(Ok)
+        };
+        let ttl = Duration::from_secs(60);
+        context.cache.get_or_load(CacheTag::Series, "all-series".into(), ttl, load).await
+    }
+
     pub(crate) async fn load_by_id(id: Id, context: &Context) -> ApiResult<Option<Self>> {
         if let Some(key) = id.key_for(Id::SERIES_KIND) {
             Self::load_by_key(key, context).await
@@ -72,7 +164,7 @@ impl Series {
                     "select {} \
                         from series \
                         where id = $1",
-                    Self::COL_NAMES,
+                    cols::COL_NAMES,
                 ),
                 &[&key],
             )
@@ -83,7 +175,7 @@ impl Series {
     }
 
     pub(crate) async fn load_by_opencast_id(id: String, context: &Context) -> ApiResult<Option<Series>> {
-        let query = format!("select {} from series where opencast_id = $1", Self::COL_NAMES);
+        let query = format!("select {} from series where opencast_id = $1", cols::COL_NAMES);
         context.db
             .query_opt(&query, &[&id])
             .await?
@@ -91,13 +183,151 @@ impl Series {
             .pipe(Ok)
     }
 
-    const COL_NAMES: &'static str = "id, title, description";
+    /// Returns the series the current user has write access to, i.e. to at
+    /// least one of their events, ordered by title. Moderators have write
+    /// access to all series.
+    pub(crate) async fn load_writable(context: &Context) -> ApiResult<Vec<Self>> {
+        if context.permissions.moderator {
+            return Self::load_all(context).await;
+        }
+
+        let query = format!(
+            "select {} from series \
+                where exists(select from events where series = series.id and write_roles && $1) \
+                order by title collate {}",
+            cols::COL_NAMES,
+            context.config.general.collation(),
+        );
+        context.db
+            .query_mapped(&query, dbargs![&context.user.roles()], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Returns the series followed by the current user, ordered by title.
+    pub(crate) async fn load_followed(context: &Context) -> ApiResult<Vec<Self>> {
+        let Some(user) = &context.user else {
+            return Ok(vec![]);
+        };
+
+        let query = format!(
+            "select {} from series \
+                where id in (select series from series_follows where username = $1) \
+                order by title collate {}",
+            cols::COL_NAMES,
+            context.config.general.collation(),
+        );
+        context.db
+            .query_mapped(&query, dbargs![&user.username], Self::from_row)
+            .await?
+            .pipe(Ok)
+    }
+
+    /// Lets the current user follow a series, i.e. be notified about new
+    /// events in it (see `series_follow.rs`). Following it again only changes
+    /// whether emails are sent.
+    pub(crate) async fn follow(id: Id, email: bool, context: &Context) -> ApiResult<Self> {
+        let user = context.user.as_ref()
+            .ok_or_else(|| not_authorized!(key = "mutation.not-logged-in", "you are not logged in"))?;
+        let key = id.key_for(Id::SERIES_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a series"))?;
+        let series = Self::load_by_key(key, context)
+            .await?
+            .ok_or_else(|| invalid_input!("`id` does not refer to an existing series"))?;
+
+        let email = if email {
+            if !context.config.saved_searches.alerts_enabled() {
+                return Err(invalid_input!("email notifications are disabled"));
+            }
+            let email = match &user.email {
+                Some(email) if !email.contains(char::is_control) => email,
+                _ => return Err(invalid_input!("the email address of the user is unknown")),
+            };
+            crypt::encrypt(&context.config.db, Column::FOLLOW_EMAIL, email)
+                .map(Some)
+                .map_err(|e| internal_server_err!("failed to encrypt email address: {:#}", e))?
+        } else {
+            None
+        };
+
+        let count = context.db
+            .query_one(
+                "select count(*) from series_follows where username = $1 and series <> $2",
+                &[&user.username, &key],
+            )
+            .await?
+            .get::<_, i64>(0);
+        if count >= Self::MAX_FOLLOWS_PER_USER {
+            return Err(invalid_input!(
+                "a user cannot follow more than {} series",
+                Self::MAX_FOLLOWS_PER_USER,
+            ));
+        }
+
+        let user_role = user.roles.iter().find(|role| role.starts_with(USER_ROLE_PREFIX));
+        context.db
+            .execute(
+                "insert into series_follows (username, series, roles, user_role, email) \
+                    values ($1, $2, $3, $4, $5) \
+                    on conflict (username, series) do update set \
+                        roles = excluded.roles, \
+                        roles_updated = now(), \
+                        user_role = excluded.user_role, \
+                        email = excluded.email",
+                &[&user.username, &key, &context.user.roles(), &user_role, &email],
+            )
+            .await?;
+        debug!("User '{}' follows series {:?} (email: {})", user.username, key, email.is_some());
+
+        Ok(series)
+    }
+
+    /// Stops notifications about new events in a series for the current user.
+    /// Does nothing if the user does not follow it.
+    pub(crate) async fn unfollow(id: Id, context: &Context) -> ApiResult<Self> {
+        let user = context.user.as_ref()
+            .ok_or_else(|| not_authorized!(key = "mutation.not-logged-in", "you are not logged in"))?;
+        let key = id.key_for(Id::SERIES_KIND)
+            .ok_or_else(|| invalid_input!("`id` does not refer to a series"))?;
+        let series = Self::load_by_key(key, context)
+            .await?
+            .ok_or_else(|| invalid_input!("`id` does not refer to an existing series"))?;
+
+        context.db
+            .execute(
+                "delete from series_follows where username = $1 and series = $2",
+                &[&user.username, &key],
+            )
+            .await?;
+
+        Ok(series)
+    }
+
+    async fn event_stats(&self, context: &Context) -> ApiResult<EventStats> {
+        let key = format!("series:{}:{}", self.key.0, self.member_access);
+        let condition = "series = $1 and (($2 and not retracted) or read_roles && $3)";
+        let args = dbargs![&self.key, &self.member_access, &context.user.roles()];
+        EventStats::load(key, condition, &args, context).await
+    }
+
+    pub(crate) fn with_member_access(self, member_access: bool) -> Self {
+        Self { member_access, ..self }
+    }
 
     fn from_row(row: Row) -> Self {
         Self {
-            key: row.get(0),
-            title: row.get(1),
-            description: row.get(2),
+            key: cols::key(&row),
+            title: cols::title(&row),
+            description: cols::description(&row),
+            member_access: false,
         }
     }
 }
+
+define_columns! {
+    mod cols {
+        key: Key = "id",
+        title: String = "title",
+        description: Option<String> = "description",
+    }
+}
@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use juniper::GraphQLObject;
+
+use crate::{
+    api::{Context, cache::CacheTag, err::{ApiResult, invalid_input}},
+    config::Overrides,
+    prelude::*,
+};
+
+
+/// A setting that overrides the config value with the same key at runtime.
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct Setting {
+    /// Key of the setting, named like the config value, e.g.
+    /// `theme.color.accent`.
+    key: String,
+    /// The value of the setting, encoded as JSON.
+    value: String,
+    updated: DateTime<Utc>,
+}
+
+impl Setting {
+    /// Returns all settings overridden in the DB. Only admins can see them.
+    pub(crate) async fn load_all(context: &Context) -> ApiResult<Vec<Self>> {
+        let db = context.db(context.require_admin()?);
+
+        db.query_mapped(
+            "select key, value::text, updated from settings order by key",
+            dbargs![],
+            |row| Self { key: row.get(0), value: row.get(1), updated: row.get(2) },
+        ).await?.pipe(Ok)
+    }
+
+    /// Overrides the config value with the given key. `value` has to be
+    /// valid JSON in the format the config value has.
+    pub(crate) async fn set(key: String, value: String, context: &Context) -> ApiResult<Self> {
+        let db = context.db(context.require_admin()?);
+        context.cache.modified(CacheTag::Settings);
+
+        if !Overrides::KEYS.contains(&key.as_str()) {
+            return Err(invalid_input!(
+                "`key` has to be one of: {}",
+                Overrides::KEYS.join(", "),
+            ));
+        }
+        Overrides::default().set(&key, &value)
+            .map_err(|e| invalid_input!("invalid value for setting '{}': {}", key, e))?;
+
+        let row = db.query_one(
+            "insert into settings (key, value) values ($1, $2::text::jsonb) \
+                on conflict (key) do update set value = excluded.value, updated = now() \
+                returning key, value::text, updated",
+            &[&key, &value],
+        ).await?;
+        info!("Setting '{}' was changed to {}", key, value);
+
+        Ok(Self { key: row.get(0), value: row.get(1), updated: row.get(2) })
+    }
+
+    /// Removes the override with the given key, so that the value from the
+    /// config file is used again.
+    pub(crate) async fn reset(key: String, context: &Context) -> ApiResult<RemovedSetting> {
+        let db = context.db(context.require_admin()?);
+        context.cache.modified(CacheTag::Settings);
+
+        let affected_rows = db.execute("delete from settings where key = $1", &[&key]).await?;
+        if affected_rows != 1 {
+            return Err(invalid_input!("setting '{}' is not overridden", key));
+        }
+        info!("Setting '{}' was reset to the config file value", key);
+
+        Ok(RemovedSetting { key })
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RemovedSetting {
+    key: String,
+}
@@ -0,0 +1,147 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use juniper::GraphQLObject;
+
+use crate::{
+    api::{Context, err::ApiResult},
+    outbox,
+};
+
+
+/// Overview of the state of this Tobira instance, e.g. for monitoring. Only
+/// admins can see it.
+#[derive(GraphQLObject)]
+pub(crate) struct SystemStatus {
+    /// Version of Tobira, e.g. `1.2.0 (abc1234), built 2022-05-17 ...`.
+    version: String,
+    db_pool: DbPoolStatus,
+    sync: SyncStatus,
+    search_index: SearchIndexStatus,
+    queues: QueueStatus,
+    cache: CacheStatus,
+}
+
+/// Connections of the DB pool of the Tobira node answering the request.
+#[derive(GraphQLObject)]
+pub(crate) struct DbPoolStatus {
+    /// Maximum number of connections (`db.max_connections`).
+    max_size: i32,
+    /// Number of open connections.
+    size: i32,
+    /// Number of open connections that are currently unused.
+    available: i32,
+    /// Number of tasks waiting for a connection.
+    waiting: i32,
+}
+
+#[derive(GraphQLObject)]
+pub(crate) struct SyncStatus {
+    /// All changes in Opencast up to this point are synced. `null` if
+    /// nothing was synced yet.
+    harvested_until: Option<DateTime<Utc>>,
+    /// Seconds since `harvestedUntil`.
+    lag: Option<f64>,
+}
+
+#[derive(GraphQLObject)]
+pub(crate) struct SearchIndexStatus {
+    /// Number of items waiting to be updated in the search index.
+    queue_length: i32,
+    /// Seconds since the oldest of these items was queued, 0 if there are
+    /// none.
+    lag: f64,
+}
+
+/// Number of items waiting to be processed by `tobira worker`.
+#[derive(GraphQLObject)]
+pub(crate) struct QueueStatus {
+    /// Webhook messages not sent yet (see `webhooks.url`).
+    outbox: i32,
+    /// Webhook messages that were given up after too many failed attempts.
+    outbox_failed: i32,
+    /// New events for which saved search alerts were not sent yet.
+    saved_search_alerts: i32,
+    /// New events in followed series that were not announced yet.
+    follow_notifications: i32,
+    /// Operations waiting for the approval of a second moderator that did
+    /// not expire yet.
+    pending_operations: i32,
+}
+
+/// Statistics of the resolver cache of the Tobira node answering the request
+/// since it was started. Counts are floats as they can exceed 2^31.
+#[derive(GraphQLObject)]
+pub(crate) struct CacheStatus {
+    /// Whether values are currently cached. If not, the counts do not change.
+    enabled: bool,
+    hits: f64,
+    misses: f64,
+    /// `hits / (hits + misses)`, `null` if nothing was looked up yet.
+    hit_rate: Option<f64>,
+}
+
+impl SystemStatus {
+    pub(crate) async fn load(context: &Context) -> ApiResult<Self> {
+        let db = context.db(context.require_admin()?);
+
+        let row = db
+            .query_one(
+                "select \
+                    (select harvested_until from sync_status), \
+                    (select extract(epoch from \
+                        now() at time zone 'utc' - harvested_until)::float8 \
+                        from sync_status), \
+                    (select count(*) from search_index_queue), \
+                    (select coalesce(extract(epoch from now() - min(queued_at)), 0)::float8 \
+                        from search_index_queue), \
+                    (select count(*) from outbox where attempts < $1), \
+                    (select count(*) from outbox where attempts >= $1), \
+                    (select count(*) from saved_search_alert_queue), \
+                    (select count(*) from pending_operations where expires > now()), \
+                    (select count(*) from series_follow_queue)",
+                &[&outbox::MAX_ATTEMPTS],
+            )
+            .await?;
+        let count = |idx| row.get::<_, i64>(idx).try_into().unwrap_or(i32::MAX);
+
+        // Stored in UTC. It is the Unix epoch before the first harvest.
+        let harvested_until = Some(DateTime::from_utc(row.get::<_, NaiveDateTime>(0), Utc))
+            .filter(|t| t.timestamp() > 0);
+
+        let pool = context.db_pool.status();
+        let cast_i32 = |x: usize| x.try_into().unwrap_or(i32::MAX);
+        let (hits, misses) = context.cache.counts();
+
+        Ok(Self {
+            version: crate::version(),
+            db_pool: DbPoolStatus {
+                max_size: cast_i32(pool.max_size),
+                size: cast_i32(pool.size),
+                available: pool.available.max(0).try_into().unwrap_or(i32::MAX),
+                waiting: (-pool.available).max(0).try_into().unwrap_or(i32::MAX),
+            },
+            sync: SyncStatus {
+                lag: harvested_until.map(|_| row.get(1)),
+                harvested_until,
+            },
+            search_index: SearchIndexStatus {
+                queue_length: count(2),
+                lag: row.get(3),
+            },
+            queues: QueueStatus {
+                outbox: count(4),
+                outbox_failed: count(5),
+                saved_search_alerts: count(6),
+                pending_operations: count(7),
+                follow_notifications: count(8),
+            },
+            cache: CacheStatus {
+                enabled: context.cache.is_enabled(),
+                hits: hits as f64,
+                misses: misses as f64,
+                hit_rate: Some(hits + misses)
+                    .filter(|&total| total > 0)
+                    .map(|total| hits as f64 / total as f64),
+            },
+        })
+    }
+}
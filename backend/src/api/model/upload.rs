@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use juniper::{GraphQLEnum, GraphQLObject};
+
+use crate::{
+    api::{Context, Id, err::{ApiResult, invalid_input, not_authorized}, model::series::Series},
+    http::upload::VirusScanConfig,
+    prelude::*,
+};
+
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct UploadConfig {
+    /// ID of the Opencast workflow that is started for uploaded videos, e.g.
+    /// "schedule-and-upload". If not set, Opencast starts its default
+    /// workflow.
+    pub(crate) workflow: Option<String>,
+
+    /// Configuration passed to the workflow, e.g. to choose the publication
+    /// channels: `{ publishToEngage = "true", publishToOaiPmh = "false" }`.
+    /// Which keys are understood depends on the workflow.
+    pub(crate) workflow_configuration: Option<HashMap<String, String>>,
+
+    /// Tags added to the uploaded tracks, e.g. `["archive"]`.
+    pub(crate) tags: Option<Vec<String>>,
+
+    /// Whether uploaders choose a series for their video: "none" (no series
+    /// can be chosen), "optional" or "required". Only series the uploader has
+    /// write access to (i.e. to at least one of their events) can be chosen.
+    /// Moderators can choose any series.
+    #[config(default = "none")]
+    pub(crate) series: UploadSeriesMode,
+
+    /// Whether uploaders may create a new series instead of choosing an
+    /// existing one. Requires `series` to be "optional" or "required". The
+    /// series is created by Opencast during ingest, with the uploader being
+    /// allowed to read and write it.
+    #[config(default = false)]
+    pub(crate) allow_new_series: bool,
+
+    /// Scanning uploaded videos for viruses before they are sent to
+    /// Opencast. If a scanner is configured, uploads go through Tobira
+    /// instead of directly to Opencast.
+    #[config(nested)]
+    pub(crate) virus_scan: VirusScanConfig,
+}
+
+impl UploadConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        let is_identifier = |s: &str| !s.is_empty()
+            && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+
+        if let Some(workflow) = &self.workflow {
+            if !is_identifier(workflow) {
+                bail!("`upload.workflow` is not a valid workflow ID: '{workflow}'");
+            }
+        }
+        if let Some(key) = self.workflow_configuration.iter().flat_map(|c| c.keys()).find(|key| !is_identifier(key)) {
+            bail!("`upload.workflow_configuration` contains invalid key '{key}'");
+        }
+        if let Some(tag) = self.tags.iter().flatten().find(|tag| tag.is_empty() || tag.contains(',')) {
+            bail!("`upload.tags` contains invalid tag '{tag}' (tags must not be empty \
+                or contain commas)");
+        }
+        if self.allow_new_series && self.series == UploadSeriesMode::None {
+            bail!("`upload.allow_new_series` requires `upload.series` to be \
+                \"optional\" or \"required\"");
+        }
+        self.virus_scan.validate()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, GraphQLEnum)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum UploadSeriesMode {
+    None,
+    Optional,
+    Required,
+}
+
+/// What the uploader can configure, as set in the `upload` section of the
+/// configuration.
+pub(crate) struct UploadSettings;
+
+#[juniper::graphql_object(Context = Context)]
+impl UploadSettings {
+    /// Tags that have to be added to each uploaded track.
+    fn tags(context: &Context) -> &[String] {
+        context.config.upload.tags.as_deref().unwrap_or_default()
+    }
+
+    fn series_mode(context: &Context) -> UploadSeriesMode {
+        context.config.upload.series
+    }
+
+    /// Whether `prepareUpload` accepts `newSeriesTitle`.
+    fn allow_new_series(context: &Context) -> bool {
+        context.config.upload.allow_new_series
+    }
+
+    /// Series the current user can choose, ordered by title. Empty if the
+    /// series mode is `NONE`.
+    async fn writable_series(context: &Context) -> ApiResult<Vec<Series>> {
+        if context.config.upload.series == UploadSeriesMode::None {
+            return Ok(vec![]);
+        }
+        Series::load_writable(context).await
+    }
+}
+
+/// Where an upload goes and how Opencast processes it. The uploader has to
+/// ingest exactly this.
+#[derive(GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct UploadTarget {
+    /// The workflow to start, or `null` for the Opencast default.
+    workflow: Option<String>,
+    workflow_configuration: Vec<WorkflowProperty>,
+    /// The series the video has to be added to.
+    series: Option<UploadSeries>,
+}
+
+#[derive(GraphQLObject)]
+pub(crate) struct WorkflowProperty {
+    key: String,
+    value: String,
+}
+
+#[derive(GraphQLObject)]
+pub(crate) struct UploadSeries {
+    opencast_id: String,
+    title: String,
+    /// Whether the series does not exist yet. Its Dublin Core catalog has to
+    /// be ingested with the video then, so that Opencast creates it.
+    is_new: bool,
+}
+
+impl UploadTarget {
+    /// Validates the choice of series against the `upload` configuration
+    /// and the user's write access.
+    pub(crate) async fn prepare(
+        series: Option<Id>,
+        new_series_title: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Self> {
+        context.require_upload_permission()?;
+        let config = &context.config.upload;
+
+        let series = match (series, new_series_title) {
+            (Some(_), Some(_)) => {
+                return Err(invalid_input!("only one of `series` and `newSeriesTitle` can be given"));
+            }
+            (Some(_), _) | (_, Some(_)) if config.series == UploadSeriesMode::None => {
+                return Err(invalid_input!("choosing a series for uploads is disabled"));
+            }
+            (Some(id), None) => Some(Self::load_series(id, context).await?),
+            (None, Some(title)) => {
+                if !config.allow_new_series {
+                    return Err(not_authorized!(
+                        key = "upload.new-series-not-allowed",
+                        "creating new series is disabled",
+                    ));
+                }
+                let title = title.trim();
+                if title.is_empty() {
+                    return Err(invalid_input!("`newSeriesTitle` must not be empty"));
+                }
+                Some(UploadSeries {
+                    opencast_id: new_series_id(),
+                    title: title.into(),
+                    is_new: true,
+                })
+            }
+            (None, None) if config.series == UploadSeriesMode::Required => {
+                return Err(invalid_input!("uploads have to be added to a series"));
+            }
+            (None, None) => None,
+        };
+
+        let mut workflow_configuration = config.workflow_configuration.iter().flatten()
+            .map(|(key, value)| WorkflowProperty { key: key.clone(), value: value.clone() })
+            .collect::<Vec<_>>();
+        workflow_configuration.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(Self {
+            workflow: config.workflow.clone(),
+            workflow_configuration,
+            series,
+        })
+    }
+
+    async fn load_series(id: Id, context: &Context) -> ApiResult<UploadSeries> {
+        let (key, roles) = match (id.key_for(Id::SERIES_KIND), &context.user) {
+            (Some(key), Some(user)) => (key, &user.roles),
+            _ => return Err(invalid_input!("`series` does not refer to a series")),
+        };
+        let row = context.db
+            .query_opt(
+                "select opencast_id, title, \
+                    $2 or exists(select from events where series = series.id and write_roles && $3) \
+                    from series where id = $1",
+                &[&key, &context.permissions.moderator, roles],
+            )
+            .await?
+            .ok_or_else(|| invalid_input!("`series` does not refer to an existing series"))?;
+
+        if !row.get::<_, bool>(2) {
+            return Err(not_authorized!(
+                key = "upload.series-not-writable",
+                "you do not have write access to this series",
+            ));
+        }
+
+        Ok(UploadSeries {
+            opencast_id: row.get(0),
+            title: row.get(1),
+            is_new: false,
+        })
+    }
+}
+
+/// Returns a random UUID (version 4), which is what Opencast uses as series
+/// IDs as well.
+fn new_series_id() -> String {
+    let bits = (rand::random::<u128>() & !(0xf000 << 64) & !(0xc << 60))
+        | (0x4000 << 64)
+        | (0x8 << 60);
+    let hex = format!("{bits:032x}");
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::new_series_id;
+
+    #[test]
+    fn series_ids() {
+        for _ in 0..100 {
+            let id = new_series_id();
+            assert_eq!(id.len(), 36);
+            assert_eq!(&id[14..15], "4");
+            assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"), "{id}");
+            assert!(id.bytes().all(|b| b == b'-' || b.is_ascii_hexdigit()));
+        }
+    }
+}
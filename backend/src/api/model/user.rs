@@ -2,10 +2,18 @@ use crate::{
     api::{
         Context,
         common::Cursor,
-        err::ApiResult,
-        model::event::{Event, EventConnection, EventSortOrder},
+        err::{ApiResult, invalid_input},
+        model::{
+            event::{Event, EventConnection, EventSortOrder},
+            moderator_delegation::ModeratorDelegation,
+            notification::Notification,
+            preference::Preference,
+            saved_search::SavedSearch,
+            series::Series,
+        },
     },
     auth::User,
+    http::avatar,
     prelude::*,
 };
 
@@ -22,19 +30,45 @@ impl User {
         &self.display_name
     }
 
+    /// The email address of the user, if known. Only available for the
+    /// current user.
+    fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    /// URL of an avatar image of the user: the uploaded one or, if there is
+    /// none, one derived from the email address (if configured).
+    async fn avatar_url(&self, context: &Context) -> ApiResult<Option<String>> {
+        if context.config.auth.avatar_upload {
+            if let Some(url) = avatar::avatar_url(&context.db, &self.username).await? {
+                return Ok(Some(url));
+            }
+        }
+
+        Ok(self.compute_avatar_url(&context.config.auth))
+    }
+
+    // These are only called for the current user (see `Query::current_user`),
+    // so the permissions of the context can be used.
+
     /// `True` if the user has the permission to upload videos.
     fn can_upload(&self, context: &Context) -> bool {
-        self.can_upload(&context.config.auth)
+        context.permissions.upload
     }
 
     /// `True` if the user has the permission to use Opencast Studio.
     fn can_use_studio(&self, context: &Context) -> bool {
-        self.can_use_studio(&context.config.auth)
+        context.permissions.studio
     }
 
     /// `True` if the user has the permission to use Opencast Studio.
     fn can_use_editor(&self, context: &Context) -> bool {
-        self.can_use_editor(&context.config.auth)
+        context.permissions.editor
+    }
+
+    /// `True` if the user can use GraphiQL (see `http.graphiql.access`).
+    fn can_use_graphiql(&self, context: &Context) -> bool {
+        context.config.http.graphiql.access.allows(self, &context.config.auth)
     }
 
     /// Returns all events that somehow "belong" to the user, i.e. that appear
@@ -53,4 +87,77 @@ impl User {
     ) -> ApiResult<EventConnection> {
         Event::load_writable_for_user(context, order, first, after, last, before).await
     }
+
+    /// Returns the newest notifications of the user (at most 100), newest
+    /// first.
+    #[graphql(arguments(unread_only(default = false)))]
+    async fn notifications(
+        &self,
+        unread_only: bool,
+        context: &Context,
+    ) -> ApiResult<Vec<Notification>> {
+        Notification::load_for_user(unread_only, context).await
+    }
+
+    /// Returns the saved searches of the user, newest first.
+    async fn saved_searches(&self, context: &Context) -> ApiResult<Vec<SavedSearch>> {
+        SavedSearch::load_for_user(context).await
+    }
+
+    /// Returns the series the user follows (see `followSeries`), ordered by
+    /// title.
+    async fn followed_series(&self, context: &Context) -> ApiResult<Vec<Series>> {
+        Series::load_followed(context).await
+    }
+
+    /// Active moderator delegations to the user, i.e. realms that the user
+    /// can moderate without being a moderator.
+    async fn moderator_delegations(
+        &self,
+        context: &Context,
+    ) -> ApiResult<Vec<ModeratorDelegation>> {
+        ModeratorDelegation::load_for_user(context).await
+    }
+
+    /// Interface preferences the user has set (see `setPreference`). For all
+    /// others, the frontend uses its defaults.
+    async fn preferences(&self, context: &Context) -> ApiResult<Vec<Preference>> {
+        Preference::load_for_user(context).await
+    }
+
+    /// Whether email alerts for saved searches are available. They also
+    /// require the email address of the user to be known.
+    fn can_receive_search_alerts(&self, context: &Context) -> bool {
+        context.config.saved_searches.alerts_enabled() && self.email.is_some()
+    }
+}
+
+impl User {
+    /// Removes the uploaded avatar of the given user. Users can remove their
+    /// own avatar, moderators the avatar of any user.
+    pub(crate) async fn remove_avatar(
+        username: String,
+        context: &Context,
+    ) -> ApiResult<RemovedAvatar> {
+        let is_own = context.user.as_ref().is_some_and(|user| user.username == username);
+        if !is_own {
+            context.require_moderator()?;
+        }
+
+        let affected_rows = context.db
+            .execute("delete from user_avatars where username = $1", &[&username])
+            .await?;
+        if affected_rows != 1 {
+            return Err(invalid_input!("user '{}' has no avatar", username));
+        }
+        info!("Avatar of user '{}' was removed", username);
+
+        Ok(RemovedAvatar { username })
+    }
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(Context = Context)]
+pub(crate) struct RemovedAvatar {
+    username: String,
 }
@@ -1,21 +1,44 @@
+use chrono::{DateTime, Utc};
 use juniper::graphql_object;
 
+use crate::auth::User;
 use super::{
     Context,
     err::ApiResult,
     id::Id,
     model::{
-        realm::{ChildIndex, NewRealm, Realm, RealmOrder, RemovedRealm, UpdateRealm},
+        event::{
+            Event, EventMarker, NewEventMarker, NewExternalEvent, RemovedEvent,
+            RemovedEventMarker,
+        },
+        moderator_delegation::{ModeratorDelegation, RemovedModeratorDelegation},
+        notification::Notification,
+        pending_operation::PendingOperation,
+        preference::{Preference, PreferenceKey},
+        retention::{RetentionExemption, RetentionFlag},
+        saved_search::{RemovedSavedSearch, SavedSearch},
+        series::Series,
+        setting::{RemovedSetting, Setting},
+        upload::UploadTarget,
+        user::RemovedAvatar,
+        realm::{
+            ChildIndex, NewRealm, PermissionChanges, PermissionChangeSummary, Realm, RealmOrder,
+            RemovedRealm, UpdateRealm,
+        },
         block::{
             BlockValue,
             NewTitleBlock,
             NewTextBlock,
             NewSeriesBlock,
             NewVideoBlock,
+            NewCreatorBlock,
+            NewExtensionBlock,
             UpdateTitleBlock,
             UpdateTextBlock,
             UpdateSeriesBlock,
             UpdateVideoBlock,
+            UpdateCreatorBlock,
+            UpdateExtensionBlock,
             RemovedBlock,
         },
     },
@@ -55,11 +78,40 @@ impl Mutation {
         Realm::update(id, set, context).await
     }
 
-    /// Remove a realm from the tree.
+    /// Changes the member roles of `realm` and all its descendants at once
+    /// (see `PermissionChanges`). Only moderators can do that. Use the query
+    /// `permissionChangePreview` to find out what would be changed.
+    async fn apply_permission_changes(
+        realm: Id,
+        changes: PermissionChanges,
+        context: &Context,
+    ) -> ApiResult<PermissionChangeSummary> {
+        Realm::apply_permission_changes(realm, changes, context).await
+    }
+
+    /// Remove a realm from the tree. Fails if `auth.deletion_approval` is
+    /// enabled: use `requestRealmRemoval` then.
     async fn remove_realm(id: Id, context: &Context) -> ApiResult<RemovedRealm> {
         Realm::remove(id, context).await
     }
 
+    /// Requests the removal of a realm and all its descendants. Another
+    /// moderator has to approve it with `approveOperation`.
+    async fn request_realm_removal(id: Id, context: &Context) -> ApiResult<PendingOperation> {
+        PendingOperation::request_realm_removal(id, context).await
+    }
+
+    /// Approves and executes a pending operation. Only moderators that did not
+    /// request the operation themselves can do that.
+    async fn approve_operation(id: Id, context: &Context) -> ApiResult<PendingOperation> {
+        PendingOperation::approve(id, context).await
+    }
+
+    /// Cancels a pending operation without executing it.
+    async fn cancel_operation(id: Id, context: &Context) -> ApiResult<PendingOperation> {
+        PendingOperation::cancel(id, context).await
+    }
+
     /// Adds a title block to a realm.
     ///
     /// The new block will be inserted at the given index,
@@ -112,6 +164,32 @@ impl Mutation {
         BlockValue::add_video(realm, index, block, context).await
     }
 
+    /// Adds a creator block to a realm. It lists all events that have the
+    /// given person as creator.
+    ///
+    /// See `addTitleBlock` for more details.
+    async fn add_creator_block(
+        realm: Id,
+        index: i32,
+        block: NewCreatorBlock,
+        context: &Context,
+    ) -> ApiResult<Realm> {
+        BlockValue::add_creator(realm, index, block, context).await
+    }
+
+    /// Adds a block of a compiled-in extension to a realm. Its data is
+    /// checked by the extension.
+    ///
+    /// See `addTitleBlock` for more details.
+    async fn add_extension_block(
+        realm: Id,
+        index: i32,
+        block: NewExtensionBlock,
+        context: &Context,
+    ) -> ApiResult<Realm> {
+        BlockValue::add_extension(realm, index, block, context).await
+    }
+
     /// Swap two blocks.
     async fn swap_blocks_by_index(
         realm: Id,
@@ -158,8 +236,196 @@ impl Mutation {
         BlockValue::update_video(id, set, context).await
     }
 
+    /// Update a creator block's data.
+    async fn update_creator_block(
+        id: Id,
+        set: UpdateCreatorBlock,
+        context: &Context,
+    ) -> ApiResult<BlockValue> {
+        BlockValue::update_creator(id, set, context).await
+    }
+
+    /// Update an extension block's data.
+    async fn update_extension_block(
+        id: Id,
+        set: UpdateExtensionBlock,
+        context: &Context,
+    ) -> ApiResult<BlockValue> {
+        BlockValue::update_extension(id, set, context).await
+    }
+
+    /// Restricts a block to users with any of the given roles (see
+    /// `Block.readRoles`). Pass an empty list to show it to everyone.
+    async fn set_block_read_roles(
+        id: Id,
+        roles: Vec<String>,
+        context: &Context,
+    ) -> ApiResult<BlockValue> {
+        BlockValue::set_read_roles(id, roles, context).await
+    }
+
     /// Remove a block from a realm.
     async fn remove_block(id: Id, context: &Context) -> ApiResult<RemovedBlock> {
         BlockValue::remove(id, context).await
     }
+
+    /// Registers an external video, i.e. one that is not managed by Opencast
+    /// but lives on some other server. It can then be used like any other
+    /// event, e.g. in series blocks and the search. Its metadata is
+    /// sanitized according to the `sanitize` config.
+    async fn add_external_event(event: NewExternalEvent, context: &Context) -> ApiResult<Event> {
+        Event::add_external(event, context).await
+    }
+
+    /// Removes an external event. Fails for events synced from Opencast.
+    async fn remove_external_event(id: Id, context: &Context) -> ApiResult<RemovedEvent> {
+        Event::remove_external(id, context).await
+    }
+
+    /// Adds a marker at a point in time of an event, e.g. for interactive
+    /// overlays of the player. Requires write access to the event.
+    async fn add_event_marker(
+        event: Id,
+        marker: NewEventMarker,
+        context: &Context,
+    ) -> ApiResult<EventMarker> {
+        EventMarker::add(event, marker, context).await
+    }
+
+    /// Removes a marker of an event. Requires write access to the event.
+    async fn remove_event_marker(id: Id, context: &Context) -> ApiResult<RemovedEventMarker> {
+        EventMarker::remove(id, context).await
+    }
+
+    /// Sets the passphrase of an event (see `Event.hasPassphrase`) or removes
+    /// it if `passphrase` is `null`. Requires write access to the event.
+    async fn set_event_passphrase(
+        id: Id,
+        passphrase: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Event> {
+        Event::set_passphrase(id, passphrase, context).await
+    }
+
+    /// Checks the passphrase of an event and returns a token, valid for one
+    /// hour, that unlocks the event's fields when passed as `accessToken`.
+    async fn unlock_event(id: Id, passphrase: String, context: &Context) -> ApiResult<String> {
+        Event::unlock(id, passphrase, context).await
+    }
+
+    /// Exempts an event from all retention rules and restores it if it was
+    /// retracted already. Only moderators can do that.
+    async fn exempt_from_retention(
+        event: Id,
+        context: &Context,
+    ) -> ApiResult<RetentionExemption> {
+        RetentionFlag::exempt(event, context).await
+    }
+
+    /// Marks notifications of the current user as read.
+    async fn mark_read(ids: Vec<Id>, context: &Context) -> ApiResult<Vec<Notification>> {
+        Notification::mark_read(ids, context).await
+    }
+
+    /// Saves a search query for the current user. With `emailAlerts`, the user
+    /// is notified via email about new videos matching the query. That
+    /// requires alerts to be configured and the email address of the user
+    /// to be known.
+    async fn save_search(
+        query: String,
+        email_alerts: bool,
+        context: &Context,
+    ) -> ApiResult<SavedSearch> {
+        SavedSearch::save(query, email_alerts, context).await
+    }
+
+    /// Removes a saved search of the current user.
+    async fn remove_saved_search(id: Id, context: &Context) -> ApiResult<RemovedSavedSearch> {
+        SavedSearch::remove(id, context).await
+    }
+
+    /// Follows a series: the current user is then notified about new events
+    /// in it, in the app and, with `emailNotifications`, via email. The
+    /// latter requires saved search alerts to be configured and the email
+    /// address of the user to be known.
+    #[graphql(arguments(email_notifications(default = false)))]
+    async fn follow_series(
+        id: Id,
+        email_notifications: bool,
+        context: &Context,
+    ) -> ApiResult<Series> {
+        Series::follow(id, email_notifications, context).await
+    }
+
+    /// Stops following a series (see `followSeries`).
+    async fn unfollow_series(id: Id, context: &Context) -> ApiResult<Series> {
+        Series::unfollow(id, context).await
+    }
+
+    /// Validates the series an upload should be added to, either an existing
+    /// one (`series`) or a new one (`newSeriesTitle`), and returns the
+    /// workflow and series the upload has to be ingested with. Has to be
+    /// called before finishing the ingest.
+    async fn prepare_upload(
+        series: Option<Id>,
+        new_series_title: Option<String>,
+        context: &Context,
+    ) -> ApiResult<UploadTarget> {
+        UploadTarget::prepare(series, new_series_title, context).await
+    }
+
+    /// Stores an interface preference of the current user, so that it
+    /// follows them across devices. `null` removes it. Returns all
+    /// preferences of the user.
+    async fn set_preference(
+        key: PreferenceKey,
+        value: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Vec<Preference>> {
+        Preference::set(key, value, context).await
+    }
+
+    /// Delegates moderation of `realm` and all its descendants to the user
+    /// with the given username until `expires` (at most 90 days from now).
+    /// The user can then edit these realms and their content, but not
+    /// remove `realm` itself, change member roles or pass the rights on.
+    /// Only moderators can do this.
+    async fn delegate_moderation(
+        realm: Id,
+        username: String,
+        expires: DateTime<Utc>,
+        context: &Context,
+    ) -> ApiResult<ModeratorDelegation> {
+        ModeratorDelegation::create(realm, username, expires, context).await
+    }
+
+    /// Revokes a moderator delegation before it expires. Only moderators can
+    /// do this.
+    async fn revoke_delegation(
+        id: Id,
+        context: &Context,
+    ) -> ApiResult<RemovedModeratorDelegation> {
+        ModeratorDelegation::revoke(id, context).await
+    }
+
+    /// Overrides the config value with the given key at runtime. Only some
+    /// cosmetic settings can be overridden: theme colors (e.g.
+    /// `theme.color.accent`), `general.announcement`, `general.footer_links`
+    /// and `auth.login_page.note`. `value` has to be JSON in the same format
+    /// as the value in the config file. Only admins can do this.
+    async fn set_setting(key: String, value: String, context: &Context) -> ApiResult<Setting> {
+        Setting::set(key, value, context).await
+    }
+
+    /// Removes the uploaded avatar of a user. Users can remove their own
+    /// avatar, moderators the avatar of any user.
+    async fn remove_avatar(username: String, context: &Context) -> ApiResult<RemovedAvatar> {
+        User::remove_avatar(username, context).await
+    }
+
+    /// Removes a runtime override, making the config file value effective
+    /// again. Only admins can do this.
+    async fn reset_setting(key: String, context: &Context) -> ApiResult<RemovedSetting> {
+        Setting::reset(key, context).await
+    }
 }
@@ -1,7 +1,9 @@
+use std::sync::Arc;
+
 use juniper::graphql_object;
 
 
-use crate::auth::User;
+use crate::{auth::User, extension::ExtensionQuery};
 
 use super::{
     Context,
@@ -9,10 +11,20 @@ use super::{
     NodeValue,
     err::ApiResult,
     model::{
-        realm::Realm,
-        event::Event,
-        search::{self, SearchResults},
+        block::BlockValue,
+        capture_agent::CaptureAgentStatus,
+        realm::{PageData, PermissionChanges, PermissionChangeSummary, Realm, RealmTree},
+        event::{Event, EventMarker, EventSortOrder, Location},
+        moderator_delegation::ModeratorDelegation,
+        notification::Notification,
+        pending_operation::PendingOperation,
+        retention::RetentionFlag,
+        saved_search::SavedSearch,
+        search::{self, InRealm, SearchResults},
         series::Series,
+        setting::Setting,
+        system_status::SystemStatus,
+        upload::UploadSettings,
     },
 };
 
@@ -43,9 +55,44 @@ impl Query {
         Realm::load_by_path(path, context).await
     }
 
-    /// Returns an event by its ID.
-    async fn event(id: Id, context: &Context) -> ApiResult<Option<Event>> {
-        Event::load_by_id(id, context).await
+    /// Reports how `applyPermissionChanges` with the same arguments would
+    /// change the realms, without changing anything.
+    async fn permission_change_preview(
+        realm: Id,
+        changes: PermissionChanges,
+        context: &Context,
+    ) -> ApiResult<PermissionChangeSummary> {
+        Realm::preview_permission_changes(realm, changes, context).await
+    }
+
+    /// Returns the realm with the given path (like `realmByPath`) together
+    /// with its ancestors, children, blocks and the first events of its
+    /// blocks. Loads everything needed to render a realm page with a few
+    /// queries.
+    async fn page_data(path: String, context: &Context) -> ApiResult<Option<PageData>> {
+        PageData::load(path, context).await
+    }
+
+    /// Returns all realms or, if `since` is given, only the realms that were
+    /// added, changed or removed after the `version` of a previous response.
+    /// Lets clients keep their copy of the realm tree up to date with small
+    /// responses.
+    async fn realm_tree(since: Option<String>, context: &Context) -> ApiResult<RealmTree> {
+        RealmTree::load(since, context).await
+    }
+
+    /// Returns an event by its ID. If `realmPath` is given and a video or
+    /// series block of that realm shows the event, members of the realm (see
+    /// `Realm.memberRoles`) can see the event even without read access.
+    async fn event(
+        id: Id,
+        realm_path: Option<String>,
+        context: &Context,
+    ) -> ApiResult<Option<Event>> {
+        match realm_path {
+            Some(realm_path) => Event::load_in_realm(id, realm_path, context).await,
+            None => Event::load_by_id(id, context).await,
+        }
     }
 
     /// Returns a list of all events the current user has read access to
@@ -53,6 +100,23 @@ impl Query {
         Event::load_all(context).await
     }
 
+    /// Returns all locations (e.g. lecture halls) at which events that the
+    /// current user can read were recorded.
+    async fn locations(context: &Context) -> ApiResult<Vec<Location>> {
+        Location::load_all(context).await
+    }
+
+    /// Returns all events recorded at `location` (see `Event.location`) that
+    /// the current user can read.
+    #[graphql(arguments(order(default = Default::default())))]
+    async fn events_at_location(
+        location: String,
+        order: EventSortOrder,
+        context: &Context,
+    ) -> ApiResult<Vec<Event>> {
+        Event::load_at_location(&location, order, context).await
+    }
+
     /// Returns a series by its Opencast ID
     async fn series_by_opencast_id(id: String, context: &Context) -> ApiResult<Option<Series>> {
         Series::load_by_opencast_id(id, context).await
@@ -63,6 +127,38 @@ impl Query {
         Series::load_all(context).await
     }
 
+    /// Returns all settings that override config values at runtime. Only
+    /// accessible for admins.
+    async fn settings(context: &Context) -> ApiResult<Vec<Setting>> {
+        Setting::load_all(context).await
+    }
+
+    /// Returns DB pool, sync, search index, queue and cache statistics and
+    /// the version, e.g. for monitoring. Only accessible for admins.
+    async fn system_status(context: &Context) -> ApiResult<SystemStatus> {
+        SystemStatus::load(context).await
+    }
+
+    /// Returns all operations that wait for the approval of a second
+    /// moderator. Only accessible for moderators.
+    async fn pending_operations(context: &Context) -> ApiResult<Vec<PendingOperation>> {
+        PendingOperation::load_all(context).await
+    }
+
+    /// Returns all events that matched a retention rule, ordered by the date
+    /// the rule's action is applied. Only accessible for moderators.
+    async fn retention_flags(context: &Context) -> ApiResult<Vec<RetentionFlag>> {
+        RetentionFlag::load_all(context).await
+    }
+
+    /// Returns the status of all capture agents and their current and
+    /// upcoming captures, as fetched from Opencast at most every
+    /// `capture_agents.cache_duration`. Only accessible for moderators and
+    /// users with `capture_agents.role`.
+    async fn capture_agents(context: &Context) -> ApiResult<Arc<CaptureAgentStatus>> {
+        CaptureAgentStatus::load(context).await
+    }
+
     /// Returns the current user.
     fn current_user(context: &Context) -> Option<&User> {
         context.user.as_ref()
@@ -77,18 +173,54 @@ impl Query {
         }
     }
 
-    /// Retrieve a node by globally unique ID. Mostly useful for relay.
+    /// Returns what can be configured when uploading videos, or `null` if
+    /// the current user is not allowed to upload.
+    fn upload_settings(context: &Context) -> Option<UploadSettings> {
+        context.permissions.upload.then_some(UploadSettings)
+    }
+
+    /// Retrieve a node by globally unique ID. Mostly useful for relay. Search
+    /// results cannot be retrieved that way, `null` is returned for them.
     async fn node(id: Id, context: &Context) -> ApiResult<Option<NodeValue>> {
-        match id.kind() {
-            Id::REALM_KIND => Ok(Realm::load_by_id(id, context).await?.map(NodeValue::from)),
-            Id::SERIES_KIND => Ok(Series::load_by_id(id, context).await?.map(NodeValue::from)),
-            Id::EVENT_KIND => Ok(Event::load_by_id(id, context).await?.map(NodeValue::from)),
-            _ => Ok(None),
-        }
+        let node = match id.kind() {
+            Id::REALM_KIND => Realm::load_by_id(id, context).await?.map(NodeValue::from),
+            Id::SERIES_KIND => Series::load_by_id(id, context).await?.map(NodeValue::from),
+            Id::EVENT_KIND => Event::load_by_id(id, context).await?.map(NodeValue::from),
+            Id::BLOCK_KIND => BlockValue::load_by_id(id, context).await?.map(NodeValue::from),
+            Id::NOTIFICATION_KIND => Notification::load_by_id(id, context).await?
+                .map(NodeValue::from),
+            Id::PENDING_OPERATION_KIND => PendingOperation::load_by_id(id, context).await?
+                .map(NodeValue::from),
+            Id::SAVED_SEARCH_KIND => SavedSearch::load_by_id(id, context).await?
+                .map(NodeValue::from),
+            Id::EVENT_MARKER_KIND => EventMarker::load_by_id(id, context).await?
+                .map(NodeValue::from),
+            Id::MODERATOR_DELEGATION_KIND => ModeratorDelegation::load_by_id(id, context).await?
+                .map(NodeValue::from),
+            _ => None,
+        };
+
+        Ok(node)
+    }
+
+    /// Returns `null` if the query is too short. `realm` is the path of the
+    /// realm the search was started from: its videos can be ranked higher
+    /// (see `search.realm_boost` in the config). With `inRealm`, only videos
+    /// appearing in and realms below the given realm are returned. With
+    /// `captioned`, only videos with (`true`) or without (`false`) captions
+    /// are returned.
+    async fn search(
+        query: String,
+        realm: Option<String>,
+        in_realm: Option<InRealm>,
+        captioned: Option<bool>,
+        context: &Context,
+    ) -> ApiResult<Option<SearchResults>> {
+        search::perform(&query, realm.as_deref(), in_realm, captioned, context).await
     }
 
-    /// Returns `null` if the query is too short.
-    async fn search(query: String, context: &Context) -> ApiResult<Option<SearchResults>> {
-        search::perform(&query, context).await
+    /// Fields of compiled-in extensions (see `docs/extensions.md`).
+    fn extensions() -> ExtensionQuery {
+        ExtensionQuery
     }
 }
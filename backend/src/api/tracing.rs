@@ -0,0 +1,167 @@
+//! Timings of resolvers in the Apollo tracing format, see `Tracer`.
+//!
+//! `juniper` offers no way to hook into the resolution of arbitrary fields.
+//! So only the fields of the root types are traced, by wrapping these in
+//! `Traced`. The duration of such a field includes all fields below it.
+
+use std::{sync::Mutex, time::Instant};
+
+use chrono::{DateTime, Utc};
+use juniper::{
+    Arguments, BoxFuture, ExecutionResult, Executor, GraphQLType, GraphQLValue,
+    GraphQLValueAsync, LookAheadMethods, Registry, ScalarValue,
+    meta::MetaType,
+};
+
+use crate::db::QueryTrace;
+use super::Context;
+
+
+/// Records how long resolving each field of the root types takes. Only used
+/// if requested by an admin, see `handle_api`.
+pub(crate) struct Tracer {
+    start: Instant,
+    start_time: DateTime<Utc>,
+    resolvers: Mutex<Vec<ResolverTrace>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResolverTrace {
+    path: Vec<String>,
+    parent_type: String,
+    field_name: String,
+    return_type: String,
+    /// In nanoseconds since the start of the request, like `duration`.
+    start_offset: u64,
+    duration: u64,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: Utc::now(),
+            resolvers: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns the value of `extensions.tracing`, in the format of
+    /// https://github.com/apollographql/apollo-tracing. Additionally, `sql`
+    /// contains the number of SQL queries and the timing of each.
+    pub(crate) fn finish(&self, queries: Vec<QueryTrace>) -> serde_json::Value {
+        let queries = queries.into_iter()
+            .map(|query| serde_json::json!({
+                "query": query.query,
+                "startOffset": nanos(query.start.saturating_duration_since(self.start)),
+                "duration": nanos(query.duration),
+            }))
+            .collect::<Vec<_>>();
+
+        serde_json::json!({
+            "version": 1,
+            "startTime": self.start_time,
+            "endTime": Utc::now(),
+            "duration": nanos(self.start.elapsed()),
+            "execution": {
+                "resolvers": *self.resolvers.lock().unwrap(),
+            },
+            "sql": {
+                "count": queries.len(),
+                "queries": queries,
+            },
+        })
+    }
+}
+
+fn nanos(duration: std::time::Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+/// Wrapper around a root type (`Query` or `Mutation`) that behaves exactly
+/// like it, but records the timing of each of its fields in `Context::tracer`.
+pub(crate) struct Traced<T>(pub(crate) T);
+
+impl<T, S> GraphQLType<S> for Traced<T>
+where
+    T: GraphQLType<S, TypeInfo = ()>,
+    S: ScalarValue,
+{
+    fn name(info: &()) -> Option<&str> {
+        T::name(info)
+    }
+
+    fn meta<'r>(info: &(), registry: &mut Registry<'r, S>) -> MetaType<'r, S>
+    where
+        S: 'r,
+    {
+        T::meta(info, registry)
+    }
+}
+
+impl<T, S> GraphQLValue<S> for Traced<T>
+where
+    T: GraphQLValue<S, TypeInfo = ()>,
+    S: ScalarValue,
+{
+    type Context = T::Context;
+    type TypeInfo = ();
+
+    fn type_name<'i>(&self, info: &'i ()) -> Option<&'i str> {
+        self.0.type_name(info)
+    }
+
+    fn resolve_field(
+        &self,
+        info: &(),
+        field_name: &str,
+        arguments: &Arguments<S>,
+        executor: &Executor<Self::Context, S>,
+    ) -> ExecutionResult<S> {
+        self.0.resolve_field(info, field_name, arguments, executor)
+    }
+}
+
+impl<T, S> GraphQLValueAsync<S> for Traced<T>
+where
+    T: GraphQLValueAsync<S, TypeInfo = (), Context = Context>,
+    S: ScalarValue + Send + Sync,
+{
+    fn resolve_field_async<'a>(
+        &'a self,
+        info: &'a (),
+        field_name: &'a str,
+        arguments: &'a Arguments<S>,
+        executor: &'a Executor<Self::Context, S>,
+    ) -> BoxFuture<'a, ExecutionResult<S>> {
+        let fut = self.0.resolve_field_async(info, field_name, arguments, executor);
+        let Some(tracer) = &executor.context().tracer else {
+            return fut;
+        };
+
+        // The alias, if any.
+        let response_name = executor.look_ahead().field_name().to_owned();
+
+        Box::pin(async move {
+            let before = Instant::now();
+            let out = fut.await;
+
+            let parent_type = self.0.type_name(info).unwrap_or_default();
+            let return_type = executor.schema()
+                .concrete_type_by_name(parent_type)
+                .and_then(|ty| ty.field_by_name(field_name))
+                .map(|field| field.field_type.to_string())
+                .unwrap_or_default();
+            tracer.resolvers.lock().unwrap().push(ResolverTrace {
+                path: vec![response_name],
+                parent_type: parent_type.to_owned(),
+                field_name: field_name.to_owned(),
+                return_type,
+                start_offset: nanos(before.saturating_duration_since(tracer.start)),
+                duration: nanos(before.elapsed()),
+            });
+
+            out
+        })
+    }
+}
@@ -3,7 +3,13 @@
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-use crate::{cmd, db::cmd::DbCommand, search::cmd::SearchIndexCommand};
+use crate::{
+    cmd,
+    db::cmd::DbCommand,
+    search::cmd::SearchIndexCommand,
+    telemetry::TelemetryCommand,
+    warehouse::ExportCommand,
+};
 
 
 #[derive(Debug, StructOpt)]
@@ -52,18 +58,45 @@ pub(crate) enum Command {
         shared: Shared,
     },
 
+    /// Anonymous usage statistics (telemetry) operations.
+    Telemetry {
+        #[structopt(subcommand)]
+        cmd: TelemetryCommand,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Exports the content of Tobira for external systems.
+    Export {
+        #[structopt(subcommand)]
+        cmd: ExportCommand,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
     /// Starts a worker/daemon process that performs all tasks that should be
     /// performed regularly.
     ///
-    /// This currently includes: updating the search index and syncing with
-    /// Opencast.
+    /// This currently includes: updating the search index, syncing with
+    /// Opencast, sending usage statistics (if enabled), applying retention
+    /// rules (if any), sending saved search alerts and webhooks (if
+    /// enabled).
     Worker {
         #[structopt(flatten)]
         shared: Shared,
     },
 
+    /// Generates documentation of the configuration.
+    Config {
+        #[structopt(subcommand)]
+        cmd: cmd::config::ConfigCommand,
+    },
+
     /// Outputs a template for the configuration file (which includes
-    /// descriptions or all options).
+    /// descriptions or all options). Same as `config docs --format
+    /// toml-template`.
     WriteConfig {
         /// Target file. If not specified, the template is written to stdout.
         target: Option<PathBuf>,
@@ -83,6 +116,76 @@ pub(crate) enum Command {
         #[structopt(flatten)]
         shared: Shared,
     },
+
+    /// Imports a mapping from URLs of a previous video portal to Tobira events
+    /// or realms. Requests to these URLs are redirected if
+    /// `http.legacy_redirects` is enabled.
+    ImportLegacyUrls {
+        #[structopt(flatten)]
+        options: cmd::import_legacy_urls::Args,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Generates reports about the content of Tobira.
+    Report {
+        #[structopt(subcommand)]
+        cmd: cmd::report::ReportCommand,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Manages the key used to sign JWTs for Opencast.
+    Jwt {
+        #[structopt(subcommand)]
+        cmd: cmd::jwt::JwtCommand,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Maintenance operations for administrators.
+    Admin {
+        #[structopt(subcommand)]
+        cmd: cmd::admin::AdminCommand,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Encrypts all sensitive columns (like email addresses) with the current
+    /// `db.encryption_key`, or decrypts them if none is set. Run this after
+    /// setting or changing the key, see `db.old_encryption_keys`.
+    Rekey {
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Runs GraphQL queries as a synthetic user and reports all returned
+    /// objects (events, notifications, ...) that the user should not be able
+    /// to see. Exits with an error if there are any. The DB is left unchanged.
+    /// Requires MeiliSearch to be reachable.
+    TestAccess {
+        #[structopt(flatten)]
+        options: cmd::test_access::Args,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
+
+    /// Runs representative GraphQL workloads against fixtures and reports
+    /// latency percentiles and SQL queries per operation. The DB is left
+    /// unchanged. Requires MeiliSearch to be reachable.
+    #[cfg(feature = "bench")]
+    Bench {
+        #[structopt(flatten)]
+        options: cmd::bench::Args,
+
+        #[structopt(flatten)]
+        shared: Shared,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -0,0 +1,186 @@
+//! Deprovisioning endpoint for identity management systems: when a user
+//! leaves the institution, `POST /~deprovision` removes everything Tobira
+//! stores about them. Tobira has no user accounts, so this only concerns data
+//! stored by username (sessions, avatar, saved searches, preferences,
+//! delegations) or by user role (notifications). Tobira does not store
+//! comments or other texts written by users. Content (events, series) belongs
+//! to Opencast and has to be reassigned there: events the user could write
+//! are flagged in `orphaned_events` until that change is synced. The username
+//! is replaced in records of actions of the user that have to stay, like
+//! requested removals.
+
+use deadpool_postgres::Transaction;
+use hyper::{Body, StatusCode, header};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use tokio_postgres::types::ToSql;
+
+use crate::{
+    db,
+    http::{self, Context, Request, Response},
+    metrics::{self, AuthEvent},
+    prelude::*,
+};
+
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct DeprovisionConfig {
+    /// Shared secret for `POST /~deprovision`, which identity management
+    /// systems can call when a user leaves. It has to be sent as
+    /// `Authorization: Bearer <secret>`. The body is JSON like
+    /// `{ "username": "jose", "userRole": "ROLE_USER_JOSE" }` (`userRole` is
+    /// optional). The user's sessions, avatar, saved searches, preferences,
+    /// followed series, received moderator delegations and notifications are
+    /// deleted and their username is replaced in requested removals, created
+    /// delegations and retention exemptions. Events that `userRole` can write
+    /// are flagged and logged, as they have to be reassigned in Opencast. If
+    /// not set, the endpoint is disabled.
+    pub(crate) secret: Option<Secret<String>>,
+}
+
+/// Maximum size of the request body in bytes.
+const MAX_BODY_SIZE: usize = 4 * 1024;
+
+/// Replaces the username of deprovisioned users where records have to stay.
+const DEPROVISIONED_USER: &str = "(deprovisioned)";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Deprovision {
+    username: String,
+    user_role: Option<String>,
+}
+
+/// Handles `POST /~deprovision`. Replies 200 with the number of affected
+/// rows per table as JSON, also if nothing was stored about the user.
+pub(crate) async fn handle_deprovision(req: Request<Body>, ctx: &Context) -> Response {
+    let Some(secret) = &ctx.config.auth.deprovision.secret else {
+        return http::response::forbidden();
+    };
+    let authorized = req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "))
+        .is_some_and(|token| {
+            ring::constant_time::verify_slices_are_equal(token, secret.expose_secret().as_bytes())
+                .is_ok()
+        });
+    if !authorized {
+        warn!("Rejected deprovisioning request with invalid secret");
+        return http::response::forbidden();
+    }
+
+    let Some(request) = http::body::read(req.into_body(), MAX_BODY_SIZE).await.ok()
+        .and_then(|body| serde_json::from_slice::<Deprovision>(&body).ok())
+        .filter(|request| !request.username.is_empty())
+    else {
+        return http::response::bad_request();
+    };
+
+    let mut db = match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+        Ok(db) => db,
+        Err(r) => return r,
+    };
+    match deprovision(&request, &mut db).await {
+        Ok(counts) => {
+            let fields = counts.iter()
+                .map(|(table, count)| (*table, count.to_string()))
+                .collect::<Vec<_>>();
+            let mut log_fields = vec![("username", request.username.as_str())];
+            log_fields.extend(fields.iter().map(|(table, count)| (*table, count.as_str())));
+            metrics::auth_event(AuthEvent::UserDeprovisioned, &log_fields);
+
+            let body = serde_json::Value::Object(
+                counts.into_iter().map(|(table, count)| (table.into(), count.into())).collect(),
+            );
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.to_string().into())
+                .unwrap()
+        }
+        Err(e) => {
+            error!("Failed to deprovision user '{}': {:?}", request.username, e);
+            http::response::internal_server_error()
+        }
+    }
+}
+
+/// Removes and anonymizes the data of the user in one transaction. Returns
+/// the number of affected rows per table.
+async fn deprovision(
+    request: &Deprovision,
+    db: &mut db::DbConnection,
+) -> Result<Vec<(&'static str, u64)>> {
+    let tx = db.transaction().await?;
+    let username = &request.username;
+    let replacement = &DEPROVISIONED_USER;
+
+    let mut counts = vec![];
+    let queries: &[(&str, &str, &[&(dyn ToSql + Sync)])] = &[
+        ("sessions", "delete from user_sessions where username = $1", &[username]),
+        ("avatars", "delete from user_avatars where username = $1", &[username]),
+        ("saved_searches", "delete from saved_searches where username = $1", &[username]),
+        ("preferences", "delete from user_preferences where username = $1", &[username]),
+        ("series_follows", "delete from series_follows where username = $1", &[username]),
+        ("delegations", "delete from moderator_delegations where username = $1", &[username]),
+        (
+            "delegations_created",
+            "update moderator_delegations set delegated_by = $2 where delegated_by = $1",
+            &[username, replacement],
+        ),
+        (
+            "pending_operations",
+            "update pending_operations set requested_by = $2 where requested_by = $1",
+            &[username, replacement],
+        ),
+        (
+            "retention_exemptions",
+            "update retention_exemptions set exempted_by = $2 where exempted_by = $1",
+            &[username, replacement],
+        ),
+    ];
+    for (name, sql, params) in queries {
+        counts.push((*name, tx.execute(*sql, params).await?));
+    }
+
+    let (notifications, orphaned) = match &request.user_role {
+        Some(role) => (
+            tx.execute("delete from notifications where recipient = $1", &[role]).await?,
+            flag_orphaned_events(role, &tx).await?,
+        ),
+        None => (0, 0),
+    };
+    counts.push(("notifications", notifications));
+    counts.push(("orphaned_events", orphaned));
+
+    tx.commit().await?;
+    Ok(counts)
+}
+
+/// Flags all events that `role` can write in `orphaned_events` and logs their
+/// Opencast IDs, so that they can be reassigned in Opencast. Returns the
+/// number of newly flagged events.
+async fn flag_orphaned_events(role: &str, tx: &Transaction<'_>) -> Result<u64> {
+    let rows = tx
+        .query(
+            "with flagged as ( \
+                insert into orphaned_events (event, user_role) \
+                    select id, $1 from events where write_roles @> array[$1] \
+                    on conflict do nothing \
+                    returning event \
+            ) \
+            select opencast_id from events where id in (select event from flagged)",
+            &[&role],
+        )
+        .await?;
+    if !rows.is_empty() {
+        let ids = rows.iter().map(|row| row.get::<_, &str>(0)).collect::<Vec<_>>();
+        warn!(
+            "Events writable by deprovisioned user role '{}' have to be reassigned \
+                in Opencast: {}",
+            role,
+            ids.join(", "),
+        );
+    }
+
+    Ok(rows.len() as u64)
+}
@@ -1,14 +1,27 @@
-use hyper::{Body, StatusCode};
-
-use crate::{db, http::{self, Context, Request, Response}, prelude::*};
+use hyper::{Body, HeaderMap, StatusCode};
+
+use crate::{
+    db,
+    http::{self, Context, Request, Response},
+    metrics::{self, AuthEvent},
+    prelude::*,
+    saved_search,
+    series_follow,
+};
 use super::{AuthMode, SessionId, User};
 
 
+/// Header in the response to `POST /~session` containing the number of other
+/// sessions of the user that were invalidated due to
+/// `auth.max_sessions_per_user`.
+const INVALIDATED_SESSIONS_HEADER: &str = "x-tobira-invalidated-sessions";
+
 /// Handles POST requests to `/~session`.
 pub(crate) async fn handle_login(req: Request<Body>, ctx: &Context) -> Result<Response, Response> {
     if ctx.config.auth.mode != AuthMode::LoginProxy {
         warn!("Got POST /~session request, but due to the authentication mode, this endpoint \
             is disabled");
+        metrics::auth_event(AuthEvent::LoginFailed, &[("reason", "endpoint_disabled")]);
 
         return Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -24,34 +37,99 @@ pub(crate) async fn handle_login(req: Request<Body>, ctx: &Context) -> Result<Re
             // need to create a DB session now and reply with a `set-cookie` header.
             debug!("Login request for '{}' (POST '/~session' with auth headers)", user.username);
 
-            // TODO: check if a user is already logged in? And remove that session then?
-
-            let db = db::get_conn_or_service_unavailable(&ctx.db_pool).await?;
-            let session_id = user.persist_new_session(&db).await.map_err(|e| {
-                error!("DB query failed when adding new user session: {}", e);
-                http::response::internal_server_error()
-            })?;
-            debug!("Persisted new session for '{}'", user.username);
-
-            Response::builder()
-                .status(StatusCode::NO_CONTENT)
-                .header("set-cookie", session_id.set_cookie(
-                    ctx.config.auth.session_duration
-                ).to_string())
-                .body(Body::empty())
-                .unwrap()
-                .pipe(Ok)
+            // The roles are stored as sent by the auth proxy. The role mapping
+            // is applied whenever the session is loaded.
+            create_session(user, req.headers(), ctx).await
         }
 
         None => {
             // No auth headers are set: this should not happen. This means that
             // either there is no auth proxy or it was incorrectly configured.
             warn!("Got POST /~session request without auth headers set: this should not happen");
+            metrics::auth_event(AuthEvent::LoginFailed, &[("reason", "missing_auth_headers")]);
             Err(http::response::bad_request())
         }
     }
 }
 
+/// Persists a new session for the user (replacing the one of the request, if
+/// any, and removing excess ones) and replies with a `set-cookie` header. All
+/// of that happens in one transaction, so that a failure in between doesn't
+/// leave the user with e.g. no session or too many sessions.
+pub(super) async fn create_session(
+    user: User,
+    headers: &HeaderMap,
+    ctx: &Context,
+) -> Result<Response, Response> {
+    let mut db = db::get_conn_or_service_unavailable(&ctx.db_pool).await?;
+    let tx = db.transaction().await.map_err(|e| {
+        error!("Failed to start transaction for new user session: {}", e);
+        http::response::internal_server_error()
+    })?;
+    let db = &*tx;
+
+    // If the user is already logged in in this browser, the old session is
+    // replaced instead of lingering until it expires.
+    if let Some(old_session) = SessionId::from_headers(headers) {
+        match old_session.remove_from_db(db).await {
+            Ok(Some(username)) => debug!("Removed previous session of '{}'", username),
+            Ok(None) => {}
+            Err(e) => {
+                error!("DB query failed when removing previous user session: {}", e);
+                return Err(http::response::internal_server_error());
+            }
+        }
+    }
+
+    let session_id = user.persist_new_session(&ctx.config.db, db).await.map_err(|e| {
+        error!("Failed to add new user session: {:#}", e);
+        http::response::internal_server_error()
+    })?;
+    debug!("Persisted new session for '{}'", user.username);
+
+    // Saved search alerts and series follow notifications use the roles of
+    // the latest login.
+    let roles = ctx.config.auth.role_mapping.apply(user.roles.clone());
+    if let Err(e) = saved_search::refresh_roles(&user.username, &roles, db).await {
+        error!("DB query failed when refreshing roles of saved searches: {}", e);
+        return Err(http::response::internal_server_error());
+    }
+    if let Err(e) = series_follow::refresh_roles(&user.username, &roles, db).await {
+        error!("DB query failed when refreshing roles of series follows: {}", e);
+        return Err(http::response::internal_server_error());
+    }
+
+    let mut invalidated = 0;
+    if let Some(max) = ctx.config.auth.max_sessions_per_user {
+        invalidated = user.remove_excess_sessions(max, db).await.map_err(|e| {
+            error!("DB query failed when removing excess user sessions: {}", e);
+            http::response::internal_server_error()
+        })?;
+    }
+
+    tx.commit().await.map_err(|e| {
+        error!("Failed to commit new user session: {}", e);
+        http::response::internal_server_error()
+    })?;
+    metrics::auth_event(AuthEvent::LoginSucceeded, &[("username", &user.username)]);
+    if invalidated > 0 {
+        metrics::auth_event(AuthEvent::SessionsInvalidated, &[
+            ("username", &user.username),
+            ("count", &invalidated.to_string()),
+        ]);
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("set-cookie", session_id.set_cookie(
+            ctx.config.auth.session_duration
+        ).to_string())
+        .header(INVALIDATED_SESSIONS_HEADER, invalidated)
+        .body(Body::empty())
+        .unwrap()
+        .pipe(Ok)
+}
+
 /// Handles DELETE requests to `/~session`.
 ///
 /// This checks for the session cookie. If it exists, tries to remove that
@@ -68,7 +146,7 @@ pub(crate) async fn handle_login(req: Request<Body>, ctx: &Context) -> Result<Re
 ///
 /// TODO: maybe notify the user about these failures?
 pub(crate) async fn handle_logout(req: Request<Body>, ctx: &Context) -> Response {
-    if ctx.config.auth.mode != AuthMode::LoginProxy {
+    if !ctx.config.auth.mode.uses_sessions() {
         warn!("Got DELETE /~session request, but due to the authentication mode, this endpoint \
             is disabled");
 
@@ -98,7 +176,7 @@ pub(crate) async fn handle_logout(req: Request<Body>, ctx: &Context) -> Response
         Ok(db) => db,
     };
 
-    match session_id.remove_from_db(&db).await {
+    match session_id.remove_from_db(&**db).await {
         Ok(Some(username)) => debug!("Removed session for '{}' from DB", username),
         Ok(None) => warn!("Session not found in DB during logout"),
         Err(e) => error!("DB error when removing session from DB: {}", e),
@@ -1,9 +1,9 @@
-use ring::{rand::{SecureRandom, SystemRandom}, signature::EcdsaKeyPair};
+use ring::{digest, hkdf, hmac, rand::{SecureRandom, SystemRandom}, signature::EcdsaKeyPair};
 use serde::Serialize;
 use serde_json::json;
-use std::{path::PathBuf, time::Duration};
+use std::{path::{Path, PathBuf}, time::Duration};
 
-use crate::prelude::*;
+use crate::{db::types::Key, metrics::{self, AuthEvent}, prelude::*};
 
 use super::User;
 
@@ -28,9 +28,21 @@ pub(crate) struct JwtConfig {
     ///     openssl pkcs8 -topk8 -nocrypt -in sec1.pem -out private-key.pem
     ///
     /// Here, the `sec1.pem` is encoded as SEC1 instead of PKCS#8. The second
-    /// command converts the key.
+    /// command converts the key. Alternatively, `tobira jwt key generate`
+    /// creates a suitable key at this path.
+    ///
+    /// During a key rotation (`tobira jwt key rotate`), the next key is
+    /// stored next to this one with the extension `.next`. Its public key is
+    /// already included in the JWKS, but it's not used for signing yet.
     secret_key: PathBuf,
 
+    /// How long the next key created by `tobira jwt key rotate` is published
+    /// in the JWKS before it may replace the current key. Has to be at least
+    /// as long as Opencast caches the JWKS (`jwksCacheExpiresIn`), otherwise
+    /// Opencast might reject JWTs signed with the new key.
+    #[config(default = "1h", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) rotation_grace_period: Duration,
+
 
     /// The duration for which a JWT is valid. JWTs are just used as temporary
     /// ways to authenticate against Opencast, so they just have to be valid
@@ -46,13 +58,16 @@ pub(crate) enum Algorithm {
 }
 
 impl Algorithm {
-    fn to_str(&self) -> &'static str {
+    pub(crate) fn to_str(&self) -> &'static str {
         match self {
             Algorithm::ES256 => "ES256",
         }
     }
 }
 
+/// How long a token returned by `JwtContext::new_event_access_token` is valid.
+const EVENT_ACCESS_DURATION: Duration = Duration::from_secs(60 * 60);
+
 /// Context for JWT operations that persists for runtime of Tobira.
 pub(crate) struct JwtContext {
     rng: SystemRandom,
@@ -87,15 +102,48 @@ impl JwtContext {
             "username": user.username,
             "exp": exp.timestamp(),
         });
+        metrics::auth_event(AuthEvent::JwtIssued, &[("username", &user.username)]);
 
         self.encode(&payload)
     }
 
+    /// Creates a token granting access to the passphrase protected event
+    /// `event` (see `Event.hasPassphrase`). This is not a JWT, as only Tobira
+    /// itself checks it. It is signed with a key derived from
+    /// `jwt.secret_key`, so all Tobira nodes accept it, until it expires.
+    pub(crate) fn new_event_access_token(&self, event: Key) -> String {
+        let exp = chrono::offset::Utc::now().timestamp() + EVENT_ACCESS_DURATION.as_secs() as i64;
+        let payload = format!("{}.{}", event.0, exp);
+        let tag = hmac::sign(&self.auth.event_access_key, payload.as_bytes());
+        format!("{payload}.{}", base64::encode_config(tag, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// Checks whether `token` was returned by `new_event_access_token` for
+    /// `event` and has not expired yet.
+    pub(crate) fn check_event_access_token(&self, token: &str, event: Key) -> bool {
+        let Some((payload, tag)) = token.rsplit_once('.') else {
+            return false;
+        };
+        let Ok(tag) = base64::decode_config(tag, base64::URL_SAFE_NO_PAD) else {
+            return false;
+        };
+        if hmac::verify(&self.auth.event_access_key, payload.as_bytes(), &tag).is_err() {
+            return false;
+        }
+
+        match payload.split_once('.') {
+            Some((key, exp)) => key == event.0.to_string()
+                && exp.parse::<i64>().is_ok_and(|exp| exp > chrono::offset::Utc::now().timestamp()),
+            None => false,
+        }
+    }
+
     /// Encodes the given payload as JWT.
     fn encode(&self, payload: &impl Serialize) -> String {
         let header = json!({
             "typ": "JWT",
             "alg": self.config.signing_algorithm.to_str(),
+            "kid": self.auth.kid,
         });
 
         let mut jwt = String::new();
@@ -118,28 +166,79 @@ impl JwtContext {
 }
 
 impl JwtConfig {
+    pub(crate) fn signing_algorithm(&self) -> Algorithm {
+        self.signing_algorithm
+    }
+
+    pub(crate) fn secret_key_path(&self) -> &Path {
+        &self.secret_key
+    }
+
+    /// Path of the key that replaces `secret_key` at the end of a rotation.
+    pub(crate) fn next_key_path(&self) -> PathBuf {
+        let mut path = self.secret_key.clone().into_os_string();
+        path.push(".next");
+        path.into()
+    }
+
+    /// Loads the secret key (and the next key, if any) and returns the JWKS
+    /// containing the public keys.
+    pub(crate) fn load_jwks(&self) -> Result<String> {
+        self.load_auth().map(|auth| auth.jwks)
+    }
+
     fn load_auth(&self) -> Result<JwtAuth> {
-        let pem_encoded = std::fs::read(&self.secret_key)
-            .context("could not load secret key file")?;
-        let pem = pem::parse(pem_encoded)
-            .context("secret key file is not a valid PEM encoded key")?;
+        let key = read_pem(&self.secret_key)?;
+        let next_path = self.next_key_path();
+        let next_key = if next_path.exists() {
+            Some(read_pem(&next_path).context("failed to load next key of rotation")?)
+        } else {
+            None
+        };
 
         match self.signing_algorithm {
-            algo @ Algorithm::ES256 => JwtAuth::load_es(algo, &pem.contents),
+            algo @ Algorithm::ES256 => JwtAuth::load_es(algo, &key, next_key.as_deref()),
         }
     }
 }
 
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    let pem_encoded = std::fs::read(path)
+        .with_context(|| format!("could not load secret key file '{}'", path.display()))?;
+    let pem = pem::parse(pem_encoded)
+        .with_context(|| format!("'{}' is not a valid PEM encoded key", path.display()))?;
+    Ok(pem.contents)
+}
+
+/// Generates a new secret key for `algo` in the format expected in
+/// `jwt.secret_key`.
+pub(crate) fn generate_secret_key(algo: Algorithm) -> Result<String> {
+    let ring_algo = match algo {
+        Algorithm::ES256 => &ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+    };
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(ring_algo, &SystemRandom::new())
+        .map_err(|_| anyhow!("failed to generate key"))?;
+
+    Ok(pem::encode(&pem::Pem {
+        tag: "PRIVATE KEY".into(),
+        contents: pkcs8.as_ref().to_vec(),
+    }))
+}
 
 
 struct JwtAuth {
     signer: Box<dyn Signer>,
+    /// Key ID of the signing key, sent in the JWT header so that Opencast
+    /// knows which key of the JWKS to use.
+    kid: String,
     jwks: String,
+    event_access_key: hmac::Key,
 }
 
 impl JwtAuth {
-    /// Loads an elliptic curve key. `algo` has to be `ES256`!
-    fn load_es(algo: Algorithm, key: &[u8]) -> Result<JwtAuth> {
+    /// Loads an elliptic curve key. `algo` has to be `ES256`! The public key
+    /// of `next_key` is only added to the JWKS.
+    fn load_es(algo: Algorithm, key: &[u8], next_key: Option<&[u8]>) -> Result<JwtAuth> {
         use elliptic_curve::pkcs8::DecodePrivateKey;
 
         // Create a `ring` key pair that is used for signing.
@@ -150,47 +249,71 @@ impl JwtAuth {
         let ring_key = EcdsaKeyPair::from_pkcs8(ring_algo, key).map_err(|e| {
             anyhow!("`jwt.secret_key` is not a valid ECDSA keypair in PKCS8 format: {}", e)
         })?;
+        if let Some(next_key) = next_key {
+            EcdsaKeyPair::from_pkcs8(ring_algo, next_key).map_err(|e| {
+                anyhow!("next key is not a valid ECDSA keypair in PKCS8 format: {}", e)
+            })?;
+        }
 
-        // Create the JWK(S) from the given key for the public route.
-        let jwk = match algo {
+        // Create the JWK(S) from the given keys for the public route.
+        let to_jwk = |key: &[u8]| match algo {
             Algorithm::ES256 => <elliptic_curve::SecretKey<p256::NistP256>>::from_pkcs8_der(key)
                 .expect("failed to read ECDSA keypair, but it worked with `ring`?!")
                 .public_key()
                 .to_jwk(),
         };
+        let jwks = std::iter::once(key).chain(next_key)
+            .map(|key| Jwk::new(algo, to_jwk(key)))
+            .collect::<Vec<_>>();
 
 
+        // The secret key is also used for event access tokens, but with a
+        // derived key, so that these can never be confused with signatures.
+        let event_access_key = hkdf::Salt::new(hkdf::HKDF_SHA256, b"tobira")
+            .extract(key)
+            .expand(&[b"event-access-token"], hmac::HMAC_SHA256)
+            .expect("HMAC key length is too large for HKDF")
+            .into();
+
         Ok(Self {
             signer: Box::new(ring_key),
-            jwks: jwk_to_jwks(algo, jwk),
+            kid: jwks[0].kid.clone(),
+            jwks: serde_json::to_string(&json!({ "keys": jwks })).expect("failed to serialize JWKS"),
+            event_access_key,
         })
     }
 }
 
-/// Serializes the given `jwk` from `elliptic_curve` into the expected JWKS structure.
-fn jwk_to_jwks(algo: Algorithm, jwk: impl Serialize) -> String {
-    #[derive(Serialize)]
-    struct Jwk<T: Serialize> {
-        #[serde(flatten)]
-        inner: T,
-
-        r#use: &'static str,
-        alg: &'static str,
-    }
+/// A public key in the JWKS.
+#[derive(Serialize)]
+struct Jwk<T: Serialize> {
+    #[serde(flatten)]
+    inner: T,
 
-    #[derive(Serialize)]
-    struct Jwks<T: Serialize> {
-        keys: [Jwk<T>; 1],
-    }
+    r#use: &'static str,
+    alg: &'static str,
+    kid: String,
+}
 
-    let jwks = Jwks {
-        keys: [Jwk {
+impl<T: Serialize> Jwk<T> {
+    /// Wraps the given `jwk` from `elliptic_curve`. The key ID is its
+    /// thumbprint as defined in RFC 7638.
+    fn new(algo: Algorithm, jwk: T) -> Self {
+        let value = serde_json::to_value(&jwk).expect("failed to serialize JWK");
+        // The required members in lexicographic order, without whitespace.
+        let canonical = format!(
+            r#"{{"crv":{},"kty":{},"x":{},"y":{}}}"#,
+            value["crv"], value["kty"], value["x"], value["y"],
+        );
+        let hash = digest::digest(&digest::SHA256, canonical.as_bytes());
+
+        Self {
             inner: jwk,
             r#use: "sig",
             alg: algo.to_str(),
-        }]
-    };
-    serde_json::to_string(&jwks).expect("failed to serialize JWKS")
+            kid: base64::encode_config(hash, base64::URL_SAFE_NO_PAD),
+        }
+    }
 }
 
 /// A signature algorithm with corresponding key. Can sign a message.
@@ -1,20 +1,38 @@
-use std::{borrow::Cow, time::Duration};
+use std::{
+    borrow::Cow,
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use deadpool_postgres::Client;
-use hyper::HeaderMap;
+use hyper::{HeaderMap, header::HeaderValue};
 use once_cell::sync::Lazy;
-use tokio_postgres::Error as PgError;
-
-use crate::{config::TranslatedString, prelude::*};
+use postgres_types::ToSql;
+use tokio_postgres::{Error as PgError, GenericClient, Row};
+
+use crate::{
+    config::{Config, TranslatedString},
+    db::{DbConfig, Transaction, crypt::{self, Column}, types::Key},
+    http::error_report,
+    metrics::{self, AuthEvent},
+    prelude::*,
+};
 
 
+mod deprovision;
 mod handlers;
 mod session_id;
 mod jwt;
+mod opencast;
+mod role_mapping;
 
 pub(crate) use self::{
     session_id::SessionId,
-    jwt::{JwtConfig, JwtContext},
+    deprovision::{DeprovisionConfig, handle_deprovision},
+    jwt::{JwtConfig, JwtContext, generate_secret_key},
+    opencast::{OpencastLoginConfig, handle_opencast_login},
+    role_mapping::RoleMappingConfig,
     handlers::{handle_login, handle_logout},
 };
 
@@ -23,7 +41,14 @@ pub(crate) use self::{
 /// administrator.
 pub(crate) const ROLE_ADMIN: &str = "ROLE_ADMIN";
 
-const ROLE_ANONYMOUS: &str = "ROLE_ANONYMOUS";
+pub(crate) const ROLE_ANONYMOUS: &str = "ROLE_ANONYMOUS";
+
+/// Opencast gives this role to all logged in users.
+pub(crate) const ROLE_USER: &str = "ROLE_USER";
+
+/// Opencast gives each user a role with this prefix followed by the username.
+/// In-app notifications are addressed to these roles.
+pub(crate) const USER_ROLE_PREFIX: &str = "ROLE_USER_";
 
 const SESSION_COOKIE: &str = "tobira-session";
 
@@ -39,6 +64,9 @@ pub(crate) struct AuthConfig {
     ///   proxy in front of every route, passing user info via auth headers.
     /// - "login-proxy": Tobira does its own session handling and expects the auth
     ///    system to send `POST /~session` with auth headers to create a session.
+    /// - "opencast": Tobira does its own session handling and checks the
+    ///   credentials entered on its login page against Opencast (see
+    ///   `auth.opencast_login`). No auth proxy is needed.
     ///
     /// **Important**: in either case, you HAVE to make sure to remove all auth
     /// headers from incoming user requests before passing them on to Tobira!
@@ -63,10 +91,53 @@ pub(crate) struct AuthConfig {
     #[config(default = "x-tobira-user-display-name")]
     pub(crate) display_name_header: String,
 
+    /// If enabled and the display name header is not set, the username is
+    /// used as display name. Otherwise, such requests are treated as if no
+    /// user is logged in. Only enable this if your auth proxy cannot provide
+    /// a display name.
+    #[config(default = false)]
+    pub(crate) display_name_fallback: bool,
+
     /// The header containing a comma-separated list of roles of the current user.
     #[config(default = "x-tobira-user-roles")]
     pub(crate) roles_header: String,
 
+    /// The header containing the email address of the current user, e.g.
+    /// "x-tobira-user-email". Only set this if your auth proxy removes this
+    /// header from incoming requests, like the other auth headers! If not
+    /// set, users have no email address. Values containing whitespace, `,`
+    /// or `;` are ignored.
+    pub(crate) email_header: Option<String>,
+
+    /// URL template for avatar images of users, e.g.
+    /// "https://www.gravatar.com/avatar/{hash}?d=identicon". `{hash}` is
+    /// replaced with the hex encoded SHA-256 hash of the user's trimmed and
+    /// lowercased email address. If not set, no avatars are shown. Avatars
+    /// uploaded by users take precedence.
+    pub(crate) avatar_url: Option<String>,
+
+    /// Whether users can upload their own avatar image (PNG or JPEG) via
+    /// `POST /~avatar` with the `X-Requested-With` header set. Images are
+    /// stored with at most 512x512 pixels and 256 KiB. Larger ones are
+    /// resized if `http.thumbnails.converter` is set and rejected otherwise.
+    /// Moderators can remove avatars.
+    #[config(default = false)]
+    pub(crate) avatar_upload: bool,
+
+    /// How the values of the auth headers are encoded. Possible values:
+    ///
+    /// - "base64": base64 with the URL-safe alphabet. Recommended, as it can
+    ///   represent any Unicode string.
+    /// - "url": percent-encoded UTF-8, e.g. `J%C3%BCrgen`.
+    /// - "raw": the value is used as is. Only printable ASCII characters are
+    ///   allowed; headers containing anything else are rejected.
+    #[config(default = "base64")]
+    pub(crate) header_encoding: HeaderEncoding,
+
+    /// Normalization of the roles from the roles header.
+    #[config(nested)]
+    pub(crate) role_mapping: RoleMappingConfig,
+
     /// If a user has this role, they are treated as a moderator in Tobira,
     /// giving them the ability to modify the realm structure among other
     /// things.
@@ -88,11 +159,43 @@ pub(crate) struct AuthConfig {
     #[config(default = "ROLE_TOBIRA_EDITOR")]
     pub(crate) editor_role: String,
 
+    /// If a user has this role, they are allowed to download all videos of a
+    /// series as ZIP archive (if `http.series_download` is enabled). Set this
+    /// to "ROLE_ANONYMOUS" to allow it for everyone.
+    #[config(default = "ROLE_TOBIRA_DOWNLOAD")]
+    pub(crate) download_role: String,
+
+    /// If enabled, removing a realm (with all its descendants) has to be
+    /// requested by one moderator and approved by another one. This protects
+    /// against accidental or malicious removal of large parts of the page
+    /// tree.
+    #[config(default = false)]
+    pub(crate) deletion_approval: bool,
+
+    /// How long a requested removal can be approved before it expires.
+    /// Only relevant if `deletion_approval` is enabled.
+    #[config(default = "7d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) deletion_approval_expiry: Duration,
+
     /// Duration of a Tobira-managed login session.
-    /// Note: This is only relevant if `auth.mode` is `login-proxy`.
+    /// Note: This is only relevant if `auth.mode` is `login-proxy` or
+    /// `opencast`.
     #[config(default = "30d", deserialize_with = crate::config::deserialize_duration)]
     pub(crate) session_duration: Duration,
 
+    /// Maximum number of concurrent sessions per user. When a user logs in
+    /// and already has this many sessions, their oldest sessions are
+    /// invalidated. By default, there is no limit. Regardless of this
+    /// setting, logging in with an existing session cookie replaces that
+    /// session. Note: This is only relevant if `auth.mode` is `login-proxy` or
+    /// `opencast`.
+    pub(crate) max_sessions_per_user: Option<u32>,
+
+    /// How long in-app notifications (e.g. about finished uploads) are kept.
+    /// Older ones are deleted, regardless of whether they have been read.
+    #[config(default = "90d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) notification_lifetime: Duration,
+
     /// Configuration related to the built-in login page.
     #[config(nested)]
     pub(crate) login_page: LoginPageConfig,
@@ -102,6 +205,30 @@ pub(crate) struct AuthConfig {
     /// user sessions.
     #[config(nested)]
     pub(crate) jwt: JwtConfig,
+
+    /// Configuration for checking credentials against Opencast. Only relevant
+    /// if `auth.mode` is `opencast`.
+    #[config(nested)]
+    pub(crate) opencast_login: OpencastLoginConfig,
+
+    /// Configuration for the deprovisioning endpoint for identity management
+    /// systems.
+    #[config(nested)]
+    pub(crate) deprovision: DeprovisionConfig,
+}
+
+impl AuthConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.max_sessions_per_user == Some(0) {
+            bail!("`auth.max_sessions_per_user` must not be 0");
+        }
+        if !self.opencast_login.endpoint.starts_with('/') {
+            bail!("`auth.opencast_login.endpoint` has to start with '/'");
+        }
+        self.role_mapping.validate()?;
+
+        Ok(())
+    }
 }
 
 /// Authentification and authorization
@@ -124,6 +251,48 @@ pub(crate) enum AuthMode {
     None,
     FullAuthProxy,
     LoginProxy,
+    Opencast,
+}
+
+impl AuthMode {
+    /// Whether Tobira manages sessions itself, i.e. users are identified by
+    /// a session cookie.
+    pub(crate) fn uses_sessions(self) -> bool {
+        matches!(self, Self::LoginProxy | Self::Opencast)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum HeaderEncoding {
+    Base64,
+    Url,
+    Raw,
+}
+
+impl HeaderEncoding {
+    /// Decodes the header value. On error, returns a stable reason.
+    fn decode(self, value: &HeaderValue) -> Result<String, &'static str> {
+        match self {
+            Self::Base64 => {
+                let decoded = base64decode(value.as_bytes()).map_err(|_| "invalid_base64")?;
+                String::from_utf8(decoded).map_err(|_| "invalid_utf8")
+            }
+            Self::Url => percent_encoding::percent_decode(value.as_bytes())
+                .decode_utf8()
+                .map(|s| s.into_owned())
+                .map_err(|_| "invalid_utf8"),
+            Self::Raw => {
+                let is_printable = |b: &u8| (b' '..=b'~').contains(b);
+                if !value.as_bytes().iter().all(is_printable) {
+                    return Err("invalid_raw_value");
+                }
+
+                // Can't fail as we just checked that it's ASCII.
+                Ok(value.to_str().unwrap().to_owned())
+            }
+        }
+    }
 }
 
 /// Data about a user.
@@ -131,6 +300,7 @@ pub(crate) enum AuthMode {
 pub(crate) struct User {
     pub(crate) username: String,
     pub(crate) display_name: String,
+    pub(crate) email: Option<String>,
     pub(crate) roles: Vec<String>,
 }
 
@@ -148,37 +318,60 @@ impl User {
     /// configuration.
     pub(crate) async fn new(
         headers: &HeaderMap,
-        auth_config: &AuthConfig,
+        config: &Config,
         db: &Client,
     ) -> Result<Option<Self>, PgError> {
-        match auth_config.mode {
-            AuthMode::None => Ok(None),
-            AuthMode::FullAuthProxy => Ok(Self::from_auth_headers(headers, auth_config).into()),
-            AuthMode::LoginProxy => Self::from_session(headers, db, auth_config.session_duration)
-                .await
-                .map(Into::into),
-        }
+        let auth_config = &config.auth;
+        let user = match auth_config.mode {
+            AuthMode::None => None,
+            AuthMode::FullAuthProxy => Self::from_auth_headers(headers, auth_config),
+            AuthMode::LoginProxy | AuthMode::Opencast => {
+                Self::from_session(headers, db, config).await?
+            }
+        };
+
+        Ok(user.map(|mut user| {
+            user.roles = auth_config.role_mapping.apply(user.roles);
+            user
+        }))
     }
 
     /// Tries to read user data auth headers (`x-tobira-username`, ...). If the
-    /// username or display name are not defined, returns `None`.
+    /// username or display name are not defined, returns `None`. The roles
+    /// are returned as is, i.e. `auth.role_mapping` is not applied yet.
     pub(crate) fn from_auth_headers(headers: &HeaderMap, auth_config: &AuthConfig) -> Option<Self> {
-        // Helper function to read and base64 decode a header value.
+        // Helper function to read and decode a header value.
         let get_header = |header_name: &str| -> Option<String> {
             let value = headers.get(header_name)?;
-            let decoded = base64decode(value.as_bytes())
-                .map_err(|e| warn!("header '{}' is set but not valid base64: {}", header_name, e))
-                .ok()?;
-
-            String::from_utf8(decoded)
-                .map_err(|e| warn!("header '{}' is set but decoded base64 is not UTF8: {}", header_name, e))
+            auth_config.header_encoding.decode(value)
+                .map_err(|reason| metrics::auth_event(
+                    AuthEvent::MalformedAuthHeader,
+                    &[("header", header_name), ("reason", reason)],
+                ))
                 .ok()
         };
 
         // Get required headers. If these are not set and valid, we treat it as
         // if there is no user session.
         let username = get_header(&auth_config.username_header)?;
-        let display_name = get_header(&auth_config.display_name_header)?;
+        let display_name = match get_header(&auth_config.display_name_header) {
+            Some(display_name) => display_name,
+            None if auth_config.display_name_fallback => {
+                static WARNED: AtomicBool = AtomicBool::new(false);
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "Display name header '{}' is not set (user '{}'): using usernames \
+                            as display names (further occurrences are only logged as debug)",
+                        auth_config.display_name_header,
+                        username,
+                    );
+                } else {
+                    debug!("Display name header not set: using username '{username}'");
+                }
+                username.clone()
+            }
+            None => return None,
+        };
 
         // Get roles from the user. If the header is not set, the user simply has no extra roles.
         let mut roles = vec![ROLE_ANONYMOUS.to_string()];
@@ -186,15 +379,28 @@ impl User {
             roles.extend(roles_raw.split(',').map(|role| role.trim().to_owned()));
         };
 
-        Some(Self { username, display_name, roles })
+        let email = auth_config.email_header.as_deref()
+            .and_then(get_header)
+            .filter(|email| {
+                let valid = is_valid_email(email);
+                if !valid {
+                    metrics::auth_event(
+                        AuthEvent::MalformedAuthHeader,
+                        &[("header", "email"), ("reason", "invalid_email")],
+                    );
+                }
+                valid
+            });
+
+        Some(Self { username, display_name, email, roles })
     }
 
     /// Tries to load user data from a DB session referred to in a session
-    /// cookie. Should only be called if the auth mode is `LoginProxy`.
+    /// cookie. Should only be called if `AuthMode::uses_sessions` is true.
     async fn from_session(
         headers: &HeaderMap,
         db: &Client,
-        session_duration: Duration,
+        config: &Config,
     ) -> Result<Option<Self>, PgError> {
         // Try to get a session ID from the cookie.
         let session_id = match SessionId::from_headers(headers) {
@@ -203,25 +409,54 @@ impl User {
         };
 
         // Check if such a session exists in the DB.
-        let sql = "select username, display_name, roles from user_sessions \
+        let sql = "select username, display_name, email, roles from user_sessions \
             where id = $1 \
-            and extract(epoch from now() - created) < $2";
-        let row = match db.query_opt(sql, &[&session_id, &session_duration.as_secs_f64()]).await? {
-            None => return Ok(None),
+            and extract(epoch from now() - created)::float8 < $2";
+        // This is executed for nearly every request, so we use the statement
+        // cache of the connection.
+        let statement = db.prepare_cached(sql).await?;
+        let session_duration = config.auth.session_duration.as_secs_f64();
+        let row = db.query_opt(&statement, &[&session_id, &session_duration]).await?;
+        let row = match row {
+            None => {
+                metrics::auth_event(
+                    AuthEvent::InvalidSessionCookie,
+                    &[("reason", "unknown_or_expired")],
+                );
+                return Ok(None);
+            }
             Some(row) => row,
         };
 
+        // Without the email address, only a few features are unavailable, so
+        // the session is still used.
+        let email = row.get::<_, Option<&str>>(2)
+            .map(|email| crypt::decrypt(&config.db, Column::SESSION_EMAIL, email))
+            .transpose()
+            .unwrap_or_else(|e| {
+                error!("Failed to decrypt email address of user session: {:#}", e);
+                None
+            });
+
         Ok(Some(Self {
             username: row.get(0),
             display_name: row.get(1),
-            roles: row.get(2),
+            email,
+            roles: row.get(3),
         }))
     }
 
     /// Creates a new session for this user and persists it in the database.
-    /// Should only be called if the auth mode is `LoginProxy`.
-    pub(crate) async fn persist_new_session(&self, db: &Client) -> Result<SessionId, PgError> {
+    /// Should only be called if `AuthMode::uses_sessions` is true.
+    pub(crate) async fn persist_new_session(
+        &self,
+        db_config: &DbConfig,
+        db: &impl GenericClient,
+    ) -> Result<SessionId> {
         let session_id = SessionId::new();
+        let email = self.email.as_deref()
+            .map(|email| crypt::encrypt(db_config, Column::SESSION_EMAIL, email))
+            .transpose()?;
 
         // A collision is so unfathomably unlikely that we don't check for it
         // here. We just pass the error up and respond with 500. Note that
@@ -229,13 +464,42 @@ impl User {
         // never compromised.
         db.execute_raw(
             "insert into \
-                user_sessions (id, username, display_name, roles) \
-                values ($1, $2, $3, $4)",
-            dbargs![&session_id, &self.username, &self.display_name, &self.roles],
+                user_sessions (id, username, display_name, email, roles) \
+                values ($1, $2, $3, $4, $5)",
+            dbargs![&session_id, &self.username, &self.display_name, &email, &self.roles],
         ).await?;
 
         Ok(session_id)
     }
+
+    /// Removes the oldest sessions of this user so that at most `max` remain.
+    /// Returns the number of removed sessions.
+    pub(crate) async fn remove_excess_sessions(
+        &self,
+        max: u32,
+        db: &impl GenericClient,
+    ) -> Result<u64, PgError> {
+        db.execute(
+            "delete from user_sessions \
+                where username = $1 \
+                and id not in (\
+                    select id from user_sessions \
+                    where username = $1 \
+                    order by created desc \
+                    limit $2\
+                )",
+            &[&self.username, &i64::from(max)],
+        ).await
+    }
+
+    /// Returns the URL of the user's avatar according to `auth.avatar_url`, or
+    /// `None` if that is not configured or the user has no email address.
+    pub(crate) fn compute_avatar_url(&self, auth_config: &AuthConfig) -> Option<String> {
+        let template = auth_config.avatar_url.as_ref()?;
+        let email = self.email.as_ref()?.trim().to_lowercase();
+        let hash = ring::digest::digest(&ring::digest::SHA256, email.as_bytes());
+        Some(template.replace("{hash}", &hex::encode(hash)))
+    }
 }
 
 
@@ -254,6 +518,106 @@ impl AuthToken {
     }
 }
 
+/// What a user is allowed to do, derived from their roles and the auth
+/// config. Computed once per API request (see `api::Context::permissions`)
+/// instead of checking the roles again in every resolver.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Permissions {
+    pub(crate) admin: bool,
+    pub(crate) moderator: bool,
+    pub(crate) upload: bool,
+    pub(crate) studio: bool,
+    pub(crate) editor: bool,
+}
+
+impl Permissions {
+    pub(crate) fn new(user: &impl HasRoles, auth_config: &AuthConfig) -> Self {
+        Self {
+            admin: user.is_admin(),
+            moderator: user.is_moderator(auth_config),
+            upload: user.can_upload(auth_config),
+            studio: user.can_use_studio(auth_config),
+            editor: user.can_use_editor(auth_config),
+        }
+    }
+
+    /// Returns an auth token IF the user is a Tobira moderator (as determined
+    /// by `config.moderator_role`).
+    pub(crate) fn require_moderator(&self) -> Option<AuthToken> {
+        AuthToken::some_if(self.moderator)
+    }
+
+    /// Returns an auth token IF the user is a global Opencast administrator.
+    pub(crate) fn require_admin(&self) -> Option<AuthToken> {
+        AuthToken::some_if(self.admin)
+    }
+
+    pub(crate) fn require_upload(&self) -> Option<AuthToken> {
+        AuthToken::some_if(self.upload)
+    }
+}
+
+/// A DB handle that delegations can be checked with: the transaction of an
+/// API request or the connection of one of the other HTTP handlers.
+pub(crate) trait DelegationDb: Sync {
+    fn query_one<'a>(
+        &'a self,
+        query: &'a str,
+        params: &'a [&'a (dyn ToSql + Sync)],
+    ) -> impl Future<Output = Result<Row, PgError>> + Send + 'a;
+}
+
+impl DelegationDb for Transaction {
+    fn query_one<'a>(
+        &'a self,
+        query: &'a str,
+        params: &'a [&'a (dyn ToSql + Sync)],
+    ) -> impl Future<Output = Result<Row, PgError>> + Send + 'a {
+        Transaction::query_one(self, query, params)
+    }
+}
+
+impl DelegationDb for Client {
+    fn query_one<'a>(
+        &'a self,
+        query: &'a str,
+        params: &'a [&'a (dyn ToSql + Sync)],
+    ) -> impl Future<Output = Result<Row, PgError>> + Send + 'a {
+        tokio_postgres::Client::query_one(self, query, params)
+    }
+}
+
+/// Returns an auth token IF moderation of `realm` or one of its ancestors was
+/// delegated to `user` and that delegation has not expired yet. This does not
+/// check `Permissions::moderator`, see `api::Context::require_realm_moderator`.
+pub(crate) async fn require_delegated_moderator(
+    db: &impl DelegationDb,
+    user: &User,
+    realm: Key,
+) -> Result<Option<AuthToken>, PgError> {
+    let delegated = db
+        .query_one(
+            "select exists(\
+                select 1 from moderator_delegations \
+                where username = $1 \
+                and expires > now() \
+                and realm in (select id from ancestors_of_realm($2))\
+            )",
+            &[&user.username, &realm],
+        )
+        .await?
+        .get::<_, bool>(0);
+
+    Ok(AuthToken::some_if(delegated))
+}
+
+/// Returns `false` for email addresses that could be interpreted as several
+/// addresses or headers, e.g. when sending alert emails. This is not a full
+/// validation.
+pub(crate) fn is_valid_email(email: &str) -> bool {
+    !email.is_empty() && !email.contains(|c: char| c.is_whitespace() || c == ',' || c == ';')
+}
+
 // Our base64 decoding with the URL safe character set.
 fn base64decode(input: impl AsRef<[u8]>) -> Result<Vec<u8>, base64::DecodeError> {
     base64::decode_config(input, base64::URL_SAFE)
@@ -267,24 +631,6 @@ pub(crate) trait HasRoles {
     /// Returns the role of the user.
     fn roles(&self) -> &[String];
 
-    /// Returns an auth token IF this user is a Tobira moderator (as determined
-    /// by `config.moderator_role`).
-    fn require_moderator(&self, auth_config: &AuthConfig) -> Option<AuthToken> {
-        AuthToken::some_if(self.is_moderator(auth_config))
-    }
-
-    fn required_upload_permission(&self, auth_config: &AuthConfig) -> Option<AuthToken> {
-        AuthToken::some_if(self.can_upload(auth_config))
-    }
-
-    fn required_studio_permission(&self, auth_config: &AuthConfig) -> Option<AuthToken> {
-        AuthToken::some_if(self.can_use_studio(auth_config))
-    }
-
-    fn required_editor_permission(&self, auth_config: &AuthConfig) -> Option<AuthToken> {
-        AuthToken::some_if(self.can_use_editor(auth_config))
-    }
-
     fn is_moderator(&self, auth_config: &AuthConfig) -> bool {
         self.is_admin() || self.roles().contains(&auth_config.moderator_role)
     }
@@ -301,6 +647,10 @@ pub(crate) trait HasRoles {
         self.is_moderator(auth_config) || self.roles().contains(&auth_config.editor_role)
     }
 
+    fn can_download(&self, auth_config: &AuthConfig) -> bool {
+        self.is_moderator(auth_config) || self.roles().contains(&auth_config.download_role)
+    }
+
     /// Returns `true` if the user is a global Opencast administrator and can do
     /// anything.
     fn is_admin(&self) -> bool {
@@ -328,21 +678,77 @@ impl HasRoles for User {
 
 /// Long running task to perform various DB maintenance.
 pub(crate) async fn db_maintenance(db: &Client, config: &AuthConfig) {
-    /// Delete outdated user sessions every hour. Note that the session
-    /// expiration time is still checked whenever the session is validated. So
-    /// this duration is not about correctness, just about how often to clean
-    /// up.
+    /// Delete outdated user sessions, expired moderator delegations, old
+    /// notifications and old frontend error reports every hour. Note that the expiration time is still
+    /// checked whenever a session or delegation is used. So this duration is
+    /// not about correctness, just about how often to clean up.
     const RUN_PERIOD: Duration = Duration::from_secs(60 * 60);
 
     loop {
         // Remove outdated user sessions.
-        let sql = "delete from user_sessions where extract(epoch from now() - created) > $1";
+        let sql = "delete from user_sessions where extract(epoch from now() - created)::float8 > $1";
         match db.execute(sql, &[&config.session_duration.as_secs_f64()]).await {
             Err(e) => error!("Error deleting outdated user sessions: {}", e),
             Ok(0) => debug!("No outdated user sessions found in DB"),
             Ok(num) => info!("Deleted {num} outdated user sessions from DB"),
         }
 
+        // Remove expired moderator delegations. They are already ignored when
+        // checking permissions.
+        let sql = "delete from moderator_delegations where expires <= now()";
+        match db.execute(sql, &[]).await {
+            Err(e) => error!("Error deleting expired moderator delegations: {}", e),
+            Ok(0) => debug!("No expired moderator delegations found in DB"),
+            Ok(num) => info!("Deleted {num} expired moderator delegations from DB"),
+        }
+
+        // Remove old notifications.
+        let sql = "delete from notifications where extract(epoch from now() - created)::float8 > $1";
+        match db.execute(sql, &[&config.notification_lifetime.as_secs_f64()]).await {
+            Err(e) => error!("Error deleting old notifications: {}", e),
+            Ok(0) => debug!("No old notifications found in DB"),
+            Ok(num) => info!("Deleted {num} old notifications from DB"),
+        }
+
+        // Remove old frontend error reports.
+        let sql = "delete from frontend_errors where extract(epoch from now() - received)::float8 > $1";
+        match db.execute(sql, &[&error_report::LIFETIME.as_secs_f64()]).await {
+            Err(e) => error!("Error deleting old frontend error reports: {}", e),
+            Ok(0) => debug!("No old frontend error reports found in DB"),
+            Ok(num) => info!("Deleted {num} old frontend error reports from DB"),
+        }
+
         tokio::time::sleep(RUN_PERIOD).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+    use super::{HeaderEncoding, is_valid_email};
+
+    #[test]
+    fn decode_header_values() {
+        let decode = |encoding: HeaderEncoding, value: &'static str| {
+            encoding.decode(&HeaderValue::from_bytes(value.as_bytes()).unwrap())
+        };
+
+        assert_eq!(decode(HeaderEncoding::Base64, "SsO8cmdlbg=="), Ok("Jürgen".into()));
+        assert_eq!(decode(HeaderEncoding::Base64, "Jürgen"), Err("invalid_base64"));
+        assert_eq!(decode(HeaderEncoding::Url, "J%C3%BCrgen"), Ok("Jürgen".into()));
+        assert_eq!(decode(HeaderEncoding::Url, "J%FCrgen"), Err("invalid_utf8"));
+        assert_eq!(decode(HeaderEncoding::Raw, "ROLE_A, ROLE_B"), Ok("ROLE_A, ROLE_B".into()));
+        assert_eq!(decode(HeaderEncoding::Raw, "Jürgen"), Err("invalid_raw_value"));
+        assert_eq!(decode(HeaderEncoding::Raw, "ROLE_A,\tROLE_B"), Err("invalid_raw_value"));
+    }
+
+    #[test]
+    fn email_addresses() {
+        assert!(is_valid_email("jane.doe@example.org"));
+        assert!(!is_valid_email(""));
+        assert!(!is_valid_email("jane@example.org,mallory@example.org"));
+        assert!(!is_valid_email("jane@example.org;mallory@example.org"));
+        assert!(!is_valid_email("jane@example.org\nBcc: mallory@example.org"));
+        assert!(!is_valid_email("Jane <jane@example.org>"));
+    }
+}
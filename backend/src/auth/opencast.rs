@@ -0,0 +1,222 @@
+//! Login against Opencast (if `auth.mode` is `opencast`): the credentials
+//! entered on Tobira's login page are checked by sending them to Opencast via
+//! basic auth. That way, small installations don't need any auth proxy.
+//!
+//! Login attempts are rate limited per client IP and failed attempts per user
+//! ID, as Tobira would otherwise allow guessing passwords of Opencast users
+//! without any restriction.
+
+use std::{net::IpAddr, time::Duration};
+
+use hyper::{Body, Client, StatusCode, Uri, header};
+use hyper_rustls::HttpsConnectorBuilder;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::{
+    http::{self, Context, Request, Response, rate_limit::{self, RateLimiter}},
+    metrics::{self, AuthEvent},
+    prelude::*,
+    util::HttpHost,
+};
+use super::{ROLE_ANONYMOUS, User, handlers::create_session, is_valid_email};
+
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct OpencastLoginConfig {
+    /// Opencast node to check credentials against. If not set, the sync node
+    /// (`opencast.sync_node` or `opencast.host`) is used.
+    pub(crate) node: Option<HttpHost>,
+
+    /// Path of the endpoint that credentials are sent to via basic auth. It
+    /// has to reply with 401 or 403 for invalid credentials and otherwise
+    /// with JSON like `/info/me.json`: `user.username`, `user.name`,
+    /// `user.email` and `roles` are used. Basic auth has to be enabled in
+    /// Opencast for this endpoint.
+    #[config(default = "/info/me.json")]
+    pub(crate) endpoint: String,
+
+    /// How long to wait for Opencast to answer.
+    #[config(default = "10s", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) timeout: Duration,
+}
+
+/// Maximum size of the login form and of Opencast's response in bytes.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Name of the user Opencast falls back to if the request is not
+/// authenticated.
+const OPENCAST_ANONYMOUS_USER: &str = "anonymous";
+
+/// Login attempts per client IP, successful or not.
+static ATTEMPTS_PER_IP: Lazy<RateLimiter<IpAddr>> =
+    Lazy::new(|| RateLimiter::new(30, Duration::from_secs(5 * 60)));
+
+/// Login attempts per user ID. Reset on successful login, so effectively
+/// consecutive failures. Counted before checking the credentials, so that
+/// concurrent attempts can't exceed the limit.
+static FAILURES_PER_USER: Lazy<RateLimiter<String>> =
+    Lazy::new(|| RateLimiter::new(5, Duration::from_secs(15 * 60)));
+
+/// Handles `POST /~login` if `auth.mode` is `opencast`. The body is sent by
+/// Tobira's login page and contains `userid` and `password`. Replies 204 with
+/// a session cookie if Opencast accepted the credentials, 429 if the client
+/// or user ID exceeded the rate limit and 403 otherwise.
+pub(crate) async fn handle_opencast_login(
+    req: Request<Body>,
+    ctx: &Context,
+) -> Result<Response, Response> {
+    let client_ip = rate_limit::client_ip(&req, &ctx.config.http);
+    if client_ip.is_some_and(|ip| !ATTEMPTS_PER_IP.hit(ip)) {
+        metrics::auth_event(AuthEvent::LoginFailed, &[("reason", "rate_limited")]);
+        return Err(too_many_requests());
+    }
+
+    let (parts, body) = req.into_parts();
+    let form = http::body::read(body, MAX_BODY_SIZE).await
+        .map_err(|_| http::response::bad_request())?;
+    let field = |name: &str| form_urlencoded::parse(&form)
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned());
+    let (userid, password) = match (field("userid"), field("password")) {
+        (Some(userid), Some(password)) if !userid.is_empty() => (userid, password),
+        _ => return Err(http::response::bad_request()),
+    };
+    if !FAILURES_PER_USER.hit(userid.clone()) {
+        metrics::auth_event(AuthEvent::LoginFailed, &[
+            ("reason", "rate_limited"),
+            ("userid", &userid),
+        ]);
+        return Err(too_many_requests());
+    }
+
+    let user = match check_credentials(&userid, &password, ctx).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            metrics::auth_event(AuthEvent::LoginFailed, &[
+                ("reason", "bad_credentials"),
+                ("userid", &userid),
+            ]);
+            return Err(http::response::forbidden());
+        }
+        Err(e) => {
+            error!("Failed to check credentials of '{}' against Opencast: {:?}", userid, e);
+            metrics::auth_event(AuthEvent::LoginFailed, &[("reason", "opencast_unavailable")]);
+            return Err(Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body("Bad gateway: could not reach Opencast".into())
+                .unwrap());
+        }
+    };
+    debug!("Login request for '{}' (checked against Opencast)", user.username);
+    FAILURES_PER_USER.reset(&userid);
+
+    // The roles are stored as returned by Opencast. The role mapping is
+    // applied whenever the session is loaded.
+    create_session(user, &parts.headers, ctx).await
+}
+
+fn too_many_requests() -> Response {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .body("Too many login attempts, try again later".into())
+        .unwrap()
+}
+
+/// Sends the credentials to Opencast. Returns `None` if they are invalid.
+/// HTTP is only used for local nodes (see `Config::validate`).
+async fn check_credentials(userid: &str, password: &str, ctx: &Context) -> Result<Option<User>> {
+    let config = &ctx.config.auth.opencast_login;
+    let node = config.node.as_ref().unwrap_or_else(|| ctx.config.opencast.sync_node());
+    let uri = Uri::builder()
+        .scheme(node.scheme.clone())
+        .authority(node.authority.clone())
+        .path_and_query(&*config.endpoint)
+        .build()?;
+    let credentials = base64::encode(format!("{userid}:{password}"));
+    let req = hyper::Request::get(&uri)
+        .header(header::AUTHORIZATION, format!("Basic {credentials}"))
+        .header(header::ACCEPT, "application/json")
+        .body(Body::empty())?;
+
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build::<_, Body>(https);
+    let response = tokio::time::timeout(config.timeout, client.request(req)).await
+        .context("request timed out")?
+        .with_context(|| format!("failed to GET {uri}"))?;
+
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => return Ok(None),
+        status if !status.is_success() => bail!("{uri} replied with {status}"),
+        _ => {}
+    }
+    let body = http::body::read(response.into_body(), MAX_BODY_SIZE).await
+        .with_context(|| format!("failed to read response of {uri}"))?;
+
+    user_from_response(&body).with_context(|| format!("invalid response from {uri}"))
+}
+
+/// Parses the JSON returned by Opencast. Returns `None` if Opencast treated
+/// the request as unauthenticated.
+fn user_from_response(body: &[u8]) -> Result<Option<User>> {
+    #[derive(Deserialize)]
+    struct Response {
+        user: ResponseUser,
+        #[serde(default)]
+        roles: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct ResponseUser {
+        username: String,
+        name: Option<String>,
+        email: Option<String>,
+    }
+
+    let Response { user, roles } = serde_json::from_slice(body)?;
+    if user.username.is_empty() || user.username == OPENCAST_ANONYMOUS_USER {
+        return Ok(None);
+    }
+
+    let non_empty = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+    Ok(Some(User {
+        display_name: non_empty(user.name).unwrap_or_else(|| user.username.clone()),
+        email: user.email.filter(|email| is_valid_email(email)),
+        username: user.username,
+        roles: std::iter::once(ROLE_ANONYMOUS.to_owned())
+            .chain(roles.into_iter().filter(|role| role != ROLE_ANONYMOUS))
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::user_from_response;
+
+    #[test]
+    fn parse_response() {
+        let body = br#"{
+            "user": { "username": "jdoe", "name": "Jane Doe", "email": "", "provider": "ldap" },
+            "roles": ["ROLE_USER", "ROLE_ANONYMOUS", "ROLE_STUDENT"],
+            "org": { "id": "mh_default_org" }
+        }"#;
+        let user = user_from_response(body).unwrap().unwrap();
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.display_name, "Jane Doe");
+        assert_eq!(user.email, None);
+        assert_eq!(user.roles, ["ROLE_ANONYMOUS", "ROLE_USER", "ROLE_STUDENT"]);
+
+        let body = br#"{ "user": { "username": "jdoe", "email": "j@example.org" } }"#;
+        let user = user_from_response(body).unwrap().unwrap();
+        assert_eq!(user.display_name, "jdoe");
+        assert_eq!(user.email.as_deref(), Some("j@example.org"));
+
+        let body = br#"{ "user": { "username": "anonymous" }, "roles": ["ROLE_ANONYMOUS"] }"#;
+        assert!(user_from_response(body).unwrap().is_none());
+        assert!(user_from_response(b"<html>").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+use super::ROLE_ANONYMOUS;
+
+
+/// Normalization of the roles passed via auth headers. Applied whenever a user
+/// is obtained from auth headers or a session, so existing sessions are
+/// affected by changes to this configuration as well. All checks (read/write
+/// access, moderator role, ...) only see the normalized roles.
+///
+/// The steps are executed in this order: `ignore_prefixes`, `map`,
+/// `strip_prefixes`. `ROLE_ANONYMOUS` is never modified.
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct RoleMappingConfig {
+    /// Roles starting with any of these prefixes are removed.
+    pub(crate) ignore_prefixes: Option<Vec<String>>,
+
+    /// Maps upstream role names to the names used in Tobira and Opencast,
+    /// e.g. `"urn:mace:example.org:staff" = "ROLE_STAFF"`.
+    pub(crate) map: Option<HashMap<String, String>>,
+
+    /// The first of these prefixes a role starts with is removed from it,
+    /// e.g. with `["urn:mace:example.org:"]`, "urn:mace:example.org:ROLE_X"
+    /// becomes "ROLE_X". Roles that are mapped via `map` are not stripped.
+    pub(crate) strip_prefixes: Option<Vec<String>>,
+}
+
+impl RoleMappingConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        let prefixes = self.ignore_prefixes.iter().flatten()
+            .chain(self.strip_prefixes.iter().flatten());
+        for prefix in prefixes {
+            if prefix.is_empty() {
+                bail!("prefixes in `auth.role_mapping` must not be empty");
+            }
+        }
+        for (from, to) in self.map.iter().flatten() {
+            if from.is_empty() || to.is_empty() {
+                bail!("roles in `auth.role_mapping.map` must not be empty");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Normalizes the given roles according to this configuration. Roles that
+    /// end up empty and duplicates are removed.
+    pub(crate) fn apply(&self, roles: Vec<String>) -> Vec<String> {
+        let mut out = Vec::with_capacity(roles.len());
+        for role in roles {
+            let role = if role == ROLE_ANONYMOUS {
+                role
+            } else if self.ignore_prefixes.iter().flatten().any(|p| role.starts_with(p.as_str())) {
+                continue;
+            } else if let Some(mapped) = self.map.as_ref().and_then(|map| map.get(&role)) {
+                mapped.clone()
+            } else {
+                self.strip_prefixes.iter().flatten()
+                    .find_map(|p| role.strip_prefix(p.as_str()))
+                    .map(ToOwned::to_owned)
+                    .unwrap_or(role)
+            };
+
+            if !role.is_empty() && !out.contains(&role) {
+                out.push(role);
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoleMappingConfig;
+
+    #[test]
+    fn apply() {
+        let config = RoleMappingConfig {
+            ignore_prefixes: Some(vec!["urn:mace:example.org:affiliation:".into()]),
+            map: Some([
+                ("urn:mace:example.org:staff".into(), "ROLE_STAFF".into()),
+                ("urn:mace:example.org:admin".into(), "ROLE_ADMIN".into()),
+            ].into_iter().collect()),
+            strip_prefixes: Some(vec!["urn:mace:example.org:".into()]),
+        };
+
+        let roles = [
+            "ROLE_ANONYMOUS",
+            "urn:mace:example.org:staff",
+            "urn:mace:example.org:affiliation:member",
+            "urn:mace:example.org:ROLE_USER_PETER",
+            "ROLE_STAFF",
+            "urn:mace:example.org:",
+            "ROLE_OTHER",
+        ];
+        let roles = roles.into_iter().map(String::from).collect();
+
+        assert_eq!(
+            config.apply(roles),
+            ["ROLE_ANONYMOUS", "ROLE_STAFF", "ROLE_USER_PETER", "ROLE_OTHER"],
+        );
+    }
+}
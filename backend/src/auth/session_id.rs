@@ -7,9 +7,9 @@ use postgres_types::ToSql;
 use rand::{CryptoRng, RngCore};
 use secrecy::{ExposeSecret, Secret};
 use std::time::Duration;
-use tokio_postgres::Error as PgError;
+use tokio_postgres::{Error as PgError, GenericClient};
 
-use crate::{db::Db, prelude::*};
+use crate::{metrics::{self, AuthEvent}, prelude::*};
 use super::{SESSION_COOKIE, base64encode};
 
 
@@ -58,12 +58,14 @@ impl SessionId {
 
             // Base64 decode value
             .and_then(|v| {
-                if v.len() != LENGTH / 3 * 4 {
+                let mut bytes = [0; LENGTH];
+                if v.len() != LENGTH / 3 * 4
+                    || base64::decode_config_slice(v, base64::URL_SAFE, &mut bytes).is_err()
+                {
+                    metrics::auth_event(AuthEvent::InvalidSessionCookie, &[("reason", "malformed")]);
                     return None;
                 }
 
-                let mut bytes = [0; LENGTH];
-                base64::decode_config_slice(v, base64::URL_SAFE, &mut bytes).ok()?;
                 Some(Self(Secret::new(bytes)))
             })
     }
@@ -112,7 +114,10 @@ impl SessionId {
 
     /// Tries to remove this session from the DB. Returns `Some(username)` if
     /// the session existed and `None` if it did not exist.
-    pub(crate) async fn remove_from_db(&self, db: &Db) -> Result<Option<String>, PgError> {
+    pub(crate) async fn remove_from_db(
+        &self,
+        db: &impl GenericClient,
+    ) -> Result<Option<String>, PgError> {
         db.query_opt("delete from user_sessions where id = $1 returning username", &[self])
             .await?
             .map(|row| row.get(0))
@@ -0,0 +1,912 @@
+//! CLI command `admin` with maintenance operations for administrators.
+
+use std::{collections::HashMap, io::Write, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{
+    api::Id,
+    config::Config,
+    db::{DbConnection, types::Key},
+    prelude::*,
+    search,
+};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum AdminCommand {
+    /// Cross-checks invariants of the DB and the search index: blocks
+    /// referencing series or events that do not exist anymore, realms whose
+    /// `full_path` does not match their parent and path segment, duplicate or
+    /// unreachable realm paths, and missing, stale or orphaned search index
+    /// entries. Fails if any problems remain. Requires MeiliSearch to be
+    /// reachable.
+    Verify {
+        /// Repair what can be repaired safely: `full_path` of realms is
+        /// recomputed and search index entries are queued for an update.
+        /// Broken blocks and realm paths that cannot be recomputed are only
+        /// reported.
+        #[structopt(long)]
+        fix: bool,
+    },
+
+    /// Exports the realm tree with the number of blocks and events of each
+    /// realm and its permissions: member roles, locks, visibility and active
+    /// moderator delegations. Member roles also apply to all descendants.
+    /// Realms that cannot be reached from the root realm are left out (see
+    /// `admin verify`).
+    Tree {
+        #[structopt(flatten)]
+        options: TreeArgs,
+    },
+
+    /// Reports how well the DB indexes fit the queries Tobira runs: the
+    /// queries with the highest total execution time (requires the
+    /// `pg_stat_statements` extension), indexes expected by Tobira that are
+    /// missing, large tables that are mostly scanned sequentially and indexes
+    /// that were never used. Suggests DDL statements, but never changes the
+    /// DB. All statistics are collected since they were last reset.
+    AnalyzeQueries {
+        #[structopt(flatten)]
+        options: AnalyzeArgs,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct AnalyzeArgs {
+    /// Number of queries listed with their execution times.
+    #[structopt(long, default_value = "15")]
+    limit: i64,
+
+    /// Tables with fewer rows are not reported for sequential scans, as
+    /// those are fast for small tables anyway.
+    #[structopt(long, default_value = "10000")]
+    min_rows: i64,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct TreeArgs {
+    /// Output format: `json` (nested objects) or `dot` (a graph for Graphviz,
+    /// e.g. `dot -Tsvg tree.dot > tree.svg`).
+    #[structopt(long, default_value = "json")]
+    format: TreeFormat,
+
+    /// Only export realms at most this many levels below the root realm. For
+    /// realms whose children are left out, only their number is exported.
+    #[structopt(long)]
+    depth: Option<u32>,
+
+    /// Output file. If not specified, the tree is written to stdout, where
+    /// log messages are written too unless `log.stdout` is disabled.
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TreeFormat {
+    Json,
+    Dot,
+}
+
+impl FromStr for TreeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "dot" => Ok(Self::Dot),
+            _ => Err(format!("invalid format '{s}', expected 'json' or 'dot'")),
+        }
+    }
+}
+
+pub(crate) async fn run(cmd: &AdminCommand, config: &Config) -> Result<()> {
+    match cmd {
+        AdminCommand::Verify { fix } => verify(config, *fix).await,
+        AdminCommand::Tree { options } => tree(options, config).await,
+        AdminCommand::AnalyzeQueries { options } => analyze_queries(options, config).await,
+    }
+}
+
+async fn verify(config: &Config, fix: bool) -> Result<()> {
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let mut db = pool.get().await?;
+
+    let mut ok = check_blocks(&db).await?;
+    ok &= check_realm_paths(&mut db, fix).await?;
+
+    let meili = config.meili.connect_only().await?;
+    ok &= search::cmd::check_consistency(&meili, &mut db, fix).await?;
+
+    if !ok {
+        if fix {
+            bail!("found problems that cannot be repaired automatically: see above");
+        }
+        bail!("found problems: see above (run with `--fix` to repair what can be repaired)");
+    }
+
+    println!("No problems found.");
+    Ok(())
+}
+
+/// Prints the heading of a check and its problems. Returns whether there
+/// were none.
+fn report(heading: &str, problems: &[String]) -> bool {
+    bunt::println!("{$bold}# {}:{/$}", heading);
+    if problems.is_empty() {
+        bunt::println!("{$green+intense}OK{/$}");
+    }
+    for problem in problems {
+        bunt::println!("{$red+intense}-{/$} {}", problem);
+    }
+    println!();
+    problems.is_empty()
+}
+
+/// Series and video blocks whose series or event was deleted. The foreign
+/// keys set the reference to `null` in that case and the frontend shows a
+/// placeholder to moderators, who might want to replace the block. So these
+/// are never removed automatically.
+async fn check_blocks(db: &DbConnection) -> Result<bool> {
+    let rows = db
+        .query(
+            "select blocks.type::text, realms.full_path, blocks.index from blocks \
+                join realms on realms.id = blocks.realm_id \
+                where (type = 'series' and (series_id is null \
+                    or not exists (select from series where id = series_id))) \
+                or (type = 'video' and (video_id is null \
+                    or not exists (select from events where id = video_id))) \
+                order by realms.full_path, blocks.index",
+            &[],
+        )
+        .await?;
+
+    let problems = rows.iter()
+        .map(|row| format!(
+            "{} block #{} of realm '{}' references a deleted item",
+            row.get::<_, &str>(0),
+            row.get::<_, i16>(2),
+            path_or_root(row.get(1)),
+        ))
+        .collect::<Vec<_>>();
+    Ok(report("Blocks", &problems))
+}
+
+/// Compares `full_path` of all realms with the path built from the path
+/// segments of the realm and its ancestors, and checks that paths are unique.
+/// Mismatched paths are recomputed by the DB triggers if `fix` is set, which
+/// is only possible for realms reachable from the root realm.
+async fn check_realm_paths(db: &mut DbConnection, fix: bool) -> Result<bool> {
+    let (problems, fixable) = find_realm_path_problems(db).await?;
+    let ok = report("Realm paths", &problems);
+    if ok || !fix || fixable.is_empty() {
+        return Ok(ok);
+    }
+
+    // The triggers only recompute `full_path` when the path segment or
+    // parent changes, so we change the path segment back and forth. This
+    // also fixes all descendants. Parents are fixed before their children,
+    // so some of the later updates do not change anything anymore.
+    let tx = db.transaction().await?;
+    for key in &fixable {
+        tx.execute(
+            "update realms set path_segment = path_segment || '~verify' where id = $1",
+            &[key],
+        ).await?;
+        tx.execute(
+            "update realms set path_segment = left(path_segment, -7) where id = $1",
+            &[key],
+        ).await?;
+    }
+    tx.commit().await?;
+    println!("Recomputed the path of {} realms (and their descendants).", fixable.len());
+    println!();
+
+    let (problems, _) = find_realm_path_problems(db).await?;
+    Ok(report("Realm paths after repair", &problems))
+}
+
+/// Returns descriptions of all problems with realm paths and the keys of
+/// realms whose `full_path` can be recomputed, parents before children.
+async fn find_realm_path_problems(db: &DbConnection) -> Result<(Vec<String>, Vec<Key>)> {
+    let rows = db
+        .query(
+            "with recursive expected(id, full_path) as ( \
+                select id, ''::text from realms where id = 0 \
+                union all \
+                select realms.id, expected.full_path || '/' || realms.path_segment \
+                from realms join expected on realms.parent = expected.id \
+                where realms.id <> 0 \
+            ) \
+            select realms.id, realms.full_path, expected.full_path \
+            from realms left join expected using (id) \
+            where expected.full_path is distinct from realms.full_path \
+            order by length(expected.full_path) nulls last",
+            &[],
+        )
+        .await?;
+
+    let mut problems = Vec::new();
+    let mut fixable = Vec::new();
+    for row in &rows {
+        let key = row.get::<_, Key>(0);
+        let actual = path_or_root(row.get(1));
+        match row.get::<_, Option<&str>>(2) {
+            Some(expected) => {
+                problems.push(format!(
+                    "realm {} has path '{actual}', but its parent and path segment \
+                        result in '{}'",
+                    Id::realm(key),
+                    path_or_root(expected),
+                ));
+                fixable.push(key);
+            }
+            None => problems.push(format!(
+                "realm {} ('{actual}') cannot be reached from the root realm",
+                Id::realm(key),
+            )),
+        }
+    }
+
+    let duplicates = db
+        .query(
+            "select full_path, count(*) from realms \
+                group by full_path having count(*) > 1 \
+                order by full_path",
+            &[],
+        )
+        .await?;
+    problems.extend(duplicates.iter().map(|row| format!(
+        "{} realms have the path '{}'",
+        row.get::<_, i64>(1),
+        path_or_root(row.get(0)),
+    )));
+
+    Ok((problems, fixable))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TreeNode {
+    /// ID as used in the GraphQL API.
+    id: String,
+    name: String,
+    path: String,
+    blocks: i64,
+    /// Number of events shown by the series, video and creator blocks of
+    /// this realm, regardless of their read roles.
+    events: i64,
+    member_roles: Vec<String>,
+    locked: bool,
+    hidden_from_navigation: bool,
+    hidden_from_search: bool,
+    /// Usernames of unexpired moderator delegations.
+    moderator_delegations: Vec<String>,
+    child_count: usize,
+    /// Empty if the children are beyond `--depth`.
+    children: Vec<TreeNode>,
+}
+
+async fn tree(args: &TreeArgs, config: &Config) -> Result<()> {
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let db = pool.get().await?;
+
+    let query = "\
+        with shown as ( \
+            select blocks.realm_id as realm, events.id as event from blocks \
+                join events on events.series = blocks.series_id \
+            union select realm_id, video_id from blocks where video_id is not null \
+            union select blocks.realm_id, events.id from blocks \
+                join events on events.creators @> array[blocks.creator] \
+                where blocks.creator is not null \
+        ) \
+        select realms.id, realms.parent, realms.name, realms.full_path, \
+            (select count(*) from blocks where realm_id = realms.id), \
+            (select count(*) from shown where realm = realms.id), \
+            realms.member_roles, realms.locked, \
+            realms.hidden_from_navigation, realms.hidden_from_search, \
+            array( \
+                select username from moderator_delegations \
+                where realm = realms.id and expires > now() \
+                order by username \
+            ) \
+        from realms \
+        order by realms.index, realms.name";
+    let rows = db.query(query, &[]).await?;
+
+    let mut children = HashMap::<Option<Key>, Vec<_>>::new();
+    for row in rows {
+        let key = row.get::<_, Key>(0);
+        let node = TreeNode {
+            id: Id::realm(key).to_string(),
+            name: row.get(2),
+            path: path_or_root(row.get(3)).to_owned(),
+            blocks: row.get(4),
+            events: row.get(5),
+            member_roles: row.get(6),
+            locked: row.get(7),
+            hidden_from_navigation: row.get(8),
+            hidden_from_search: row.get(9),
+            moderator_delegations: row.get(10),
+            child_count: 0,
+            children: vec![],
+        };
+        children.entry(row.get(1)).or_default().push((key, node));
+    }
+
+    let (root_key, root) = children.remove(&None)
+        .and_then(|mut roots| roots.pop())
+        .context("root realm is missing")?;
+    let root = build_tree(root_key, root, &mut children, 0, args.depth);
+    info!("Exported realm tree");
+
+    let out = match args.format {
+        TreeFormat::Json => serde_json::to_string_pretty(&root)? + "\n",
+        TreeFormat::Dot => to_dot(&root),
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, out)
+            .with_context(|| format!("failed to write tree to '{}'", path.display()))?,
+        None => std::io::stdout().write_all(out.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Moves the descendants of `node` from `children` into it, up to `max_depth`
+/// levels below the root.
+fn build_tree(
+    key: Key,
+    mut node: TreeNode,
+    children: &mut HashMap<Option<Key>, Vec<(Key, TreeNode)>>,
+    depth: u32,
+    max_depth: Option<u32>,
+) -> TreeNode {
+    let own_children = children.remove(&Some(key)).unwrap_or_default();
+    node.child_count = own_children.len();
+    if max_depth.is_none_or(|max| depth < max) {
+        node.children = own_children.into_iter()
+            .map(|(key, child)| build_tree(key, child, children, depth + 1, max_depth))
+            .collect();
+    }
+    node
+}
+
+fn to_dot(root: &TreeNode) -> String {
+    fn add(node: &TreeNode, out: &mut String) {
+        let mut label = if node.name.is_empty() {
+            node.path.clone()
+        } else {
+            format!("{}\n{}", node.name, node.path)
+        };
+        label += &format!("\n{} events, {} blocks", node.events, node.blocks);
+        if !node.member_roles.is_empty() {
+            label += &format!("\nmembers: {}", node.member_roles.join(", "));
+        }
+        if !node.moderator_delegations.is_empty() {
+            label += &format!("\nmoderators: {}", node.moderator_delegations.join(", "));
+        }
+        let flags = [
+            (node.locked, "locked"),
+            (node.hidden_from_navigation, "hidden from navigation"),
+            (node.hidden_from_search, "hidden from search"),
+        ];
+        let flags = flags.iter().filter(|(set, _)| *set).map(|(_, f)| *f).collect::<Vec<_>>();
+        if !flags.is_empty() {
+            label += &format!("\n({})", flags.join(", "));
+        }
+        if node.children.is_empty() && node.child_count > 0 {
+            label += &format!("\n+{} children not shown", node.child_count);
+        }
+
+        let mut attrs = format!("label={}", dot_string(&label));
+        if node.hidden_from_navigation {
+            attrs += ", style=dashed";
+        }
+        if node.locked {
+            attrs += ", color=red";
+        }
+        *out += &format!("  {} [{attrs}];\n", dot_string(&node.id));
+        for child in &node.children {
+            *out += &format!("  {} -> {};\n", dot_string(&node.id), dot_string(&child.id));
+            add(child, out);
+        }
+    }
+
+    let mut out = String::from("digraph realms {\n  node [shape=box];\n");
+    add(root, &mut out);
+    out += "}\n";
+    out
+}
+
+/// Quotes `s` as DOT string. Line breaks become `\n` escapes, which center
+/// the lines in labels.
+fn dot_string(s: &str) -> String {
+    let escaped = s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+/// An index Tobira's queries rely on.
+struct ExpectedIndex {
+    name: &'static str,
+    table: &'static str,
+    method: &'static str,
+    columns: &'static [&'static str],
+    /// What the index is used for.
+    purpose: &'static str,
+    /// `None` for indexes created by the migrations. Other indexes only help
+    /// on some instances, so they are only suggested if a hot query contains
+    /// this string.
+    suggest_if: Option<&'static str>,
+}
+
+impl ExpectedIndex {
+    fn ddl(&self) -> String {
+        format!(
+            "create index concurrently {} on {} using {} ({});",
+            self.name,
+            self.table,
+            self.method,
+            self.columns.join(", "),
+        )
+    }
+}
+
+const EXPECTED_INDEXES: &[ExpectedIndex] = &[
+    ExpectedIndex {
+        name: "idx_block_realm_id",
+        table: "blocks",
+        method: "btree",
+        columns: &["realm_id"],
+        purpose: "loading the blocks of a realm",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_block_series_id",
+        table: "blocks",
+        method: "btree",
+        columns: &["series_id"],
+        purpose: "finding the realms showing a series",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_block_video_id",
+        table: "blocks",
+        method: "btree",
+        columns: &["video_id"],
+        purpose: "finding the realms showing an event",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_block_creator",
+        table: "blocks",
+        method: "btree",
+        columns: &["creator"],
+        purpose: "finding the realms showing events of a creator",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_events_series",
+        table: "events",
+        method: "btree",
+        columns: &["series"],
+        purpose: "loading the events of a series",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_events_creators",
+        table: "events",
+        method: "gin",
+        columns: &["creators"],
+        purpose: "loading the events of creator blocks",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_events_write_roles",
+        table: "events",
+        method: "gin",
+        columns: &["write_roles"],
+        purpose: "loading the events a user can edit (\"My videos\")",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_events_location",
+        table: "events",
+        method: "btree",
+        columns: &["location"],
+        purpose: "loading the events of a room",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_realm_parent",
+        table: "realms",
+        method: "btree",
+        columns: &["parent"],
+        purpose: "loading the children of a realm",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_realm_path",
+        table: "realms",
+        method: "btree",
+        columns: &["full_path text_pattern_ops"],
+        purpose: "resolving realm paths",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_event_markers_event",
+        table: "event_markers",
+        method: "btree",
+        columns: &["event"],
+        purpose: "loading the markers of an event",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_user_sessions_username",
+        table: "user_sessions",
+        method: "btree",
+        columns: &["username"],
+        purpose: "removing the sessions of a user",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_notifications_recipient",
+        table: "notifications",
+        method: "btree",
+        columns: &["recipient"],
+        purpose: "loading the notifications of a user",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_series_follows_series",
+        table: "series_follows",
+        method: "btree",
+        columns: &["series"],
+        purpose: "notifying the followers of a series",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_search_words_trgm",
+        table: "search_words",
+        method: "gin",
+        columns: &["word gin_trgm_ops"],
+        purpose: "search suggestions",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_moderator_delegations_username",
+        table: "moderator_delegations",
+        method: "btree",
+        columns: &["username"],
+        purpose: "checking the moderator delegations of a user",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_saved_searches_username",
+        table: "saved_searches",
+        method: "btree",
+        columns: &["username"],
+        purpose: "loading the saved searches of a user",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_legacy_event_ids_event",
+        table: "legacy_event_ids",
+        method: "btree",
+        columns: &["event"],
+        purpose: "removing legacy IDs of deleted events",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_realm_tree_changes_version",
+        table: "realm_tree_changes",
+        method: "btree",
+        columns: &["version"],
+        purpose: "loading changes of the realm tree since a version",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "frontend_errors_received",
+        table: "frontend_errors",
+        method: "btree",
+        columns: &["received"],
+        purpose: "removing old frontend errors",
+        suggest_if: None,
+    },
+    ExpectedIndex {
+        name: "idx_events_read_roles",
+        table: "events",
+        method: "gin",
+        columns: &["read_roles"],
+        purpose: "filtering events by read access, if most events are only readable \
+            by few users",
+        suggest_if: Some("read_roles &&"),
+    },
+];
+
+/// A query from `pg_stat_statements`.
+struct HotQuery {
+    query: String,
+    calls: i64,
+    /// Total execution time in ms.
+    total: f64,
+    /// Mean execution time in ms.
+    mean: f64,
+}
+
+async fn analyze_queries(args: &AnalyzeArgs, config: &Config) -> Result<()> {
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let db = pool.get().await?;
+
+    let stats_reset = db
+        .query_one(
+            "select stats_reset from pg_stat_database where datname = current_database()",
+            &[],
+        )
+        .await?
+        .get::<_, Option<DateTime<Utc>>>(0);
+    match stats_reset {
+        Some(reset) => println!("Statistics collected since {}.", reset.to_rfc3339()),
+        None => println!("Statistics were never reset."),
+    }
+    println!();
+
+    let hot_queries = load_hot_queries(&db, args.limit).await?;
+    if let Some(queries) = &hot_queries {
+        bunt::println!("{$bold}# Hot queries (by total execution time):{/$}");
+        for q in queries {
+            let query = q.query.split_whitespace().collect::<Vec<_>>().join(" ");
+            let query = match query.char_indices().nth(160) {
+                Some((end, _)) => format!("{}…", &query[..end]),
+                None => query,
+            };
+            bunt::println!(
+                "{$dimmed}{:>10.0} ms total, {:>9} calls, {:>8.2} ms mean:{/$} {}",
+                q.total,
+                q.calls,
+                q.mean,
+                query,
+            );
+        }
+        println!();
+    }
+
+    let sizes = db
+        .query("select relname::text, n_live_tup from pg_stat_user_tables", &[])
+        .await?
+        .into_iter()
+        .map(|row| (row.get::<_, String>(0), row.get::<_, i64>(1)))
+        .collect::<HashMap<_, _>>();
+    let existing = db
+        .query(
+            "select tablename::text, indexdef from pg_indexes \
+                where schemaname = current_schema()",
+            &[],
+        )
+        .await?
+        .into_iter()
+        .filter_map(|row| {
+            let (method, columns) = parse_index_def(row.get(1))?;
+            Some((row.get::<_, String>(0), method, columns))
+        })
+        .collect::<Vec<_>>();
+
+    let mut missing = Vec::new();
+    for index in EXPECTED_INDEXES {
+        let exists = existing.iter().any(|(table, method, columns)| {
+            table == index.table
+                && method == index.method
+                && columns.len() >= index.columns.len()
+                && columns.iter().zip(index.columns).all(|(a, b)| a == b)
+        });
+        if exists {
+            continue;
+        }
+        let suggested = match index.suggest_if {
+            None => true,
+            Some(pattern) => sizes.get(index.table).is_some_and(|&n| n >= args.min_rows)
+                && hot_queries.iter().flatten().any(|q| q.query.contains(pattern)),
+        };
+        if suggested {
+            missing.push(format!("{} (for {}): {}", index.name, index.purpose, index.ddl()));
+        }
+    }
+    report("Missing indexes", &missing);
+
+    let seq_scans = db
+        .query(
+            "select relname::text, n_live_tup, seq_scan, seq_tup_read, coalesce(idx_scan, 0) \
+                from pg_stat_user_tables \
+                where n_live_tup >= $1 and seq_scan > coalesce(idx_scan, 0) \
+                order by seq_tup_read desc",
+            &[&args.min_rows],
+        )
+        .await?
+        .into_iter()
+        .map(|row| format!(
+            "table '{}' ({} rows): {} sequential scans reading {} rows, but only {} index scans",
+            row.get::<_, String>(0),
+            row.get::<_, i64>(1),
+            row.get::<_, i64>(2),
+            row.get::<_, i64>(3),
+            row.get::<_, i64>(4),
+        ))
+        .collect::<Vec<_>>();
+    report("Large tables mostly scanned sequentially", &seq_scans);
+
+    // Unique indexes enforce constraints and indexes created by the
+    // migrations might just not be needed yet, so neither is reported.
+    let unused = db
+        .query(
+            "select s.indexrelname::text, s.relname::text, \
+                pg_size_pretty(pg_relation_size(s.indexrelid)) \
+                from pg_stat_user_indexes s \
+                join pg_index i on i.indexrelid = s.indexrelid \
+                where s.idx_scan = 0 and not i.indisunique \
+                order by pg_relation_size(s.indexrelid) desc",
+            &[],
+        )
+        .await?
+        .into_iter()
+        .filter(|row| {
+            let name = row.get::<_, &str>(0);
+            !EXPECTED_INDEXES.iter().any(|index| index.name == name && index.suggest_if.is_none())
+        })
+        .map(|row| format!(
+            "index '{}' on '{}' ({}) was never used: drop index concurrently {};",
+            row.get::<_, &str>(0),
+            row.get::<_, &str>(1),
+            row.get::<_, &str>(2),
+            row.get::<_, &str>(0),
+        ))
+        .collect::<Vec<_>>();
+    report("Unused indexes", &unused);
+
+    Ok(())
+}
+
+/// Loads the queries on the current DB with the highest total execution
+/// time. Returns `None` if `pg_stat_statements` is not available.
+async fn load_hot_queries(db: &DbConnection, limit: i64) -> Result<Option<Vec<HotQuery>>> {
+    let installed = db
+        .query_one(
+            "select exists(select from pg_extension where extname = 'pg_stat_statements')",
+            &[],
+        )
+        .await?
+        .get::<_, bool>(0);
+    if !installed {
+        println!(
+            "The extension `pg_stat_statements` is not installed, so hot queries cannot \
+                be analyzed. To install it, add it to `shared_preload_libraries` in the \
+                PostgreSQL config and run `create extension pg_stat_statements;` as superuser."
+        );
+        println!();
+        return Ok(None);
+    }
+
+    // The columns were renamed in PostgreSQL 13.
+    let version = db
+        .query_one("select current_setting('server_version_num')::int", &[])
+        .await?
+        .get::<_, i32>(0);
+    let (total, mean) = if version >= 130000 {
+        ("total_exec_time", "mean_exec_time")
+    } else {
+        ("total_time", "mean_time")
+    };
+    let utility_statements = "begin|commit|rollback|savepoint|release|set|show\
+        |create|alter|drop|analyze|vacuum";
+    let query = format!(
+        "select query, calls, {total}, {mean} from pg_stat_statements \
+            where dbid = (select oid from pg_database where datname = current_database()) \
+            and query !~* '^\\s*({utility_statements})\\M' \
+            order by {total} desc \
+            limit $1",
+    );
+    let rows = match db.query(&query, &[&limit]).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Could not read `pg_stat_statements` (not preloaded?): {e}");
+            println!();
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(rows.into_iter().map(|row| HotQuery {
+        query: row.get(0),
+        calls: row.get(1),
+        total: row.get(2),
+        mean: row.get(3),
+    }).collect()))
+}
+
+/// Extracts the access method and the column list from an index definition
+/// as returned by `pg_get_indexdef`, e.g. `CREATE INDEX idx ON public.events
+/// USING btree (series, created) WHERE ...`.
+fn parse_index_def(def: &str) -> Option<(String, Vec<String>)> {
+    let (_, rest) = def.split_once(" USING ")?;
+    let (method, rest) = rest.split_once(" (")?;
+    let columns = match rest.split_once(" WHERE ") {
+        Some((columns, _)) => columns,
+        None => rest,
+    };
+    let columns = columns.trim_end().strip_suffix(')')?;
+    let columns = columns.split(", ").map(|c| c.trim().to_owned()).collect();
+    Some((method.to_owned(), columns))
+}
+
+fn path_or_root(path: &str) -> &str {
+    if path.is_empty() { "/" } else { path }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TreeNode, dot_string, parse_index_def, to_dot};
+
+    fn node(id: &str, name: &str, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            id: id.into(),
+            name: name.into(),
+            path: format!("/{}", name.to_lowercase()),
+            blocks: 2,
+            events: 5,
+            member_roles: vec![],
+            locked: false,
+            hidden_from_navigation: false,
+            hidden_from_search: false,
+            moderator_delegations: vec![],
+            child_count: children.len(),
+            children,
+        }
+    }
+
+    #[test]
+    fn dot() {
+        assert_eq!(dot_string(r#"a "b" \ c"#), r#""a \"b\" \\ c""#);
+        assert_eq!(dot_string("a\nb"), r#""a\nb""#);
+
+        let mut child = node("R2", "Lectures", vec![]);
+        child.locked = true;
+        child.member_roles = vec!["ROLE_STUDENT".into()];
+        child.child_count = 3;
+        let mut root = node("R1", "", vec![child]);
+        root.path = "/".into();
+
+        assert_eq!(to_dot(&root), "digraph realms {\n  \
+            node [shape=box];\n  \
+            \"R1\" [label=\"/\\n5 events, 2 blocks\"];\n  \
+            \"R1\" -> \"R2\";\n  \
+            \"R2\" [label=\"Lectures\\n/lectures\\n5 events, 2 blocks\\n\
+                members: ROLE_STUDENT\\n(locked)\\n+3 children not shown\", color=red];\n\
+            }\n");
+    }
+
+    #[test]
+    fn index_defs() {
+        let parse = |def: &str| parse_index_def(def).map(|(method, columns)| {
+            (method, columns.iter().map(|c| c.as_str()).collect::<Vec<_>>().join("|"))
+        });
+        let pair = |method: &str, columns: &str| Some((method.to_owned(), columns.to_owned()));
+
+        assert_eq!(
+            parse("CREATE INDEX idx_events_series ON public.events USING btree (series)"),
+            pair("btree", "series"),
+        );
+        assert_eq!(
+            parse("CREATE UNIQUE INDEX u ON public.blocks USING btree (realm_id, index)"),
+            pair("btree", "realm_id|index"),
+        );
+        assert_eq!(
+            parse("CREATE INDEX t ON public.search_words USING gin (word gin_trgm_ops)"),
+            pair("gin", "word gin_trgm_ops"),
+        );
+        assert_eq!(
+            parse("CREATE INDEX p ON public.events USING btree (lower(title)) WHERE (x > 3)"),
+            pair("btree", "lower(title)"),
+        );
+        assert_eq!(parse("CREATE INDEX broken"), None);
+    }
+}
@@ -0,0 +1,265 @@
+//! CLI command `bench` to run representative GraphQL workloads and report
+//! latencies and the number of SQL queries per operation. Only available
+//! with the `bench` feature.
+//!
+//! All fixtures are inserted in a transaction that is rolled back at the end,
+//! so the DB is left unchanged. Each operation runs in its own savepoint,
+//! similar to how each API request runs in its own transaction.
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use structopt::StructOpt;
+
+use crate::{
+    api::{self, Id, cache::{RequestCache, ResolverCache}},
+    auth::{JwtContext, Permissions},
+    config::Config,
+    db::{self, types::Key},
+    extension::Extensions,
+    prelude::*,
+};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Args {
+    /// How often each operation is executed (after a few warm-up runs).
+    #[structopt(long, default_value = "50")]
+    iterations: u32,
+
+    /// Only run operations whose name contains this string.
+    #[structopt(long)]
+    filter: Option<String>,
+}
+
+/// Number of untimed executions before each operation is measured.
+const WARMUP_ITERATIONS: u32 = 3;
+
+/// Data inserted for the benchmark.
+struct Fixtures {
+    event: Key,
+}
+
+struct Operation {
+    name: &'static str,
+    query: String,
+}
+
+pub(crate) async fn run(args: &Args, config: Config) -> Result<()> {
+    let db = db::create_pool(&config.db).await
+        .context("failed to create database connection pool (database not running?)")?;
+    db::check_migrations(&mut *db.get().await?, &config.db).await
+        .context("failed to check DB migrations")?;
+    let search = Arc::new(config.meili.connect_only().await?);
+    let jwt = Arc::new(JwtContext::new(&config.auth.jwt)?);
+    let extensions = Arc::new(Extensions::load(&config)?);
+    let config = Arc::new(config);
+    let root = &api::root_node();
+
+    let mut conn = db.get().await?;
+    let mut outer = conn.transaction().await?;
+    let fixtures = seed(&outer).await.context("failed to insert fixtures")?;
+    info!("Inserted fixtures");
+
+    let operations = operations(&fixtures).into_iter()
+        .filter(|op| args.filter.as_ref().is_none_or(|f| op.name.contains(f.as_str())))
+        .collect::<Vec<_>>();
+
+    println!(
+        "{:<16} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "operation", "p50", "p90", "p99", "max", "queries",
+    );
+    for op in &operations {
+        let mut durations = Vec::new();
+        let mut num_queries = 0;
+        for i in 0..WARMUP_ITERATIONS + args.iterations {
+            let make_context = |tx| api::Context {
+                db: tx,
+                db_pool: db.clone(),
+                user: None,
+                permissions: Permissions::new(&None, &config.auth),
+                config: config.clone(),
+                jwt: jwt.clone(),
+                search: search.clone(),
+                cache: RequestCache::new(Arc::new(ResolverCache::new())),
+                extensions: extensions.clone(),
+                tracer: None,
+                client_ip: None,
+                client_country: None,
+            };
+            let tx = outer.transaction().await?;
+            let (result, tx) = api::with_transaction(tx, None, make_context, |context| async move {
+                let before = Instant::now();
+                let result = juniper::execute(
+                    &op.query,
+                    None,
+                    root,
+                    &juniper::Variables::new(),
+                    &*context,
+                ).await;
+                result.map(|(_, errors)| (before.elapsed(), errors, context.db.num_queries()))
+            }).await;
+            tx.rollback().await?;
+
+            let (duration, errors, queries) = result
+                .map_err(|e| anyhow!("operation '{}' failed: {e}", op.name))?;
+            if let Some(e) = errors.first() {
+                bail!("operation '{}' failed: {:?}", op.name, e);
+            }
+            if i >= WARMUP_ITERATIONS {
+                durations.push(duration);
+                num_queries = queries;
+            }
+        }
+
+        durations.sort_unstable();
+        println!(
+            "{:<16} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            op.name,
+            format_ms(percentile(&durations, 50)),
+            format_ms(percentile(&durations, 90)),
+            format_ms(percentile(&durations, 99)),
+            format_ms(durations.last().copied().unwrap_or_default()),
+            num_queries,
+        );
+    }
+
+    outer.rollback().await?;
+    Ok(())
+}
+
+/// Returns the `p`-th percentile of the sorted `durations` (nearest-rank
+/// method).
+fn percentile(durations: &[Duration], p: usize) -> Duration {
+    if durations.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (p * durations.len()).div_ceil(100);
+    durations[rank.clamp(1, durations.len()) - 1]
+}
+
+fn format_ms(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Inserts 20 series with 25 events each and a realm `/tobira-bench` with
+/// one child realm per series, each with a title and a series block.
+async fn seed(tx: &deadpool_postgres::Transaction<'_>) -> Result<Fixtures> {
+    tx.batch_execute("
+        insert into series (opencast_id, title, description, updated)
+            select 'tobira-bench-series-' || i, 'Benchmark series ' || i, 'Fixture', now()
+            from generate_series(0, 19) as i;
+
+        insert into events (
+            opencast_id, series, part_of, read_roles, write_roles, title, description,
+            duration, created, updated, creators, thumbnail, tracks
+        )
+            select
+                'tobira-bench-event-' || i, series.id, series.opencast_id,
+                '{ROLE_ANONYMOUS}', '{ROLE_ADMIN}', 'Benchmark event ' || i, 'Fixture',
+                3600000, now() - make_interval(days => i), now(), '{Jane Doe}', null,
+                array[row(
+                    'https://example.org/' || i || '.mp4',
+                    'presentation/preview',
+                    'video/mp4',
+                    '{1280,720}',
+                    false
+                )::event_track]
+            from generate_series(0, 499) as i
+            join series on series.opencast_id = 'tobira-bench-series-' || (i % 20);
+
+        insert into realms (parent, name, path_segment)
+            values (0, 'Benchmark', 'tobira-bench');
+        insert into realms (parent, name, path_segment)
+            select id, 'Benchmark realm ' || i, 'r' || i
+            from realms, generate_series(0, 19) as i
+            where full_path = '/tobira-bench';
+
+        insert into blocks (realm_id, type, index, text_content)
+            select id, 'title', 0, name
+            from realms
+            where starts_with(full_path, '/tobira-bench/');
+        insert into blocks (realm_id, type, index, series_id, videolist_order)
+            select realms.id, 'series', 1, series.id, 'new_to_old'
+            from realms
+            join series on series.opencast_id = 'tobira-bench-series-' || substring(realms.path_segment, 2)
+            where starts_with(realms.full_path, '/tobira-bench/');
+    ").await?;
+
+    let event = tx
+        .query_one("select id from events where opencast_id = 'tobira-bench-event-0'", &[])
+        .await?
+        .get(0);
+
+    Ok(Fixtures { event })
+}
+
+/// The workloads, modeled after the queries of the most important pages.
+fn operations(fixtures: &Fixtures) -> Vec<Operation> {
+    vec![
+        Operation {
+            name: "navigation",
+            query: "{
+                rootRealm {
+                    children { id name path children { id name path } }
+                }
+            }".into(),
+        },
+        Operation {
+            name: "realm-page",
+            query: "{
+                realmByPath(path: \"/tobira-bench/r0\") {
+                    id name path isRoot effectiveLocale noindex
+                    ancestors { name path }
+                    children { id name path }
+                    blocks {
+                        __typename id index
+                        ... on TitleBlock { content }
+                        ... on SeriesBlock {
+                            showTitle order
+                            series {
+                                title
+                                events { id title thumbnail duration created creators }
+                            }
+                        }
+                    }
+                }
+            }".into(),
+        },
+        Operation {
+            name: "video-page",
+            query: format!("{{
+                event(id: \"{}\") {{
+                    title description duration thumbnail created creators canWrite
+                    tracks {{ uri flavor mimetype resolution isAudio }}
+                    series {{ title }}
+                    hostRealms {{ name path }}
+                }}
+            }}", Id::event(fixtures.event)),
+        },
+        Operation {
+            name: "series-page",
+            query: "{
+                seriesByOpencastId(id: \"tobira-bench-series-0\") {
+                    title description
+                    events { id title thumbnail duration created }
+                }
+            }".into(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::percentile;
+
+    #[test]
+    fn percentiles() {
+        let durations = (1..=10).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&durations, 50), Duration::from_millis(5));
+        assert_eq!(percentile(&durations, 90), Duration::from_millis(9));
+        assert_eq!(percentile(&durations, 99), Duration::from_millis(10));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+}
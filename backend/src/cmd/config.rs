@@ -0,0 +1,64 @@
+//! CLI command `config` to generate documentation of the configuration from
+//! its definition in the code.
+
+use std::{fs, io::Write, path::PathBuf, str::FromStr};
+
+use structopt::StructOpt;
+
+use crate::{config, prelude::*};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum ConfigCommand {
+    /// Generates the documentation of all configuration options. The
+    /// shipped `docs/config.toml` and `docs/config.md` are generated with
+    /// this.
+    Docs {
+        /// `toml-template` (a configuration file with all options commented
+        /// out and described) or `markdown` (a reference document).
+        #[structopt(long, default_value = "toml-template")]
+        format: DocsFormat,
+
+        /// Target file. If not specified, the docs are written to stdout.
+        target: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DocsFormat {
+    TomlTemplate,
+    Markdown,
+}
+
+impl FromStr for DocsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toml-template" => Ok(Self::TomlTemplate),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(format!("invalid format '{s}', expected 'toml-template' or 'markdown'")),
+        }
+    }
+}
+
+pub(crate) fn run(cmd: &ConfigCommand) -> Result<()> {
+    match cmd {
+        ConfigCommand::Docs { format, target } => write_docs(*format, target.as_ref()),
+    }
+}
+
+/// Writes the docs in the given format to `target` or stdout.
+pub(crate) fn write_docs(format: DocsFormat, target: Option<&PathBuf>) -> Result<()> {
+    let docs = match format {
+        DocsFormat::TomlTemplate => config::docs::toml_template(),
+        DocsFormat::Markdown => config::docs::markdown(),
+    };
+    match target {
+        Some(path) => fs::write(path, docs)
+            .with_context(|| format!("failed to write '{}'", path.display()))?,
+        None => std::io::stdout().write_all(docs.as_bytes())?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! CLI command `import-legacy-urls` to read a mapping from URLs of a previous
+//! video portal to Tobira events/realms from a YAML file and store it in the
+//! DB.
+
+use hyper::Uri;
+use serde::Deserialize;
+use std::{fs::File, path::PathBuf};
+use structopt::StructOpt;
+
+use crate::{
+    config::Config,
+    prelude::*,
+};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Args {
+    /// YAML file containing a list of mappings. Each entry has a `url` and
+    /// either an `event` (Opencast ID) or a `realm` (path) field.
+    input_file: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Mapping {
+    url: String,
+    event: Option<String>,
+    realm: Option<String>,
+}
+
+pub(crate) async fn run(args: &Args, config: &Config) -> Result<()> {
+    let file = File::open(&args.input_file)?;
+    let mappings: Vec<Mapping> = serde_yaml::from_reader(file)?;
+    info!("Read {} mappings from YAML file", mappings.len());
+
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let mut conn = pool.get().await?;
+    let tx = conn.transaction().await?;
+
+    let mut imported = 0;
+    for mapping in &mappings {
+        let path = match normalize_url(&mapping.url) {
+            Some(path) => path,
+            None => {
+                warn!("'{}' is not a valid URL or path: skipping", mapping.url);
+                continue;
+            }
+        };
+
+        let (event, realm) = match (&mapping.event, &mapping.realm) {
+            (Some(opencast_id), None) => {
+                let row = tx
+                    .query_opt("select id from events where opencast_id = $1", &[opencast_id])
+                    .await?;
+                match row {
+                    Some(row) => (Some(row.get::<_, i64>(0)), None),
+                    None => {
+                        warn!("Event '{}' not found (for '{}'): skipping", opencast_id, path);
+                        continue;
+                    }
+                }
+            }
+            (None, Some(realm_path)) => {
+                let full_path = realm_path.trim_end_matches('/');
+                let row = tx
+                    .query_opt("select id from realms where full_path = $1", &[&full_path])
+                    .await?;
+                match row {
+                    Some(row) => (None, Some(row.get::<_, i64>(0))),
+                    None => {
+                        warn!("Realm '{}' not found (for '{}'): skipping", realm_path, path);
+                        continue;
+                    }
+                }
+            }
+            _ => bail!("mapping for '{}' has to specify exactly one of `event` and `realm`", path),
+        };
+
+        // Existing mappings are overwritten, but their statistics are kept.
+        let sql = "insert into legacy_urls (path, event, realm) \
+            values ($1, $2, $3) \
+            on conflict (path) do update set event = excluded.event, realm = excluded.realm";
+        tx.execute(sql, &[&path, &event, &realm]).await?;
+        imported += 1;
+    }
+
+    tx.commit().await?;
+    info!("Imported {} of {} legacy URL mappings", imported, mappings.len());
+
+    Ok(())
+}
+
+/// Returns the path and query of the given URL, which can also be a path
+/// only. That's what incoming requests are matched against.
+fn normalize_url(url: &str) -> Option<String> {
+    let uri = url.parse::<Uri>().ok()?;
+    let pq = uri.path_and_query()?.as_str();
+    if !pq.starts_with('/') {
+        return None;
+    }
+
+    Some(pq.to_owned())
+}
@@ -59,7 +59,7 @@ pub(crate) async fn run(args: &Args, config: &Config) -> Result<()> {
     // Open DB connection, check consistency and migrate if necessary.
     let db = db::create_pool(&config.db).await
         .context("failed to create database connection pool (database not running?)")?;
-    db::migrate(&mut *db.get().await?).await
+    db::migrate(&mut *db.get().await?, &config.db).await
         .context("failed to check/run DB migrations")?;
     let conn = db.get().await?;
 
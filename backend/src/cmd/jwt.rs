@@ -0,0 +1,158 @@
+//! CLI command `jwt` to manage the key used to sign JWTs for Opencast (see
+//! `auth/jwt.rs`).
+
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use structopt::StructOpt;
+
+use crate::{
+    auth::{self, JwtConfig},
+    config::Config,
+    prelude::*,
+};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum JwtCommand {
+    /// Operations on the signing key (`auth.jwt.secret_key`).
+    Key {
+        #[structopt(subcommand)]
+        cmd: KeyCommand,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum KeyCommand {
+    /// Generates a new key for `auth.jwt.signing_algorithm` and writes it to
+    /// `auth.jwt.secret_key`, readable only by the current user. Fails if
+    /// that file already exists, use `rotate` to replace a key.
+    Generate,
+
+    /// Rotates the key in two steps. The first call generates the next key,
+    /// whose public key is then included in the JWKS (after restarting
+    /// Tobira). Once `auth.jwt.rotation_grace_period` has passed, the second
+    /// call replaces the current key with the next one. Restart Tobira after
+    /// each step.
+    Rotate {
+        /// Replace the key even if the grace period has not passed yet.
+        /// Opencast might reject JWTs until it fetches the JWKS again.
+        #[structopt(long)]
+        force: bool,
+    },
+
+    /// Checks the key, prints the JWKS served at `/.well-known/jwks.json`
+    /// and the configuration Opencast needs to accept the JWTs.
+    ShowPublic,
+}
+
+pub(crate) async fn run(cmd: &JwtCommand, config: &Config) -> Result<()> {
+    let jwt_config = &config.auth.jwt;
+    match cmd {
+        JwtCommand::Key { cmd: KeyCommand::Generate } => {
+            write_new_key(jwt_config, jwt_config.secret_key_path())?;
+            println!(
+                "Generated new key '{}'. Run `tobira jwt key show-public` for the \
+                    configuration Opencast needs.",
+                jwt_config.secret_key_path().display(),
+            );
+        }
+        JwtCommand::Key { cmd: KeyCommand::Rotate { force } } => rotate(jwt_config, *force)?,
+        JwtCommand::Key { cmd: KeyCommand::ShowPublic } => show_public(config)?,
+    }
+
+    Ok(())
+}
+
+/// Writes a new key to `path`, which must not exist yet. The file is only
+/// readable by the current user.
+fn write_new_key(config: &JwtConfig, path: &Path) -> Result<()> {
+    let pem = auth::generate_secret_key(config.signing_algorithm())?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("failed to create '{}'", path.display()))?;
+    file.write_all(pem.as_bytes())
+        .with_context(|| format!("failed to write '{}'", path.display()))?;
+    Ok(())
+}
+
+fn rotate(config: &JwtConfig, force: bool) -> Result<()> {
+    let current = config.secret_key_path();
+    let next = config.next_key_path();
+    if !current.exists() {
+        bail!("'{}' does not exist: use `tobira jwt key generate`", current.display());
+    }
+
+    if !next.exists() {
+        write_new_key(config, &next)?;
+        println!("Generated next key '{}'.", next.display());
+        println!(
+            "Restart Tobira now to publish its public key in the JWKS. Then run this \
+                command again after {:?} to replace the current key.",
+            config.rotation_grace_period,
+        );
+        return Ok(());
+    }
+
+    // Make sure the next key is valid before it replaces the current one.
+    config.load_jwks()?;
+    let created = fs::metadata(&next)?.modified()?;
+    let age = SystemTime::now().duration_since(created).unwrap_or_default();
+    if age < config.rotation_grace_period && !force {
+        bail!(
+            "the next key was only created {:?} ago, but `auth.jwt.rotation_grace_period` \
+                is {:?}: Opencast might not know it yet (use `--force` to replace the key anyway)",
+            Duration::from_secs(age.as_secs()),
+            config.rotation_grace_period,
+        );
+    }
+
+    fs::rename(&next, current)
+        .with_context(|| format!("failed to move '{}' to '{}'", next.display(), current.display()))?;
+    println!("Replaced '{}' with the next key.", current.display());
+    println!(
+        "Restart Tobira now to sign JWTs with it. Passphrase access to events has \
+            to be granted again, as the old tokens are not valid anymore."
+    );
+    Ok(())
+}
+
+fn show_public(config: &Config) -> Result<()> {
+    let jwt_config = &config.auth.jwt;
+    let jwks = jwt_config.load_jwks()
+        .with_context(|| format!("failed to load '{}'", jwt_config.secret_key_path().display()))?;
+    if jwt_config.next_key_path().exists() {
+        println!("# JWKS (the second key is the next key of a running rotation)");
+    } else {
+        println!("# JWKS");
+    }
+    println!("{jwks}");
+    println!();
+
+    let jwks_url = match &config.general.site_url {
+        Some(site_url) => format!("{site_url}/.well-known/jwks.json"),
+        None => "https://<your-tobira-domain>/.well-known/jwks.json".into(),
+    };
+    let cache_minutes = (jwt_config.rotation_grace_period.as_secs() / 60).max(1);
+    let algorithm = jwt_config.signing_algorithm().to_str();
+    println!("# Opencast configuration (`etc/security/mh_default_org.xml`, see docs/auth/jwt.md)");
+    println!(r#"<property name="jwksUrl" value="{jwks_url}" />"#);
+    println!(r#"<property name="jwksCacheExpiresIn" value="{cache_minutes}" />"#);
+    println!(r#"<property name="expectedAlgorithms" ref="jwtExpectedAlgorithms" />"#);
+    println!(r#"<property name="usernameMapping" value="['username'].asString()" />"#);
+    println!(r#"<property name="nameMapping" value="['name'].asString()" />"#);
+    println!(r#"<property name="emailMapping" value="['username'].asString() + '@tobira.invalid'" />"#);
+    println!();
+    println!(r#"<util:list id="jwtExpectedAlgorithms" value-type="java.lang.String">"#);
+    println!("  <value>{algorithm}</value>");
+    println!("</util:list>");
+    Ok(())
+}
@@ -1,2 +1,11 @@
+pub(crate) mod admin;
+#[cfg(feature = "bench")]
+pub(crate) mod bench;
+pub(crate) mod config;
 pub(crate) mod export_api_schema;
+pub(crate) mod import_legacy_urls;
 pub(crate) mod import_realm_tree;
+pub(crate) mod jwt;
+pub(crate) mod rekey;
+pub(crate) mod report;
+pub(crate) mod test_access;
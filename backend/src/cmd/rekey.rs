@@ -0,0 +1,66 @@
+//! CLI command `rekey` to encrypt all sensitive columns with the current
+//! `db.encryption_key` (see `db/crypt.rs`), e.g. after rotating the key.
+
+use crate::{
+    config::Config,
+    db::crypt::{self, Column},
+    prelude::*,
+};
+
+
+/// Re-encrypts all values of encrypted columns that are not encrypted with
+/// `db.encryption_key` yet. Plain text values are encrypted. If no key is
+/// configured, all values are decrypted instead. Everything happens in one
+/// transaction, so nothing is changed if any value cannot be decrypted.
+pub(crate) async fn run(config: &Config) -> Result<()> {
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let mut conn = pool.get().await?;
+    let tx = conn.transaction().await?;
+
+    if config.db.encryption_key.is_none() {
+        warn!("`db.encryption_key` is not set: decrypting all values");
+    }
+
+    for column in Column::ALL {
+        // Values are random for each row, so updating by value touches
+        // exactly the rows that had it. Plain text values might appear in
+        // several rows, which all get the same new value.
+        let values = tx
+            .query(
+                &format!(
+                    "select distinct {col} from {table} where {col} is not null",
+                    col = column.name,
+                    table = column.table,
+                ),
+                &[],
+            )
+            .await
+            .with_context(|| format!("failed to load {}.{}", column.table, column.name))?;
+
+        let mut updated = 0;
+        for row in &values {
+            let old = row.get::<_, &str>(0);
+            if crypt::is_current(&config.db, old)? {
+                continue;
+            }
+
+            let new = crypt::encrypt(&config.db, column, &crypt::decrypt(&config.db, column, old)?)?;
+            updated += tx
+                .execute(
+                    &format!(
+                        "update {table} set {col} = $2 where {col} = $1",
+                        col = column.name,
+                        table = column.table,
+                    ),
+                    &[&old, &new],
+                )
+                .await?;
+        }
+        info!("Updated {} rows of {}.{}", updated, column.table, column.name);
+    }
+
+    tx.commit().await?;
+    info!("All encrypted columns use the current key now");
+
+    Ok(())
+}
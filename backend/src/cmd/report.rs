@@ -0,0 +1,234 @@
+//! CLI command `report` to generate reports about the content of Tobira from
+//! the synced data, e.g. for compliance officers.
+
+use std::{io::Write, path::PathBuf, str::FromStr};
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::{
+    api::Id,
+    config::Config,
+    db::types::Key,
+    prelude::*,
+};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum ReportCommand {
+    /// Lists all events with accessibility problems: missing captions, audio
+    /// descriptions, descriptions or titles. Events are included if they are
+    /// shown in a series or video block of the given realm or its
+    /// descendants. For the root realm, all events are included.
+    Accessibility {
+        #[structopt(flatten)]
+        options: AccessibilityArgs,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct AccessibilityArgs {
+    /// Path of the realm whose subtree is reported on.
+    #[structopt(long, default_value = "/")]
+    realm: String,
+
+    /// Output format: `csv` (one row per event with problems) or `json`
+    /// (additionally containing the total numbers).
+    #[structopt(long, default_value = "csv")]
+    format: Format,
+
+    /// Tracks whose flavor starts with this are considered audio
+    /// descriptions.
+    #[structopt(long, default_value = "audio-description/")]
+    audio_description_flavor: String,
+
+    /// Output file. If not specified, the report is written to stdout, where
+    /// log messages are written too unless `log.stdout` is disabled.
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("invalid format '{s}', expected 'csv' or 'json'")),
+        }
+    }
+}
+
+pub(crate) async fn run(cmd: &ReportCommand, config: &Config) -> Result<()> {
+    match cmd {
+        ReportCommand::Accessibility { options } => accessibility(options, config).await,
+    }
+}
+
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessibilityReport {
+    realm: String,
+    events: usize,
+    missing_captions: usize,
+    missing_audio_description: usize,
+    missing_description: usize,
+    missing_title: usize,
+    problems: Vec<EventProblems>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventProblems {
+    /// ID as used in the GraphQL API.
+    id: String,
+    opencast_id: Option<String>,
+    title: String,
+    series: Option<String>,
+    /// Paths of the realms in the subtree showing the event.
+    realms: Vec<String>,
+    missing_captions: bool,
+    missing_audio_description: bool,
+    missing_description: bool,
+    missing_title: bool,
+}
+
+impl EventProblems {
+    fn any(&self) -> bool {
+        self.missing_captions
+            || self.missing_audio_description
+            || self.missing_description
+            || self.missing_title
+    }
+}
+
+async fn accessibility(args: &AccessibilityArgs, config: &Config) -> Result<()> {
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let db = pool.get().await?;
+
+    let path = args.realm.trim_end_matches('/');
+    let realm_exists = db
+        .query_opt("select from realms where full_path = $1", &[&path])
+        .await?
+        .is_some();
+    if !realm_exists {
+        bail!("realm '{}' does not exist", args.realm);
+    }
+
+    let query = "\
+        with subtree as ( \
+            select id, full_path from realms \
+            where full_path = $1 or starts_with(full_path, $1 || '/') \
+        ), \
+        hosts as ( \
+            select events.id as event, array_agg(distinct subtree.full_path) as paths \
+            from blocks \
+            join subtree on subtree.id = blocks.realm_id \
+            join events on events.id = blocks.video_id or events.series = blocks.series_id \
+            group by events.id \
+        ) \
+        select events.id, events.opencast_id, events.title, series.title, \
+            coalesce(hosts.paths, '{}'), \
+            cardinality(events.captions) = 0, \
+            not exists ( \
+                select from unnest(events.tracks) as track \
+                where starts_with(track.flavor, $2) \
+            ), \
+            coalesce(trim(events.description), '') = '', \
+            trim(events.title) = '' \
+        from events \
+        left join series on series.id = events.series \
+        left join hosts on hosts.event = events.id \
+        where $1 = '' or hosts.event is not null \
+        order by series.title nulls last, events.title";
+    let rows = db.query(query, &[&path, &args.audio_description_flavor]).await?;
+
+    let events = rows.into_iter()
+        .map(|row| EventProblems {
+            id: Id::event(row.get::<_, Key>(0)).to_string(),
+            opencast_id: row.get(1),
+            title: row.get(2),
+            series: row.get(3),
+            realms: row.get::<_, Vec<String>>(4).into_iter()
+                .map(|path| if path.is_empty() { "/".into() } else { path })
+                .collect(),
+            missing_captions: row.get(5),
+            missing_audio_description: row.get(6),
+            missing_description: row.get(7),
+            missing_title: row.get(8),
+        })
+        .collect::<Vec<_>>();
+
+    let count = |f: fn(&EventProblems) -> bool| events.iter().filter(|e| f(e)).count();
+    let report = AccessibilityReport {
+        realm: args.realm.clone(),
+        events: events.len(),
+        missing_captions: count(|e| e.missing_captions),
+        missing_audio_description: count(|e| e.missing_audio_description),
+        missing_description: count(|e| e.missing_description),
+        missing_title: count(|e| e.missing_title),
+        problems: events.into_iter().filter(EventProblems::any).collect(),
+    };
+    info!(
+        "Checked {} events: {} without captions, {} without audio description, \
+            {} without description, {} without title",
+        report.events,
+        report.missing_captions,
+        report.missing_audio_description,
+        report.missing_description,
+        report.missing_title,
+    );
+
+    let out = match args.format {
+        Format::Json => serde_json::to_string_pretty(&report)? + "\n",
+        Format::Csv => to_csv(&report.problems),
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, out)
+            .with_context(|| format!("failed to write report to '{}'", path.display()))?,
+        None => std::io::stdout().write_all(out.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+fn to_csv(problems: &[EventProblems]) -> String {
+    let mut out = String::from(
+        "id,opencast_id,title,series,realms,missing_captions,\
+            missing_audio_description,missing_description,missing_title\n",
+    );
+    for event in problems {
+        let fields = [
+            event.id.clone(),
+            event.opencast_id.clone().unwrap_or_default(),
+            event.title.clone(),
+            event.series.clone().unwrap_or_default(),
+            event.realms.join(" "),
+            event.missing_captions.to_string(),
+            event.missing_audio_description.to_string(),
+            event.missing_description.to_string(),
+            event.missing_title.to_string(),
+        ];
+        let line = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",");
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Quotes `field` if necessary, as described in RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
@@ -0,0 +1,273 @@
+//! CLI command `test-access` to detect access control regressions: runs
+//! GraphQL queries as a synthetic user and reports every returned object the
+//! user is not allowed to see according to the rules in the DB.
+//!
+//! Only objects whose `id` is selected in the query are checked. Objects
+//! without row level access rules (realms, blocks, series, ...) are ignored.
+//! The queries run in a transaction that is rolled back at the end, so even
+//! mutations leave the DB unchanged.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use juniper::{DefaultScalarValue, ScalarValue, Value};
+use postgres_types::ToSql;
+use structopt::StructOpt;
+
+use crate::{
+    api::{self, Id, cache::{RequestCache, ResolverCache}},
+    auth::{HasRoles, JwtContext, Permissions, ROLE_ANONYMOUS, User},
+    config::Config,
+    db::{self, types::Key},
+    extension::Extensions,
+    prelude::*,
+};
+
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Args {
+    /// Comma separated roles of the synthetic user. `ROLE_ANONYMOUS` is
+    /// always added and `auth.role_mapping` is applied. If not specified, the
+    /// queries are run as a logged out user.
+    #[structopt(long)]
+    user: Option<String>,
+
+    /// Username of the synthetic user. Only relevant for data belonging to a
+    /// user, like saved searches.
+    #[structopt(long, default_value = "tobira-test-access")]
+    username: String,
+
+    /// File containing a GraphQL query. Can be specified multiple times.
+    #[structopt(long, required = true, number_of_values = 1)]
+    query: Vec<PathBuf>,
+}
+
+/// An object returned by a query that the user should not see.
+struct Violation {
+    path: String,
+    id: Id,
+    reason: &'static str,
+}
+
+pub(crate) async fn run(args: &Args, config: Config) -> Result<()> {
+    let roles = args.user.as_ref().map(|roles| {
+        let roles = std::iter::once(ROLE_ANONYMOUS.to_owned())
+            .chain(roles.split(',').map(|role| role.trim().to_owned()))
+            .filter(|role| !role.is_empty())
+            .collect();
+        config.auth.role_mapping.apply(roles)
+    });
+    let user = || roles.as_ref().map(|roles| User {
+        username: args.username.clone(),
+        display_name: args.username.clone(),
+        email: None,
+        roles: roles.clone(),
+    });
+
+    let db = db::create_pool(&config.db).await
+        .context("failed to create database connection pool (database not running?)")?;
+    db::check_migrations(&mut *db.get().await?, &config.db).await
+        .context("failed to check DB migrations")?;
+    let search = Arc::new(config.meili.connect_only().await?);
+    let jwt = Arc::new(JwtContext::new(&config.auth.jwt)?);
+    let extensions = Arc::new(Extensions::load(&config)?);
+    let config = Arc::new(config);
+    let root = &api::root_node();
+
+    let mut conn = db.get().await?;
+    let mut num_violations = 0;
+    for path in &args.query {
+        let query = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+
+        let make_context = |tx| api::Context {
+            db: tx,
+            db_pool: db.clone(),
+            permissions: Permissions::new(&user(), &config.auth),
+            user: user(),
+            config: config.clone(),
+            jwt: jwt.clone(),
+            search: search.clone(),
+            cache: RequestCache::new(Arc::new(ResolverCache::new())),
+            extensions: extensions.clone(),
+            tracer: None,
+            client_ip: None,
+            client_country: None,
+        };
+        let tx = conn.transaction().await?;
+        let (result, tx) = api::with_transaction(tx, None, make_context, |context| async move {
+            let (value, errors) = juniper::execute(
+                &query,
+                None,
+                root,
+                &juniper::Variables::new(),
+                &*context,
+            ).await.map_err(|e| anyhow!("query '{}' is invalid: {e}", path.display()))?;
+
+            // Errors are expected, e.g. when the user cannot access something.
+            for e in &errors {
+                info!("'{}': error at {:?}: {}", path.display(), e.path(), e.error().message());
+            }
+
+            let mut ids = Vec::new();
+            collect_ids(&value, "", &mut ids);
+            let violations = check(&ids, &context).await?;
+            Ok::<_, anyhow::Error>((ids.len(), violations))
+        }).await;
+        tx.rollback().await?;
+        let (num_ids, violations) = result?;
+
+        println!("{}: checked {} objects", path.display(), num_ids);
+        for v in &violations {
+            println!("  {} {} ({})", v.path, v.id, v.reason);
+        }
+        num_violations += violations.len();
+    }
+
+    if num_violations > 0 {
+        bail!("found {num_violations} objects the user should not be able to see");
+    }
+    Ok(())
+}
+
+/// Collects all `id` fields that are valid IDs, together with the path of
+/// the object they belong to, e.g. `realm.blocks[2].series`.
+fn collect_ids(value: &Value<DefaultScalarValue>, path: &str, out: &mut Vec<(String, Id)>) {
+    match value {
+        Value::Object(object) => {
+            for (field, value) in object.iter() {
+                if field == "id" {
+                    let id = value.as_scalar()
+                        .and_then(|s| s.as_str())
+                        .and_then(|s| s.parse::<Id>().ok());
+                    if let Some(id) = id {
+                        out.push((path.to_owned(), id));
+                    }
+                } else {
+                    let path = if path.is_empty() { field.clone() } else { format!("{path}.{field}") };
+                    collect_ids(value, &path, out);
+                }
+            }
+        }
+        Value::List(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_ids(item, &format!("{path}[{i}]"), out);
+            }
+        }
+        Value::Null | Value::Scalar(_) => {}
+    }
+}
+
+/// Returns all of the given objects the current user should not see.
+async fn check(ids: &[(String, Id)], context: &api::Context) -> Result<Vec<Violation>> {
+    let keys_of = |kinds: &[[u8; 2]]| ids.iter()
+        .filter(|(_, id)| kinds.contains(&id.kind()))
+        .map(|(_, id)| id.key_for(id.kind()).unwrap())
+        .collect::<Vec<_>>();
+    let roles = context.user.roles();
+    let username = context.user.as_ref().map(|u| u.username.as_str());
+
+    const EVENT_KINDS: &[[u8; 2]] = &[Id::EVENT_KIND, Id::SEARCH_EVENT_KIND];
+    const MARKER_KINDS: &[[u8; 2]] = &[Id::EVENT_MARKER_KIND];
+    const NOTIFICATION_KINDS: &[[u8; 2]] = &[Id::NOTIFICATION_KIND];
+    const SAVED_SEARCH_KINDS: &[[u8; 2]] = &[Id::SAVED_SEARCH_KIND];
+    let event_keys = keys_of(EVENT_KINDS);
+    let marker_keys = keys_of(MARKER_KINDS);
+    let notification_keys = keys_of(NOTIFICATION_KINDS);
+    let saved_search_keys = keys_of(SAVED_SEARCH_KINDS);
+
+    type Check<'a> = (&'a [[u8; 2]], &'a str, &'a [&'a (dyn ToSql + Sync)], &'static str);
+    let checks: [Check; 4] = [
+        (
+            EVENT_KINDS,
+            // Events can be seen with a read role or if they are shown on a
+            // page of a realm (or its descendants) whose member roles the
+            // user has.
+            "select id from events \
+                where id = any($1) \
+                and not read_roles && $2 \
+                and not exists ( \
+                    select from blocks \
+                    cross join lateral ancestors_of_realm(blocks.realm_id) as realm \
+                    where (blocks.video_id = events.id or blocks.series_id = events.series) \
+                    and realm.member_roles && $2 \
+                )",
+            &[&event_keys, &roles],
+            "event not readable",
+        ),
+        (
+            MARKER_KINDS,
+            "select id from event_markers \
+                where id = any($1) \
+                and not (select read_roles from events where id = event) && $2",
+            &[&marker_keys, &roles],
+            "event of marker not readable",
+        ),
+        (
+            NOTIFICATION_KINDS,
+            "select id from notifications where id = any($1) and not recipient = any($2)",
+            &[&notification_keys, &roles],
+            "notification of someone else",
+        ),
+        (
+            SAVED_SEARCH_KINDS,
+            "select id from saved_searches where id = any($1) and username is distinct from $2",
+            &[&saved_search_keys, &username],
+            "saved search of someone else",
+        ),
+    ];
+
+    let mut hidden = HashMap::new();
+    for (kinds, query, args, reason) in checks {
+        if keys_of(kinds).is_empty() {
+            continue;
+        }
+        for key in context.db.query_mapped(query, args.iter().copied(), |row| row.get::<_, Key>(0)).await? {
+            for &kind in kinds {
+                hidden.insert((kind, key), reason);
+            }
+        }
+    }
+
+    ids.iter()
+        .filter_map(|(path, id)| {
+            let key = id.key_for(id.kind())?;
+            hidden.get(&(id.kind(), key)).map(|&reason| Violation {
+                path: path.clone(),
+                id: *id,
+                reason,
+            })
+        })
+        .collect::<Vec<_>>()
+        .pipe(Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use juniper::graphql_value;
+
+    use crate::{api::Id, db::types::Key};
+    use super::collect_ids;
+
+    #[test]
+    fn ids() {
+        let event = Id::event(Key(1)).to_string();
+        let value = graphql_value!({
+            "realm": {
+                "id": "not an ID",
+                "blocks": [
+                    { "id": "blAAAAAAAAAAC", "series": None },
+                    { "id": "blAAAAAAAAAAD", "event": { "id": (event.as_str()), "title": "x" } },
+                ],
+            },
+        });
+
+        let mut ids = Vec::new();
+        collect_ids(&value, "", &mut ids);
+        let ids = ids.into_iter().map(|(path, id)| (path, id.to_string())).collect::<Vec<_>>();
+        assert_eq!(ids, [
+            ("realm.blocks[0]".to_owned(), "blAAAAAAAAAAC".to_owned()),
+            ("realm.blocks[1]".to_owned(), "blAAAAAAAAAAD".to_owned()),
+            ("realm.blocks[1].event".to_owned(), event),
+        ]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Generating documentation of all configuration options from their
+//! definitions, so that it cannot drift from the code. The TOML template is
+//! generated by confique, the Markdown reference here.
+
+use std::fmt::Write;
+
+use confique::{Config as _, meta::{Expr, FieldKind, LeafKind, Meta}};
+
+use super::Config;
+
+
+/// Returns the commented TOML template with all options.
+pub(crate) fn toml_template() -> String {
+    confique::toml::format::<Config>(confique::toml::FormatOptions::default())
+}
+
+/// Returns a Markdown document describing all options, with one section per
+/// table.
+pub(crate) fn markdown() -> String {
+    markdown_for(&Config::META)
+}
+
+fn markdown_for(meta: &Meta) -> String {
+    let mut out = String::new();
+    out += "# Configuration reference\n\n";
+    out += "<!-- Generated by `tobira config docs --format markdown`. Do not edit! -->\n\n";
+    write_doc(&mut out, meta.doc);
+    write_table(&mut out, meta, &[]);
+
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    out
+}
+
+fn write_table(out: &mut String, meta: &Meta, path: &[&str]) {
+    for field in meta.fields {
+        let FieldKind::Leaf { env, kind } = &field.kind else {
+            continue;
+        };
+
+        let key = path.iter().copied().chain([field.name]).collect::<Vec<_>>().join(".");
+        writeln!(out, "### `{key}`\n").unwrap();
+        write_doc(out, field.doc);
+        match kind {
+            LeafKind::Required { default: Some(default) } => {
+                writeln!(out, "- Default: `{}`", PrintExpr(default)).unwrap();
+            }
+            LeafKind::Required { default: None } => *out += "- **Required**\n",
+            LeafKind::Optional => *out += "- Optional\n",
+        }
+        if let Some(env) = env {
+            writeln!(out, "- Environment variable: `{env}`").unwrap();
+        }
+        *out += "\n";
+    }
+
+    for field in meta.fields {
+        let FieldKind::Nested { meta } = &field.kind else {
+            continue;
+        };
+
+        let child_path = path.iter().copied().chain([field.name]).collect::<Vec<_>>();
+        writeln!(out, "## `[{}]`\n", child_path.join(".")).unwrap();
+        write_doc(out, field.doc);
+        write_table(out, meta, &child_path);
+    }
+}
+
+/// Writes doc comment lines as a paragraph. The space after `///` is removed,
+/// so that indented lines (used for examples) become code blocks.
+fn write_doc(out: &mut String, doc: &[&str]) {
+    if doc.is_empty() {
+        return;
+    }
+    for line in doc {
+        *out += line.strip_prefix(' ').unwrap_or(line).trim_end();
+        *out += "\n";
+    }
+    if !out.ends_with("\n\n") {
+        *out += "\n";
+    }
+}
+
+/// Formats default values like in the TOML template.
+struct PrintExpr<'a>(&'a Expr);
+
+impl std::fmt::Display for PrintExpr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Expr::Str(v) => toml::Value::String((*v).to_owned()).fmt(f),
+            Expr::Float(v) => v.fmt(f),
+            Expr::Integer(v) => v.fmt(f),
+            Expr::Bool(v) => v.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use confique::Config;
+
+    /// Test configuration.
+    #[derive(Config)]
+    #[allow(dead_code)]
+    struct TestConfig {
+        /// Name of the thing.
+        name: String,
+
+        #[config(nested)]
+        log: LogConfig,
+    }
+
+    #[derive(Config)]
+    #[allow(dead_code)]
+    struct LogConfig {
+        /// Whether to log to stdout. Example:
+        ///
+        ///     stdout = false
+        #[config(default = true, env = "LOG_STDOUT")]
+        stdout: bool,
+
+        #[config(default = "info")]
+        level: String,
+
+        file: Option<String>,
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(super::markdown_for(&TestConfig::META), "\
+            # Configuration reference\n\
+            \n\
+            <!-- Generated by `tobira config docs --format markdown`. Do not edit! -->\n\
+            \n\
+            Test configuration.\n\
+            \n\
+            ### `name`\n\
+            \n\
+            Name of the thing.\n\
+            \n\
+            - **Required**\n\
+            \n\
+            ## `[log]`\n\
+            \n\
+            ### `log.stdout`\n\
+            \n\
+            Whether to log to stdout. Example:\n\
+            \n    stdout = false\n\
+            \n\
+            - Default: `true`\n\
+            - Environment variable: `LOG_STDOUT`\n\
+            \n\
+            ### `log.level`\n\
+            \n\
+            - Default: `\"info\"`\n\
+            \n\
+            ### `log.file`\n\
+            \n\
+            - Optional\n\
+        ");
+    }
+}
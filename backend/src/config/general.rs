@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+
+use crate::{prelude::*, util::HttpHost};
 use super::TranslatedString;
 
 
@@ -8,6 +11,12 @@ pub(crate) struct GeneralConfig {
     // TODO: fix automatically generated `site_title =` template output.
     pub(crate) site_title: TranslatedString,
 
+    /// Public URL of this Tobira instance, e.g. "https://tobira.example.org".
+    /// Used where absolute links are needed: for `<link rel="canonical">`
+    /// (which is omitted if this is not set) and in saved search alert
+    /// emails.
+    pub(crate) site_url: Option<HttpHost>,
+
     /// Links that are shown in the footer. By default, two links are shown:
     ///
     /// ```
@@ -28,14 +37,109 @@ pub(crate) struct GeneralConfig {
     // TODO: this shouldn't be `Option`, but `config(default = ...)` does not
     // support complex types like this yet.
     footer_links: Option<Vec<FooterLink>>,
+
+    /// A note that is shown on every page above the main content, e.g. to
+    /// announce a maintenance window. If not set, no note is shown.
+    pub(crate) announcement: Option<TranslatedString>,
+
+    /// Metadata field by which the events of series and creator blocks can
+    /// be filtered, e.g. to show multiple semesters of a course on one realm
+    /// page with a switcher. The field is specified by its namespace and name
+    /// as sent by the harvest API, plus a label for the switcher. Example:
+    ///
+    /// ```
+    /// event_filter = {
+    ///     namespace = "http://purl.org/dc/terms/",
+    ///     field = "temporal",
+    ///     label = { en = "Semester", de = "Semester" },
+    /// }
+    /// ```
+    ///
+    /// If not set, events cannot be filtered.
+    pub(crate) event_filter: Option<EventFilter>,
+
+    /// Names of the rooms in which events are recorded, by the location sent
+    /// by Opencast (usually the capture agent ID). Shown instead of the
+    /// location where available. Example:
+    ///
+    /// ```
+    /// rooms = { "ca-hs1" = "Lecture hall 1", "ca-hs2" = "Lecture hall 2" }
+    /// ```
+    rooms: Option<HashMap<String, String>>,
+
+    /// Locale by which texts are sorted alphabetically, e.g. the children of
+    /// realms with an alphabetical order and events sorted by title. Must be
+    /// a locale for which PostgreSQL has an ICU collation, like "de" or "sv"
+    /// (see `pg_collation`). The default "und" sorts in a way that works
+    /// reasonably for most languages, e.g. "Ä" next to "A".
+    #[config(default = "und")]
+    sort_locale: String,
+
+    /// Sites whose links in event descriptions are marked as known in
+    /// `Event.descriptionLinks`, e.g. the library or other institutional
+    /// pages. Each is specified by a URL prefix and the name of the site.
+    /// Example:
+    ///
+    /// ```
+    /// known_links = [
+    ///     { prefix = "https://library.example.org/", name = "University library" },
+    /// ]
+    /// ```
+    known_links: Option<Vec<KnownLink>>,
 }
 
 impl GeneralConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.sort_locale.is_empty()
+            || !self.sort_locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!("invalid `general.sort_locale` '{}'", self.sort_locale);
+        }
+
+        Ok(())
+    }
+
+    /// Name of the ICU collation for `sort_locale`, as stored in
+    /// `pg_collation`.
+    pub(crate) fn collation_name(&self) -> String {
+        format!("{}-x-icu", self.sort_locale)
+    }
+
+    /// The collation for `sort_locale`, quoted to be used in SQL like
+    /// `order by title collate {}`.
+    pub(crate) fn collation(&self) -> String {
+        format!("\"{}\"", self.collation_name())
+    }
+
+    /// Returns the name of the room configured for `location`.
+    pub(crate) fn room(&self, location: &str) -> Option<&str> {
+        self.rooms.as_ref()?.get(location).map(String::as_str)
+    }
+
+    pub(crate) fn known_links(&self) -> &[KnownLink] {
+        self.known_links.as_deref().unwrap_or(&[])
+    }
+
     pub(crate) fn footer_links(&self) -> &[FooterLink] {
         self.footer_links.as_deref().unwrap_or(&[FooterLink::About, FooterLink::GraphiQL])
     }
 }
 
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct EventFilter {
+    pub(crate) namespace: String,
+    pub(crate) field: String,
+    pub(crate) label: TranslatedString,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct KnownLink {
+    pub(crate) prefix: String,
+    pub(crate) name: String,
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(untagged)]
 pub(crate) enum FooterLink {
@@ -1,6 +1,5 @@
 use std::{
-    fs,
-    io::{self, Write},
+    collections::HashMap,
     path::{Path, PathBuf},
     time::Duration,
 };
@@ -9,14 +8,19 @@ use confique::Config as _;
 use crate::prelude::*;
 
 
+pub(crate) mod docs;
+
 mod color;
 mod general;
+mod overrides;
 mod theme;
 mod translated_string;
 mod opencast;
 
 pub(crate) use self::{
     color::{Color, Hsl},
+    general::KnownLink,
+    overrides::Overrides,
     translated_string::TranslatedString,
     theme::ThemeConfig,
     opencast::OpencastConfig,
@@ -66,11 +70,48 @@ pub(crate) struct Config {
     #[config(nested)]
     pub(crate) sync: crate::sync::SyncConfig,
 
+    #[config(nested)]
+    pub(crate) external_events: crate::api::ExternalEventsConfig,
+
+    #[config(nested)]
+    pub(crate) upload: crate::api::UploadConfig,
+
+    #[config(nested)]
+    pub(crate) capture_agents: crate::api::CaptureAgentsConfig,
+
     #[config(nested)]
     pub(crate) meili: crate::search::MeiliConfig,
 
+    #[config(nested)]
+    pub(crate) search: crate::search::SearchConfig,
+
     #[config(nested)]
     pub(crate) theme: ThemeConfig,
+
+    #[config(nested)]
+    pub(crate) telemetry: crate::telemetry::TelemetryConfig,
+
+    #[config(nested)]
+    pub(crate) retention: crate::retention::RetentionConfig,
+
+    #[config(nested)]
+    pub(crate) saved_searches: crate::saved_search::SavedSearchConfig,
+
+    #[config(nested)]
+    pub(crate) sanitize: crate::sanitize::SanitizeConfig,
+
+    #[config(nested)]
+    pub(crate) webhooks: crate::outbox::WebhookConfig,
+
+    #[config(nested)]
+    pub(crate) geoblocking: crate::geoblock::GeoblockingConfig,
+
+    #[config(nested)]
+    pub(crate) warehouse: crate::warehouse::WarehouseConfig,
+
+    /// Configuration of compiled-in extensions (see `docs/extensions.md`),
+    /// one table per extension name, e.g. `[extensions.my-extension]`.
+    pub(crate) extensions: Option<HashMap<String, toml::Value>>,
 }
 
 impl Config {
@@ -96,7 +137,10 @@ impl Config {
         let path = path.as_ref();
         info!("Loading configuration from '{}'", path.display());
 
-        let mut config = Config::from_file(path)
+        let mut config = Config::builder()
+            .env()
+            .file(path)
+            .load()
             .context(format!("failed to read config file '{}'", path.display()))?;
 
         config.validate().context("failed to validate configuration")?;
@@ -109,7 +153,35 @@ impl Config {
     /// illegal or conflicting values.
     fn validate(&self) -> Result<()> {
         debug!("Validating configuration...");
+        self.general.validate()?;
+        self.db.validate()?;
+        self.http.validate()?;
+        self.auth.validate()?;
         self.opencast.validate()?;
+        self.sync.validate()?;
+        self.telemetry.validate()?;
+        self.retention.validate()?;
+        self.search.validate()?;
+        self.saved_searches.validate()?;
+        self.webhooks.validate()?;
+        self.upload.validate()?;
+        self.capture_agents.validate()?;
+        self.geoblocking.validate()?;
+        self.warehouse.validate()?;
+        if self.saved_searches.alerts_enabled() && self.general.site_url.is_none() {
+            bail!("`general.site_url` has to be set if `saved_searches.sendmail` is set, \
+                as it is used for links in alert emails");
+        }
+
+        if self.auth.mode == crate::auth::AuthMode::Opencast {
+            let node = self.auth.opencast_login.node.as_ref()
+                .unwrap_or_else(|| self.opencast.sync_node());
+            if node.scheme != hyper::http::uri::Scheme::HTTPS && !node.is_local() {
+                bail!("passwords are sent to the Opencast node used for login \
+                    ('{node}'), so it has to use HTTPS unless it is on the local machine \
+                    (see `auth.opencast_login.node`)");
+            }
+        }
 
         Ok(())
     }
@@ -132,6 +204,26 @@ impl Config {
             fix_path(&base, p);
         }
 
+        if let Some(p) = &mut self.http.thumbnails.cache_dir {
+            fix_path(base, p);
+        }
+
+        if let Some(p) = &mut self.http.graphiql.examples {
+            fix_path(base, p);
+        }
+
+        if let Some(p) = &mut self.upload.virus_scan.temp_dir {
+            fix_path(base, p);
+        }
+
+        if let Some(p) = &mut self.upload.virus_scan.quarantine_dir {
+            fix_path(base, p);
+        }
+
+        if let Some(p) = &mut self.geoblocking.database {
+            fix_path(base, p);
+        }
+
         if let Some(p) = &mut self.log.file {
             fix_path(&base, p);
         }
@@ -143,24 +235,6 @@ impl Config {
     }
 }
 
-/// Writes the generated TOML config template file to the given destination or
-/// stdout.
-pub(crate) fn write_template(path: Option<&PathBuf>) -> Result<()> {
-    use confique::toml::FormatOptions;
-
-    info!(
-        "Writing configuration template to '{}'",
-        path.map(|p| p.display().to_string()).unwrap_or("<stdout>".into()),
-    );
-
-    let template = confique::toml::format::<Config>(FormatOptions::default());
-    match path {
-        Some(path) => fs::write(path, template)?,
-        None => io::stdout().write_all(template.as_bytes())?,
-    }
-
-    Ok(())
-}
 
 /// Our custom format for durations. We allow a couple useful units and required
 /// a unit to increase readability of config files.
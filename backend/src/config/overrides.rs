@@ -0,0 +1,136 @@
+//! Settings that administrators can change at runtime via the API. They are
+//! stored in the `settings` table and take precedence over the corresponding
+//! values from the configuration file. Only a small set of mostly cosmetic
+//! settings can be overridden like this.
+
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::{db::Db, prelude::*};
+use super::{Color, Config, TranslatedString, general::FooterLink, theme::ColorConfig};
+
+
+/// Config that can be overridden, loaded from the `settings` table. `None`
+/// means the value from the config file is used.
+#[derive(Debug, Default)]
+pub(crate) struct Overrides {
+    navigation_color: Option<Color>,
+    accent_color: Option<Color>,
+    grey50_color: Option<Color>,
+    danger_color: Option<Color>,
+    happy_color: Option<Color>,
+    announcement: Option<TranslatedString>,
+    footer_links: Option<Vec<FooterLink>>,
+    login_page_note: Option<TranslatedString>,
+}
+
+impl Overrides {
+    /// The keys of all settings that can be overridden. They are named like
+    /// the corresponding value in the config file.
+    pub(crate) const KEYS: &'static [&'static str] = &[
+        "theme.color.navigation",
+        "theme.color.accent",
+        "theme.color.grey50",
+        "theme.color.danger",
+        "theme.color.happy",
+        "general.announcement",
+        "general.footer_links",
+        "auth.login_page.note",
+    ];
+
+    /// Loads all overrides from the DB. Invalid entries are ignored with a
+    /// warning.
+    pub(crate) async fn load(db: &Db) -> Result<Self, tokio_postgres::Error> {
+        let statement = db.prepare_cached("select key, value::text from settings").await?;
+        let rows = db.query(&statement, &[]).await?;
+
+        let mut out = Self::default();
+        for row in rows {
+            let key = row.get::<_, String>(0);
+            if let Err(e) = out.set(&key, row.get(1)) {
+                warn!("Ignoring invalid setting '{}' stored in DB: {}", key, e);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Returns `true` if no setting is overridden.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.navigation_color.is_none()
+            && self.accent_color.is_none()
+            && self.grey50_color.is_none()
+            && self.danger_color.is_none()
+            && self.happy_color.is_none()
+            && self.announcement.is_none()
+            && self.footer_links.is_none()
+            && self.login_page_note.is_none()
+    }
+
+    /// Returns the overridden `general.announcement`, if any.
+    pub(crate) fn announcement(&self) -> Option<&TranslatedString> {
+        self.announcement.as_ref()
+    }
+
+    /// Sets the setting with the given key to the JSON-encoded `value`.
+    /// Returns an error if the key is unknown or the value has the wrong
+    /// format. Can also be used to just validate settings.
+    pub(crate) fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: DeserializeOwned>(value: &str) -> Result<Option<T>, String> {
+            serde_json::from_str(value).map(Some).map_err(|e| e.to_string())
+        }
+
+        match key {
+            "theme.color.navigation" => self.navigation_color = parse(value)?,
+            "theme.color.accent" => self.accent_color = parse(value)?,
+            "theme.color.grey50" => self.grey50_color = parse(value)?,
+            "theme.color.danger" => self.danger_color = parse(value)?,
+            "theme.color.happy" => self.happy_color = parse(value)?,
+            "general.announcement" => self.announcement = parse(value)?,
+            "general.footer_links" => self.footer_links = parse(value)?,
+            "auth.login_page.note" => self.login_page_note = parse(value)?,
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the theme CSS (see `ThemeConfig::to_css`) with overridden
+    /// colors, or `None` if no color is overridden.
+    pub(crate) fn theme_css(&self, config: &Config) -> Option<String> {
+        let colors = [
+            self.navigation_color,
+            self.accent_color,
+            self.grey50_color,
+            self.danger_color,
+            self.happy_color,
+        ];
+        if colors.iter().all(Option::is_none) {
+            return None;
+        }
+
+        let base = &config.theme.color;
+        let color = ColorConfig {
+            navigation: self.navigation_color.unwrap_or(base.navigation),
+            accent: self.accent_color.unwrap_or(base.accent),
+            grey50: self.grey50_color.unwrap_or(base.grey50),
+            danger: self.danger_color.unwrap_or(base.danger),
+            happy: self.happy_color.unwrap_or(base.happy),
+        };
+        Some(config.theme.to_css_with_colors(&color))
+    }
+
+    /// Overrides values in the frontend config JSON object that is embedded in
+    /// `index.html`.
+    pub(crate) fn apply_to_frontend_config(&self, frontend_config: &mut serde_json::Value) {
+        if let Some(announcement) = &self.announcement {
+            frontend_config["announcement"] = json!(announcement);
+        }
+        if let Some(footer_links) = &self.footer_links {
+            frontend_config["footerLinks"] = json!(footer_links);
+        }
+        if let Some(note) = &self.login_page_note {
+            frontend_config["auth"]["loginPageNote"] = json!(note);
+        }
+    }
+}
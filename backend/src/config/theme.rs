@@ -66,7 +66,7 @@ pub(crate) struct SingleLogoConfig {
 #[derive(serde::Deserialize)]
 pub(crate) struct LogoResolution(pub(crate) [u32; 2]);
 
-#[derive(Debug, confique::Config)]
+#[derive(Debug, Clone, confique::Config)]
 pub(crate) struct ColorConfig {
     #[config(default = "#347856")]
     pub(crate) navigation: Color,
@@ -104,6 +104,11 @@ impl ThemeConfig {
     /// Returns a string containing CSS that sets lots of variables on the
     /// `:root` element.
     pub(crate) fn to_css(&self) -> String {
+        self.to_css_with_colors(&self.color)
+    }
+
+    /// Like `to_css`, but uses the given colors instead of `self.color`.
+    pub(crate) fn to_css_with_colors(&self, color: &ColorConfig) -> String {
         let mut out = String::from(":root {\n");
 
         // Helper macros
@@ -133,38 +138,38 @@ impl ThemeConfig {
 
 
         // Colors
-        let nav = Hsl::from(self.color.navigation);
+        let nav = Hsl::from(color.navigation);
         add!("--nav-hue" => format_args!("{:.2}", nav.h));
         add!("--nav-sat" => format_args!("{:.2}%", nav.s * 100.0));
         add!("--nav-color" => hsl!("nav", nav.l));
         add!("--nav-color-dark" => hsl!("nav", nav.darken(0.2).l));
         add!("--nav-color-darker" => hsl!("nav", nav.darken(0.4).l));
-        add!("--nav-color-bw-contrast" => self.color.navigation.bw_contrast());
+        add!("--nav-color-bw-contrast" => color.navigation.bw_contrast());
 
-        let accent = Hsl::from(self.color.accent);
+        let accent = Hsl::from(color.accent);
         add!("--accent-hue" => format_args!("{:.2}", accent.h));
         add!("--accent-sat" => format_args!("{:.2}%", accent.s * 100.0));
         add!("--accent-color" => hsl!("accent", accent.l));
         add!("--accent-color-darker" => hsl!("accent", accent.darken(0.4).l));
-        add!("--accent-color-bw-contrast" => self.color.accent.bw_contrast());
+        add!("--accent-color-bw-contrast" => color.accent.bw_contrast());
 
-        let danger = Hsl::from(self.color.danger);
+        let danger = Hsl::from(color.danger);
         add!("--danger-hue" => format_args!("{:.2}", danger.h));
         add!("--danger-sat" => format_args!("{:.2}%", danger.s * 100.0));
         add!("--danger-color" => hsl!("danger", danger.l));
         add!("--danger-color-darker" => hsl!("danger", danger.darken(0.4).l));
-        add!("--danger-color-bw-contrast" => self.color.danger.bw_contrast());
+        add!("--danger-color-bw-contrast" => color.danger.bw_contrast());
 
-        let happy = Hsl::from(self.color.happy);
+        let happy = Hsl::from(color.happy);
         add!("--happy-hue" => format_args!("{:.2}", happy.h));
         add!("--happy-sat" => format_args!("{:.2}%", happy.s * 100.0));
         add!("--happy-color" => hsl!("happy", happy.l));
         add!("--happy-color-lighter" => hsl!("happy", happy.lighten(0.15).l));
         add!("--happy-color-darker" => hsl!("happy", happy.darken(0.1).l));
         add!("--happy-color-dark" => hsl!("happy", happy.darken(0.3).l));
-        add!("--happy-color-bw-contrast" => self.color.happy.bw_contrast());
+        add!("--happy-color-bw-contrast" => color.happy.bw_contrast());
 
-        let grey = Hsl::from(self.color.grey50);
+        let grey = Hsl::from(color.grey50);
         add!("--grey-hue" => format_args!("{:.2}", grey.h));
         add!("--grey-sat" => format_args!("{:.2}%", grey.s * 100.0));
         add!("--grey97" => hsl!("grey", 0.97));
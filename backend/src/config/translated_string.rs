@@ -18,6 +18,48 @@ impl TranslatedString {
     pub(crate) fn en(&self) -> &str {
         &self.0["en"]
     }
+
+    /// Returns the string in the given language, falling back to English.
+    pub(crate) fn get(&self, lang: &str) -> &str {
+        self.0.get(lang).unwrap_or_else(|| &self.0["en"])
+    }
+
+    /// Returns the languages this string is specified in, in the order of
+    /// `LANGUAGES`.
+    pub(crate) fn languages(&self) -> impl Iterator<Item = &'static str> + '_ {
+        Self::LANGUAGES.iter().copied().filter(|lang| self.0.contains_key(*lang))
+    }
+
+    /// Picks the language to show to a client with the given
+    /// `Accept-Language` header. Only the primary subtag of the requested
+    /// languages is considered and ties are broken by the order in the
+    /// header. If none of `LANGUAGES` is acceptable, `default` (e.g. the
+    /// locale of a realm) is used if supported, and English otherwise.
+    pub(crate) fn negotiate(accept_language: Option<&str>, default: Option<&str>) -> &'static str {
+        let supported = |tag: &str| {
+            let primary = tag.split('-').next().unwrap_or_default().trim().to_ascii_lowercase();
+            Self::LANGUAGES.iter().copied().find(|lang| *lang == primary)
+        };
+
+        let mut best: Option<(&'static str, f32)> = None;
+        for entry in accept_language.unwrap_or_default().split(',') {
+            let mut parts = entry.split(';');
+            let Some(lang) = parts.next().and_then(supported) else {
+                continue;
+            };
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if quality > 0.0 && best.is_none_or(|(_, best_q)| quality > best_q) {
+                best = Some((lang, quality));
+            }
+        }
+
+        best.map(|(lang, _)| lang)
+            .or_else(|| default.and_then(supported))
+            .unwrap_or("en")
+    }
 }
 
 impl<'de> Deserialize<'de> for TranslatedString {
@@ -59,3 +101,19 @@ impl fmt::Debug for TranslatedString {
         f.debug_map().entries(self.0.iter()).finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TranslatedString;
+
+    #[test]
+    fn negotiate() {
+        let negotiate = TranslatedString::negotiate;
+        assert_eq!(negotiate(Some("de-CH,de;q=0.9,en;q=0.8"), None), "de");
+        assert_eq!(negotiate(Some("fr, en;q=0.5, de;q=0.7"), None), "de");
+        assert_eq!(negotiate(Some("en;q=0.5, DE;q=0.5"), Some("de")), "en");
+        assert_eq!(negotiate(Some("de;q=0, fr"), Some("de-AT")), "de");
+        assert_eq!(negotiate(Some("*"), Some("fr")), "en");
+        assert_eq!(negotiate(None, None), "en");
+    }
+}
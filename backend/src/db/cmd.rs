@@ -50,10 +50,10 @@ pub(crate) async fn run(cmd: &DbCommand, config: &Config) -> Result<()> {
     // Dispatch command
     match cmd {
         DbCommand::Clear => clear(&mut db, config).await?,
-        DbCommand::Migrate => super::migrate(&mut db).await?,
+        DbCommand::Migrate => super::migrate(&mut db, &config.db).await?,
         DbCommand::Reset => {
             clear(&mut db, config).await?;
-            super::migrate(&mut db).await?;
+            super::migrate(&mut db, &config.db).await?;
         }
         DbCommand::Script { script } => run_script(&db, &script).await?,
         DbCommand::Console => unreachable!("already handled above"),
@@ -0,0 +1,194 @@
+//! Application-level encryption of sensitive columns, like email addresses of
+//! users, so that DB dumps leak less. See `db.encryption_key`.
+//!
+//! Encrypted values are stored as `enc1:<key ID>:<base64>` with AES-256-GCM,
+//! where the base64 part contains the nonce followed by the ciphertext. The
+//! name of the column is used as associated data, so values cannot be moved
+//! to other columns unnoticed. Values without that prefix are plain text,
+//! e.g. stored before a key was configured. They are still read as is and
+//! `tobira rekey` encrypts them.
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::prelude::*;
+use super::DbConfig;
+
+
+const PREFIX: &str = "enc1:";
+
+/// A column whose values are encrypted if a key is configured.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Column {
+    pub(crate) table: &'static str,
+    pub(crate) name: &'static str,
+}
+
+impl Column {
+    pub(crate) const SESSION_EMAIL: Self = Self { table: "user_sessions", name: "email" };
+    pub(crate) const ALERT_EMAIL: Self = Self { table: "saved_searches", name: "alert_email" };
+    pub(crate) const FOLLOW_EMAIL: Self = Self { table: "series_follows", name: "email" };
+
+    /// All encrypted columns, see `tobira rekey`.
+    pub(crate) const ALL: [Self; 3] = [Self::SESSION_EMAIL, Self::ALERT_EMAIL, Self::FOLLOW_EMAIL];
+
+    fn aad(self) -> String {
+        format!("{}.{}", self.table, self.name)
+    }
+}
+
+struct ColumnKey {
+    /// Start of the SHA-256 hash of the key, to find the right one for
+    /// decrypting.
+    id: String,
+    key: LessSafeKey,
+}
+
+impl ColumnKey {
+    fn parse(secret: &Secret<String>) -> Result<Self> {
+        let bytes = base64::decode(secret.expose_secret().trim())
+            .context("encryption key is not valid base64")?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| anyhow!("encryption key has to be 32 bytes long, but is {}", bytes.len()))?;
+        let id = hex::encode(&digest::digest(&digest::SHA256, &bytes).as_ref()[..4]);
+
+        Ok(Self { id, key: LessSafeKey::new(key) })
+    }
+}
+
+impl DbConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        for key in self.keys() {
+            ColumnKey::parse(key).context("invalid key in `db.encryption_key` \
+                or `db.old_encryption_keys`")?;
+        }
+        Ok(())
+    }
+
+    /// The current and all old encryption keys.
+    fn keys(&self) -> impl Iterator<Item = &Secret<String>> {
+        self.encryption_key.iter().chain(self.old_encryption_keys.iter().flatten())
+    }
+}
+
+/// Encrypts `value` with `db.encryption_key`. Returns it unchanged if no key
+/// is configured.
+pub(crate) fn encrypt(config: &DbConfig, column: Column, value: &str) -> Result<String> {
+    let Some(key) = &config.encryption_key else {
+        return Ok(value.to_owned());
+    };
+    let key = ColumnKey::parse(key)?;
+
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| anyhow!("failed to generate nonce"))?;
+    let mut data = value.as_bytes().to_vec();
+    key.key
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(column.aad()),
+            &mut data,
+        )
+        .map_err(|_| anyhow!("failed to encrypt value of {}", column.aad()))?;
+
+    Ok(format!("{PREFIX}{}:{}", key.id, base64::encode([&nonce[..], &data].concat())))
+}
+
+/// Decrypts a value stored by `encrypt`, with `db.encryption_key` or one of
+/// `db.old_encryption_keys`. Plain text values are returned as is.
+pub(crate) fn decrypt(config: &DbConfig, column: Column, value: &str) -> Result<String> {
+    let Some((key_id, data)) = value.strip_prefix(PREFIX).and_then(|v| v.split_once(':')) else {
+        return Ok(value.to_owned());
+    };
+
+    let key = config.keys()
+        .map(ColumnKey::parse)
+        .find(|key| key.as_ref().map_or(true, |key| key.id == key_id))
+        .ok_or_else(|| anyhow!("value of {} was encrypted with unknown key {}", column.aad(), key_id))??;
+
+    let mut data = base64::decode(data)
+        .with_context(|| format!("encrypted value of {} is not valid base64", column.aad()))?;
+    if data.len() < NONCE_LEN {
+        bail!("encrypted value of {} is too short", column.aad());
+    }
+    let nonce = Nonce::try_assume_unique_for_key(&data[..NONCE_LEN])
+        .map_err(|_| anyhow!("invalid nonce"))?;
+    let plain = key.key
+        .open_in_place(nonce, Aad::from(column.aad()), &mut data[NONCE_LEN..])
+        .map_err(|_| anyhow!("failed to decrypt value of {}", column.aad()))?;
+
+    String::from_utf8(plain.to_vec())
+        .with_context(|| format!("decrypted value of {} is not valid UTF-8", column.aad()))
+}
+
+/// Whether `value` is encrypted with the current `db.encryption_key`, or is
+/// plain text if there is none. Used by `tobira rekey`.
+pub(crate) fn is_current(config: &DbConfig, value: &str) -> Result<bool> {
+    match &config.encryption_key {
+        None => Ok(!value.starts_with(PREFIX)),
+        Some(key) => {
+            let prefix = format!("{PREFIX}{}:", ColumnKey::parse(key)?.id);
+            Ok(value.starts_with(&prefix))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(key: Option<&str>, old_keys: &[&str]) -> DbConfig {
+        DbConfig {
+            user: "tobira".into(),
+            password: Secret::new("tobira".into()),
+            host: "127.0.0.1".into(),
+            port: 5432,
+            database: "tobira".into(),
+            explain_slow_queries: None,
+            encryption_key: key.map(|key| Secret::new(key.into())),
+            old_encryption_keys: Some(
+                old_keys.iter().map(|key| Secret::new(key.to_string())).collect(),
+            ),
+            migration_lock_timeout: std::time::Duration::from_secs(600),
+        }
+    }
+
+    const KEY_A: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const KEY_B: &str = "BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBA=";
+
+    #[test]
+    fn round_trip() {
+        let config = config(Some(KEY_A), &[]);
+        let column = Column::SESSION_EMAIL;
+        let encrypted = encrypt(&config, column, "peter@example.org").unwrap();
+        assert!(encrypted.starts_with(PREFIX));
+        assert_ne!(encrypted, encrypt(&config, column, "peter@example.org").unwrap());
+        assert!(is_current(&config, &encrypted).unwrap());
+        assert_eq!(decrypt(&config, column, &encrypted).unwrap(), "peter@example.org");
+
+        // Values are bound to their column.
+        assert!(decrypt(&config, Column::FOLLOW_EMAIL, &encrypted).is_err());
+    }
+
+    #[test]
+    fn key_rotation() {
+        let encrypted = encrypt(&config(Some(KEY_A), &[]), Column::ALERT_EMAIL, "a@b.c").unwrap();
+
+        let rotated = config(Some(KEY_B), &[KEY_A]);
+        assert!(!is_current(&rotated, &encrypted).unwrap());
+        assert_eq!(decrypt(&rotated, Column::ALERT_EMAIL, &encrypted).unwrap(), "a@b.c");
+
+        assert!(decrypt(&config(Some(KEY_B), &[]), Column::ALERT_EMAIL, &encrypted).is_err());
+    }
+
+    #[test]
+    fn plain_text() {
+        let config = config(None, &[]);
+        assert_eq!(encrypt(&config, Column::SESSION_EMAIL, "a@b.c").unwrap(), "a@b.c");
+        assert_eq!(decrypt(&config, Column::SESSION_EMAIL, "a@b.c").unwrap(), "a@b.c");
+        assert!(is_current(&config, "a@b.c").unwrap());
+    }
+}
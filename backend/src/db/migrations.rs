@@ -1,176 +1,198 @@
 use chrono::{DateTime, Utc, offset::TimeZone};
 use once_cell::sync::Lazy;
-use std::{collections::BTreeMap, time::Duration};
-use tokio_postgres::{IsolationLevel, error::SqlState};
+use std::collections::BTreeMap;
+use tokio_postgres::error::SqlState;
 
-use crate::prelude::*;
-use super::Db;
+use crate::{http, prelude::*};
+use super::{Db, DbConfig};
 
 
+/// Key of the advisory lock that is held while checking and applying
+/// migrations ("tobira" in ASCII).
+const LOCK_KEY: i64 = 0x746f62697261;
+
 /// Makes sure the database schema is up to date by checking the active
 /// migrations and applying all missing ones.
 ///
 /// If anything unexpected is noticed, an error is returned to notify the user
 /// they have to manually deal with it.
-pub async fn migrate(db: &mut Db) -> Result<()> {
-    // The whole migration process is wrapped in one serializable transaction.
-    // This guarantees that only one Tobira node ever does the migrations. As
-    // this only happens during startup, the potential slow down from such a
-    // strong isolation level is fine.
-    //
-    // Serializable transactions can fail when committing them. That means we
-    // have to wrap everything in a loop and retry. If the transaction ever
-    // fails for one Tobira node and that node retries, we _expect_ that in the
-    // second loop iteration the node will observe that the `__db_migrations`
-    // table already exists as the transaction of another node is expected to
-    // have correctly committed by that point.
-    loop {
-        let tx = db.build_transaction()
-            .isolation_level(IsolationLevel::Serializable)
-            .start()
-            .await?;
-
-        // Create the meta table `__db_migrations` if it doesn't exist yet.
-        if !super::query::does_table_exist(&*tx, "__db_migrations").await? {
-            // Check if there are any other tables in the database, which would be fishy.
-            let tables = super::query::all_table_names(&*tx).await?;
-            if !tables.is_empty() {
-                bail!(
-                    "migration table '__db_migrations' does not exist, but some other \
-                        tables ({}) do exist. This should not happen.",
-                    tables.join(", "),
-                );
-            }
+pub async fn migrate(db: &mut Db, config: &DbConfig) -> Result<()> {
+    run(db, config, true).await
+}
 
-            info!("Database is empty. Creating table '__db_migrations'...");
-            tx.batch_execute(include_str!("db-migrations.sql"))
-                .await
-                .context("could not create migrations meta table")?;
-        } else {
-            debug!("Table '__db_migrations' already exists");
-        }
+/// Like `migrate`, but returns an error instead of applying missing
+/// migrations. For commands that must not change the DB.
+pub async fn check_migrations(db: &mut Db, config: &DbConfig) -> Result<()> {
+    run(db, config, false).await
+}
 
+async fn run(db: &mut Db, config: &DbConfig, apply: bool) -> Result<()> {
+    // The whole migration process is wrapped in one transaction holding an
+    // advisory lock. This guarantees that only one Tobira node ever does the
+    // migrations, e.g. if several replicas are started at the same time after
+    // an upgrade. The others wait until the first one is done and then find
+    // the DB up to date. Nodes that only check the migrations take the lock
+    // in shared mode, so they do not block each other. The lock is released
+    // with the end of the transaction.
+    let tx = db.transaction().await?;
+    let (try_lock, lock) = if apply {
+        ("select pg_try_advisory_xact_lock($1)", "select pg_advisory_xact_lock($1)")
+    } else {
+        (
+            "select pg_try_advisory_xact_lock_shared($1)",
+            "select pg_advisory_xact_lock_shared($1)",
+        )
+    };
+    if !tx.query_one(try_lock, &[&LOCK_KEY]).await?.get::<_, bool>(0) {
+        let timeout = config.migration_lock_timeout;
+        info!(
+            "Another Tobira process is migrating the database. Waiting for it to finish \
+                (at most {:?}, see `db.migration_lock_timeout`) ...",
+            timeout,
+        );
+        http::startup::set_status("waiting for another Tobira process to migrate the database");
 
-        /// The migration data from the DB.
-        #[derive(Debug)]
-        struct RawMigration {
-            name: String,
-            applied_on: DateTime<Utc>,
-            script: String,
-        }
+        tx.batch_execute(&format!("set local lock_timeout = {}", timeout.as_millis())).await?;
+        match tx.query_one(lock, &[&LOCK_KEY]).await {
+            Err(e) if e.code() == Some(&SqlState::LOCK_NOT_AVAILABLE) => bail!(
+                "another Tobira process did not finish migrating the database within {:?} \
+                    (see `db.migration_lock_timeout`)",
+                timeout,
+            ),
+            result => result?,
+        };
+        tx.batch_execute("set local lock_timeout to default").await?;
+        info!("The other Tobira process finished migrating the database");
+    }
+    http::startup::set_status("checking database migrations");
 
-        debug!("Checking DB migrations");
-
-        // Retrieve all active migrations from the DB.
-        let active_migrations = tx
-            .query_raw("select id, name, applied_on, script from __db_migrations", dbargs![])
-            .await?
-            .map_ok(|row| (
-                row.get::<_, i64>(0) as u64,
-                RawMigration {
-                    name: row.get(1),
-                    applied_on: Utc.from_utc_datetime(&row.get(2)),
-                    script: row.get(3),
-                }
-            ))
-            .try_collect::<BTreeMap<_, _>>()
-            .await?;
-
-
-        // Make sure the IDs are consecutive
-        if !active_migrations.keys().copied().eq(1..active_migrations.len() as u64 + 1) {
-            bail!("The IDs of the active migrations are not consecutive. This is unexpected.");
+    // Create the meta table `__db_migrations` if it doesn't exist yet.
+    if !super::query::does_table_exist(&*tx, "__db_migrations").await? {
+        // Check if there are any other tables in the database, which would be fishy.
+        let tables = super::query::all_table_names(&*tx).await?;
+        if !tables.is_empty() {
+            bail!(
+                "migration table '__db_migrations' does not exist, but some other \
+                    tables ({}) do exist. This should not happen.",
+                tables.join(", "),
+            );
+        }
+        if !apply {
+            bail!("the database is empty, run `tobira db migrate` first");
         }
 
-        // Make sure existing migration match the ones we know about.
-        for (id, actual_migration) in &active_migrations {
-            let expected_migration = MIGRATIONS.get(id).ok_or_else(|| anyhow!(
-                "The migration '{}-{}' is active in the database (applied on {}), but no \
-                    such migration is known to this Tobira application. This is unexpected.",
+        info!("Database is empty. Creating table '__db_migrations'...");
+        tx.batch_execute(include_str!("db-migrations.sql"))
+            .await
+            .context("could not create migrations meta table")?;
+    } else {
+        debug!("Table '__db_migrations' already exists");
+    }
+
+
+    /// The migration data from the DB.
+    #[derive(Debug)]
+    struct RawMigration {
+        name: String,
+        applied_on: DateTime<Utc>,
+        script: String,
+    }
+
+    debug!("Checking DB migrations");
+
+    // Retrieve all active migrations from the DB.
+    let active_migrations = tx
+        .query_raw("select id, name, applied_on, script from __db_migrations", dbargs![])
+        .await?
+        .map_ok(|row| (
+            row.get::<_, i64>(0) as u64,
+            RawMigration {
+                name: row.get(1),
+                applied_on: Utc.from_utc_datetime(&row.get(2)),
+                script: row.get(3),
+            }
+        ))
+        .try_collect::<BTreeMap<_, _>>()
+        .await?;
+
+
+    // Make sure the IDs are consecutive
+    if !active_migrations.keys().copied().eq(1..active_migrations.len() as u64 + 1) {
+        bail!("The IDs of the active migrations are not consecutive. This is unexpected.");
+    }
+
+    // Make sure existing migration match the ones we know about.
+    for (id, actual_migration) in &active_migrations {
+        let expected_migration = MIGRATIONS.get(id).ok_or_else(|| anyhow!(
+            "The migration '{}-{}' is active in the database (applied on {}), but no \
+                such migration is known to this Tobira application. This is unexpected.",
+            id,
+            actual_migration.name,
+            actual_migration.applied_on,
+        ))?;
+
+        if actual_migration.script != expected_migration.script {
+            debug!(
+                "Expected script for '{}-{}':\n{}",
+                id,
+                expected_migration.name,
+                expected_migration.script,
+            );
+            debug!(
+                "Actual (in database) script for '{}-{}':\n{}",
+                id,
+                actual_migration.name,
+                actual_migration.script,
+            );
+
+            bail!(
+                "The script of active migration '{}-{}' (applied on {}) does not match the \
+                    expected script for that migration. This is unexpected.",
                 id,
                 actual_migration.name,
                 actual_migration.applied_on,
-            ))?;
-
-            if actual_migration.script != expected_migration.script {
-                debug!(
-                    "Expected script for '{}-{}':\n{}",
-                    id,
-                    expected_migration.name,
-                    expected_migration.script,
-                );
-                debug!(
-                    "Actual (in database) script for '{}-{}':\n{}",
-                    id,
-                    actual_migration.name,
-                    actual_migration.script,
-                );
-
-                bail!(
-                    "The script of active migration '{}-{}' (applied on {}) does not match the \
-                        expected script for that migration. This is unexpected.",
-                    id,
-                    actual_migration.name,
-                    actual_migration.applied_on,
-                );
-            }
+            );
         }
+    }
 
 
-        // Apply missing migrations in order. We already know that `MIGRATIONS` and
-        // `active_migrations` have consecutive IDs, so we can simply iterate over
-        // this range. We already know that `MIGRATIONS` contains at least as many
-        // elements as `active_migrations`.
-        if MIGRATIONS.len() == active_migrations.len() {
-            info!("All migrations are already applied: database schema is up to date.")
-        } else {
-            info!("The database is missing some migrations. Applying them now.");
-            for (id, migration) in MIGRATIONS.range(active_migrations.len() as u64 + 1..) {
-                debug!("Applying migration '{}-{}' ...", id, migration.name);
-                trace!("Executing:\n{}", migration.script);
-
-                tx.batch_execute(migration.script)
-                    .await
-                    .context(format!("failed to run script for '{}-{}'", id, migration.name))?;
-
-                let query = "insert into __db_migrations (id, name, applied_on, script) \
-                    values ($1, $2, now() at time zone 'utc', $3)";
-                // let params = ;
-                tx.execute(query, &[&(*id as i64), &migration.name, &migration.script])
-                    .await
-                    .context("failed to update __db_migrations")?;
-            }
-        }
-
-        match tx.commit().await {
-            Ok(_) => {
-                let number_of_executed_migrations = MIGRATIONS.len() - active_migrations.len();
-                if number_of_executed_migrations > 0 {
-                    info!(
-                        "Applied {} migrations. DB is up to date now.",
-                        number_of_executed_migrations,
-                    );
-                }
-
-                return Ok(());
-            }
+    // Apply missing migrations in order. We already know that `MIGRATIONS` and
+    // `active_migrations` have consecutive IDs, so we can simply iterate over
+    // this range. We already know that `MIGRATIONS` contains at least as many
+    // elements as `active_migrations`.
+    if MIGRATIONS.len() == active_migrations.len() {
+        info!("All migrations are already applied: database schema is up to date.")
+    } else if !apply {
+        bail!(
+            "the database is missing {} migrations, run `tobira db migrate` first",
+            MIGRATIONS.len() - active_migrations.len(),
+        );
+    } else {
+        info!("The database is missing some migrations. Applying them now.");
+        for (id, migration) in MIGRATIONS.range(active_migrations.len() as u64 + 1..) {
+            debug!("Applying migration '{}-{}' ...", id, migration.name);
+            trace!("Executing:\n{}", migration.script);
 
-            Err(e) if e.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE) => {
-                let backoff_duration = Duration::from_millis(500);
-                warn!(
-                    "Database migration transaction failed to commit. This is likely because \
-                    of another Tobira node which has executed the same transaction concurrently. \
-                    Will try again in {:?}.",
-                    backoff_duration,
-                );
-
-                tokio::time::sleep(backoff_duration).await;
-                continue;
-            }
+            tx.batch_execute(migration.script)
+                .await
+                .context(format!("failed to run script for '{}-{}'", id, migration.name))?;
 
-            Err(e) => Err(e)?,
+            let query = "insert into __db_migrations (id, name, applied_on, script) \
+                values ($1, $2, now() at time zone 'utc', $3)";
+            // let params = ;
+            tx.execute(query, &[&(*id as i64), &migration.name, &migration.script])
+                .await
+                .context("failed to update __db_migrations")?;
         }
     }
+
+    tx.commit().await?;
+    let number_of_executed_migrations = MIGRATIONS.len() - active_migrations.len();
+    if number_of_executed_migrations > 0 {
+        info!("Applied {} migrations. DB is up to date now.", number_of_executed_migrations);
+    }
+
+    Ok(())
 }
 
 // Helper macro to include migrations in the `migations` folder and add them to
@@ -217,4 +239,59 @@ static MIGRATIONS: Lazy<BTreeMap<u64, Migration>> = include_migrations![
     07: "sync-status",
     08: "user-sessions",
     09: "search-index-queue",
+    10: "external-events",
+    11: "legacy-urls",
+    12: "settings",
+    13: "audio-tracks",
+    14: "event-trimming",
+    15: "realm-locales",
+    16: "realm-noindex",
+    17: "notifications",
+    18: "retention",
+    19: "pending-operations",
+    20: "search-index-queue-time",
+    21: "search-event-created",
+    22: "search-suggestions",
+    23: "cache-invalidation",
+    24: "user-session-email",
+    25: "user-avatars",
+    26: "realm-member-roles",
+    27: "saved-searches",
+    28: "playback-stats",
+    29: "event-markers",
+    30: "user-sessions-username-index",
+    31: "creator-blocks",
+    32: "host-realms",
+    33: "canonical-blocks",
+    34: "moderator-delegations",
+    35: "settings-cache-invalidation",
+    36: "playback-stats-daily",
+    37: "saved-search-alert-queue",
+    38: "audio-tracks-by-mimetype",
+    39: "search-host-realms",
+    40: "retention-recompute",
+    41: "pending-operations-unique",
+    42: "extension-blocks",
+    43: "blocks-cache-invalidation",
+    44: "realm-tree-versions",
+    45: "thumbnail-colors",
+    46: "event-metadata",
+    47: "event-captions",
+    48: "event-location",
+    49: "outbox",
+    50: "realm-display-options",
+    51: "event-passphrase",
+    52: "series-follows",
+    53: "realm-locks",
+    54: "block-read-roles",
+    55: "job-status",
+    56: "realm-icons",
+    57: "frontend-errors",
+    58: "realm-views",
+    59: "user-preferences",
+    60: "realm-hidden",
+    61: "legacy-event-ids",
+    62: "events-cache-invalidation",
+    63: "orphaned-events",
+    64: "realm-tree-versions-per-statement",
 ];
@@ -0,0 +1,16 @@
+-- Adds support for events that do not originate from Opencast, but are
+-- registered manually by moderators with a URL pointing to some other server
+-- ("external videos"). This is mainly useful during migration periods, where
+-- legacy videos still live on other servers but should already appear in
+-- Tobira (e.g. in series blocks and the search).
+
+create type event_source as enum ('opencast', 'external');
+
+alter table events
+    add column source event_source not null default 'opencast',
+    alter column opencast_id drop not null,
+
+    -- Only events synced from Opencast have an Opencast ID.
+    add constraint opencast_id_iff_from_opencast check (
+        (source = 'opencast') = (opencast_id is not null)
+    );
@@ -0,0 +1,19 @@
+-- Mapping from URLs of a previous video portal to events or realms in Tobira.
+-- Requests to these old URLs are redirected to the corresponding Tobira page
+-- (if enabled in the configuration), which keeps old links alive after
+-- migrating to Tobira.
+create table legacy_urls (
+    -- Path and query of the old URL, e.g. '/engage/ui/watch.html?id=1234'.
+    path text primary key,
+
+    -- Exactly one of these is set.
+    event bigint references events on delete cascade,
+    realm bigint references realms on delete cascade,
+
+    -- Statistics about how often the old URL is still requested.
+    hits bigint not null default 0,
+    last_hit timestamp with time zone,
+
+    constraint exactly_one_target check ((event is null) <> (realm is null)),
+    constraint path_is_absolute check (path like '/%')
+);
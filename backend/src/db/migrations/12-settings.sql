@@ -0,0 +1,10 @@
+-- Settings that can be changed at runtime by administrators via the API. Each
+-- entry overrides the config value with the same key (e.g.
+-- `theme.color.accent`). The value is stored in the same JSON representation
+-- that the corresponding TOML value would have.
+
+create table settings (
+    key text primary key,
+    value jsonb not null,
+    updated timestamptz not null default now()
+);
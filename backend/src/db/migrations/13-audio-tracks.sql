@@ -0,0 +1,18 @@
+-- Marks tracks that only contain audio (e.g. for podcasts). That's not always
+-- clear from the mimetype alone, so it is decided when syncing.
+
+alter type event_track add attribute is_audio boolean;
+
+-- For existing tracks, we use the same rules as the sync code: tracks from
+-- Opencast without resolution have no video stream.
+update events set tracks = array(
+    select row(
+        t.uri,
+        t.flavor,
+        t.mimetype,
+        t.resolution,
+        coalesce(t.mimetype like 'audio/%', false)
+            or (events.source = 'opencast' and t.resolution is null)
+    )::event_track
+    from unnest(events.tracks) as t
+);
@@ -0,0 +1,9 @@
+-- Stores the trimming applied to events by non-destructive editing in
+-- Opencast: only the part between `trim_start` and `trim_end` (in ms) is
+-- played. Both are `null` for untrimmed events.
+
+alter table events
+    add column trim_start int,
+    add column trim_end int,
+    add constraint trim_start_iff_trim_end check ((trim_start is null) = (trim_end is null)),
+    add constraint valid_trim check (trim_start >= 0 and trim_start < trim_end);
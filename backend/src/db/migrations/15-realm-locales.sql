@@ -0,0 +1,35 @@
+-- Adds an optional locale (BCP 47 language tag, e.g. `fr` or `de-CH`) to
+-- realms. It is inherited by all descendants that don't have their own and is
+-- used for formatting when rendering pages of that realm.
+
+alter table realms
+    add column locale text
+        constraint valid_locale check (locale ~ '^[a-zA-Z]{2,3}(-[a-zA-Z0-9]{1,8})*$');
+
+-- This function returns all columns of `realms`, so it has to be recreated.
+drop function ancestors_of_realm(bigint);
+create function ancestors_of_realm(realm_id bigint)
+    returns table (
+        id bigint,
+        parent bigint,
+        name text,
+        path_segment text,
+        index int,
+        child_order realm_order,
+        full_path text,
+        locale text,
+        height int
+    )
+    language 'sql'
+as $$
+with recursive ancestors(id, parent, name, path_segment, index, child_order, full_path, locale) as (
+    select *, 0 as height from realms
+    where id = realm_id
+  union
+    select r.id, r.parent, r.name, r.path_segment, r.index, r.child_order, r.full_path, r.locale, a.height + 1 as height
+    from ancestors a
+    join realms r on a.parent = r.id
+    where a.id <> 0
+)
+SELECT * FROM ancestors order by height desc
+$$;
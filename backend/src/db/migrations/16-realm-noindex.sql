@@ -0,0 +1,33 @@
+-- Allows excluding realms (and all their descendants) from search engines.
+-- They are listed in `/robots.txt` and their pages get a `noindex` meta tag.
+
+alter table realms add column noindex boolean not null default false;
+
+-- This function returns all columns of `realms`, so it has to be recreated.
+drop function ancestors_of_realm(bigint);
+create function ancestors_of_realm(realm_id bigint)
+    returns table (
+        id bigint,
+        parent bigint,
+        name text,
+        path_segment text,
+        index int,
+        child_order realm_order,
+        full_path text,
+        locale text,
+        noindex boolean,
+        height int
+    )
+    language 'sql'
+as $$
+with recursive ancestors(id, parent, name, path_segment, index, child_order, full_path, locale, noindex) as (
+    select *, 0 as height from realms
+    where id = realm_id
+  union
+    select r.id, r.parent, r.name, r.path_segment, r.index, r.child_order, r.full_path, r.locale, r.noindex, a.height + 1 as height
+    from ancestors a
+    join realms r on a.parent = r.id
+    where a.id <> 0
+)
+SELECT * FROM ancestors order by height desc
+$$;
@@ -0,0 +1,23 @@
+-- In-app notifications for users, e.g. "your upload finished processing".
+--
+-- Tobira does not know users, only roles. So notifications are addressed to a
+-- role, usually the user role that Opencast gives each user (e.g.
+-- `ROLE_USER_JOSE`). Every user with that role sees the notification.
+
+select prepare_randomized_ids('notification');
+
+create type notification_kind as enum ('upload_finished');
+
+create table notifications (
+    id bigint primary key default randomized_id('notification'),
+    recipient text not null,
+    kind notification_kind not null,
+
+    -- The event the notification is about, if any.
+    event bigint references events on delete cascade,
+
+    created timestamptz not null default now(),
+    read boolean not null default false
+);
+
+create index idx_notifications_recipient on notifications (recipient);
@@ -0,0 +1,34 @@
+-- Support for retention rules (see `retention` in the config): events that
+-- match a rule are flagged and, depending on the rule, retracted after a
+-- grace period.
+
+create type retention_action as enum ('flag', 'retract');
+
+create table retention_flags (
+    event bigint primary key references events on delete cascade,
+
+    -- Path of the realm of the rule that matched this event.
+    realm text not null,
+    action retention_action not null,
+    flagged timestamptz not null default now(),
+
+    -- When the action is applied, i.e. `flagged` plus the grace period.
+    due timestamptz not null
+);
+
+-- Retracted events are only visible to users with write access. As the sync
+-- overwrites `read_roles`, this is enforced by a trigger.
+alter table events add column retracted boolean not null default false;
+
+create function restrict_retracted_event() returns trigger as $$
+begin
+    new.read_roles := new.write_roles;
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger restrict_retracted_event
+    before insert or update on events
+    for each row
+    when (new.retracted)
+    execute procedure restrict_retracted_event();
@@ -0,0 +1,23 @@
+-- Destructive operations that have to be approved by a second moderator
+-- before they are executed (see `auth.deletion_approval`).
+
+select prepare_randomized_ids('pending_operation');
+
+create type pending_operation_kind as enum ('remove_realm');
+
+create table pending_operations (
+    id bigint primary key default randomized_id('pending_operation'),
+    kind pending_operation_kind not null,
+
+    -- The realm to remove, for `remove_realm`. If it is removed in some other
+    -- way, the operation is obsolete.
+    realm bigint references realms on delete cascade,
+
+    -- Username of the moderator that requested the operation. A different
+    -- moderator has to approve it.
+    requested_by text not null,
+    requested timestamptz not null default now(),
+    expires timestamptz not null,
+
+    constraint realm_for_remove_realm check ((kind = 'remove_realm') = (realm is not null))
+);
@@ -0,0 +1,4 @@
+-- Remember when items were queued to be able to report how far the search
+-- index lags behind the DB.
+
+alter table search_index_queue add column queued_at timestamptz not null default now();
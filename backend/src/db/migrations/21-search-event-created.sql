@@ -0,0 +1,6 @@
+-- The creation date of events was added to the search index (for the recency
+-- boost), so all events have to be reindexed.
+
+insert into search_index_queue (item_id, kind)
+    select id, 'event' from events
+    on conflict do nothing;
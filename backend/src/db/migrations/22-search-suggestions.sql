@@ -0,0 +1,35 @@
+-- All words that appear in titles of public events, their series and realm
+-- names. Used to suggest corrections for misspelled search queries ("did you
+-- mean"). Only public events are considered, as suggestions are shown to
+-- everyone and must not leak titles of private events.
+--
+-- This is refreshed by the search index daemon after the index was updated.
+--
+-- Requires the `pg_trgm` extension. Since Postgres 13, the owner of the
+-- database can create it, for older versions a superuser has to.
+
+do $$
+begin
+    create extension if not exists pg_trgm;
+exception when insufficient_privilege then
+    raise exception 'Tobira requires the Postgres extension "pg_trgm", but the '
+        'database user is not allowed to create it. Please run '
+        '"create extension pg_trgm;" in the Tobira database as superuser.';
+end $$;
+
+create materialized view search_words as
+    select word, count(*) as frequency
+    from (
+        select regexp_split_to_table(lower(events.title || ' ' || coalesce(series.title, '')), '\W+')
+        from events
+        left join series on series.id = events.series
+        where 'ROLE_ANONYMOUS' = any(events.read_roles)
+        union all
+        select regexp_split_to_table(lower(name), '\W+') from realms
+    ) as words(word)
+    where char_length(word) >= 3
+    group by word;
+
+-- Required for `refresh materialized view concurrently`.
+create unique index idx_search_words_word on search_words (word);
+create index idx_search_words_trgm on search_words using gin (word gin_trgm_ops);
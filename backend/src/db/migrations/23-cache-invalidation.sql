@@ -0,0 +1,21 @@
+-- The API caches some results that depend on these tables in memory (see
+-- `api/cache.rs`). Whenever they are changed, all Tobira processes are
+-- notified to invalidate their cache. Notifications are only delivered once
+-- the transaction commits and identical ones are merged.
+
+create function notify_cache_invalidation() returns trigger language plpgsql as $$
+begin
+    perform pg_notify('cache_invalidation', tg_table_name);
+    return null;
+end;
+$$;
+
+create trigger notify_cache_invalidation
+    after insert or update or delete or truncate on realms
+    for each statement
+    execute procedure notify_cache_invalidation();
+
+create trigger notify_cache_invalidation
+    after insert or update or delete or truncate on series
+    for each statement
+    execute procedure notify_cache_invalidation();
@@ -0,0 +1,3 @@
+-- Email address of the user, read from the email auth header. Might be null
+-- as not all auth systems provide it.
+alter table user_sessions add column email text;
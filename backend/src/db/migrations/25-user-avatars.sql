@@ -0,0 +1,8 @@
+-- Avatar images uploaded by users. Users are only identified by their
+-- username, as there is no user table.
+create table user_avatars (
+    username text primary key,
+    data bytea not null,
+    mimetype text not null,
+    updated timestamp with time zone not null default now()
+);
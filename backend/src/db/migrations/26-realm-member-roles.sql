@@ -0,0 +1,34 @@
+-- Users with any of these roles can see all videos shown on pages of this
+-- realm and all its descendants, regardless of the videos' read roles.
+
+alter table realms add column member_roles text[] not null default '{}';
+
+-- This function returns all columns of `realms`, so it has to be recreated.
+drop function ancestors_of_realm(bigint);
+create function ancestors_of_realm(realm_id bigint)
+    returns table (
+        id bigint,
+        parent bigint,
+        name text,
+        path_segment text,
+        index int,
+        child_order realm_order,
+        full_path text,
+        locale text,
+        noindex boolean,
+        member_roles text[],
+        height int
+    )
+    language 'sql'
+as $$
+with recursive ancestors(id, parent, name, path_segment, index, child_order, full_path, locale, noindex, member_roles) as (
+    select *, 0 as height from realms
+    where id = realm_id
+  union
+    select r.id, r.parent, r.name, r.path_segment, r.index, r.child_order, r.full_path, r.locale, r.noindex, r.member_roles, a.height + 1 as height
+    from ancestors a
+    join realms r on a.parent = r.id
+    where a.id <> 0
+)
+SELECT * FROM ancestors order by height desc
+$$;
@@ -0,0 +1,23 @@
+-- Search queries saved by users. After each harvest, new events matching a
+-- saved search can be announced to its owner via email (see
+-- `saved_search.rs`).
+
+select prepare_randomized_ids('saved_search');
+
+create table saved_searches (
+    id bigint primary key default randomized_id('saved_search'),
+    username text not null,
+    query text not null,
+
+    -- The roles of the user when saving the search. Only events readable by
+    -- one of these roles are announced, as the user is not necessarily
+    -- logged in when the alert is sent.
+    roles text[] not null,
+
+    -- Address that alerts are sent to. `null` if alerts are disabled.
+    alert_email text,
+
+    created timestamptz not null default now()
+);
+
+create index idx_saved_searches_username on saved_searches (username);
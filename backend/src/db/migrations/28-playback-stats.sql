@@ -0,0 +1,9 @@
+-- Anonymized playback statistics (see `http/playback.rs`). Each event is
+-- divided into a fixed number of equally sized buckets and we only count how
+-- often each bucket was played.
+create table playback_stats (
+    event bigint not null references events on delete cascade,
+    bucket int not null,
+    views bigint not null,
+    primary key (event, bucket)
+);
@@ -0,0 +1,18 @@
+-- Markers at points in time of events, e.g. polls or questions shown by
+-- interactive overlays of the player. Tobira does not interpret them.
+
+select prepare_randomized_ids('event_marker');
+
+create table event_markers (
+    id bigint primary key default randomized_id('event_marker'),
+    event bigint not null references events on delete cascade,
+    kind text not null,
+
+    -- Position in the event in ms.
+    time int not null check (time >= 0),
+
+    payload jsonb not null,
+    created timestamptz not null default now()
+);
+
+create index idx_event_markers_event on event_markers (event);
@@ -0,0 +1,3 @@
+-- Needed to efficiently find all sessions of a user when enforcing
+-- `auth.max_sessions_per_user`.
+create index idx_user_sessions_username on user_sessions (username);
@@ -0,0 +1,16 @@
+-- Creator blocks list all events whose creators contain a specific person.
+-- The list is computed whenever the block is loaded, so it always reflects
+-- the synced data.
+
+alter type block_type add value 'creator';
+
+alter table blocks add column creator text;
+
+-- The new enum value cannot be used in the same transaction, hence the cast.
+alter table blocks add constraint creator_block_has_fields check (type::text <> 'creator' or (
+    creator is not null and
+    videolist_order is not null and
+    show_title is not null
+));
+
+create index idx_events_creators on events using gin (creators);
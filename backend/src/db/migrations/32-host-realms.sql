@@ -0,0 +1,25 @@
+-- Realms that show an event via one of their blocks, in one place so that all
+-- queries agree on it. `directness` says how the event is referenced: 0 by a
+-- video block, 1 by a series block and 2 by a creator block. Realms with
+-- several such blocks are returned only once, with the most direct one.
+--
+-- The branches are combined with `union all` (instead of joining with `or`)
+-- so that each of them can use an index.
+create function event_host_realms(event_id bigint)
+    returns table (realm bigint, directness smallint)
+    language sql stable
+as $$
+    select realm_id, min(directness)::smallint
+    from (
+        select realm_id, 0 as directness from blocks where video_id = event_id
+        union all
+        select realm_id, 1 from blocks
+            where series_id = (select series from events where id = event_id)
+        union all
+        select realm_id, 2 from blocks
+            where creator = any((select creators from events where id = event_id)::text[])
+    ) as hosts
+    group by realm_id
+$$;
+
+create index idx_block_creator on blocks (creator);
@@ -0,0 +1,35 @@
+-- Information to determine the canonical realm of events shown in several
+-- realms (see `http.canonical_realm`): moderators can mark blocks as
+-- canonical and we remember when blocks were added. The creation time of
+-- existing blocks is unknown and stays `null`.
+
+alter table blocks
+    add column canonical boolean not null default false,
+    add column created timestamptz;
+alter table blocks alter column created set default now();
+
+-- Like before, but also returns whether any of the blocks is marked as
+-- canonical and when the first of them was added (`-infinity` for blocks
+-- older than this migration).
+drop function event_host_realms;
+create function event_host_realms(event_id bigint)
+    returns table (realm bigint, directness smallint, canonical boolean, mounted timestamptz)
+    language sql stable
+as $$
+    select
+        realm_id,
+        min(directness)::smallint,
+        bool_or(canonical),
+        min(coalesce(created, '-infinity'))
+    from (
+        select realm_id, 0 as directness, canonical, created
+            from blocks where video_id = event_id
+        union all
+        select realm_id, 1, canonical, created from blocks
+            where series_id = (select series from events where id = event_id)
+        union all
+        select realm_id, 2, canonical, created from blocks
+            where creator = any((select creators from events where id = event_id)::text[])
+    ) as hosts
+    group by realm_id
+$$;
@@ -0,0 +1,22 @@
+-- Moderation rights on a realm and its descendants, temporarily delegated by
+-- a moderator to another user (e.g. as a vacation replacement). Expired
+-- delegations are ignored and regularly deleted by the maintenance job.
+
+select prepare_randomized_ids('moderator_delegation');
+
+create table moderator_delegations (
+    id bigint primary key default randomized_id('moderator_delegation'),
+    realm bigint not null references realms on delete cascade,
+
+    -- The user who receives the moderation rights.
+    username text not null,
+
+    -- The moderator who created the delegation.
+    delegated_by text not null,
+
+    expires timestamptz not null,
+    created timestamptz not null default now()
+);
+
+-- Needed to efficiently check the delegations of the current user.
+create index idx_moderator_delegations_username on moderator_delegations (username);
@@ -0,0 +1,7 @@
+-- The settings overridden in the DB are needed for every `index.html` and
+-- are thus cached in memory as well (see `api/cache.rs`).
+
+create trigger notify_cache_invalidation
+    after insert or update or delete or truncate on settings
+    for each statement
+    execute procedure notify_cache_invalidation();
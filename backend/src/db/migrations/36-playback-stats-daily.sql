@@ -0,0 +1,13 @@
+-- Daily counters of playback reports per event, to export watch statistics
+-- for a date range (see `http/stats.rs`). Like `playback_stats`, nothing
+-- about the viewers is stored. Each report is one `POST /~playback/...`,
+-- `starts` counts the ones covering the first part of the event and
+-- `completions` the ones covering the last part.
+create table playback_stats_daily (
+    event bigint not null references events on delete cascade,
+    day date not null,
+    reports bigint not null,
+    starts bigint not null,
+    completions bigint not null,
+    primary key (event, day)
+);
@@ -0,0 +1,15 @@
+-- Saved search alerts are sent by a worker job instead of during the
+-- harvest (see `saved_search.rs`). The harvest only queues new events here.
+create table saved_search_alert_queue (
+    event bigint primary key references events on delete cascade,
+    queued timestamptz not null default now()
+);
+
+-- When `roles` was last refreshed (on login or when the user loads their
+-- saved searches). Alerts are paused once that is longer ago than
+-- `auth.session_duration`, as the user might have lost access since.
+alter table saved_searches add column roles_updated timestamptz;
+update saved_searches set roles_updated = created;
+alter table saved_searches
+    alter column roles_updated set not null,
+    alter column roles_updated set default now();
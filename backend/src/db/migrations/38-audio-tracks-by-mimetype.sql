@@ -0,0 +1,18 @@
+-- Whether a track is audio-only is now only decided by its mimetype: a
+-- missing resolution does not mean there is no video stream, e.g. for HLS or
+-- DASH manifests. For tracks with neither `audio/*` nor `video/*` mimetype,
+-- it is unknown (`null`).
+
+update events set tracks = array(
+    select row(
+        t.uri,
+        t.flavor,
+        t.mimetype,
+        t.resolution,
+        case
+            when t.mimetype like 'audio/%' then true
+            when t.mimetype like 'video/%' then false
+        end
+    )::event_track
+    from unnest(events.tracks) as t
+);
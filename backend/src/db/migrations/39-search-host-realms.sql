@@ -0,0 +1,62 @@
+-- The search index contains the paths of the realms each event appears in
+-- and of all ancestors of realms (see `search/event.rs` and `search/realm.rs`).
+-- These triggers queue the affected items for reindexing when these change.
+
+create function queue_block_events_for_reindex()
+    returns trigger
+    language plpgsql
+as $$
+begin
+    if tg_op <> 'INSERT' then
+        insert into search_index_queue (item_id, kind)
+            select id, 'event' from events
+            where id = old.video_id or series = old.series_id
+            on conflict do nothing;
+    end if;
+    if tg_op <> 'DELETE' then
+        insert into search_index_queue (item_id, kind)
+            select id, 'event' from events
+            where id = new.video_id or series = new.series_id
+            on conflict do nothing;
+    end if;
+    return null;
+end;
+$$;
+
+create trigger queue_block_events_for_reindex
+    after insert or delete or update of realm_id, video_id, series_id on blocks
+    for each row
+    execute procedure queue_block_events_for_reindex();
+
+-- `full_path` is updated by triggers for all descendants of a moved or renamed
+-- realm, so this also fires for them.
+create function queue_realm_path_change_for_reindex()
+    returns trigger
+    language plpgsql
+as $$
+begin
+    insert into search_index_queue (item_id, kind)
+        values (new.id, 'realm')
+        on conflict do nothing;
+    insert into search_index_queue (item_id, kind)
+        select events.id, 'event' from blocks
+        join events on events.id = blocks.video_id or events.series = blocks.series_id
+        where blocks.realm_id = new.id
+        on conflict do nothing;
+    return null;
+end;
+$$;
+
+create trigger queue_realm_path_change_for_reindex
+    after update on realms
+    for each row
+    when (old.full_path is distinct from new.full_path)
+    execute procedure queue_realm_path_change_for_reindex();
+
+-- All existing items have to be reindexed to include the new fields.
+insert into search_index_queue (item_id, kind)
+    select id, 'event' from events
+    on conflict do nothing;
+insert into search_index_queue (item_id, kind)
+    select id, 'realm' from realms
+    on conflict do nothing;
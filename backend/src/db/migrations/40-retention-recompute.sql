@@ -0,0 +1,138 @@
+-- Retention flags are kept up to date whenever blocks, realm paths or the
+-- series of events change, not only when the worker evaluates the rules. For
+-- that, the worker stores the rules of the config in the DB. Moderators can
+-- exempt events from retention, which also restores retracted events.
+
+create table retention_rules (
+    -- Position of the rule in the config. The first matching rule wins.
+    index int primary key,
+    realm text not null,
+    max_age interval not null,
+    action retention_action not null,
+    grace_period interval not null
+);
+
+create table retention_exemptions (
+    event bigint primary key references events on delete cascade,
+    exempted_by text not null,
+    exempted timestamptz not null default now()
+);
+
+-- The read roles an event would have if it were not retracted, so that they
+-- can be restored.
+alter table events add column retracted_read_roles text[];
+
+create or replace function restrict_retracted_event() returns trigger as $$
+begin
+    -- Updates that don't touch `read_roles` keep the forced ones, which must
+    -- not be stored as the original ones.
+    if tg_op = 'INSERT' or not old.retracted or new.read_roles is distinct from old.read_roles then
+        new.retracted_read_roles := new.read_roles;
+    end if;
+    new.read_roles := new.write_roles;
+    return new;
+end;
+$$ language plpgsql;
+
+-- Makes `retention_flags` of the given events (or of all events if `null`)
+-- match the current rules: flags of events that no longer match any rule are
+-- removed and flags of events whose first matching rule changed are updated,
+-- keeping the time they were flagged at. Flags of retracted events stay.
+create function update_retention_flags(affected bigint[])
+    returns void
+    language sql
+as $$
+    with matches as (
+        select distinct on (events.id)
+            events.id as event, rules.realm, rules.action, rules.grace_period
+        from retention_rules rules
+        join realms on realms.full_path = rules.realm
+            or starts_with(realms.full_path, rules.realm || '/')
+        join blocks on blocks.realm_id = realms.id
+        join events on events.id = blocks.video_id or events.series = blocks.series_id
+        where events.created < now() - rules.max_age
+            and not events.retracted
+            and (affected is null or events.id = any(affected))
+            and not exists (select from retention_exemptions x where x.event = events.id)
+        order by events.id, rules.index
+    ), removed as (
+        delete from retention_flags flags
+        using events
+        where flags.event = events.id
+            and not events.retracted
+            and (affected is null or flags.event = any(affected))
+            and not exists (select from matches where matches.event = flags.event)
+    )
+    insert into retention_flags (event, realm, action, due)
+        select event, realm, action, now() + grace_period from matches
+        on conflict (event) do update set
+            realm = excluded.realm,
+            action = excluded.action,
+            due = retention_flags.flagged + (excluded.due - excluded.flagged)
+        where (retention_flags.realm, retention_flags.action, retention_flags.due)
+            is distinct from
+            (excluded.realm, excluded.action, retention_flags.flagged + (excluded.due - excluded.flagged));
+$$;
+
+create function update_block_retention_flags()
+    returns trigger
+    language plpgsql
+as $$
+begin
+    if exists (select from retention_rules) then
+        perform update_retention_flags(array(
+            select id from events
+            where (tg_op <> 'INSERT' and (id = old.video_id or series = old.series_id))
+                or (tg_op <> 'DELETE' and (id = new.video_id or series = new.series_id))
+        ));
+    end if;
+    return null;
+end;
+$$;
+
+create trigger update_block_retention_flags
+    after insert or delete or update of realm_id, video_id, series_id on blocks
+    for each row
+    execute procedure update_block_retention_flags();
+
+-- Like `queue_realm_path_change_for_reindex`, this fires for all descendants
+-- of a moved or renamed realm.
+create function update_realm_retention_flags()
+    returns trigger
+    language plpgsql
+as $$
+begin
+    if exists (select from retention_rules) then
+        perform update_retention_flags(array(
+            select events.id from blocks
+            join events on events.id = blocks.video_id or events.series = blocks.series_id
+            where blocks.realm_id = new.id
+        ));
+    end if;
+    return null;
+end;
+$$;
+
+create trigger update_realm_retention_flags
+    after update on realms
+    for each row
+    when (old.full_path is distinct from new.full_path)
+    execute procedure update_realm_retention_flags();
+
+create function update_event_retention_flags()
+    returns trigger
+    language plpgsql
+as $$
+begin
+    if exists (select from retention_rules) then
+        perform update_retention_flags(array[new.id]);
+    end if;
+    return null;
+end;
+$$;
+
+create trigger update_event_retention_flags
+    after update on events
+    for each row
+    when (old.series is distinct from new.series)
+    execute procedure update_event_retention_flags();
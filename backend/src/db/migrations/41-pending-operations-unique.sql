@@ -0,0 +1,11 @@
+-- There can only be one pending operation of each kind per realm. Checking
+-- that before inserting is racy, so it's enforced by a constraint.
+
+delete from pending_operations a
+    using pending_operations b
+    where a.realm = b.realm
+        and a.kind = b.kind
+        and (a.requested, a.id) > (b.requested, b.id);
+
+alter table pending_operations
+    add constraint one_operation_per_realm unique (realm, kind);
@@ -0,0 +1,14 @@
+-- Blocks provided by compiled-in extensions. Their data is stored as JSON, its
+-- format is defined by the extension the block belongs to.
+
+alter type block_type add value 'extension';
+
+alter table blocks
+    add column extension text,
+    add column extension_data jsonb;
+
+-- The new enum value cannot be used in the same transaction, hence the cast.
+alter table blocks add constraint extension_block_has_fields check (type::text <> 'extension' or (
+    extension is not null and
+    extension_data is not null
+));
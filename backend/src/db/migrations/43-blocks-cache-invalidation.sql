@@ -0,0 +1,7 @@
+-- The blocks of the root realm are needed for the home page, the most
+-- requested page, and are thus cached in memory as well (see `api/cache.rs`).
+
+create trigger notify_cache_invalidation
+    after insert or update or delete or truncate on blocks
+    for each statement
+    execute procedure notify_cache_invalidation();
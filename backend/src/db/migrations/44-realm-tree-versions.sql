@@ -0,0 +1,39 @@
+-- Latest version of each realm for `Query.realmTree`, with which clients
+-- can fetch only the changes of the realm tree since they last fetched it.
+-- Versions are bumped by triggers whenever a realm is inserted, updated or
+-- removed. Removed realms keep their row so that clients learn about them.
+--
+-- This is a separate table since `ancestors_of_realm` returns all columns of
+-- `realms`. Realms that were not changed since this migration have no row.
+create table realm_tree_changes (
+    realm bigint primary key,
+    version bigint not null,
+    removed boolean not null
+);
+
+create index idx_realm_tree_changes_version on realm_tree_changes (version);
+
+create sequence realm_tree_version;
+
+create function bump_realm_tree_version() returns trigger language plpgsql as $$
+declare
+    realm_id bigint := case when tg_op = 'DELETE' then old.id else new.id end;
+begin
+    -- Transactions changing realms are serialized, so that versions become
+    -- visible in order. Otherwise, a client could get a version newer than
+    -- that of a change that isn't committed yet and never learn about it.
+    lock table realm_tree_changes in exclusive mode;
+
+    insert into realm_tree_changes (realm, version, removed)
+        values (realm_id, nextval('realm_tree_version'), tg_op = 'DELETE')
+        on conflict (realm) do update set
+            version = excluded.version,
+            removed = excluded.removed;
+    return null;
+end;
+$$;
+
+create trigger bump_realm_tree_version
+    after insert or update or delete on realms
+    for each row
+    execute procedure bump_realm_tree_version();
@@ -0,0 +1,19 @@
+-- Average color of the event's thumbnail as `#rrggbb`, shown as placeholder
+-- until the thumbnail is loaded. Computed by the thumbnail proxy (see
+-- `http/thumbnail.rs`) when the thumbnail is requested the first time and
+-- reset whenever the thumbnail changes.
+alter table events add column thumbnail_color text
+    constraint thumbnail_color_is_hex check (thumbnail_color ~ '^#[0-9a-f]{6}$');
+
+create function reset_thumbnail_color() returns trigger language plpgsql as $$
+begin
+    new.thumbnail_color := null;
+    return new;
+end;
+$$;
+
+create trigger reset_thumbnail_color
+    before update of thumbnail on events
+    for each row
+    when (old.thumbnail is distinct from new.thumbnail)
+    execute procedure reset_thumbnail_color();
@@ -0,0 +1,6 @@
+-- Additional metadata fields of events as sent by the harvest API, as object
+-- of namespaces containing objects of fields with string arrays as values.
+-- Used to filter event lists by the field configured as
+-- `general.event_filter`.
+alter table events add column metadata jsonb not null default '{}'
+    constraint metadata_is_object check (jsonb_typeof(metadata) = 'object');
@@ -0,0 +1,14 @@
+-- Captions (subtitles) of events as sent by the harvest API. `lang` is the
+-- language tag, if known.
+create type event_caption as (
+    uri text,
+    lang text
+);
+
+alter table events add column captions event_caption[] not null default '{}';
+
+-- Whether an event has captions was added to the search index, so all events
+-- have to be reindexed.
+insert into search_index_queue (item_id, kind)
+    select id, 'event' from events
+    on conflict do nothing;
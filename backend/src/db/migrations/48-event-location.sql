@@ -0,0 +1,6 @@
+-- Where an event was recorded as sent by the harvest API (`dcterms:spatial` in
+-- Opencast), usually the ID of the capture agent in a lecture hall. Events can
+-- be listed by location, so it is indexed.
+alter table events add column location text;
+
+create index idx_events_location on events (location);
@@ -0,0 +1,16 @@
+-- Messages about changes made via the API that have to be sent to other
+-- systems (currently webhooks, see `outbox.rs`). They are inserted in the
+-- transaction making the change and sent by the worker afterwards, in order
+-- of `id`.
+create table outbox (
+    id bigint primary key generated always as identity,
+    kind text not null,
+    payload jsonb not null,
+    created timestamptz not null default now(),
+
+    -- Failed attempts to send this message. Once the maximum is reached, the
+    -- message is kept for inspection but not sent anymore.
+    attempts int not null default 0,
+    next_attempt timestamptz not null default now(),
+    last_error text
+);
@@ -0,0 +1,43 @@
+-- Display options moderators can set per realm to tune the appearance of its
+-- page. These only affect the realm itself, not its descendants.
+
+create type realm_list_density as enum ('comfortable', 'compact');
+create type realm_banner_style as enum ('default', 'dark');
+
+alter table realms
+    add column list_density realm_list_density not null default 'comfortable',
+    add column show_children boolean not null default true,
+    add column banner_style realm_banner_style not null default 'default';
+
+-- This function returns all columns of `realms`, so it has to be recreated.
+drop function ancestors_of_realm(bigint);
+create function ancestors_of_realm(realm_id bigint)
+    returns table (
+        id bigint,
+        parent bigint,
+        name text,
+        path_segment text,
+        index int,
+        child_order realm_order,
+        full_path text,
+        locale text,
+        noindex boolean,
+        member_roles text[],
+        list_density realm_list_density,
+        show_children boolean,
+        banner_style realm_banner_style,
+        height int
+    )
+    language 'sql'
+as $$
+with recursive ancestors(id, parent, name, path_segment, index, child_order, full_path, locale, noindex, member_roles, list_density, show_children, banner_style) as (
+    select *, 0 as height from realms
+    where id = realm_id
+  union
+    select r.id, r.parent, r.name, r.path_segment, r.index, r.child_order, r.full_path, r.locale, r.noindex, r.member_roles, r.list_density, r.show_children, r.banner_style, a.height + 1 as height
+    from ancestors a
+    join realms r on a.parent = r.id
+    where a.id <> 0
+)
+SELECT * FROM ancestors order by height desc
+$$;
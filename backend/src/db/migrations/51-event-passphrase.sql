@@ -0,0 +1,6 @@
+-- Owners can protect events with a passphrase: users without write access
+-- only see minimal metadata until they present it. Stored as PBKDF2 hash in
+-- the format `pbkdf2-sha256$<iterations>$<salt>$<hash>` (salt and hash are
+-- hex-encoded).
+
+alter table events add column passphrase_hash text;
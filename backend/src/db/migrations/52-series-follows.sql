@@ -0,0 +1,34 @@
+-- Users can follow series to be notified about new events in them, in the
+-- app and optionally via email (see `series_follow.rs`).
+
+create table series_follows (
+    username text not null,
+    series bigint not null references series on delete cascade,
+
+    -- Like `saved_searches.roles`: only events readable by these roles are
+    -- announced. Refreshed whenever the user logs in or follows a series.
+    roles text[] not null,
+    roles_updated timestamptz not null default now(),
+
+    -- The user role (e.g. `ROLE_USER_JOSE`) that in-app notifications are
+    -- addressed to. `null` if the user has none.
+    user_role text,
+
+    -- Address that notifications are also sent to. `null` if email
+    -- notifications are disabled.
+    email text,
+
+    created timestamptz not null default now(),
+
+    primary key (username, series)
+);
+
+create index idx_series_follows_series on series_follows (series);
+
+-- New events in followed series, queued by the harvest.
+create table series_follow_queue (
+    event bigint primary key references events on delete cascade,
+    queued timestamptz not null default now()
+);
+
+alter type notification_kind add value 'new_series_event';
@@ -0,0 +1,6 @@
+-- Blocks can be restricted to users with certain roles, e.g. a text block
+-- with internal instructions. `null` means the block is visible to everyone.
+
+alter table blocks
+    add column read_roles text[],
+    add constraint read_roles_not_empty check (cardinality(read_roles) > 0);
@@ -0,0 +1,12 @@
+-- Health of the periodic jobs of the worker (syncing, sending webhooks, ...),
+-- exported as metrics by all Tobira nodes (see `metrics.rs`). A row is only
+-- added once the job ran for the first time.
+create table job_status (
+    job text primary key,
+
+    -- When the job last succeeded, `null` if it never did.
+    last_success timestamp with time zone,
+
+    -- Number of failed runs since the last successful one.
+    consecutive_failures int not null default 0
+);
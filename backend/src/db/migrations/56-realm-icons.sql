@@ -0,0 +1,60 @@
+-- Icons and accent colors shown for realms in the navigation and breadcrumbs,
+-- making large trees easier to scan. A realm either has one of the curated
+-- icons (`icon`) or an uploaded image (stored in `realm_icons`, with the time
+-- of the upload in `icon_uploaded` to build cache-busting URLs without
+-- loading the image).
+
+create type realm_icon as enum (
+    'award', 'book', 'briefcase', 'calendar', 'camera', 'code', 'compass', 'cpu',
+    'feather', 'film', 'globe', 'heart', 'map', 'mic', 'monitor', 'music', 'star', 'users'
+);
+
+alter table realms
+    add column icon realm_icon,
+    add column icon_uploaded timestamp with time zone,
+    add column accent_color text,
+    add constraint single_icon check (icon is null or icon_uploaded is null),
+    add constraint valid_accent_color check (accent_color ~ '^#[0-9a-f]{6}$');
+
+create table realm_icons (
+    realm bigint primary key references realms on delete cascade,
+    data bytea not null,
+    mimetype text not null
+);
+
+-- This function returns all columns of `realms`, so it has to be recreated.
+drop function ancestors_of_realm(bigint);
+create function ancestors_of_realm(realm_id bigint)
+    returns table (
+        id bigint,
+        parent bigint,
+        name text,
+        path_segment text,
+        index int,
+        child_order realm_order,
+        full_path text,
+        locale text,
+        noindex boolean,
+        member_roles text[],
+        list_density realm_list_density,
+        show_children boolean,
+        banner_style realm_banner_style,
+        locked boolean,
+        icon realm_icon,
+        icon_uploaded timestamp with time zone,
+        accent_color text,
+        height int
+    )
+    language 'sql'
+as $$
+with recursive ancestors(id, parent, name, path_segment, index, child_order, full_path, locale, noindex, member_roles, list_density, show_children, banner_style, locked, icon, icon_uploaded, accent_color) as (
+    select *, 0 as height from realms
+    where id = realm_id
+  union
+    select r.id, r.parent, r.name, r.path_segment, r.index, r.child_order, r.full_path, r.locale, r.noindex, r.member_roles, r.list_density, r.show_children, r.banner_style, r.locked, r.icon, r.icon_uploaded, r.accent_color, a.height + 1 as height
+    from ancestors a
+    join realms r on a.parent = r.id
+    where a.id <> 0
+)
+SELECT * FROM ancestors order by height desc
+$$;
@@ -0,0 +1,18 @@
+-- Error reports sent by the frontend (see `http/error_report.rs`), unless
+-- they are forwarded to Sentry. Nothing about the user is stored. Old reports
+-- are removed by the worker.
+create table frontend_errors (
+    id bigint primary key generated always as identity,
+    received timestamp with time zone not null default now(),
+
+    -- Type of the error, e.g. `TypeError`.
+    name text,
+    message text not null,
+    stack text,
+
+    -- URL of the page the error occured on, without query and fragment.
+    url text,
+    user_agent text
+);
+
+create index frontend_errors_received on frontend_errors (received);
@@ -0,0 +1,9 @@
+-- Daily counters of page views per realm (see `http/realm_views.rs`), so that
+-- moderators can see whether their pages are visited. Like
+-- `playback_stats_daily`, nothing about the visitors is stored.
+create table realm_views_daily (
+    realm bigint not null references realms on delete cascade,
+    day date not null,
+    views bigint not null,
+    primary key (realm, day)
+);
@@ -0,0 +1,11 @@
+-- Interface preferences of users (see `api/model/preference.rs`), so that
+-- they follow users across devices. Values are validated by the API.
+create type user_preference as enum ('playback_speed', 'preferred_quality', 'language', 'theme');
+
+create table user_preferences (
+    username text not null,
+    key user_preference not null,
+    value text not null,
+    updated timestamptz not null default now(),
+    primary key (username, key)
+);
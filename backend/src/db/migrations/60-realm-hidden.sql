@@ -0,0 +1,47 @@
+-- Realms can be hidden from the navigation of their parent (and the list of
+-- children on crawler pages) and from the search, e.g. for unlisted project
+-- pages. They stay accessible by their URL. The root realm cannot be hidden.
+
+alter table realms
+    add column hidden_from_navigation boolean not null default false,
+    add column hidden_from_search boolean not null default false,
+    add constraint root_not_hidden check (id <> 0 or not (hidden_from_navigation or hidden_from_search));
+
+-- This function returns all columns of `realms`, so it has to be recreated.
+drop function ancestors_of_realm(bigint);
+create function ancestors_of_realm(realm_id bigint)
+    returns table (
+        id bigint,
+        parent bigint,
+        name text,
+        path_segment text,
+        index int,
+        child_order realm_order,
+        full_path text,
+        locale text,
+        noindex boolean,
+        member_roles text[],
+        list_density realm_list_density,
+        show_children boolean,
+        banner_style realm_banner_style,
+        locked boolean,
+        icon realm_icon,
+        icon_uploaded timestamp with time zone,
+        accent_color text,
+        hidden_from_navigation boolean,
+        hidden_from_search boolean,
+        height int
+    )
+    language 'sql'
+as $$
+with recursive ancestors(id, parent, name, path_segment, index, child_order, full_path, locale, noindex, member_roles, list_density, show_children, banner_style, locked, icon, icon_uploaded, accent_color, hidden_from_navigation, hidden_from_search) as (
+    select *, 0 as height from realms
+    where id = realm_id
+  union
+    select r.id, r.parent, r.name, r.path_segment, r.index, r.child_order, r.full_path, r.locale, r.noindex, r.member_roles, r.list_density, r.show_children, r.banner_style, r.locked, r.icon, r.icon_uploaded, r.accent_color, r.hidden_from_navigation, r.hidden_from_search, a.height + 1 as height
+    from ancestors a
+    join realms r on a.parent = r.id
+    where a.id <> 0
+)
+SELECT * FROM ancestors order by height desc
+$$;
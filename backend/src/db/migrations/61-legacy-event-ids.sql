@@ -0,0 +1,10 @@
+-- IDs that events had in a previous Opencast instance, imported during sync
+-- from the metadata field configured as `sync.legacy_ids`. Links to the old
+-- Opencast players (e.g. '/play/<id>') are redirected to the event with that
+-- ID (if `http.legacy_redirects` is enabled).
+create table legacy_event_ids (
+    legacy_id text primary key,
+    event bigint not null references events on delete cascade
+);
+
+create index idx_legacy_event_ids_event on legacy_event_ids (event);
@@ -0,0 +1,7 @@
+-- Aggregates over events (like the number of events of a series) are cached
+-- in memory as well (see `api/cache.rs`).
+
+create trigger notify_cache_invalidation
+    after insert or update or delete or truncate on events
+    for each statement
+    execute procedure notify_cache_invalidation();
@@ -0,0 +1,27 @@
+-- Events that deprovisioned users could write (see `auth/deprovision.rs`).
+-- They have to be reassigned in Opencast, as the sync overwrites the write
+-- roles. A flag is removed once its role is not a write role of the event
+-- anymore, i.e. once the reassignment was synced.
+
+create table orphaned_events (
+    event bigint not null references events on delete cascade,
+
+    -- The user role of the deprovisioned user, e.g. `ROLE_USER_JOSE`.
+    user_role text not null,
+    flagged timestamptz not null default now(),
+
+    primary key (event, user_role)
+);
+
+create function remove_orphaned_event_flags() returns trigger as $$
+begin
+    delete from orphaned_events
+        where event = new.id and not user_role = any(new.write_roles);
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger remove_orphaned_event_flags
+    after update of write_roles on events
+    for each row
+    execute procedure remove_orphaned_event_flags();
@@ -0,0 +1,46 @@
+-- Bumps realm tree versions (see `44-realm-tree-versions.sql`) once per
+-- statement instead of once per changed realm, so that the table lock is
+-- only taken once per statement and not at all by statements that don't
+-- change any realm. Transition tables are only allowed for triggers with a
+-- single event, hence the three triggers.
+
+drop trigger bump_realm_tree_version on realms;
+drop function bump_realm_tree_version;
+
+create function bump_realm_tree_versions() returns trigger language plpgsql as $$
+begin
+    if not exists (select from changed_realms) then
+        return null;
+    end if;
+
+    -- Transactions changing realms are serialized, so that versions become
+    -- visible in order. Otherwise, a client could get a version newer than
+    -- that of a change that isn't committed yet and never learn about it.
+    lock table realm_tree_changes in exclusive mode;
+
+    insert into realm_tree_changes (realm, version, removed)
+        select id, nextval('realm_tree_version'), tg_op = 'DELETE' from changed_realms
+        on conflict (realm) do update set
+            version = excluded.version,
+            removed = excluded.removed;
+    return null;
+end;
+$$;
+
+create trigger bump_realm_tree_versions_insert
+    after insert on realms
+    referencing new table as changed_realms
+    for each statement
+    execute procedure bump_realm_tree_versions();
+
+create trigger bump_realm_tree_versions_update
+    after update on realms
+    referencing new table as changed_realms
+    for each statement
+    execute procedure bump_realm_tree_versions();
+
+create trigger bump_realm_tree_versions_delete
+    after delete on realms
+    referencing old table as changed_realms
+    for each statement
+    execute procedure bump_realm_tree_versions();
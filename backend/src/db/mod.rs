@@ -3,12 +3,13 @@
 use deadpool_postgres::{Config as PoolConfig, Pool, Runtime};
 use secrecy::{ExposeSecret, Secret};
 use std::time::{Duration, Instant};
-use tokio_postgres::NoTls;
+use tokio_postgres::{NoTls, Socket, tls::NoTlsStream};
 
 use crate::{http::{self, Response}, prelude::*};
 
 
 pub(crate) mod cmd;
+pub(crate) mod crypt;
 mod migrations;
 mod query;
 mod tx;
@@ -16,8 +17,8 @@ pub(crate) mod types;
 pub(crate) mod util;
 
 pub use self::{
-    tx::Transaction,
-    migrations::migrate,
+    tx::{QueryTrace, Transaction},
+    migrations::{check_migrations, migrate},
 };
 
 
@@ -42,6 +43,38 @@ pub(crate) struct DbConfig {
     /// The name of the database to use.
     #[config(default = "tobira")]
     database: String,
+
+    /// How long Tobira waits at startup while another Tobira process (e.g.
+    /// another replica started at the same time) migrates the database.
+    /// Startup fails if the migration takes longer. "0s" waits forever.
+    #[config(default = "10min", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) migration_lock_timeout: Duration,
+
+    /// Debugging aid for development: if set, every SQL query of an API
+    /// request that takes longer than this duration is executed again with
+    /// `EXPLAIN ANALYZE`. The resulting query plans are attached to the
+    /// GraphQL response in `extensions.queryPlans`. Only has an effect in
+    /// debug builds. Example: "50ms".
+    #[config(deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) explain_slow_queries: Option<Duration>,
+
+    /// Key to encrypt sensitive columns with, like the email addresses of
+    /// users, so that they are not readable in DB dumps. Base64 encoded, 32
+    /// bytes, e.g. generated with `openssl rand -base64 32`. Can also be set
+    /// via the environment variable `TOBIRA_DB_ENCRYPTION_KEY`, e.g. by a
+    /// key management system. If not set, these columns are stored in plain
+    /// text.
+    ///
+    /// Values stored before setting the key stay in plain text until
+    /// `tobira rekey` is run. Keep the key safe: encrypted values cannot be
+    /// read without it.
+    #[config(env = "TOBIRA_DB_ENCRYPTION_KEY")]
+    pub(crate) encryption_key: Option<Secret<String>>,
+
+    /// Previous values of `encryption_key` that are still used to decrypt.
+    /// To rotate the key, move the old one here, set a new one, run
+    /// `tobira rekey` and then remove the old key from this list.
+    pub(crate) old_encryption_keys: Option<Vec<Secret<String>>>,
 }
 
 
@@ -95,6 +128,22 @@ pub(crate) async fn create_pool(config: &DbConfig) -> Result<Pool> {
     Ok(pool)
 }
 
+/// Opens a single DB connection outside of the pool. Only for special
+/// purposes, like `LISTEN`, which requires access to the `Connection`.
+pub(crate) async fn connect_single(
+    config: &DbConfig,
+) -> Result<(tokio_postgres::Client, tokio_postgres::Connection<Socket, NoTlsStream>)> {
+    tokio_postgres::Config::new()
+        .user(&config.user)
+        .password(config.password.expose_secret())
+        .host(&config.host)
+        .port(config.port)
+        .dbname(&config.database)
+        .connect(NoTls)
+        .await
+        .context("failed to connect to the database")
+}
+
 /// Checks out one DB connection from the pool or returns `Err` with a "service
 /// unavailable" response.
 pub(crate) async fn get_conn_or_service_unavailable(pool: &Pool) -> Result<DbConnection, Response> {
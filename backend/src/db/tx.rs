@@ -1,6 +1,10 @@
-use std::sync::{Arc, atomic::{AtomicU32, Ordering, AtomicBool}};
+use std::{
+    sync::{Arc, Mutex, atomic::{AtomicU32, Ordering, AtomicBool}},
+    time::{Duration, Instant},
+};
+use futures::TryStreamExt;
 use postgres_types::{BorrowToSql, ToSql};
-use tokio_postgres::{Error, Row, RowStream};
+use tokio_postgres::{Error, Row};
 
 use crate::{prelude::*, search};
 
@@ -15,17 +19,73 @@ pub struct Transaction {
     inner: Arc<deadpool_postgres::Transaction<'static>>,
     num_queries: AtomicU32,
     error: AtomicBool,
+    explainer: Option<Explainer>,
+    /// All queries executed so far, if tracing is enabled (see `trace_queries`).
+    traces: Option<Mutex<Vec<QueryTrace>>>,
+}
+
+/// Records query plans of slow queries. See `DbConfig::explain_slow_queries`.
+struct Explainer {
+    threshold: Duration,
+    plans: Mutex<Vec<QueryPlan>>,
+}
+
+/// When and how long one query was executed, for tracing.
+pub struct QueryTrace {
+    pub query: String,
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+/// The result of `EXPLAIN ANALYZE` for one slow query.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryPlan {
+    query: String,
+    duration_ms: f64,
+    plan: Vec<String>,
 }
 
 impl Transaction {
-    pub fn new(inner: Arc<deadpool_postgres::Transaction<'static>>) -> Self {
+    /// Creates a new transaction wrapper. If `explain_threshold` is set, plans
+    /// of queries slower than that are recorded (see `take_query_plans`).
+    pub fn new(
+        inner: Arc<deadpool_postgres::Transaction<'static>>,
+        explain_threshold: Option<Duration>,
+    ) -> Self {
         Self {
             inner,
             num_queries: AtomicU32::new(0),
             error: AtomicBool::new(false),
+            explainer: explain_threshold.map(|threshold| Explainer {
+                threshold,
+                plans: Mutex::new(vec![]),
+            }),
+            traces: None,
         }
     }
 
+    /// Makes this transaction record all queries, see `take_query_traces`.
+    pub fn trace_queries(mut self) -> Self {
+        self.traces = Some(Mutex::new(vec![]));
+        self
+    }
+
+    /// Returns all queries recorded so far if tracing is enabled, leaving an
+    /// empty list.
+    pub fn take_query_traces(&self) -> Vec<QueryTrace> {
+        self.traces.as_ref()
+            .map(|traces| std::mem::take(&mut *traces.lock().unwrap()))
+            .unwrap_or_default()
+    }
+
+    /// Returns all query plans recorded so far, leaving an empty list.
+    pub fn take_query_plans(&self) -> Vec<QueryPlan> {
+        self.explainer.as_ref()
+            .map(|e| std::mem::take(&mut *e.plans.lock().unwrap()))
+            .unwrap_or_default()
+    }
+
     pub fn num_queries(&self) -> u32 {
         self.num_queries.load(Ordering::SeqCst)
     }
@@ -48,6 +108,68 @@ impl Transaction {
         res
     }
 
+    fn trace(&self, query: &str, start: Instant) {
+        if let Some(traces) = &self.traces {
+            traces.lock().unwrap().push(QueryTrace {
+                query: query.to_owned(),
+                start,
+                duration: start.elapsed(),
+            });
+        }
+    }
+
+    /// If the query took longer than the configured threshold, executes it
+    /// again with `EXPLAIN ANALYZE` and records the plan.
+    async fn explain_if_slow<'a>(
+        &self,
+        query: &str,
+        params: impl IntoIterator<Item = &'a dyn ToSql, IntoIter = impl ExactSizeIterator>,
+        duration: Duration,
+    ) {
+        let explainer = match &self.explainer {
+            Some(explainer) if duration >= explainer.threshold => explainer,
+            _ => return,
+        };
+
+        // After an error, the transaction is aborted and does not accept any
+        // more queries.
+        if self.has_errored() {
+            return;
+        }
+
+        // `EXPLAIN ANALYZE` actually executes the query. To not repeat its
+        // side effects, we do that in a savepoint that we roll back afterwards.
+        if let Err(e) = self.inner.batch_execute("savepoint tobira_explain").await {
+            error!("Failed to create savepoint to explain slow query \"{}\": {}", query, e);
+            self.error.store(true, Ordering::SeqCst);
+            return;
+        }
+
+        let explain_query = format!("explain analyze {}", query);
+        let res = async {
+            self.inner.query_raw(explain_query.as_str(), params).await?
+                .try_collect::<Vec<_>>()
+                .await
+        }.await;
+
+        // The rollback is required even if explaining failed, as the failed
+        // query would otherwise leave the transaction aborted and all
+        // following queries of this request would fail.
+        if let Err(e) = self.inner.batch_execute("rollback to savepoint tobira_explain").await {
+            error!("Failed to roll back after explaining slow query \"{}\": {}", query, e);
+            self.error.store(true, Ordering::SeqCst);
+        }
+
+        match res {
+            Ok(rows) => explainer.plans.lock().unwrap().push(QueryPlan {
+                query: query.to_owned(),
+                duration_ms: duration.as_secs_f64() * 1000.0,
+                plan: rows.iter().map(|row| row.get(0)).collect(),
+            }),
+            Err(e) => warn!("Failed to explain slow query \"{}\": {}", query, e),
+        }
+    }
+
     /// Marks a specific item as "needs reindex". Meaning that data that is
     /// relevant for the search index has changed.
     pub(crate) async fn queue_for_reindex(
@@ -82,7 +204,11 @@ impl Transaction {
         trace!("Executing SQL query: \"{}\" with {:?}", query, params);
         let statement = self.inner.prepare_cached(query).await?;
         self.increase_num_queries();
-        self.check_error(self.inner.query_one(&statement, params).await)
+        let before = Instant::now();
+        let out = self.check_error(self.inner.query_one(&statement, params).await);
+        self.trace(query, before);
+        self.explain_if_slow(query, params.iter().map(|p| *p as _), before.elapsed()).await;
+        out
     }
 
     pub async fn query_opt(
@@ -93,23 +219,15 @@ impl Transaction {
         trace!("Executing SQL query: \"{}\" with {:?}", query, params);
         let statement = self.inner.prepare_cached(query).await?;
         self.increase_num_queries();
-        self.check_error(self.inner.query_opt(&statement, params).await)
-    }
-
-    pub async fn query_raw<P, I>(&self, query: &str, params: I) -> Result<RowStream, Error>
-    where
-        P: BorrowToSql,
-        I: IntoIterator<Item = P> + std::fmt::Debug,
-        I::IntoIter: ExactSizeIterator,
-    {
-        trace!("Executing SQL query: \"{}\" with {:?}", query, params);
-        let statement = self.inner.prepare_cached(query).await?;
-        self.increase_num_queries();
-        self.check_error(self.inner.query_raw(&statement, params).await)
+        let before = Instant::now();
+        let out = self.check_error(self.inner.query_opt(&statement, params).await);
+        self.trace(query, before);
+        self.explain_if_slow(query, params.iter().map(|p| *p as _), before.elapsed()).await;
+        out
     }
 
     /// Convenience method to query many rows and convert each row to a specific
-    /// type with `from_row`.
+    /// type with `from_row`. The duration includes receiving all rows.
     pub async fn query_mapped<P, I, F, T>(
         &self,
         query: &str,
@@ -122,7 +240,23 @@ impl Transaction {
         I::IntoIter: ExactSizeIterator,
         F: FnMut(Row) -> T,
     {
-        collect_rows_mapped(self.query_raw(query, params), from_row).await
+        trace!("Executing SQL query: \"{}\" with {:?}", query, params);
+        let statement = self.inner.prepare_cached(query).await?;
+        self.increase_num_queries();
+
+        // The parameters are needed twice when explaining.
+        let params = params.into_iter().collect::<Vec<_>>();
+        let before = Instant::now();
+        let rows = collect_rows_mapped(
+            self.inner.query_raw(&statement, params.iter().map(|p| p.borrow_to_sql())),
+            from_row,
+        ).await;
+        let rows = self.check_error(rows);
+        self.trace(query, before);
+        self.explain_if_slow(query, params.iter().map(|p| p.borrow_to_sql()), before.elapsed())
+            .await;
+
+        rows
     }
 
     pub async fn execute(
@@ -133,6 +267,10 @@ impl Transaction {
         trace!("Executing SQL query: \"{}\" with {:?}", query, params);
         let statement = self.inner.prepare_cached(query).await?;
         self.increase_num_queries();
-        self.check_error(self.inner.execute(&statement, params).await)
+        let before = Instant::now();
+        let out = self.check_error(self.inner.execute(&statement, params).await);
+        self.trace(query, before);
+        self.explain_if_slow(query, params.iter().map(|p| *p as _), before.elapsed()).await;
+        out
     }
 }
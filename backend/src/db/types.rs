@@ -6,13 +6,32 @@ use serde::{Deserialize, Serialize};
 
 
 /// Represents the `event_track` type defined in `5-events.sql`.
-#[derive(Debug, FromSql, ToSql)]
+#[derive(Debug, Clone, FromSql, ToSql)]
 #[postgres(name = "event_track")]
 pub struct EventTrack {
     pub uri: String,
     pub flavor: String,
     pub mimetype: Option<String>,
     pub resolution: Option<[i32; 2]>,
+    pub is_audio: Option<bool>,
+}
+
+/// Represents the `event_caption` type defined in `47-event-captions.sql`.
+#[derive(Debug, Clone, FromSql, ToSql)]
+#[postgres(name = "event_caption")]
+pub struct EventCaption {
+    pub uri: String,
+    pub lang: Option<String>,
+}
+
+/// Returns whether a track with the given mimetype only contains audio, or
+/// `None` if the mimetype does not tell (e.g. for HLS or DASH manifests).
+pub fn is_audio_mimetype(mimetype: &str) -> Option<bool> {
+    match mimetype.split_once('/')?.0 {
+        "audio" => Some(true),
+        "video" => Some(false),
+        _ => None,
+    }
 }
 
 
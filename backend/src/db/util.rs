@@ -20,6 +20,64 @@ macro_rules! dbargs {
 pub(crate) use dbargs;
 
 
+/// Defines a module describing the columns that are selected to construct a
+/// type from a DB row. Each column is specified with a name, the Rust type and
+/// the SQL expression. The generated module contains:
+///
+/// - `COL_NAMES`: all SQL expressions joined by `, `, for `select` clauses,
+/// - `COLUMNS`: all SQL expressions as slice,
+/// - one function per column that reads that column with the specified type
+///   from a row that was selected with `COL_NAMES`.
+///
+/// That way, the column list and the row indices cannot drift apart. Example:
+///
+/// ```ignore
+/// define_columns! {
+///     mod cols {
+///         key: Key = "id",
+///         title: String = "title",
+///     }
+/// }
+///
+/// let query = format!("select {} from series", cols::COL_NAMES);
+/// let title = cols::title(&row);
+/// ```
+macro_rules! define_columns {
+    (
+        mod $mod:ident {
+            $first_name:ident: $first_ty:ty = $first_sql:literal
+            $(, $name:ident: $ty:ty = $sql:literal)* $(,)?
+        }
+    ) => {
+        #[allow(dead_code)]
+        mod $mod {
+            use super::*;
+
+            pub(crate) const COL_NAMES: &str = concat!($first_sql $(, ", ", $sql)*);
+            pub(crate) const COLUMNS: &[&str] = &[$first_sql $(, $sql)*];
+
+            /// Index of each column in `COLUMNS`.
+            #[allow(non_camel_case_types)]
+            enum Index {
+                $first_name,
+                $($name,)*
+            }
+
+            pub(crate) fn $first_name(row: &tokio_postgres::Row) -> $first_ty {
+                row.get(Index::$first_name as usize)
+            }
+            $(
+                pub(crate) fn $name(row: &tokio_postgres::Row) -> $ty {
+                    row.get(Index::$name as usize)
+                }
+            )*
+        }
+    };
+}
+
+pub(crate) use define_columns;
+
+
 /// Collects all rows of the given raw query result into a vector, but mapping
 /// each row to a given type.
 pub(crate) async fn collect_rows_mapped<R, F, O>(rows: R, from_row: F) -> Result<Vec<O>, Error>
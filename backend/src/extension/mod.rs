@@ -0,0 +1,138 @@
+//! Compile-time extensions. Downstream forks can add custom GraphQL fields,
+//! blocks and HTTP routes by implementing `Extension` in a module of
+//! `registry/` and registering it in `registry.rs`. That way, core modules don't
+//! have to be patched. `registry.rs` is only compiled with the `extensions`
+//! cargo feature, otherwise `no_registry.rs` is used. See
+//! `docs/extensions.md` for more information.
+
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use futures::future::BoxFuture;
+use hyper::Body;
+
+use crate::{
+    api::err::{ApiResult, invalid_input},
+    config::Config,
+    http::{self, Request, Response},
+    prelude::*,
+};
+
+#[cfg(feature = "extensions")]
+mod registry;
+#[cfg(not(feature = "extensions"))]
+#[path = "no_registry.rs"]
+mod registry;
+
+
+/// HTTP requests to `<PATH_PREFIX><name>/...` are passed to the extension with
+/// that name.
+pub(crate) const PATH_PREFIX: &str = "/~ext/";
+
+/// An extension that is compiled into Tobira.
+pub(crate) trait Extension: Any + Send + Sync {
+    /// Handles HTTP requests to `/~ext/<name>/<rest>` (all methods). `rest`
+    /// is the path after the name, without leading `/`. Returns `None` if the
+    /// path is unknown, resulting in a 404 response.
+    fn handle_http<'a>(
+        &'a self,
+        req: Request<Body>,
+        rest: &'a str,
+        ctx: &'a http::Context,
+    ) -> BoxFuture<'a, Option<Response>> {
+        let _ = (req, rest, ctx);
+        Box::pin(async { None })
+    }
+
+    /// Checks the data of a new or updated `ExtensionBlock` of this
+    /// extension. Blocks are only stored if this returns `Ok`.
+    fn validate_block(&self, data: &serde_json::Value) -> ApiResult<()> {
+        let _ = data;
+        Err(invalid_input!("extension does not provide blocks"))
+    }
+}
+
+/// Creates an extension from its configuration, the `[extensions.<name>]`
+/// table in the config file (`None` if that does not exist).
+pub(crate) type Constructor = fn(Option<&toml::Value>) -> Result<Box<dyn Extension>>;
+
+/// All extensions of this Tobira instance, by name.
+pub(crate) struct Extensions(HashMap<&'static str, Box<dyn Extension>>);
+
+impl Extensions {
+    /// Creates all registered extensions with their configuration.
+    pub(crate) fn load(config: &Config) -> Result<Self> {
+        let registered = registry::EXTENSIONS;
+
+        let configs = config.extensions.as_ref();
+        for name in configs.into_iter().flat_map(|configs| configs.keys()) {
+            if !registered.iter().any(|(n, _)| n == name) {
+                warn!("Configuration for unknown extension '{}' is ignored", name);
+            }
+        }
+
+        let mut out = HashMap::new();
+        for &(name, constructor) in registered {
+            let ext_config = configs.and_then(|configs| configs.get(name));
+            let extension = constructor(ext_config)
+                .with_context(|| format!("failed to initialize extension '{name}'"))?;
+            info!("Loaded extension '{}'", name);
+            out.insert(name, extension);
+        }
+
+        Ok(Self(out))
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&dyn Extension> {
+        self.0.get(name).map(|ext| &**ext)
+    }
+
+    /// Returns the extension with the given name if it has type `T`. Useful in
+    /// the resolvers of `registry.rs` to access an extension's configuration.
+    #[allow(dead_code)] // Only used by registered extensions.
+    pub(crate) fn get_as<T: Extension>(&self, name: &str) -> Option<&T> {
+        let extension: &dyn Any = self.get(name)?;
+        extension.downcast_ref()
+    }
+
+    /// Names of all loaded extensions, sorted.
+    pub(crate) fn names(&self) -> Vec<&'static str> {
+        let mut out = self.0.keys().copied().collect::<Vec<_>>();
+        out.sort_unstable();
+        out
+    }
+
+    /// Parses and validates the data of an `ExtensionBlock` of the extension
+    /// `name`.
+    pub(crate) fn block_data(&self, name: &str, data: &str) -> ApiResult<serde_json::Value> {
+        let extension = self.get(name)
+            .ok_or_else(|| invalid_input!("there is no extension '{}'", name))?;
+        let data = serde_json::from_str(data)
+            .map_err(|e| invalid_input!("block data is not valid JSON: {}", e))?;
+        extension.validate_block(&data)?;
+        Ok(data)
+    }
+}
+
+/// Entry point of GraphQL fields of extensions: `Query.extensions`. The
+/// fields are defined in `registry.rs`.
+pub(crate) struct ExtensionQuery;
+
+/// Data of an `ExtensionBlock`. Its GraphQL fields are defined in
+/// `registry.rs`, e.g. one per extension that deserializes `data` into the
+/// type of its blocks.
+#[derive(Clone)]
+pub(crate) struct ExtensionBlockData {
+    pub(crate) extension: String,
+    #[allow(dead_code)] // Only used by registered extensions.
+    pub(crate) data: serde_json::Value,
+}
+
+/// Handles requests to `PATH_PREFIX`.
+pub(crate) async fn handle_http(req: Request<Body>, ctx: Arc<http::Context>) -> Option<Response> {
+    let path = req.uri().path().to_owned();
+    let (name, rest) = path.strip_prefix(PATH_PREFIX)?
+        .split_once('/')
+        .unwrap_or((&path[PATH_PREFIX.len()..], ""));
+
+    ctx.extensions.get(name)?.handle_http(req, rest, &ctx).await
+}
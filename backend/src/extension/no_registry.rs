@@ -0,0 +1,26 @@
+//! Used instead of `registry.rs` when Tobira is built without the
+//! `extensions` feature: no extensions and only the basic GraphQL fields.
+
+use juniper::graphql_object;
+
+use crate::api::Context;
+use super::{Constructor, ExtensionBlockData, ExtensionQuery};
+
+
+pub(super) const EXTENSIONS: &[(&str, Constructor)] = &[];
+
+#[graphql_object(Context = Context)]
+impl ExtensionQuery {
+    /// Names of all loaded extensions.
+    fn loaded(context: &Context) -> Vec<&'static str> {
+        context.extensions.names()
+    }
+}
+
+#[graphql_object(Context = Context)]
+impl ExtensionBlockData {
+    /// Name of the extension the block belongs to.
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+}
@@ -0,0 +1,39 @@
+//! The list of extensions compiled into this Tobira instance and their
+//! GraphQL fields. To add an extension, add its module to `registry/` (e.g.
+//! `mod my_extension;` here) and its name and constructor to `EXTENSIONS`.
+//! API fields of the extension are added to `ExtensionQuery` and, if it
+//! provides blocks, to `ExtensionBlockData`.
+
+use juniper::graphql_object;
+
+use crate::api::Context;
+use super::{Constructor, ExtensionBlockData, ExtensionQuery};
+
+
+pub(super) const EXTENSIONS: &[(&str, Constructor)] = &[
+    // ("my-extension", my_extension::new),
+];
+
+#[graphql_object(Context = Context)]
+impl ExtensionQuery {
+    /// Names of all loaded extensions.
+    fn loaded(context: &Context) -> Vec<&'static str> {
+        context.extensions.names()
+    }
+
+    // async fn my_extension(context: &Context) -> ApiResult<my_extension::Data> {
+    //     my_extension::load(context).await
+    // }
+}
+
+#[graphql_object(Context = Context)]
+impl ExtensionBlockData {
+    /// Name of the extension the block belongs to.
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    // fn my_extension(&self) -> Option<my_extension::Block> {
+    //     my_extension::Block::from_data(self)
+    // }
+}
@@ -0,0 +1,273 @@
+//! Geoblocking: events can be restricted to clients in certain countries,
+//! e.g. because of licensing constraints.
+//!
+//! The country of a client is looked up by its IP address in a GeoIP
+//! database that is loaded at startup. Restricted events are still listed and
+//! shown with their metadata, but for clients in other countries, their
+//! tracks and captions are hidden in the API (see `Event.isGeoblocked`), they
+//! are left out of series downloads and their videos are not preloaded.
+//! Users with write access to an event are never blocked.
+
+use std::{net::IpAddr, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct GeoblockingConfig {
+    /// Path to the GeoIP database: a CSV file with one IP range per line,
+    /// consisting of the first and last address of the range and the ISO
+    /// 3166 country code, e.g. `1.0.0.0,1.0.0.255,AU`. That is the format of
+    /// the free "IP to Country Lite" database by DB-IP. Required if `rules`
+    /// are set. It is loaded at startup, so Tobira has to be restarted after
+    /// updating it.
+    pub(crate) database: Option<PathBuf>,
+
+    /// Countries from which restricted events can be played. Each rule
+    /// applies to the events whose read roles contain `role` or whose
+    /// Opencast ID is listed in `events`. If several rules apply to an event,
+    /// all of them have to allow the country. Behind a reverse proxy,
+    /// `http.client_ip_header` has to be set. Example:
+    ///
+    ///     rules = [
+    ///         { role = "ROLE_LICENSE_CH", countries = ["CH", "LI"] },
+    ///         { events = ["7e2ba6d5-..."], countries = ["DE", "AT", "CH"] },
+    ///     ]
+    pub(crate) rules: Option<Vec<GeoRule>>,
+
+    /// Whether restricted events can be played by clients whose country is
+    /// unknown, e.g. because they are in a private network.
+    #[config(default = false)]
+    pub(crate) allow_unknown: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct GeoRule {
+    role: Option<String>,
+    events: Option<Vec<String>>,
+    countries: Vec<Country>,
+}
+
+impl GeoblockingConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        for rule in self.rules() {
+            if rule.role.is_some() == rule.events.is_some() {
+                bail!("each rule in `geoblocking.rules` needs exactly one of `role` and `events`");
+            }
+            if rule.countries.is_empty() {
+                bail!("`countries` of rules in `geoblocking.rules` must not be empty");
+            }
+        }
+        if self.is_enabled() && self.database.is_none() {
+            bail!("`geoblocking.database` has to be set if `geoblocking.rules` are set");
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.rules().is_empty()
+    }
+
+    fn rules(&self) -> &[GeoRule] {
+        self.rules.as_deref().unwrap_or_default()
+    }
+
+    fn applying_rules<'a>(
+        &'a self,
+        opencast_id: Option<&'a str>,
+        read_roles: &'a [String],
+    ) -> impl Iterator<Item = &'a GeoRule> {
+        self.rules().iter().filter(move |rule| {
+            rule.role.as_ref().is_some_and(|role| read_roles.contains(role))
+                || rule.events.iter().flatten().any(|id| Some(id.as_str()) == opencast_id)
+        })
+    }
+
+    /// Whether any rule applies to the given event.
+    pub(crate) fn restricts(&self, opencast_id: Option<&str>, read_roles: &[String]) -> bool {
+        self.applying_rules(opencast_id, read_roles).next().is_some()
+    }
+
+    /// Whether the given event can be played by clients in `country`.
+    pub(crate) fn allows(
+        &self,
+        opencast_id: Option<&str>,
+        read_roles: &[String],
+        country: Option<Country>,
+    ) -> bool {
+        self.applying_rules(opencast_id, read_roles).all(|rule| match country {
+            Some(country) => rule.countries.contains(&country),
+            None => self.allow_unknown,
+        })
+    }
+}
+
+/// An ISO 3166 alpha-2 country code, e.g. `CH`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Country([u8; 2]);
+
+impl std::str::FromStr for Country {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.as_bytes() {
+            &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                Ok(Self([a.to_ascii_uppercase(), b.to_ascii_uppercase()]))
+            }
+            _ => bail!("invalid country code '{s}'"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Country {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// IP ranges with their country, sorted by their first address.
+#[derive(Debug, Default)]
+pub(crate) struct GeoIpDb {
+    v4: Vec<(u32, u32, Country)>,
+    v6: Vec<(u128, u128, Country)>,
+}
+
+impl GeoIpDb {
+    /// Loads the database configured as `geoblocking.database`, if
+    /// geoblocking is enabled.
+    pub(crate) fn load(config: &GeoblockingConfig) -> Result<Option<Self>> {
+        let Some(path) = config.database.as_ref().filter(|_| config.is_enabled()) else {
+            return Ok(None);
+        };
+        let csv = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read GeoIP database '{}'", path.display()))?;
+        let db = Self::parse(&csv)
+            .with_context(|| format!("failed to parse GeoIP database '{}'", path.display()))?;
+        info!(
+            "Loaded GeoIP database with {} IPv4 and {} IPv6 ranges",
+            db.v4.len(),
+            db.v6.len(),
+        );
+        Ok(Some(db))
+    }
+
+    fn parse(csv: &str) -> Result<Self> {
+        let mut out = Self::default();
+        for (i, line) in csv.lines().enumerate() {
+            let fields = line.split(',').map(|f| f.trim().trim_matches('"')).collect::<Vec<_>>();
+            let [first, last, country, ..] = fields.as_slice() else {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                bail!("line {} has less than three fields", i + 1);
+            };
+
+            let parse = || -> Result<_> {
+                Ok((first.parse::<IpAddr>()?, last.parse::<IpAddr>()?, country.parse::<Country>()?))
+            };
+            let (first, last, country) = match parse() {
+                Ok(range) => range,
+                // Skip a header line.
+                Err(_) if i == 0 => continue,
+                Err(e) => return Err(e).with_context(|| format!("invalid line {}", i + 1)),
+            };
+            match (first, last) {
+                (IpAddr::V4(first), IpAddr::V4(last)) => {
+                    out.v4.push((first.into(), last.into(), country));
+                }
+                (IpAddr::V6(first), IpAddr::V6(last)) => {
+                    out.v6.push((first.into(), last.into(), country));
+                }
+                _ => bail!("line {} mixes IPv4 and IPv6 addresses", i + 1),
+            }
+        }
+
+        out.v4.sort_unstable_by_key(|range| range.0);
+        out.v6.sort_unstable_by_key(|range| range.0);
+        Ok(out)
+    }
+
+    /// Returns the country of the given address, if known.
+    pub(crate) fn lookup(&self, ip: IpAddr) -> Option<Country> {
+        fn find<T: Ord + Copy>(ranges: &[(T, T, Country)], ip: T) -> Option<Country> {
+            let i = ranges.partition_point(|range| range.0 <= ip);
+            let (_, last, country) = ranges.get(i.checked_sub(1)?)?;
+            (ip <= *last).then_some(*country)
+        }
+
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => find(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => find(&self.v6, u128::from(ip)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Country, GeoIpDb, GeoRule, GeoblockingConfig};
+
+    fn country(s: &str) -> Option<Country> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn lookup() {
+        let db = GeoIpDb::parse("\
+            ip_start,ip_end,country\n\
+            1.0.0.0,1.0.0.255,AU\n\
+            \"2.16.0.0\",\"2.16.0.255\",\"DE\"\n\
+            1.0.4.0,1.0.7.255,au\n\
+            2001:200::,2001:200:ffff:ffff:ffff:ffff:ffff:ffff,JP\n\
+        ").unwrap();
+
+        let lookup = |ip: &str| db.lookup(ip.parse().unwrap());
+        assert_eq!(lookup("0.255.255.255"), None);
+        assert_eq!(lookup("1.0.0.0"), country("AU"));
+        assert_eq!(lookup("1.0.0.255"), country("AU"));
+        assert_eq!(lookup("1.0.1.0"), None);
+        assert_eq!(lookup("1.0.5.17"), country("AU"));
+        assert_eq!(lookup("2.16.0.7"), country("DE"));
+        assert_eq!(lookup("::ffff:2.16.0.7"), country("DE"));
+        assert_eq!(lookup("2001:200::1"), country("JP"));
+        assert_eq!(lookup("2001:201::1"), None);
+
+        assert!(GeoIpDb::parse("1.0.0.0,1.0.0.255,AU\n1.0.0.0,1.0.0.255,AUS").is_err());
+        assert!(GeoIpDb::parse("1.0.0.0,::1,AU").is_err());
+    }
+
+    #[test]
+    fn rules() {
+        let config = GeoblockingConfig {
+            database: Some("geo.csv".into()),
+            rules: Some(vec![
+                GeoRule {
+                    role: Some("ROLE_CH".into()),
+                    events: None,
+                    countries: vec!["CH".parse().unwrap(), "LI".parse().unwrap()],
+                },
+                GeoRule {
+                    role: None,
+                    events: Some(vec!["special".into()]),
+                    countries: vec!["CH".parse().unwrap(), "DE".parse().unwrap()],
+                },
+            ]),
+            allow_unknown: false,
+        };
+        config.validate().unwrap();
+        let roles = ["ROLE_ANONYMOUS".to_owned(), "ROLE_CH".to_owned()];
+
+        assert!(!config.restricts(Some("other"), &roles[..1]));
+        assert!(config.allows(Some("other"), &roles[..1], None));
+        assert!(config.restricts(Some("other"), &roles));
+        assert!(config.allows(Some("other"), &roles, country("LI")));
+        assert!(!config.allows(Some("other"), &roles, country("DE")));
+        assert!(!config.allows(Some("other"), &roles, None));
+        assert!(config.allows(Some("special"), &roles[..1], country("DE")));
+        assert!(config.allows(Some("special"), &roles, country("CH")));
+        assert!(!config.allows(Some("special"), &roles, country("LI")));
+    }
+}
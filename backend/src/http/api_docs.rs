@@ -0,0 +1,235 @@
+//! Human readable documentation of the GraphQL API, served at `/~api-docs`.
+//!
+//! The page is generated from the schema language representation of our
+//! juniper schema, so it is always in sync with the actual API. In contrast
+//! to GraphiQL, it is a static page that does not require JS.
+
+use std::collections::HashSet;
+
+use hyper::StatusCode;
+use once_cell::sync::OnceCell;
+
+use super::{Context, Response, escape_html};
+
+
+/// Path of the route serving the API documentation.
+pub(super) const PATH: &str = "/~api-docs";
+
+/// Serves the rendered documentation. It is only rendered once and then
+/// cached, as the schema cannot change at runtime.
+pub(super) fn serve(ctx: &Context) -> Response {
+    static HTML: OnceCell<String> = OnceCell::new();
+    let html = HTML.get_or_init(|| render(&ctx.api_root.as_schema_language()));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html; charset=UTF-8")
+        .body(html.clone().into())
+        .unwrap()
+}
+
+/// The kinds of top level definitions, in the order they appear on the page.
+const KINDS: &[(&str, &str)] = &[
+    ("type", "Object types"),
+    ("interface", "Interfaces"),
+    ("union", "Unions"),
+    ("input", "Input types"),
+    ("enum", "Enums"),
+    ("scalar", "Scalars"),
+];
+
+/// A top level definition in the schema, including its description.
+#[derive(Debug, PartialEq)]
+pub(super) struct Definition<'a> {
+    pub(super) kind: &'a str,
+    pub(super) name: &'a str,
+    pub(super) source: String,
+}
+
+/// Splits the schema into its top level definitions. The `schema { ... }`
+/// block is skipped.
+pub(super) fn definitions(schema: &str) -> Vec<Definition<'_>> {
+    let mut out = Vec::new();
+    let mut source = String::new();
+    let mut header = None;
+    let mut in_description = false;
+
+    for line in schema.lines() {
+        if source.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        source.push_str(line);
+        source.push('\n');
+
+        // Only unindented lines are interesting to find the start and end of
+        // definitions. Multi line descriptions of top level definitions are
+        // also unindented, so we have to keep track of those.
+        if line.is_empty() || line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        if line == "\"\"\"" {
+            in_description = !in_description;
+            continue;
+        }
+        if in_description || line.starts_with('"') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let first = words.next().unwrap_or_default();
+        if header.is_none() && (first == "schema" || KINDS.iter().any(|(kind, _)| *kind == first)) {
+            header = Some((first, words.next().unwrap_or_default()));
+        }
+
+        if !line.ends_with('{') {
+            if let Some((kind, name)) = header.take() {
+                if kind != "schema" {
+                    out.push(Definition { kind, name, source: source.clone() });
+                }
+            }
+            source.clear();
+        }
+    }
+
+    out
+}
+
+fn render(schema: &str) -> String {
+    let mut definitions = definitions(schema);
+    definitions.sort_by_key(|def| def.name);
+    let names = definitions.iter().map(|def| def.name).collect::<HashSet<_>>();
+
+    // The root types are shown first, followed by all other definitions
+    // grouped by kind.
+    let is_root = |def: &Definition| matches!(def.name, "Query" | "Mutation");
+    let mut groups = vec![(
+        "Root types",
+        definitions.iter().filter(|def| is_root(def)).collect::<Vec<_>>(),
+    )];
+    for (kind, label) in KINDS {
+        let defs = definitions.iter()
+            .filter(|def| def.kind == *kind && !is_root(def))
+            .collect::<Vec<_>>();
+        if !defs.is_empty() {
+            groups.push((label, defs));
+        }
+    }
+
+    let mut toc = String::new();
+    let mut body = String::new();
+    for (label, defs) in &groups {
+        toc += &format!("<li>{}<ul>", label);
+        body += &format!("<h2>{}</h2>", label);
+        for def in defs {
+            toc += &format!(r##"<li><a href="#{0}">{0}</a></li>"##, def.name);
+            body += &format!(
+                r#"<section id="{}"><pre>{}</pre></section>"#,
+                def.name,
+                link_types(&escape_html(&def.source), &names),
+            );
+        }
+        toc += "</ul></li>";
+    }
+
+    format!(
+        concat!(
+            "<!DOCTYPE html>",
+            r#"<html lang="en"><head><meta charset="utf-8">"#,
+            r#"<meta name="viewport" content="width=device-width, initial-scale=1">"#,
+            "<title>Tobira API documentation</title>",
+            "<style>{css}</style>",
+            "</head><body>",
+            "<nav><h1>Tobira API</h1><ul>{toc}</ul></nav>",
+            "<main>",
+            "<p>All requests are sent as <code>POST</code> to <code>/graphql</code>. ",
+            r#"This page is generated from the schema (<a href="/~graphiql">GraphiQL</a>).</p>"#,
+            "{body}",
+            "</main></body></html>",
+        ),
+        css = CSS,
+        toc = toc,
+        body = body,
+    )
+}
+
+const CSS: &str = "\
+    body { display: flex; margin: 0; font-family: sans-serif; }\
+    nav { flex: 0 0 260px; height: 100vh; overflow-y: auto; position: sticky; top: 0; \
+        padding: 0 16px; background: #f3f3f3; }\
+    nav ul { padding-left: 16px; }\
+    main { flex: 1; min-width: 0; padding: 0 24px; }\
+    pre { padding: 8px; background: #fafafa; border: 1px solid #ddd; overflow-x: auto; }\
+    section:target pre { border-color: #347; }\
+";
+
+/// Wraps all identifiers that are names of known types in a link to their
+/// definition.
+fn link_types(s: &str, names: &HashSet<&str>) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(is_ident_char) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+        let ident = &rest[..end];
+        if names.contains(ident) {
+            out += &format!(r##"<a href="#{0}">{0}</a>"##, ident);
+        } else {
+            out.push_str(ident);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_definitions() {
+        let schema = concat!(
+            "\"A thing.\"\n",
+            "type Foo implements Node {\n",
+            "  \"\"\"\n",
+            "    Multi line\n",
+            "\n",
+            "    description.\n",
+            "  \"\"\"\n",
+            "  bar: Bar!\n",
+            "}\n",
+            "\n",
+            "\"\"\"\n",
+            "Top level\n",
+            "description\n",
+            "\"\"\"\n",
+            "scalar Bar\n",
+            "\n",
+            "schema {\n",
+            "  query: Foo\n",
+            "}\n",
+        );
+
+        let defs = definitions(schema);
+        assert_eq!(defs.iter().map(|d| (d.kind, d.name)).collect::<Vec<_>>(), vec![
+            ("type", "Foo"),
+            ("scalar", "Bar"),
+        ]);
+        assert_eq!(defs[0].source, schema.lines().take(9).map(|l| format!("{}\n", l)).collect::<String>());
+        assert!(defs[1].source.starts_with("\"\"\"\nTop level"));
+    }
+
+    #[test]
+    fn links() {
+        let names = ["Foo", "Bar"].into_iter().collect();
+        assert_eq!(
+            link_types("foo: [Foo!]! # Foobar Bar", &names),
+            r##"foo: [<a href="#Foo">Foo</a>!]! # Foobar <a href="#Bar">Bar</a>"##,
+        );
+    }
+}
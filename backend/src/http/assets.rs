@@ -4,8 +4,8 @@ use hyper::Body;
 use reinda::{assets, Setup};
 use serde_json::json;
 
-use crate::{config::Config, prelude::*};
-use super::Response;
+use crate::{config::{Config, Overrides}, prelude::*};
+use super::{Response, graphiql::GraphiqlAccess};
 
 
 const ASSETS: Setup = assets! {
@@ -65,6 +65,10 @@ const ASSETS: Setup = assets! {
 
 const INDEX_FILE: &str = "index.html";
 
+/// The opening tag of the `<script>` containing the frontend config in
+/// `index.html`.
+const FRONTEND_CONFIG_START: &str = r#"<script id="tobira-frontend-config" type="application/json">"#;
+
 pub(crate) struct Assets {
     assets: reinda::Assets,
 }
@@ -93,10 +97,26 @@ impl Assets {
         variables.insert("upload-node".into(), config.opencast.upload_node().to_string());
         variables.insert("studio-url".into(), config.opencast.studio_url());
         variables.insert("editor-url".into(), config.opencast.editor_url());
+        variables.insert(
+            "virus-scan".into(),
+            config.upload.virus_scan.is_enabled().to_string(),
+        );
+
+        variables.insert("playback-stats".into(), config.http.playback_stats.to_string());
+        variables.insert("error-reports".into(), config.http.error_reports.enabled.to_string());
+        variables.insert(
+            "graphiql-public".into(),
+            (config.http.graphiql.access == GraphiqlAccess::Everyone).to_string(),
+        );
 
         variables.insert("html-title".into(), config.general.site_title.en().into());
         variables.insert("site-title".into(), config.general.site_title.to_json());
         variables.insert("footer-links".into(), json!(config.general.footer_links()).to_string());
+        variables.insert("announcement".into(), json!(config.general.announcement).to_string());
+        variables.insert(
+            "event-filter-label".into(),
+            json!(config.general.event_filter.as_ref().map(|filter| &filter.label)).to_string(),
+        );
         variables.insert(
             "large-logo-resolution".into(),
             format!("{:?}", config.theme.logo.large.resolution.0),
@@ -158,19 +178,47 @@ impl Assets {
     }
 
     pub(crate) async fn index(&self) -> Body {
+        self.index_bytes().await.into()
+    }
+
+    async fn index_bytes(&self) -> bytes::Bytes {
         // We treat the `index.html` missing as internal server error. We are
         // not a general file server. We require this index file to function.
         self.assets.get(INDEX_FILE).await
             .expect("failed to read 'index.html'")
             .expect("`index.html` missing in internal assets")
-            .into()
     }
 
     /// Serves the main entry point of the application. This is replied to `/`
     /// and other "public routes", like `/lectures`. Basically everywhere where
     /// the user is supposed to see the website.
-    pub(crate) async fn serve_index(&self) -> Response {
-        let html = self.index().await;
+    ///
+    /// The settings in `overrides` are applied on top of the values from the
+    /// config file that were inserted into `index.html` at startup. `head` is
+    /// inserted at the end of `<head>` and `lang` is set as language of the
+    /// document.
+    pub(crate) async fn serve_index(
+        &self,
+        overrides: &Overrides,
+        head: &str,
+        lang: Option<&str>,
+        config: &Config,
+    ) -> Response {
+        let html = if overrides.is_empty() && head.is_empty() && lang.is_none() {
+            self.index().await
+        } else {
+            let html = self.index_bytes().await;
+            let html = std::str::from_utf8(&html).expect("`index.html` is not valid UTF-8");
+            let mut html = apply_overrides(html, overrides, config);
+            let head_end = html.find("</head>").expect("`index.html` has no `</head>`");
+            html.insert_str(head_end, head);
+            if let Some(lang) = lang {
+                let html_tag_end = html.find("<html").expect("`index.html` has no `<html>`")
+                    + "<html".len();
+                html.insert_str(html_tag_end, &format!(r#" lang="{}""#, super::escape_html(lang)));
+            }
+            html.into()
+        };
 
         // TODO: include useful data into the HTML file
 
@@ -183,3 +231,34 @@ impl Assets {
         builder.body(html).expect("bug: invalid response")
     }
 }
+
+/// Replaces the frontend config embedded in `html` by one with `overrides`
+/// applied. Overridden theme colors are added as additional `<style>`.
+fn apply_overrides(html: &str, overrides: &Overrides, config: &Config) -> String {
+    let start = html.find(FRONTEND_CONFIG_START)
+        .expect("frontend config missing in `index.html`")
+        + FRONTEND_CONFIG_START.len();
+    let len = html[start..].find("</script>")
+        .expect("frontend config not terminated in `index.html`");
+
+    if overrides.is_empty() {
+        return html.to_owned();
+    }
+
+    let mut frontend_config = serde_json::from_str(&html[start..start + len])
+        .expect("frontend config in `index.html` is not valid JSON");
+    overrides.apply_to_frontend_config(&mut frontend_config);
+
+    let mut out = String::with_capacity(html.len() + 4096);
+    out.push_str(&html[..start]);
+    // Prevent settings from terminating the `<script>` tag early.
+    out.push_str(&frontend_config.to_string().replace("</", "<\\/"));
+    out.push_str(&html[start + len..]);
+
+    if let Some(css) = overrides.theme_css(config) {
+        let head_end = out.find("</head>").expect("`index.html` has no `</head>`");
+        out.insert_str(head_end, &format!("<style>{}</style>", css));
+    }
+
+    out
+}
@@ -0,0 +1,277 @@
+//! Avatar images uploaded by users (if `auth.avatar_upload` is enabled).
+//!
+//! The images are stored in the DB. Only PNG and JPEG images are accepted.
+//! Larger images are resized with the ImageMagick binary configured as
+//! `http.thumbnails.converter`. Without it, they are rejected. The format and
+//! dimensions are read from the image header and the image is served with the
+//! detected content type only, so browsers never interpret it as anything
+//! else.
+
+use hyper::{Body, StatusCode};
+
+use crate::{auth::User, db::{self, DbConnection}, prelude::*};
+use super::{Context, Request, Response, body, handlers::reply_404, response, thumbnail};
+
+
+/// `POST` uploads the avatar of the current user, `GET <PATH>/<username>`
+/// returns the avatar of the given user.
+pub(crate) const PATH: &str = "/~avatar";
+
+/// Maximum size of a stored image in bytes. This is also the maximum size of
+/// uploads if images cannot be resized.
+const MAX_SIZE: usize = 256 * 1024;
+
+/// Maximum size of an uploaded image in bytes if it can be resized.
+const MAX_UPLOAD_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum width and height of a stored image in pixels.
+const MAX_DIMENSION: u32 = 512;
+
+/// Header that has to be set for uploads. Browsers only send custom headers
+/// cross-origin after a CORS preflight request, which Tobira never allows.
+/// So this prevents other sites from changing the avatar of a user (CSRF).
+pub(super) const CSRF_HEADER: &str = "x-requested-with";
+
+/// Handles `POST /~avatar`: the body is the image, which replaces the
+/// current avatar of the user. The `X-Requested-With` header has to be set.
+pub(super) async fn upload(
+    req: Request<Body>,
+    db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let Some(user) = user else {
+        return response::forbidden();
+    };
+    let what = format!("avatar of '{}'", user.username);
+    let (data, mimetype) = match read_image(req, MAX_DIMENSION, MAX_SIZE, &what, ctx).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+
+    let res = db.execute(
+        "insert into user_avatars (username, data, mimetype) values ($1, $2, $3) \
+            on conflict (username) do update \
+            set data = excluded.data, mimetype = excluded.mimetype, updated = now()",
+        &[&user.username, &data, &mimetype],
+    ).await;
+    if let Err(e) = res {
+        error!("DB error when storing avatar: {}", e);
+        return response::internal_server_error();
+    }
+    info!("User '{}' uploaded a new avatar ({} bytes)", user.username, data.len());
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Reads an uploaded PNG or JPEG image from the request body and returns it
+/// with its mimetype. Images larger than `max_dimension` are resized if
+/// possible. The stored image must not be larger than `max_size` bytes. The
+/// `X-Requested-With` header has to be set. `what` describes the image for
+/// log messages.
+pub(super) async fn read_image(
+    req: Request<Body>,
+    max_dimension: u32,
+    max_size: usize,
+    what: &str,
+    ctx: &Context,
+) -> Result<(Vec<u8>, &'static str), Response> {
+    if !req.headers().contains_key(CSRF_HEADER) {
+        return Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("The 'X-Requested-With' header has to be set".into())
+            .unwrap());
+    }
+
+    let converter = ctx.config.http.thumbnails.converter.as_deref();
+    let max_upload_size = if converter.is_some() { MAX_UPLOAD_SIZE } else { max_size };
+    let mut data = match body::read(req.into_body(), max_upload_size).await {
+        Ok(data) => data,
+        Err(body::ReadError::TooLarge) => return Err(too_large(max_upload_size)),
+        Err(e) => {
+            warn!("Failed to read upload body of {}: {}", what, e);
+            return Err(response::bad_request());
+        }
+    };
+
+    let mimetype = match (image_info(&data), converter) {
+        (Some(ImageInfo { mimetype, width, height }), _)
+            if width <= max_dimension && height <= max_dimension => mimetype,
+        (Some(ImageInfo { mimetype, .. }), Some(converter)) => {
+            let geometry = format!("{max_dimension}x{max_dimension}>");
+            let coder = if mimetype == "image/png" { "png" } else { "jpeg" };
+            data = match thumbnail::resize(data, &geometry, coder, converter, ctx).await {
+                Ok(resized) => resized,
+                Err(e) => {
+                    warn!("Failed to resize {}: {:?}", what, e);
+                    return Err(Response::builder()
+                        .status(StatusCode::UNPROCESSABLE_ENTITY)
+                        .body("Image could not be processed".into())
+                        .unwrap());
+                }
+            };
+            mimetype
+        }
+        (Some(_), None) => {
+            return Err(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Image must be at most {max_dimension}x{max_dimension} pixels").into())
+                .unwrap());
+        }
+        (None, _) => {
+            return Err(Response::builder()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .body("Only PNG and JPEG images are supported".into())
+                .unwrap());
+        }
+    };
+    if data.len() > max_size {
+        return Err(too_large(max_size));
+    }
+
+    Ok((data, mimetype))
+}
+
+fn too_large(max_size: usize) -> Response {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(format!("Image must not be larger than {} KiB", max_size / 1024).into())
+        .unwrap()
+}
+
+/// Handles `GET /~avatar/<username>`.
+pub(super) async fn serve(req: Request<Body>, ctx: &Context) -> Response {
+    let path = req.uri().path();
+    let username = path.strip_prefix(PATH)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|username| percent_encoding::percent_decode_str(username).decode_utf8().ok());
+    let username = match username {
+        Some(username) => username,
+        None => return reply_404(&ctx.assets, req.method(), path).await,
+    };
+
+    let db = match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+        Ok(db) => db,
+        Err(r) => return r,
+    };
+    let row = db.query_opt(
+        "select data, mimetype from user_avatars where username = $1",
+        &[&username],
+    ).await;
+    match row {
+        Ok(Some(row)) => Response::builder()
+            .header("Content-Type", row.get::<_, String>(1))
+            .header("X-Content-Type-Options", "nosniff")
+            .header("Content-Security-Policy", "default-src 'none'")
+            // The URL contains the time of the upload (see `avatar_url`).
+            .header("Cache-Control", "public, max-age=31536000, immutable")
+            .body(Body::from(row.get::<_, Vec<u8>>(0)))
+            .unwrap(),
+        Ok(None) => reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("DB error when loading avatar: {}", e);
+            response::internal_server_error()
+        }
+    }
+}
+
+/// Returns the URL of the uploaded avatar of the given user, if any.
+pub(crate) async fn avatar_url(
+    db: &db::Transaction,
+    username: &str,
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let row = db.query_opt(
+        "select extract(epoch from updated)::bigint from user_avatars where username = $1",
+        &[&username],
+    ).await?;
+
+    Ok(row.map(|row| {
+        let username = percent_encoding::utf8_percent_encode(
+            username,
+            percent_encoding::NON_ALPHANUMERIC,
+        );
+        format!("{PATH}/{username}?v={}", row.get::<_, i64>(0))
+    }))
+}
+
+#[derive(Debug, PartialEq)]
+struct ImageInfo {
+    mimetype: &'static str,
+    width: u32,
+    height: u32,
+}
+
+/// Reads the format and dimensions from the header of a PNG or JPEG image.
+/// Returns `None` for anything else.
+fn image_info(data: &[u8]) -> Option<ImageInfo> {
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    let u16_at = |pos: usize| -> Option<u16> {
+        data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        data.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    if data.starts_with(PNG_SIGNATURE) {
+        // The first chunk is always `IHDR`, starting with width and height.
+        if data.get(12..16)? != b"IHDR" {
+            return None;
+        }
+        return Some(ImageInfo { mimetype: "image/png", width: u32_at(16)?, height: u32_at(20)? });
+    }
+
+    if data.starts_with(&[0xFF, 0xD8]) {
+        // Walk through the segments until we find a "start of frame" one.
+        let mut pos = 2;
+        loop {
+            if *data.get(pos)? != 0xFF {
+                return None;
+            }
+            let marker = *data.get(pos + 1)?;
+            let len = usize::from(u16_at(pos + 2)?);
+            let is_sof = (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker);
+            if is_sof {
+                return Some(ImageInfo {
+                    mimetype: "image/jpeg",
+                    height: u16_at(pos + 5)?.into(),
+                    width: u16_at(pos + 7)?.into(),
+                });
+            }
+            pos += 2 + len;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageInfo, image_info};
+
+    #[test]
+    fn png_info() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&300u32.to_be_bytes());
+        png.extend_from_slice(&200u32.to_be_bytes());
+        assert_eq!(image_info(&png), Some(ImageInfo { mimetype: "image/png", width: 300, height: 200 }));
+        assert_eq!(image_info(&png[..18]), None);
+    }
+
+    #[test]
+    fn jpeg_info() {
+        let jpeg = [
+            0xFF, 0xD8,
+            // APP0 segment with 2 bytes of data
+            0xFF, 0xE0, 0x00, 0x04, 0xAB, 0xCD,
+            // SOF0: precision, height = 100, width = 400
+            0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x64, 0x01, 0x90,
+        ];
+        assert_eq!(image_info(&jpeg), Some(ImageInfo { mimetype: "image/jpeg", width: 400, height: 100 }));
+        assert_eq!(image_info(&jpeg[..10]), None);
+        assert_eq!(image_info(b"<svg></svg>"), None);
+    }
+}
@@ -0,0 +1,40 @@
+//! Reading bodies of requests and of responses from other services into
+//! memory, with an upper bound on their size.
+
+use std::fmt;
+
+use hyper::{Body, body::HttpBody};
+
+
+/// Why `read` failed.
+#[derive(Debug)]
+pub(crate) enum ReadError {
+    /// The body is larger than allowed.
+    TooLarge,
+    /// The body could not be read, e.g. because the connection was closed.
+    Failed(hyper::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge => write!(f, "body is too large"),
+            Self::Failed(e) => write!(f, "failed to read body: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Reads the whole `body`, failing as soon as it exceeds `max_size` bytes.
+pub(crate) async fn read(mut body: Body, max_size: usize) -> Result<Vec<u8>, ReadError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(ReadError::Failed)?;
+        if data.len() + chunk.len() > max_size {
+            return Err(ReadError::TooLarge);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
@@ -0,0 +1,85 @@
+//! Selection of the canonical page of events that are shown in several realms.
+//! The canonical page is linked via `<link rel="canonical">` and listed first
+//! in `Event.hostRealms`.
+
+use deadpool_postgres::Client;
+
+use crate::{db::types::Key, prelude::*, util::HttpHost};
+use super::escape_html;
+
+
+/// Which realm is preferred if an event is shown in several realms and none
+/// or several of the blocks are marked as canonical.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CanonicalRealm {
+    /// Video blocks win over series blocks, which win over creator blocks.
+    MostDirect,
+    /// The realm where the event (or its series) was added first.
+    FirstMount,
+    /// The realm with the shortest path.
+    ShortestPath,
+}
+
+impl CanonicalRealm {
+    /// Returns the `order by` expressions to sort host realms, the first one
+    /// being the canonical one. Refers to `realms` and `hosts` with the
+    /// columns returned by `event_host_realms`.
+    pub(crate) fn order_by(self) -> String {
+        // Number of segments of the realm path, i.e. its depth.
+        let depth = "length(realms.full_path) - length(replace(realms.full_path, '/', ''))";
+        let preferred = match self {
+            Self::MostDirect => format!("hosts.directness, {depth}"),
+            Self::FirstMount => format!("hosts.mounted, hosts.directness, {depth}"),
+            Self::ShortestPath => format!("{depth}, length(realms.full_path), hosts.directness"),
+        };
+
+        // Blocks marked as canonical always win. The path is only compared
+        // to make the order deterministic.
+        format!("hosts.canonical desc, {preferred}, realms.full_path")
+    }
+}
+
+/// Returns the path of the canonical page of the given event: inside its
+/// canonical realm or `/!v/<id>` if it's not shown in any realm.
+pub(super) async fn video_path(key: Key, preference: CanonicalRealm, db: &Client) -> Result<String> {
+    let query = format!("select {}", realm_path_sql("$1", preference));
+    let realm_path = db.query_one(&query, &[&key]).await?.get::<_, Option<String>>(0);
+
+    Ok(video_path_in(realm_path, key))
+}
+
+/// SQL subquery returning the path of the canonical realm of the event with
+/// the ID `event` (an SQL expression), or `null` if it's not shown in any
+/// realm. Allows loading it together with other data.
+pub(crate) fn realm_path_sql(event: &str, preference: CanonicalRealm) -> String {
+    format!(
+        "(select realms.full_path from event_host_realms({event}) as hosts \
+            inner join realms on realms.id = hosts.realm \
+            order by {} \
+            limit 1)",
+        preference.order_by(),
+    )
+}
+
+/// Returns the path of the video page inside the canonical realm returned by
+/// `realm_path_sql`, see `video_path`.
+pub(crate) fn video_path_in(realm_path: Option<String>, key: Key) -> String {
+    let mut buf = [0; 11];
+    let id = key.to_base64(&mut buf);
+    match realm_path {
+        Some(path) => format!("{path}/v/{id}"),
+        None => format!("/!v/{id}"),
+    }
+}
+
+/// Returns the `<link rel="canonical">` tag pointing to the page at `path`.
+/// Crawlers expect an absolute URL, so without `general.site_url`, there is
+/// no link and an empty string is returned.
+pub(super) fn link(path: &str, site_url: Option<&HttpHost>) -> String {
+    let Some(site_url) = site_url else {
+        return String::new();
+    };
+    let url = format!("{site_url}{path}");
+    format!(r#"<link rel="canonical" href="{}">"#, escape_html(&url))
+}
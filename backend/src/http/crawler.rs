@@ -0,0 +1,394 @@
+//! Special responses for search engine crawlers.
+//!
+//! Crawlers are detected by their user agent and get simple static HTML pages
+//! with the content of realm, video and series pages instead of the JS app.
+//! Only public content (readable by `ROLE_ANONYMOUS`) is included. Normal
+//! users are not affected by any of this.
+//!
+//! Translated texts (site title and announcement) are shown in the language
+//! requested with `?lang=` or negotiated via `Accept-Language`, see
+//! `TranslatedString::negotiate`. If they are available in several
+//! languages, the page links to its alternates with `hreflang`.
+
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode, header};
+use tokio_postgres::Row;
+
+use crate::{
+    auth::ROLE_ANONYMOUS,
+    config::TranslatedString,
+    db::{DbConnection, types::Key},
+    prelude::*,
+    util::HttpHost,
+};
+use super::{
+    HttpConfig, Request, Response,
+    canonical::{self, CanonicalRealm},
+    escape_html, percent_encode_path,
+};
+
+
+/// Substrings of user agents (lowercase) that identify crawlers.
+const CRAWLER_USER_AGENTS: &[&str] = &[
+    "googlebot",
+    "bingbot",
+    "yandex",
+    "baiduspider",
+    "duckduckbot",
+    "slurp",
+    "applebot",
+    "facebookexternalhit",
+    "twitterbot",
+    "linkedinbot",
+    "crawler",
+    "spider",
+    "bot/",
+    "+http",
+];
+
+/// Returns `true` if the request was sent by a (well behaved) crawler.
+pub(super) fn is_crawler(req: &Request<Body>) -> bool {
+    let user_agent = match req.headers().get(header::USER_AGENT).and_then(|v| v.to_str().ok()) {
+        Some(user_agent) => user_agent.to_lowercase(),
+        None => return false,
+    };
+
+    CRAWLER_USER_AGENTS.iter().any(|pattern| user_agent.contains(pattern))
+}
+
+/// Contents of `/robots.txt`: internal routes, the paths from
+/// `http.robots_disallow` and all realms marked as `noindex` are disallowed.
+pub(super) async fn robots_txt(config: &HttpConfig, db: &DbConnection) -> Result<Response> {
+    let mut out = String::from("User-agent: *\nDisallow: /~\nDisallow: /graphql\n");
+    for path in config.robots_disallow.iter().flatten() {
+        out += &format!("Disallow: {}\n", robots_path(path));
+    }
+
+    let realms = db.query("select full_path from realms where noindex order by full_path", &[]).await?;
+    for realm in realms {
+        let path: &str = realm.get(0);
+        if path.is_empty() {
+            // The root realm: everything below it is excluded anyway.
+            out += "Disallow: /\n";
+        } else {
+            // `$` anchors the end, so that `/foo` does not exclude `/foobar`.
+            let path = robots_path(path);
+            out += &format!("Disallow: {}$\nDisallow: {}/\n", path, path);
+        }
+    }
+
+    if let Some(delay) = config.crawl_delay {
+        out += &format!("Crawl-delay: {}\n", delay.as_secs().max(1));
+    }
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; charset=UTF-8")
+        .body(out.into())
+        .unwrap())
+}
+
+/// Encodes `path` for use in `robots.txt`, where `*` and `$` have special
+/// meaning.
+fn robots_path(path: &str) -> String {
+    percent_encode_path(path).replace('*', "%2A").replace('$', "%24")
+}
+
+/// Site-wide information shown on every page.
+pub(super) struct Site<'a> {
+    pub(super) title: &'a TranslatedString,
+    pub(super) announcement: Option<&'a TranslatedString>,
+    pub(super) url: Option<&'a HttpHost>,
+    pub(super) canonical_realm: CanonicalRealm,
+}
+
+/// Returns the language requested with the `lang` query parameter, if it's
+/// one of `TranslatedString::LANGUAGES`.
+pub(super) fn requested_language(req: &Request<Body>) -> Option<&'static str> {
+    let lang = req.uri().query()?
+        .split('&')
+        .find_map(|param| param.strip_prefix("lang="))?;
+    TranslatedString::LANGUAGES.iter().copied().find(|l| *l == lang)
+}
+
+/// Serves the static page for `path` in the language `lang`, or a 404 page
+/// if there is nothing to show for crawlers. Video pages link to the
+/// canonical page of the video.
+pub(super) async fn serve(
+    path: &str,
+    lang: &'static str,
+    site: Site<'_>,
+    noindex: bool,
+    db: &DbConnection,
+) -> Result<Response> {
+    let page = if let Some(key) = super::preload::video_key(path) {
+        video_page(key, site.canonical_realm, db).await?
+    } else if let Some(opencast_id) = path.strip_prefix("/!s/:") {
+        series_page(opencast_id, db).await?
+    } else {
+        realm_page(path.trim_end_matches('/'), db).await?
+    };
+
+    let (status, page) = match page {
+        Some(page) => (StatusCode::OK, page),
+        None => (StatusCode::NOT_FOUND, Page {
+            title: "Not found".into(),
+            description: None,
+            canonical: None,
+            body: "<h1>Not found</h1>".into(),
+        }),
+    };
+
+    let html = format!(
+        concat!(
+            "<!DOCTYPE html>",
+            r#"<html lang="{lang}"><head><meta charset="utf-8">"#,
+            "<title>{title} – {site}</title>",
+            "{description}",
+            "{robots}",
+            "{canonical}",
+            "{alternates}",
+            "</head><body>{announcement}{body}</body></html>",
+        ),
+        lang = lang,
+        title = escape_html(&page.title),
+        site = escape_html(site.title.get(lang)),
+        description = page.description
+            .map(|d| format!(r#"<meta name="description" content="{}">"#, escape_html(&d)))
+            .unwrap_or_default(),
+        robots = if noindex { r#"<meta name="robots" content="noindex">"# } else { "" },
+        canonical = page.canonical
+            .map(|path| canonical::link(&path, site.url))
+            .unwrap_or_default(),
+        alternates = alternate_links(path, &site),
+        announcement = site.announcement
+            .map(|a| format!("<aside>{}</aside>", escape_html(a.get(lang))))
+            .unwrap_or_default(),
+        body = page.body,
+    );
+
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=UTF-8")
+        .header(header::CONTENT_LANGUAGE, lang)
+        .header(header::VARY, "User-Agent, Accept-Language")
+        .body(html.into())
+        .unwrap())
+}
+
+/// Returns `<link rel="alternate" hreflang="...">` tags for all languages the
+/// translated texts of the page are available in, plus `x-default` for the
+/// negotiated one. Like canonical links, these need an absolute URL, so
+/// without `general.site_url` or with only one language, an empty string is
+/// returned.
+fn alternate_links(path: &str, site: &Site<'_>) -> String {
+    let Some(site_url) = site.url else {
+        return String::new();
+    };
+    let languages = TranslatedString::LANGUAGES.iter()
+        .copied()
+        .filter(|lang| {
+            site.title.languages().any(|l| l == *lang)
+                || site.announcement.is_some_and(|a| a.languages().any(|l| l == *lang))
+        })
+        .collect::<Vec<_>>();
+    if languages.len() < 2 {
+        return String::new();
+    }
+
+    let link = |hreflang: &str, url: String| {
+        format!(r#"<link rel="alternate" hreflang="{hreflang}" href="{}">"#, escape_html(&url))
+    };
+    let mut out = link("x-default", format!("{site_url}{path}"));
+    for lang in languages {
+        out += &link(lang, format!("{site_url}{path}?lang={lang}"));
+    }
+    out
+}
+
+struct Page {
+    title: String,
+    description: Option<String>,
+    /// Path of the canonical page, if it differs from the current one.
+    canonical: Option<String>,
+    /// Already escaped HTML.
+    body: String,
+}
+
+// Crawlers must not see details of events with a passphrase.
+const EVENT_COLS: &str = "id, title, \
+    case when passphrase_hash is null then description end as description, \
+    case when passphrase_hash is null then thumbnail end as thumbnail, \
+    creators, created";
+
+async fn realm_page(path: &str, db: &DbConnection) -> Result<Option<Page>> {
+    let realm = match db.query_opt("select id, name from realms where full_path = $1", &[&path]).await? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let key: Key = realm.get(0);
+    let name: String = realm.get(1);
+
+    let mut body = String::new();
+    if !name.is_empty() {
+        body += &format!("<h1>{}</h1>", escape_html(&name));
+    }
+
+    let blocks = db.query(
+        "select type::text, text_content, series_id, video_id \
+            from blocks \
+            where realm_id = $1 and (read_roles is null or $2 = any(read_roles)) \
+            order by index",
+        &[&key, &ROLE_ANONYMOUS],
+    ).await?;
+    for block in blocks {
+        match block.get::<_, &str>(0) {
+            "title" => body += &format!("<h2>{}</h2>", escape_html(block.get(1))),
+            "text" => {
+                let text: &str = block.get(1);
+                for paragraph in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+                    body += &format!("<p>{}</p>", escape_html(paragraph));
+                }
+            }
+            "series" => {
+                if let Some(series) = block.get::<_, Option<Key>>(2) {
+                    let events = db.query(
+                        &*format!(
+                            "select {} from events \
+                                where series = $1 and read_roles && $2 \
+                                order by created desc",
+                            EVENT_COLS,
+                        ),
+                        &[&series, &vec![ROLE_ANONYMOUS]],
+                    ).await?;
+                    body += "<ul>";
+                    for event in events {
+                        body += &event_list_item(&event, path);
+                    }
+                    body += "</ul>";
+                }
+            }
+            "video" => {
+                if let Some(event) = block.get::<_, Option<Key>>(3) {
+                    let event = db.query_opt(
+                        &*format!("select {} from events where id = $1 and read_roles && $2", EVENT_COLS),
+                        &[&event, &vec![ROLE_ANONYMOUS]],
+                    ).await?;
+                    if let Some(event) = event {
+                        body += &format!("<ul>{}</ul>", event_list_item(&event, path));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let children = db.query(
+        "select name, full_path from realms \
+            where parent = $1 and not hidden_from_navigation \
+            order by index, name",
+        &[&key],
+    ).await?;
+    if !children.is_empty() {
+        body += "<nav><ul>";
+        for child in children {
+            body += &format!(
+                r#"<li><a href="{}">{}</a></li>"#,
+                escape_html(child.get(1)),
+                escape_html(child.get(0)),
+            );
+        }
+        body += "</ul></nav>";
+    }
+
+    Ok(Some(Page { title: name, description: None, canonical: None, body }))
+}
+
+async fn video_page(
+    key: Key,
+    canonical_realm: CanonicalRealm,
+    db: &DbConnection,
+) -> Result<Option<Page>> {
+    let event = db.query_opt(
+        &*format!("select {} from events where id = $1 and read_roles && $2", EVENT_COLS),
+        &[&key, &vec![ROLE_ANONYMOUS]],
+    ).await?;
+    let event = match event {
+        Some(event) => event,
+        None => return Ok(None),
+    };
+
+    let title: String = event.get(1);
+    let description: Option<String> = event.get(2);
+    let thumbnail: Option<String> = event.get(3);
+    let creators: Vec<String> = event.get(4);
+    let created: DateTime<Utc> = event.get(5);
+
+    let mut body = format!("<h1>{}</h1>", escape_html(&title));
+    if let Some(thumbnail) = &thumbnail {
+        body += &format!(r#"<img src="{}" alt="">"#, escape_html(thumbnail));
+    }
+    body += &format!(
+        r#"<p>{}<time datetime="{}">{}</time></p>"#,
+        if creators.is_empty() {
+            String::new()
+        } else {
+            format!("{} – ", escape_html(&creators.join(", ")))
+        },
+        created.to_rfc3339(),
+        created.format("%Y-%m-%d"),
+    );
+    if let Some(description) = &description {
+        body += &format!("<p>{}</p>", escape_html(description));
+    }
+
+    let canonical = canonical::video_path(key, canonical_realm, db).await?;
+    Ok(Some(Page { title, description, canonical: Some(canonical), body }))
+}
+
+async fn series_page(opencast_id: &str, db: &DbConnection) -> Result<Option<Page>> {
+    let series = db.query_opt(
+        "select id, title, description from series where opencast_id = $1",
+        &[&opencast_id],
+    ).await?;
+    let series = match series {
+        Some(series) => series,
+        None => return Ok(None),
+    };
+    let key: Key = series.get(0);
+    let title: String = series.get(1);
+    let description: Option<String> = series.get(2);
+
+    let mut body = format!("<h1>{}</h1>", escape_html(&title));
+    if let Some(description) = &description {
+        body += &format!("<p>{}</p>", escape_html(description));
+    }
+
+    let events = db.query(
+        &*format!(
+            "select {} from events where series = $1 and read_roles && $2 order by created desc",
+            EVENT_COLS,
+        ),
+        &[&key, &vec![ROLE_ANONYMOUS]],
+    ).await?;
+    body += "<ul>";
+    for event in events {
+        body += &event_list_item(&event, "");
+    }
+    body += "</ul>";
+
+    Ok(Some(Page { title, description, canonical: None, body }))
+}
+
+/// Renders a link to the video page of the event (selected with
+/// `EVENT_COLS`) in the given realm.
+fn event_list_item(event: &Row, realm_path: &str) -> String {
+    let mut buf = [0; 11];
+    let key = event.get::<_, Key>(0);
+    let url = if realm_path.is_empty() {
+        format!("/!v/{}", key.to_base64(&mut buf))
+    } else {
+        format!("{}/v/{}", realm_path, key.to_base64(&mut buf))
+    };
+
+    format!(r#"<li><a href="{}">{}</a></li>"#, escape_html(&url), escape_html(event.get(1)))
+}
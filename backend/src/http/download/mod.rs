@@ -0,0 +1,302 @@
+//! Downloading all videos of a series as one ZIP archive.
+//!
+//! The archive is created on the fly while the video files are fetched from
+//! their servers, so only a few chunks are ever held in memory. As the sizes
+//! of the video files are not known in advance, the response has no
+//! `Content-Length` and range requests are not supported.
+
+use chrono::{DateTime, Utc};
+use hyper::{Body, Client, StatusCode, body::HttpBody};
+use hyper_rustls::HttpsConnectorBuilder;
+
+use crate::{
+    auth::{HasRoles, User},
+    db::{DbConnection, types::{EventTrack, Key}},
+    prelude::*,
+};
+use super::{Context, Request, Response, handlers::reply_404, response};
+use self::zip::ZipWriter;
+
+mod zip;
+
+
+/// Prefix of the route, followed by `<series key>.zip`.
+pub(super) const SERIES_PREFIX: &str = "/~download/series/";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quality {
+    High,
+    Low,
+}
+
+/// A file in the archive.
+struct File {
+    name: String,
+    modified: DateTime<Utc>,
+    uri: String,
+}
+
+/// Handles `GET /~download/series/<key>.zip`. The archive contains one video
+/// file for each event of the series the current user can read. External
+/// events are skipped, as their tracks can point to arbitrary servers, which
+/// Tobira should not fetch on behalf of users. So are events that are
+/// geoblocked for the client (see `geoblocking`). The
+/// `quality` query parameter selects the track with the highest (`high`,
+/// default) or lowest (`low`) resolution.
+pub(super) async fn series(
+    req: Request<Body>,
+    db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let path = req.uri().path();
+    let key = path.strip_prefix(SERIES_PREFIX)
+        .and_then(|rest| rest.strip_suffix(".zip"))
+        .and_then(Key::from_base64);
+    let key = match key {
+        Some(key) => key,
+        None => return reply_404(&ctx.assets, req.method(), path).await,
+    };
+
+    let quality = match req.uri().query().map(quality_from_query) {
+        Some(Some(quality)) => quality,
+        Some(None) => return response::bad_request(),
+        None => Quality::High,
+    };
+
+    let res = async {
+        if !user.can_download(&ctx.config.auth) {
+            return Ok(None);
+        }
+
+        let title = db.query_opt("select title from series where id = $1", &[&key]).await?
+            .map(|row| row.get::<_, String>(0));
+        let rows = db.query(
+            "select title, created, tracks, opencast_id, read_roles, \
+                write_roles && $2 as can_write \
+                from events \
+                where series = $1 and read_roles && $2 and source = 'opencast' \
+                and passphrase_hash is null \
+                order by created",
+            &[&key, &user.roles()],
+        ).await?;
+
+        Ok::<_, tokio_postgres::Error>(Some((title, rows)))
+    }.await;
+
+    let (title, rows) = match res {
+        Ok(Some((Some(title), rows))) => (title, rows),
+        Ok(Some((None, _))) => return reply_404(&ctx.assets, req.method(), path).await,
+        Ok(None) => return response::forbidden(),
+        Err(e) => {
+            error!("DB error when preparing series download: {}", e);
+            return response::internal_server_error();
+        }
+    };
+    // The download potentially takes very long, so we don't want to keep the
+    // connection.
+    drop(db);
+
+    let country = ctx.client_country(&req);
+    let rows = rows.into_iter()
+        .filter(|row| row.get::<_, bool>(5) || ctx.config.geoblocking.allows(
+            row.get(3),
+            &row.get::<_, Vec<String>>(4),
+            country,
+        ))
+        .collect::<Vec<_>>();
+
+    let digits = rows.len().to_string().len();
+    let files = rows.iter()
+        .filter_map(|row| {
+            let tracks = row.get::<_, Vec<EventTrack>>(2);
+            let track = select_track(&tracks, quality)?;
+            Some((row.get::<_, String>(0), row.get(1), track.clone()))
+        })
+        .enumerate()
+        .map(|(i, (title, modified, track))| File {
+            name: file_name(i + 1, digits, &title, &track),
+            modified,
+            uri: track.uri,
+        })
+        .collect::<Vec<_>>();
+
+    let (sender, body) = Body::channel();
+    tokio::spawn(write_archive(files, sender));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/zip")
+        .header(
+            "Content-Disposition",
+            format!(r#"attachment; filename="{}.zip""#, sanitize(&title, true)),
+        )
+        .body(body)
+        .unwrap()
+}
+
+/// Fetches all files and writes the archive to `sender`. Files that cannot be
+/// fetched at all are skipped. If fetching a file fails midway, the response
+/// is aborted, so that the client does not end up with a broken archive
+/// without noticing.
+async fn write_archive(files: Vec<File>, mut sender: hyper::body::Sender) {
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build::<_, Body>(https);
+
+    let mut zip = ZipWriter::new();
+    for file in files {
+        let uri = match file.uri.parse() {
+            Ok(uri) => uri,
+            Err(e) => {
+                warn!("Skipping '{}' in series download: invalid URI: {}", file.uri, e);
+                continue;
+            }
+        };
+        let mut body = match client.get(uri).await {
+            Ok(response) if response.status().is_success() => response.into_body(),
+            Ok(response) => {
+                warn!(
+                    "Skipping '{}' in series download: server replied with {}",
+                    file.uri,
+                    response.status(),
+                );
+                continue;
+            }
+            Err(e) => {
+                warn!("Skipping '{}' in series download: {}", file.uri, e);
+                continue;
+            }
+        };
+
+        if sender.send_data(zip.start_file(&file.name, file.modified).into()).await.is_err() {
+            // The client went away.
+            return;
+        }
+
+        let mut crc = crc32fast::Hasher::new();
+        let mut size = 0;
+        while let Some(chunk) = body.data().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    error!("Failed to fetch '{}' for series download: {}", file.uri, e);
+                    sender.abort();
+                    return;
+                }
+            };
+            crc.update(&chunk);
+            size += chunk.len() as u64;
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+
+        if sender.send_data(zip.finish_file(crc.finalize(), size).into()).await.is_err() {
+            return;
+        }
+    }
+
+    let _ = sender.send_data(zip.finish().into()).await;
+}
+
+fn quality_from_query(query: &str) -> Option<Quality> {
+    let value = form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "quality")
+        .map(|(_, value)| value);
+
+    match value.as_deref() {
+        None | Some("high") => Some(Quality::High),
+        Some("low") => Some(Quality::Low),
+        Some(_) => None,
+    }
+}
+
+/// Selects the track to download. Only video tracks with the same flavor as
+/// the first video track are considered. Events with only audio tracks are
+/// downloaded as audio.
+fn select_track(tracks: &[EventTrack], quality: Quality) -> Option<&EventTrack> {
+    let is_audio = |track: &EventTrack| track.is_audio == Some(true);
+    let has_video = tracks.iter().any(|track| !is_audio(track));
+    let flavor = &tracks.iter().find(|track| is_audio(track) != has_video)?.flavor;
+    let candidates = tracks.iter()
+        .filter(|track| &track.flavor == flavor && is_audio(track) != has_video);
+    let pixels = |track: &&EventTrack| match track.resolution {
+        Some([w, h]) => w as i64 * h as i64,
+        None => 0,
+    };
+
+    match quality {
+        Quality::High => candidates.max_by_key(pixels),
+        Quality::Low => candidates.min_by_key(pixels),
+    }
+}
+
+/// Returns the name of the file in the archive, like `03 - Title.mp4`. The
+/// number keeps the files in order and makes the names unique.
+fn file_name(index: usize, digits: usize, title: &str, track: &EventTrack) -> String {
+    // We prefer the extension from the URL, as the subtype of the mimetype is
+    // often not a suitable extension.
+    let path = track.uri.split(['?', '#']).next().unwrap_or_default();
+    let is_extension = |ext: &&str| !ext.is_empty()
+        && ext.len() <= 5
+        && ext.chars().all(|c| c.is_ascii_alphanumeric());
+    let extension = path.rsplit('/').next()
+        .and_then(|last_segment| last_segment.rsplit_once('.'))
+        .map(|(_, ext)| ext)
+        .filter(is_extension)
+        .or_else(|| track.mimetype.as_deref()?.split('/').nth(1).filter(is_extension));
+
+    let mut out = format!("{:0width$} - {}", index, sanitize(title, false), width = digits);
+    if let Some(extension) = extension {
+        out.push('.');
+        out.push_str(extension);
+    }
+    out
+}
+
+/// Replaces characters that are problematic in file names. If `ascii` is
+/// true, all non-ASCII characters are replaced as well.
+fn sanitize(s: &str, ascii: bool) -> String {
+    s.trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c if ascii && !c.is_ascii() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let track = |uri: &str, mimetype: Option<&str>| EventTrack {
+            uri: uri.into(),
+            flavor: "presenter/preview".into(),
+            mimetype: mimetype.map(Into::into),
+            resolution: None,
+            is_audio: None,
+        };
+
+        assert_eq!(
+            file_name(3, 2, "Intro: what/why?", &track("https://x.test/a/video.mp4?t=1", None)),
+            "03 - Intro_ what_why_.mp4",
+        );
+        assert_eq!(
+            file_name(12, 2, "Übung", &track("https://x.test/stream", Some("video/webm"))),
+            "12 - Übung.webm",
+        );
+        assert_eq!(
+            file_name(1, 1, "x", &track("https://x.test/v.d/file", Some("video/x-matroska"))),
+            "1 - x",
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! A minimal writer for ZIP archives that are streamed: the size and CRC of
+//! each file only have to be known after its data was written. Files are
+//! always stored uncompressed (videos are compressed already) and the Zip64
+//! extensions are always used, so that files and archives can be larger than
+//! 4 GiB.
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+
+/// Version 4.5 of the spec is required for Zip64.
+const VERSION: u16 = 45;
+/// Bit 3: sizes and CRC are in the data descriptor. Bit 11: names are UTF-8.
+const FLAGS: u16 = 1 << 3 | 1 << 11;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// Keeps track of everything needed to write the central directory at the
+/// end. The methods return the bytes that have to be written to the output
+/// around the data of each file.
+pub(super) struct ZipWriter {
+    offset: u64,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    time: u16,
+    date: u16,
+    offset: u64,
+    crc: u32,
+    size: u64,
+}
+
+impl ZipWriter {
+    pub(super) fn new() -> Self {
+        Self { offset: 0, entries: vec![] }
+    }
+
+    /// Returns the header that has to be written before the data of the file
+    /// with the given name.
+    pub(super) fn start_file(&mut self, name: &str, modified: DateTime<Utc>) -> Vec<u8> {
+        let (time, date) = dos_date_time(modified);
+        let entry = Entry { name: name.into(), time, date, offset: self.offset, crc: 0, size: 0 };
+
+        let mut out = Vec::new();
+        put_u32(&mut out, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut out, VERSION);
+        put_u16(&mut out, FLAGS);
+        put_u16(&mut out, 0); // method: stored
+        put_u16(&mut out, entry.time);
+        put_u16(&mut out, entry.date);
+        put_u32(&mut out, 0); // CRC, in data descriptor
+        put_u32(&mut out, u32::MAX); // sizes, in data descriptor
+        put_u32(&mut out, u32::MAX);
+        put_u16(&mut out, entry.name.len() as u16);
+        put_u16(&mut out, 20); // length of extra field
+        out.extend_from_slice(entry.name.as_bytes());
+        put_u16(&mut out, ZIP64_EXTRA_ID);
+        put_u16(&mut out, 16);
+        put_u64(&mut out, 0);
+        put_u64(&mut out, 0);
+
+        self.offset += out.len() as u64;
+        self.entries.push(entry);
+        out
+    }
+
+    /// Returns the data descriptor that has to be written after the data of
+    /// the current file. `size` is the length of that data.
+    pub(super) fn finish_file(&mut self, crc: u32, size: u64) -> Vec<u8> {
+        let entry = self.entries.last_mut().expect("bug: `finish_file` before `start_file`");
+        entry.crc = crc;
+        entry.size = size;
+
+        let mut out = Vec::new();
+        put_u32(&mut out, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut out, crc);
+        put_u64(&mut out, size);
+        put_u64(&mut out, size);
+
+        self.offset += size + out.len() as u64;
+        out
+    }
+
+    /// Returns the central directory and end records that have to be written
+    /// after all files.
+    pub(super) fn finish(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for entry in &self.entries {
+            put_u32(&mut out, CENTRAL_HEADER_SIGNATURE);
+            put_u16(&mut out, 3 << 8 | VERSION); // made by: Unix
+            put_u16(&mut out, VERSION);
+            put_u16(&mut out, FLAGS);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, entry.time);
+            put_u16(&mut out, entry.date);
+            put_u32(&mut out, entry.crc);
+            put_u32(&mut out, u32::MAX); // sizes, in Zip64 extra field
+            put_u32(&mut out, u32::MAX);
+            put_u16(&mut out, entry.name.len() as u16);
+            put_u16(&mut out, 28); // length of extra field
+            put_u16(&mut out, 0); // comment length
+            put_u16(&mut out, 0); // disk number
+            put_u16(&mut out, 0); // internal attributes
+            put_u32(&mut out, 0o100644 << 16); // external attributes: regular file
+            put_u32(&mut out, u32::MAX); // offset, in Zip64 extra field
+            out.extend_from_slice(entry.name.as_bytes());
+            put_u16(&mut out, ZIP64_EXTRA_ID);
+            put_u16(&mut out, 24);
+            put_u64(&mut out, entry.size);
+            put_u64(&mut out, entry.size);
+            put_u64(&mut out, entry.offset);
+        }
+
+        let central_dir_offset = self.offset;
+        let central_dir_size = out.len() as u64;
+        let num_entries = self.entries.len() as u64;
+
+        let zip64_end_offset = central_dir_offset + central_dir_size;
+        put_u32(&mut out, ZIP64_END_SIGNATURE);
+        put_u64(&mut out, 44); // size of the remaining record
+        put_u16(&mut out, 3 << 8 | VERSION);
+        put_u16(&mut out, VERSION);
+        put_u32(&mut out, 0); // disk number
+        put_u32(&mut out, 0); // disk with central directory
+        put_u64(&mut out, num_entries);
+        put_u64(&mut out, num_entries);
+        put_u64(&mut out, central_dir_size);
+        put_u64(&mut out, central_dir_offset);
+
+        put_u32(&mut out, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut out, 0); // disk with Zip64 end record
+        put_u64(&mut out, zip64_end_offset);
+        put_u32(&mut out, 1); // number of disks
+
+        // The classic end record with all values in the Zip64 record.
+        put_u32(&mut out, END_SIGNATURE);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, u16::MAX);
+        put_u16(&mut out, u16::MAX);
+        put_u32(&mut out, u32::MAX);
+        put_u32(&mut out, u32::MAX);
+        put_u16(&mut out, 0); // comment length
+
+        out
+    }
+}
+
+/// Converts the timestamp to the MS-DOS format used by ZIP. That format
+/// cannot represent dates before 1980, those are clamped.
+fn dos_date_time(t: DateTime<Utc>) -> (u16, u16) {
+    if t.year() < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+
+    let time = t.hour() << 11 | t.minute() << 5 | (t.second() / 2);
+    let date = (t.year() as u32 - 1980) << 9 | t.month() << 5 | t.day();
+    (time as u16, date as u16)
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, v: u64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    #[test]
+    fn dos_timestamps() {
+        assert_eq!(
+            dos_date_time(Utc.ymd(2022, 3, 14).and_hms(15, 9, 27)),
+            (15 << 11 | 9 << 5 | 13, 42 << 9 | 3 << 5 | 14),
+        );
+        assert_eq!(dos_date_time(Utc.ymd(1970, 1, 1).and_hms(0, 0, 0)), (0, 1 << 5 | 1));
+    }
+
+    #[test]
+    fn offsets() {
+        let mut zip = ZipWriter::new();
+        let mut out = zip.start_file("a.txt", Utc.ymd(2022, 1, 1).and_hms(0, 0, 0));
+        out.extend_from_slice(b"hello");
+        out.extend(zip.finish_file(crc32fast::hash(b"hello"), 5));
+        let second = out.len();
+        out.extend(zip.start_file("b.txt", Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)));
+        out.extend(zip.finish_file(0, 0));
+        let central_dir = out.len() as u64;
+        out.extend(zip.finish());
+
+        assert_eq!(&out[second..second + 4], &LOCAL_HEADER_SIGNATURE.to_le_bytes());
+
+        // The Zip64 end record points to the central directory.
+        let zip64_end = out.len() - 22 - 20 - 56;
+        assert_eq!(&out[zip64_end..zip64_end + 4], &ZIP64_END_SIGNATURE.to_le_bytes());
+        assert_eq!(&out[zip64_end + 48..zip64_end + 56], &central_dir.to_le_bytes());
+    }
+}
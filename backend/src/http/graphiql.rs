@@ -0,0 +1,237 @@
+//! The interactive GraphQL API explorer served at `/~graphiql`. Access can be
+//! restricted to moderators or admins and the editor is preloaded with
+//! example queries: those configured via `http.graphiql.examples` and ones
+//! generated from the schema.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use hyper::{Body, StatusCode};
+use once_cell::sync::OnceCell;
+
+use crate::{
+    auth::{AuthConfig, HasRoles, User},
+    prelude::*,
+};
+use super::{Context, Request, Response, api_docs, handlers::reply_404, response};
+
+
+/// Path of the route serving GraphiQL.
+pub(super) const PATH: &str = "/~graphiql";
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct GraphiqlConfig {
+    /// Who can use GraphiQL. It does not expose anything that isn't already
+    /// exposed by the API itself, but you might not want to advertise the
+    /// API publicly. Possible values: "everyone", "moderators", "admins" and
+    /// "nobody". The footer link is only shown to users who can use it.
+    #[config(default = "everyone")]
+    pub(crate) access: GraphiqlAccess,
+
+    /// Path to a file with GraphQL queries and fragments that are shown in
+    /// GraphiQL before the examples generated from the schema, e.g. queries
+    /// that are relevant to integrators at your institution. Every query
+    /// needs a name.
+    pub(crate) examples: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum GraphiqlAccess {
+    Everyone,
+    Moderators,
+    Admins,
+    Nobody,
+}
+
+impl GraphiqlAccess {
+    pub(crate) fn allows(self, user: &impl HasRoles, auth_config: &AuthConfig) -> bool {
+        match self {
+            Self::Everyone => true,
+            Self::Moderators => user.is_moderator(auth_config),
+            Self::Admins => user.is_admin(),
+            Self::Nobody => false,
+        }
+    }
+}
+
+/// Serves GraphiQL if the current user is allowed to use it. `user` is only
+/// looked up if the access is restricted to moderators or admins.
+pub(super) async fn serve(req: Request<Body>, user: Option<User>, ctx: &Context) -> Response {
+    let access = ctx.config.http.graphiql.access;
+    match access {
+        GraphiqlAccess::Nobody => return reply_404(&ctx.assets, req.method(), PATH).await,
+        _ if !access.allows(&user, &ctx.config.auth) => return response::forbidden(),
+        _ => {}
+    }
+
+    static HTML: OnceCell<String> = OnceCell::new();
+    let html = HTML.get_or_init(|| {
+        let custom = ctx.config.http.graphiql.examples.as_ref().and_then(|path| {
+            std::fs::read_to_string(path)
+                .map_err(|e| warn!("Failed to read GraphiQL examples '{}': {}", path.display(), e))
+                .ok()
+        });
+        let generated = generate_examples(&ctx.api_root.as_schema_language());
+        let examples = format!("{INTRO}\n{}\n{generated}", custom.unwrap_or_default());
+
+        // Juniper does not allow passing a default query, so we inject it.
+        // The JSON string is safe to embed in a script, except for `</`.
+        let source = juniper::http::graphiql::graphiql_source("/graphql", None);
+        let default_query = serde_json::to_string(&examples).unwrap().replace("</", "<\\/");
+        source.replacen(
+            "React.createElement(GraphiQL, {",
+            &format!("React.createElement(GraphiQL, {{ defaultQuery: {default_query},"),
+            1,
+        )
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html; charset=UTF-8")
+        .body(html.clone().into())
+        .unwrap()
+}
+
+const INTRO: &str = "\
+# Welcome to Tobira's GraphQL API explorer!
+#
+# Below are some example queries. Choose one to run via the play button.
+# All types and fields are documented in the \"Docs\" panel and at /~api-docs.
+";
+
+/// Generates an example query for each argument-less field of `Query`
+/// returning an object, together with a fragment selecting all
+/// argument-less scalar fields of that object.
+fn generate_examples(schema: &str) -> String {
+    let definitions = api_docs::definitions(schema);
+    let leaf_types = definitions.iter()
+        .filter(|def| matches!(def.kind, "scalar" | "enum"))
+        .map(|def| def.name)
+        .chain(["String", "Int", "Float", "Boolean", "ID"])
+        .collect::<HashSet<_>>();
+    let fields_of = |name: &str| definitions.iter()
+        .find(|def| def.name == name && matches!(def.kind, "type" | "interface"))
+        .map(|def| simple_fields(&def.source));
+
+    let mut queries = String::new();
+    let mut fragments = String::new();
+    let mut fragment_types = HashSet::new();
+    for (field, ty) in fields_of("Query").unwrap_or_default() {
+        let Some(fields) = fields_of(ty) else { continue };
+        let leaf_fields = fields.iter()
+            .filter(|(_, ty)| leaf_types.contains(ty))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if leaf_fields.is_empty() {
+            continue;
+        }
+
+        let mut chars = field.chars();
+        let op_name = chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars).collect());
+        queries += &format!("query {op_name} {{\n  {field} {{ ...{ty}Fields }}\n}}\n\n");
+        if fragment_types.insert(ty) {
+            fragments += &format!("fragment {ty}Fields on {ty} {{\n");
+            for name in leaf_fields {
+                fragments += &format!("  {name}\n");
+            }
+            fragments += "}\n\n";
+        }
+    }
+
+    queries.push_str(&fragments);
+    queries
+}
+
+/// Returns the name and the named type (without list and non-null
+/// modifiers) of all fields without arguments in the given definition.
+fn simple_fields(source: &str) -> Vec<(&str, &str)> {
+    let mut out = Vec::new();
+    let mut in_description = false;
+    let mut in_arguments = false;
+    for line in source.lines() {
+        // Fields are indented by exactly two spaces. Everything indented
+        // more belongs to descriptions or arguments.
+        let Some(line) = line.strip_prefix("  ").filter(|l| !l.starts_with(' ')) else {
+            continue;
+        };
+        if line == "\"\"\"" {
+            in_description = !in_description;
+            continue;
+        }
+        if in_arguments {
+            in_arguments = !line.starts_with(')');
+            continue;
+        }
+        if in_description || line.starts_with('"') {
+            continue;
+        }
+        if line.contains('(') {
+            in_arguments = !line.contains(')');
+            continue;
+        }
+        if let Some((name, ty)) = line.split_once(':') {
+            let ty = ty.split('@').next().unwrap_or_default();
+            out.push((name.trim(), ty.trim().trim_matches(|c| matches!(c, '[' | ']' | '!'))));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_examples, simple_fields};
+
+    #[test]
+    fn fields() {
+        let source = concat!(
+            "type Realm {\n",
+            "  \"The ID.\"\n",
+            "  id: ID!\n",
+            "  \"\"\"\n",
+            "    Multi line\n",
+            "    description: not a field\n",
+            "  \"\"\"\n",
+            "  children: [Realm!]!\n",
+            "  blocks(first: Int): [Block!]!\n",
+            "  search(\n",
+            "    \"Query\"\n",
+            "    query: String!\n",
+            "  ): [Event!]!\n",
+            "  name: String @deprecated\n",
+            "}\n",
+        );
+        assert_eq!(simple_fields(source), [("id", "ID"), ("children", "Realm"), ("name", "String")]);
+    }
+
+    #[test]
+    fn examples() {
+        let schema = concat!(
+            "type Query {\n",
+            "  rootRealm: Realm!\n",
+            "  realm(id: ID!): Realm\n",
+            "  version: String!\n",
+            "}\n",
+            "\n",
+            "type Realm {\n",
+            "  id: ID!\n",
+            "  order: RealmOrder!\n",
+            "  children: [Realm!]!\n",
+            "}\n",
+            "\n",
+            "enum RealmOrder {\n",
+            "  BY_INDEX\n",
+            "}\n",
+        );
+        assert_eq!(generate_examples(schema), concat!(
+            "query RootRealm {\n",
+            "  rootRealm { ...RealmFields }\n",
+            "}\n",
+            "\n",
+            "fragment RealmFields on Realm {\n",
+            "  id\n",
+            "  order\n",
+            "}\n",
+            "\n",
+        ));
+    }
+}
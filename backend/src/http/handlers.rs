@@ -1,12 +1,24 @@
-use hyper::{Body, Method, StatusCode};
+use hyper::{Body, Method, StatusCode, header::{self, HeaderValue}};
 use std::{
-    mem,
+    collections::HashMap,
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use crate::{api, auth::{self, User}, db::{self, Transaction}, prelude::*};
-use super::{Context, Request, Response, assets::Assets, response};
+use crate::{
+    api::{self, cache::{CacheTag, RequestCache}, tracing::Tracer},
+    auth::{self, AuthMode, Permissions, User},
+    config::{Overrides, TranslatedString},
+    db::{self, DbConnection},
+    extension,
+    metrics,
+    prelude::*,
+};
+use super::{
+    Context, Request, Response, assets::Assets, avatar, crawler, download, error_report,
+    graphiql::{self, GraphiqlAccess}, placeholder, playback, public_event, rate_limit,
+    realm_icon, realm_info::RealmInfo, realm_views, response, startup, stats, thumbnail, upload,
+};
 
 
 /// This is the main HTTP entry point, called for each incoming request.
@@ -22,14 +34,59 @@ pub(super) async fn handle(req: Request<Body>, ctx: Arc<Context>) -> Response {
 
     const ASSET_PREFIX: &str = "/~assets/";
 
+    // Looks up the user of the request (see `request_user`) and evaluates the
+    // handler with the DB connection and the user, or replies with an error.
+    macro_rules! with_user {
+        (|$db:pat_param, $user:ident| $handler:expr) => {
+            match request_user(&req, &ctx).await {
+                Ok(($db, $user)) => $handler,
+                Err(r) => r,
+            }
+        };
+    }
+
     match path {
         // Paths for which POST requests are allowed
-        "/graphql" if method == Method::POST
-            => handle_api(req, &ctx).await.unwrap_or_else(|r| r),
+        "/graphql" if method == Method::POST => with_user!(|db, user| {
+            handle_api(req, db, user, &ctx).await.unwrap_or_else(|r| r)
+        }),
         "/~session" if method == Method::POST
             => auth::handle_login(req, &ctx).await.unwrap_or_else(|r| r),
         "/~session" if method == Method::DELETE
             => auth::handle_logout(req, &ctx).await,
+        "/~login" if method == Method::POST && ctx.config.auth.mode == AuthMode::Opencast
+            => auth::handle_opencast_login(req, &ctx).await.unwrap_or_else(|r| r),
+        "/~deprovision" if method == Method::POST && ctx.config.auth.deprovision.secret.is_some()
+            => auth::handle_deprovision(req, &ctx).await,
+        avatar::PATH if method == Method::POST && ctx.config.auth.avatar_upload
+            => with_user!(|db, user| avatar::upload(req, db, user, &ctx).await),
+        upload::PATH if method == Method::POST && ctx.config.upload.virus_scan.is_enabled()
+            => with_user!(|_, user| upload::upload_track(req, user, &ctx).await),
+        path if path.starts_with(realm_icon::PATH) && method == Method::POST
+            => with_user!(|db, user| realm_icon::upload(req, db, user, &ctx).await),
+        path if path.starts_with(playback::PREFIX)
+            && method == Method::POST
+            && ctx.config.http.playback_stats
+            => with_user!(|db, user| playback::record(req, db, user, &ctx).await),
+        path if path.starts_with(realm_views::PREFIX)
+            && method == Method::POST
+            && ctx.config.http.playback_stats
+            => match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+                Ok(db) => realm_views::record(req, db, &ctx).await,
+                Err(r) => r,
+            },
+        error_report::PATH if method == Method::POST && ctx.config.http.error_reports.enabled
+            => match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+                Ok(db) => error_report::receive(req, db, &ctx).await,
+                Err(r) => r,
+            },
+        path if path.starts_with(extension::PATH_PREFIX) => {
+            let path = path.to_owned();
+            match extension::handle_http(req, ctx.clone()).await {
+                Some(response) => response,
+                None => reply_404(&ctx.assets, &method, &path).await,
+            }
+        }
 
         // From this point on, we only support GET and HEAD requests. All others
         // will result in 404.
@@ -59,15 +116,64 @@ pub(super) async fn handle(req: Request<Body>, ctx: Arc<Context>) -> Response {
                 .unwrap()
         }
 
+        startup::READY_PATH => startup::ready(),
+
+        "/robots.txt" => {
+            let db = match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+                Ok(db) => db,
+                Err(response) => return response,
+            };
+            crawler::robots_txt(&ctx.config.http, &db).await.unwrap_or_else(|e| {
+                error!("Failed to generate robots.txt: {}", e);
+                response::internal_server_error()
+            })
+        }
+
+        path if path.starts_with(placeholder::THUMBNAIL_PREFIX)
+            => with_user!(|db, user| placeholder::serve_thumbnail(req, db, user, &ctx).await),
+
         // The interactive GraphQL API explorer/IDE. We actually keep this in
         // production as it does not hurt and in particular: does not expose any
         // information that isn't already exposed by the API itself.
-        "/~graphiql" => juniper_hyper::graphiql("/graphql", None).await,
+        // The user is only relevant if access is restricted to some users.
+        graphiql::PATH => match ctx.config.http.graphiql.access {
+            GraphiqlAccess::Everyone | GraphiqlAccess::Nobody
+                => graphiql::serve(req, None, &ctx).await,
+            GraphiqlAccess::Moderators | GraphiqlAccess::Admins
+                => with_user!(|_, user| graphiql::serve(req, user, &ctx).await),
+        },
+
+        path if path.starts_with(download::SERIES_PREFIX) && ctx.config.http.series_download
+            => with_user!(|db, user| download::series(req, db, user, &ctx).await),
+
+        path if path.starts_with(stats::PREFIX) && ctx.config.http.playback_stats
+            => with_user!(|db, user| stats::serve(req, db, user, &ctx).await),
+
+        path if path.starts_with(public_event::PREFIX) && ctx.config.http.public_event_json => {
+            match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+                Ok(db) => public_event::serve(req, db, &ctx).await,
+                Err(response) => response,
+            }
+        }
+
+        super::api_docs::PATH if ctx.config.http.api_docs => super::api_docs::serve(&ctx),
+
+        metrics::PATH if ctx.config.http.metrics => metrics::serve(&ctx).await,
+
+        path if path.strip_prefix(avatar::PATH).is_some_and(|rest| rest.starts_with('/'))
+            && ctx.config.auth.avatar_upload
+            => avatar::serve(req, &ctx).await,
+
+        path if path.strip_prefix(realm_icon::PATH).is_some_and(|rest| rest.starts_with('/'))
+            => realm_icon::serve(req, &ctx).await,
+
+        path if path.starts_with(thumbnail::PREFIX) && ctx.config.http.thumbnails.enabled()
+            => with_user!(|db, user| thumbnail::serve(req, db, user, &ctx).await),
 
         // Listing all potential routes here is duplication of routing logic and not really
         // all that useful. So for now at least, we just assume all non-asset requests
         // to `/~*` are fine.
-        path if path.starts_with("/~") => ctx.assets.serve_index().await,
+        path if path.starts_with("/~") => serve_index(&req, &ctx).await,
 
 
         // Currently we just reply with our `index.html` to everything else.
@@ -83,8 +189,129 @@ pub(super) async fn handle(req: Request<Body>, ctx: Arc<Context>) -> Response {
         // duplicate logic. So yeah:
         //
         // TODO: fix that at some point ^
-        _ => ctx.assets.serve_index().await,
+        _ => {
+            if ctx.config.http.legacy_redirects {
+                if let Some(response) = super::legacy_urls::redirect(&req, &ctx).await {
+                    return response;
+                }
+            }
+
+            if !ctx.config.http.crawler_pages {
+                return serve_index(&req, &ctx).await;
+            }
+            if crawler::is_crawler(&req) {
+                return serve_crawler_page(&req, &ctx).await;
+            }
+
+            // The response depends on the user agent, which caches have to
+            // know.
+            let mut response = serve_index(&req, &ctx).await;
+            response.headers_mut().insert(header::VARY, HeaderValue::from_static("User-Agent"));
+            response
+        }
+    }
+}
+
+/// Serves the main `index.html` with the settings overridden in the DB
+/// applied, resource hints for the requested page added and the language set
+/// to the locale of the realm the page belongs to. Pages of `noindex` realms
+/// also get the corresponding meta tag.
+async fn serve_index(req: &Request<Body>, ctx: &Context) -> Response {
+    let path = req.uri().path();
+
+    // Failing to load any of these is not critical: the values from the config
+    // file are used then and the page just loads a bit slower.
+    let mut personalized = false;
+    let (overrides, hints, locale) = match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+        Ok(db) => {
+            let overrides = load_overrides(ctx, &db).await;
+
+            // Only video pages have hints, which depend on the user. So we
+            // only look up the user for those.
+            let mut hints = String::new();
+            if let Some(key) = super::preload::video_key(path) {
+                let user = User::new(req.headers(), &ctx.config, &db)
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("DB error when checking user session: {}", e);
+                        None
+                    });
+                let realm_path = super::preload::video_realm_path(path);
+                hints = super::preload::hints(key, realm_path, &user, &ctx.config, &db)
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Failed to determine resource hints: {}", e);
+                        String::new()
+                    });
+                personalized = user.is_some() && !hints.is_empty();
+            }
+
+            let realm_info = RealmInfo::load_cached(ctx, &db).await;
+            if realm_info.is_noindex(path) {
+                hints += r#"<meta name="robots" content="noindex">"#;
+            }
+            let locale = realm_info.locale(path).map(ToOwned::to_owned);
+            (overrides, hints, locale)
+        }
+        Err(_) => (Arc::new(Overrides::default()), String::new(), None),
+    };
+
+    let mut response = ctx.assets.serve_index(&overrides, &hints, locale.as_deref(), &ctx.config)
+        .await;
+
+    // The hints might reveal URLs of videos only this user can see.
+    if personalized {
+        response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
     }
+    response
+}
+
+/// Loads the settings overridden in the DB. They are needed for every page,
+/// but rarely change, so they are cached. Failing to load them is not
+/// critical: the values from the config file are used then.
+async fn load_overrides(ctx: &Context, db: &DbConnection) -> Arc<Overrides> {
+    let cache = RequestCache::new(ctx.resolver_cache.clone());
+    let load = async { Ok(Arc::new(Overrides::load(db).await?)) };
+    cache
+        .get_or_load(CacheTag::Settings, "overrides".into(), Duration::from_secs(3600), load)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to load settings from DB: {}", e.msg);
+            Arc::new(Overrides::default())
+        })
+}
+
+/// Serves the static page for crawlers in the language requested via `?lang=`
+/// or, failing that, the one negotiated from the `Accept-Language` header
+/// with the locale of the realm as fallback.
+async fn serve_crawler_page(req: &Request<Body>, ctx: &Context) -> Response {
+    let db = match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+        Ok(db) => db,
+        Err(response) => return response,
+    };
+
+    let path = req.uri().path();
+    let general = &ctx.config.general;
+    let overrides = load_overrides(ctx, &db).await;
+    let realm_info = RealmInfo::load_cached(ctx, &db).await;
+    let lang = crawler::requested_language(req).unwrap_or_else(|| {
+        let accept_language = req.headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok());
+        TranslatedString::negotiate(accept_language, realm_info.locale(path))
+    });
+    let site = crawler::Site {
+        title: &general.site_title,
+        announcement: overrides.announcement().or(general.announcement.as_ref()),
+        url: general.site_url.as_ref(),
+        canonical_realm: ctx.config.http.canonical_realm,
+    };
+    crawler::serve(path, lang, site, realm_info.is_noindex(path), &db)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to render page for crawler: {}", e);
+            response::internal_server_error()
+        })
 }
 
 /// Replies with a 404 Not Found.
@@ -107,21 +334,50 @@ pub(super) async fn reply_404(assets: &Assets, method: &Method, path: &str) -> R
         .unwrap()
 }
 
-/// Handles a request to `/graphql`.
-async fn handle_api(req: Request<Body>, ctx: &Context) -> Result<Response, Response> {
-    let before = Instant::now();
-
-    // Get a connection for this request.
-    let mut connection = db::get_conn_or_service_unavailable(&ctx.db_pool).await?;
-
-    // Get user session
-    let user = match User::new(req.headers(), &ctx.config.auth, &connection).await {
-        Ok(user) => user,
+/// Gets a DB connection for the request and obtains the current user (from
+/// auth headers or the session) with it. This is done once per request by
+/// `handle` and both are passed to the handlers that need them.
+async fn request_user(
+    req: &Request<Body>,
+    ctx: &Context,
+) -> Result<(DbConnection, Option<User>), Response> {
+    let db = db::get_conn_or_service_unavailable(&ctx.db_pool).await?;
+    match User::new(req.headers(), &ctx.config, &db).await {
+        Ok(user) => Ok((db, user)),
         Err(e) => {
             error!("DB error when checking user session: {}", e);
-            return Err(response::internal_server_error());
-        },
-    };
+            Err(response::internal_server_error())
+        }
+    }
+}
+
+/// If a request to `/graphql` has this header with the value `true`, the
+/// transaction is rolled back instead of committed, so mutations only report
+/// what they would change: their results as usual and the number of rows that
+/// would be inserted, updated and deleted per table (including changes by
+/// triggers) in `extensions.dryRun.changes`.
+const DRY_RUN_HEADER: &str = "x-tobira-dry-run";
+
+/// If a request to `/graphql` by an admin has this header with the value
+/// `true`, the response contains the timings of the root fields and all SQL
+/// queries in `extensions.tracing` (see `api::tracing`). Ignored for other
+/// users, as the queries reveal internals.
+const TRACE_HEADER: &str = "x-tobira-trace";
+
+/// Handles a request to `/graphql`.
+async fn handle_api(
+    req: Request<Body>,
+    mut connection: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Result<Response, Response> {
+    let before = Instant::now();
+    let dry_run = req.headers().get(DRY_RUN_HEADER).is_some_and(|value| value == "true");
+    let permissions = Permissions::new(&user, &ctx.config.auth);
+    let client_ip = rate_limit::client_ip(&req, &ctx.config.http);
+    let client_country = ctx.client_country(&req);
+    let trace = req.headers().get(TRACE_HEADER).is_some_and(|value| value == "true")
+        && permissions.require_admin().is_some();
 
     let tx = match connection.transaction().await {
         Ok(tx) => tx,
@@ -131,88 +387,84 @@ async fn handle_api(req: Request<Body>, ctx: &Context) -> Result<Response, Respo
         }
     };
 
-    // Okay, lets take a deep breath.
-    //
-    // Unfortunately, `juniper` does not support contexts with a lifetime
-    // parameter. However, we'd like to have one SQL transaction per API
-    // request. The transaction type (`deadpool_postgres::Transaction`) borrows
-    // from the DB connection (`tokio_postgres::Client`) and thus has a
-    // lifetime parameter. This makes sense for the API of that library since
-    // it statically prevents a number of logic bugs. But it is inconvenient
-    // for us.
-    //
-    // Unfortunately, we think the best solution for us is to use `unsafe` here
-    // to just get rid of the lifetime parameter. We can pretend that the
-    // lifetime is `'static`. Of course, we then have to make sure that the
-    // transaction does not outlive the borrowed connection. We do that by
-    // putting the transaction into an `Arc`. That way we can check whether
-    // there still exists a reference after calling the API handlers. The
-    // transaction is not `Clone` and `Arc` only gives an immutable reference
-    // to the underlying value. So even a buggy handler could not move the
-    // transaction out of the `Arc`.
-    //
-    // Unfortunately, `connection` is not treated as borrowed after this unsafe
-    // block. So we must make sure not to access it at all until we get rid of
-    // the transaction (by committing it below).
-    type PgTx<'a> = deadpool_postgres::Transaction<'a>;
-    let tx = unsafe {
-        let static_tx = mem::transmute::<PgTx<'_>, PgTx<'static>>(tx);
-        Arc::new(static_tx)
-    };
-
-    let api_context = Arc::new(api::Context {
-        db: Transaction::new(tx.clone()),
+    // Explaining slow queries is only a debugging aid and is never done in
+    // release builds.
+    let explain_threshold = ctx.config.db.explain_slow_queries
+        .filter(|_| cfg!(debug_assertions));
+    let make_context = |db: db::Transaction| api::Context {
+        db: if trace { db.trace_queries() } else { db },
+        db_pool: ctx.db_pool.clone(),
+        permissions,
         user,
         config: ctx.config.clone(),
         jwt: ctx.jwt.clone(),
         search: ctx.search.clone(),
-    });
-    let out = juniper_hyper::graphql(ctx.api_root.clone(), api_context.clone(), req).await;
-
-    // Get some values out of the context before dropping it
-    let num_queries = api_context.db.num_queries();
-    let has_errored = api_context.db.has_errored();
-    let username = auth::debug_log_username(&api_context.user);
-    drop(api_context);
-
-    // Check whether we own the last remaining handle of this Arc.
-    let out = match Arc::try_unwrap(tx) {
-        Err(_) => {
-            // There are still other handles, meaning that the API handler
-            // incorrectly stored the transaction in some static variable. This
-            // is our fault and should NEVER happen. If it does happen, we
-            // would have UB after this function exits. We can't have that. And
-            // since panicking only brings down the current thread, we have to
-            // reach for more drastic measures.
-            error!("FATAL BUG: API handler kept reference to transaction. Ending process.");
-            std::process::abort();
+        cache: RequestCache::new(ctx.resolver_cache.clone()),
+        extensions: ctx.extensions.clone(),
+        tracer: trace.then(Tracer::new),
+        client_ip,
+        client_country,
+    };
+    let (outputs, tx) = api::with_transaction(
+        tx,
+        explain_threshold,
+        make_context,
+        |api_context| async move {
+            let before = if dry_run { Some(row_counts(&api_context.db).await) } else { None };
+            let out = juniper_hyper::graphql(ctx.api_root.clone(), api_context.clone(), req).await;
+            let changes = match before {
+                Some(before) => Some(changes_since(before, &api_context.db).await),
+                None => None,
+            };
+
+            // Get some values out of the context before dropping it
+            let num_queries = api_context.db.num_queries();
+            let has_errored = api_context.db.has_errored();
+            let query_plans = api_context.db.take_query_plans();
+            let tracing = api_context.tracer.as_ref()
+                .map(|tracer| tracer.finish(api_context.db.take_query_traces()));
+            let username = auth::debug_log_username(&api_context.user);
+            (out, changes, num_queries, has_errored, query_plans, tracing, username)
+        },
+    ).await;
+    let (out, changes, num_queries, has_errored, query_plans, tracing, username) = outputs;
+
+    if has_errored {
+        error!("Error has occured during API DB transaction. Rolling back transaction...");
+        if let Err(e) = tx.rollback().await {
+            error!("Failed to rollback transaction: {e}\nWill give up now. Transaction \
+                should be rolled back automatically since it won't be committed.");
+
         }
-        Ok(tx) => {
-            if has_errored {
-                error!("Error has occured during API DB transaction. Rolling back transaction...");
-                if let Err(e) = tx.rollback().await {
-                    error!("Failed to rollback transaction: {e}\nWill give up now. Transaction \
-                        should be rolled back automatically since it won't be committed.");
 
-                }
+        return Ok(response::internal_server_error());
+    }
 
-                return Ok(response::internal_server_error());
-            }
+    let mut extensions = vec![];
+    if !query_plans.is_empty() {
+        extensions.push(("queryPlans", serde_json::json!(query_plans)));
+    }
+    if let Some(changes) = changes {
+        extensions.push(("dryRun", serde_json::json!({ "changes": changes })));
+    }
+    if let Some(tracing) = tracing {
+        extensions.push(("tracing", tracing));
+    }
 
-            match tx.commit().await {
-                // If the transaction succeeded we can return the generated response.
-                Ok(_) => Ok(out),
-
-                // Otherwise, we would like to retry a couple times, but for now
-                // we just immediately reply 5xx.
-                //
-                // TODO: write `graphql_hyper` logic ourselves to be able to put
-                // all of this code in a loop and retry a couple times.
-                Err(e) => {
-                    error!("Failed to commit transaction for API request: {}", e);
-                    Err(response::service_unavailable())
-                }
-            }
+    let result = if dry_run { tx.rollback().await } else { tx.commit().await };
+    let out = match result {
+        // If the transaction succeeded we can return the generated response.
+        Ok(_) if extensions.is_empty() => Ok(out),
+        Ok(_) => Ok(attach_extensions(out, extensions).await),
+
+        // Otherwise, we would like to retry a couple times, but for now
+        // we just immediately reply 5xx.
+        //
+        // TODO: write `graphql_hyper` logic ourselves to be able to put
+        // all of this code in a loop and retry a couple times.
+        Err(e) => {
+            error!("Failed to end transaction for API request: {}", e);
+            Err(response::service_unavailable())
         }
     };
 
@@ -226,3 +478,71 @@ async fn handle_api(req: Request<Body>, ctx: &Context) -> Result<Response, Respo
     out
 }
 
+/// Number of rows inserted, updated and deleted per table.
+type RowCounts = HashMap<String, [i64; 3]>;
+
+/// Returns the number of rows inserted, updated and deleted per table by this
+/// connection that the statistics collector does not know yet. That includes
+/// the current transaction, but possibly also previous ones. Errors are
+/// logged and result in no counts, as they are only informative.
+async fn row_counts(db: &db::Transaction) -> RowCounts {
+    let query = "select relname, n_tup_ins, n_tup_upd, n_tup_del \
+        from pg_stat_xact_user_tables \
+        where n_tup_ins + n_tup_upd + n_tup_del > 0";
+    let counts = db.query_mapped(query, dbargs![], |row| {
+        (row.get::<_, String>(0), [row.get(1), row.get(2), row.get(3)])
+    }).await;
+
+    match counts {
+        Ok(counts) => counts.into_iter().collect(),
+        Err(e) => {
+            error!("Failed to load row counts for dry run: {}", e);
+            RowCounts::new()
+        }
+    }
+}
+
+/// Returns the number of rows inserted, updated and deleted per table since
+/// `before` was loaded with `row_counts`, as JSON object.
+async fn changes_since(before: RowCounts, db: &db::Transaction) -> serde_json::Value {
+    let mut changes = row_counts(db).await.into_iter()
+        .map(|(table, counts)| {
+            let prev = before.get(&table).copied().unwrap_or_default();
+            (table, [0, 1, 2].map(|i| counts[i] - prev[i]))
+        })
+        .filter(|(_, diff)| diff.iter().any(|n| *n != 0))
+        .collect::<Vec<_>>();
+    changes.sort();
+
+    let changes = changes.into_iter().map(|(table, [inserted, updated, deleted])| {
+        (table, serde_json::json!({ "inserted": inserted, "updated": updated, "deleted": deleted }))
+    });
+    serde_json::Value::Object(changes.collect())
+}
+
+/// Adds the given fields to the `extensions` of the GraphQL response.
+async fn attach_extensions(
+    response: Response,
+    extensions: Vec<(&str, serde_json::Value)>,
+) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read GraphQL response body: {}", e);
+            return response::internal_server_error();
+        }
+    };
+
+    let mut json = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(json @ serde_json::Value::Object(_)) => json,
+        _ => return Response::from_parts(parts, body.into()),
+    };
+    for (key, value) in extensions {
+        json["extensions"][key] = value;
+    }
+
+    let body = json.to_string();
+    parts.headers.insert("content-length", body.len().into());
+    Response::from_parts(parts, body.into())
+}
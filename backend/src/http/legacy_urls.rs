@@ -0,0 +1,130 @@
+//! Redirecting URLs of a previous video portal to Tobira pages. See the
+//! `legacy_urls` table and the `import-legacy-urls` command. Links to the
+//! players of Opencast are redirected to the event with that Opencast ID or
+//! legacy ID (see `sync.legacy_ids`) without an explicit mapping.
+
+use hyper::{Body, StatusCode};
+
+use crate::{db::{self, DbConnection, types::Key}, prelude::*};
+use super::{Context, Request, Response, percent_encode_path};
+
+
+/// Looks up the path of the given request in the legacy URL table. If it is
+/// found, the hit is recorded and a permanent redirect to the corresponding
+/// Tobira page is returned. Otherwise, Opencast player URLs are redirected
+/// to the corresponding event.
+pub(super) async fn redirect(req: &Request<Body>, ctx: &Context) -> Option<Response> {
+    let path = req.uri().path_and_query()?.as_str();
+    let db = db::get_conn_or_service_unavailable(&ctx.db_pool).await.ok()?;
+
+    let location = match lookup(path, &db).await {
+        Ok(Some(location)) => location,
+        Ok(None) => {
+            let id = opencast_player_id(req.uri().path(), req.uri().query())?;
+            match lookup_event(id, &db).await {
+                Ok(location) => location?,
+                Err(e) => {
+                    error!("DB error when looking up event of Opencast player URL: {}", e);
+                    return None;
+                }
+            }
+        }
+        Err(e) => {
+            error!("DB error when looking up legacy URL: {}", e);
+            return None;
+        }
+    };
+
+    debug!("Redirecting legacy URL '{}' to '{}'", path, location);
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header("Location", location)
+        .body(Body::empty())
+        .unwrap()
+        .pipe(Some)
+}
+
+/// Returns the location of the Tobira page `path` is mapped to in the legacy
+/// URL table, and records the hit.
+async fn lookup(path: &str, db: &DbConnection) -> Result<Option<String>, tokio_postgres::Error> {
+
+    let sql = "update legacy_urls \
+        set hits = hits + 1, last_hit = now() \
+        where path = $1 \
+        returning event, (select full_path from realms where id = legacy_urls.realm)";
+    let statement = db.prepare_cached(sql).await?;
+    let Some(row) = db.query_opt(&statement, &[&path]).await? else {
+        return Ok(None);
+    };
+
+    let location = match row.get::<_, Option<Key>>(0) {
+        Some(event) => video_path(event),
+        None => {
+            let full_path = row.get::<_, String>(1);
+            if full_path.is_empty() { "/".into() } else { percent_encode_path(&full_path) }
+        }
+    };
+    Ok(Some(location))
+}
+
+/// Returns the location of the page of the event with the given Opencast ID
+/// or legacy ID.
+async fn lookup_event(id: &str, db: &DbConnection) -> Result<Option<String>, tokio_postgres::Error> {
+    let sql = "select id from events where opencast_id = $1 \
+        union all select event from legacy_event_ids where legacy_id = $1 \
+        limit 1";
+    let statement = db.prepare_cached(sql).await?;
+    let row = db.query_opt(&statement, &[&id]).await?;
+    Ok(row.map(|row| video_path(row.get(0))))
+}
+
+fn video_path(event: Key) -> String {
+    let mut buf = [0; 11];
+    format!("/!v/{}", event.to_base64(&mut buf))
+}
+
+/// Returns the media package ID in URLs of the players of Opencast:
+/// `/play/<id>`, and `?id=<id>` for the Paella, Theodul and engage players.
+fn opencast_player_id<'a>(path: &'a str, query: Option<&'a str>) -> Option<&'a str> {
+    const PLAYER_PATHS: &[&str] = &[
+        "/paella/ui/watch.html",
+        "/paella7/ui/watch.html",
+        "/engage/theodul/ui/core.html",
+        "/engage/ui/watch.html",
+    ];
+
+    let id = if let Some(id) = path.strip_prefix("/play/") {
+        id.trim_end_matches('/')
+    } else if PLAYER_PATHS.contains(&path) {
+        query?.split('&').find_map(|param| param.strip_prefix("id="))?
+    } else {
+        return None;
+    };
+
+    let valid = !id.is_empty() && id.len() <= 128
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+    valid.then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::opencast_player_id;
+
+    #[test]
+    fn player_ids() {
+        let id = "8a4b8a9e-7c1f-4a6e-9d3c-5b2f1e0d9c8b";
+        assert_eq!(opencast_player_id(&format!("/play/{id}"), None), Some(id));
+        assert_eq!(opencast_player_id(&format!("/play/{id}/"), None), Some(id));
+        assert_eq!(opencast_player_id("/paella/ui/watch.html", Some(&format!("id={id}"))), Some(id));
+        assert_eq!(
+            opencast_player_id("/engage/theodul/ui/core.html", Some(&format!("mode=embed&id={id}"))),
+            Some(id),
+        );
+        assert_eq!(opencast_player_id("/play/", None), None);
+        assert_eq!(opencast_player_id("/play/a/b", None), None);
+        assert_eq!(opencast_player_id("/paella/ui/watch.html", None), None);
+        assert_eq!(opencast_player_id("/paella/ui/watch.html", Some("id=%27")), None);
+        assert_eq!(opencast_player_id("/lectures/play", Some(&format!("id={id}"))), None);
+    }
+}
+
@@ -5,10 +5,15 @@
 
 use deadpool_postgres::Pool;
 use hyper::{
-    Body, Server,
+    Body, Client, Server,
+    client::HttpConnector,
+    server::conn::AddrStream,
     service::{make_service_fn, service_fn},
 };
-use hyperlocal::UnixServerExt;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyperlocal::{SocketIncoming, UnixServerExt};
+use serde::Deserialize;
+use socket2::{Domain, Socket, Type};
 use std::{
     convert::Infallible,
     fs,
@@ -16,20 +21,53 @@ use std::{
     net::{IpAddr, SocketAddr},
     os::unix::fs::PermissionsExt,
     panic::AssertUnwindSafe,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use crate::{api, auth::JwtContext, config::Config, prelude::*, search};
+use crate::{
+    api::{self, cache::{self, ResolverCache}},
+    auth::JwtContext,
+    config::Config,
+    extension::Extensions,
+    geoblock::{Country, GeoIpDb},
+    prelude::*,
+    search,
+    systemd::{self, ListenSocket},
+};
 use self::{
     assets::Assets,
     handlers::handle,
+    rate_limit::PeerAddr,
 };
 
 
+mod api_docs;
 mod assets;
+pub(crate) mod avatar;
+pub(crate) mod body;
+pub(crate) mod canonical;
+mod crawler;
+pub(crate) mod error_report;
+mod download;
+pub(crate) mod graphiql;
 mod handlers;
+mod legacy_urls;
+pub(crate) mod placeholder;
+pub(crate) mod playback;
+mod preload;
+mod public_event;
+pub(crate) mod rate_limit;
+pub(crate) mod realm_icon;
+mod realm_info;
+mod realm_views;
 pub(crate) mod response;
+pub(crate) mod startup;
+mod stats;
+pub(crate) mod thumbnail;
+pub(crate) mod upload;
+mod warmup;
 
 
 /// HTTP server configuration.
@@ -43,6 +81,16 @@ pub(crate) struct HttpConfig {
     #[config(default = "127.0.0.1")]
     pub(crate) address: IpAddr,
 
+    /// List of addresses to listen on, each with its own options. Use this
+    /// instead of `address` and `port` to listen on multiple addresses or
+    /// interfaces. If set, `address` and `port` are ignored. `ipv6_only`
+    /// controls whether a socket with an IPv6 address also accepts IPv4
+    /// connections. It is only allowed for IPv6 addresses and defaults to
+    /// the system setting (usually `false` on Linux). For explicit dual-stack:
+    ///
+    ///     listen = [{ address = "[::]:3080", ipv6_only = true }, { address = "0.0.0.0:3080" }]
+    pub(crate) listen: Option<Vec<ListenAddress>>,
+
     /// Unix domain socket to listen on. Specifying this will overwrite
     /// the TCP configuration. Example: "/tmp/tobira.socket".
     pub(crate) unix_socket: Option<PathBuf>,
@@ -50,6 +98,163 @@ pub(crate) struct HttpConfig {
     /// Unix domain socket file permissions.
     #[config(default = 0o755)]
     pub(crate) unix_socket_permissions: u32,
+
+    /// Header that your reverse proxy sets to the IP address of the client,
+    /// e.g. "X-Real-IP" or "X-Forwarded-For". It is used to rate limit
+    /// logins and playback reports per client. If not set, the address of
+    /// the peer of the connection is used, which is the reverse proxy if
+    /// there is one. With `unix_socket`, there is no such address, so
+    /// without this header, there is no rate limit per client at all.
+    pub(crate) client_ip_header: Option<String>,
+
+    /// Whether to redirect URLs of a previous video portal to Tobira pages.
+    /// The mapping is imported with the `import-legacy-urls` command. Links
+    /// to the Opencast players (e.g. `/play/<id>`) are redirected to the
+    /// event with that Opencast ID or legacy ID (see `sync.legacy_ids`). If
+    /// enabled, every request that would otherwise serve the main page
+    /// performs one or two additional DB lookups.
+    #[config(default = false)]
+    pub(crate) legacy_redirects: bool,
+
+    /// Whether to serve a rendered documentation of the GraphQL API at
+    /// `/~api-docs`. It is generated from the API schema and does not expose
+    /// anything that isn't already exposed by the API itself.
+    #[config(default = true)]
+    pub(crate) api_docs: bool,
+
+    /// Whether all videos of a series can be downloaded as one ZIP archive at
+    /// `/~download/series/<id>.zip`. Only users with `auth.download_role` can
+    /// do that and only videos they can see are included. The archive is
+    /// created on the fly, with Tobira fetching all video files.
+    #[config(default = false)]
+    pub(crate) series_download: bool,
+
+    /// Whether to serve public metadata of events (title, duration,
+    /// thumbnail and page URL) as JSON at `/~public/event/<id>.json`, so
+    /// that other websites can show teasers of Tobira videos. Only events
+    /// readable by `ROLE_ANONYMOUS` are served. The responses can be read by
+    /// any origin (CORS) and cached for 10 minutes.
+    #[config(default = false)]
+    pub(crate) public_event_json: bool,
+
+    /// Whether to warm up caches on startup, before Tobira accepts requests
+    /// and reports being ready to systemd: the home page, the navigation of
+    /// all realms (up to 1000) and the pages of the 20 series shown in most
+    /// realms are loaded once. Makes the first requests after a restart
+    /// faster, at the cost of a slower startup.
+    #[config(default = false)]
+    pub(crate) cache_warmup: bool,
+
+    /// Whether to serve simple static HTML pages (without JS) to search
+    /// engine crawlers, detected by their user agent. These pages only
+    /// contain public content. This improves how Tobira pages appear in
+    /// search results. Translated texts are shown in the language requested
+    /// via `Accept-Language` or `?lang=`, with `hreflang` alternates if
+    /// `general.site_url` is set.
+    #[config(default = true)]
+    pub(crate) crawler_pages: bool,
+
+    /// If set, `/robots.txt` asks crawlers to wait this long between
+    /// requests (`Crawl-delay`). Not all crawlers respect that.
+    #[config(deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) crawl_delay: Option<Duration>,
+
+    /// Additional paths that `/robots.txt` asks crawlers not to visit, e.g.
+    /// `["/intern"]`. Realms can also be excluded individually in the realm
+    /// settings.
+    pub(crate) robots_disallow: Option<Vec<String>>,
+
+    /// Whether to serve counters of authentication related events (failed
+    /// logins, invalid session cookies, ...) and the lag of the search index
+    /// in the Prometheus text format at `/~metrics`. Access to it should be
+    /// restricted in your reverse proxy. The events are logged with target
+    /// `tobira::audit` either way.
+    #[config(default = false)]
+    pub(crate) metrics: bool,
+
+    /// Whether to collect anonymized playback statistics: the player reports
+    /// which parts of a video were watched and users with write access to a
+    /// video can see how often each part was played. Only counters per part
+    /// of a video and per day are stored, nothing about the viewers. Owners of
+    /// series and realm moderators can export them as CSV at
+    /// `/~stats/series/<id>.csv` and `/~stats/realm/<id>.csv`. Views of
+    /// realm pages are counted per day as well and realm moderators can
+    /// query them via the API (`Realm.views`).
+    #[config(default = false)]
+    pub(crate) playback_stats: bool,
+
+    /// Which realm's page is the canonical one for videos shown in several
+    /// realms. It is linked via `<link rel="canonical">` so that search
+    /// engines only list it once. "most-direct": realms with a video block
+    /// for it win over ones with a series block, which win over ones with a
+    /// creator block. "first-mount": the realm where it was shown first.
+    /// "shortest-path": the realm closest to the root. Blocks can be marked
+    /// as canonical in the page editor, which overrides this.
+    #[config(default = "most-direct")]
+    pub(crate) canonical_realm: canonical::CanonicalRealm,
+
+    #[config(nested)]
+    pub(crate) error_reports: error_report::ErrorReportConfig,
+
+    #[config(nested)]
+    pub(crate) thumbnails: thumbnail::ThumbnailConfig,
+
+    #[config(nested)]
+    pub(crate) graphiql: graphiql::GraphiqlConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ListenAddress {
+    address: SocketAddr,
+    ipv6_only: Option<bool>,
+}
+
+impl HttpConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(listen) = &self.listen {
+            if listen.is_empty() {
+                bail!("`http.listen` must not be empty (remove it to use `http.address`)");
+            }
+            for addr in listen {
+                if addr.ipv6_only.is_some() && !addr.address.is_ipv6() {
+                    bail!("`ipv6_only` is set for IPv4 address '{}' in `http.listen`", addr.address);
+                }
+            }
+        }
+        self.error_reports.validate()?;
+        self.thumbnails.validate()?;
+
+        Ok(())
+    }
+
+    /// Returns all TCP addresses the server should listen on.
+    fn listen_addresses(&self) -> Vec<ListenAddress> {
+        match &self.listen {
+            Some(listen) => listen.clone(),
+            None => vec![ListenAddress {
+                address: SocketAddr::new(self.address, self.port),
+                ipv6_only: None,
+            }],
+        }
+    }
+}
+
+impl ListenAddress {
+    fn bind(&self) -> Result<std::net::TcpListener> {
+        let socket = Socket::new(Domain::for_address(self.address), Type::STREAM, None)?;
+        if let Some(ipv6_only) = self.ipv6_only {
+            socket.set_only_v6(ipv6_only)?;
+        }
+
+        // The same as what `std` and thus `Server::bind` does.
+        socket.set_reuse_address(true)?;
+        socket.bind(&self.address.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(socket.into())
+    }
 }
 
 
@@ -57,6 +262,28 @@ pub(crate) struct HttpConfig {
 pub(crate) type Response<T = Body> = hyper::Response<T>;
 pub(crate) type Request<T = Body> = hyper::Request<T>;
 
+/// Percent-encodes all bytes of the given path that are not allowed to appear
+/// verbatim in a `Location` header or `robots.txt` (e.g. non-ASCII characters of realm paths).
+fn percent_encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_graphic() {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Escapes text to be used in HTML, both as content and attribute value.
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 
 /// Context that the request handler has access to.
 pub(crate) struct Context {
@@ -66,8 +293,28 @@ pub(crate) struct Context {
     pub(crate) config: Arc<Config>,
     pub(crate) jwt: Arc<JwtContext>,
     pub(crate) search: Arc<search::Client>,
+    pub(crate) resolver_cache: Arc<ResolverCache>,
+    pub(crate) extensions: Arc<Extensions>,
+
+    /// Client for requests to other servers (e.g. fetching thumbnails),
+    /// shared so that connections are reused.
+    pub(crate) http_client: HttpClient,
+
+    /// Only loaded if geoblocking is enabled.
+    pub(crate) geoip: Option<GeoIpDb>,
+}
+
+impl Context {
+    /// Returns the country of the client that sent `req` if geoblocking is
+    /// enabled and the country is known.
+    pub(crate) fn client_country(&self, req: &Request<Body>) -> Option<Country> {
+        let ip = rate_limit::client_ip(req, &self.config.http)?;
+        self.geoip.as_ref()?.lookup(ip)
+    }
 }
 
+pub(crate) type HttpClient = Client<HttpsConnector<HttpConnector>, Body>;
+
 
 /// Starts the HTTP server. The future returned by this function must be awaited
 /// to actually run it.
@@ -76,9 +323,13 @@ pub(crate) async fn serve(
     api_root: api::RootNode,
     db: Pool,
     search: search::Client,
+    startup_server: Option<startup::StartupServer>,
 ) -> Result<()> {
     let assets = Assets::init(&config).await.context("failed to initialize assets")?;
     let http_config = config.http.clone();
+    let resolver_cache = Arc::new(ResolverCache::new());
+    let extensions = Extensions::load(&config).context("failed to load extensions")?;
+    let geoip = GeoIpDb::load(&config.geoblocking)?;
     let ctx = Arc::new(Context {
         api_root: Arc::new(api_root),
         db_pool: db,
@@ -86,8 +337,28 @@ pub(crate) async fn serve(
         jwt: Arc::new(JwtContext::new(&config.auth.jwt)?),
         config: Arc::new(config),
         search: Arc::new(search),
+        resolver_cache: resolver_cache.clone(),
+        extensions: Arc::new(extensions),
+        http_client: Client::builder().build(
+            HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .build(),
+        ),
+        geoip,
+    });
+
+    let ctx_for_listener = Arc::clone(&ctx);
+    tokio::spawn(async move {
+        cache::invalidation_listener(resolver_cache, &ctx_for_listener.config.db).await;
     });
 
+    if ctx.config.http.cache_warmup {
+        startup::set_status("warming up caches");
+        warmup::run(&ctx).await;
+    }
+
     // This sets up all the hyper server stuff. It's a bit of magic and touching
     // this code likely results in strange lifetime errors.
     //
@@ -106,38 +377,97 @@ pub(crate) async fn serve(
     // different factories. One for binding to a unix socket and one for
     // binding to a TCP socket. The code for defining the factory is exactly
     // the same, but due to type inference, it results in a different type. The
-    // macro avoids code duplication.
+    // macro avoids code duplication. It takes the context as argument since
+    // one factory is created per TCP address.
+    //
+    // For TCP connections, the address of the peer is added to each request
+    // (see `rate_limit::client_ip`).
     macro_rules! factory {
-        () => {
+        ($ctx:ident) => {
             make_service_fn(move |_| {
-                let ctx = Arc::clone(&ctx);
+                let ctx = Arc::clone(&$ctx);
                 async {
                     Ok::<_, Infallible>(service_fn(move |req| {
                         handle_internal_errors(handle(req, Arc::clone(&ctx)))
                     }))
                 }
             })
-        }
+        };
+        ($ctx:ident, tcp) => {
+            make_service_fn(move |conn: &AddrStream| {
+                let ctx = Arc::clone(&$ctx);
+                let peer = PeerAddr(conn.remote_addr().ip());
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                        req.extensions_mut().insert(peer);
+                        handle_internal_errors(handle(req, Arc::clone(&ctx)))
+                    }))
+                }
+            })
+        };
     }
 
 
     // Start the server with our service.
-    if let Some(unix_socket) = &http_config.unix_socket {
+    if let Some(startup_server) = startup_server {
+        startup_server.stop().await;
+    }
+    let activated_sockets = systemd::listen_sockets()
+        .context("failed to use sockets passed by systemd")?;
+    if !activated_sockets.is_empty() {
+        // Use the sockets passed by systemd (socket activation). The socket
+        // configuration is ignored in this case.
+        let mut servers = Vec::new();
+        for socket in activated_sockets {
+            let ctx = Arc::clone(&ctx);
+            let server = match socket {
+                ListenSocket::Tcp(listener) => {
+                    let server = Server::from_tcp(listener)?.serve(factory!(ctx, tcp));
+                    info!("Listening on http://{} (passed by systemd)", server.local_addr());
+                    server.boxed()
+                }
+                ListenSocket::Unix(listener) => {
+                    let addr = listener.local_addr()?;
+                    let path = addr.as_pathname().unwrap_or_else(|| Path::new("<unnamed>"));
+                    info!("Listening on unix://{} (passed by systemd)", path.display());
+                    let listener = tokio::net::UnixListener::from_std(listener)?;
+                    Server::builder(SocketIncoming::from_listener(listener))
+                        .serve(factory!(ctx))
+                        .boxed()
+                }
+            };
+            servers.push(server);
+        }
+        systemd::notify_ready();
+        futures::future::try_join_all(servers).await?;
+    } else if let Some(unix_socket) = &http_config.unix_socket {
         // Bind to Unix domain socket.
         if unix_socket.exists() {
             fs::remove_file(unix_socket)?;
         }
-        let server = Server::bind_unix(&unix_socket)?.serve(factory!());
+        let server = Server::bind_unix(&unix_socket)?.serve(factory!(ctx));
         info!("Listening on unix://{}", unix_socket.display());
+        if http_config.client_ip_header.is_none() {
+            warn!("'http.client_ip_header' is not set, so client addresses are unknown \
+                on a Unix socket and requests are not rate limited per client");
+        }
         let permissions = fs::Permissions::from_mode(http_config.unix_socket_permissions);
         fs::set_permissions(unix_socket, permissions)?;
+        systemd::notify_ready();
         server.await?;
     } else {
-        // Bind to TCP socket.
-        let addr = SocketAddr::new(http_config.address, http_config.port);
-        let server = Server::bind(&addr).serve(factory!());
-        info!("Listening on http://{}", server.local_addr());
-        server.await?;
+        // Bind to all TCP sockets.
+        let mut servers = Vec::new();
+        for addr in http_config.listen_addresses() {
+            let listener = addr.bind()
+                .with_context(|| format!("failed to bind to {}", addr.address))?;
+            let ctx = Arc::clone(&ctx);
+            let server = Server::from_tcp(listener)?.serve(factory!(ctx, tcp));
+            info!("Listening on http://{}", server.local_addr());
+            servers.push(server);
+        }
+        systemd::notify_ready();
+        futures::future::try_join_all(servers).await?;
     }
 
     Ok(())
@@ -0,0 +1,173 @@
+//! Generated placeholder images, used for events without a thumbnail.
+
+use hyper::{Body, StatusCode};
+
+use crate::{
+    auth::{HasRoles, User},
+    db::{DbConnection, types::Key},
+    prelude::*,
+};
+use super::{Context, Request, Response, handlers::reply_404, response};
+
+
+/// Prefix of the route serving placeholder thumbnails, followed by the key
+/// of the event.
+pub(super) const THUMBNAIL_PREFIX: &str = "/~placeholder/thumbnail/";
+
+/// Returns the URL of the placeholder thumbnail of the event `event`.
+pub(crate) fn thumbnail_url(event: Key) -> String {
+    let mut buf = [0; 11];
+    format!("{THUMBNAIL_PREFIX}{}", event.to_base64(&mut buf))
+}
+
+/// Handles `GET /~placeholder/thumbnail/<event key>`: an SVG image with a
+/// background color derived from the title of the event and the title
+/// written on it. Like `Event.title`, only for users who can read the event.
+pub(super) async fn serve_thumbnail(
+    req: Request<Body>,
+    db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let path = req.uri().path();
+    let Some(key) = path.strip_prefix(THUMBNAIL_PREFIX).and_then(Key::from_base64) else {
+        return reply_404(&ctx.assets, req.method(), path).await;
+    };
+
+    let res = db.query_opt(
+        "select title from events where id = $1 and read_roles && $2",
+        &[&key, &user.roles()],
+    ).await;
+    let title = match res {
+        Ok(Some(row)) => row.get::<_, String>(0),
+        Ok(None) => return reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("DB error when loading title for placeholder thumbnail: {}", e);
+            return response::internal_server_error();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "image/svg+xml")
+        // Titles rarely change and the image depends on the user's roles.
+        .header("Cache-Control", "private, max-age=86400")
+        .body(thumbnail_svg(&title).into())
+        .unwrap()
+}
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 360;
+const FONT_SIZE: u32 = 36;
+const MAX_LINES: usize = 4;
+const MAX_LINE_LEN: usize = 26;
+
+fn thumbnail_svg(title: &str) -> String {
+    let lines = wrap(title);
+    let first_y = HEIGHT as f32 / 2.0
+        - (lines.len() as f32 - 1.0) / 2.0 * FONT_SIZE as f32 * 1.2;
+    let text = lines.iter()
+        .enumerate()
+        .map(|(i, line)| format!(
+            r#"<text x="50%" y="{:.1}">{}</text>"#,
+            first_y + i as f32 * FONT_SIZE as f32 * 1.2,
+            escape_xml(line),
+        ))
+        .collect::<String>();
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {w} {h}" width="{w}" height="{h}">"#,
+            r#"<rect width="100%" height="100%" fill="hsl({hue}, 35%, 40%)"/>"#,
+            r#"<g fill="white" font-family="sans-serif" font-size="{size}" "#,
+            r#"text-anchor="middle" dominant-baseline="middle">{text}</g>"#,
+            "</svg>",
+        ),
+        w = WIDTH,
+        h = HEIGHT,
+        hue = hue_for(title),
+        size = FONT_SIZE,
+        text = text,
+    )
+}
+
+/// Derives a stable hue (0..360) from the given string (FNV-1a hash).
+fn hue_for(s: &str) -> u32 {
+    let hash = s.bytes().fold(0x811c9dc5u32, |hash, b| (hash ^ b as u32).wrapping_mul(0x01000193));
+    hash % 360
+}
+
+/// Splits the title into lines of roughly `MAX_LINE_LEN` characters at word
+/// boundaries. If the title does not fit into `MAX_LINES` lines, it is
+/// truncated with an ellipsis.
+fn wrap(title: &str) -> Vec<String> {
+    let mut lines = Vec::<String>::new();
+    let mut truncated = false;
+    for word in title.split_whitespace() {
+        let fits_in_last_line = match lines.last() {
+            Some(line) => line.chars().count() + 1 + word.chars().count() <= MAX_LINE_LEN,
+            None => false,
+        };
+        if fits_in_last_line {
+            let line = lines.last_mut().unwrap();
+            line.push(' ');
+            line.push_str(word);
+        } else if lines.len() == MAX_LINES {
+            truncated = true;
+            break;
+        } else {
+            lines.push(word.chars().take(MAX_LINE_LEN).collect());
+        }
+    }
+
+    if truncated {
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+
+    lines
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_title() {
+        assert_eq!(wrap(""), Vec::<String>::new());
+        assert_eq!(wrap("  Intro to  Rust "), vec!["Intro to Rust"]);
+        assert_eq!(
+            wrap("Lecture 12: Advanced topics in linear algebra and numerical methods"),
+            vec!["Lecture 12: Advanced", "topics in linear algebra", "and numerical methods"],
+        );
+        assert_eq!(
+            wrap("a b c d e f g h i j k l m n o p q r s t u v w x y z a b c d e f g h i j k l \
+                m n o p q r s t u v w x y z a b c d e f g"),
+            vec![
+                "a b c d e f g h i j k l m",
+                "n o p q r s t u v w x y z",
+                "a b c d e f g h i j k l m",
+                "n o p q r s t u v w x y z…",
+            ],
+        );
+    }
+
+    #[test]
+    fn url() {
+        assert_eq!(thumbnail_url(Key(1)), "/~placeholder/thumbnail/AAAAAAAAAAB");
+    }
+
+    #[test]
+    fn svg_escaping() {
+        let svg = thumbnail_svg("Tom & Jerry: <3 100%");
+        assert!(svg.contains(">Tom &amp; Jerry: &lt;3 100%<"));
+    }
+}
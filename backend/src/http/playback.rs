@@ -0,0 +1,197 @@
+//! Anonymized playback statistics (if `http.playback_stats` is enabled).
+//!
+//! While a video is played, the frontend records which ranges were watched
+//! and sends them to `POST /~playback/<event key>` when the page is left or
+//! hidden. Each event is divided into `BUCKETS` equally sized parts and we
+//! only count how often each part was played. For trimmed events, only the
+//! played part is divided. Nothing about the user is stored, not even in the
+//! logs.
+//!
+//! Reports don't require a login, so they are rate limited per client IP
+//! (only kept in memory) to make inflating the statistics harder.
+
+use std::{net::IpAddr, time::Duration};
+
+use hyper::{Body, Method, StatusCode};
+use once_cell::sync::Lazy;
+
+use crate::{
+    auth::{HasRoles, User},
+    db::{DbConnection, types::Key},
+    prelude::*,
+};
+use super::{
+    Context, Request, Response, body, handlers::reply_404, response,
+    rate_limit::{self, RateLimiter},
+};
+
+
+/// Prefix of the route, followed by the key of the event.
+pub(super) const PREFIX: &str = "/~playback/";
+
+/// Number of parts each event is divided into.
+pub(crate) const BUCKETS: i32 = 100;
+
+/// Maximum size of the request body in bytes.
+const MAX_BODY_SIZE: usize = 8 * 1024;
+
+/// Maximum number of ranges per request. Additional ones are ignored.
+const MAX_RANGES: usize = 200;
+
+/// Reports per client IP and event. The frontend sends one whenever the page
+/// is hidden, so watching a video while switching tabs sends several.
+static REPORTS_PER_EVENT: Lazy<RateLimiter<(IpAddr, Key)>> =
+    Lazy::new(|| RateLimiter::new(20, Duration::from_secs(60 * 60)));
+
+/// Reports per client IP for all events.
+static REPORTS_PER_IP: Lazy<RateLimiter<IpAddr>> =
+    Lazy::new(|| RateLimiter::new(300, Duration::from_secs(60 * 60)));
+
+/// Handles `POST /~playback/<event key>`. The body is form encoded and
+/// contains any number of `range=<start>-<end>` pairs, with both values in
+/// milliseconds of the media time. Replies 204 even if nothing was stored
+/// (e.g. due to the rate limit), as browsers ignore the response to beacons
+/// anyway.
+pub(super) async fn record(
+    req: Request<Body>,
+    db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let path = req.uri().path().to_owned();
+    let key = match path.strip_prefix(PREFIX).and_then(Key::from_base64) {
+        Some(key) => key,
+        None => return reply_404(&ctx.assets, &Method::POST, &path).await,
+    };
+
+    let roles = user.roles().to_owned();
+    let client_ip = rate_limit::client_ip(&req, &ctx.config.http);
+
+    let Ok(data) = body::read(req.into_body(), MAX_BODY_SIZE).await else {
+        return response::bad_request();
+    };
+    let ranges = form_urlencoded::parse(&data)
+        .filter(|(key, _)| key == "range")
+        .take(MAX_RANGES)
+        .map(|(_, value)| parse_range(&value))
+        .collect::<Option<Vec<_>>>();
+    let ranges = match ranges {
+        Some(ranges) if !ranges.is_empty() => ranges,
+        _ => return response::bad_request(),
+    };
+
+    // Only events the user can see are counted.
+    let res = db.query_opt(
+        "select coalesce(trim_start, 0), coalesce(trim_end, duration) \
+            from events where id = $1 and read_roles && $2",
+        &[&key, &roles],
+    ).await;
+    let played = match res {
+        Ok(Some(row)) => (row.get::<_, i32>(0), row.get::<_, i32>(1)),
+        Ok(None) => return reply_404(&ctx.assets, &Method::POST, &path).await,
+        Err(e) => {
+            error!("DB error when loading event for playback stats: {}", e);
+            return response::internal_server_error();
+        }
+    };
+
+    let limited = client_ip.is_some_and(|ip| {
+        let per_event = REPORTS_PER_EVENT.hit((ip, key));
+        let per_ip = REPORTS_PER_IP.hit(ip);
+        !(per_event && per_ip)
+    });
+    let buckets = buckets(&ranges, played);
+    if !buckets.is_empty() && !limited {
+        let res = db.execute(
+            "insert into playback_stats (event, bucket, views) \
+                select $1, bucket, count(*) from unnest($2::int[]) as bucket group by bucket \
+                on conflict (event, bucket) do update \
+                set views = playback_stats.views + excluded.views",
+            &[&key, &buckets],
+        ).await;
+        if let Err(e) = res {
+            error!("DB error when storing playback stats: {}", e);
+            return response::internal_server_error();
+        }
+
+        let started = i64::from(buckets.contains(&0));
+        let completed = i64::from(buckets.contains(&(BUCKETS - 1)));
+        let res = db.execute(
+            "insert into playback_stats_daily (event, day, reports, starts, completions) \
+                values ($1, current_date, 1, $2, $3) \
+                on conflict (event, day) do update set \
+                    reports = playback_stats_daily.reports + 1, \
+                    starts = playback_stats_daily.starts + excluded.starts, \
+                    completions = playback_stats_daily.completions + excluded.completions",
+            &[&key, &started, &completed],
+        ).await;
+        if let Err(e) = res {
+            error!("DB error when storing daily playback stats: {}", e);
+            return response::internal_server_error();
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Parses `<start>-<end>` (in milliseconds).
+fn parse_range(s: &str) -> Option<(i32, i32)> {
+    let (start, end) = s.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+/// Returns the buckets covered by the given ranges, with `played` being the
+/// start and end of the played part of the media (i.e. without trimmed
+/// parts). A bucket is returned once per range covering it, so rewatched
+/// parts are counted multiple times. Ranges outside of the played part are
+/// clamped and empty ones are ignored.
+fn buckets(ranges: &[(i32, i32)], (played_start, played_end): (i32, i32)) -> Vec<i32> {
+    let duration = played_end - played_start;
+    if duration <= 0 {
+        return vec![];
+    }
+
+    let bucket_of = |ms: i32| (i64::from(ms) * i64::from(BUCKETS) / i64::from(duration)) as i32;
+    let relative = |ms: i32| (ms.saturating_sub(played_start)).clamp(0, duration);
+    ranges.iter()
+        .map(|&(start, end)| (relative(start), relative(end)))
+        .filter(|(start, end)| start < end)
+        .flat_map(|(start, end)| bucket_of(start)..=bucket_of(end - 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{buckets, parse_range};
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("0-1500"), Some((0, 1500)));
+        assert_eq!(parse_range("1500"), None);
+        assert_eq!(parse_range("-5-10"), None);
+        assert_eq!(parse_range("a-10"), None);
+    }
+
+    #[test]
+    fn covered_buckets() {
+        let played = (0, 100_000);
+        assert_eq!(buckets(&[(0, 1000)], played), vec![0]);
+        assert_eq!(buckets(&[(500, 2500)], played), vec![0, 1, 2]);
+        assert_eq!(buckets(&[(98_500, 200_000)], played), vec![98, 99]);
+        assert_eq!(buckets(&[(0, 1000), (0, 1000)], played), vec![0, 0]);
+        assert_eq!(buckets(&[(3000, 3000), (5000, 4000)], played), Vec::<i32>::new());
+        assert_eq!(buckets(&[(0, 1000)], (0, 0)), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn trimmed_buckets() {
+        let played = (10_000, 20_000);
+        assert_eq!(buckets(&[(10_000, 10_100)], played), vec![0]);
+        assert_eq!(buckets(&[(0, 10_250)], played), vec![0, 1, 2]);
+        assert_eq!(buckets(&[(19_900, 30_000)], played), vec![99]);
+        assert_eq!(buckets(&[(0, 5000), (25_000, 30_000)], played), Vec::<i32>::new());
+    }
+}
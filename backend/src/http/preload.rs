@@ -0,0 +1,152 @@
+//! Resource hints (`<link rel="preload">` and friends) that are added to the
+//! `index.html` served for video pages. Without them, the browser only learns
+//! about the thumbnail and video files after the JS bundle was loaded and the
+//! API request finished. With them, it can fetch those in parallel. The link
+//! to the canonical page of the video is added here as well.
+//!
+//! This costs one DB query for each video page served (and none for other
+//! pages), plus looking up the user, which might require a query for the
+//! session. Hints are only added for videos the user can read, so pages with
+//! hints must not be cached in shared caches.
+
+use deadpool_postgres::Client;
+use hyper::Uri;
+
+use crate::{
+    api,
+    auth::{HasRoles, User},
+    config::Config,
+    db::types::{EventTrack, Key},
+    prelude::*,
+};
+use super::{canonical, escape_html, thumbnail};
+
+
+/// Returns the resource hints for the video page with the given path, or an
+/// empty string if there are none. Only pages of videos `user` can read have
+/// hints, as hints reveal URLs of the video. That includes videos the user
+/// can watch as member of the realm of the page (see `video_realm_path`).
+/// Events with a passphrase have none and for events restricted by
+/// `geoblocking.rules`, the video is not hinted.
+pub(super) async fn hints(
+    key: Key,
+    realm_path: Option<&str>,
+    user: &Option<User>,
+    config: &Config,
+    db: &Client,
+) -> Result<String> {
+    let statement = db.prepare_cached(&format!(
+        "select thumbnail, tracks, {}, opencast_id, read_roles from events \
+            where id = $1 and passphrase_hash is null \
+            and (read_roles && $2 or exists( \
+                select from realms where full_path = $3 and {} \
+            ))",
+        canonical::realm_path_sql("events.id", config.http.canonical_realm),
+        api::member_access_sql("$1", "realms.id", "$2"),
+    )).await?;
+    let row = match db.query_opt(&statement, &[&key, &user.roles(), &realm_path]).await? {
+        Some(row) => row,
+        None => return Ok(String::new()),
+    };
+
+    let mut out = String::new();
+    if let Some(mut thumbnail) = row.get::<_, Option<String>>(0) {
+        // Has to match the URL the frontend uses (see `Event.thumbnail`).
+        if config.http.thumbnails.enabled() {
+            thumbnail = thumbnail::url(key);
+        }
+        out += &format!(r#"<link rel="preload" as="image" href="{}">"#, escape_html(&thumbnail));
+    }
+
+    // The frontend plays the first track. Manifests of adaptive streams are
+    // small and can be preloaded. Preloading a whole video file is
+    // undesirable, so in that case we only establish the connection early.
+    let tracks = row.get::<_, Vec<EventTrack>>(1);
+    let restricted = config.geoblocking.restricts(row.get(3), &row.get::<_, Vec<String>>(4));
+    if let Some(track) = tracks.first().filter(|_| !restricted) {
+        if is_manifest(track) {
+            out += &format!(
+                r#"<link rel="preload" as="fetch" crossorigin href="{}">"#,
+                escape_html(&track.uri),
+            );
+        } else if let Some(origin) = origin(&track.uri) {
+            out += &format!(r#"<link rel="preconnect" href="{}">"#, escape_html(&origin));
+        }
+    }
+
+    let canonical = canonical::video_path_in(row.get(2), key);
+    out += &canonical::link(&canonical, config.general.site_url.as_ref());
+
+    Ok(out)
+}
+
+/// Extracts the event key from paths of video pages, i.e. `/!v/<id>` and
+/// `/<realm path>/v/<id>`.
+pub(super) fn video_key(path: &str) -> Option<Key> {
+    let mut segments = path.trim_end_matches('/').rsplit('/');
+    let id = segments.next()?;
+    let prefix = segments.next()?;
+    let is_direct_link = prefix == "!v" && segments.next() == Some("");
+    if prefix != "v" && !is_direct_link {
+        return None;
+    }
+
+    Key::from_base64(id)
+}
+
+/// Returns the path of the realm of video pages inside a realm, i.e.
+/// `<realm path>` of `/<realm path>/v/<id>`, which is empty for the root
+/// realm. `None` for all other paths, including `/!v/<id>`.
+pub(super) fn video_realm_path(path: &str) -> Option<&str> {
+    let (rest, _id) = path.trim_end_matches('/').rsplit_once('/')?;
+    rest.strip_suffix("/v")
+}
+
+fn is_manifest(track: &EventTrack) -> bool {
+    const MANIFEST_TYPES: &[&str] = &[
+        "application/x-mpegurl",
+        "application/vnd.apple.mpegurl",
+        "application/dash+xml",
+    ];
+
+    match &track.mimetype {
+        Some(mimetype) => MANIFEST_TYPES.contains(&mimetype.to_lowercase().as_str()),
+        None => {
+            let path = track.uri.split(['?', '#']).next().unwrap_or_default();
+            path.ends_with(".m3u8") || path.ends_with(".mpd")
+        }
+    }
+}
+
+/// Returns `scheme://authority` of absolute URLs.
+fn origin(uri: &str) -> Option<String> {
+    let uri = uri.parse::<Uri>().ok()?;
+    Some(format!("{}://{}", uri.scheme_str()?, uri.authority()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_paths() {
+        let key = |path| video_key(path).map(|key| key.0);
+        assert_eq!(key("/!v/AAAAAAAAAAB"), Some(1));
+        assert_eq!(key("/!v/AAAAAAAAAAB/"), Some(1));
+        assert_eq!(key("/lectures/math/v/AAAAAAAAAAC"), Some(2));
+        assert_eq!(key("/v/AAAAAAAAAAC"), Some(2));
+        assert_eq!(key("/foo/!v/AAAAAAAAAAB"), None);
+        assert_eq!(key("/lectures/AAAAAAAAAAB"), None);
+        assert_eq!(key("/!v/not-an-id"), None);
+        assert_eq!(key("/"), None);
+    }
+
+    #[test]
+    fn video_realm_paths() {
+        assert_eq!(video_realm_path("/lectures/math/v/AAAAAAAAAAC"), Some("/lectures/math"));
+        assert_eq!(video_realm_path("/lectures/math/v/AAAAAAAAAAC/"), Some("/lectures/math"));
+        assert_eq!(video_realm_path("/v/AAAAAAAAAAC"), Some(""));
+        assert_eq!(video_realm_path("/!v/AAAAAAAAAAB"), None);
+        assert_eq!(video_realm_path("/lectures"), None);
+    }
+}
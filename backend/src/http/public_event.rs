@@ -0,0 +1,71 @@
+//! Public metadata of single events as JSON (if `http.public_event_json` is
+//! enabled), for other websites to show teasers of Tobira videos without
+//! using the GraphQL API. Only events readable by `ROLE_ANONYMOUS` are served
+//! and only data that is shown on their public pages anyway. As the response
+//! is the same for everyone, it can be cached and read by any origin.
+
+use hyper::{Body, StatusCode, header};
+
+use crate::{
+    api::Id,
+    auth::ROLE_ANONYMOUS,
+    db::DbConnection,
+    prelude::*,
+};
+use super::{Context, Request, Response, canonical, handlers::reply_404, response};
+
+
+/// Prefix of the route, followed by `<id>.json` with the ID as used in the
+/// GraphQL API.
+pub(super) const PREFIX: &str = "/~public/event/";
+
+/// How long clients and proxies may cache responses, in seconds.
+const MAX_AGE: u32 = 600;
+
+/// Handles `GET /~public/event/<id>.json`. Replies with the title, duration
+/// (in milliseconds), thumbnail URL and URL of the video page. The latter is
+/// only absolute if `general.site_url` is set.
+pub(super) async fn serve(req: Request<Body>, db: DbConnection, ctx: &Context) -> Response {
+    let path = req.uri().path();
+    let key = path.strip_prefix(PREFIX)
+        .and_then(|rest| rest.strip_suffix(".json"))
+        .and_then(|id| id.parse::<Id>().ok())
+        .and_then(|id| id.key_for(Id::EVENT_KIND));
+    let Some(key) = key else {
+        return reply_404(&ctx.assets, req.method(), path).await;
+    };
+
+    let event = db.query_opt(
+        "select title, duration, \
+            case when passphrase_hash is null then thumbnail end \
+            from events \
+            where id = $1 and read_roles && $2 and not retracted",
+        &[&key, &vec![ROLE_ANONYMOUS]],
+    ).await;
+    let event = match event {
+        Ok(Some(event)) => event,
+        Ok(None) => return reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("Failed to load public event data: {}", e);
+            return response::internal_server_error();
+        }
+    };
+
+    let site_url = ctx.config.general.site_url.as_ref()
+        .map(|url| url.to_string())
+        .unwrap_or_default();
+    let body = serde_json::json!({
+        "title": event.get::<_, String>(0),
+        "duration": event.get::<_, i32>(1),
+        "thumbnail": event.get::<_, Option<String>>(2),
+        "url": format!("{site_url}{}", canonical::video_path_in(None, key)),
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, format!("public, max-age={MAX_AGE}"))
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(body.to_string().into())
+        .unwrap()
+}
@@ -0,0 +1,109 @@
+//! Simple in-memory rate limiting for endpoints that can be abused without
+//! being logged in, e.g. to guess passwords.
+//!
+//! The limits are per Tobira process. With several Tobira nodes behind a
+//! load balancer, the effective limit is multiplied by the number of nodes,
+//! which is fine for the purpose of making abuse impractical.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hyper::Body;
+
+use super::{HttpConfig, Request};
+
+
+/// Address of the peer of the connection a request was received on. Added
+/// to the request extensions for TCP connections.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PeerAddr(pub(crate) IpAddr);
+
+/// Returns the IP address of the client that sent `req`: from the header
+/// configured as `http.client_ip_header` if set, and otherwise the peer of
+/// the connection. `None` for Unix sockets without configured header.
+pub(crate) fn client_ip(req: &Request<Body>, config: &HttpConfig) -> Option<IpAddr> {
+    match &config.client_ip_header {
+        // Proxies append to `X-Forwarded-For`, so the last entry is the one
+        // added by the proxy in front of Tobira.
+        Some(header) => req.headers().get(header)?
+            .to_str().ok()?
+            .rsplit(',')
+            .next()?
+            .trim()
+            .parse()
+            .ok(),
+        None => req.extensions().get::<PeerAddr>().map(|addr| addr.0),
+    }
+}
+
+/// Allows at most `max` hits per key in a fixed window of time.
+pub(crate) struct RateLimiter<K> {
+    max: u32,
+    window: Duration,
+    hits: Mutex<HashMap<K, (Instant, u32)>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    /// Entries of expired windows are removed once the map has this many.
+    const PRUNE_THRESHOLD: usize = 10_000;
+
+    pub(crate) fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts one hit for `key` and returns whether it is within the limit.
+    pub(crate) fn hit(&self, key: K) -> bool {
+        self.hit_at(key, Instant::now())
+    }
+
+    /// Removes all hits of `key`, e.g. after a successful login.
+    pub(crate) fn reset(&self, key: &K) {
+        self.hits.lock().unwrap().remove(key);
+    }
+
+    fn hit_at(&self, key: K, now: Instant) -> bool {
+        let mut hits = self.hits.lock().unwrap();
+        if hits.len() >= Self::PRUNE_THRESHOLD {
+            hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let (start, count) = hits.entry(key).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        *count <= self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::RateLimiter;
+
+    #[test]
+    fn limits() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(limiter.hit_at("a", start));
+        assert!(limiter.hit_at("a", start + Duration::from_secs(1)));
+        assert!(!limiter.hit_at("a", start + Duration::from_secs(2)));
+        assert!(limiter.hit_at("b", start + Duration::from_secs(2)));
+        assert!(limiter.hit_at("a", start + Duration::from_secs(61)));
+
+        limiter.reset(&"b");
+        assert!(limiter.hit_at("b", start + Duration::from_secs(3)));
+        assert!(limiter.hit_at("b", start + Duration::from_secs(4)));
+        assert!(!limiter.hit_at("b", start + Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,159 @@
+//! Icon images uploaded for realms, as an alternative to the curated icons
+//! (see `RealmIcon`).
+//!
+//! Like avatars (see `avatar.rs`), the images are stored in the DB and only
+//! PNG and JPEG images are accepted. They can be uploaded by moderators of
+//! the realm, including delegated ones. Choosing a curated icon or removing
+//! the icon via `updateRealm` also removes the uploaded image.
+
+use chrono::{DateTime, Utc};
+use hyper::{Body, StatusCode};
+
+use crate::{
+    api::Id,
+    auth::{self, HasRoles, User},
+    db::{self, DbConnection, types::Key},
+    prelude::*,
+};
+use super::{Context, Request, Response, avatar, handlers::reply_404, response};
+
+
+/// `POST <PATH>/<id>` uploads the icon of the realm with the given ID (as
+/// used in the GraphQL API), `GET <PATH>/<id>` returns it.
+pub(crate) const PATH: &str = "/~realm-icon";
+
+/// Maximum size of a stored icon in bytes.
+const MAX_SIZE: usize = 64 * 1024;
+
+/// Maximum width and height of a stored icon in pixels.
+const MAX_DIMENSION: u32 = 128;
+
+/// Returns the key of the realm in the path `<PATH>/<id>`, if valid.
+fn realm_key(path: &str) -> Option<Key> {
+    path.strip_prefix(PATH)?
+        .strip_prefix('/')?
+        .parse::<Id>()
+        .ok()?
+        .key_for(Id::REALM_KIND)
+}
+
+/// Handles `POST /~realm-icon/<id>`: the body is the image, which replaces
+/// the current icon of the realm. The `X-Requested-With` header has to be set.
+pub(super) async fn upload(
+    req: Request<Body>,
+    mut db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let path = req.uri().path();
+    let Some(key) = realm_key(path) else {
+        return reply_404(&ctx.assets, req.method(), path).await;
+    };
+    let Some(user) = user else {
+        return response::forbidden();
+    };
+
+    // Same checks as `api::Context::require_realm_moderator`.
+    let allowed: Result<Option<bool>, tokio_postgres::Error> = async {
+        let row = db.query_opt(
+            "select exists(select from ancestors_of_realm($1) where locked) \
+                from realms where id = $1",
+            &[&key],
+        ).await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        if user.is_admin() {
+            return Ok(Some(true));
+        }
+        if row.get::<_, bool>(0) {
+            return Ok(Some(false));
+        }
+        let is_moderator = user.is_moderator(&ctx.config.auth)
+            || auth::require_delegated_moderator(&db, &user, key).await?.is_some();
+        Ok(Some(is_moderator))
+    }.await;
+    match allowed {
+        Ok(Some(true)) => {}
+        Ok(Some(false)) => return response::forbidden(),
+        Ok(None) => return reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("DB error when checking realm icon upload permission: {}", e);
+            return response::internal_server_error();
+        }
+    }
+
+    let what = format!("icon of realm {}", key.0);
+    let (data, mimetype) = match avatar::read_image(req, MAX_DIMENSION, MAX_SIZE, &what, ctx).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = store(&mut db, key, &data, mimetype).await {
+        error!("DB error when storing realm icon: {}", e);
+        return response::internal_server_error();
+    }
+    info!("User '{}' uploaded a new icon for realm {} ({} bytes)", user.username, key.0, data.len());
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn store(
+    db: &mut DbConnection,
+    key: Key,
+    data: &[u8],
+    mimetype: &str,
+) -> Result<(), tokio_postgres::Error> {
+    let tx = db.transaction().await?;
+    tx.execute(
+        "insert into realm_icons (realm, data, mimetype) values ($1, $2, $3) \
+            on conflict (realm) do update \
+            set data = excluded.data, mimetype = excluded.mimetype",
+        &[&key, &data, &mimetype],
+    ).await?;
+    tx.execute(
+        "update realms set icon = null, icon_uploaded = now() where id = $1",
+        &[&key],
+    ).await?;
+    tx.commit().await
+}
+
+/// Handles `GET /~realm-icon/<id>`.
+pub(super) async fn serve(req: Request<Body>, ctx: &Context) -> Response {
+    let path = req.uri().path();
+    let Some(key) = realm_key(path) else {
+        return reply_404(&ctx.assets, req.method(), path).await;
+    };
+
+    let db = match db::get_conn_or_service_unavailable(&ctx.db_pool).await {
+        Ok(db) => db,
+        Err(r) => return r,
+    };
+    let row = db.query_opt(
+        "select data, mimetype from realm_icons where realm = $1",
+        &[&key],
+    ).await;
+    match row {
+        Ok(Some(row)) => Response::builder()
+            .header("Content-Type", row.get::<_, String>(1))
+            .header("X-Content-Type-Options", "nosniff")
+            .header("Content-Security-Policy", "default-src 'none'")
+            // The URL contains the time of the upload (see `icon_url`).
+            .header("Cache-Control", "public, max-age=31536000, immutable")
+            .body(Body::from(row.get::<_, Vec<u8>>(0)))
+            .unwrap(),
+        Ok(None) => reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("DB error when loading realm icon: {}", e);
+            response::internal_server_error()
+        }
+    }
+}
+
+/// Returns the URL of the icon uploaded for the given realm at `uploaded`.
+pub(crate) fn icon_url(realm: Key, uploaded: DateTime<Utc>) -> String {
+    format!("{PATH}/{}?v={}", Id::realm(realm), uploaded.timestamp())
+}
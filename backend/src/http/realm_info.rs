@@ -0,0 +1,124 @@
+//! Information about realms that is needed to serve every page, independent
+//! of the page content.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    api::cache::{CacheTag, RequestCache},
+    db::DbConnection,
+    prelude::*,
+};
+use super::Context;
+
+
+/// Paths of all realms that have a property inherited by the pages inside of
+/// them. These are few, so we just load all of them and cache the result
+/// instead of querying the DB for every page.
+#[derive(Clone, Default)]
+pub(super) struct RealmInfo {
+    /// Paths of realms marked as `noindex`.
+    noindex: Vec<String>,
+
+    /// Paths and locales of realms with a locale set, longest paths first.
+    locales: Vec<(String, String)>,
+}
+
+impl RealmInfo {
+    /// Returns the cached info or loads it from the DB. Errors are logged and
+    /// result in empty info, as that's not critical.
+    pub(super) async fn load_cached(ctx: &Context, db: &DbConnection) -> Arc<Self> {
+        let cache = RequestCache::new(ctx.resolver_cache.clone());
+        let load = async { Ok(Arc::new(Self::load(db).await?)) };
+        cache.get_or_load(CacheTag::Realms, "realm-info".into(), Duration::from_secs(3600), load)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to load realm info from DB: {}", e.msg);
+                Arc::new(Self::default())
+            })
+    }
+
+    async fn load(db: &DbConnection) -> Result<Self, tokio_postgres::Error> {
+        let rows = db.query(
+            "select full_path, noindex, locale from realms \
+                where noindex or locale is not null \
+                order by length(full_path) desc",
+            &[],
+        ).await?;
+
+        let mut out = Self::default();
+        for row in rows {
+            let path: String = row.get(0);
+            if let Some(locale) = row.get::<_, Option<String>>(2) {
+                out.locales.push((path.clone(), locale));
+            }
+            if row.get(1) {
+                out.noindex.push(path);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Returns `true` if the page at `path` belongs to a realm that is marked
+    /// as `noindex` or has such an ancestor.
+    pub(super) fn is_noindex(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        self.noindex.iter().any(|realm| belongs_to(path, realm))
+    }
+
+    /// Returns the effective locale of the deepest realm whose path is a
+    /// prefix of `path`. That's the realm the page belongs to, e.g. for video
+    /// pages inside realms. For all other pages, it's the locale of the root
+    /// realm.
+    pub(super) fn locale(&self, path: &str) -> Option<&str> {
+        let path = path.trim_end_matches('/');
+        self.locales.iter()
+            .find(|(realm, _)| belongs_to(path, realm))
+            .map(|(_, locale)| &**locale)
+    }
+}
+
+/// Returns `true` if `path` is the path of the realm with `realm_path` or
+/// points to something inside of it. `path` must not end with `/`.
+fn belongs_to(path: &str, realm_path: &str) -> bool {
+    path.strip_prefix(realm_path).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{belongs_to, RealmInfo};
+
+    #[test]
+    fn realm_paths() {
+        assert!(belongs_to("", ""));
+        assert!(belongs_to("/lectures", ""));
+        assert!(belongs_to("/lectures", "/lectures"));
+        assert!(belongs_to("/lectures/math/v/abc", "/lectures"));
+        assert!(!belongs_to("/lectures-old", "/lectures"));
+        assert!(!belongs_to("/lectures", "/lectures/math"));
+    }
+
+    #[test]
+    fn noindex() {
+        let info = RealmInfo { noindex: vec!["/internal".into()], locales: vec![] };
+        assert!(info.is_noindex("/internal/"));
+        assert!(info.is_noindex("/internal/team/v/abc"));
+        assert!(!info.is_noindex("/"));
+        assert!(!info.is_noindex("/internals"));
+    }
+
+    #[test]
+    fn locales() {
+        let locales = vec![
+            ("/lectures/fr".into(), "fr".into()),
+            ("/lectures".into(), "de".into()),
+            ("".into(), "en".into()),
+        ];
+        let info = RealmInfo { noindex: vec![], locales };
+        assert_eq!(info.locale("/"), Some("en"));
+        assert_eq!(info.locale("/lectures/fr/v/abc"), Some("fr"));
+        assert_eq!(info.locale("/lectures/math"), Some("de"));
+        assert_eq!(info.locale("/lectures-fr"), Some("en"));
+        assert_eq!(RealmInfo::default().locale("/"), None);
+    }
+}
@@ -0,0 +1,66 @@
+//! Anonymized page views of realms (if `http.playback_stats` is enabled).
+//!
+//! The frontend sends `POST /~realm-view/<realm key>` whenever a realm page
+//! is shown and we only count the views per realm and day (see
+//! `realm_views_daily`). Moderators can query them via `Realm.views`.
+//! Nothing about the visitor is stored and, like playback reports, views are
+//! rate limited per client IP (only kept in memory).
+
+use std::{net::IpAddr, time::Duration};
+
+use hyper::{Body, Method, StatusCode};
+use once_cell::sync::Lazy;
+
+use crate::{db::{DbConnection, types::Key}, prelude::*};
+use super::{
+    Context, Request, Response, handlers::reply_404, response,
+    rate_limit::{self, RateLimiter},
+};
+
+
+/// Prefix of the route, followed by the key of the realm.
+pub(super) const PREFIX: &str = "/~realm-view/";
+
+/// Views per client IP and realm. Navigating back and forth between pages
+/// shows the same realm several times, which we don't want to count.
+static VIEWS_PER_REALM: Lazy<RateLimiter<(IpAddr, Key)>> =
+    Lazy::new(|| RateLimiter::new(5, Duration::from_secs(60 * 60)));
+
+/// Views per client IP for all realms.
+static VIEWS_PER_IP: Lazy<RateLimiter<IpAddr>> =
+    Lazy::new(|| RateLimiter::new(300, Duration::from_secs(60 * 60)));
+
+/// Handles `POST /~realm-view/<realm key>`. Replies 204 even if the view was
+/// not counted due to the rate limit, as browsers ignore the response to
+/// beacons anyway.
+pub(super) async fn record(req: Request<Body>, db: DbConnection, ctx: &Context) -> Response {
+    let path = req.uri().path();
+    let Some(key) = path.strip_prefix(PREFIX).and_then(Key::from_base64) else {
+        return reply_404(&ctx.assets, &Method::POST, path).await;
+    };
+
+    let client_ip = rate_limit::client_ip(&req, &ctx.config.http);
+    let limited = client_ip.is_some_and(|ip| {
+        let per_realm = VIEWS_PER_REALM.hit((ip, key));
+        let per_ip = VIEWS_PER_IP.hit(ip);
+        !(per_realm && per_ip)
+    });
+    if !limited {
+        let res = db.execute(
+            "insert into realm_views_daily (realm, day, views) \
+                select id, current_date, 1 from realms where id = $1 \
+                on conflict (realm, day) do update \
+                set views = realm_views_daily.views + 1",
+            &[&key],
+        ).await;
+        if let Err(e) = res {
+            error!("DB error when storing realm view: {}", e);
+            return response::internal_server_error();
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
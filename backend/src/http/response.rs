@@ -17,6 +17,13 @@ pub(crate) fn bad_request() -> Response {
         .unwrap()
 }
 
+pub(crate) fn forbidden() -> Response {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body("Forbidden".into())
+        .unwrap()
+}
+
 pub(crate) fn internal_server_error() -> Response {
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
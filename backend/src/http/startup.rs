@@ -0,0 +1,126 @@
+//! Answering requests while Tobira is starting.
+//!
+//! Before the actual server listens, e.g. while another Tobira process
+//! migrates the database, a minimal server answers all requests with 503 and
+//! what Tobira is waiting for. So load balancers and orchestrators see that
+//! Tobira is starting instead of refused connections. Once the actual server
+//! runs, `/~ready` answers with 200.
+
+use std::{convert::Infallible, fs, os::unix::fs::PermissionsExt, sync::Mutex};
+
+use hyper::{
+    Body, Server, StatusCode,
+    service::{make_service_fn, service_fn},
+};
+use hyperlocal::UnixServerExt;
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{prelude::*, systemd};
+use super::{HttpConfig, Response};
+
+
+/// Path of the readiness route.
+pub(crate) const READY_PATH: &str = "/~ready";
+
+/// What Tobira is currently doing, see `set_status`.
+static STATUS: Mutex<&str> = Mutex::new("starting");
+
+/// Sets what Tobira is currently doing during startup, which is sent in
+/// responses of the startup server and reported to systemd.
+pub(crate) fn set_status(status: &'static str) {
+    *STATUS.lock().unwrap() = status;
+    systemd::notify_status(status);
+}
+
+/// Response of the actual server to `/~ready`.
+pub(super) fn ready() -> Response {
+    Response::builder()
+        .header("Content-Type", "text/plain; charset=UTF-8")
+        .header("Cache-Control", "no-store")
+        .body(Body::from("ready\n"))
+        .unwrap()
+}
+
+fn not_ready() -> Response {
+    let status = *STATUS.lock().unwrap();
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Content-Type", "text/plain; charset=UTF-8")
+        .header("Cache-Control", "no-store")
+        .header("Retry-After", "5")
+        .body(Body::from(format!("Tobira is not ready yet: {status}\n")))
+        .unwrap()
+}
+
+/// The servers started by `StartupServer::start`.
+pub(crate) struct StartupServer {
+    shutdown: watch::Sender<()>,
+    servers: Vec<JoinHandle<()>>,
+}
+
+impl StartupServer {
+    /// Listens on the configured socket(s), unless systemd passed sockets
+    /// (which keep connections waiting anyway). Binding errors are only
+    /// logged, as the actual server reports them later.
+    pub(crate) fn start(config: &HttpConfig) -> Option<Self> {
+        if systemd::has_listen_sockets() {
+            return None;
+        }
+
+        // As in `serve`, the service has a different type for each kind of
+        // socket, so it is defined in this macro.
+        let (shutdown, receiver) = watch::channel(());
+        macro_rules! spawn {
+            ($server:expr) => {{
+                let service = make_service_fn(|_| async {
+                    Ok::<_, Infallible>(service_fn(|_| async { Ok::<_, Infallible>(not_ready()) }))
+                });
+                let mut receiver = receiver.clone();
+                let server = $server.serve(service).with_graceful_shutdown(async move {
+                    let _ = receiver.changed().await;
+                });
+                tokio::spawn(async move {
+                    if let Err(e) = server.await {
+                        warn!("Error in startup server: {e}");
+                    }
+                })
+            }};
+        }
+
+        let result = (|| -> Result<Vec<JoinHandle<()>>> {
+            if let Some(unix_socket) = &config.unix_socket {
+                if unix_socket.exists() {
+                    fs::remove_file(unix_socket)?;
+                }
+                let server = Server::bind_unix(unix_socket)?;
+                let permissions = fs::Permissions::from_mode(config.unix_socket_permissions);
+                fs::set_permissions(unix_socket, permissions)?;
+                Ok(vec![spawn!(server)])
+            } else {
+                config.listen_addresses()
+                    .iter()
+                    .map(|addr| Ok(spawn!(Server::from_tcp(addr.bind()?)?)))
+                    .collect()
+            }
+        })();
+
+        match result {
+            Ok(servers) => {
+                debug!("Started startup server answering with 503 until Tobira is ready");
+                Some(Self { shutdown, servers })
+            }
+            Err(e) => {
+                debug!("Could not start startup server: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Stops all servers and waits until they released their sockets.
+    pub(crate) async fn stop(self) {
+        let _ = self.shutdown.send(());
+        for server in self.servers {
+            let _ = server.await;
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Exporting watch statistics as CSV (if `http.playback_stats` is enabled).
+//!
+//! For each event, the export contains how often it was played, an estimate
+//! of the number of viewers and the share of them that watched until the
+//! end, all based on the daily counters in `playback_stats_daily` (see
+//! `http/playback.rs`). As nothing about viewers is stored, the estimate is
+//! the number of playback reports that include the start of the video, so
+//! someone watching a video twice is counted twice.
+
+use std::fmt::Write;
+
+use chrono::{Duration, NaiveDate, Utc};
+use hyper::{Body, StatusCode};
+use tokio_postgres::Row;
+
+use crate::{
+    api::Id,
+    auth::{self, HasRoles, User},
+    db::DbConnection,
+    prelude::*,
+};
+use super::{Context, Request, Response, handlers::reply_404, response};
+
+
+/// Prefix of the routes, followed by `series/<id>.csv` or `realm/<id>.csv`,
+/// with the IDs as used in the GraphQL API.
+pub(super) const PREFIX: &str = "/~stats/";
+
+/// The date range used if the request does not specify one.
+const DEFAULT_DAYS: i64 = 30;
+
+const CSV_HEADER: &str = "id,opencast_id,title,created,views,estimated_viewers,completion_rate\n";
+
+/// Aggregates the daily counters of all events matching `filter` (with `$1`
+/// being the series or realm key and `$4` the user's roles).
+fn query(filter: &str) -> String {
+    format!(
+        "select events.id, events.opencast_id, events.title, events.created, \
+            coalesce(sum(reports), 0)::bigint, \
+            coalesce(sum(starts), 0)::bigint, \
+            coalesce(sum(completions), 0)::bigint \
+        from events \
+        left join playback_stats_daily \
+            on event = events.id and day between $2 and $3 \
+        where ({filter}) and ($5 or events.write_roles && $4) \
+        group by events.id \
+        order by events.created",
+    )
+}
+
+/// Handles `GET /~stats/series/<id>.csv` and `GET /~stats/realm/<id>.csv`.
+/// The former can be used by moderators and users with write access to
+/// events of the series and contains only those events. The latter can be
+/// used by moderators of the realm (including delegated ones) and contains
+/// all events shown in the realm or its descendants via video or series
+/// blocks. The date range is given by the `from` and `to` query parameters
+/// (`YYYY-MM-DD`, both inclusive) and defaults to the last 30 days.
+pub(super) async fn serve(
+    req: Request<Body>,
+    db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let path = req.uri().path();
+    let target = path.strip_prefix(PREFIX)
+        .and_then(|rest| rest.strip_suffix(".csv"))
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(kind, id)| {
+            let id = id.parse::<Id>().ok()?;
+            match kind {
+                "series" => Some((kind, id.key_for(Id::SERIES_KIND)?)),
+                "realm" => Some((kind, id.key_for(Id::REALM_KIND)?)),
+                _ => None,
+            }
+        });
+    let Some((kind, key)) = target else {
+        return reply_404(&ctx.assets, req.method(), path).await;
+    };
+
+    let (from, to) = match date_range(req.uri().query().unwrap_or(""), Utc::today().naive_utc()) {
+        Some(range) => range,
+        None => return response::bad_request(),
+    };
+
+    let res: Result<Option<Option<Vec<Row>>>, tokio_postgres::Error> = async {
+        let is_moderator = user.is_moderator(&ctx.config.auth);
+        let roles = user.roles();
+
+        if kind == "series" {
+            let exists = db.query_opt("select from series where id = $1", &[&key]).await?;
+            if exists.is_none() {
+                return Ok(None);
+            }
+
+            let rows = db.query(
+                &query("events.series = $1"),
+                &[&key, &from, &to, &roles, &is_moderator],
+            ).await?;
+            let allowed = is_moderator || !rows.is_empty();
+            Ok(Some(Some(rows).filter(|_| allowed)))
+        } else {
+            let Some(user) = &user else {
+                return Ok(Some(None));
+            };
+            let exists = db.query_opt("select from realms where id = $1", &[&key]).await?;
+            if exists.is_none() {
+                return Ok(None);
+            }
+            if !is_moderator && auth::require_delegated_moderator(&db, user, key).await?.is_none() {
+                return Ok(Some(None));
+            }
+
+            // The moderator of the realm can see the statistics of all
+            // events in it, regardless of write access.
+            let filter = "events.id in (select video_id from blocks where realm_id in (select id from subtree)) \
+                or events.series in (select series_id from blocks where realm_id in (select id from subtree))";
+            let sql = format!(
+                "with subtree as (\
+                    select id from realms \
+                    where id = $1 \
+                    or starts_with(full_path, (select full_path from realms where id = $1) || '/')\
+                ) {}",
+                query(filter),
+            );
+            let rows = db.query(&sql, &[&key, &from, &to, &roles, &true]).await?;
+            Ok(Some(Some(rows)))
+        }
+    }.await;
+
+    let rows = match res {
+        Ok(Some(Some(rows))) => rows,
+        Ok(Some(None)) => return response::forbidden(),
+        Ok(None) => return reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("DB error when exporting watch statistics: {}", e);
+            return response::internal_server_error();
+        }
+    };
+
+    let mut out = String::from(CSV_HEADER);
+    for row in rows {
+        write_row(&mut out, &row);
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/csv; charset=UTF-8")
+        .header(
+            "Content-Disposition",
+            format!(r#"attachment; filename="watch-stats-{from}-{to}.csv""#),
+        )
+        .body(Body::from(out))
+        .unwrap()
+}
+
+fn write_row(out: &mut String, row: &Row) {
+    let reports: i64 = row.get(4);
+    let starts: i64 = row.get(5);
+    let completions: i64 = row.get(6);
+    let completion_rate = if starts > 0 {
+        format!("{:.3}", (completions as f64 / starts as f64).min(1.0))
+    } else {
+        String::new()
+    };
+
+    let _ = writeln!(
+        out,
+        "{},{},{},{},{},{},{}",
+        Id::event(row.get(0)),
+        csv_field(row.get::<_, Option<&str>>(1).unwrap_or("")),
+        csv_field(row.get(2)),
+        row.get::<_, chrono::DateTime<Utc>>(3).to_rfc3339(),
+        reports,
+        starts,
+        completion_rate,
+    );
+}
+
+/// Parses the `from` and `to` query parameters. A missing `to` means
+/// `today`, a missing `from` means `DEFAULT_DAYS` before `to`.
+fn date_range(query: &str, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    let mut from = None;
+    let mut to = None;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let date = || NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok();
+        match &*key {
+            "from" => from = Some(date()?),
+            "to" => to = Some(date()?),
+            _ => {}
+        }
+    }
+
+    let to = to.unwrap_or(today);
+    let from = from.unwrap_or(to - Duration::days(DEFAULT_DAYS - 1));
+    Some((from, to)).filter(|_| from <= to)
+}
+
+/// Quotes the value if necessary. Values that spreadsheet applications would
+/// interpret as formula are prefixed with `'`.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{csv_field, date_range};
+
+    #[test]
+    fn dates() {
+        let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let today = date("2024-03-31");
+
+        assert_eq!(date_range("", today), Some((date("2024-03-02"), today)));
+        assert_eq!(
+            date_range("from=2024-01-01&to=2024-01-31", today),
+            Some((date("2024-01-01"), date("2024-01-31"))),
+        );
+        assert_eq!(
+            date_range("to=2024-01-31", today),
+            Some((date("2024-01-02"), date("2024-01-31"))),
+        );
+        assert_eq!(date_range("from=2024-02-01&to=2024-01-31", today), None);
+        assert_eq!(date_range("from=yesterday", today), None);
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(csv_field("Lecture 1"), "Lecture 1");
+        assert_eq!(csv_field("Math, part 2"), r#""Math, part 2""#);
+        assert_eq!(csv_field(r#"The "best" one"#), r#""The ""best"" one""#);
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+        assert_eq!(csv_field("-5, really"), r#""'-5, really""#);
+    }
+}
@@ -0,0 +1,393 @@
+//! Thumbnail proxy (if `http.thumbnails.converter` is set): serves event
+//! thumbnails resized to the requested width and in the best format the
+//! browser supports, so that list views don't load full size images.
+//!
+//! We cannot decode images ourselves, so an external ImageMagick binary does
+//! the conversion. Only JPEG, PNG and WebP originals are converted. Converted
+//! images are cached on disk. If the conversion fails, the client is
+//! redirected to the original thumbnail.
+//!
+//! When a thumbnail is requested the first time, its average color is stored
+//! as well, which list views show until the thumbnail is loaded.
+
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use hyper::{Body, StatusCode, header};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    auth::{HasRoles, User},
+    db::{DbConnection, types::Key},
+    prelude::*,
+};
+use super::{Context, Request, Response, body, handlers::reply_404, response};
+
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct ThumbnailConfig {
+    /// Path to the ImageMagick binary (`magick`, or `convert` for version 6).
+    /// If set, thumbnails of events are served via Tobira, resized to the
+    /// size they are displayed in and, if supported by the browser, as AVIF
+    /// or WebP. For AVIF and WebP, ImageMagick needs to be built with the
+    /// corresponding libraries. If not set, the original thumbnails from
+    /// Opencast are used.
+    pub(crate) converter: Option<PathBuf>,
+
+    /// Directory in which converted thumbnails are cached. Has to be set if
+    /// `converter` is set. Tobira never deletes files from this directory,
+    /// but it can be emptied at any time.
+    pub(crate) cache_dir: Option<PathBuf>,
+
+    /// Quality of converted images, between 1 and 100.
+    #[config(default = 80)]
+    pub(crate) quality: u8,
+}
+
+impl ThumbnailConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.converter.is_some() && self.cache_dir.is_none() {
+            bail!("`http.thumbnails.cache_dir` has to be set if `http.thumbnails.converter` is set");
+        }
+        if !(1..=100).contains(&self.quality) {
+            bail!("`http.thumbnails.quality` has to be between 1 and 100");
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.converter.is_some()
+    }
+}
+
+/// Prefix of the route, followed by the key of the event.
+pub(super) const PREFIX: &str = "/~thumbnail/";
+
+/// Requested widths are rounded up to one of these, to limit the number of
+/// cached variants. Larger widths are served with the largest one.
+const WIDTHS: &[u32] = &[160, 320, 480, 640, 960, 1280];
+
+/// Maximum size of an original thumbnail we are willing to convert.
+const MAX_ORIGINAL_SIZE: usize = 10 * 1024 * 1024;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const CONVERSION_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Avif,
+    Webp,
+    Jpeg,
+}
+
+impl Format {
+    /// Picks the best format that the client accepts.
+    fn from_accept(accept: &str) -> Self {
+        let accepts = |mimetype: &str| accept.split(',')
+            .any(|part| part.split(';').next().unwrap_or("").trim() == mimetype);
+
+        if accepts("image/avif") {
+            Self::Avif
+        } else if accepts("image/webp") {
+            Self::Webp
+        } else {
+            Self::Jpeg
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Webp => "webp",
+            Self::Jpeg => "jpeg",
+        }
+    }
+
+    fn mimetype(self) -> &'static str {
+        match self {
+            Self::Avif => "image/avif",
+            Self::Webp => "image/webp",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// Returns the URL of the proxied thumbnail of the given event.
+pub(crate) fn url(event: Key) -> String {
+    let mut buf = [0; 11];
+    format!("{PREFIX}{}", event.to_base64(&mut buf))
+}
+
+/// Handles `GET /~thumbnail/<event key>`. The `width` query parameter selects
+/// the width of the image.
+pub(super) async fn serve(
+    req: Request<Body>,
+    db: DbConnection,
+    user: Option<User>,
+    ctx: &Context,
+) -> Response {
+    let path = req.uri().path();
+    let (converter, cache_dir) = match &ctx.config.http.thumbnails {
+        ThumbnailConfig { converter: Some(converter), cache_dir: Some(cache_dir), .. }
+            => (converter, cache_dir),
+        _ => return reply_404(&ctx.assets, req.method(), path).await,
+    };
+    let key = match path.strip_prefix(PREFIX).and_then(Key::from_base64) {
+        Some(key) => key,
+        None => return reply_404(&ctx.assets, req.method(), path).await,
+    };
+    let width = match req.uri().query().map(width_from_query) {
+        Some(Some(width)) => width,
+        Some(None) => return response::bad_request(),
+        None => *WIDTHS.last().unwrap(),
+    };
+    let format = req.headers().get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(Format::Jpeg, Format::from_accept);
+
+    let res = db.query_opt(
+        "select thumbnail, thumbnail_color is null from events \
+            where id = $1 and read_roles && $2 and passphrase_hash is null",
+        &[&key, &user.roles()],
+    ).await;
+    let (original, needs_color) = match res {
+        Ok(Some(row)) => match row.get::<_, Option<String>>(0) {
+            Some(original) => (original, row.get::<_, bool>(1)),
+            None => return reply_404(&ctx.assets, req.method(), path).await,
+        },
+        Ok(None) => return reply_404(&ctx.assets, req.method(), path).await,
+        Err(e) => {
+            error!("DB error when loading thumbnail: {}", e);
+            return response::internal_server_error();
+        }
+    };
+    drop(db);
+
+    // The cache key contains the original URL, so that changed thumbnails
+    // are converted again.
+    let hash = hex::encode(ring::digest::digest(&ring::digest::SHA256, original.as_bytes()));
+    let cache_path = cache_dir.join(format!("{hash}-{width}.{}", format.extension()));
+    let cached = tokio::fs::read(&cache_path).await.ok();
+
+    // The original is also needed to determine the color of the thumbnail
+    // once (see `Event.thumbnailColor`).
+    let data = if cached.is_none() || needs_color {
+        match fetch(&original, ctx).await {
+            Ok(data) => Some(data),
+            Err(e) => {
+                warn!("Failed to fetch thumbnail '{}': {:?}", original, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    if let (true, Some(data)) = (needs_color, &data) {
+        if let Err(e) = store_color(key, &original, data.clone(), converter, ctx).await {
+            warn!("Failed to determine color of thumbnail '{}': {:?}", original, e);
+        }
+    }
+
+    let image = match (cached, data) {
+        (Some(image), _) => image,
+        (None, Some(data)) => {
+            let geometry = format!("{width}x>");
+            match resize(data, &geometry, format.extension(), converter, ctx).await {
+                Ok(image) => {
+                    if let Err(e) = write_cache(&cache_path, &image).await {
+                        warn!("Failed to cache thumbnail in '{}': {}", cache_path.display(), e);
+                    }
+                    image
+                }
+                Err(e) => {
+                    warn!("Failed to convert thumbnail '{}': {:?}", original, e);
+                    return redirect(original);
+                }
+            }
+        }
+        (None, None) => return redirect(original),
+    };
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.mimetype())
+        .header(header::VARY, "Accept")
+        .header(header::CACHE_CONTROL, "private, max-age=86400")
+        .body(image.into())
+        .unwrap()
+}
+
+/// Redirects the client to the original thumbnail, if it could not be
+/// converted.
+fn redirect(original: String) -> Response {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, original)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Fetches the original thumbnail.
+async fn fetch(original: &str, ctx: &Context) -> Result<Vec<u8>> {
+    let fetch = async {
+        let response = ctx.http_client.get(original.parse()?).await?;
+        if !response.status().is_success() {
+            bail!("server replied with {}", response.status());
+        }
+        body::read(response.into_body(), MAX_ORIGINAL_SIZE).await
+            .with_context(|| format!("failed to read image of at most {MAX_ORIGINAL_SIZE} bytes"))
+    };
+    tokio::time::timeout(FETCH_TIMEOUT, fetch).await
+        .context("fetching the original timed out")?
+}
+
+/// Determines the average color of the thumbnail and stores it, unless the
+/// thumbnail of the event changed in the meantime.
+async fn store_color(
+    key: Key,
+    original: &str,
+    data: Vec<u8>,
+    converter: &std::path::Path,
+    ctx: &Context,
+) -> Result<()> {
+    let color = average_color(data, converter).await?;
+    ctx.db_pool.get().await?
+        .execute(
+            "update events set thumbnail_color = $2 where id = $1 and thumbnail = $3",
+            &[&key, &color, &original],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Returns the ImageMagick coder for the given image data, based on its magic
+/// bytes. Only JPEG, PNG and WebP are supported: the input format is never
+/// left for ImageMagick to guess, as some of its coders (e.g. MVG, MSL or
+/// SVG) can read files or make requests.
+pub(super) fn input_coder(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpeg"),
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some("png"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        _ => None,
+    }
+}
+
+/// Resizes the image with ImageMagick to `geometry` (e.g. `320x>`) and
+/// writes it with the given coder. Only the first frame is used.
+pub(super) async fn resize(
+    data: Vec<u8>,
+    geometry: &str,
+    output_coder: &str,
+    converter: &std::path::Path,
+    ctx: &Context,
+) -> Result<Vec<u8>> {
+    let quality = ctx.config.http.thumbnails.quality.to_string();
+    let output = format!("{output_coder}:-");
+    let args = ["-resize", geometry, "-strip", "-quality", &quality, &output];
+    run_converter(data, &args, converter).await
+}
+
+/// Returns the average color of the image as `#rrggbb`, by scaling it down
+/// to a single pixel.
+async fn average_color(data: Vec<u8>, converter: &std::path::Path) -> Result<String> {
+    let args = ["-alpha", "off", "-resize", "1x1!", "-depth", "8", "rgb:-"];
+    match run_converter(data, &args, converter).await?[..] {
+        [r, g, b] => Ok(format!("#{r:02x}{g:02x}{b:02x}")),
+        ref other => bail!("expected 3 bytes, got {}", other.len()),
+    }
+}
+
+/// Runs ImageMagick with the image as input and `args` (which have to
+/// specify the output) and returns its output. Only the first frame is used.
+async fn run_converter(
+    data: Vec<u8>,
+    args: &[&str],
+    converter: &std::path::Path,
+) -> Result<Vec<u8>> {
+    let input_coder = input_coder(&data).context("unsupported image format")?;
+    let mut child = tokio::process::Command::new(converter)
+        .arg(format!("{input_coder}:-[0]"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to start '{}'", converter.display()))?;
+    let mut stdin = child.stdin.take().expect("stdin of child process is not piped");
+    let write = async move {
+        stdin.write_all(&data).await
+        // Dropping `stdin` closes it.
+    };
+    let (written, output) = tokio::time::timeout(
+        CONVERSION_TIMEOUT,
+        async { tokio::join!(write, child.wait_with_output()) },
+    ).await.context("conversion timed out")?;
+    written?;
+    let output = output?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        bail!("'{}' exited with {}", converter.display(), output.status);
+    }
+
+    Ok(output.stdout)
+}
+
+/// Writes the image to a temporary file first, so that concurrent requests
+/// never read partially written files.
+async fn write_cache(path: &std::path::Path, image: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension(format!("tmp-{}", rand::random::<u32>()));
+    tokio::fs::write(&tmp_path, image).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+/// Returns the smallest allowed width not smaller than the `width` query
+/// parameter, or `None` if the parameter is invalid.
+fn width_from_query(query: &str) -> Option<u32> {
+    let width = form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "width")
+        .map(|(_, value)| value.parse::<u32>());
+
+    match width {
+        None => WIDTHS.last().copied(),
+        Some(Ok(width)) if width > 0 => Some(
+            WIDTHS.iter().copied().find(|&w| w >= width).unwrap_or(*WIDTHS.last().unwrap())
+        ),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, input_coder, width_from_query};
+
+    #[test]
+    fn format_from_accept() {
+        let firefox = "image/avif,image/webp,*/*";
+        assert_eq!(Format::from_accept(firefox), Format::Avif);
+        assert_eq!(Format::from_accept("image/webp;q=0.9, image/png"), Format::Webp);
+        assert_eq!(Format::from_accept("image/*"), Format::Jpeg);
+        assert_eq!(Format::from_accept("image/webpx"), Format::Jpeg);
+    }
+
+    #[test]
+    fn input_coders() {
+        assert_eq!(input_coder(b"\xFF\xD8\xFF\xE0\0\x10JFIF"), Some("jpeg"));
+        assert_eq!(input_coder(b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR"), Some("png"));
+        assert_eq!(input_coder(b"RIFF\x24\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(input_coder(b"RIFF\x24\0\0\0WAVEfmt "), None);
+        assert_eq!(input_coder(b"push graphic-context\nviewbox 0 0 640 480"), None);
+        assert_eq!(input_coder(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"), None);
+        assert_eq!(input_coder(b""), None);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(width_from_query("width=1"), Some(160));
+        assert_eq!(width_from_query("width=320"), Some(320));
+        assert_eq!(width_from_query("width=321"), Some(480));
+        assert_eq!(width_from_query("width=5000"), Some(1280));
+        assert_eq!(width_from_query("foo=bar"), Some(1280));
+        assert_eq!(width_from_query("width=0"), None);
+        assert_eq!(width_from_query("width=abc"), None);
+    }
+}
@@ -0,0 +1,584 @@
+//! Scanning uploaded videos for viruses (if `upload.virus_scan` is
+//! configured).
+//!
+//! Without a scanner, the uploader sends tracks directly to Opencast. With
+//! one, it sends them to `POST /~upload/track` instead, with the same
+//! multipart body as for `/ingest/addTrack` and the file as last field
+//! `BODY`. Tobira stores the body in a temporary file and streams the file
+//! through the scanner. Only if nothing was found, the body is forwarded to
+//! Opencast, authenticated with a JWT for the uploader. Otherwise, the file is
+//! moved to the quarantine directory (or deleted) and the uploader is told
+//! what was found.
+
+use std::{path::{Path, PathBuf}, process::Stdio, time::Duration};
+
+use bstr::ByteSlice;
+use bytes::Bytes;
+use hyper::{Body, StatusCode, Uri, body::HttpBody, header};
+use serde_json::json;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+};
+
+use crate::{auth::User, prelude::*};
+use super::{Context, Request, Response, avatar, response};
+
+
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct VirusScanConfig {
+    /// Address of a clamd daemon that uploaded videos are scanned with: the
+    /// path of its Unix socket (`LocalSocket` in `clamd.conf`) or
+    /// `host:port`. clamd rejects files larger than its `StreamMaxLength`
+    /// (25MB by default), so that has to be raised to the size of the
+    /// largest expected video.
+    pub(crate) clamd: Option<String>,
+
+    /// Command that uploaded videos are scanned with instead of clamd, as
+    /// program and arguments, e.g. `["clamdscan", "--no-summary", "-"]`. The
+    /// file is streamed to its stdin. Exit code 0 means that the file is
+    /// clean, 1 that a virus was found (described by the first line of
+    /// stdout). Anything else is treated as failure.
+    pub(crate) command: Option<Vec<String>>,
+
+    /// Directory in which uploads are stored while being scanned. Needs
+    /// enough space for all concurrent uploads. Defaults to the temporary
+    /// directory of the system.
+    pub(crate) temp_dir: Option<PathBuf>,
+
+    /// Directory to which infected files are moved, named
+    /// `<timestamp>-<username>-<filename>`. If not set, they are deleted.
+    pub(crate) quarantine_dir: Option<PathBuf>,
+
+    /// How long scanning a single file may take. Uploads are rejected if the
+    /// scanner takes longer or fails.
+    #[config(default = "10min", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) timeout: Duration,
+}
+
+impl VirusScanConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.clamd.is_some() && self.command.is_some() {
+            bail!("only one of `upload.virus_scan.clamd` and `upload.virus_scan.command` \
+                can be set");
+        }
+        if self.command.as_ref().is_some_and(|c| c.is_empty()) {
+            bail!("`upload.virus_scan.command` must not be empty");
+        }
+        if !self.is_enabled() && (self.temp_dir.is_some() || self.quarantine_dir.is_some()) {
+            bail!("`upload.virus_scan.temp_dir` and `upload.virus_scan.quarantine_dir` \
+                require `clamd` or `command` to be set");
+        }
+
+        Ok(())
+    }
+
+    /// Whether uploads are scanned, i.e. sent to Opencast via Tobira.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.clamd.is_some() || self.command.is_some()
+    }
+}
+
+/// `POST` receives a track, see the module docs.
+pub(crate) const PATH: &str = "/~upload/track";
+
+/// Maximum size of the part headers of `BODY`.
+const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+
+/// Handles `POST /~upload/track`. The `X-Requested-With` header has to be
+/// set. Responds with the response of Opencast, or with 422 and a JSON object
+/// `{ "found": <description> }` if the scanner found a virus.
+pub(super) async fn upload_track(req: Request<Body>, user: Option<User>, ctx: &Context) -> Response {
+    let Some(user) = user.filter(|user| user.can_upload(&ctx.config.auth)) else {
+        return response::forbidden();
+    };
+    if !req.headers().contains_key(avatar::CSRF_HEADER) {
+        return response::forbidden();
+    }
+    let Some(content_type) = req.headers().get(header::CONTENT_TYPE).cloned() else {
+        return response::bad_request();
+    };
+    let Some(boundary) = content_type.to_str().ok().and_then(boundary) else {
+        return response::bad_request();
+    };
+    let delimiter = format!("\r\n--{boundary}").into_bytes();
+
+    let config = &ctx.config.upload.virus_scan;
+    let dir = config.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!("tobira-upload-{:032x}", rand::random::<u128>()));
+    let result = async {
+        let (mut file, len, delimiters) = receive(req.into_body(), &path, delimiter).await?;
+        let Some(part) = file_part(&mut file, len, &delimiters, &boundary).await? else {
+            return Ok(response::bad_request());
+        };
+        let what = format!("'{}' uploaded by '{}' ({} bytes)", part.filename, user.username, len);
+
+        let verdict = tokio::time::timeout(config.timeout, scan(&file, &part, config)).await;
+        match verdict {
+            Ok(Ok(Verdict::Clean)) => {
+                debug!("Virus scanner found nothing in {what}, forwarding it to Opencast");
+                forward(file, len, content_type, &user, ctx).await
+            }
+            Ok(Ok(Verdict::Infected(found))) => {
+                warn!("Virus scanner found '{found}' in {what}");
+                quarantine(&file, &part, &user, config).await;
+                Ok(Response::builder()
+                    .status(StatusCode::UNPROCESSABLE_ENTITY)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(json!({ "found": found }).to_string().into())
+                    .unwrap())
+            }
+            Ok(Err(e)) => {
+                error!("Failed to scan {what} for viruses: {e:#}");
+                Ok(response::service_unavailable())
+            }
+            Err(_) => {
+                error!("Timeout when scanning {what} for viruses (see `upload.virus_scan.timeout`)");
+                Ok(response::service_unavailable())
+            }
+        }
+    }.await;
+
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove temporary upload file '{}': {e}", path.display());
+        }
+    }
+
+    result.unwrap_or_else(|e| {
+        error!("Failed to handle upload of '{}': {e:#}", user.username);
+        response::internal_server_error()
+    })
+}
+
+/// Returns the boundary of a `multipart/form-data` content type.
+fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim_matches('"').to_owned())
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+/// Writes the request body to a new file at `path`. Returns the file, its
+/// length and the positions of all `delimiter`s in it.
+async fn receive(
+    mut body: Body,
+    path: &Path,
+    delimiter: Vec<u8>,
+) -> Result<(File, u64, Vec<u64>)> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+        .with_context(|| format!("failed to create '{}'", path.display()))?;
+    let mut search = DelimiterSearch::new(delimiter);
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.context("failed to read request body")?;
+        search.feed(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    Ok((file, search.offset, search.positions))
+}
+
+/// Finds all positions of a delimiter in data that is fed in chunks.
+struct DelimiterSearch {
+    delimiter: Vec<u8>,
+    /// The end of the data fed so far that could be the start of a
+    /// delimiter.
+    carry: Vec<u8>,
+    /// How many bytes were fed.
+    offset: u64,
+    positions: Vec<u64>,
+}
+
+impl DelimiterSearch {
+    fn new(delimiter: Vec<u8>) -> Self {
+        Self { delimiter, carry: vec![], offset: 0, positions: vec![] }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        let start = self.offset - self.carry.len() as u64;
+        self.carry.extend_from_slice(chunk);
+        self.positions.extend(self.carry.find_iter(&self.delimiter).map(|i| start + i as u64));
+        let keep = self.carry.len().min(self.delimiter.len() - 1);
+        self.carry.drain(..self.carry.len() - keep);
+        self.offset += chunk.len() as u64;
+    }
+}
+
+/// The file in a multipart body.
+struct FilePart {
+    filename: String,
+    start: u64,
+    end: u64,
+}
+
+/// Returns the part of the multipart body in `file` that contains the file,
+/// or `None` if the body is not as expected: the last part has to be the file
+/// field `BODY`, i.e. the last delimiter has to end the body.
+async fn file_part(
+    file: &mut File,
+    len: u64,
+    delimiters: &[u64],
+    boundary: &str,
+) -> Result<Option<FilePart>> {
+    let [.., previous, last] = *delimiters else {
+        return Ok(None);
+    };
+
+    // The body ends with the delimiter, `--` and optionally a line break.
+    let close = format!("\r\n--{boundary}--");
+    let end_len = len - last;
+    if end_len > close.len() as u64 + 2 {
+        return Ok(None);
+    }
+    let mut end = vec![0; end_len as usize];
+    file.seek(std::io::SeekFrom::Start(last)).await?;
+    file.read_exact(&mut end).await?;
+    if end != close.as_bytes() && end != format!("{close}\r\n").as_bytes() {
+        return Ok(None);
+    }
+
+    // Read the headers of the last part.
+    let mut head = vec![0; (last - previous).min(MAX_PART_HEADER_SIZE as u64) as usize];
+    file.seek(std::io::SeekFrom::Start(previous)).await?;
+    file.read_exact(&mut head).await?;
+    let Some((header_len, filename)) = parse_part_header(&head, boundary) else {
+        return Ok(None);
+    };
+
+    Ok(Some(FilePart {
+        filename,
+        start: previous + header_len as u64,
+        end: last,
+    }))
+}
+
+/// Parses the delimiter and headers at the start of `head`. Returns their
+/// length and the filename if they belong to the file field `BODY`.
+fn parse_part_header(head: &[u8], boundary: &str) -> Option<(usize, String)> {
+    let rest = head.strip_prefix(format!("\r\n--{boundary}\r\n").as_bytes())?;
+    let headers_len = rest.find("\r\n\r\n")?;
+    let headers = std::str::from_utf8(&rest[..headers_len]).ok()?;
+    let disposition = headers.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))?
+        .1;
+
+    let mut params = disposition.split(';').map(str::trim);
+    if params.next() != Some("form-data") {
+        return None;
+    }
+    let mut name = None;
+    let mut filename = None;
+    for param in params {
+        match param.split_once('=') {
+            Some(("name", v)) => name = Some(v.trim_matches('"')),
+            Some(("filename", v)) => filename = Some(v.trim_matches('"')),
+            _ => {}
+        }
+    }
+    if name != Some("BODY") {
+        return None;
+    }
+
+    let len = head.len() - rest.len() + headers_len + 4;
+    Some((len, filename.unwrap_or_default().to_owned()))
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Clean,
+    /// What the scanner found.
+    Infected(String),
+}
+
+async fn scan(file: &File, part: &FilePart, config: &VirusScanConfig) -> Result<Verdict> {
+    let mut file = file.try_clone().await?;
+    file.seek(std::io::SeekFrom::Start(part.start)).await?;
+    let content = file.take(part.end - part.start);
+
+    if let Some(clamd) = &config.clamd {
+        if clamd.contains('/') {
+            let stream = UnixStream::connect(clamd).await
+                .with_context(|| format!("failed to connect to clamd at '{clamd}'"))?;
+            scan_with_clamd(content, stream).await
+        } else {
+            let stream = TcpStream::connect(clamd).await
+                .with_context(|| format!("failed to connect to clamd at '{clamd}'"))?;
+            scan_with_clamd(content, stream).await
+        }
+    } else if let Some([program, args @ ..]) = config.command.as_deref() {
+        scan_with_command(content, program, args).await
+    } else {
+        bail!("no virus scanner configured");
+    }
+}
+
+/// Sends the content via the `INSTREAM` command to clamd.
+async fn scan_with_clamd(
+    mut content: impl AsyncRead + Unpin,
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+) -> Result<Verdict> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = content.read(&mut buf).await?;
+        stream.write_all(&(len as u32).to_be_bytes()).await?;
+        if len == 0 {
+            break;
+        }
+        stream.write_all(&buf[..len]).await?;
+    }
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    parse_clamd_reply(&String::from_utf8_lossy(&reply))
+}
+
+/// Parses a reply like `stream: OK` or `stream: Eicar-Signature FOUND`.
+fn parse_clamd_reply(reply: &str) -> Result<Verdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    match reply.strip_prefix("stream: ") {
+        Some("OK") => Ok(Verdict::Clean),
+        Some(found) if found.ends_with(" FOUND") => {
+            Ok(Verdict::Infected(found.trim_end_matches(" FOUND").to_owned()))
+        }
+        _ => bail!("clamd replied '{reply}'"),
+    }
+}
+
+/// Streams the content to stdin of the given command.
+async fn scan_with_command(
+    mut content: impl AsyncRead + Unpin,
+    program: &str,
+    args: &[String],
+) -> Result<Verdict> {
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to start '{program}'"))?;
+    let mut stdin = child.stdin.take().expect("stdin of child process is not piped");
+    let write = async move {
+        tokio::io::copy(&mut content, &mut stdin).await
+        // Dropping `stdin` closes it.
+    };
+    let (written, output) = tokio::join!(write, child.wait_with_output());
+    let output = output?;
+
+    match output.status.code() {
+        Some(0) => {
+            written?;
+            Ok(Verdict::Clean)
+        }
+        Some(1) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let found = stdout.lines().next().unwrap_or_default().trim();
+            Ok(Verdict::Infected(found.to_owned()))
+        }
+        _ => bail!("'{program}' exited with {}", output.status),
+    }
+}
+
+/// Moves the infected file to the quarantine directory, if configured.
+/// Failures are only logged.
+async fn quarantine(file: &File, part: &FilePart, user: &User, config: &VirusScanConfig) {
+    let Some(dir) = &config.quarantine_dir else {
+        return;
+    };
+    let name = format!(
+        "{}-{}-{}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+        user.username,
+        part.filename,
+    ).replace(|c: char| c == '/' || c == '\\' || c.is_control(), "_");
+    let path = dir.join(name);
+
+    let result = async {
+        let mut file = file.try_clone().await?;
+        file.seek(std::io::SeekFrom::Start(part.start)).await?;
+        let mut target = File::create(&path).await?;
+        tokio::io::copy(&mut file.take(part.end - part.start), &mut target).await?;
+        target.flush().await
+    }.await;
+    match result {
+        Ok(()) => info!("Moved infected file to '{}'", path.display()),
+        Err(e) => error!("Failed to move infected file to '{}': {e}", path.display()),
+    }
+}
+
+/// Sends the body in `file` to `/ingest/addTrack` of the upload node and
+/// returns the response of Opencast.
+async fn forward(
+    file: File,
+    len: u64,
+    content_type: header::HeaderValue,
+    user: &User,
+    ctx: &Context,
+) -> Result<Response> {
+    let node = ctx.config.opencast.upload_node();
+    let uri = Uri::builder()
+        .scheme(node.scheme.clone())
+        .authority(node.authority.clone())
+        .path_and_query("/ingest/addTrack")
+        .build()
+        .context("failed to build URI")?;
+
+    let mut file = file;
+    file.seek(std::io::SeekFrom::Start(0)).await?;
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(len) => if sender.send_data(Bytes::copy_from_slice(&buf[..len])).await.is_err() {
+                    break;
+                },
+                Err(e) => {
+                    error!("Failed to read temporary upload file: {e}");
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+    let req = hyper::Request::post(&uri)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, len)
+        .header(header::AUTHORIZATION, format!("Bearer {}", ctx.jwt.new_upload_token(user)))
+        .body(body)
+        .expect("bug: failed to build request");
+
+    let response = ctx.http_client.request(req).await
+        .with_context(|| format!("failed to POST {uri}"))?;
+    if response.status().is_redirection() {
+        // Opencast redirects to its login page if the JWT is not accepted.
+        bail!("Opencast did not accept the JWT for '{}'", user.username);
+    }
+    if !response.status().is_success() {
+        warn!("Opencast responded with {} to upload by '{}'", response.status(), user.username);
+    }
+
+    let (parts, body) = response.into_parts();
+    let mut out = Response::builder().status(parts.status);
+    if let Some(content_type) = parts.headers.get(header::CONTENT_TYPE) {
+        out = out.header(header::CONTENT_TYPE, content_type);
+    }
+    Ok(out.body(body).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::Body;
+
+    use super::{
+        DelimiterSearch, Verdict, VirusScanConfig,
+        boundary, file_part, parse_clamd_reply, parse_part_header, receive, scan,
+    };
+
+    #[test]
+    fn content_type_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=----abc123").as_deref(),
+            Some("----abc123"),
+        );
+        assert_eq!(boundary("multipart/form-data;boundary=\"x y\"").as_deref(), Some("x y"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+    }
+
+    #[test]
+    fn delimiters_across_chunks() {
+        let data = b"--b\r\nfoo\r\n--b\r\nbar\r\n--b--\r\n";
+        for chunk_size in 1..data.len() {
+            let mut search = DelimiterSearch::new(b"\r\n--b".to_vec());
+            for chunk in data.chunks(chunk_size) {
+                search.feed(chunk);
+            }
+            assert_eq!(search.positions, [8, 18], "chunk size {chunk_size}");
+            assert_eq!(search.offset, data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn part_header() {
+        let head = b"\r\n--b\r\n\
+            Content-Disposition: form-data; name=\"BODY\"; filename=\"video.mp4\"\r\n\
+            Content-Type: video/mp4\r\n\r\ndata";
+        assert_eq!(parse_part_header(head, "b"), Some((head.len() - 4, "video.mp4".into())));
+
+        let head = b"\r\n--b\r\nContent-Disposition: form-data; name=\"flavor\"\r\n\r\nx";
+        assert_eq!(parse_part_header(head, "b"), None);
+        assert_eq!(parse_part_header(b"\r\n--b\r\nContent-Disposition: form-data", "b"), None);
+    }
+
+    #[test]
+    fn clamd_reply() {
+        assert_eq!(parse_clamd_reply("stream: OK\0").unwrap(), Verdict::Clean);
+        assert_eq!(
+            parse_clamd_reply("stream: Eicar-Signature FOUND\0").unwrap(),
+            Verdict::Infected("Eicar-Signature".into()),
+        );
+        assert!(parse_clamd_reply("INSTREAM size limit exceeded. ERROR\0").is_err());
+    }
+
+    async fn scan_body(content: &str, script: &str) -> Option<Verdict> {
+        let body = format!("--xyz\r\n\
+            Content-Disposition: form-data; name=\"flavor\"\r\n\r\n\
+            presenter/source\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"BODY\"; filename=\"a.mp4\"\r\n\
+            Content-Type: video/mp4\r\n\r\n\
+            {content}\r\n\
+            --xyz--\r\n");
+        let path = std::env::temp_dir()
+            .join(format!("tobira-upload-test-{:032x}", rand::random::<u128>()));
+        let (mut file, len, delimiters) = receive(Body::from(body), &path, b"\r\n--xyz".to_vec())
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let part = file_part(&mut file, len, &delimiters, "xyz").await.unwrap()?;
+        assert_eq!(part.filename, "a.mp4");
+        let config = VirusScanConfig {
+            clamd: None,
+            command: Some(vec!["sh".into(), "-c".into(), script.into()]),
+            temp_dir: None,
+            quarantine_dir: None,
+            timeout: Duration::from_secs(10),
+        };
+        Some(scan(&file, &part, &config).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn scan_file_part() {
+        let script = r#"test "$(cat)" = "hello" || { echo wrong content; exit 1; }"#;
+        assert_eq!(scan_body("hello", script).await, Some(Verdict::Clean));
+        assert_eq!(
+            scan_body("hello\r\n--xyz\r\nContent-Disposition: form-data; name=\"tags\"", script).await,
+            None,
+        );
+
+        let script = "if grep -q EICAR; then echo Eicar-Test-Signature; exit 1; fi";
+        assert_eq!(
+            scan_body("X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR", script).await,
+            Some(Verdict::Infected("Eicar-Test-Signature".into())),
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Cache warmup on startup (if `http.cache_warmup` is enabled): before Tobira
+//! accepts requests, the pages that are requested most are loaded once as a
+//! logged out user. That fills the resolver cache (see `api/cache.rs`) with
+//! the home page and the realm navigation and loads the data of the most
+//! prominent series pages into the DB's memory.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    api::{self, cache::RequestCache},
+    auth::Permissions,
+    prelude::*,
+};
+use super::Context;
+
+
+/// Values are only cached while the cache listens for invalidations, so we
+/// wait for that, but not forever.
+const LISTENER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of realms whose children are loaded. Realms closer to the
+/// root come first.
+const MAX_REALMS: i64 = 1000;
+
+/// Number of series whose pages are loaded: the ones shown in most blocks.
+const NUM_SERIES: i64 = 20;
+
+/// Number of fields per GraphQL query when loading many realms or series.
+const CHUNK_SIZE: usize = 100;
+
+/// Loads the hot pages. Errors are logged, as a failed warmup only makes the
+/// first requests slower.
+pub(super) async fn run(ctx: &Context) {
+    let before = Instant::now();
+    while !ctx.resolver_cache.is_listening() {
+        if before.elapsed() > LISTENER_TIMEOUT {
+            warn!("Skipping cache warmup: not listening for cache invalidations");
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    match warm(ctx).await {
+        Ok(num_queries) => info!(
+            "Warmed up caches with {num_queries} queries in {:.2?}",
+            before.elapsed(),
+        ),
+        Err(e) => error!("Failed to warm up caches: {e:#}"),
+    }
+}
+
+async fn warm(ctx: &Context) -> Result<usize> {
+    let mut db = ctx.db_pool.get().await?;
+
+    let mut queries = vec![
+        "{ rootRealm { childOrder blocks { id } children { id } } }".to_owned(),
+    ];
+
+    let paths = db.query(
+        "select full_path from realms where id <> 0 \
+            order by length(full_path) - length(replace(full_path, '/', '')), full_path \
+            limit $1",
+        &[&MAX_REALMS],
+    ).await?;
+    let paths = paths.iter().map(|row| row.get::<_, String>(0)).collect::<Vec<_>>();
+    for chunk in paths.chunks(CHUNK_SIZE) {
+        let fields = chunk.iter().enumerate()
+            .map(|(i, path)| {
+                format!("r{i}: realmByPath(path: {}) {{ children {{ id }} }}", string(path))
+            })
+            .collect::<Vec<_>>();
+        queries.push(format!("{{ {} }}", fields.join(" ")));
+    }
+
+    let series = db.query(
+        "select series.opencast_id from series \
+            inner join blocks on blocks.series_id = series.id \
+            group by series.id \
+            order by count(*) desc, series.updated desc \
+            limit $1",
+        &[&NUM_SERIES],
+    ).await?;
+    let series = series.iter().map(|row| row.get::<_, String>(0)).collect::<Vec<_>>();
+    for chunk in series.chunks(CHUNK_SIZE) {
+        let fields = chunk.iter().enumerate()
+            .map(|(i, id)| format!(
+                "s{i}: seriesByOpencastId(id: {}) {{ \
+                    title events(order: {{ column: CREATED, direction: DESCENDING }}) {{ id }} \
+                }}",
+                string(id),
+            ))
+            .collect::<Vec<_>>();
+        queries.push(format!("{{ {} }}", fields.join(" ")));
+    }
+
+    for query in &queries {
+        let tx = db.transaction().await?;
+        let make_context = |db| api::Context {
+            db,
+            db_pool: ctx.db_pool.clone(),
+            permissions: Permissions::new(&None, &ctx.config.auth),
+            user: None,
+            config: ctx.config.clone(),
+            jwt: ctx.jwt.clone(),
+            search: ctx.search.clone(),
+            cache: RequestCache::new(ctx.resolver_cache.clone()),
+            extensions: ctx.extensions.clone(),
+            tracer: None,
+            client_ip: None,
+            client_country: None,
+        };
+        let (result, tx) = api::with_transaction(tx, None, make_context, |context| async move {
+            juniper::execute(query, None, &ctx.api_root, &juniper::Variables::new(), &*context)
+                .await
+                .map(|(_, errors)| errors.len())
+        }).await;
+        tx.rollback().await?;
+
+        match result {
+            Ok(0) => {}
+            Ok(num_errors) => warn!("Cache warmup query returned {num_errors} errors"),
+            Err(e) => bail!("invalid cache warmup query: {e}"),
+        }
+    }
+
+    Ok(queries.len())
+}
+
+/// Returns `s` as GraphQL string literal, which is escaped like in JSON.
+fn string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
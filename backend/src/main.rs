@@ -16,16 +16,44 @@ mod auth;
 mod config;
 mod cmd;
 mod db;
+mod extension;
+mod geoblock;
 mod http;
 mod logger;
+mod metrics;
+mod outbox;
 mod prelude;
+mod retention;
+mod sanitize;
+mod saved_search;
 mod search;
+mod series_follow;
 mod sync;
+mod systemd;
+mod telemetry;
 mod util;
+mod warehouse;
 
-#[tokio::main]
-async fn main() {
-    if let Err(e) = run().await {
+fn main() {
+    // The environment is only read and modified here, before the runtime
+    // starts other threads, as doing that concurrently is unsound.
+    //
+    // If `RUST_BACKTRACE` wasn't already set, we default to `1`. Backtraces are
+    // almost always useful for debugging. Generating a backtrace is somewhat
+    // costly, which is why it is disabled by default. However, we don't expect
+    // panics to occur regularly, so it shouldn't be a problem. Only
+    // consideration: maaaybe this is a way to DOS tobira? If someone finds a
+    // request that triggers a panic?
+    if env::var("RUST_BACKTRACE") == Err(env::VarError::NotPresent) {
+        env::set_var("RUST_BACKTRACE", "1");
+    }
+    systemd::read_env();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to start async runtime");
+    if let Err(e) = runtime.block_on(run()) {
         // Log error in case stdout is not connected and it is logged into a file.
         error!("{:?}", e);
 
@@ -47,16 +75,6 @@ async fn main() {
 
 /// Main entry point.
 async fn run() -> Result<()> {
-    // If `RUST_BACKTRACE` wasn't already set, we default to `1`. Backtraces are
-    // almost always useful for debugging. Generating a backtrace is somewhat
-    // costly, which is why it is disabled by default. However, we don't expect
-    // panics to occur regularly, so it shouldn't be a problem. Only
-    // consideration: maaaybe this is a way to DOS tobira? If someone finds a
-    // request that triggers a panic?
-    if env::var("RUST_BACKTRACE") == Err(env::VarError::NotPresent) {
-        env::set_var("RUST_BACKTRACE", "1");
-    }
-
     // Parse CLI args.
     // This is a bit roundabout because we want to override the version
     // using some runtime code.
@@ -84,16 +102,56 @@ async fn run() -> Result<()> {
             let config = load_config_and_init_logger(shared)?;
             search::cmd::run(cmd, &config).await?;
         }
+        Command::Telemetry { cmd, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            telemetry::run(cmd, &config).await?;
+        }
+        Command::Export { cmd, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            warehouse::run(cmd, &config).await?;
+        }
         Command::Worker { shared } => {
             let config = load_config_and_init_logger(shared)?;
             start_worker(config).await?;
         }
-        Command::WriteConfig { target } => config::write_template(target.as_ref())?,
+        Command::Config { cmd } => cmd::config::run(cmd)?,
+        Command::WriteConfig { target } => {
+            cmd::config::write_docs(cmd::config::DocsFormat::TomlTemplate, target.as_ref())?;
+        }
         Command::ExportApiSchema { args } => cmd::export_api_schema::run(args)?,
         Command::ImportRealmTree { options, shared } => {
             let config = load_config_and_init_logger(shared)?;
             cmd::import_realm_tree::run(options, &config).await?;
         }
+        Command::ImportLegacyUrls { options, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::import_legacy_urls::run(options, &config).await?;
+        }
+        Command::Report { cmd, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::report::run(cmd, &config).await?;
+        }
+        Command::Jwt { cmd, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::jwt::run(cmd, &config).await?;
+        }
+        Command::Admin { cmd, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::admin::run(cmd, &config).await?;
+        }
+        Command::Rekey { shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::rekey::run(&config).await?;
+        }
+        Command::TestAccess { options, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::test_access::run(options, config).await?;
+        }
+        #[cfg(feature = "bench")]
+        Command::Bench { options, shared } => {
+            let config = load_config_and_init_logger(shared)?;
+            cmd::bench::run(options, config).await?;
+        }
     }
 
     Ok(())
@@ -102,11 +160,14 @@ async fn run() -> Result<()> {
 async fn start_server(config: Config) -> Result<()> {
     info!("Starting Tobira backend ...");
     trace!("Configuration: {:#?}", config);
+
+    // Until the actual server listens, requests are answered with 503.
+    let startup_server = http::startup::StartupServer::start(&config.http);
     let (db, search) = connect_and_prepare_db_and_meili(&config).await?;
 
     // Start web server
     let root_node = api::root_node();
-    http::serve(config, root_node, db, search).await
+    http::serve(config, root_node, db, search, startup_server).await
         .context("failed to start HTTP server")?;
 
     Ok(())
@@ -119,12 +180,23 @@ async fn start_worker(config: Config) -> Result<()> {
     let mut search_conn = db.get().await?;
     let sync_conn = db.get().await?;
     let db_maintenance_conn = db.get().await?;
+    let telemetry_conn = db.get().await?;
+    let retention_conn = db.get().await?;
+    let alert_conn = db.get().await?;
+    let follow_conn = db.get().await?;
+    let outbox_conn = db.get().await?;
     let auth_config = config.auth.clone();
+    systemd::notify_ready();
 
     tokio::select! {
         _ = search::update_index_daemon(&search, &mut search_conn) => {}
         _ = sync::run(true, sync_conn, &config) => {}
         _ = auth::db_maintenance(&db_maintenance_conn, &auth_config) => {}
+        _ = telemetry::report_daemon(telemetry_conn, &config.telemetry) => {}
+        _ = retention::daemon(retention_conn, &config.retention) => {}
+        _ = saved_search::alert_daemon(alert_conn, &config) => {}
+        _ = series_follow::notify_daemon(follow_conn, &config) => {}
+        _ = outbox::dispatch_daemon(outbox_conn, &config.webhooks) => {}
     };
 
     Ok(())
@@ -149,8 +221,19 @@ fn load_config_and_init_logger(args: &args::Shared) -> Result<Config> {
 async fn connect_and_migrate_db(config: &Config) -> Result<Pool> {
     let db = db::create_pool(&config.db).await
         .context("failed to create database connection pool (database not running?)")?;
-    db::migrate(&mut *db.get().await?).await
+    db::migrate(&mut *db.get().await?, &config.db).await
         .context("failed to check/run DB migrations")?;
+
+    let collation = config.general.collation_name();
+    let collation_exists = db.get().await?
+        .query_one("select exists(select from pg_collation where collname = $1)", &[&collation])
+        .await?
+        .get::<_, bool>(0);
+    if !collation_exists {
+        bail!("collation '{collation}' for `general.sort_locale` does not exist in the \
+            database (is PostgreSQL built with ICU support?)");
+    }
+
     Ok(db)
 }
 
@@ -0,0 +1,312 @@
+//! Counters for authentication related events, the state of the search
+//! index update queue and the health of the worker's jobs, exposed in the
+//! Prometheus text format at `/~metrics` (if `http.metrics` is enabled).
+//!
+//! Each event is also logged with the target `tobira::audit` in the form
+//! `event=<name> <field>="<value>" ...`. Event and field names are stable, so
+//! that log processing (e.g. a SIEM) can rely on them.
+
+use std::{fmt::Write, sync::atomic::{AtomicU64, Ordering}};
+
+use hyper::Body;
+use tokio_postgres::GenericClient;
+
+use crate::{http::{Context, Response}, prelude::*};
+
+
+/// Path of the metrics endpoint.
+pub(crate) const PATH: &str = "/~metrics";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuthEvent {
+    /// A session was created via `POST /~session`.
+    LoginSucceeded,
+    /// `POST /~session` was rejected.
+    LoginFailed,
+    /// A request had a session cookie that is malformed or that refers to
+    /// an unknown or expired session.
+    InvalidSessionCookie,
+    /// An auth header (`x-tobira-username`, ...) was set, but is not valid
+    /// base64 or UTF-8.
+    MalformedAuthHeader,
+    /// A JWT (e.g. for uploading) was issued.
+    JwtIssued,
+    /// Sessions of a user were removed on login because the user exceeded
+    /// `auth.max_sessions_per_user`.
+    SessionsInvalidated,
+    /// The data of a user was removed via `POST /~deprovision`.
+    UserDeprovisioned,
+}
+
+impl AuthEvent {
+    const ALL: [Self; 7] = [
+        Self::LoginSucceeded,
+        Self::LoginFailed,
+        Self::InvalidSessionCookie,
+        Self::MalformedAuthHeader,
+        Self::JwtIssued,
+        Self::SessionsInvalidated,
+        Self::UserDeprovisioned,
+    ];
+
+    /// The stable name used in logs and metrics.
+    fn name(self) -> &'static str {
+        match self {
+            Self::LoginSucceeded => "login_succeeded",
+            Self::LoginFailed => "login_failed",
+            Self::InvalidSessionCookie => "invalid_session_cookie",
+            Self::MalformedAuthHeader => "malformed_auth_header",
+            Self::JwtIssued => "jwt_issued",
+            Self::SessionsInvalidated => "sessions_invalidated",
+            Self::UserDeprovisioned => "user_deprovisioned",
+        }
+    }
+
+    fn counter(self) -> &'static AtomicU64 {
+        static COUNTERS: [AtomicU64; AuthEvent::ALL.len()] = [
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+        ];
+
+        &COUNTERS[self as usize]
+    }
+}
+
+/// Counts the event and logs it together with the given fields. Never pass
+/// secrets (like session IDs) as fields.
+pub(crate) fn auth_event(event: AuthEvent, fields: &[(&str, &str)]) {
+    event.counter().fetch_add(1, Ordering::Relaxed);
+
+    let mut msg = format!("event={}", event.name());
+    for (name, value) in fields {
+        write!(msg, " {name}={value:?}").unwrap();
+    }
+
+    let level = match event {
+        AuthEvent::LoginSucceeded
+        | AuthEvent::JwtIssued
+        | AuthEvent::SessionsInvalidated
+        | AuthEvent::UserDeprovisioned => log::Level::Info,
+        _ => log::Level::Warn,
+    };
+    log::log!(target: "tobira::audit", level, "{msg}");
+}
+
+/// Periodic jobs of the worker. Their health is stored in the DB, as jobs run
+/// in the worker process, but metrics are served by `tobira serve`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Job {
+    /// Harvesting from Opencast.
+    Sync,
+    /// Sending the outbox messages to `webhooks.url`.
+    Webhooks,
+    SavedSearchAlerts,
+    FollowNotifications,
+}
+
+impl Job {
+    /// The stable name used in the DB and metrics.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sync => "sync",
+            Self::Webhooks => "webhooks",
+            Self::SavedSearchAlerts => "saved_search_alerts",
+            Self::FollowNotifications => "follow_notifications",
+        }
+    }
+}
+
+/// Records whether a run of `job` succeeded. Failing to do so is only
+/// logged, as it must not stop the job.
+pub(crate) async fn job_run(job: Job, succeeded: bool, db: &impl GenericClient) {
+    let result = db.execute(
+        "insert into job_status (job, last_success, consecutive_failures) \
+            values ($1, case when $2 then now() end, case when $2 then 0 else 1 end) \
+            on conflict (job) do update set \
+                last_success = case when $2 then now() else job_status.last_success end, \
+                consecutive_failures = case when $2 then 0 \
+                    else job_status.consecutive_failures + 1 end",
+        &[&job.name(), &succeeded],
+    ).await;
+    if let Err(e) = result {
+        error!("Failed to record status of job '{}': {}", job.name(), e);
+    }
+}
+
+/// Health of a job that ran at least once, see `Job`.
+struct JobStatus {
+    job: String,
+    /// Unix timestamp, 0 if it never succeeded.
+    last_success: f64,
+    consecutive_failures: i32,
+}
+
+impl JobStatus {
+    async fn load_all(ctx: &Context) -> Result<Vec<Self>> {
+        let rows = ctx.db_pool.get().await?
+            .query(
+                "select job, coalesce(extract(epoch from last_success), 0)::float8, \
+                    consecutive_failures \
+                    from job_status \
+                    order by job",
+                &[],
+            )
+            .await?;
+        Ok(rows.into_iter()
+            .map(|row| Self {
+                job: row.get(0),
+                last_success: row.get(1),
+                consecutive_failures: row.get(2),
+            })
+            .collect())
+    }
+}
+
+/// State of the search index update queue: number of queued items and the
+/// age of the oldest one in seconds (0 if the queue is empty).
+struct IndexQueue {
+    len: i64,
+    lag: f64,
+}
+
+impl IndexQueue {
+    async fn load(ctx: &Context) -> Result<Self> {
+        let row = ctx.db_pool.get().await?
+            .query_one(
+                "select count(*), \
+                    coalesce(extract(epoch from now() - min(queued_at)), 0)::float8 \
+                    from search_index_queue",
+                &[],
+            )
+            .await?;
+        Ok(Self { len: row.get(0), lag: row.get(1) })
+    }
+}
+
+/// Renders all metrics in the Prometheus text format. Metrics stored in the
+/// DB are omitted if they could not be loaded.
+fn render(index_queue: Option<IndexQueue>, jobs: &[JobStatus]) -> String {
+    let mut out = String::new();
+    out += "# HELP tobira_auth_events_total Number of authentication related events.\n";
+    out += "# TYPE tobira_auth_events_total counter\n";
+    for event in AuthEvent::ALL {
+        let count = event.counter().load(Ordering::Relaxed);
+        writeln!(out, "tobira_auth_events_total{{event=\"{}\"}} {count}", event.name()).unwrap();
+    }
+
+    if let Some(IndexQueue { len, lag }) = index_queue {
+        out += "# HELP tobira_search_index_lag Seconds since the oldest item in the \
+            search index update queue was queued.\n";
+        out += "# TYPE tobira_search_index_lag gauge\n";
+        writeln!(out, "tobira_search_index_lag {lag:.3}").unwrap();
+        out += "# HELP tobira_search_index_queue_length Number of items in the search \
+            index update queue.\n";
+        out += "# TYPE tobira_search_index_queue_length gauge\n";
+        writeln!(out, "tobira_search_index_queue_length {len}").unwrap();
+    }
+
+    // Syncing is the most important job, so it gets its own metrics that
+    // are easy to alert on.
+    if let Some(sync) = jobs.iter().find(|status| status.job == Job::Sync.name()) {
+        out += "# HELP tobira_sync_last_success_timestamp Unix timestamp of the last \
+            successful harvest from Opencast.\n";
+        out += "# TYPE tobira_sync_last_success_timestamp gauge\n";
+        writeln!(out, "tobira_sync_last_success_timestamp {:.3}", sync.last_success).unwrap();
+        out += "# HELP tobira_sync_consecutive_failures Number of failed harvests since the \
+            last successful one.\n";
+        out += "# TYPE tobira_sync_consecutive_failures gauge\n";
+        writeln!(out, "tobira_sync_consecutive_failures {}", sync.consecutive_failures).unwrap();
+    }
+
+    let jobs = jobs.iter().filter(|status| status.job != Job::Sync.name()).collect::<Vec<_>>();
+    if !jobs.is_empty() {
+        out += "# HELP tobira_job_last_success_timestamp Unix timestamp of the last \
+            successful run of a worker job.\n";
+        out += "# TYPE tobira_job_last_success_timestamp gauge\n";
+        for status in &jobs {
+            writeln!(
+                out,
+                "tobira_job_last_success_timestamp{{job=\"{}\"}} {:.3}",
+                status.job,
+                status.last_success,
+            ).unwrap();
+        }
+        out += "# HELP tobira_job_consecutive_failures Number of failed runs of a worker job \
+            since its last successful one.\n";
+        out += "# TYPE tobira_job_consecutive_failures gauge\n";
+        for status in &jobs {
+            writeln!(
+                out,
+                "tobira_job_consecutive_failures{{job=\"{}\"}} {}",
+                status.job,
+                status.consecutive_failures,
+            ).unwrap();
+        }
+    }
+
+    out
+}
+
+/// Responds to a request to `PATH`.
+pub(crate) async fn serve(ctx: &Context) -> Response {
+    let index_queue = IndexQueue::load(ctx).await
+        .map_err(|e| error!("Failed to load search index queue for metrics: {:?}", e))
+        .ok();
+    let jobs = JobStatus::load_all(ctx).await
+        .map_err(|e| error!("Failed to load job status for metrics: {:?}", e))
+        .unwrap_or_default();
+
+    Response::builder()
+        .header("Content-Type", "text/plain; version=0.0.4; charset=UTF-8")
+        .body(Body::from(render(index_queue, &jobs)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthEvent, IndexQueue, JobStatus, auth_event, render};
+
+    #[test]
+    fn counts_events() {
+        auth_event(AuthEvent::MalformedAuthHeader, &[("header", "x-tobira-roles")]);
+        auth_event(AuthEvent::MalformedAuthHeader, &[]);
+
+        let metrics = render(None, &[]);
+        assert!(metrics.contains("tobira_auth_events_total{event=\"malformed_auth_header\"} 2\n"));
+        assert!(metrics.contains("tobira_auth_events_total{event=\"jwt_issued\"} 0\n"));
+        assert!(!metrics.contains("tobira_search_index_lag"));
+    }
+
+    #[test]
+    fn index_queue() {
+        let metrics = render(Some(IndexQueue { len: 3, lag: 12.5 }), &[]);
+        assert!(metrics.contains("# TYPE tobira_search_index_lag gauge\n"));
+        assert!(metrics.contains("tobira_search_index_lag 12.500\n"));
+        assert!(metrics.contains("tobira_search_index_queue_length 3\n"));
+    }
+
+    #[test]
+    fn jobs() {
+        let status = |job: &str, last_success, consecutive_failures| JobStatus {
+            job: job.into(),
+            last_success,
+            consecutive_failures,
+        };
+        let metrics = render(None, &[
+            status("sync", 1700000000.0, 0),
+            status("webhooks", 0.0, 4),
+        ]);
+        assert!(metrics.contains("tobira_sync_last_success_timestamp 1700000000.000\n"));
+        assert!(metrics.contains("tobira_sync_consecutive_failures 0\n"));
+        assert!(metrics.contains("tobira_job_last_success_timestamp{job=\"webhooks\"} 0.000\n"));
+        assert!(metrics.contains("tobira_job_consecutive_failures{job=\"webhooks\"} 4\n"));
+        assert!(!metrics.contains("job=\"sync\""));
+
+        assert!(!render(None, &[]).contains("tobira_sync"));
+    }
+}
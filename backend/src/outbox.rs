@@ -0,0 +1,198 @@
+//! Transactional outbox for side effects of changes made via the API,
+//! currently webhooks.
+//!
+//! API mutations never call other systems directly. Instead, they insert a
+//! message into the `outbox` table in their transaction (see `push`). So a
+//! message exists if and only if the change was committed. The worker sends
+//! the messages in order and retries failed ones with exponential backoff,
+//! so a temporarily unavailable endpoint only delays them.
+
+use std::time::Duration;
+
+use hyper::{Body, Client, Request, Uri};
+use hyper_rustls::HttpsConnectorBuilder;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    config::Config,
+    db::{self, DbConnection},
+    http::HttpClient,
+    metrics::{self, Job},
+    prelude::*,
+};
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct WebhookConfig {
+    /// URL to which changes made in Tobira (realms being added, changed or
+    /// removed) are sent via `POST` request with a JSON body like
+    /// `{ "id": 7, "kind": "realm-changed", "payload": { ... }, "created": ... }`.
+    /// Requests are sent in order by `tobira worker` and retried until the
+    /// endpoint replies with a 2xx status (at most 20 times). If not set,
+    /// nothing is sent.
+    pub(crate) url: Option<String>,
+
+    /// If set, requests contain the header `X-Tobira-Signature` with the
+    /// hex-encoded HMAC-SHA256 of the body using this secret.
+    pub(crate) secret: Option<Secret<String>>,
+}
+
+impl WebhookConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(url) = &self.url {
+            let uri = url.parse::<Uri>().context("`webhooks.url` is not a valid URL")?;
+            if !matches!(uri.scheme_str(), Some("http" | "https")) {
+                bail!("`webhooks.url` has to be an HTTP(S) URL");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How often the worker checks for messages to send.
+const DISPATCH_PERIOD: Duration = Duration::from_secs(10);
+
+/// Number of failed attempts after which a message is not sent anymore.
+pub(crate) const MAX_ATTEMPTS: i32 = 20;
+
+/// Upper bound for the backoff between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How long the endpoint has to reply.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Adds a message to the outbox, to be sent after the transaction `db`
+/// commits. Does nothing if webhooks are disabled.
+pub(crate) async fn push(
+    db: &db::Transaction,
+    config: &Config,
+    kind: &str,
+    payload: serde_json::Value,
+) -> Result<(), tokio_postgres::Error> {
+    if config.webhooks.url.is_none() {
+        return Ok(());
+    }
+
+    db.execute("insert into outbox (kind, payload) values ($1, $2)", &[&kind, &payload]).await?;
+    Ok(())
+}
+
+/// Long running task that sends messages from the outbox, if webhooks are
+/// enabled. Otherwise, this never resolves.
+pub(crate) async fn dispatch_daemon(mut db: DbConnection, config: &WebhookConfig) {
+    let url = match &config.url {
+        Some(url) => url.parse::<Uri>().expect("bug: URL was not validated"),
+        None => return futures::future::pending().await,
+    };
+    info!("Webhooks are enabled: sending changes to {}", url);
+
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build::<_, Body>(https);
+
+    loop {
+        let succeeded = match dispatch(&mut db, &client, &url, config).await {
+            Ok(all_sent) => all_sent,
+            Err(e) => {
+                error!("Failed to dispatch outbox messages: {:?}", e);
+                false
+            }
+        };
+        metrics::job_run(Job::Webhooks, succeeded, &**db).await;
+
+        tokio::time::sleep(DISPATCH_PERIOD).await;
+    }
+}
+
+/// Sends messages until the outbox is empty or sending one fails. Messages
+/// are sent strictly in order, so a failed message blocks all later ones
+/// until it was sent or given up. Returns whether all messages were sent.
+async fn dispatch(
+    db: &mut DbConnection,
+    client: &HttpClient,
+    url: &Uri,
+    config: &WebhookConfig,
+) -> Result<bool> {
+    let mut all_sent = true;
+    loop {
+        // The row stays locked until the transaction ends, so that multiple
+        // workers never send the same message.
+        let tx = db.transaction().await?;
+        let row = tx.query_opt(
+            "select id, kind, payload, created, attempts, next_attempt <= now() from outbox \
+                where attempts < $1 \
+                order by id \
+                limit 1 \
+                for update skip locked",
+            &[&MAX_ATTEMPTS],
+        ).await?;
+        let Some(row) = row else {
+            return Ok(all_sent);
+        };
+        let id = row.get::<_, i64>(0);
+        let attempts = row.get::<_, i32>(4);
+        if !row.get::<_, bool>(5) {
+            // The first message is waiting for its next attempt.
+            return Ok(false);
+        }
+
+        let body = serde_json::json!({
+            "id": id,
+            "kind": row.get::<_, String>(1),
+            "payload": row.get::<_, serde_json::Value>(2),
+            "created": row.get::<_, chrono::DateTime<chrono::Utc>>(3),
+        }).to_string();
+
+        match send(client, url, body, config).await {
+            Ok(()) => {
+                tx.execute("delete from outbox where id = $1", &[&id]).await?;
+                tx.commit().await?;
+                debug!("Sent outbox message {}", id);
+            }
+            Err(e) => {
+                let backoff = DISPATCH_PERIOD.saturating_mul(1 << attempts.min(16))
+                    .min(MAX_BACKOFF);
+                tx.execute(
+                    "update outbox set \
+                        attempts = attempts + 1, \
+                        next_attempt = now() + make_interval(secs => $2), \
+                        last_error = $3 \
+                        where id = $1",
+                    &[&id, &backoff.as_secs_f64(), &format!("{e:#}")],
+                ).await?;
+                tx.commit().await?;
+
+                if attempts + 1 >= MAX_ATTEMPTS {
+                    error!("Giving up sending outbox message {} after {} attempts: {:#}",
+                        id, MAX_ATTEMPTS, e);
+                    all_sent = false;
+                    continue;
+                }
+                warn!("Failed to send outbox message {} (retrying in {:?}): {:#}", id, backoff, e);
+                return Ok(false);
+            }
+        }
+    }
+}
+
+async fn send(client: &HttpClient, url: &Uri, body: String, config: &WebhookConfig) -> Result<()> {
+    let mut req = Request::post(url).header("Content-Type", "application/json");
+    if let Some(secret) = &config.secret {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.expose_secret().as_bytes());
+        let signature = ring::hmac::sign(&key, body.as_bytes());
+        req = req.header("X-Tobira-Signature", hex::encode(signature.as_ref()));
+    }
+
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, client.request(req.body(body.into())?))
+        .await
+        .context("endpoint did not reply in time")??;
+    if !response.status().is_success() {
+        bail!("endpoint replied with status {}", response.status());
+    }
+
+    Ok(())
+}
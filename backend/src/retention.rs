@@ -0,0 +1,160 @@
+//! Retention rules: events in a realm subtree that are older than some age
+//! are flagged and, if the rule says so, retracted after a grace period.
+//!
+//! The worker evaluates the rules regularly. Flagged events are listed for
+//! moderators (`retentionFlags` in the API) so they can review them before
+//! the grace period ends. Retracted events are only visible to users with
+//! write access, they are not deleted. Moderators can exempt events from
+//! retention, which also restores them if they were retracted.
+//!
+//! The worker stores the rules in the DB, so that triggers can update the
+//! flags whenever blocks, realm paths or the series of events change (see
+//! migration 40).
+
+use std::time::Duration;
+
+use postgres_types::{FromSql, ToSql};
+use serde::Deserialize;
+
+use crate::{
+    db::{DbConnection, types::Key},
+    prelude::*,
+    search::{self, IndexItemKind},
+};
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct RetentionConfig {
+    /// List of retention rules. Each rule applies to all events that appear
+    /// in the realm with path `realm` or any of its descendants (via series
+    /// or video blocks) and were created more than `max_age` ago. `action`
+    /// is either "flag" (only list them for moderators) or "retract" (make
+    /// them invisible for everyone without write access after the grace
+    /// period). If an event matches several rules, the first one wins.
+    /// Example:
+    ///
+    ///     rules = [{ realm = "/lectures", max_age = "1825d", action = "retract" }]
+    pub(crate) rules: Option<Vec<Rule>>,
+
+    /// Time between flagging an event and retracting it.
+    #[config(default = "30d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) grace_period: Duration,
+
+    /// How often the worker evaluates the rules.
+    #[config(default = "1d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) check_interval: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Rule {
+    realm: String,
+    #[serde(deserialize_with = "crate::config::deserialize_duration")]
+    max_age: Duration,
+    action: RetentionAction,
+}
+
+/// What happens to events matching a retention rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, FromSql, ToSql, juniper::GraphQLEnum)]
+#[serde(rename_all = "snake_case")]
+#[postgres(name = "retention_action")]
+pub(crate) enum RetentionAction {
+    /// The event is only listed for moderators.
+    #[postgres(name = "flag")]
+    Flag,
+    /// The event is retracted after the grace period.
+    #[postgres(name = "retract")]
+    Retract,
+}
+
+impl RetentionConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        for rule in self.rules.iter().flatten() {
+            if !(rule.realm.is_empty() || rule.realm.starts_with('/'))
+                || rule.realm.ends_with('/')
+            {
+                bail!(
+                    "invalid realm path '{}' in `retention.rules`: has to start with \
+                        and must not end with '/' (use \"\" for the root realm)",
+                    rule.realm,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Long running task that regularly evaluates the retention rules. If there
+/// are none, the flags of previous rules are removed once and this never
+/// resolves.
+pub(crate) async fn daemon(mut db: DbConnection, config: &RetentionConfig) {
+    loop {
+        if let Err(e) = evaluate(&mut db, config).await {
+            error!("Failed to evaluate retention rules: {}", e);
+        }
+
+        if config.rules.iter().flatten().next().is_none() {
+            return futures::future::pending().await;
+        }
+        tokio::time::sleep(config.check_interval).await;
+    }
+}
+
+/// Flags new matching events and retracts all flagged events that are due.
+async fn evaluate(db: &mut DbConnection, config: &RetentionConfig) -> Result<()> {
+    let rules = config.rules.as_deref().unwrap_or_default();
+    let mut tx = db.transaction().await?;
+
+    // The rules in the DB are replaced by the ones from the config, which
+    // might have changed since the last evaluation.
+    tx.execute("delete from retention_rules", &[]).await?;
+    for (index, rule) in rules.iter().enumerate() {
+        tx.execute(
+            "insert into retention_rules (index, realm, max_age, action, grace_period) \
+                values ($1, $2, make_interval(secs => $3), $4, make_interval(secs => $5))",
+            &[
+                &(index as i32),
+                &rule.realm,
+                &rule.max_age.as_secs_f64(),
+                &rule.action,
+                &config.grace_period.as_secs_f64(),
+            ],
+        ).await?;
+    }
+
+    // Events also start to match rules by getting older, which no trigger
+    // notices. So all flags are recomputed.
+    let flags_before = tx.query_one("select count(*) from retention_flags", &[]).await?;
+    tx.execute("select update_retention_flags(null)", &[]).await?;
+    let flags_after = tx.query_one("select count(*) from retention_flags", &[]).await?;
+    let flagged = flags_after.get::<_, i64>(0) - flags_before.get::<_, i64>(0);
+
+    let retracted = tx.query(
+        "update events set retracted = true \
+            from retention_flags f \
+            where f.event = events.id \
+                and f.action = 'retract' \
+                and f.due <= now() \
+                and not events.retracted \
+            returning events.id",
+        &[],
+    ).await?;
+    let retracted = retracted.iter().map(|row| row.get::<_, Key>(0)).collect::<Vec<_>>();
+    let num_retracted = retracted.len();
+
+    // The ACL of retracted events changed, so the search index has to be
+    // updated.
+    let items = retracted.into_iter().map(|key| (key, IndexItemKind::Event));
+    search::queue_many(&mut *tx, items).await?;
+    tx.commit().await?;
+
+    if flagged != 0 || num_retracted > 0 {
+        info!(
+            "Retention rules: {:+} flagged events, retracted {} events",
+            flagged,
+            num_retracted,
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! Sanitization of user-facing metadata (titles, descriptions, creators) of
+//! events and series. It is applied to harvested items and to metadata set
+//! via the API, so that the DB only contains clean text and the frontend and
+//! other consumers don't need to defend against HTML or control characters.
+
+use unicode_normalization::UnicodeNormalization;
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct SanitizeConfig {
+    /// Maximum length of titles and creator names in characters. Longer ones
+    /// are truncated.
+    #[config(default = 300)]
+    pub(crate) max_title_length: usize,
+
+    /// Maximum length of descriptions in characters. Longer ones are
+    /// truncated.
+    #[config(default = 10000)]
+    pub(crate) max_description_length: usize,
+
+    /// Whether to normalize all text to the Unicode normalization form C
+    /// (NFC), so that visually equal strings are also equal byte-wise.
+    #[config(default = true)]
+    pub(crate) unicode_normalization: bool,
+}
+
+impl SanitizeConfig {
+    /// Sanitizes a single line of text like a title or a creator name: all
+    /// tags and control characters are removed and whitespace is collapsed.
+    pub(crate) fn line(&self, s: &str) -> String {
+        // Entities are decoded first, so that encoded tags are removed, too.
+        let s = decode_entities(s);
+        let s = strip_tags(&s, false);
+        let s = s.split(|c: char| c.is_whitespace() || c.is_control() || is_invisible(c))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        self.finish(s, self.max_title_length)
+    }
+
+    /// Sanitizes a multi line text like a description: all tags and control
+    /// characters are removed, trailing whitespace of lines is removed and
+    /// more than one empty line in a row is collapsed. Returns `None` if
+    /// nothing is left.
+    pub(crate) fn text(&self, s: &str) -> Option<String> {
+        let s = decode_entities(s);
+        let s = strip_tags(&s, true);
+
+        let mut out = String::with_capacity(s.len());
+        let mut empty_lines = 0;
+        for line in s.replace("\r\n", "\n").split(['\n', '\r']) {
+            let line = line.chars()
+                .map(|c| if c == '\t' { ' ' } else { c })
+                .filter(|&c| !c.is_control() && !is_invisible(c))
+                .collect::<String>();
+            let line = line.trim_end();
+
+            if line.is_empty() {
+                empty_lines += 1;
+                continue;
+            }
+            if !out.is_empty() {
+                out.push_str(if empty_lines > 0 { "\n\n" } else { "\n" });
+            }
+            out.push_str(line);
+            empty_lines = 0;
+        }
+
+        Some(self.finish(out, self.max_description_length)).filter(|s| !s.trim().is_empty())
+    }
+
+    fn finish(&self, s: String, max_len: usize) -> String {
+        let s = if self.unicode_normalization { s.nfc().collect() } else { s };
+        truncate(s, max_len)
+    }
+}
+
+/// Characters that are not control characters, but still invisible and
+/// potentially confusing: zero width characters and bidi overrides.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}' | '\u{FEFF}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Removes all HTML tags and comments. If `line_breaks` is `true`, `<br>` and
+/// the end of block elements are replaced by line breaks. Text that only
+/// looks like a tag partially (e.g. `a < b`) is kept.
+fn strip_tags(s: &str, line_breaks: bool) -> String {
+    const BLOCK_ENDS: &[&str] = &["/p", "/div", "/li", "/h1", "/h2", "/h3", "/tr", "br", "br/"];
+
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let tag = &rest[start..];
+
+        if let Some(comment) = tag.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let closing = tag[1..].starts_with('/');
+        let name_start = if closing { 2 } else { 1 };
+        let is_tag = tag[name_start..].starts_with(|c: char| c.is_ascii_alphabetic());
+        let end = tag.find('>');
+        let (true, Some(end)) = (is_tag, end) else {
+            out.push('<');
+            rest = &tag[1..];
+            continue;
+        };
+
+        let name = tag[name_start..end]
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if line_breaks {
+            let self_closing = tag[..end].ends_with('/');
+            let key = format!(
+                "{}{name}{}",
+                if closing { "/" } else { "" },
+                if self_closing { "/" } else { "" },
+            );
+            if BLOCK_ENDS.contains(&key.as_str()) {
+                out.push('\n');
+            }
+        }
+        rest = &tag[end + 1..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Decodes the most common named HTML entities and all numeric ones.
+/// Unknown entities are kept as they are.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let entity = &rest[start..];
+        let decoded = entity.find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| {
+                let c = match &entity[1..end] {
+                    "amp" => '&',
+                    "lt" => '<',
+                    "gt" => '>',
+                    "quot" => '"',
+                    "apos" => '\'',
+                    "nbsp" => ' ',
+                    num => {
+                        let code = match num.strip_prefix("#x").or_else(|| num.strip_prefix("#X")) {
+                            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                            None => num.strip_prefix('#')?.parse().ok()?,
+                        };
+                        char::from_u32(code)?
+                    }
+                };
+                Some((c, end))
+            });
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &entity[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &entity[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Truncates `s` to at most `max_len` characters, ending with "…" if it was
+/// truncated.
+fn truncate(s: String, max_len: usize) -> String {
+    if s.chars().count() <= max_len {
+        return s;
+    }
+
+    let mut out = s.chars().take(max_len.saturating_sub(1)).collect::<String>();
+    out.truncate(out.trim_end().len());
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SanitizeConfig;
+
+    fn config() -> SanitizeConfig {
+        SanitizeConfig {
+            max_title_length: 20,
+            max_description_length: 100,
+            unicode_normalization: true,
+        }
+    }
+
+    #[test]
+    fn line() {
+        let config = config();
+        assert_eq!(config.line("  <b>Intro</b>\tto\n Physics "), "Intro to Physics");
+        assert_eq!(config.line("Tom &amp; Jerry &#8211; &#x41;&foo;"), "Tom & Jerry – A&foo;");
+        assert_eq!(config.line("a < b <!-- hidden --> c\u{202E}\u{0}"), "a < b c");
+        assert_eq!(config.line("Cafe\u{301}"), "Café");
+        assert_eq!(config.line("&lt;script&gt;alert(1)&lt;/script&gt; x"), "alert(1) x");
+        assert_eq!(config.line("A very long title that goes on"), "A very long title t…");
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            config().text(
+                "<p class=\"x\">One <B onclick=\"x\">two</B></p>\r\n\r\n\r\n<p>Three &lt;</p>",
+            ),
+            Some("One two\n\nThree <".into()),
+        );
+        assert_eq!(
+            config().text("Line<br>  next  \n\n\n\tlast &lt;3"),
+            Some("Line\n  next\n\n last <3".into()),
+        );
+        assert_eq!(config().text("&lt;p&gt;Encoded&lt;/p&gt;&lt;img src=x&gt;"), Some("Encoded".into()));
+        assert_eq!(config().text(" <p></p> \n "), None);
+    }
+}
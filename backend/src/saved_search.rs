@@ -0,0 +1,273 @@
+//! Saved searches: users can save a search query and optionally receive
+//! email alerts about new events matching it.
+//!
+//! The harvest queues newly inserted events and a worker job periodically
+//! checks them against all saved searches with alerts enabled. This matching
+//! is done by the DB (full text search over title, description and creators)
+//! and not by Meili, as the new events might not be indexed yet at that
+//! point. So alerts might differ slightly from the results of the search page.
+//!
+//! As the user is not logged in when alerts are sent, only events readable by
+//! the roles stored with the saved search are announced. These are refreshed
+//! whenever the user logs in or loads their saved searches. Alerts are paused
+//! if that was longer ago than `auth.session_duration`, as the user might
+//! have lost access to some events since.
+
+use std::{collections::BTreeMap, path::{Path, PathBuf}, process::Stdio, time::Duration};
+
+use tokio::io::AsyncWriteExt;
+use tokio_postgres::GenericClient;
+
+use crate::{
+    auth::{ROLE_ADMIN, is_valid_email},
+    config::Config,
+    db::{DbConnection, crypt::{self, Column}, types::Key},
+    metrics::{self, Job},
+    prelude::*,
+};
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct SavedSearchConfig {
+    /// Path to a `sendmail` compatible program that is used to send email
+    /// alerts for saved searches and notifications about followed series.
+    /// It is called with `-t -i` and receives the complete email via stdin.
+    /// Emails are sent by `tobira worker`, about once per minute. If not set,
+    /// emails are disabled: users can still save searches and follow series,
+    /// but are not notified about new videos via email.
+    pub(crate) sendmail: Option<PathBuf>,
+
+    /// Sender of alert emails, e.g. "Tobira <tobira@example.org>". Has to be
+    /// set if `sendmail` is set. Links in alert emails use
+    /// `general.site_url`, which has to be set as well.
+    pub(crate) from: Option<String>,
+}
+
+impl SavedSearchConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.sendmail.is_some() && self.from.is_none() {
+            bail!("`saved_searches.from` has to be set if `saved_searches.sendmail` is set");
+        }
+        if self.from.as_deref().is_some_and(|from| from.contains(char::is_control)) {
+            bail!("`saved_searches.from` must not contain control characters");
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn alerts_enabled(&self) -> bool {
+        self.sendmail.is_some()
+    }
+}
+
+/// How often queued events are checked against saved searches.
+const ALERT_PERIOD: Duration = Duration::from_secs(60);
+
+/// Queues newly inserted events to be checked against saved searches by
+/// `alert_daemon`. Does nothing if alerts are disabled.
+pub(crate) async fn queue_alerts(
+    new_events: &[i64],
+    db: &impl GenericClient,
+    config: &Config,
+) -> Result<()> {
+    if !config.saved_searches.alerts_enabled() || new_events.is_empty() {
+        return Ok(());
+    }
+
+    db.execute(
+        "insert into saved_search_alert_queue (event) select unnest($1::bigint[]) \
+            on conflict do nothing",
+        &[&new_events],
+    ).await.context("failed to queue events for saved search alerts")?;
+
+    Ok(())
+}
+
+/// Periodically sends alerts about queued events (see `queue_alerts`).
+pub(crate) async fn alert_daemon(mut db: DbConnection, config: &Config) {
+    if !config.saved_searches.alerts_enabled() {
+        return futures::future::pending().await;
+    }
+
+    loop {
+        let result = send_alerts(&mut db, config).await;
+        if let Err(e) = &result {
+            error!("Failed to send saved search alerts: {:?}", e);
+        }
+        metrics::job_run(Job::SavedSearchAlerts, result.is_ok(), &**db).await;
+
+        tokio::time::sleep(ALERT_PERIOD).await;
+    }
+}
+
+/// Takes all queued events, checks them against all saved searches with
+/// alerts enabled and sends one email per saved search with matching events.
+/// Failing to send a single email is only logged and not retried.
+async fn send_alerts(db: &mut DbConnection, config: &Config) -> Result<()> {
+    let (Some(sendmail), Some(from), Some(site_url)) = (
+        &config.saved_searches.sendmail,
+        &config.saved_searches.from,
+        &config.general.site_url,
+    ) else {
+        return Ok(());
+    };
+
+    // Events are only removed from the queue if matching them succeeded.
+    let tx = db.transaction().await?;
+    let new_events = tx
+        .query("delete from saved_search_alert_queue returning event", &[])
+        .await
+        .context("failed to take events from the alert queue")?
+        .into_iter()
+        .map(|row| row.get::<_, i64>(0))
+        .collect::<Vec<_>>();
+    if new_events.is_empty() {
+        return Ok(());
+    }
+
+    let rows = tx
+        .query(
+            "select saved_searches.id, saved_searches.alert_email, saved_searches.query, \
+                    events.id, events.title \
+                from saved_searches \
+                join events on events.id = any($1) \
+                    and (events.read_roles && saved_searches.roles \
+                        or $2 = any(saved_searches.roles)) \
+                    and to_tsvector('simple', events.title \
+                        || ' ' || coalesce(events.description, '') \
+                        || ' ' || array_to_string(events.creators, ' ')) \
+                        @@ plainto_tsquery('simple', saved_searches.query) \
+                where saved_searches.alert_email is not null \
+                    and saved_searches.roles_updated > now() - make_interval(secs => $3) \
+                order by saved_searches.id, events.created",
+            &[&new_events, &ROLE_ADMIN, &config.auth.session_duration.as_secs_f64()],
+        )
+        .await
+        .context("failed to match new events against saved searches")?;
+    tx.commit().await?;
+
+    // Group the matching events by saved search.
+    let mut alerts = BTreeMap::<i64, (String, String, Vec<(Key, String)>)>::new();
+    for row in rows {
+        let (_, _, events) = alerts.entry(row.get(0))
+            .or_insert_with(|| (row.get(1), row.get(2), vec![]));
+        events.push((row.get(3), row.get(4)));
+    }
+
+    let site_url = site_url.to_string();
+    let site_title = config.general.site_title.en();
+    for (to, query, events) in alerts.into_values() {
+        let to = match crypt::decrypt(&config.db, Column::ALERT_EMAIL, &to) {
+            Ok(to) => to,
+            Err(e) => {
+                error!("Skipping saved search alert: {:#}", e);
+                continue;
+            }
+        };
+        // Addresses stored before they were validated might be unsafe to
+        // put into the `To` header.
+        if !is_valid_email(&to) {
+            warn!("Skipping saved search alert to invalid address '{}'", to);
+            continue;
+        }
+        let mail = alert_mail(from, &to, &query, &events, &site_url, site_title);
+        match send_mail(sendmail, &mail).await {
+            Ok(()) => debug!("Sent saved search alert about {} events to {}", events.len(), to),
+            Err(e) => error!("Failed to send saved search alert to {}: {:?}", to, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the stored roles of all saved searches of the given user, e.g.
+/// after they logged in.
+pub(crate) async fn refresh_roles(
+    username: &str,
+    roles: &[String],
+    db: &impl GenericClient,
+) -> Result<(), tokio_postgres::Error> {
+    db.execute(
+        "update saved_searches set roles = $2, roles_updated = now() where username = $1",
+        &[&username, &roles],
+    ).await?;
+    Ok(())
+}
+
+fn alert_mail(
+    from: &str,
+    to: &str,
+    query: &str,
+    events: &[(Key, String)],
+    site_url: &str,
+    site_title: &str,
+) -> String {
+    // Non-ASCII characters in headers have to be encoded (RFC 2047).
+    let subject = format!("New videos for “{query}”");
+    let subject = format!("=?utf-8?B?{}?=", base64::encode(subject));
+
+    let mut body = format!("New videos matching your saved search “{query}” \
+        are available on {site_title}:\n\n");
+    for (key, title) in events {
+        let mut buf = [0; 11];
+        body += &format!("- {title}\n  {site_url}/!v/{}\n", key.to_base64(&mut buf));
+    }
+    body += "\nYou receive this email because you enabled alerts for this search. \
+        To stop them, remove the saved search.\n";
+
+    format!("From: {from}\r\n\
+        To: {to}\r\n\
+        Subject: {subject}\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        Content-Transfer-Encoding: 8bit\r\n\
+        \r\n\
+        {body}")
+}
+
+pub(crate) async fn send_mail(sendmail: &Path, mail: &str) -> Result<()> {
+    let mut child = tokio::process::Command::new(sendmail)
+        .args(["-t", "-i"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to start '{}'", sendmail.display()))?;
+
+    // Dropping `stdin` closes it, signaling the end of the email.
+    let mut stdin = child.stdin.take().expect("stdin of child process is not piped");
+    stdin.write_all(mail.as_bytes()).await?;
+    drop(stdin);
+
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!("'{}' exited with {}", sendmail.display(), status);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::types::Key;
+    use super::alert_mail;
+
+    #[test]
+    fn alert_mail_headers_and_links() {
+        let mail = alert_mail(
+            "Tobira <tobira@example.org>",
+            "peter@example.org",
+            "physik",
+            &[(Key(0), "Einführung".into())],
+            "https://tobira.example.org",
+            "Tobira",
+        );
+        let (headers, body) = mail.split_once("\r\n\r\n").unwrap();
+
+        assert!(headers.is_ascii());
+        assert!(headers.contains("\r\nTo: peter@example.org\r\n"));
+        assert!(headers.contains(&format!(
+            "\r\nSubject: =?utf-8?B?{}?=\r\n",
+            base64::encode("New videos for “physik”"),
+        )));
+        assert!(body.contains("- Einführung\n  https://tobira.example.org/!v/AAAAAAAAAAA\n"));
+    }
+}
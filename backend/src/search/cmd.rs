@@ -1,9 +1,13 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
 use meilisearch_sdk::indexes::Index;
+use serde::Serialize;
 use structopt::StructOpt;
 
-use crate::{prelude::*, config::Config, db};
+use crate::{prelude::*, config::Config, db::{self, DbConnection, types::Key}};
 
-use super::{Client, util};
+use super::{Client, Event, IndexItem, IndexItemKind, Realm, util};
 
 
 #[derive(Debug, StructOpt)]
@@ -27,6 +31,16 @@ pub(crate) enum SearchIndexCommand {
         without_clear: bool,
     },
 
+    /// Compares the search index with the DB and reports missing, stale and
+    /// orphaned entries. Items that are queued for an update are ignored.
+    /// Fails if any discrepancies are found, unless `--repair` is given.
+    Check {
+        /// If specified, queues all items with discrepancies for an update.
+        /// The queue is processed by the worker or `search-index update`.
+        #[structopt(long)]
+        repair: bool,
+    },
+
     /// Reads queued updates from the DB and pushes them into the search index.
     Update {
         /// If specified, will not stop after clearing the queue, but runs
@@ -41,7 +55,8 @@ pub(crate) async fn run(cmd: &SearchIndexCommand, config: &Config) -> Result<()>
     let meili = config.meili.connect_only().await?;
 
     match cmd {
-        SearchIndexCommand::Status => status(&meili).await?,
+        SearchIndexCommand::Status => status(&meili, config).await?,
+        SearchIndexCommand::Check { repair } => check(&meili, config, *repair).await?,
         SearchIndexCommand::Clear { yes_absolutely_clear_index: yes } => clear(meili, *yes).await?,
         SearchIndexCommand::Update { daemon } => update(&meili, config, *daemon).await?,
         SearchIndexCommand::Rebuild { without_clear } => {
@@ -56,6 +71,13 @@ pub(crate) async fn run(cmd: &SearchIndexCommand, config: &Config) -> Result<()>
     Ok(())
 }
 
+macro_rules! info_line {
+    ($label:expr, $value:expr) => {
+        bunt::println!("{$dimmed}{}:{/$} {[blue+intense]}", $label, $value);
+    };
+}
+
+
 // ===== Rebuild ===============================================================================
 
 async fn rebuild(meili: &Client, config: &Config) -> Result<()> {
@@ -66,6 +88,140 @@ async fn rebuild(meili: &Client, config: &Config) -> Result<()> {
 }
 
 
+// ===== Check =================================================================================
+
+/// Keys of items for which the index does not match the DB.
+#[derive(Default)]
+struct Discrepancies {
+    /// In the DB, but not in the index.
+    missing: Vec<Key>,
+    /// In both, but with different data.
+    stale: Vec<Key>,
+    /// In the index, but not in the DB anymore.
+    orphaned: Vec<Key>,
+}
+
+impl Discrepancies {
+    fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.stale.is_empty() && self.orphaned.is_empty()
+    }
+
+    fn keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.missing.iter().chain(&self.stale).chain(&self.orphaned).copied()
+    }
+}
+
+async fn check(meili: &Client, config: &Config, repair: bool) -> Result<()> {
+    let pool = db::create_pool(&config.db).await?;
+    let mut db = pool.get().await?;
+
+    if !check_consistency(meili, &mut db, repair).await? {
+        bail!("the search index is inconsistent with the DB: run with `--repair` to fix it");
+    }
+
+    Ok(())
+}
+
+/// Prints the discrepancies between the search index and the DB. If `repair`
+/// is set, all affected items are queued for an update. Returns whether the
+/// index is consistent or was repaired.
+pub(crate) async fn check_consistency(
+    meili: &Client,
+    db: &mut DbConnection,
+    repair: bool,
+) -> Result<bool> {
+    let events = Event::load_all(&***db).await?;
+    let events = check_index(&meili.event_index, events, db).await?;
+    let realms = Realm::load_all(&***db).await?;
+    let realms = check_index(&meili.realm_index, realms, db).await?;
+
+    for (name, discrepancies) in [("event", &events), ("realm", &realms)] {
+        bunt::println!("{$bold}# Index `{[green+intense]}`:{/$}", name);
+        info_line!("Missing", discrepancies.missing.len());
+        info_line!("Stale", discrepancies.stale.len());
+        info_line!("Orphaned", discrepancies.orphaned.len());
+        println!();
+    }
+
+    if events.is_empty() && realms.is_empty() {
+        println!("The search index is consistent with the DB.");
+        return Ok(true);
+    }
+
+    if !repair {
+        return Ok(false);
+    }
+
+    let items = events.keys().map(|key| (key, IndexItemKind::Event))
+        .chain(realms.keys().map(|key| (key, IndexItemKind::Realm)))
+        .collect::<Vec<_>>();
+    let count = items.len();
+    super::queue_many(&mut ***db, items).await?;
+    println!("Queued {count} items for an update. The queue is processed by the worker or \
+        `tobira search-index update`.");
+
+    Ok(true)
+}
+
+/// Compares all documents in `index` with the items loaded from the DB.
+async fn check_index<T: IndexItem + Serialize + 'static>(
+    index: &Index,
+    db_items: Vec<T>,
+    db: &DbConnection,
+) -> Result<Discrepancies> {
+    const PAGE_SIZE: usize = 1000;
+
+    let queued = db
+        .query(
+            "select item_id from search_index_queue where kind = $1",
+            &[&T::KIND],
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.get::<_, Key>(0))
+        .collect::<HashSet<_>>();
+
+    let mut expected = db_items.iter()
+        .map(|item| Ok((item.id().0, serde_json::to_value(item)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let mut out = Discrepancies::default();
+    let mut offset = 0;
+    loop {
+        let documents = match index.get_documents::<T>(Some(offset), Some(PAGE_SIZE), None).await {
+            Ok(documents) => documents,
+            Err(e) if util::is_index_not_found(&e) => {
+                bail!(
+                    "the {} index does not exist: run `search-index rebuild`",
+                    T::KIND.plural_name(),
+                );
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let num_documents = documents.len();
+
+        for document in documents {
+            let key = document.id().0;
+            match expected.remove(&key) {
+                _ if queued.contains(&key) => {}
+                None => out.orphaned.push(key),
+                Some(value) if value != serde_json::to_value(&document)? => out.stale.push(key),
+                Some(_) => {}
+            }
+        }
+
+        if num_documents < PAGE_SIZE {
+            break;
+        }
+        offset += PAGE_SIZE;
+    }
+
+    out.missing = expected.into_keys().filter(|key| !queued.contains(key)).collect();
+
+    Ok(out)
+}
+
+
 // ===== Update ================================================================================
 
 async fn update(meili: &Client, config: &Config, daemon: bool) -> Result<()> {
@@ -76,7 +232,10 @@ async fn update(meili: &Client, config: &Config, daemon: bool) -> Result<()> {
     if daemon {
         super::update_index_daemon(meili, &mut db).await.map(|_| ())
     } else {
-        super::update_index(meili, &mut db).await
+        if super::update_index(meili, &mut db).await? > 0 {
+            super::refresh_search_words(&db).await?;
+        }
+        Ok(())
     }
 }
 
@@ -103,13 +262,7 @@ async fn clear(meili: Client, yes: bool) -> Result<()> {
 
 // ===== Status ================================================================================
 
-macro_rules! info_line {
-    ($label:expr, $value:expr) => {
-        bunt::println!("{$dimmed}{}:{/$} {[blue+intense]}", $label, $value);
-    };
-}
-
-async fn status(meili: &Client) -> Result<()> {
+async fn status(meili: &Client, config: &Config) -> Result<()> {
     // Configuration
     println!();
     bunt::println!("{$bold}# Configuration:{/$}");
@@ -137,6 +290,20 @@ async fn status(meili: &Client) -> Result<()> {
     info_line!("Health", meili.client.health().await?.status);
     println!();
 
+    // Queue of pending updates, i.e. how far the index lags behind the DB.
+    let pool = db::create_pool(&config.db).await?;
+    let row = pool.get().await?
+        .query_one("select count(*), min(queued_at) from search_index_queue", &[])
+        .await?;
+    let oldest: Option<DateTime<Utc>> = row.get(1);
+    bunt::println!("{$bold}# Update queue:{/$}");
+    info_line!("Queued items", row.get::<_, i64>(0));
+    if let Some(oldest) = oldest {
+        let lag = Utc::now().signed_duration_since(oldest).to_std().unwrap_or_default();
+        info_line!("Oldest item queued", format!("{} ({:.0?} ago)", oldest, lag));
+    }
+    println!();
+
     // Individual indexes
     index_status("event", &meili.event_index).await?;
     println!();
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use deadpool_postgres::Transaction;
 use meilisearch_sdk::{document::Document, tasks::Task, indexes::Index};
 use serde::{Serialize, Deserialize};
@@ -23,6 +24,14 @@ pub(crate) struct Event {
     pub(crate) thumbnail: Option<String>,
     pub(crate) duration: i32,
 
+    /// Filterable. Only `false` for documents indexed by older Tobira versions.
+    #[serde(default)]
+    pub(crate) has_captions: bool,
+
+    /// Only `None` for documents indexed by older Tobira versions.
+    #[serde(default)]
+    pub(crate) created: Option<DateTime<Utc>>,
+
     // These are filterable. All roles are hex encoded to work around Meilis
     // inability to filter case-sensitively. For roles, we have to compare
     // case-sensitively. Encoding as hex is one possibility. There likely also
@@ -35,6 +44,16 @@ pub(crate) struct Event {
     // items.
     pub(crate) read_roles: Vec<String>,
     pub(crate) write_roles: Vec<String>,
+
+    /// Paths of the realms showing this event via a video or series block,
+    /// encoded with `util::encode_path`. Filterable.
+    #[serde(default)]
+    pub(crate) host_realms: Vec<String>,
+
+    /// Like `host_realms`, but also including all ancestors of these realms,
+    /// i.e. the paths of all realms in whose subtree this event appears.
+    #[serde(default)]
+    pub(crate) host_subtrees: Vec<String>,
 }
 
 impl Document for Event {
@@ -52,9 +71,24 @@ impl Event {
     const SQL_SELECT_FIELDS: &'static str = "\
         events.id, \
         events.series, series.title, \
-        events.title, events.description, events.creators, \
-        events.thumbnail, events.duration, \
-        events.read_roles, events.write_roles\
+        events.title, \
+        case when events.passphrase_hash is null then events.description end, \
+        events.creators, \
+        case when events.passphrase_hash is null then events.thumbnail end, \
+        coalesce(events.trim_end - events.trim_start, events.duration), \
+        events.read_roles, events.write_roles, \
+        events.created, \
+        cardinality(events.captions) > 0, \
+        array( \
+            select distinct realms.full_path from blocks \
+            join realms on realms.id = blocks.realm_id \
+            where blocks.video_id = events.id or blocks.series_id = events.series \
+        ), \
+        array( \
+            select distinct ancestors.full_path from blocks \
+            cross join lateral ancestors_of_realm(blocks.realm_id) as ancestors \
+            where blocks.video_id = events.id or blocks.series_id = events.series \
+        )\
     ";
 
     /// Converts a row to `Self` when the query selected `SQL_SELECT_FIELDS`.
@@ -70,6 +104,10 @@ impl Event {
             duration: row.get(7),
             read_roles: util::encode_acl(&row.get::<_, Vec<String>>(8)),
             write_roles: util::encode_acl(&row.get::<_, Vec<String>>(9)),
+            created: row.get(10),
+            has_captions: row.get(11),
+            host_realms: encode_paths(row.get(12)),
+            host_subtrees: encode_paths(row.get(13)),
         }
     }
 
@@ -109,6 +147,10 @@ pub(super) async fn prepare_index(index: &Index) -> Result<()> {
         index,
         "event",
         &["title", "creators", "description", "series_title"],
-        &["read_roles", "write_roles"],
+        &["read_roles", "write_roles", "host_realms", "host_subtrees", "has_captions"],
     ).await
 }
+
+fn encode_paths(paths: Vec<String>) -> Vec<String> {
+    paths.iter().map(|path| util::encode_path(path)).collect()
+}
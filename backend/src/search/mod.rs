@@ -28,7 +28,8 @@ mod util;
 pub(crate) use self::{
     event::Event,
     realm::Realm,
-    update::{update_index, update_index_daemon},
+    update::{refresh_search_words, update_index, update_index_daemon},
+    util::encode_path,
 };
 
 // ===== Configuration ============================================================================
@@ -55,6 +56,72 @@ pub(crate) struct MeiliConfig {
     update_interval: Duration,
 }
 
+/// Tuning of the order of search results. Tobira computes its own relevance
+/// score for each result from the matched fields. That score decides how
+/// events and realms are interleaved. If `recency_boost` or `realm_boost` is
+/// set, events are also ordered by that score instead of Meili's ranking.
+#[derive(Debug, Clone, confique::Config)]
+pub(crate) struct SearchConfig {
+    /// Weight of matches in the title of events.
+    #[config(default = 10.0)]
+    pub(crate) title_weight: f64,
+
+    /// Weight of matches in the creators of events.
+    #[config(default = 3.0)]
+    pub(crate) creators_weight: f64,
+
+    /// Weight of matches in the description of events.
+    #[config(default = 2.0)]
+    pub(crate) description_weight: f64,
+
+    /// Weight of matches in the title of the series of events.
+    #[config(default = 1.0)]
+    pub(crate) series_title_weight: f64,
+
+    /// Weight of matches in the name of realms.
+    #[config(default = 10.0)]
+    pub(crate) realm_name_weight: f64,
+
+    /// Added to the score of events and realms whose title or name is
+    /// exactly the search query.
+    #[config(default = 200.0)]
+    pub(crate) exact_match_boost: f64,
+
+    /// Added to the score of new events. An event created just now gets the
+    /// full boost, which is halved every `recency_half_life`. 0 disables it.
+    #[config(default = 0.0)]
+    pub(crate) recency_boost: f64,
+
+    /// See `recency_boost`. Must be greater than 0.
+    #[config(default = "365d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) recency_half_life: Duration,
+
+    /// Added to the score of events that appear in the realm the search was
+    /// started from (or its descendants). 0 disables it.
+    #[config(default = 0.0)]
+    pub(crate) realm_boost: f64,
+
+    /// If a search returns fewer results than this, Tobira tries to suggest
+    /// a corrected query ("did you mean"). The suggestion is built from
+    /// titles of public events and series and realm names. 0 disables it.
+    #[config(default = 3)]
+    pub(crate) suggestion_threshold: u32,
+}
+
+impl SearchConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.recency_half_life.is_zero() {
+            bail!("`search.recency_half_life` must be greater than 0");
+        }
+        Ok(())
+    }
+
+    /// Whether events have to be reordered by Tobira's score.
+    pub(crate) fn reorders_events(&self) -> bool {
+        self.recency_boost != 0.0 || self.realm_boost != 0.0
+    }
+}
+
 impl MeiliConfig {
     /// Connects to Meili, tests the connections and prepares all indexes.
     pub(crate) async fn connect_and_prepare(&self, db: &mut DbConnection) -> Result<Client> {
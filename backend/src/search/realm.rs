@@ -19,6 +19,15 @@ pub(crate) struct Realm {
     /// itself. It starts with a direct child of the root and ends with the
     /// parent of `self`.
     pub(crate) ancestor_names: Vec<String>,
+
+    /// `full_path` encoded with `util::encode_path`. Filterable.
+    #[serde(default)]
+    pub(crate) encoded_path: String,
+
+    /// Encoded paths of this realm and all its ancestors, i.e. of all realms
+    /// in whose subtree this realm is. Filterable.
+    #[serde(default)]
+    pub(crate) subtrees: Vec<String>,
 }
 
 impl Document for Realm {
@@ -37,7 +46,8 @@ impl Realm {
         id, \
         name, \
         full_path, \
-        ARRAY(select name from ancestors_of_realm(id) where height <> 0 offset 1)\
+        ARRAY(select name from ancestors_of_realm(id) where height <> 0 offset 1), \
+        ARRAY(select full_path from ancestors_of_realm(id))\
     ";
 
     /// Converts a row to `Self` when the query selected `SQL_SELECT_FIELDS`.
@@ -47,17 +57,27 @@ impl Realm {
             name: row.get(1),
             full_path: row.get(2),
             ancestor_names: row.get(3),
+            encoded_path: util::encode_path(&row.get::<_, String>(2)),
+            subtrees: row.get::<_, Vec<String>>(4).iter().map(|p| util::encode_path(p)).collect(),
         }
     }
 
+    /// Realms hidden from the search are never loaded, so they are removed
+    /// from the index when they are queued.
     pub(crate) async fn load_by_ids(db: &impl GenericClient, ids: &[Key]) -> Result<Vec<Self>> {
-        let query = format!("select {} from realms where id = any($1)", Self::SQL_SELECT_FIELDS);
+        let query = format!(
+            "select {} from realms where id = any($1) and not hidden_from_search",
+            Self::SQL_SELECT_FIELDS,
+        );
         let rows = db.query_raw(&query, dbargs![&ids]);
         collect_rows_mapped(rows, Self::from_row).await.map_err(Into::into)
     }
 
     pub(crate) async fn load_all(db: &impl GenericClient) -> Result<Vec<Self>> {
-        let query = format!("select {} from realms", Self::SQL_SELECT_FIELDS);
+        let query = format!(
+            "select {} from realms where not hidden_from_search",
+            Self::SQL_SELECT_FIELDS,
+        );
         let rows = db.query_raw(&query, dbargs![]);
         collect_rows_mapped(rows, Self::from_row).await.map_err(Into::into)
     }
@@ -77,5 +97,6 @@ pub(super) async fn rebuild(meili: &Client, db: &Transaction<'_>) -> Result<Task
 }
 
 pub(super) async fn prepare_index(index: &Index) -> Result<()> {
-    util::lazy_set_special_attributes(index, "relam", &["name"], &[]).await
+    util::lazy_set_special_attributes(index, "relam", &["name"], &["encoded_path", "subtrees"])
+        .await
 }
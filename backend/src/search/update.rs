@@ -1,7 +1,7 @@
 use std::{
     collections::HashSet,
     future::Future,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use crate::{
@@ -16,12 +16,25 @@ use super::{
 };
 
 
+/// Minimum time between two refreshes of the `search_words` view, as each
+/// refresh scans all public events.
+const SEARCH_WORDS_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 /// Calls `update_index` roughly every `config.update_interval` and never returns.
 pub(crate) async fn update_index_daemon(meili: &Client, db: &mut DbConnection) -> Result<Never> {
+    let mut words_stale = false;
+    let mut words_refreshed_at = None::<Instant>;
     loop {
         let loop_started_at = Instant::now();
 
-        update_index(meili, db).await?;
+        words_stale |= update_index(meili, db).await? > 0;
+        if words_stale
+            && words_refreshed_at.is_none_or(|t| t.elapsed() >= SEARCH_WORDS_REFRESH_INTERVAL)
+        {
+            refresh_search_words(db).await?;
+            words_stale = false;
+            words_refreshed_at = Some(Instant::now());
+        }
 
         let next_update_in = meili.config.update_interval.saturating_sub(loop_started_at.elapsed());
         trace!("Cleared search index queue: waiting for {:.1?}", next_update_in);
@@ -29,13 +42,26 @@ pub(crate) async fn update_index_daemon(meili: &Client, db: &mut DbConnection) -
     }
 }
 
+/// The words used for spelling suggestions are derived from the indexed
+/// data, so they have to be refreshed after the index was updated.
+pub(crate) async fn refresh_search_words(db: &DbConnection) -> Result<()> {
+    let before = Instant::now();
+    db.execute("refresh materialized view concurrently search_words", &[]).await
+        .context("failed to refresh search words")?;
+    debug!("Refreshed search words (in {:.2?})", before.elapsed());
+
+    Ok(())
+}
+
 /// Processes the "search index queue" in the DB by dequeuing some items and
-/// sending them to the search index. Stops once the queue is empty.
-pub(crate) async fn update_index(meili: &Client, db: &mut DbConnection) -> Result<()> {
+/// sending them to the search index. Stops once the queue is empty. Returns
+/// the number of processed items.
+pub(crate) async fn update_index(meili: &Client, db: &mut DbConnection) -> Result<usize> {
     const CHUNK_SIZE: u32 = 5000;
 
+    let mut processed = 0;
     loop {
-        let done = writer::with_write_lock(db, meili, move |tx, meili| Box::pin(async move {
+        let (done, count) = writer::with_write_lock(db, meili, move |tx, meili| Box::pin(async move {
             // First, we retrieve a list of items that need updating.
             let query = format!("select item_id, kind \
                 from search_index_queue \
@@ -60,7 +86,7 @@ pub(crate) async fn update_index(meili: &Client, db: &mut DbConnection) -> Resul
             let count = event_ids.len() + realm_ids.len();
             if count == 0 {
                 trace!("No index update queued -> doing nothing");
-                return Ok(true);
+                return Ok((true, 0));
             }
 
             trace!("Loaded {} IDs from search index queue", count);
@@ -85,16 +111,16 @@ pub(crate) async fn update_index(meili: &Client, db: &mut DbConnection) -> Resul
                     but deleted {affected}");
             }
 
-            Ok(count < CHUNK_SIZE as usize)
+            Ok((count < CHUNK_SIZE as usize, count))
         })).await?;
 
-
+        processed += count;
         if done {
             break;
         }
     }
 
-    Ok(())
+    Ok(processed)
 }
 
 impl MeiliWriter<'_> {
@@ -44,6 +44,13 @@ pub(super) fn encode_acl(roles: &[String]) -> Vec<String> {
         .collect()
 }
 
+/// Encodes a realm path to be stored in the index and used in filters. Like
+/// roles, paths are hex encoded to be filterable case-sensitively. A `/` is
+/// appended first, so that the root realm is not encoded as empty string.
+pub(crate) fn encode_path(path: &str) -> String {
+    hex::encode(format!("{path}/"))
+}
+
 /// Returns `true` if the given error has the error code `IndexNotFound`
 pub(super) fn is_index_not_found(err: &Error) -> bool {
     matches!(err, Error::Meilisearch(e) if e.error_code == ErrorCode::IndexNotFound)
@@ -0,0 +1,216 @@
+//! Following series: users are notified about new events in series they
+//! follow, in the app and, if they want, via email.
+//!
+//! Like saved search alerts (see `saved_search.rs`), the harvest queues new
+//! events and a worker job periodically sends the notifications. The same
+//! rules apply: only events readable by the roles stored with the follow are
+//! announced, and notifications are paused if these were not refreshed for
+//! longer than `auth.session_duration`. Emails are sent with the settings of
+//! `saved_searches`.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use tokio_postgres::GenericClient;
+
+use crate::{
+    auth::{ROLE_ADMIN, is_valid_email},
+    config::Config,
+    db::{DbConnection, crypt::{self, Column}, types::Key},
+    metrics::{self, Job},
+    prelude::*,
+    saved_search,
+};
+
+
+/// How often queued events are announced to followers.
+const NOTIFY_PERIOD: Duration = Duration::from_secs(60);
+
+/// Queues newly inserted events that are in a followed series, to be
+/// announced by `notify_daemon`.
+pub(crate) async fn queue_new_events(new_events: &[i64], db: &impl GenericClient) -> Result<()> {
+    if new_events.is_empty() {
+        return Ok(());
+    }
+
+    db.execute(
+        "insert into series_follow_queue (event) \
+            select id from events \
+                where id = any($1) \
+                    and exists(select from series_follows where series = events.series) \
+            on conflict do nothing",
+        &[&new_events],
+    ).await.context("failed to queue events for series followers")?;
+
+    Ok(())
+}
+
+/// Periodically announces queued events (see `queue_new_events`).
+pub(crate) async fn notify_daemon(mut db: DbConnection, config: &Config) {
+    loop {
+        let result = notify(&mut db, config).await;
+        if let Err(e) = &result {
+            error!("Failed to notify series followers: {:?}", e);
+        }
+        metrics::job_run(Job::FollowNotifications, result.is_ok(), &**db).await;
+
+        tokio::time::sleep(NOTIFY_PERIOD).await;
+    }
+}
+
+/// Takes all queued events and creates in-app notifications for all
+/// followers of their series. Followers with email notifications also get
+/// one email listing all their new events. Failing to send a single email is
+/// only logged and not retried.
+async fn notify(db: &mut DbConnection, config: &Config) -> Result<()> {
+    // Events are only removed from the queue if the notifications were
+    // stored.
+    let tx = db.transaction().await?;
+    let new_events = tx
+        .query("delete from series_follow_queue returning event", &[])
+        .await
+        .context("failed to take events from the series follow queue")?
+        .into_iter()
+        .map(|row| row.get::<_, i64>(0))
+        .collect::<Vec<_>>();
+    if new_events.is_empty() {
+        return Ok(());
+    }
+
+    let rows = tx
+        .query(
+            "with announced as ( \
+                select series_follows.*, events.id as event, events.title as event_title, \
+                        events.created as event_created, series.title as series_title \
+                    from series_follows \
+                    join events on events.id = any($1) \
+                        and events.series = series_follows.series \
+                        and (events.read_roles && series_follows.roles \
+                            or $2 = any(series_follows.roles)) \
+                    join series on series.id = series_follows.series \
+                    where series_follows.roles_updated > now() - make_interval(secs => $3) \
+            ), inserted as ( \
+                insert into notifications (recipient, kind, event) \
+                    select distinct user_role, 'new_series_event'::notification_kind, event \
+                        from announced \
+                        where user_role is not null \
+            ) \
+            select email, event, event_title, series_title \
+                from announced \
+                where email is not null \
+                order by username, event_created",
+            &[&new_events, &ROLE_ADMIN, &config.auth.session_duration.as_secs_f64()],
+        )
+        .await
+        .context("failed to notify followers about new events")?;
+    tx.commit().await?;
+    debug!("Announced {} new events to series followers", new_events.len());
+
+    let (Some(sendmail), Some(from), Some(site_url)) = (
+        &config.saved_searches.sendmail,
+        &config.saved_searches.from,
+        &config.general.site_url,
+    ) else {
+        return Ok(());
+    };
+
+    // Group the new events by recipient. Encrypted addresses differ for each
+    // follow, so they are decrypted first.
+    let mut mails = BTreeMap::<String, Vec<(Key, String, String)>>::new();
+    for row in rows {
+        let to = match crypt::decrypt(&config.db, Column::FOLLOW_EMAIL, row.get(0)) {
+            Ok(to) => to,
+            Err(e) => {
+                error!("Skipping series follow notification: {:#}", e);
+                continue;
+            }
+        };
+        mails.entry(to).or_default().push((row.get(1), row.get(2), row.get(3)));
+    }
+
+    let site_url = site_url.to_string();
+    let site_title = config.general.site_title.en();
+    for (to, events) in mails {
+        if !is_valid_email(&to) {
+            warn!("Skipping series follow notification to invalid address '{}'", to);
+            continue;
+        }
+        let mail = notification_mail(from, &to, &events, &site_url, site_title);
+        match saved_search::send_mail(sendmail, &mail).await {
+            Ok(()) => debug!("Sent notification about {} new events to {}", events.len(), to),
+            Err(e) => error!("Failed to send series follow notification to {}: {:?}", to, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the stored roles of all follows of the given user, e.g. after
+/// they logged in.
+pub(crate) async fn refresh_roles(
+    username: &str,
+    roles: &[String],
+    db: &impl GenericClient,
+) -> Result<(), tokio_postgres::Error> {
+    db.execute(
+        "update series_follows set roles = $2, roles_updated = now() where username = $1",
+        &[&username, &roles],
+    ).await?;
+    Ok(())
+}
+
+/// `events` contains the key and title of each event and the title of its
+/// series.
+fn notification_mail(
+    from: &str,
+    to: &str,
+    events: &[(Key, String, String)],
+    site_url: &str,
+    site_title: &str,
+) -> String {
+    let subject = format!("New videos on {site_title}");
+    let subject = format!("=?utf-8?B?{}?=", base64::encode(subject));
+
+    let mut body = format!("New videos in series you follow are available on {site_title}:\n\n");
+    for (key, title, series_title) in events {
+        let mut buf = [0; 11];
+        body += &format!(
+            "- {title} ({series_title})\n  {site_url}/!v/{}\n",
+            key.to_base64(&mut buf),
+        );
+    }
+    body += "\nYou receive this email because you follow these series. \
+        To stop these emails, unfollow them.\n";
+
+    format!("From: {from}\r\n\
+        To: {to}\r\n\
+        Subject: {subject}\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        Content-Transfer-Encoding: 8bit\r\n\
+        \r\n\
+        {body}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::types::Key;
+    use super::notification_mail;
+
+    #[test]
+    fn notification_mail_lists_events() {
+        let mail = notification_mail(
+            "Tobira <tobira@example.org>",
+            "peter@example.org",
+            &[(Key(0), "Vorlesung 3".into(), "Physik für Anfänger".into())],
+            "https://tobira.example.org",
+            "Tobira",
+        );
+        let (headers, body) = mail.split_once("\r\n\r\n").unwrap();
+
+        assert!(headers.is_ascii());
+        assert!(headers.contains("\r\nTo: peter@example.org\r\n"));
+        assert!(body.contains(
+            "- Vorlesung 3 (Physik für Anfänger)\n  https://tobira.example.org/!v/AAAAAAAAAAA\n",
+        ));
+    }
+}
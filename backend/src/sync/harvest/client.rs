@@ -37,19 +37,11 @@ impl HarvestClient {
             .build();
         let http_client = Client::builder().build(https);
 
-        // Prepare authentication
-        let credentials = format!(
-            "{}:{}",
-            config.sync.user,
-            config.sync.password.expose_secret(),
-        );
-        let auth_header = format!("Basic {}", base64::encode(credentials));
-
         Self {
             http_client,
             scheme: config.opencast.sync_node().scheme.clone(),
             authority: config.opencast.sync_node().authority.clone(),
-            auth_header: Secret::new(auth_header),
+            auth_header: config.sync.basic_auth_header(),
         }
     }
 
@@ -1,15 +1,18 @@
 use std::{
-    cmp::min,
+    cmp::{max, min},
     time::{Duration, Instant},
 };
 
 use hyper::http::status::StatusCode;
-use tokio_postgres::types::ToSql;
 
 use crate::{
-    db::{types::{EventTrack, Key}, DbConnection},
+    db::DbConnection,
+    metrics::{self, Job},
     prelude::*,
-    search::{self, IndexItemKind}, config::Config,
+    config::Config,
+    sanitize::SanitizeConfig,
+    saved_search,
+    series_follow,
 };
 use super::status::SyncStatus;
 use self::{client::HarvestClient, response::{HarvestItem, HarvestResponse}};
@@ -18,6 +21,8 @@ use self::{client::HarvestClient, response::{HarvestItem, HarvestResponse}};
 
 mod client;
 mod response;
+pub(crate) mod transform;
+mod write;
 
 
 // TODO: make (some of) this stuff configurable.
@@ -25,6 +30,11 @@ mod response;
 const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
 const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
 
+/// Only new events that were changed in Opencast at most this long ago are
+/// announced to users (via notifications and saved search alerts). This avoids
+/// announcements of old events when syncing for the first time.
+const MAX_NOTIFICATION_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
 
 /// Continuiously fetches from the harvesting API and writes new data into our
 /// database.
@@ -38,11 +48,13 @@ pub(crate) async fn run(
     let mut backoff = INITIAL_BACKOFF;
 
     /// Helper macro to call in case of not being able to get a proper response
-    /// from Opencast. Forwards all arguments to `error!`, increases `backoff`
-    /// and sleeps for the backoff period.
+    /// from Opencast. Forwards all arguments to `error!`, records the failure
+    /// (see `metrics::Job`), increases `backoff` and sleeps for the backoff
+    /// period.
     macro_rules! request_failed {
         ($($t:tt)*) => {{
             error!($($t)*);
+            metrics::job_run(Job::Sync, false, &**db).await;
 
             // We increase the backoff duration exponentially until we hit the
             // defined maximum.
@@ -105,12 +117,41 @@ pub(crate) async fn run(
 
 
         // Write received data into the database, updating the sync status if
-        // everything worked out alright.
+        // everything worked out alright. Large responses are written in
+        // several transactions (see `sync.items_per_transaction`). Until the
+        // last one, the sync status is only advanced to the last item
+        // written: if Tobira is stopped in between, items with that
+        // timestamp are simply harvested again.
         let last_updated = harvest_data.items.last().map(|item| item.updated());
-        let mut transaction = db.transaction().await?;
-        store_in_db(harvest_data.items, &sync_status, &mut transaction).await?;
-        SyncStatus::update_harvested_until(harvest_data.includes_items_until, &*transaction).await?;
-        transaction.commit().await?;
+        let items_per_transaction = config.sync.items_per_transaction as usize;
+        let mut items = harvest_data.items.into_iter().peekable();
+        loop {
+            let chunk = items.by_ref().take(items_per_transaction).collect::<Vec<_>>();
+            let harvested_until = match (items.peek(), chunk.last()) {
+                (Some(_), Some(last)) => max(sync_status.harvested_until, last.updated()),
+                _ => harvest_data.includes_items_until,
+            };
+
+            let mut transaction = db.transaction().await?;
+            let new_events = write::store_in_db(
+                chunk,
+                &sync_status,
+                config.sync.transform.as_deref().unwrap_or_default(),
+                config.sync.legacy_ids.as_ref(),
+                &config.sanitize,
+                config.sync.write_batch_size.into(),
+                &mut transaction,
+            ).await?;
+            saved_search::queue_alerts(&new_events, &*transaction, config).await?;
+            series_follow::queue_new_events(&new_events, &*transaction).await?;
+            SyncStatus::update_harvested_until(harvested_until, &*transaction).await?;
+            transaction.commit().await?;
+
+            if items.peek().is_none() {
+                break;
+            }
+        }
+        metrics::job_run(Job::Sync, true, &**db).await;
 
 
         // Decide how to proceed (immediately continue, sleep or exit).
@@ -148,199 +189,20 @@ pub(crate) async fn run(
     }
 }
 
-async fn store_in_db(
-    items: Vec<HarvestItem>,
-    sync_status: &SyncStatus,
-    db: &mut deadpool_postgres::Transaction<'_>,
-) -> Result<()> {
-    let before = Instant::now();
-    let mut upserted_events = 0;
-    let mut removed_events = 0;
-    let mut upserted_series = 0;
-    let mut removed_series = 0;
-
-    let mut new_search_items = Vec::new();
-
-    for item in items {
-        // Make sure we haven't received this update yet. The code below can
-        // handle duplicate items alright, but this way we can save on some DB
-        // accesses and the logged statistics are more correct.
-        if item.updated() < sync_status.harvested_until {
-            debug!("Skipping item which `updated` value is earlier than `harvested_until`");
-            continue;
+/// Sanitizes the metadata of events and series (see `sanitize.rs`).
+fn sanitize(config: &SanitizeConfig, mut item: HarvestItem) -> HarvestItem {
+    match &mut item {
+        HarvestItem::Event { title, description, creator, .. } => {
+            *title = config.line(title);
+            *description = description.as_deref().and_then(|d| config.text(d));
+            *creator = creator.as_deref().map(|c| config.line(c)).filter(|c| !c.is_empty());
         }
-
-        match item {
-            HarvestItem::Event {
-                id: opencast_id,
-                title,
-                description,
-                part_of,
-                tracks,
-                created,
-                creator,
-                duration,
-                thumbnail,
-                acl,
-                updated,
-            } => {
-                let series_id = match &part_of {
-                    None => None,
-                    Some(part_of) => {
-                        db.query_opt("select id from series where opencast_id = $1", &[part_of])
-                            .await?
-                            .map(|row| row.get::<_, i64>(0))
-                    },
-                };
-
-                // We upsert the event data.
-                let new_id = upsert(db, "events", "opencast_id", &[
-                    ("opencast_id", &opencast_id),
-                    ("series", &series_id),
-                    ("part_of", &part_of),
-                    ("title", &title),
-                    ("description", &description),
-                    ("duration", &duration),
-                    ("created", &created),
-                    ("updated", &updated),
-                    ("creators", &creator.clone().map_or(vec![], |creator| vec![creator])),
-                    ("thumbnail", &thumbnail),
-                    ("read_roles", &acl.read),
-                    ("write_roles", &acl.write),
-                    ("tracks", &tracks.into_iter().map(Into::into).collect::<Vec<EventTrack>>()),
-                ]).await?;
-
-                new_search_items.push((Key(new_id as u64), IndexItemKind::Event));
-
-                debug!("Inserted or updated event {} ({})", opencast_id, title);
-                upserted_events += 1;
-            }
-
-            HarvestItem::EventDeleted { id: opencast_id, .. } => {
-                let rows_affected = db
-                    .execute("delete from events where opencast_id = $1", &[&opencast_id])
-                    .await?;
-                check_affected_rows_removed(rows_affected, "event", &opencast_id);
-                removed_events += 1;
-            }
-
-            HarvestItem::Series { id: opencast_id, title, description, updated } => {
-                // We first simply upsert the series.
-                let new_id = upsert(db, "series", "opencast_id", &[
-                    ("opencast_id", &opencast_id),
-                    ("title", &title),
-                    ("description", &description),
-                    ("updated", &updated),
-                ]).await?;
-
-                // But now we have to fix the foreign key for any events that
-                // previously referenced this series (via the Opencast UUID)
-                // but did not have the correct foreign key yet.
-                let query = "update events set series = $1 where part_of = $2 and series <> $1";
-                let updated_events = db.execute(query, &[&new_id, &opencast_id]).await?;
-
-                debug!("Inserted or updated series {} ({})", opencast_id, title);
-                if updated_events != 0 {
-                    debug!(
-                        "Fixed foreign series key of {} event(s) after upserting series {} ({})",
-                        updated_events,
-                        opencast_id,
-                        title,
-                    );
-                }
-                upserted_series += 1;
-            },
-
-            HarvestItem::SeriesDeleted { id: opencast_id, .. } => {
-                // We simply remove the series and do not care about any linked
-                // events. The foreign key has `on delete set null`. That's
-                // what we want: treat it as if the event has no series
-                // attached to it. Also see the comment on the migration.
-                let rows_affected = db
-                    .execute("delete from series where opencast_id = $1", &[&opencast_id])
-                    .await?;
-                check_affected_rows_removed(rows_affected, "series", &opencast_id);
-                removed_series += 1;
-            }
+        HarvestItem::Series { title, description, .. } => {
+            *title = config.line(title);
+            *description = description.as_deref().and_then(|d| config.text(d));
         }
+        HarvestItem::EventDeleted { .. } | HarvestItem::SeriesDeleted { .. } => {}
     }
 
-    if upserted_events == 0 && upserted_series == 0 && removed_events == 0 && removed_series == 0 {
-        info!("Harvest outcome: nothing changed!");
-    } else {
-        info!(
-            "Harvest outcome: upserted {} events, upserted {} series, \
-                removed {} events, removed {} series (in {:.2?})",
-            upserted_events,
-            upserted_series,
-            removed_events,
-            removed_series,
-            before.elapsed(),
-        );
-    }
-
-    if !new_search_items.is_empty() {
-        search::queue_many(&mut **db, new_search_items).await?;
-    }
-
-    Ok(())
-}
-
-fn check_affected_rows_removed(rows_affected: u64, entity: &str, opencast_id: &str) {
-    // The 0 rows affected case is fine: it is deleted anyway, so if we don't
-    // have it, then we don't have to do anything.
-    match rows_affected {
-        0 => debug!("The deleted {} {} from OC did not exist in our database", entity, opencast_id),
-        1 => debug!("Removed {} {}", entity, opencast_id),
-        _ => unreachable!("DB unique constraints violation when removing a {}", entity),
-    }
-}
-
-/// Inserts a new row or updates an existing one if the value in `unique_col`
-/// already exists. Returns the value of the `id` column, which is assumed to
-/// be `i64`.
-async fn upsert(
-    db: &deadpool_postgres::Transaction<'_>,
-    table_name: &str,
-    unique_col: &str,
-    cols: &[(&str, &(dyn ToSql + Sync))],
-) -> Result<i64> {
-    let mut query_col_names = String::new();
-    let mut query_col_values = String::new();
-    let mut query_update = String::new();
-    let mut values = Vec::new();
-    for (i, (name, value)) in cols.iter().copied().enumerate() {
-        if !query_col_names.is_empty() {
-            query_col_names += ", ";
-        }
-        query_col_names += name;
-
-        if !query_col_values.is_empty() {
-            query_col_values += ", ";
-        }
-        query_col_values += &format!("${}", i + 1);
-
-        if !query_update.is_empty() {
-            query_update += ", ";
-        }
-        if name != unique_col {
-            query_update += &format!("{0} = excluded.{0}", name);
-        }
-
-        values.push(value);
-    }
-
-    let query = format!(
-        "insert into {} ({}) values ({}) on conflict ({}) do update set {} returning id",
-        table_name,
-        query_col_names,
-        query_col_values,
-        unique_col,
-        query_update,
-    );
-
-    // We prepare the statement beforehand. This is cached by `db` so this
-    // actually makes sense to do here.
-    let statement = db.prepare_cached(&*query).await?;
-    Ok(db.query_one(&statement, &values).await?.get::<_, i64>(0))
+    item
 }
@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
-use crate::db::types::EventTrack;
+use crate::db::types::{EventCaption, EventTrack, is_audio_mimetype};
 
 
 /// What the harvesting API returns.
@@ -14,6 +16,8 @@ pub(super) struct HarvestResponse {
     pub(super) items: Vec<HarvestItem>,
 }
 
+// Most items are events anyway, so boxing their data would not save memory.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize)]
 #[serde(tag = "kind")]
 #[serde(rename_all = "kebab-case")]
@@ -28,9 +32,22 @@ pub(super) enum HarvestItem {
         created: DateTime<Utc>,
         creator: Option<String>,
         duration: i32,
+        /// Only sent by newer versions of the harvest API.
+        #[serde(default)]
+        trim: Option<Trim>,
         tracks: Vec<Track>,
+        /// Only sent by newer versions of the harvest API.
+        #[serde(default)]
+        captions: Vec<Caption>,
+        /// Only sent by newer versions of the harvest API.
+        #[serde(default)]
+        location: Option<String>,
         thumbnail: Option<String>,
         acl: Acl,
+        /// Additional metadata fields by namespace and field name. Only sent
+        /// by newer versions of the harvest API.
+        #[serde(default)]
+        metadata: ExtraMetadata,
         #[serde(with = "chrono::serde::ts_milliseconds")]
         updated: DateTime<Utc>,
     },
@@ -70,6 +87,9 @@ impl HarvestItem {
     }
 }
 
+/// Values of metadata fields, by namespace and field name.
+pub(super) type ExtraMetadata = BTreeMap<String, BTreeMap<String, Vec<String>>>;
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(super) struct Track {
@@ -81,15 +101,40 @@ pub(super) struct Track {
 
 impl Into<EventTrack> for Track {
     fn into(self) -> EventTrack {
+        let is_audio = self.mimetype.as_deref().and_then(is_audio_mimetype);
+
         EventTrack {
             uri: self.uri,
             flavor: self.flavor,
             mimetype: self.mimetype,
             resolution: self.resolution.map(Into::into),
+            is_audio,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Caption {
+    uri: String,
+    lang: Option<String>,
+}
+
+impl From<Caption> for EventCaption {
+    fn from(src: Caption) -> Self {
+        Self {
+            uri: src.uri,
+            lang: src.lang,
         }
     }
 }
 
+/// The part of the event that is played after non-destructive editing, in ms.
+#[derive(Debug, Deserialize)]
+pub(super) struct Trim {
+    pub(super) start: i32,
+    pub(super) end: i32,
+}
+
 #[derive(Debug, Deserialize)]
 pub(super) struct Acl {
     pub(super) read: Vec<String>,
@@ -0,0 +1,178 @@
+//! Configurable transformation of harvested items before they are stored (see
+//! `sync.transform`). This allows institutions to clean up Opencast metadata
+//! without changing it in Opencast.
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+use super::response::HarvestItem;
+
+
+/// A single rule of `sync.transform`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum TransformRule {
+    /// Replaces all occurrences of `find` in `field` with `with`.
+    Replace { field: Field, find: String, with: String },
+
+    /// Removes all occurrences of `text` from `field` and trims whitespace.
+    Strip { field: Field, text: String },
+
+    /// Events whose `field` contains `text` are not stored. If they were
+    /// stored before, they are removed.
+    Drop { field: Field, text: String },
+
+    /// Events that are part of the series with Opencast ID `from` are stored
+    /// as part of the series `to`.
+    MapSeries { from: String, to: String },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Field {
+    Title,
+    Description,
+    Creator,
+}
+
+pub(crate) fn validate(rules: &[TransformRule]) -> Result<()> {
+    for rule in rules {
+        let empty = match rule {
+            TransformRule::Replace { find, .. } => find.is_empty(),
+            TransformRule::Strip { text, .. } | TransformRule::Drop { text, .. } => text.is_empty(),
+            TransformRule::MapSeries { from, to } => from.is_empty() || to.is_empty(),
+        };
+        if empty {
+            bail!("invalid rule in `sync.transform`: strings to search for and \
+                series IDs must not be empty ({rule:?})");
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies all rules in order to the given item. Dropped events are turned
+/// into `EventDeleted` items.
+pub(super) fn apply(rules: &[TransformRule], mut item: HarvestItem) -> HarvestItem {
+    for rule in rules {
+        match rule {
+            TransformRule::Replace { field, find, with } => {
+                if let Some(value) = text_field(&mut item, *field) {
+                    *value = value.replace(find.as_str(), with);
+                }
+            }
+            TransformRule::Strip { field, text } => {
+                if let Some(value) = text_field(&mut item, *field) {
+                    *value = value.replace(text.as_str(), "").trim().to_owned();
+                }
+            }
+            TransformRule::Drop { field, text } => {
+                let matches = matches!(item, HarvestItem::Event { .. })
+                    && text_field(&mut item, *field).is_some_and(|v| v.contains(text.as_str()));
+                if let (true, HarvestItem::Event { id, updated, .. }) = (matches, &item) {
+                    debug!("Dropping event {} due to `sync.transform` rule {:?}", id, rule);
+                    return HarvestItem::EventDeleted { id: id.clone(), updated: *updated };
+                }
+            }
+            TransformRule::MapSeries { from, to } => {
+                if let HarvestItem::Event { part_of: Some(series), .. } = &mut item {
+                    if series == from {
+                        *series = to.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    item
+}
+
+/// Returns the given text field of an event or series, if it exists.
+fn text_field(item: &mut HarvestItem, field: Field) -> Option<&mut String> {
+    match (item, field) {
+        (HarvestItem::Event { title, .. } | HarvestItem::Series { title, .. }, Field::Title)
+            => Some(title),
+        (
+            HarvestItem::Event { description, .. } | HarvestItem::Series { description, .. },
+            Field::Description,
+        ) => description.as_mut(),
+        (HarvestItem::Event { creator, .. }, Field::Creator) => creator.as_mut(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use super::{HarvestItem, TransformRule, apply};
+
+    fn rules() -> Vec<TransformRule> {
+        #[derive(Deserialize)]
+        struct Config {
+            transform: Vec<TransformRule>,
+        }
+
+        toml::from_str::<Config>(r#"
+            transform = [
+                { action = "drop", field = "description", text = "[TEST]" },
+                { action = "strip", field = "title", text = "[internal]" },
+                { action = "replace", field = "creator", find = "Dr.", with = "Dr" },
+                { action = "map_series", from = "old", to = "new" },
+            ]
+        "#).unwrap().transform
+    }
+
+    fn event(title: &str, description: &str, part_of: &str) -> HarvestItem {
+        serde_json::from_value(serde_json::json!({
+            "kind": "event",
+            "id": "e1",
+            "title": title,
+            "description": description,
+            "partOf": part_of,
+            "created": 0,
+            "creator": "Dr. Peter",
+            "duration": 1000,
+            "tracks": [],
+            "thumbnail": null,
+            "acl": { "read": [], "write": [] },
+            "updated": 0,
+        })).unwrap()
+    }
+
+    #[test]
+    fn transform_event() {
+        match apply(&rules(), event("[internal] Intro ", "About it", "old")) {
+            HarvestItem::Event { title, description, part_of, creator, .. } => {
+                assert_eq!(title, "Intro");
+                assert_eq!(description.as_deref(), Some("About it"));
+                assert_eq!(part_of.as_deref(), Some("new"));
+                assert_eq!(creator.as_deref(), Some("Dr Peter"));
+            }
+            other => panic!("unexpected item {other:?}"),
+        }
+
+        assert!(matches!(
+            apply(&rules(), event("Intro", "[TEST] please ignore", "other")),
+            HarvestItem::EventDeleted { id, .. } if id == "e1",
+        ));
+    }
+
+    #[test]
+    fn transform_series() {
+        let series = serde_json::from_value(serde_json::json!({
+            "kind": "series",
+            "id": "s1",
+            "title": "Physics [internal]",
+            "description": "[TEST]",
+            "updated": 0,
+        })).unwrap();
+
+        match apply(&rules(), series) {
+            HarvestItem::Series { title, description, .. } => {
+                assert_eq!(title, "Physics");
+                assert_eq!(description.as_deref(), Some("[TEST]"));
+            }
+            other => panic!("unexpected item {other:?}"),
+        }
+    }
+}
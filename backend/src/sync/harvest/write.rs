@@ -0,0 +1,525 @@
+//! Writing harvested items into the DB.
+//!
+//! Instead of one statement per item, all series or events of a batch of up
+//! to `sync.write_batch_size` items are upserted with one statement. Their
+//! values are passed as one array per column, which `unnest` turns into rows
+//! again. Compared to one statement per item, this makes the initial import
+//! a lot faster and produces less WAL.
+
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    time::Instant,
+};
+
+use chrono::Utc;
+
+use crate::{
+    auth::USER_ROLE_PREFIX,
+    db::types::{EventCaption, EventTrack, Key},
+    prelude::*,
+    sanitize::SanitizeConfig,
+    search::{self, IndexItemKind},
+};
+use super::{
+    MAX_NOTIFICATION_AGE,
+    response::HarvestItem,
+    sanitize,
+    super::{MetadataField, status::SyncStatus},
+    transform,
+};
+
+
+type Transaction<'a> = deadpool_postgres::Transaction<'a>;
+
+/// Writes the harvested items into the DB. Returns the IDs of newly inserted
+/// events that should be announced to users.
+pub(super) async fn store_in_db(
+    items: Vec<HarvestItem>,
+    sync_status: &SyncStatus,
+    transform_rules: &[transform::TransformRule],
+    legacy_ids: Option<&MetadataField>,
+    sanitize_config: &SanitizeConfig,
+    batch_size: usize,
+    db: &mut Transaction<'_>,
+) -> Result<Vec<i64>> {
+    let before = Instant::now();
+
+    // Only the last change of each event and series matters. Postgres does
+    // not allow one upsert statement to change a row twice anyway.
+    let mut changes = Vec::new();
+    let mut positions = HashMap::new();
+    for item in items {
+        // Make sure we haven't received this update yet. The code below can
+        // handle duplicate items alright, but this way we can save on some DB
+        // accesses and the logged statistics are more correct.
+        if item.updated() < sync_status.harvested_until {
+            debug!("Skipping item which `updated` value is earlier than `harvested_until`");
+            continue;
+        }
+
+        let item = sanitize(sanitize_config, transform::apply(transform_rules, item));
+        let key = match &item {
+            HarvestItem::Event { id, .. } | HarvestItem::EventDeleted { id, .. }
+                => ("event", id.clone()),
+            HarvestItem::Series { id, .. } | HarvestItem::SeriesDeleted { id, .. }
+                => ("series", id.clone()),
+        };
+        match positions.entry(key) {
+            Entry::Occupied(entry) => {
+                let (kind, id) = entry.key();
+                debug!("Ignoring earlier change of {} {} in the same harvest", kind, id);
+                changes[*entry.get()] = item;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(changes.len());
+                changes.push(item);
+            }
+        }
+    }
+
+    let mut series = Vec::new();
+    let mut removed_series = Vec::new();
+    let mut events = Vec::new();
+    let mut removed_events = Vec::new();
+    for item in changes {
+        match item {
+            HarvestItem::Event { .. } => events.push(item),
+            HarvestItem::EventDeleted { id, .. } => removed_events.push(id),
+            HarvestItem::Series { .. } => series.push(item),
+            HarvestItem::SeriesDeleted { id, .. } => removed_series.push(id),
+        }
+    }
+    let upserted_events = events.len();
+    let upserted_series = series.len();
+
+    // Series are written first, so that events find their series. We simply
+    // remove series and do not care about any linked events. The foreign key
+    // has `on delete set null`. That's what we want: treat it as if the event
+    // has no series attached to it. Also see the comment on the migration.
+    for batch in batches(series, batch_size) {
+        upsert_series(db, batch).await?;
+    }
+    remove(db, "series", &removed_series).await?;
+    remove(db, "events", &removed_events).await?;
+
+    let mut new_search_items = Vec::new();
+    let mut new_events = Vec::new();
+    for batch in batches(events, batch_size) {
+        let upserted = upsert_events(db, batch, legacy_ids).await?;
+        for (id, is_new) in upserted {
+            new_search_items.push((Key(id as u64), IndexItemKind::Event));
+            if is_new {
+                new_events.push(id);
+            }
+        }
+    }
+
+    if upserted_events == 0 && upserted_series == 0
+        && removed_events.is_empty() && removed_series.is_empty()
+    {
+        info!("Harvest outcome: nothing changed!");
+    } else {
+        info!(
+            "Harvest outcome: upserted {} events, upserted {} series, \
+                removed {} events, removed {} series (in {:.2?})",
+            upserted_events,
+            upserted_series,
+            removed_events.len(),
+            removed_series.len(),
+            before.elapsed(),
+        );
+    }
+
+    if !new_search_items.is_empty() {
+        search::queue_many(&mut **db, new_search_items).await?;
+    }
+
+    Ok(new_events)
+}
+
+/// Splits `items` into batches of at most `size` items.
+fn batches<T>(items: Vec<T>, size: usize) -> impl Iterator<Item = Vec<T>> {
+    let mut items = items.into_iter().peekable();
+    std::iter::from_fn(move || {
+        items.peek()?;
+        Some(items.by_ref().take(size).collect())
+    })
+}
+
+/// Inserts or updates the given series (which all have to be
+/// `HarvestItem::Series`).
+async fn upsert_series(db: &Transaction<'_>, batch: Vec<HarvestItem>) -> Result<()> {
+    let mut opencast_ids = Vec::new();
+    let mut titles = Vec::new();
+    let mut descriptions = Vec::new();
+    let mut updated_timestamps = Vec::new();
+    for item in batch {
+        let HarvestItem::Series { id, title, description, updated } = item else {
+            unreachable!("non-series item in series batch");
+        };
+        debug!("Inserting or updating series {} ({})", id, title);
+        opencast_ids.push(id);
+        titles.push(title);
+        descriptions.push(description);
+        updated_timestamps.push(updated);
+    }
+
+    let statement = db.prepare_cached(
+        "insert into series (opencast_id, title, description, updated) \
+            select * from unnest($1::text[], $2::text[], $3::text[], $4::timestamptz[]) \
+            on conflict (opencast_id) do update set \
+                title = excluded.title, \
+                description = excluded.description, \
+                updated = excluded.updated"
+    ).await?;
+    db.execute(&statement, &[&opencast_ids, &titles, &descriptions, &updated_timestamps]).await?;
+
+    // But now we have to fix the foreign key for any events that previously
+    // referenced these series (via the Opencast UUID) but did not have the
+    // correct foreign key yet.
+    let updated_events = db
+        .execute(
+            "update events set series = series.id from series \
+                where series.opencast_id = any($1) \
+                and events.part_of = series.opencast_id \
+                and events.series is distinct from series.id",
+            &[&opencast_ids],
+        )
+        .await?;
+    if updated_events != 0 {
+        debug!(
+            "Fixed foreign series key of {} event(s) after upserting {} series",
+            updated_events,
+            opencast_ids.len(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes the rows of `table` with the given Opencast IDs.
+async fn remove(db: &Transaction<'_>, table: &str, opencast_ids: &[String]) -> Result<()> {
+    if opencast_ids.is_empty() {
+        return Ok(());
+    }
+
+    let query = format!("delete from {table} where opencast_id = any($1) returning opencast_id");
+    let removed = db.query(&query, &[&opencast_ids])
+        .await?
+        .into_iter()
+        .map(|row| row.get::<_, String>(0))
+        .collect::<HashSet<_>>();
+
+    // It's fine if some did not exist: they are deleted anyway, so if we
+    // don't have them, we don't have to do anything.
+    for id in opencast_ids {
+        if removed.contains(id) {
+            debug!("Removed {} from {}", id, table);
+        } else {
+            debug!("{} deleted in Opencast did not exist in {}", id, table);
+        }
+    }
+
+    Ok(())
+}
+
+/// Inserts or updates the given events (which all have to be
+/// `HarvestItem::Event`), including their legacy IDs and notifications about
+/// new events. Returns the ID of each event and whether it is new and should
+/// be announced to users.
+async fn upsert_events(
+    db: &Transaction<'_>,
+    batch: Vec<HarvestItem>,
+    legacy_ids: Option<&MetadataField>,
+) -> Result<Vec<(i64, bool)>> {
+    let mut opencast_ids = Vec::new();
+    let mut part_ofs = Vec::new();
+    let mut titles = Vec::new();
+    let mut descriptions = Vec::new();
+    let mut durations = Vec::new();
+    let mut trim_starts = Vec::new();
+    let mut trim_ends = Vec::new();
+    let mut created_timestamps = Vec::new();
+    let mut updated_timestamps = Vec::new();
+    let mut thumbnails = Vec::new();
+    let mut locations = Vec::new();
+    let mut metadata_values = Vec::new();
+    let mut creators = Concatenated::new();
+    let mut read_roles = Concatenated::new();
+    let mut write_roles = Concatenated::new();
+    let mut all_tracks = Concatenated::new();
+    let mut all_captions = Concatenated::new();
+
+    // What is needed after the upsert, in the order of the batch.
+    let mut follow_up = Vec::new();
+
+    for item in batch {
+        let HarvestItem::Event {
+            id: opencast_id,
+            title,
+            description,
+            part_of,
+            tracks,
+            captions,
+            location,
+            created,
+            creator,
+            duration,
+            trim,
+            thumbnail,
+            acl,
+            metadata,
+            updated,
+        } = item else {
+            unreachable!("non-event item in event batch");
+        };
+
+        let trim = trim.filter(|trim| {
+            let valid = 0 <= trim.start && trim.start < trim.end;
+            if !valid {
+                warn!("Ignoring invalid trim {:?} of event {}", trim, opencast_id);
+            }
+            valid
+        });
+
+        let recipients = acl.write.iter()
+            .filter(|role| role.starts_with(USER_ROLE_PREFIX))
+            .cloned()
+            .collect::<Vec<_>>();
+        let ids = legacy_ids
+            .and_then(|field| metadata.get(&field.namespace)?.get(&field.field))
+            .map(|ids| ids.iter()
+                .map(|id| id.trim())
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        debug!("Inserting or updating event {} ({})", opencast_id, title);
+        follow_up.push((opencast_id.clone(), updated, recipients, ids));
+        opencast_ids.push(opencast_id);
+        part_ofs.push(part_of);
+        titles.push(title);
+        descriptions.push(description);
+        durations.push(duration);
+        trim_starts.push(trim.as_ref().map(|trim| trim.start));
+        trim_ends.push(trim.as_ref().map(|trim| trim.end));
+        created_timestamps.push(created);
+        updated_timestamps.push(updated);
+        thumbnails.push(thumbnail);
+        locations.push(location);
+        metadata_values.push(serde_json::json!(metadata));
+        creators.push(creator);
+        read_roles.push(acl.read);
+        write_roles.push(acl.write);
+        all_tracks.push(tracks.into_iter().map(Into::<EventTrack>::into));
+        all_captions.push(captions.into_iter().map(EventCaption::from));
+    }
+
+    // `xmax` is only 0 for rows that were not updated, i.e. inserted.
+    let query = format!(
+        "insert into events (opencast_id, series, part_of, title, description, duration, \
+                trim_start, trim_end, created, updated, thumbnail, location, metadata, \
+                creators, read_roles, write_roles, tracks, captions) \
+            select t.opencast_id, \
+                (select id from series where series.opencast_id = t.part_of), \
+                t.part_of, t.title, t.description, t.duration, t.trim_start, t.trim_end, \
+                t.created, t.updated, t.thumbnail, t.location, t.metadata, \
+                {}, {}, {}, {}, {} \
+            from unnest($1::text[], $2::text[], $3::text[], $4::text[], $5::int[], \
+                $6::int[], $7::int[], $8::timestamptz[], $9::timestamptz[], $10::text[], \
+                $11::text[], $12::jsonb[]) \
+                with ordinality as t(opencast_id, part_of, title, description, duration, \
+                    trim_start, trim_end, created, updated, thumbnail, location, metadata, i) \
+            on conflict (opencast_id) do update set \
+                series = excluded.series, \
+                part_of = excluded.part_of, \
+                title = excluded.title, \
+                description = excluded.description, \
+                duration = excluded.duration, \
+                trim_start = excluded.trim_start, \
+                trim_end = excluded.trim_end, \
+                created = excluded.created, \
+                updated = excluded.updated, \
+                thumbnail = excluded.thumbnail, \
+                location = excluded.location, \
+                metadata = excluded.metadata, \
+                creators = excluded.creators, \
+                read_roles = excluded.read_roles, \
+                write_roles = excluded.write_roles, \
+                tracks = excluded.tracks, \
+                captions = excluded.captions \
+            returning opencast_id, id, xmax = 0",
+        slice(13, "text"),
+        slice(15, "text"),
+        slice(17, "text"),
+        slice(19, "event_track"),
+        slice(21, "event_caption"),
+    );
+
+    // The query only depends on the number of columns, so the prepared
+    // statement is reused for all batches.
+    let statement = db.prepare_cached(&query).await?;
+    let rows = db
+        .query(&statement, &[
+            &opencast_ids,
+            &part_ofs,
+            &titles,
+            &descriptions,
+            &durations,
+            &trim_starts,
+            &trim_ends,
+            &created_timestamps,
+            &updated_timestamps,
+            &thumbnails,
+            &locations,
+            &metadata_values,
+            &creators.values,
+            &creators.offsets,
+            &read_roles.values,
+            &read_roles.offsets,
+            &write_roles.values,
+            &write_roles.offsets,
+            &all_tracks.values,
+            &all_tracks.offsets,
+            &all_captions.values,
+            &all_captions.offsets,
+        ])
+        .await?;
+    let upserted = rows.into_iter()
+        .map(|row| (row.get::<_, String>(0), (row.get::<_, i64>(1), row.get::<_, bool>(2))))
+        .collect::<HashMap<_, _>>();
+
+    let mut out = Vec::new();
+    let mut event_ids = Vec::new();
+    let mut notifications = (Vec::new(), Vec::new());
+    let mut legacy = HashMap::new();
+    for (opencast_id, updated, recipients, ids) in follow_up {
+        let (id, inserted) = upserted[&opencast_id];
+        let age = Utc::now().signed_duration_since(updated);
+        let is_recent = age.num_seconds() <= MAX_NOTIFICATION_AGE.as_secs() as i64;
+        let is_new = inserted && is_recent;
+        if is_new {
+            for recipient in recipients {
+                notifications.0.push(recipient);
+                notifications.1.push(id);
+            }
+        }
+
+        // An ID that was mapped to another event before is moved to this
+        // one, as is one of an earlier event in this batch.
+        legacy.extend(ids.into_iter().map(|legacy_id| (legacy_id, id)));
+        event_ids.push(id);
+        out.push((id, is_new));
+    }
+
+    if legacy_ids.is_some() {
+        store_legacy_ids(db, &event_ids, legacy.into_iter().unzip()).await?;
+    }
+    notify_upload_finished(db, notifications).await?;
+
+    Ok(out)
+}
+
+/// Replaces the legacy IDs of the given events (see `sync.legacy_ids`) with
+/// the given pairs of legacy ID and event ID.
+async fn store_legacy_ids(
+    db: &Transaction<'_>,
+    events: &[i64],
+    (legacy_ids, legacy_events): (Vec<String>, Vec<i64>),
+) -> Result<()> {
+    db.execute(
+        "delete from legacy_event_ids \
+            where event = any($1) \
+            and not exists(select from unnest($2::text[], $3::bigint[]) as new(legacy_id, event) \
+                where new.legacy_id = legacy_event_ids.legacy_id \
+                and new.event = legacy_event_ids.event)",
+        &[&events, &legacy_ids, &legacy_events],
+    ).await?;
+    if !legacy_ids.is_empty() {
+        db.execute(
+            "insert into legacy_event_ids (legacy_id, event) \
+                select * from unnest($1::text[], $2::bigint[]) \
+                on conflict (legacy_id) do update set event = excluded.event",
+            &[&legacy_ids, &legacy_events],
+        ).await?;
+    }
+
+    Ok(())
+}
+
+/// Notifies all users with write access to new events that they are
+/// available now, via their user roles in the ACL. Takes pairs of user role
+/// and event ID.
+async fn notify_upload_finished(
+    db: &Transaction<'_>,
+    (recipients, events): (Vec<String>, Vec<i64>),
+) -> Result<()> {
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    db.execute(
+        "insert into notifications (recipient, kind, event) \
+            select recipient, 'upload_finished', event \
+            from unnest($1::text[], $2::bigint[]) as t(recipient, event)",
+        &[&recipients, &events],
+    ).await?;
+
+    Ok(())
+}
+
+/// The arrays of one column of all rows in a batch, concatenated into one
+/// array. Postgres does not support arrays of arrays with different lengths,
+/// so the array of each row is sliced out of that via `offsets` (see
+/// `slice`).
+struct Concatenated<T> {
+    values: Vec<T>,
+    /// The array of the `i`-th row (zero-based) consists of the values from
+    /// `offsets[i]` (inclusive) to `offsets[i + 1]` (exclusive).
+    offsets: Vec<i32>,
+}
+
+impl<T> Concatenated<T> {
+    fn new() -> Self {
+        Self { values: vec![], offsets: vec![0] }
+    }
+
+    fn push(&mut self, row: impl IntoIterator<Item = T>) {
+        self.values.extend(row);
+        self.offsets.push(self.values.len().try_into().expect("too many values in batch"));
+    }
+}
+
+/// SQL expression for the array of the current row of a `Concatenated`
+/// column, with its values being parameter `$param` of type `ty[]` and its
+/// offsets `$param + 1`. The row number (1-based) has to be available as
+/// `t.i`.
+fn slice(param: usize, ty: &str) -> String {
+    let offsets = param + 1;
+    format!("(${param}::{ty}[])[(${offsets}::int[])[t.i::int] + 1 : (${offsets}::int[])[t.i::int + 1]]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Concatenated, batches};
+
+    #[test]
+    fn batching() {
+        let split = |n: u32, size| batches((0..n).collect(), size).collect::<Vec<_>>();
+        assert_eq!(split(0, 2), Vec::<Vec<u32>>::new());
+        assert_eq!(split(4, 2), vec![vec![0, 1], vec![2, 3]]);
+        assert_eq!(split(5, 2), vec![vec![0, 1], vec![2, 3], vec![4]]);
+        assert_eq!(split(3, 10), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn concatenated() {
+        let mut c = Concatenated::new();
+        c.push(["a", "b"]);
+        c.push([]);
+        c.push(["c"]);
+        assert_eq!(c.values, ["a", "b", "c"]);
+        assert_eq!(c.offsets, [0, 2, 2, 3]);
+    }
+}
@@ -1,7 +1,8 @@
-use secrecy::Secret;
+use secrecy::{ExposeSecret, Secret};
 use std::time::Duration;
 
 use crate::{config::Config, db::DbConnection, prelude::*};
+use self::harvest::transform::TransformRule;
 
 
 pub(crate) mod cmd;
@@ -41,9 +42,90 @@ pub(crate) struct SyncConfig {
     #[config(default = 500)]
     preferred_harvest_size: u32,
 
+    /// How many events or series are written to the DB with one statement.
+    /// Larger batches speed up syncing, in particular the initial import,
+    /// but need more memory.
+    #[config(default = 200)]
+    write_batch_size: u16,
+
+    /// How many harvested items are written in one DB transaction at most.
+    /// Larger harvest responses (see `preferred_harvest_size`) are written
+    /// in several transactions, so that the initial import does not hold
+    /// locks for a long time and does not lose all progress if interrupted.
+    /// The next harvest request is only sent once all items are written, so
+    /// Opencast never sends data faster than the DB can take it.
+    #[config(default = 1000)]
+    items_per_transaction: u32,
+
     /// The duration to wait after a "no new data" reply from Opencast. Only
     /// relevant in `--daemon` mode.
     #[config(default = "30s", deserialize_with = crate::config::deserialize_duration)]
     poll_period: Duration,
+
+    /// Rules to transform harvested items before they are stored, e.g. to
+    /// clean up titles. They are applied in the given order. Each rule has
+    /// one of these `action`s:
+    ///
+    /// - "replace": replaces all occurrences of `find` in `field` with `with`.
+    /// - "strip": removes all occurrences of `text` from `field` and trims
+    ///   whitespace.
+    /// - "drop": events with `field` containing `text` are not stored (and
+    ///   removed if they were stored before).
+    /// - "map_series": events that are part of the series with Opencast ID
+    ///   `from` are treated as part of the series `to` instead.
+    ///
+    /// `field` is "title", "description" or "creator". "replace" and "strip"
+    /// also apply to the title and description of series. Changed rules only
+    /// affect items harvested afterwards. Example:
+    ///
+    ///     transform = [
+    ///         { action = "strip", field = "title", text = "[internal]" },
+    ///         { action = "drop", field = "title", text = "[TEST]" },
+    ///     ]
+    transform: Option<Vec<TransformRule>>,
+
+    /// Metadata field holding the IDs events had in a previous Opencast
+    /// instance, specified by its namespace and name as sent by the harvest
+    /// API. Links to the players of that instance (e.g. `/play/<id>`) are
+    /// then redirected to these events if `http.legacy_redirects` is enabled.
+    /// Only events harvested after setting this are mapped. Example:
+    ///
+    ///     legacy_ids = { namespace = "http://purl.org/dc/terms/", field = "source" }
+    legacy_ids: Option<MetadataField>,
+
+    /// Metadata field holding the DOI of events (e.g. "10.1234/5678"),
+    /// specified like `legacy_ids`. It is included in citations of events.
+    /// Example:
+    ///
+    ///     doi = { namespace = "http://purl.org/dc/terms/", field = "identifier" }
+    pub(crate) doi: Option<MetadataField>,
+}
+
+/// A metadata field of events, as sent by the harvest API.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MetadataField {
+    pub(crate) namespace: String,
+    pub(crate) field: String,
+}
+
+impl SyncConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.write_batch_size == 0 {
+            bail!("`sync.write_batch_size` must be positive");
+        }
+        if self.items_per_transaction == 0 {
+            bail!("`sync.items_per_transaction` must be positive");
+        }
+
+        harvest::transform::validate(self.transform.as_deref().unwrap_or_default())
+    }
+
+    /// Value of the `Authorization` header for requests to Opencast as the
+    /// sync user.
+    pub(crate) fn basic_auth_header(&self) -> Secret<String> {
+        let credentials = format!("{}:{}", self.user, self.password.expose_secret());
+        Secret::new(format!("Basic {}", base64::encode(credentials)))
+    }
 }
 
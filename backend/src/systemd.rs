@@ -0,0 +1,149 @@
+//! Integration with systemd: socket activation and readiness notification.
+//! Both are only used if systemd passes the corresponding environment
+//! variables, so Tobira works the same without systemd.
+//!
+//! With socket activation, systemd owns the listening sockets and keeps them
+//! open while Tobira restarts. Connections arriving in the meantime wait
+//! instead of being refused. Readiness notification (`Type=notify`) tells
+//! systemd when Tobira is actually able to serve requests, i.e. after the
+//! configuration was loaded and the DB was migrated. Until then, the status
+//! tells what Tobira is waiting for.
+
+use std::{
+    env,
+    ffi::OsString,
+    os::unix::{io::FromRawFd, net::{UnixDatagram, UnixListener}},
+    net::TcpListener,
+    sync::Mutex,
+};
+
+use once_cell::sync::OnceCell;
+use socket2::{Socket, Type};
+
+use crate::prelude::*;
+
+
+/// The first file descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: i32 = 3;
+
+/// The values of the environment variables systemd uses to talk to us. They
+/// are read once by `read_env` and the environment is never modified, as
+/// that is unsound while other threads might read it.
+struct SystemdEnv {
+    /// `LISTEN_PID` and `LISTEN_FDS`, taken by `listen_sockets`, so that the
+    /// file descriptors are not owned twice.
+    listen: Mutex<Option<(String, String)>>,
+    notify_socket: Option<OsString>,
+}
+
+static ENV: OnceCell<SystemdEnv> = OnceCell::new();
+
+/// Reads the environment variables set by systemd. Has to be called in
+/// `main` before the async runtime (or any other thread) is started.
+pub(crate) fn read_env() {
+    let listen = env::var("LISTEN_PID").ok().zip(env::var("LISTEN_FDS").ok());
+    let env = SystemdEnv {
+        listen: Mutex::new(listen),
+        notify_socket: env::var_os("NOTIFY_SOCKET"),
+    };
+    if ENV.set(env).is_err() {
+        panic!("bug: systemd environment read twice");
+    }
+}
+
+fn get_env() -> &'static SystemdEnv {
+    ENV.get().expect("bug: systemd environment was not read in `main`")
+}
+
+/// A listening socket passed by systemd.
+pub(crate) enum ListenSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Whether sockets were passed to this process via socket activation that
+/// were not taken by `listen_sockets` yet.
+pub(crate) fn has_listen_sockets() -> bool {
+    get_env().listen.lock().unwrap()
+        .as_ref()
+        .is_some_and(|(pid, _)| pid.parse::<u32>().ok() == Some(std::process::id()))
+}
+
+/// Returns all sockets passed to this process via socket activation
+/// (`LISTEN_FDS`). Returns an empty list if there are none or if this was
+/// called before.
+pub(crate) fn listen_sockets() -> Result<Vec<ListenSocket>> {
+    // The variables might have been meant for another process, e.g. our parent.
+    let Some((pid, num_fds)) = get_env().listen.lock().unwrap().take() else {
+        return Ok(vec![]);
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(vec![]);
+    }
+    let num_fds: i32 = num_fds.parse().context("invalid value for 'LISTEN_FDS'")?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + num_fds)
+        .map(|fd| {
+            // SAFETY: systemd guarantees that these file descriptors are open
+            // and belong to us. We only take ownership of each once.
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            socket.set_cloexec(true)?;
+            socket.set_nonblocking(true)?;
+
+            if socket.r#type()? != Type::STREAM || !socket.is_listener()? {
+                bail!("socket passed by systemd (fd {fd}) is not a listening stream socket");
+            }
+
+            let socket = if socket.local_addr()?.as_socket().is_some() {
+                ListenSocket::Tcp(socket.into())
+            } else {
+                ListenSocket::Unix(socket.into())
+            };
+            Ok(socket)
+        })
+        .collect()
+}
+
+/// Tells systemd that the service is ready (`READY=1`), if it asked for it
+/// (`NOTIFY_SOCKET`). Errors are only logged.
+pub(crate) fn notify_ready() {
+    if notify("READY=1") {
+        debug!("Notified systemd that Tobira is ready");
+    }
+}
+
+/// Tells systemd what Tobira is currently doing (`STATUS=`), e.g. while it
+/// is not ready yet. Shown by `systemctl status`.
+pub(crate) fn notify_status(status: &str) {
+    notify(&format!("STATUS={status}"));
+}
+
+/// Sends `message` to the notification socket, if any. Returns whether it
+/// was sent.
+fn notify(message: &str) -> bool {
+    let Some(path) = &get_env().notify_socket else {
+        return false;
+    };
+
+    let result = (|| -> Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        match path.to_str().and_then(|p| p.strip_prefix('@')) {
+            // Socket in the abstract namespace.
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(message.as_bytes(), &addr)?;
+            }
+            None => { socket.send_to(message.as_bytes(), path)?; }
+        }
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to notify systemd: {e}");
+            false
+        }
+    }
+}
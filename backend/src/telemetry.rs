@@ -0,0 +1,146 @@
+//! Opt-in reporting of anonymous usage statistics.
+//!
+//! If enabled, the worker periodically sends a few aggregate numbers (see
+//! `Report`) to the configured endpoint. Nothing is sent by default. The
+//! exact payload can be inspected with `tobira telemetry show`.
+
+use std::time::Duration;
+
+use hyper::{Body, Client, Request, Uri};
+use hyper_rustls::HttpsConnectorBuilder;
+use structopt::StructOpt;
+use tokio_postgres::GenericClient;
+
+use crate::{config::Config, db::{self, DbConnection}, prelude::*};
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct TelemetryConfig {
+    /// URL to which anonymous usage statistics are sent via `POST` request
+    /// with a JSON body. If not set (the default), nothing is ever sent. Run
+    /// `tobira telemetry show` to see exactly what would be sent.
+    pub(crate) endpoint: Option<String>,
+
+    /// How often to send the statistics. Only relevant if `endpoint` is set.
+    /// Has to be at least one hour.
+    #[config(default = "7d", deserialize_with = crate::config::deserialize_duration)]
+    pub(crate) interval: Duration,
+}
+
+impl TelemetryConfig {
+    /// The reported numbers hardly change within an hour, so sending them
+    /// more often would only put load on the endpoint.
+    const MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(endpoint) = &self.endpoint {
+            let uri = endpoint.parse::<Uri>().context("`telemetry.endpoint` is not a valid URL")?;
+            if !matches!(uri.scheme_str(), Some("http" | "https")) {
+                bail!("`telemetry.endpoint` has to be an HTTP(S) URL");
+            }
+        }
+        if self.interval < Self::MIN_INTERVAL {
+            bail!("`telemetry.interval` has to be at least one hour");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum TelemetryCommand {
+    /// Prints the statistics that would be sent to the configured endpoint.
+    Show,
+}
+
+/// The data that is sent. Only contains aggregate numbers and nothing that
+/// identifies the installation.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report {
+    version: &'static str,
+    num_events: i64,
+    num_series: i64,
+    num_realms: i64,
+}
+
+impl Report {
+    async fn gather(db: &impl GenericClient) -> Result<Self> {
+        let row = db.query_one(
+            "select \
+                (select count(*) from events), \
+                (select count(*) from series), \
+                (select count(*) from realms where id <> 0)",
+            &[],
+        ).await?;
+
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION"),
+            num_events: row.get(0),
+            num_series: row.get(1),
+            num_realms: row.get(2),
+        })
+    }
+}
+
+/// Entry point for `telemetry` commands.
+pub(crate) async fn run(cmd: &TelemetryCommand, config: &Config) -> Result<()> {
+    match cmd {
+        TelemetryCommand::Show => {
+            let pool = db::create_pool(&config.db).await?;
+            let report = Report::gather(&**pool.get().await?).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+
+            match &config.telemetry.endpoint {
+                Some(endpoint) => eprintln!(
+                    "This is sent to '{}' every {:?}.",
+                    endpoint,
+                    config.telemetry.interval,
+                ),
+                None => eprintln!("Telemetry is disabled: this is never sent."),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Long running task that regularly sends reports, if an endpoint is
+/// configured. Otherwise, this never resolves.
+pub(crate) async fn report_daemon(db: DbConnection, config: &TelemetryConfig) {
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint.parse::<Uri>().expect("bug: endpoint was not validated"),
+        None => return futures::future::pending().await,
+    };
+    info!("Telemetry is enabled: sending anonymous statistics to {}", endpoint);
+
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build::<_, Body>(https);
+
+    loop {
+        let res = async {
+            let report = Report::gather(&**db).await?;
+            let req = Request::post(&endpoint)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(&report)?.into())?;
+            let response = client.request(req).await?;
+            if !response.status().is_success() {
+                bail!("endpoint replied with status {}", response.status());
+            }
+            Ok(report)
+        }.await;
+
+        // Failing to send statistics is never critical, so we just try again
+        // next time.
+        match res {
+            Ok(report) => debug!("Sent telemetry report: {:?}", report),
+            Err(e) => warn!("Failed to send telemetry report: {}", e),
+        }
+
+        tokio::time::sleep(config.interval).await;
+    }
+}
@@ -79,22 +79,7 @@ impl FromStr for HttpHost {
         // for others, we require a special "safe word" at the end. This is
         // just to avoid human errors.
         let host = authority.host();
-        let is_local = {
-            let bracketed_ipv6 =
-                (|| host.strip_prefix('[')?.strip_suffix(']')?.parse::<Ipv6Addr>().ok())();
-
-
-            if let Some(ipv6) = bracketed_ipv6 {
-                ipv6.is_loopback()
-            } else if let Ok(ipv4) = host.parse::<Ipv4Addr>() {
-                ipv4.is_loopback()
-            } else {
-                // Sure, "localhost" could resolve to anything. But this check
-                // is for catching human errors, not for defending against
-                // attackers, so nothing here needs to be bulletproof.
-                host == "localhost"
-            }
-        };
+        let is_local = is_local_host(host);
 
         const SAFE_WORD: &str = "#allow-insecure";
         if scheme == uri::Scheme::HTTP && !(is_local || src.ends_with(SAFE_WORD)) {
@@ -106,6 +91,30 @@ impl FromStr for HttpHost {
     }
 }
 
+impl HttpHost {
+    /// Whether this refers to the local machine, for which unencrypted HTTP
+    /// is fine.
+    pub(crate) fn is_local(&self) -> bool {
+        is_local_host(self.authority.host())
+    }
+}
+
+fn is_local_host(host: &str) -> bool {
+    let bracketed_ipv6 =
+        (|| host.strip_prefix('[')?.strip_suffix(']')?.parse::<Ipv6Addr>().ok())();
+
+    if let Some(ipv6) = bracketed_ipv6 {
+        ipv6.is_loopback()
+    } else if let Ok(ipv4) = host.parse::<Ipv4Addr>() {
+        ipv4.is_loopback()
+    } else {
+        // Sure, "localhost" could resolve to anything. But this check
+        // is for catching human errors, not for defending against
+        // attackers, so nothing here needs to be bulletproof.
+        host == "localhost"
+    }
+}
+
 impl TryFrom<String> for HttpHost {
     type Error = <Self as FromStr>::Err;
     fn try_from(value: String) -> Result<Self, Self::Error> {
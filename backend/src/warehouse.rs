@@ -0,0 +1,404 @@
+//! Exporting the content of Tobira for institutional data warehouses.
+//!
+//! `tobira export warehouse` writes newline-delimited JSON: one object per
+//! line, each with a `type` field. Analysts can load that into their
+//! warehouse instead of querying Tobira's DB directly. Roles are stripped or
+//! pseudonymized according to the config, as they can identify users.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::TryStreamExt;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::{
+    api::Id,
+    config::Config,
+    db::{DbConnection, types::Key, util::dbargs},
+    prelude::*,
+};
+
+
+#[derive(Debug, confique::Config)]
+pub(crate) struct WarehouseConfig {
+    /// How the read and write roles of events are exported: "strip" (not at
+    /// all), "pseudonymize" (each role is replaced by a keyed hash, so that
+    /// events with the same roles can still be grouped) or "keep".
+    #[config(default = "strip")]
+    pub(crate) roles: RoleExport,
+
+    /// Secret used to pseudonymize roles, at least 32 characters long.
+    /// Required if `roles` is "pseudonymize". As long as it does not change,
+    /// the same role always results in the same pseudonym, also across
+    /// exports.
+    pub(crate) pseudonymization_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RoleExport {
+    Strip,
+    Pseudonymize,
+    Keep,
+}
+
+impl WarehouseConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        match (&self.pseudonymization_secret, self.roles) {
+            (None, RoleExport::Pseudonymize) => bail!(
+                "`warehouse.pseudonymization_secret` has to be set if `warehouse.roles` \
+                    is \"pseudonymize\"",
+            ),
+            (Some(secret), _) if secret.len() < 32 => bail!(
+                "`warehouse.pseudonymization_secret` has to be at least 32 characters long",
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum ExportCommand {
+    /// Writes events, series, realms and daily view numbers as
+    /// newline-delimited JSON for data warehouses. Deleted items are not
+    /// exported, so a full export is required to notice those.
+    Warehouse {
+        #[structopt(flatten)]
+        options: WarehouseArgs,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct WarehouseArgs {
+    /// Only export events and series updated at or after this point in time
+    /// and view numbers of days since then, e.g. `2024-03-01` (midnight UTC)
+    /// or `2024-03-01T12:00:00+01:00`. All realms are always exported.
+    #[structopt(long)]
+    since: Option<Since>,
+
+    /// Output file. If not specified, the export is written to stdout, where
+    /// log messages are written too unless `log.stdout` is disabled.
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Since(DateTime<Utc>);
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Self(DateTime::from_utc(date.and_hms(0, 0, 0), Utc)));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|datetime| Self(datetime.with_timezone(&Utc)))
+            .map_err(|_| format!("invalid date '{s}', expected e.g. '2024-03-01' or RFC 3339"))
+    }
+}
+
+/// One line of the export.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Record {
+    #[serde(rename_all = "camelCase")]
+    Event {
+        id: String,
+        opencast_id: Option<String>,
+        series: Option<String>,
+        title: String,
+        description: Option<String>,
+        creators: Vec<String>,
+        location: Option<String>,
+        /// Duration in ms.
+        duration: i32,
+        created: DateTime<Utc>,
+        updated: DateTime<Utc>,
+        retracted: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        read_roles: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        write_roles: Option<Vec<String>>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Series {
+        id: String,
+        opencast_id: String,
+        title: String,
+        description: Option<String>,
+        updated: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Realm {
+        id: String,
+        parent: Option<String>,
+        name: String,
+        path: String,
+        /// Series shown by series blocks.
+        series: Vec<String>,
+        /// Events shown by video blocks.
+        events: Vec<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    RealmViews {
+        realm: String,
+        day: NaiveDate,
+        views: i64,
+    },
+    #[serde(rename_all = "camelCase")]
+    EventPlayback {
+        event: String,
+        day: NaiveDate,
+        starts: i64,
+        completions: i64,
+    },
+}
+
+/// Entry point for `export` commands.
+pub(crate) async fn run(cmd: &ExportCommand, config: &Config) -> Result<()> {
+    match cmd {
+        ExportCommand::Warehouse { options } => warehouse(options, config).await,
+    }
+}
+
+async fn warehouse(args: &WarehouseArgs, config: &Config) -> Result<()> {
+    let pool = crate::connect_and_migrate_db(config).await?;
+    let db = pool.get().await?;
+    let roles = Roles::new(&config.warehouse);
+
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)
+            .with_context(|| format!("failed to create '{}'", path.display()))?),
+        None => Box::new(io::stdout()),
+    };
+    let mut out = Writer { out: BufWriter::new(out), count: 0 };
+
+    let since = args.since.map(|since| since.0);
+    let day = since.map(|since| since.date().naive_utc());
+    export_events(&db, since, &roles, &mut out).await?;
+    export_series(&db, since, &mut out).await?;
+    export_realms(&db, &mut out).await?;
+    export_views(&db, day, &mut out).await?;
+    out.out.flush()?;
+
+    info!("Exported {} records for data warehouses", out.count);
+    Ok(())
+}
+
+struct Writer {
+    out: BufWriter<Box<dyn Write>>,
+    count: u64,
+}
+
+impl Writer {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+async fn export_events(
+    db: &DbConnection,
+    since: Option<DateTime<Utc>>,
+    roles: &Roles,
+    out: &mut Writer,
+) -> Result<()> {
+    // Retracted events have no read roles, so the original ones are exported.
+    let query = "select id, opencast_id, series, title, description, creators, location, \
+            duration, created, updated, retracted, \
+            case when retracted then retracted_read_roles else read_roles end, write_roles \
+        from events \
+        where $1::timestamptz is null or updated >= $1 \
+        order by id";
+    let rows = db.query_raw(query, dbargs![&since]).await?;
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.try_next().await? {
+        out.write(&Record::Event {
+            id: Id::event(row.get(0)).to_string(),
+            opencast_id: row.get(1),
+            series: row.get::<_, Option<Key>>(2).map(|key| Id::series(key).to_string()),
+            title: row.get(3),
+            description: row.get(4),
+            creators: row.get(5),
+            location: row.get(6),
+            duration: row.get(7),
+            created: row.get(8),
+            updated: row.get(9),
+            retracted: row.get(10),
+            read_roles: roles.export(row.get::<_, Option<Vec<String>>>(11).unwrap_or_default()),
+            write_roles: roles.export(row.get(12)),
+        })?;
+    }
+
+    Ok(())
+}
+
+async fn export_series(
+    db: &DbConnection,
+    since: Option<DateTime<Utc>>,
+    out: &mut Writer,
+) -> Result<()> {
+    let query = "select id, opencast_id, title, description, updated from series \
+        where $1::timestamptz is null or updated >= $1 \
+        order by id";
+    let rows = db.query_raw(query, dbargs![&since]).await?;
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.try_next().await? {
+        out.write(&Record::Series {
+            id: Id::series(row.get(0)).to_string(),
+            opencast_id: row.get(1),
+            title: row.get(2),
+            description: row.get(3),
+            updated: row.get(4),
+        })?;
+    }
+
+    Ok(())
+}
+
+async fn export_realms(db: &DbConnection, out: &mut Writer) -> Result<()> {
+    let query = "select id, parent, name, full_path, \
+            array(select series_id from blocks \
+                where realm_id = realms.id and series_id is not null order by index), \
+            array(select video_id from blocks \
+                where realm_id = realms.id and video_id is not null order by index) \
+        from realms \
+        order by full_path";
+    let rows = db.query_raw(query, dbargs![]).await?;
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.try_next().await? {
+        out.write(&Record::Realm {
+            id: Id::realm(row.get(0)).to_string(),
+            parent: row.get::<_, Option<Key>>(1).map(|key| Id::realm(key).to_string()),
+            name: row.get(2),
+            path: row.get(3),
+            series: row.get::<_, Vec<Key>>(4).into_iter()
+                .map(|key| Id::series(key).to_string())
+                .collect(),
+            events: row.get::<_, Vec<Key>>(5).into_iter()
+                .map(|key| Id::event(key).to_string())
+                .collect(),
+        })?;
+    }
+
+    Ok(())
+}
+
+async fn export_views(db: &DbConnection, since: Option<NaiveDate>, out: &mut Writer) -> Result<()> {
+    let query = "select realm, day, views from realm_views_daily \
+        where $1::date is null or day >= $1 \
+        order by day, realm";
+    let rows = db.query_raw(query, dbargs![&since]).await?;
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.try_next().await? {
+        out.write(&Record::RealmViews {
+            realm: Id::realm(row.get(0)).to_string(),
+            day: row.get(1),
+            views: row.get(2),
+        })?;
+    }
+
+    let query = "select event, day, starts, completions from playback_stats_daily \
+        where $1::date is null or day >= $1 \
+        order by day, event";
+    let rows = db.query_raw(query, dbargs![&since]).await?;
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.try_next().await? {
+        out.write(&Record::EventPlayback {
+            event: Id::event(row.get(0)).to_string(),
+            day: row.get(1),
+            starts: row.get(2),
+            completions: row.get(3),
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Exports roles as configured by `warehouse.roles`.
+enum Roles {
+    Strip,
+    Pseudonymize(hmac::Key),
+    Keep,
+}
+
+impl Roles {
+    fn new(config: &WarehouseConfig) -> Self {
+        match config.roles {
+            RoleExport::Strip => Self::Strip,
+            RoleExport::Keep => Self::Keep,
+            RoleExport::Pseudonymize => {
+                let secret = config.pseudonymization_secret.as_ref()
+                    .expect("bug: pseudonymization secret was not validated");
+                Self::Pseudonymize(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()))
+            }
+        }
+    }
+
+    fn export(&self, roles: Vec<String>) -> Option<Vec<String>> {
+        match self {
+            Self::Strip => None,
+            Self::Keep => Some(roles),
+            Self::Pseudonymize(key) => Some(roles.iter().map(|role| {
+                // 128 bits are plenty to avoid collisions.
+                let tag = hmac::sign(key, role.as_bytes());
+                let hex = tag.as_ref()[..16].iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>();
+                format!("pseudo:{hex}")
+            }).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RoleExport, Roles, Since, WarehouseConfig};
+
+    #[test]
+    fn since() {
+        let parse = |s: &str| s.parse::<Since>().map(|since| since.0.to_rfc3339());
+        assert_eq!(parse("2024-03-01").unwrap(), "2024-03-01T00:00:00+00:00");
+        assert_eq!(parse("2024-03-01T12:00:00+01:00").unwrap(), "2024-03-01T11:00:00+00:00");
+        assert!(parse("03/01/2024").is_err());
+    }
+
+    #[test]
+    fn pseudonymize() {
+        let config = WarehouseConfig {
+            roles: RoleExport::Pseudonymize,
+            pseudonymization_secret: Some("0123456789abcdef0123456789abcdef".into()),
+        };
+        config.validate().unwrap();
+        let roles = Roles::new(&config);
+
+        let export = |roles: &Roles, names: &[&str]| {
+            roles.export(names.iter().map(|s| s.to_string()).collect()).unwrap()
+        };
+        let a = export(&roles, &["ROLE_A", "ROLE_B", "ROLE_A"]);
+        assert_eq!(a[0], a[2]);
+        assert_ne!(a[0], a[1]);
+        assert!(a[0].starts_with("pseudo:") && a[0].len() == 7 + 32);
+
+        let other = Roles::new(&WarehouseConfig {
+            pseudonymization_secret: Some("another secret that is long enough".into()),
+            ..config
+        });
+        assert_ne!(export(&other, &["ROLE_A"])[0], a[0]);
+
+        let without_secret = |roles| WarehouseConfig { roles, pseudonymization_secret: None };
+        assert!(without_secret(RoleExport::Pseudonymize).validate().is_err());
+        let stripped = Roles::new(&without_secret(RoleExport::Strip));
+        assert!(stripped.export(vec!["ROLE_A".into()]).is_none());
+    }
+}
@@ -10,6 +10,7 @@ module.exports = {
     customScalars: {
         "DateTimeUtc": "string",
         "Cursor": "string",
+        "NaiveDate": "string",
     },
     schemaExtensions: [APP_PATH],
 };
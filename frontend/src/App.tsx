@@ -11,6 +11,7 @@ import { MenuProvider } from "./layout/MenuState";
 import { InitialLoading } from "./layout/Root";
 import { GraphQLErrorBoundary } from "./relay/boundary";
 import { LoadingIndicator } from "./ui/LoadingIndicator";
+import { reportError } from "./util/errorReport";
 
 
 
@@ -80,6 +81,10 @@ class GlobalErrorBoundary
         return { error };
     }
 
+    public componentDidCatch(error: unknown) {
+        reportError(error);
+    }
+
     public render(): ReactNode {
         const error = this.state.error;
         if (!error) {
@@ -15,6 +15,8 @@ export type User = {
     canUpload: boolean;
     canUseStudio: boolean;
     canUseEditor: boolean;
+    canUseGraphiql: boolean;
+    canReceiveSearchAlerts: boolean;
 };
 
 const UserContext = React.createContext<UserState>("unknown");
@@ -42,6 +44,8 @@ export const userDataFragment = graphql`
             canUpload
             canUseStudio
             canUseEditor
+            canUseGraphiql
+            canReceiveSearchAlerts
         }
     }
 `;
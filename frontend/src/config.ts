@@ -26,6 +26,12 @@ type Config = {
     siteTitle: TranslatedString;
     opencast: OpencastConfig;
     footerLinks: FooterLink[];
+    announcement: TranslatedString | null;
+    eventFilterLabel: TranslatedString | null;
+    playbackStats: boolean;
+    errorReports: boolean;
+    virusScan: boolean;
+    graphiqlPublic: boolean;
     logo: LogoConfig;
     plyr: PlyrConfig;
 };
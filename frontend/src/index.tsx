@@ -3,8 +3,11 @@ import ReactDOM from "react-dom/client";
 import { App } from "./App";
 import "./i18n";
 import { matchInitialRoute } from "./router";
+import { reportUncaughtErrors } from "./util/errorReport";
 
 
+reportUncaughtErrors();
+
 const initialRoute = matchInitialRoute();
 const root = document.createElement("div");
 document.body.appendChild(root);
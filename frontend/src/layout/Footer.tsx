@@ -2,11 +2,15 @@ import { useTranslation } from "react-i18next";
 import CONFIG from "../config";
 import { Link } from "../router";
 import { ABOUT_PATH } from "../routes/paths";
+import { useUser } from "../User";
 import { translatedConfig } from "../util";
 
 
 export const Footer: React.FC = () => {
     const { t, i18n } = useTranslation();
+    const user = useUser();
+    const showGraphiql = CONFIG.graphiqlPublic
+        || (typeof user === "object" && user.canUseGraphiql);
 
     return (
         <footer css={{
@@ -34,7 +38,7 @@ export const Footer: React.FC = () => {
                             <Link to={ABOUT_PATH}>{t("footer.about-tobira")}</Link>
                         </li>;
                     } else if (entry === "graphiql") {
-                        return <li key={i}>
+                        return showGraphiql && <li key={i}>
                             <Link to="/~graphiql" htmlLink>Graph<em>i</em>QL</Link>
                         </li>;
                     } else {
@@ -5,7 +5,6 @@ import { graphql, useFragment } from "react-relay";
 import type { NavigationData$key } from "./__generated__/NavigationData.graphql";
 import { useTranslation } from "react-i18next";
 import { FOCUS_STYLE_INSET, LinkList, LinkWithIcon, SIDE_BOX_BORDER_RADIUS } from "../ui";
-import { match } from "../util";
 
 
 /** The breakpoint, in pixels, where mobile/desktop navigations are swapped. */
@@ -24,13 +23,12 @@ type Props = {
  * it.
  */
 export const Nav: React.FC<Props> = ({ fragRef }) => {
-    const { t, i18n } = useTranslation();
+    const { t } = useTranslation();
     const realm = useFragment(
         graphql`
             fragment NavigationData on Realm {
                 id
                 name
-                childOrder
                 children { id name path }
                 parent {
                     isRoot
@@ -42,15 +40,8 @@ export const Nav: React.FC<Props> = ({ fragRef }) => {
         fragRef,
     );
 
-    const children = [...realm.children];
-    match(realm.childOrder, {
-        "ALPHABETIC_ASC": () => {
-            children.sort((a, b) => a.name.localeCompare(b.name, i18n.language));
-        },
-        "ALPHABETIC_DESC": () => {
-            children.sort((a, b) => b.name.localeCompare(a.name, i18n.language));
-        },
-    }, () => {});
+    // The children are already sorted according to `childOrder` by the API.
+    const children = realm.children;
 
     return <nav>
         {realm.parent !== null && <>
@@ -13,6 +13,8 @@ import { userDataFragment, UserProvider } from "../User";
 import { GraphQLTaggedNode, PreloadedQuery, useFragment, usePreloadedQuery } from "react-relay";
 import { OperationType } from "relay-runtime";
 import { UserData$key } from "../__generated__/UserData.graphql";
+import CONFIG, { TranslatedString } from "../config";
+import { useTranslatedConfig } from "../util";
 
 
 export const MAIN_PADDING = 16;
@@ -41,6 +43,7 @@ export const Root: React.FC<Props> = ({ nav, children }) => {
                     backgroundColor: "var(--grey92)",
                 }}/>
             </div>
+            {CONFIG.announcement && <Announcement text={CONFIG.announcement} />}
             <Main>
                 {/* Sidebar */}
                 {navExists && <div css={{
@@ -70,6 +73,19 @@ export const Root: React.FC<Props> = ({ nav, children }) => {
     );
 };
 
+/** A site-wide note configured by the administrators, shown above the main content. */
+const Announcement: React.FC<{ text: TranslatedString }> = ({ text }) => (
+    <div css={{ margin: OUTER_CONTAINER_MARGIN }}>
+        <div css={{
+            margin: "0 16px 32px 16px",
+            padding: "8px 16px",
+            borderRadius: 4,
+            backgroundColor: "var(--grey97)",
+            border: "1px solid var(--grey80)",
+        }}>{useTranslatedConfig(text)}</div>
+    </div>
+);
+
 type OuterProps = {
     disableScrolling?: boolean;
 };
@@ -81,7 +81,12 @@ export const SearchField: React.FC<SearchFieldProps> = ({ variant }) => {
                     clearTimeout(lastTimeout.current);
                 }
                 lastTimeout.current = setTimeout(() => {
-                    router.goto(`/~search?q=${encodeURIComponent(e.target.value)}`);
+                    const realm = searchRealm();
+                    const realmParam = realm ? `&realm=${encodeURIComponent(realm)}` : "";
+                    const scopedParam = realm && isSearchScoped() ? "&scoped=1" : "";
+                    router.goto(
+                        `/~search?q=${encodeURIComponent(e.target.value)}${realmParam}${scopedParam}`,
+                    );
                 }, 30);
             }}
             css={{
@@ -112,3 +117,26 @@ export const SearchField: React.FC<SearchFieldProps> = ({ variant }) => {
         />}
     </div>;
 };
+
+/**
+ * Returns the path of the realm the search is started from, or `null` if the
+ * current page does not belong to a realm (or is the root realm). On the
+ * search page itself, the realm of the previous search is kept.
+ */
+const searchRealm = (): string | null => {
+    const { pathname, search } = document.location;
+    if (isSearchActive()) {
+        return new URLSearchParams(search).get("realm");
+    }
+    if (pathname.startsWith("/~") || pathname.startsWith("/!")) {
+        return null;
+    }
+
+    // Video pages inside realms are `<realm path>/v/<id>`.
+    const realm = pathname.replace(/\/v\/[^/]+\/?$/, "").replace(/\/$/, "");
+    return realm === "" ? null : realm;
+};
+
+/** Whether the current search is restricted to the realm it was started from. */
+const isSearchScoped = (): boolean => isSearchActive()
+    && new URLSearchParams(document.location.search).has("scoped");
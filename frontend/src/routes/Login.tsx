@@ -141,6 +141,10 @@ const LoginBox: React.FC = () => {
             // 403 Forbidden means the login data was incorrect
             setState("idle");
             setLoginError(t("login-page.bad-credentials"));
+        } else if (response.status === 429) {
+            // 429 Too Many Requests means the rate limit for logins was hit
+            setState("idle");
+            setLoginError(t("login-page.too-many-attempts"));
         } else {
             // Everything else is unexpected and should not happen.
             setState("idle");
@@ -1,4 +1,4 @@
-import React from "react";
+import React, { useEffect } from "react";
 
 import { graphql, loadQuery } from "react-relay/hooks";
 import type { RealmQuery, RealmQuery$data } from "./__generated__/RealmQuery.graphql";
@@ -15,6 +15,7 @@ import { LinkList, LinkWithIcon } from "../ui";
 import CONFIG from "../config";
 import { characterClass, useTitle, useTranslatedConfig } from "../util";
 import { makeRoute } from "../rauta";
+import { RealmLocaleProvider } from "../ui/time";
 
 
 // eslint-disable-next-line @typescript-eslint/quotes
@@ -82,7 +83,9 @@ export const RealmRoute = makeRoute(url => {
             }}
             render={data => (
                 data.realm
-                    ? <RealmPage realm={data.realm} />
+                    ? <RealmLocaleProvider locale={data.realm.effectiveLocale}>
+                        <RealmPage realm={data.realm} />
+                    </RealmLocaleProvider>
                     : <NotFound kind="page" />
             )}
         />,
@@ -98,6 +101,7 @@ const query = graphql`
             name
             path
             canCurrentUserEdit
+            effectiveLocale
             ancestors { name path }
             parent { id }
             ... BlocksData
@@ -118,6 +122,14 @@ const RealmPage: React.FC<Props> = ({ realm }) => {
     const title = isRoot ? siteTitle : realm.name;
     useTitle(title, isRoot);
 
+    // Counted per realm and day if statistics are enabled (see `views` in
+    // the API). Nothing but the realm is sent.
+    useEffect(() => {
+        if (CONFIG.playbackStats) {
+            navigator.sendBeacon(`/~realm-view/${realm.id.slice(2)}`);
+        }
+    }, [realm.id]);
+
     return <>
         {!isRoot && <Breadcrumbs path={breadcrumbs} tail={realm.name} />}
         {title && <h1>{title}</h1>}
@@ -1,8 +1,10 @@
+import { useState } from "react";
 import { useTranslation } from "react-i18next";
-import { graphql } from "react-relay";
+import { graphql, useMutation } from "react-relay";
 
 import { RootLoader } from "../layout/Root";
 import { SearchQuery, SearchQuery$data } from "./__generated__/SearchQuery.graphql";
+import { SearchSaveMutation } from "./__generated__/SearchSaveMutation.graphql";
 import { makeRoute } from "../rauta";
 import { loadQuery } from "../relay";
 import { Link } from "../router";
@@ -13,6 +15,9 @@ import { Card } from "../ui/Card";
 import { PageTitle } from "../layout/header/ui";
 import { FiFolder } from "react-icons/fi";
 import { HiOutlineUserCircle } from "react-icons/hi";
+import { useUser } from "../User";
+import { Button } from "../ui/Button";
+import { displayCommitError } from "./manage/Realm/util";
 
 
 export const isSearchActive = (): boolean => document.location.pathname === "/~search";
@@ -23,26 +28,33 @@ export const SearchRoute = makeRoute(url => {
     }
 
     const q = url.searchParams.get("q") ?? "";
-    const queryRef = loadQuery<SearchQuery>(query, { q });
+    const realm = url.searchParams.get("realm");
+    const scoped = realm !== null && url.searchParams.has("scoped");
+    const inRealm = scoped ? { path: realm, includeDescendants: true } : null;
+    const queryRef = loadQuery<SearchQuery>(query, { q, realm, inRealm });
 
     return {
         render: () => <RootLoader
             {...{ query, queryRef }}
             nav={() => []}
-            render={data => <SearchPage q={q} results={data.search} />}
+            render={data => <SearchPage {...{ q, realm, scoped }} results={data.search} />}
         />,
         dispose: () => queryRef.dispose(),
     };
 });
 
 const query = graphql`
-    query SearchQuery($q: String!) {
+    query SearchQuery($q: String!, $realm: String, $inRealm: InRealm) {
         ... UserData
-        search(query: $q) {
+        search(query: $q, realm: $realm, inRealm: $inRealm) {
+            suggestion
             items {
                 id
                 __typename
-                ... on SearchEvent { title description thumbnail duration creators seriesTitle }
+                ... on SearchEvent {
+                    title description thumbnail duration creators seriesTitle
+                    hostRealms { path name }
+                }
                 ... on SearchRealm { name path ancestorNames }
             }
         }
@@ -51,14 +63,21 @@ const query = graphql`
 
 type Props = {
     q: string;
+    /** Path of the realm the search was started from. */
+    realm: string | null;
+    /** Whether the search is restricted to `realm`. */
+    scoped: boolean;
     results: SearchQuery$data["search"];
 };
 
-const SearchPage: React.FC<Props> = ({ q, results }) => {
+const SearchPage: React.FC<Props> = ({ q, realm, scoped, results }) => {
     const { t } = useTranslation();
 
     return <div css={{ maxWidth: 950, margin: "0 auto" }}>
         <PageTitle title={t("search.title", { query: q })} />
+        {realm !== null && <ScopeToggle {...{ q, realm, scoped }} />}
+        <SaveSearch q={q} />
+        {results?.suggestion && <Suggestion suggestion={results.suggestion} />}
         {results === null
             ? <CenteredNote>{t("search.too-few-characters")}</CenteredNote>
             : results.items.length === 0
@@ -68,6 +87,80 @@ const SearchPage: React.FC<Props> = ({ q, results }) => {
     </div>;
 };
 
+type ScopeToggleProps = {
+    q: string;
+    realm: string;
+    scoped: boolean;
+};
+
+/** Switches between searching everywhere and only in the realm the search was started from. */
+const ScopeToggle: React.FC<ScopeToggleProps> = ({ q, realm, scoped }) => {
+    const { t } = useTranslation();
+    const params = new URLSearchParams({ q, realm });
+    if (!scoped) {
+        params.set("scoped", "1");
+    }
+
+    return <p css={{ margin: 16 }}>
+        {t(scoped ? "search.only-in-realm" : "search.everywhere", { realm }) + " "}
+        <Link to={"/~search?" + params.toString()}>
+            {t(scoped ? "search.search-everywhere" : "search.search-only-in-realm", { realm })}
+        </Link>
+    </p>;
+};
+
+/** Lets logged-in users save the current query, optionally with email alerts. */
+const SaveSearch: React.FC<{ q: string }> = ({ q }) => {
+    const { t } = useTranslation();
+    const user = useUser();
+    const [emailAlerts, setEmailAlerts] = useState(false);
+    const [saved, setSaved] = useState(false);
+    const [error, setError] = useState<JSX.Element | null>(null);
+
+    const [commit, inFlight] = useMutation<SearchSaveMutation>(graphql`
+        mutation SearchSaveMutation($query: String!, $emailAlerts: Boolean!) {
+            saveSearch(query: $query, emailAlerts: $emailAlerts) { id }
+        }
+    `);
+
+    if (user === "none" || user === "unknown" || q.trim() === "") {
+        return null;
+    }
+    if (saved) {
+        return <p css={{ margin: 16 }}>{t("search.saved")}</p>;
+    }
+
+    const save = () => commit({
+        variables: { query: q, emailAlerts },
+        onCompleted: () => setSaved(true),
+        onError: e => setError(displayCommitError(e, t("search.saving-failed"))),
+    });
+
+    return <div css={{ margin: 16, display: "flex", alignItems: "center", gap: 16 }}>
+        <Button disabled={inFlight} onClick={save}>{t("search.save")}</Button>
+        {user.canReceiveSearchAlerts && <label>
+            <input
+                type="checkbox"
+                checked={emailAlerts}
+                onChange={e => setEmailAlerts(e.target.checked)}
+            />
+            {" " + t("search.email-alerts")}
+        </label>}
+        {error}
+    </div>;
+};
+
+const Suggestion: React.FC<{ suggestion: string }> = ({ suggestion }) => {
+    const { t } = useTranslation();
+    const link = "/~search?" + new URLSearchParams({ q: suggestion }).toString();
+
+    return <p css={{ margin: 16 }}>
+        {t("search.did-you-mean") + " "}
+        <Link to={link} css={{ fontWeight: "bold" }}>{suggestion}</Link>
+        {"?"}
+    </p>;
+};
+
 const CenteredNote: React.FC = ({ children }) => (
     <div css={{ textAlign: "center" }}>
         <Card kind="info">{children}</Card>
@@ -94,6 +187,7 @@ const SearchResults: React.FC<SearchResultsProps> = ({ items }) => (
                     duration: unwrapUndefined(item.duration),
                     creators: unwrapUndefined(item.creators),
                     seriesTitle: unwrapUndefined(item.seriesTitle),
+                    hostRealms: unwrapUndefined(item.hostRealms),
                 }}/>;
             } else if (item.__typename === "SearchRealm") {
                 return <SearchRealm key={item.id} {...{
@@ -119,15 +213,22 @@ type SearchEventProps = {
     duration: number;
     creators: readonly string[];
     seriesTitle: string | null;
+    hostRealms: readonly { path: string; name: string }[];
 };
 
 const SearchEvent: React.FC<SearchEventProps> = ({
-    id, title, description, thumbnail, duration, creators, seriesTitle,
+    id, title, description, thumbnail, duration, creators, seriesTitle, hostRealms,
 }) => {
     const { t } = useTranslation();
 
+    // An event can be mounted in several realms, but is only listed once. We
+    // link to it in the context of the first one.
+    const link = hostRealms.length > 0
+        ? `${hostRealms[0].path.replace(/\/$/u, "")}/v/${id.slice(2)}`
+        : `/!${id.slice(2)}`;
+
     return (
-        <Item key={id} link={`/!${id.slice(2)}`}>
+        <Item key={id} link={link}>
             <Thumbnail
                 event={{
                     title,
@@ -159,6 +260,9 @@ const SearchEvent: React.FC<SearchEventProps> = ({
                 {seriesTitle && <div css={{ fontSize: 14, marginTop: 4 }}>
                     {t("video.part-of-series") + ": " + seriesTitle}
                 </div>}
+                {hostRealms.length > 1 && <div css={{ fontSize: 14, marginTop: 4 }}>
+                    {t("search.appears-in") + ": " + hostRealms.map(r => r.name).join(", ")}
+                </div>}
             </div>
         </Item>
     );
@@ -3,22 +3,26 @@ import { useTranslation } from "react-i18next";
 import { graphql, useRelayEnvironment } from "react-relay";
 import { keyframes } from "@emotion/react";
 import { useForm } from "react-hook-form";
-import { Environment, fetchQuery } from "relay-runtime";
+import { commitMutation, Environment, fetchQuery } from "relay-runtime";
 import { FiCheckCircle, FiUpload } from "react-icons/fi";
 
 import { RootLoader } from "../layout/Root";
 import { loadQuery } from "../relay";
-import { UploadQuery } from "./__generated__/UploadQuery.graphql";
+import { UploadQuery, UploadQuery$data } from "./__generated__/UploadQuery.graphql";
 import { UPLOAD_PATH } from "./paths";
 import { makeRoute } from "../rauta";
 import { UploadJwtQuery } from "./__generated__/UploadJwtQuery.graphql";
+import {
+    UploadPrepareMutation,
+    UploadPrepareMutation$data,
+} from "./__generated__/UploadPrepareMutation.graphql";
 import { assertNever, bug, ErrorDisplay, errorDisplayInfo, unreachable } from "../util/err";
 import { currentRef, useNavBlocker } from "../util";
 import CONFIG from "../config";
 import { Button } from "../ui/Button";
 import { boxError, ErrorBox } from "../ui/error";
 import { Form } from "../ui/Form";
-import { Input, TextArea } from "../ui/Input";
+import { Input, Select, TextArea } from "../ui/Input";
 import { User, useUser } from "../User";
 import { useRefState } from "../util";
 import { Card } from "../ui/Card";
@@ -36,7 +40,7 @@ export const UploadRoute = makeRoute(url => {
         render: () => <RootLoader
             {...{ query, queryRef }}
             nav={() => []}
-            render={() => <Upload />}
+            render={data => <Upload settings={data.uploadSettings} />}
         />,
         dispose: () => queryRef.dispose(),
     };
@@ -45,16 +49,34 @@ export const UploadRoute = makeRoute(url => {
 const query = graphql`
     query UploadQuery {
         ... UserData
+        uploadSettings {
+            tags
+            seriesMode
+            allowNewSeries
+            writableSeries { id title }
+        }
     }
 `;
 
 
+type UploadSettings = UploadQuery$data["uploadSettings"];
+
 type Metadata = {
     title: string;
     description: string;
+    /** ID of the chosen series, `NEW_SERIES` or empty for no series. */
+    series: string;
+    newSeriesTitle: string;
 };
 
-const Upload: React.FC = () => {
+/** Value of the series field if a new series should be created. */
+const NEW_SERIES = "new";
+
+type UploadProps = {
+    settings: UploadSettings;
+};
+
+const Upload: React.FC<UploadProps> = ({ settings }) => {
     const { t } = useTranslation();
 
     return (
@@ -66,12 +88,12 @@ const Upload: React.FC = () => {
         }}>
             <PageTitle title={t("upload.title")} />
             <div css={{ fontSize: 14, marginBottom: 16 }}>{t("upload.public-note")}</div>
-            <UploadMain />
+            <UploadMain settings={settings} />
         </div>
     );
 };
 
-const UploadMain: React.FC = () => {
+const UploadMain: React.FC<UploadProps> = ({ settings }) => {
     // TODO: on first mount, send an `ocRequest` to `info/me.json` and make sure
     // that connection works. That way we can show an error very early, before
     // the user selected a file.
@@ -89,12 +111,13 @@ const UploadMain: React.FC = () => {
 
     // Get user info
     const user = useUser();
-    if (user === "none" || user === "unknown") {
+    if (user === "none" || user === "unknown" || !settings) {
         // TODO: if not logged in, suggest doing so
         return <div css={{ textAlign: "center" }}>
             <ErrorBox>{t("upload.not-authorized")}</ErrorBox>
         </div>;
     }
+    const tags = settings.tags;
 
     /** Called when the files are selected. Starts uploading those files. */
     const onFileSelect = async (files: FileList) => {
@@ -113,7 +136,7 @@ const UploadMain: React.FC = () => {
                 finishUpload(relayEnv, mediaPackage, metadata.current, user, setUploadState);
             }
         };
-        startUpload(relayEnv, files, setUploadState, onProgressCallback, onDone);
+        startUpload(relayEnv, files, tags, setUploadState, onProgressCallback, onDone);
     };
 
     if (files === null) {
@@ -168,7 +191,11 @@ const UploadMain: React.FC = () => {
                         - ...
                     */}
                     {!metadata.current
-                        ? <MetaDataEdit onSave={onMetadataSave} disabled={hasUploadError} />
+                        ? <MetaDataEdit
+                            onSave={onMetadataSave}
+                            disabled={hasUploadError}
+                            settings={settings}
+                        />
                         : !hasUploadError && (
                             <div css={{ margin: "0 auto", maxWidth: 500 }}>
                                 <Card kind="info">{t("upload.still-uploading")}</Card>
@@ -260,6 +287,18 @@ const UploadErrorBox: React.FC<{ error: unknown }> = ({ error }) => {
             probablyOurFault: true,
             potentiallyInternetProblem: false,
         };
+    } else if (error instanceof VirusFound) {
+        info = {
+            causes: new Set([t("upload.errors.virus-found", { found: error.found })]),
+            probablyOurFault: false,
+            potentiallyInternetProblem: false,
+        };
+    } else if (error instanceof VirusScanFailed) {
+        info = {
+            causes: new Set([t("upload.errors.virus-scan-failed")]),
+            probablyOurFault: true,
+            potentiallyInternetProblem: false,
+        };
     } else if (error instanceof JwtInvalid) {
         // TODO: make it so that this error should not occur. And once that is
         // done, change `probablyOurFault` to `true`.
@@ -552,15 +591,18 @@ const ProgressBar: React.FC<ProgressBarProps> = ({ state }) => {
 type MetaDataEditProps = {
     onSave: (metadata: Metadata) => void;
     disabled: boolean;
+    settings: NonNullable<UploadSettings>;
 };
 
 /** Form that lets the user set metadata about the video */
-const MetaDataEdit: React.FC<MetaDataEditProps> = ({ onSave, disabled }) => {
+const MetaDataEdit: React.FC<MetaDataEditProps> = ({ onSave, disabled, settings }) => {
     const { t } = useTranslation();
 
-    const { register, handleSubmit, formState: { errors } } = useForm<Metadata>({
+    const { register, handleSubmit, watch, formState: { errors } } = useForm<Metadata>({
         mode: "onChange",
+        defaultValues: { series: "", newSeriesTitle: "" },
     });
+    const seriesRequired = settings.seriesMode === "REQUIRED";
 
     const onSubmit = handleSubmit(data => onSave(data));
 
@@ -589,6 +631,52 @@ const MetaDataEdit: React.FC<MetaDataEditProps> = ({ onSave, disabled }) => {
                 <TextArea id="description-field" {...register("description")} />
             </InputContainer>
 
+            {/* Series */}
+            {settings.seriesMode !== "NONE" && <InputContainer>
+                <label htmlFor="series-field">
+                    {t("upload.metadata.series")}
+                    {seriesRequired && <>
+                        {" "}
+                        <small>({t("upload.metadata.required")})</small>
+                    </>}
+                </label>
+                <Select
+                    id="series-field"
+                    error={!!errors.series}
+                    css={{ display: "block", width: 400, maxWidth: "100%" }}
+                    {...register("series", {
+                        required: seriesRequired && t("upload.errors.field-required") as string,
+                    })}
+                >
+                    <option value="" disabled={seriesRequired}>
+                        {t("upload.metadata.no-series")}
+                    </option>
+                    {settings.writableSeries.map(({ id, title }) => (
+                        <option key={id} value={id}>{title}</option>
+                    ))}
+                    {settings.allowNewSeries && <option value={NEW_SERIES}>
+                        {t("upload.metadata.new-series")}
+                    </option>}
+                </Select>
+                {boxError(errors.series?.message)}
+            </InputContainer>}
+
+            {/* Title of the new series */}
+            {watch("series") === NEW_SERIES && <InputContainer>
+                <label htmlFor="new-series-field">{t("upload.metadata.new-series-title")}</label>
+                <Input
+                    id="new-series-field"
+                    error={!!errors.newSeriesTitle}
+                    css={{ width: 400, maxWidth: "100%" }}
+                    {...register("newSeriesTitle", {
+                        required: t("upload.errors.field-required") as string,
+                        validate: value => value.trim() !== ""
+                            || t("upload.errors.field-required") as string,
+                    })}
+                />
+                {boxError(errors.newSeriesTitle?.message)}
+            </InputContainer>}
+
             {/* Submit button */}
             <Button kind="happy" type="submit" disabled={disabled}>
                 {t("upload.metadata.save")}
@@ -640,6 +728,27 @@ export class JwtInvalid extends Error {
     }
 }
 
+/** The virus scanner found something in an uploaded file */
+export class VirusFound extends Error {
+    public found: string;
+
+    public constructor(found: string) {
+        super();
+        this.name = "Virus found";
+        this.found = found;
+        this.message = `virus scanner found '${found}' in uploaded file`;
+    }
+}
+
+/** The uploaded file could not be scanned for viruses */
+export class VirusScanFailed extends Error {
+    public constructor() {
+        super();
+        this.name = "Virus scan failed";
+        this.message = "uploaded file could not be scanned for viruses";
+    }
+}
+
 /** Performs a request against Opencast, authenticated via JWT */
 const ocRequest = async (
     relayEnv: Environment,
@@ -674,6 +783,36 @@ const ocRequest = async (
         .catch(e => { throw new OcNetworkError(e); });
 };
 
+/**
+ * Lets the backend validate the chosen series and returns the workflow and
+ * series the upload has to be ingested with.
+ */
+const prepareUpload = (
+    relayEnv: Environment,
+    metadata: Metadata,
+): Promise<UploadPrepareMutation$data["prepareUpload"]> => new Promise((resolve, reject) => {
+    const mutation = graphql`
+        mutation UploadPrepareMutation($series: ID, $newSeriesTitle: String) {
+            prepareUpload(series: $series, newSeriesTitle: $newSeriesTitle) {
+                workflow
+                workflowConfiguration { key value }
+                series { opencastId title isNew }
+            }
+        }
+    `;
+
+    const isNew = metadata.series === NEW_SERIES;
+    commitMutation<UploadPrepareMutation>(relayEnv, {
+        mutation,
+        variables: {
+            series: isNew || !metadata.series ? null : metadata.series,
+            newSeriesTitle: isNew ? metadata.newSeriesTitle : null,
+        },
+        onCompleted: data => resolve(data.prepareUpload),
+        onError: error => reject(error),
+    });
+});
+
 /** Fetches a new JWT for uploading to Opencast */
 const getJwt = (relayEnv: Environment): Promise<string> => new Promise((resolve, reject) => {
     const query = graphql`
@@ -713,6 +852,7 @@ const getJwt = (relayEnv: Environment): Promise<string> => new Promise((resolve,
 const startUpload = async (
     relayEnv: Environment,
     files: FileList,
+    tags: readonly string[],
     setUploadState: (state: UploadState) => void,
     onProgress: (progress: Progress) => void,
     onDone: (mediaPackage: string) => void,
@@ -733,7 +873,7 @@ const startUpload = async (
 
         const tracks = Array.from(files)
             .map(file => ({ file, flavor: "presentation/source" as const }));
-        mediaPackage = await uploadTracks(relayEnv, mediaPackage, tracks, onProgress);
+        mediaPackage = await uploadTracks(relayEnv, mediaPackage, tracks, tags, onProgress);
         onDone(mediaPackage);
     } catch (error) {
         setUploadState({ state: "error", error });
@@ -747,7 +887,7 @@ type Track = {
 };
 
 /**
- * Uploads the given tracks via the ingest API. Calls `onProgress` regularly
+ * Uploads the given tracks with the given tags via the ingest API. Calls `onProgress` regularly
  * with a number between 0 and 1, indicating how much of the data was already
  * uploaded. Returns the resulting media package returned by the last request.
  */
@@ -755,6 +895,7 @@ const uploadTracks = async (
     relayEnv: Environment,
     mediaPackage: string,
     tracks: Track[],
+    tags: readonly string[],
     onProgress: (progress: number) => void,
 ): Promise<string> => {
     const totalBytes = tracks.map(t => t.file.size).reduce((a, b) => a + b, 0);
@@ -765,19 +906,29 @@ const uploadTracks = async (
         const body = new FormData();
         body.append("mediaPackage", mediaPackage);
         body.append("flavor", flavor);
-        body.append("tags", "");
+        body.append("tags", tags.join(","));
         body.append("BODY", file, file.name);
 
-        const url = ocUrl("/ingest/addTrack");
-        const jwt = await getJwt(relayEnv);
+        // If uploads are scanned for viruses, they are sent to Opencast via
+        // Tobira, which authenticates the user via the session.
+        const url = CONFIG.virusScan ? "/~upload/track" : ocUrl("/ingest/addTrack");
+        const jwt = CONFIG.virusScan ? null : await getJwt(relayEnv);
         mediaPackage = await new Promise((resolve, reject) => {
             const xhr = new XMLHttpRequest();
             xhr.open("POST", url);
-            xhr.setRequestHeader("Authorization", `Bearer ${jwt}`);
+            if (jwt === null) {
+                xhr.setRequestHeader("X-Requested-With", "XMLHttpRequest");
+            } else {
+                xhr.setRequestHeader("Authorization", `Bearer ${jwt}`);
+            }
 
             xhr.onload = () => {
-                if (xhr.responseURL !== url) {
+                if (jwt !== null && xhr.responseURL !== url) {
                     reject(new JwtInvalid());
+                } else if (jwt === null && xhr.status === 422) {
+                    reject(new VirusFound(JSON.parse(xhr.responseText).found));
+                } else if (jwt === null && xhr.status === 503) {
+                    reject(new VirusScanFailed());
                 } else if (xhr.status !== 200) {
                     reject(new OcServerError(xhr.status, xhr.statusText));
                 } else {
@@ -815,9 +966,12 @@ const finishUpload = async (
     try {
         setUploadState({ state: "finishing" });
 
+        // Let the backend check the series before anything is ingested
+        const target = await prepareUpload(relayEnv, metadata);
+
         // Add metadata in DC-Catalog
         {
-            const dcc = constructDcc(metadata, user);
+            const dcc = constructDcc(metadata, target.series?.opencastId ?? null, user);
             const body = new FormData();
             body.append("mediaPackage", mediaPackage);
             body.append("dublinCore", dcc);
@@ -851,13 +1005,44 @@ const finishUpload = async (
                 "/ingest/addAttachment",
                 { method: "post", body },
             );
+
+            // Opencast creates a new series from the series catalog and ACL
+            // in the media package.
+            if (target.series?.isNew) {
+                const dcc = constructSeriesDcc(target.series.opencastId, target.series.title);
+                const dccBody = new FormData();
+                dccBody.append("mediaPackage", mediaPackage);
+                dccBody.append("dublinCore", dcc);
+                dccBody.append("flavor", "dublincore/series");
+                mediaPackage = await ocRequest(
+                    relayEnv,
+                    "/ingest/addDCCatalog",
+                    { method: "post", body: dccBody },
+                );
+
+                const aclBody = new FormData();
+                aclBody.append("flavor", "security/xacml+series");
+                aclBody.append("mediaPackage", mediaPackage);
+                aclBody.append("BODY", new Blob([acl]), "acl.xml");
+                mediaPackage = await ocRequest(
+                    relayEnv,
+                    "/ingest/addAttachment",
+                    { method: "post", body: aclBody },
+                );
+            }
         }
 
-        // Finish ingest
+        // Finish ingest with the configured workflow
         {
             const body = new FormData();
             body.append("mediaPackage", mediaPackage);
-            await ocRequest(relayEnv, "/ingest/ingest", { method: "post", body: body });
+            for (const { key, value } of target.workflowConfiguration) {
+                body.append(key, value);
+            }
+            const path = target.workflow
+                ? `/ingest/ingest/${encodeURIComponent(target.workflow)}`
+                : "/ingest/ingest";
+            await ocRequest(relayEnv, path, { method: "post", body: body });
         }
 
         setUploadState({ state: "done" });
@@ -880,7 +1065,7 @@ const encodeValue = (value: string): string => {
 };
 
 /** Creates a Dublin Core Catalog in XML format that describes the given metadata. */
-const constructDcc = (metadata: Metadata, user: User): string => {
+const constructDcc = (metadata: Metadata, seriesId: string | null, user: User): string => {
     const tag = (tag: string, value: string): string =>
         value ? `<${tag}>${encodeValue(value)}</${tag}>` : "";
 
@@ -895,10 +1080,21 @@ const constructDcc = (metadata: Metadata, user: User): string => {
             ${tag("dcterms:description", metadata.description)}
             ${tag("dcterms:creator", user.displayName)}
             ${tag("dcterms:spatial", "Tobira Upload")}
+            ${tag("dcterms:isPartOf", seriesId ?? "")}
         </dublincore>
     `;
 };
 
+/** Creates a Dublin Core Catalog in XML format that describes a new series. */
+const constructSeriesDcc = (id: string, title: string): string => `
+    <?xml version="1.0" encoding="UTF-8"?>
+    <dublincore xmlns="http://www.opencastproject.org/xsd/1.0/dublincore/"
+                xmlns:dcterms="http://purl.org/dc/terms/">
+        <dcterms:identifier>${encodeValue(id)}</dcterms:identifier>
+        <dcterms:title>${encodeValue(title)}</dcterms:title>
+    </dublincore>
+`.trim();
+
 /** Constructs an ACL XML description from the given roles that are allowd to read/write */
 const constructAcl = (readRoles: string[], writeRoles: string[]): string => {
     // TODO: maybe we should escape the role somehow?
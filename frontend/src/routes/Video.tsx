@@ -17,6 +17,8 @@ import { isValidPathSegment } from "./Realm";
 import { Breadcrumbs } from "../ui/Breadcrumbs";
 import { PageTitle } from "../layout/header/ui";
 import { unreachable } from "../util/err";
+import { Card } from "../ui/Card";
+import { RealmLocaleProvider, useDateLocale } from "../ui/time";
 
 
 export const b64regex = "[a-zA-Z0-9\\-_]";
@@ -84,7 +86,11 @@ const prepare = (id: string, realmPath?: string): MatchedRoute => {
         render: () => <RootLoader
             {... { query, queryRef }}
             nav={data => data.realm ? <Nav fragRef={data.realm} /> : []}
-            render={render}
+            render={data => (
+                <RealmLocaleProvider locale={data.realm?.effectiveLocale ?? null}>
+                    {render(data)}
+                </RealmLocaleProvider>
+            )}
         />,
         dispose: () => queryRef.dispose(),
     };
@@ -94,7 +100,7 @@ const prepare = (id: string, realmPath?: string): MatchedRoute => {
 const query = graphql`
     query VideoQuery($id: ID!, $realmPath: String!) {
         ... UserData
-        event(id: $id) {
+        event(id: $id, realmPath: $realmPath) {
             title
             description
             creators
@@ -103,6 +109,8 @@ const query = graphql`
             duration
             thumbnail
             canWrite
+            isGeoblocked
+            citation { apa bibtex }
             series { title, ...SeriesBlockSeriesData }
             tracks { uri flavor mimetype resolution }
         }
@@ -110,6 +118,7 @@ const query = graphql`
             name
             path
             isRoot
+            effectiveLocale
             ancestors { name path }
             referencesVideo: references(id: $id)
             ... NavigationData
@@ -125,16 +134,17 @@ type Props = {
 };
 
 const VideoPage: React.FC<Props> = ({ event, realm, id, basePath }) => {
-    const { t, i18n } = useTranslation();
+    const { t } = useTranslation();
+    const locale = useDateLocale();
 
     const createdDate = new Date(event.created);
-    const created = createdDate.toLocaleString(i18n.language);
+    const created = createdDate.toLocaleString(locale);
 
     // If the event was updated only shortly after the creation date, we don't
     // want to show it.
     const updatedDate = new Date(event.updated);
     const updated = updatedDate.getTime() - createdDate.getTime() > 5 * 60 * 1000
-        ? updatedDate.toLocaleString(i18n.language)
+        ? updatedDate.toLocaleString(locale)
         : null;
 
     const { title, tracks, description } = event;
@@ -144,13 +154,18 @@ const VideoPage: React.FC<Props> = ({ event, realm, id, basePath }) => {
 
     return <>
         <Breadcrumbs path={breadcrumbs} tail={event.title} />
-        <Player
-            tracks={tracks as Track[]}
-            title={title}
-            duration={event.duration}
-            coverImage={event.thumbnail}
-            css={{ margin: "0 auto" }}
-        />
+        {event.isGeoblocked
+            ? <div css={{ display: "flex", justifyContent: "center" }}>
+                <Card kind="info">{t("video.geoblocked")}</Card>
+            </div>
+            : <Player
+                eventId={id}
+                tracks={tracks as Track[]}
+                title={title}
+                duration={event.duration}
+                coverImage={event.thumbnail}
+                css={{ margin: "0 auto" }}
+            />}
         <PageTitle title={title} css={{ marginTop: 24, fontSize: 24 }} />
         {description !== null && <TextBlock content={description} />}
         <table css={{
@@ -171,6 +186,18 @@ const VideoPage: React.FC<Props> = ({ event, realm, id, basePath }) => {
             </tbody>
         </table>
 
+        <details css={{ marginBottom: 16 }}>
+            <summary css={{ cursor: "pointer" }}>{t("video.cite")}</summary>
+            <p css={{ margin: "8px 0" }}>{event.citation.apa}</p>
+            <pre css={{
+                padding: 8,
+                overflowX: "auto",
+                backgroundColor: "var(--grey97)",
+                border: "1px solid var(--grey80)",
+                borderRadius: 4,
+            }}>{event.citation.bibtex}</pre>
+        </details>
+
         {event.canWrite && (
             <Link
                 to={`/~manage/videos/${id.slice(2)}`}
@@ -67,7 +67,10 @@ export const ChildOrder: React.FC<Props> = ({ fragRef }) => {
         "%future added value": () => bug("unknown realm sort order"),
     });
     const [sortOrder, setSortOrder] = useState<SortOrder>(intialSortOrder);
-    const [children, setChildren] = useState(realm.children);
+    // The API returns the children in the current order, which is not
+    // necessarily their index order.
+    const childrenByIndex = [...realm.children].sort((a, b) => a.index - b.index);
+    const [children, setChildren] = useState(childrenByIndex);
 
     // Swaps `index` with `index + 1`.
     const swap = (index: number) => {
@@ -81,7 +84,7 @@ export const ChildOrder: React.FC<Props> = ({ fragRef }) => {
 
     // Check if anything has changed
     const anyChange = intialSortOrder !== sortOrder
-        || children.some((c, i) => c.id !== realm.children[i].id);
+        || children.some((c, i) => c.id !== childrenByIndex[i].id);
 
     const [commitError, setCommitError] = useState<JSX.Element | null>(null);
     const [commit, isInFlight] = useMutation(mutation);
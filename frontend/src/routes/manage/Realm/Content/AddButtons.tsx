@@ -8,6 +8,7 @@ import {
     FiType,
     FiGrid,
     FiFilm,
+    FiUser,
 } from "react-icons/fi";
 
 import { AddButtonsRealmData$key } from "./__generated__/AddButtonsRealmData.graphql";
@@ -75,6 +76,7 @@ export const AddButtons: React.FC<Props> = ({ index, realm }) => {
             onClick={() => addBlock("Series", (_store, block) => {
                 block.setValue("NEW_TO_OLD", "order");
                 block.setValue(true, "showTitle");
+                block.setValue(false, "canonical");
             })}
         >
             <FiGrid />
@@ -83,9 +85,21 @@ export const AddButtons: React.FC<Props> = ({ index, realm }) => {
             title={t("manage.realm.content.add-video")}
             onClick={() => addBlock("Video", (_store, block) => {
                 block.setValue(true, "showTitle");
+                block.setValue(false, "canonical");
             })}
         >
             <FiFilm />
         </Button>
+        <Button
+            title={t("manage.realm.content.add-creator")}
+            onClick={() => addBlock("Creator", (_store, block) => {
+                block.setValue("", "creator");
+                block.setValue("NEW_TO_OLD", "order");
+                block.setValue(true, "showTitle");
+                block.setValue(false, "canonical");
+            })}
+        >
+            <FiUser />
+        </Button>
     </ButtonGroup>;
 };
@@ -0,0 +1,101 @@
+import React from "react";
+import { useTranslation } from "react-i18next";
+import { graphql, useFragment, useMutation } from "react-relay";
+import { useFormContext } from "react-hook-form";
+
+import { Card } from "../../../../../../ui/Card";
+import { Input } from "../../../../../../ui/Input";
+import { EditModeForm } from ".";
+import { Canonical, Heading, ShowTitle } from "./util";
+import type {
+    VideoListOrder,
+    CreatorEditModeBlockData$key,
+} from "./__generated__/CreatorEditModeBlockData.graphql";
+import {
+    CreatorEditSaveMutation,
+} from "./__generated__/CreatorEditSaveMutation.graphql";
+import {
+    CreatorEditCreateMutation,
+} from "./__generated__/CreatorEditCreateMutation.graphql";
+
+
+type CreatorFormData = {
+    creator: string;
+    order: VideoListOrder;
+};
+
+type EditCreatorBlockProps = {
+    block: CreatorEditModeBlockData$key;
+};
+
+export const EditCreatorBlock: React.FC<EditCreatorBlockProps> = ({ block: blockRef }) => {
+    const { creator, showTitle, order, canonical } = useFragment(graphql`
+        fragment CreatorEditModeBlockData on CreatorBlock {
+            creator
+            showTitle
+            order
+            canonical
+        }
+    `, blockRef);
+
+
+    const [save] = useMutation<CreatorEditSaveMutation>(graphql`
+        mutation CreatorEditSaveMutation($id: ID!, $set: UpdateCreatorBlock!) {
+            updateCreatorBlock(id: $id, set: $set) {
+                ... BlocksBlockData
+            }
+        }
+    `);
+
+    const [create] = useMutation<CreatorEditCreateMutation>(graphql`
+        mutation CreatorEditCreateMutation($realm: ID!, $index: Int!, $block: NewCreatorBlock!) {
+            addCreatorBlock(realm: $realm, index: $index, block: $block) {
+                ... ContentManageRealmData
+            }
+        }
+    `);
+
+
+    const { t } = useTranslation();
+
+    const form = useFormContext<CreatorFormData>();
+    const { formState: { errors } } = form;
+
+    return <EditModeForm create={create} save={save}>
+        <Heading>{t("manage.realm.content.series.order.heading")}</Heading>
+        <label>
+            <input
+                type="radio"
+                value="NEW_TO_OLD"
+                defaultChecked={order === "NEW_TO_OLD"}
+                {...form.register("order")}
+            />
+            {t("manage.realm.content.series.order.new-to-old")}
+        </label><br />
+        <label>
+            <input
+                type="radio"
+                value="OLD_TO_NEW"
+                defaultChecked={order === "OLD_TO_NEW"}
+                {...form.register("order")}
+            />
+            {t("manage.realm.content.series.order.old-to-new")}
+        </label>
+
+        <Heading>{t("manage.realm.content.creator.heading")}</Heading>
+        <p css={{ fontSize: 14, marginBottom: 8 }}>
+            {t("manage.realm.content.creator.description")}
+        </p>
+        {"creator" in errors && <div css={{ margin: "8px 0" }}>
+            <Card kind="error">{t("manage.realm.content.creator.invalid")}</Card>
+        </div>}
+        <Input
+            css={{ width: 400, maxWidth: "100%" }}
+            error={"creator" in errors}
+            defaultValue={creator}
+            {...form.register("creator", { required: true, validate: v => v.trim() !== "" })}
+        />
+        <ShowTitle showTitle={showTitle} />
+        <Canonical canonical={canonical} />
+    </EditModeForm>;
+};
@@ -7,7 +7,7 @@ import { Card } from "../../../../../../ui/Card";
 import { Select } from "../../../../../../ui/Input";
 import { ContentManageQueryContext } from "../..";
 import { EditModeForm } from ".";
-import { Heading, ShowTitle } from "./util";
+import { Canonical, Heading, ShowTitle } from "./util";
 import type {
     VideoListOrder,
     SeriesEditModeBlockData$key,
@@ -40,11 +40,12 @@ export const EditSeriesBlock: React.FC<EditSeriesBlockProps> = ({ block: blockRe
         }
     `, useContext(ContentManageQueryContext) as SeriesEditModeSeriesData$key);
 
-    const { series, showTitle, order } = useFragment(graphql`
+    const { series, showTitle, order, canonical } = useFragment(graphql`
         fragment SeriesEditModeBlockData on SeriesBlock {
             series { id }
             showTitle
             order
+            canonical
         }
     `, blockRef);
 
@@ -110,5 +111,6 @@ export const EditSeriesBlock: React.FC<EditSeriesBlockProps> = ({ block: blockRe
             ))}
         </Select>
         <ShowTitle showTitle={showTitle} />
+        <Canonical canonical={canonical} />
     </EditModeForm>;
 };
@@ -7,7 +7,7 @@ import { Card } from "../../../../../../ui/Card";
 import { Select } from "../../../../../../ui/Input";
 import { ContentManageQueryContext } from "../..";
 import { EditModeForm } from ".";
-import { Heading, ShowTitle } from "./util";
+import { Canonical, Heading, ShowTitle } from "./util";
 import type { VideoEditModeBlockData$key } from "./__generated__/VideoEditModeBlockData.graphql";
 import type { VideoEditModeEventData$key } from "./__generated__/VideoEditModeEventData.graphql";
 import type { VideoEditSaveMutation } from "./__generated__/VideoEditSaveMutation.graphql";
@@ -30,10 +30,11 @@ export const EditVideoBlock: React.FC<EditVideoBlockProps> = ({ block: blockRef
         }
     `, useContext(ContentManageQueryContext) as VideoEditModeEventData$key);
 
-    const { event, showTitle } = useFragment(graphql`
+    const { event, showTitle, canonical } = useFragment(graphql`
         fragment VideoEditModeBlockData on VideoBlock {
             event { id }
             showTitle
+            canonical
         }
     `, blockRef);
 
@@ -79,5 +80,6 @@ export const EditVideoBlock: React.FC<EditVideoBlockProps> = ({ block: blockRef
             ))}
         </Select>
         <ShowTitle showTitle={showTitle} />
+        <Canonical canonical={canonical} />
     </EditModeForm>;
 };
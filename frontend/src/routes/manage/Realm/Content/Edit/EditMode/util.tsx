@@ -26,3 +26,20 @@ export const ShowTitle: React.FC<ShowTitleProps> = ({ showTitle }) => {
         </label>
     </>;
 };
+
+type CanonicalProps = {
+    canonical: boolean;
+};
+
+export const Canonical: React.FC<CanonicalProps> = ({ canonical }) => {
+    const { t } = useTranslation();
+    const { register } = useFormContext<CanonicalProps>();
+
+    return <>
+        <Heading>{t("manage.realm.content.canonical.title")}</Heading>
+        <label>
+            <input type="checkbox" defaultChecked={canonical} {...register("canonical")} />
+            {t("manage.realm.content.canonical.label")}
+        </label>
+    </>;
+};
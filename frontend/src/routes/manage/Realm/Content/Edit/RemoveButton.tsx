@@ -9,23 +9,34 @@ import { ConfirmationModal, ConfirmationModalHandle } from "../../../../../ui/Mo
 import { displayCommitError } from "../../util";
 import { Button } from "../util";
 import { currentRef } from "../../../../../util";
+import { Link } from "../../../../../router";
 
 
 type Props = {
     block: RemoveButtonData$key;
+    /** ID of the realm the block belongs to. */
+    realmId: string;
     onConfirm?: () => void;
 };
 
-export const RemoveButton: React.FC<Props> = ({ block: blockRef, onConfirm }) => {
+export const RemoveButton: React.FC<Props> = ({ block: blockRef, realmId, onConfirm }) => {
     const { t } = useTranslation();
 
 
     const block = useFragment(graphql`
         fragment RemoveButtonData on Block {
             id
+            ... on SeriesBlock { series { hostRealms { id name path isRoot } } }
+            ... on VideoBlock { event { hostRealms { id name path isRoot } } }
         }
     `, blockRef);
 
+    // Other pages showing the same series or video, if it's that kind of block.
+    const hostRealms = "series" in block ? block.series?.hostRealms
+        : "event" in block ? block.event?.hostRealms
+        : undefined;
+    const otherHosts = hostRealms?.filter(realm => realm.id !== realmId);
+
 
     const [commit] = useMutation<RemoveButtonMutation>(graphql`
         mutation RemoveButtonMutation($id: ID!) {
@@ -40,7 +51,7 @@ export const RemoveButton: React.FC<Props> = ({ block: blockRef, onConfirm }) =>
 
     const remove = () => {
         commit({
-            variables: block,
+            variables: { id: block.id },
             onCompleted: () => {
                 currentRef(modalRef).done();
             },
@@ -80,6 +91,18 @@ export const RemoveButton: React.FC<Props> = ({ block: blockRef, onConfirm }) =>
             <p>
                 <Trans i18nKey="manage.realm.danger-zone.delete.cannot-be-undone" />
             </p>
+            {otherHosts && <div css={{ marginTop: 8 }}>
+                {otherHosts.length === 0
+                    ? <p>{t("manage.realm.content.remove-no-other-pages")}</p>
+                    : <>
+                        <p>{t("manage.realm.content.remove-other-pages")}</p>
+                        <ul>{otherHosts.map(realm => <li key={realm.id}>
+                            <Link to={realm.path}>{
+                                realm.isRoot ? <i>{t("general.homepage")}</i> : realm.name
+                            }</Link>
+                        </li>)}</ul>
+                    </>}
+            </div>}
         </ConfirmationModal>
     </>;
 };
@@ -32,13 +32,14 @@ export const EditButtons: React.FC<Props> = ({
 
     const realm = useFragment(graphql`
         fragment EditButtonsRealmData on Realm {
+            id
             ... MoveButtonsData
             blocks {
                 ... RemoveButtonData
             }
         }
     `, realmRef);
-    const { blocks } = realm;
+    const { id: realmId, blocks } = realm;
 
     const onMoveError = (error: Error) => {
         onError?.(error, "manage.realm.content.moving-failed");
@@ -52,7 +53,7 @@ export const EditButtons: React.FC<Props> = ({
         >
             <FiEdit />
         </Button>
-        <RemoveButton block={blocks[index]} onConfirm={onCompleted} />
+        <RemoveButton block={blocks[index]} realmId={realmId} onConfirm={onCompleted} />
     </ButtonGroup>;
 };
 
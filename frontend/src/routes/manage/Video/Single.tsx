@@ -79,6 +79,7 @@ const query = graphql`
             series { title ...SeriesBlockSeriesData }
             tracks { flavor resolution }
             hostRealms { id isRoot name path }
+            watchSegments { start end relativeViews }
         }
     }
 `;
@@ -103,7 +104,10 @@ const ManageSingleVideo: React.FC<Props> = ({ event }) => {
     if (user === "none" || user === "unknown") {
         return <NotAuthorized />;
     }
-    const editorUrl = `${CONFIG.opencast.editorUrl}?mediaPackageId=${event.opencastId}`;
+    // External events are not in Opencast and can thus not be edited there.
+    const editorUrl = event.opencastId === null
+        ? null
+        : `${CONFIG.opencast.editorUrl}?mediaPackageId=${event.opencastId}`;
 
     return <>
         <Breadcrumbs path={breadcrumbs} tail={event.title} />
@@ -124,7 +128,7 @@ const ManageSingleVideo: React.FC<Props> = ({ event }) => {
         }}>
             <ThumbnailDateInfo event={event} />
             <div css={{ margin: "8px 2px", flex: "1 0 auto" }}>
-                {user.canUseEditor && event.canWrite && (
+                {user.canUseEditor && event.canWrite && editorUrl !== null && (
                     <LinkButton to={editorUrl} css={{ marginBottom: 16 }}>
                         {t("manage.my-videos.open-in-editor")}
                     </LinkButton>
@@ -136,6 +140,9 @@ const ManageSingleVideo: React.FC<Props> = ({ event }) => {
         <section css={{ marginBottom: 32 }}>
             <HostRealms event={event} />
         </section>
+        {event.watchSegments && <section css={{ marginBottom: 32 }}>
+            <PlaybackStats segments={event.watchSegments} />
+        </section>}
         <section>
             <TechnicalDetails event={event} />
         </section>
@@ -245,17 +252,62 @@ const HostRealms: React.FC<Props> = ({ event }) => {
     </>;
 };
 
+type PlaybackStatsProps = {
+    segments: NonNullable<Event["watchSegments"]>;
+};
+
+const PlaybackStats: React.FC<PlaybackStatsProps> = ({ segments }) => {
+    const { t } = useTranslation();
+
+    return <>
+        <h2 css={{ fontSize: 20, marginBottom: 8 }}>{t("manage.my-videos.playback-stats")}</h2>
+        {segments.length === 0
+            ? <i>{t("manage.my-videos.no-playback-stats")}</i>
+            : <>
+                <p>{t("manage.my-videos.playback-stats-explanation")}</p>
+                <div css={{
+                    maxWidth: 800,
+                    height: 80,
+                    marginTop: 8,
+                    display: "flex",
+                    alignItems: "flex-end",
+                    borderBottom: "1px solid var(--grey65)",
+                }}>
+                    {segments.map(({ start, relativeViews }) => <div
+                        key={start}
+                        title={`${Math.round(relativeViews * 100)}%`}
+                        css={{
+                            flex: 1,
+                            height: `${relativeViews * 100}%`,
+                            backgroundColor: "var(--accent-color)",
+                        }}
+                    />)}
+                </div>
+                <div css={{
+                    maxWidth: 800,
+                    display: "flex",
+                    justifyContent: "space-between",
+                    fontSize: 14,
+                    color: "var(--grey40)",
+                }}>
+                    <span>{t("manage.my-videos.playback-stats-start")}</span>
+                    <span>{t("manage.my-videos.playback-stats-end")}</span>
+                </div>
+            </>}
+    </>;
+};
+
 const TechnicalDetails: React.FC<Props> = ({ event }) => {
     const { t } = useTranslation();
 
     return <>
         <h2 css={{ fontSize: 20, marginBottom: 8 }}>{t("manage.my-videos.technical-details")}</h2>
-        <div>
+        {event.opencastId !== null && <div>
             <span css={{ color: "var(--grey40)", marginRight: 8 }}>
                 {t("manage.my-videos.opencast-id") + ":"}
             </span>
             <code css={{ fontSize: 14 }}>{event.opencastId}</code>
-        </div>
+        </div>}
         <div css={{ marginTop: 8 }}>
             <span css={{ color: "var(--grey40)", marginRight: 8 }}>
                 {t("manage.my-videos.available-resolutions") + ":"}
@@ -1,13 +1,17 @@
-import { ReactElement } from "react";
+import { ReactElement, useState } from "react";
 import { useTranslation } from "react-i18next";
-import { FiFilm, FiUpload, FiVideo } from "react-icons/fi";
+import { FiBell, FiFilm, FiTrash, FiUpload, FiVideo } from "react-icons/fi";
 import { HiOutlineTemplate } from "react-icons/hi";
-import { graphql } from "react-relay";
+import { graphql, useMutation } from "react-relay";
 
 import { RootLoader } from "../../layout/Root";
 import {
     manageDashboardQuery as ManageDashboardQuery,
+    manageDashboardQuery$data as ManageDashboardQueryData,
 } from "./__generated__/manageDashboardQuery.graphql";
+import {
+    manageRemoveSavedSearchMutation as RemoveSavedSearchMutation,
+} from "./__generated__/manageRemoveSavedSearchMutation.graphql";
 import { makeRoute } from "../../rauta";
 import { loadQuery } from "../../relay";
 import { Link } from "../../router";
@@ -17,6 +21,8 @@ import { useUser } from "../../User";
 import CONFIG from "../../config";
 import { Breadcrumbs } from "../../ui/Breadcrumbs";
 import { PageTitle } from "../../layout/header/ui";
+import { Button } from "../../ui/Button";
+import { displayCommitError } from "./Realm/util";
 
 
 const PATH = "/~manage";
@@ -32,7 +38,7 @@ export const ManageRoute = makeRoute(url => {
         render: () => <RootLoader
             {...{ query, queryRef }}
             nav={() => <ManageNav key={1} active={PATH} />}
-            render={() => <Manage />}
+            render={data => <Manage savedSearches={data.currentUser?.savedSearches ?? []} />}
         />,
         dispose: () => queryRef.dispose(),
     };
@@ -40,10 +46,15 @@ export const ManageRoute = makeRoute(url => {
 
 
 const query = graphql`
-    query manageDashboardQuery { ...UserData }
+    query manageDashboardQuery {
+        ...UserData
+        currentUser { savedSearches { id query emailAlerts } }
+    }
 `;
 
-const Manage: React.FC = () => {
+type SavedSearchList = NonNullable<ManageDashboardQueryData["currentUser"]>["savedSearches"];
+
+const Manage: React.FC<{ savedSearches: SavedSearchList }> = ({ savedSearches }) => {
     const { t } = useTranslation();
     const user = useUser();
     if (user === "none" || user === "unknown") {
@@ -83,9 +94,55 @@ const Manage: React.FC = () => {
                 {t("manage.dashboard.manage-pages-tile-body")}
             </GridTile>
         </div>
+        <SavedSearches initial={savedSearches} />
     </>;
 };
 
+/** Lists the saved searches of the user and allows removing them. */
+const SavedSearches: React.FC<{ initial: SavedSearchList }> = ({ initial }) => {
+    const { t } = useTranslation();
+    const [savedSearches, setSavedSearches] = useState(initial);
+    const [error, setError] = useState<JSX.Element | null>(null);
+
+    const [commit, inFlight] = useMutation<RemoveSavedSearchMutation>(graphql`
+        mutation manageRemoveSavedSearchMutation($id: ID!) {
+            removeSavedSearch(id: $id) { id }
+        }
+    `);
+
+    const remove = (id: string) => commit({
+        variables: { id },
+        onCompleted: () => setSavedSearches(list => list.filter(s => s.id !== id)),
+        onError: e => setError(displayCommitError(e, t("manage.saved-searches.removing-failed"))),
+    });
+
+    return <section css={{ maxWidth: 950 }}>
+        <h2 css={{ fontSize: 18 }}>{t("manage.saved-searches.title")}</h2>
+        {error}
+        {savedSearches.length === 0
+            ? <p>{t("manage.saved-searches.none")}</p>
+            : <ul css={{ listStyle: "none", padding: 0 }}>
+                {savedSearches.map(({ id, query, emailAlerts }) => (
+                    <li key={id} css={{ display: "flex", alignItems: "center", gap: 8, margin: 8 }}>
+                        <Link to={"/~search?" + new URLSearchParams({ q: query }).toString()}>
+                            {query}
+                        </Link>
+                        {emailAlerts && <FiBell title={t("manage.saved-searches.email-alerts")} />}
+                        <Button
+                            kind="danger"
+                            disabled={inFlight}
+                            title={t("manage.saved-searches.remove")}
+                            onClick={() => remove(id)}
+                        >
+                            <FiTrash />
+                        </Button>
+                    </li>
+                ))}
+            </ul>
+        }
+    </section>;
+};
+
 type GridTileProps = {
     link?: string;
 };
@@ -0,0 +1,39 @@
+import React from "react";
+import { useTranslation } from "react-i18next";
+import { graphql, useFragment } from "react-relay";
+
+import { CreatorBlockData$key } from "./__generated__/CreatorBlockData.graphql";
+import { SeriesBlock } from "./Series";
+
+
+type Props = {
+    fragRef: CreatorBlockData$key;
+    basePath: string;
+};
+
+/** Lists all videos of one creator, looking exactly like a series block. */
+export const CreatorBlock: React.FC<Props> = ({ fragRef, basePath }) => {
+    const { t } = useTranslation();
+    const { creator, events, showTitle, order } = useFragment(graphql`
+        fragment CreatorBlockData on CreatorBlock {
+            creator
+            showTitle
+            order
+            events {
+                id
+                title
+                thumbnail
+                duration: durationTrimmed
+                created
+                creators
+                tracks { resolution }
+            }
+        }
+    `, fragRef);
+
+    return <SeriesBlock
+        series={{ title: t("series.by-creator", { creator }), events }}
+        noEventsText={t("series.no-events-by-creator", { creator })}
+        {...{ showTitle, order, basePath }}
+    />;
+};
@@ -37,7 +37,7 @@ const seriesFragment = graphql`
             id
             title
             thumbnail
-            duration
+            duration: durationTrimmed
             created
             creators
             tracks { resolution }
@@ -74,7 +74,9 @@ export const SeriesBlockFromSeries: React.FC<FromSeriesProps> = ({ fragRef, ...r
 };
 
 type Props = SharedProps & BlockOnlyProps & {
-    series: NonNullable<SeriesBlockSeriesData$data>;
+    series: Omit<NonNullable<SeriesBlockSeriesData$data>, " $fragmentType">;
+    /** Shown instead of the default text if there are no events. */
+    noEventsText?: string;
 };
 
 const VIDEO_GRID_BREAKPOINT = 600;
@@ -86,6 +88,7 @@ export const SeriesBlock: React.FC<Props> = ({
     basePath,
     activeEventId,
     order,
+    noEventsText,
 }) => {
     const sortedEvents = [...series.events];
 
@@ -136,7 +139,7 @@ export const SeriesBlock: React.FC<Props> = ({
                         active={event.id === activeEventId}
                         {...{ basePath, event }}
                     />)
-                    : t("series.no-events")}
+                    : noEventsText ?? t("series.no-events")}
             </div>
         </div>
     );
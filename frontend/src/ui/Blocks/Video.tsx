@@ -16,6 +16,7 @@ export const VideoBlock: React.FC<Props> = ({ fragRef }) => {
     const { event, showTitle } = useFragment(graphql`
         fragment VideoBlockData on VideoBlock {
             event {
+                id
                 title
                 duration
                 thumbnail
@@ -33,6 +34,7 @@ export const VideoBlock: React.FC<Props> = ({ fragRef }) => {
         {showTitle && <Title title={event.title} />}
         <Player
             {...event}
+            eventId={event.id}
             // Relay returns `readonly` objects ...
             tracks={event.tracks as Track[]}
             coverImage={event.thumbnail}
@@ -9,6 +9,7 @@ import { TitleBlock } from "./Title";
 import { TextBlockByQuery } from "./Text";
 import { SeriesBlockFromBlock } from "./Series";
 import { VideoBlock } from "./Video";
+import { CreatorBlock } from "./Creator";
 
 
 type BlocksProps = {
@@ -60,6 +61,7 @@ export const Block: React.FC<BlockProps> = ({ block: blockRef, realm }) => {
             ... on TextBlock { ... TextBlockData }
             ... on SeriesBlock { ... SeriesBlockData }
             ... on VideoBlock { ... VideoBlockData }
+            ... on CreatorBlock { ... CreatorBlockData }
         }
     `, blockRef);
     const { __typename } = block;
@@ -71,6 +73,8 @@ export const Block: React.FC<BlockProps> = ({ block: blockRef, realm }) => {
             "TextBlock": () => <TextBlockByQuery fragRef={block} />,
             "SeriesBlock": () => <SeriesBlockFromBlock fragRef={block} basePath={basePath} />,
             "VideoBlock": () => <VideoBlock fragRef={block} />,
-        })}
+            "CreatorBlock": () => <CreatorBlock fragRef={block} basePath={basePath} />,
+        // Blocks of extensions are only shown by forks that provide them.
+        }, () => null)}
     </div>;
 };
@@ -3,6 +3,26 @@ import { useTranslation } from "react-i18next";
 import { FiFilm, FiPlay, FiVolume2 } from "react-icons/fi";
 
 
+/** Widths (in pixels) the thumbnail proxy of the backend serves images in. */
+const THUMBNAIL_WIDTHS = [160, 320, 480, 640, 960, 1280];
+
+/**
+ * If thumbnails are served by our backend (see `http.thumbnails` in the
+ * backend config), it can resize them. In that case, we let the browser pick
+ * the smallest sufficient size. Thumbnails are shown full width on small
+ * screens and in grids of roughly this size otherwise.
+ */
+const responsiveSources = (url: string): JSX.IntrinsicElements["img"] => {
+    if (!url.startsWith("/~thumbnail/")) {
+        return {};
+    }
+
+    return {
+        srcSet: THUMBNAIL_WIDTHS.map(w => `${url}?width=${w} ${w}w`).join(", "),
+        sizes: "(max-width: 600px) 100vw, 360px",
+    };
+};
+
 type ThumbnailProps = JSX.IntrinsicElements["div"] & {
     /** The event of which a thumbnail should be shown */
     event: {
@@ -33,6 +53,7 @@ export const Thumbnail: React.FC<ThumbnailProps> = ({
         // We have a proper thumbnail.
         inner = <img
             src={event.thumbnail}
+            {...responsiveSources(event.thumbnail)}
             alt={t("video.thumbnail-for", { video: event.title })}
             width={16}
             height={9}
@@ -5,9 +5,12 @@ import { MAIN_PADDING } from "../../layout/Root";
 import { Spinner } from "../Spinner";
 import PaellaPlayer from "./Paella";
 import PlyrPlayer from "./Plyr";
+import { usePlaybackStats } from "./stats";
 
 
 export type PlayerProps = {
+    /** ID of the event, used for playback statistics. */
+    eventId?: string;
     coverImage: string | null;
     title: string;
     duration: number;
@@ -23,6 +26,7 @@ export type Track = {
 };
 
 export const Player: React.FC<PlayerProps> = ({
+    eventId,
     className,
     tracks,
     coverImage,
@@ -37,8 +41,10 @@ export const Player: React.FC<PlayerProps> = ({
     // with multi stream video.
     const aspectRatio = usePaella ? [16, 9] : tracks[0].resolution ?? [16, 9];
 
+    const statsRef = usePlaybackStats(eventId);
+
     return (
-        <div className={className} css={{
+        <div ref={statsRef} className={className} css={{
             // We want to make sure that the player does not take up all the
             // vertical and horizontal page, as this could make scrolling hard.
             // And if users want that, there is a fullscreen mode for a reason.
@@ -0,0 +1,86 @@
+import { useEffect, useRef } from "react";
+
+import CONFIG from "../../config";
+
+
+/**
+ * If playback statistics are enabled (`http.playback_stats` in the backend
+ * config), records which parts of the video inside the element with the
+ * returned ref are played. The ranges are reported to the backend when the
+ * page is hidden or the player is removed. Nothing but the ranges is sent.
+ */
+export const usePlaybackStats = (eventId: string | undefined) => {
+    const ref = useRef<HTMLDivElement>(null);
+
+    useEffect(() => {
+        const container = ref.current;
+        if (!CONFIG.playbackStats || eventId === undefined || container === null) {
+            return;
+        }
+
+        // Ranges in ms. `current` is the one that is currently being played.
+        const ranges: [number, number][] = [];
+        let current: [number, number] | null = null;
+
+        const endRange = () => {
+            if (current !== null && current[1] > current[0]) {
+                ranges.push(current);
+            }
+            current = null;
+        };
+
+        // Media events don't bubble, but they can be captured. Paella might
+        // contain multiple videos, which are played in sync, so we only look
+        // at the first one.
+        const onTimeUpdate = (e: Event) => {
+            const video = e.target;
+            if (!(video instanceof HTMLMediaElement)
+                || video.paused
+                || video !== container.querySelector("video")) {
+                return;
+            }
+
+            // `timeupdate` is fired at least every 250ms while playing, so
+            // larger jumps are seeks.
+            const now = Math.round(video.currentTime * 1000);
+            if (current !== null && (now < current[1] || now - current[1] > 2000)) {
+                endRange();
+            }
+            if (current === null) {
+                current = [now, now];
+            } else {
+                current[1] = now;
+            }
+        };
+
+        const send = () => {
+            endRange();
+            if (ranges.length > 0) {
+                const body = new URLSearchParams(
+                    ranges.map(([start, end]) => ["range", `${start}-${end}`]),
+                );
+                navigator.sendBeacon(`/~playback/${eventId.slice(2)}`, body);
+                ranges.length = 0;
+            }
+        };
+        const onVisibilityChange = () => {
+            if (document.visibilityState === "hidden") {
+                send();
+            }
+        };
+
+        const mediaEvents = ["pause", "seeking", "ended"];
+        container.addEventListener("timeupdate", onTimeUpdate, true);
+        mediaEvents.forEach(e => container.addEventListener(e, endRange, true));
+        document.addEventListener("visibilitychange", onVisibilityChange);
+
+        return () => {
+            send();
+            container.removeEventListener("timeupdate", onTimeUpdate, true);
+            mediaEvents.forEach(e => container.removeEventListener(e, endRange, true));
+            document.removeEventListener("visibilitychange", onVisibilityChange);
+        };
+    }, [eventId]);
+
+    return ref;
+};
@@ -1,17 +1,38 @@
+import React, { useContext } from "react";
 import { useTranslation } from "react-i18next";
 
 
+/**
+ * The effective locale of the realm the current page belongs to (see
+ * `Realm.effectiveLocale`), or `null` if there is none.
+ */
+const RealmLocaleContext = React.createContext<string | null>(null);
+
+export const RealmLocaleProvider: React.FC<{ locale: string | null }> = ({
+    locale,
+    children,
+}) => <RealmLocaleContext.Provider value={locale}>{children}</RealmLocaleContext.Provider>;
+
+/**
+ * Returns the locale dates and times should be formatted with: that of the
+ * realm of the current page if it has one, and the user's language otherwise.
+ */
+export const useDateLocale = (): string => {
+    const { i18n } = useTranslation();
+    return useContext(RealmLocaleContext) ?? i18n.language;
+};
+
 type RelativeDateProps = {
     date: Date;
 };
 
 /** Formats a date as something relative like "3 days ago" */
 export const RelativeDate: React.FC<RelativeDateProps> = ({ date }) => {
-    const { i18n } = useTranslation();
+    const locale = useDateLocale();
     const secsAgo = Math.floor((Date.now() - date.getTime()) / 1000);
 
     const prettyDate = (() => {
-        const intl = new Intl.RelativeTimeFormat(i18n.language);
+        const intl = new Intl.RelativeTimeFormat(locale);
         if (secsAgo <= 55) {
             return intl.format(-secsAgo, "second");
         } else if (secsAgo <= 55 * 60) {
@@ -29,7 +50,7 @@ export const RelativeDate: React.FC<RelativeDateProps> = ({ date }) => {
         }
     })();
 
-    const preciseDate = date.toLocaleString(i18n.language);
+    const preciseDate = date.toLocaleString(locale);
 
     return <span title={preciseDate}>{prettyDate}</span>;
 };
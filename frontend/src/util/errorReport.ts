@@ -0,0 +1,34 @@
+import CONFIG from "../config";
+
+
+/** Maximum number of reports sent per page load. */
+const MAX_REPORTS = 10;
+
+let sentReports = 0;
+
+/**
+ * If error reports are enabled (`http.error_reports.enabled` in the backend
+ * config), sends the given error to the backend, so that administrators
+ * learn about it. The backend removes query strings and email addresses.
+ */
+export const reportError = (error: unknown) => {
+    if (!CONFIG.errorReports || sentReports >= MAX_REPORTS) {
+        return;
+    }
+    sentReports += 1;
+
+    const report = error instanceof Error
+        ? { name: error.name, message: error.message, stack: error.stack }
+        : { message: String(error) };
+    const body = new Blob(
+        [JSON.stringify({ ...report, url: window.location.href })],
+        { type: "application/json" },
+    );
+    navigator.sendBeacon("/~report/error", body);
+};
+
+/** Reports all errors that are not caught anywhere else. */
+export const reportUncaughtErrors = () => {
+    window.addEventListener("error", e => reportError(e.error ?? e.message));
+    window.addEventListener("unhandledrejection", e => reportError(e.reason));
+};